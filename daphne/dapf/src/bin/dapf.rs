@@ -5,15 +5,21 @@ use anyhow::{anyhow, Context, Result};
 use clap::{Parser, Subcommand};
 use daphne::{
     aborts::ProblemDetails,
+    collector::{DapCollectionCheck, DapCollectionLog},
     constants::DapMediaType,
-    hpke::HpkeReceiverConfig,
-    messages::{BatchSelector, Collection, CollectionReq, HpkeConfig, Query, TaskId},
+    hpke::{gen_hpke_receiver_config_schedule, HpkeReceiverConfig},
+    messages::{
+        BatchSelector, Collection, CollectionReq, Duration, HpkeConfig, HpkeKemId, Query, TaskId,
+        Time,
+    },
     DapMeasurement, DapVersion, VdafConfig,
 };
 use prio::codec::{Decode, ParameterizedDecode, ParameterizedEncode};
 use reqwest::blocking::{Client, ClientBuilder};
 use std::{
-    io::{stdin, Read},
+    fs,
+    io::{stdin, ErrorKind, Read},
+    path::PathBuf,
     time::SystemTime,
 };
 use url::Url;
@@ -27,12 +33,16 @@ struct Cli {
 
     /// DAP task ID (base64, URL-safe encoding)
     #[clap(short, long, action)]
-    task_id: String,
+    task_id: Option<String>,
 
     /// Bearer token for authorizing request
     #[clap(short, long, action)]
     bearer_token: Option<String>,
 
+    /// Send the bearer token in the "Authorization: Bearer" header instead of "DAP-Auth-Token"
+    #[clap(long, action)]
+    authorization_bearer: bool,
+
     /// HPKE receiver configuration for decrypting response
     #[clap(long, action)]
     hpke_receiver: Option<HpkeReceiverConfig>,
@@ -70,6 +80,36 @@ enum Action {
         /// JSON-formatted VDAF config
         #[clap(short, long, action)]
         vdaf: VdafConfig,
+
+        /// File in which to record the collections consumed so far. If set, then the aggregate
+        /// result is rejected if the Leader previously served a different result for the same
+        /// batch selector.
+        #[clap(long, action)]
+        collection_log: Option<PathBuf>,
+    },
+    /// Generate a schedule of HPKE receiver configs with consecutive validity windows and print
+    /// them as JSON. No network access is required, so this can be run on an offline machine. The
+    /// output can be installed on an Aggregator via its admin API.
+    GenerateHpkeReceiverConfigs {
+        /// HPKE KEM algorithm, e.g., "x25519_hkdf_sha256"
+        #[clap(long, value_parser = parse_hpke_kem_id)]
+        kem: HpkeKemId,
+
+        /// Config ID of the first config; subsequent IDs are chosen by incrementing this value
+        #[clap(long, action)]
+        first_config_id: u8,
+
+        /// Number of configs to generate
+        #[clap(long, action)]
+        count: usize,
+
+        /// Start of the first validity window (UNIX timestamp, in seconds)
+        #[clap(long, action)]
+        not_before: Time,
+
+        /// Length of each validity window (in seconds)
+        #[clap(long, action)]
+        period: Duration,
     },
 }
 
@@ -81,12 +121,20 @@ async fn main() -> Result<()> {
         .as_secs();
 
     let cli = Cli::parse();
-    let task_id = parse_id(&cli.task_id).with_context(|| "failed to parse task ID")?;
+    let task_id = || -> Result<TaskId> {
+        let task_id = cli
+            .task_id
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing task ID"))?;
+        parse_id(task_id).with_context(|| "failed to parse task ID")
+    };
 
     // HTTP client should not handle redirects automatically.
-    let http_client = ClientBuilder::new()
-        .redirect(reqwest::redirect::Policy::none())
-        .build()?;
+    let http_client = || {
+        ClientBuilder::new()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+    };
 
     match &cli.action {
         Action::Upload {
@@ -94,6 +142,9 @@ async fn main() -> Result<()> {
             helper_url,
             vdaf,
         } => {
+            let task_id = task_id()?;
+            let http_client = http_client()?;
+
             // Read the measurement from stdin.
             let mut buf = String::new();
             stdin()
@@ -147,6 +198,9 @@ async fn main() -> Result<()> {
             Ok(())
         }
         Action::Collect { leader_url } => {
+            let task_id = task_id()?;
+            let http_client = http_client()?;
+
             // Read the batch selector from stdin.
             let mut buf = String::new();
             stdin()
@@ -177,11 +231,20 @@ async fn main() -> Result<()> {
                 )
                 .expect("failed to construct content-type hader"),
             );
-            if let Some(ref token) = cli.bearer_token {
-                headers.insert(
-                    reqwest::header::HeaderName::from_static("dap-auth-token"),
-                    reqwest::header::HeaderValue::from_str(token)?,
-                );
+            match cli.bearer_token {
+                Some(ref token) if cli.authorization_bearer => {
+                    headers.insert(
+                        reqwest::header::AUTHORIZATION,
+                        reqwest::header::HeaderValue::from_str(&format!("Bearer {token}"))?,
+                    );
+                }
+                Some(ref token) => {
+                    headers.insert(
+                        reqwest::header::HeaderName::from_static("dap-auth-token"),
+                        reqwest::header::HeaderValue::from_str(token)?,
+                    );
+                }
+                None => (),
             }
 
             let resp = http_client
@@ -208,7 +271,14 @@ async fn main() -> Result<()> {
             println!("{uri}");
             Ok(())
         }
-        Action::CollectPoll { uri, vdaf } => {
+        Action::CollectPoll {
+            uri,
+            vdaf,
+            collection_log,
+        } => {
+            let task_id = task_id()?;
+            let http_client = http_client()?;
+
             // Read the batch selector from stdin.
             let mut buf = String::new();
             stdin()
@@ -234,17 +304,57 @@ async fn main() -> Result<()> {
                     &task_id,
                     &batch_selector,
                     collect_resp.report_count,
-                    collect_resp.encrypted_agg_shares,
+                    collect_resp.encrypted_agg_shares.clone(),
                     version,
                 )
                 .await?;
 
+            if let Some(path) = collection_log {
+                let mut log: DapCollectionLog = match fs::read_to_string(path) {
+                    Ok(log_str) => serde_json::from_str(&log_str)
+                        .with_context(|| "failed to parse collection log")?,
+                    Err(e) if e.kind() == ErrorKind::NotFound => DapCollectionLog::default(),
+                    Err(e) => return Err(e).with_context(|| "failed to read collection log"),
+                };
+                if let DapCollectionCheck::Inconsistent { previous, current } =
+                    log.check_and_record(&task_id, &batch_selector, &collect_resp, &agg_res)
+                {
+                    return Err(anyhow!(
+                        "Leader served a different result for the same batch selector: previous digest {previous}, current digest {current}"
+                    ));
+                }
+                fs::write(path, serde_json::to_string(&log)?)
+                    .with_context(|| "failed to write collection log")?;
+            }
+
             print!("{}", serde_json::to_string(&agg_res)?);
             Ok(())
         }
+        Action::GenerateHpkeReceiverConfigs {
+            kem,
+            first_config_id,
+            count,
+            not_before,
+            period,
+        } => {
+            let schedule = gen_hpke_receiver_config_schedule(
+                *kem,
+                *first_config_id,
+                *count,
+                *not_before,
+                *period,
+            )?;
+            print!("{}", serde_json::to_string(&schedule)?);
+            Ok(())
+        }
     }
 }
 
+fn parse_hpke_kem_id(kem_str: &str) -> Result<HpkeKemId> {
+    serde_json::from_value(serde_json::Value::String(kem_str.to_string()))
+        .with_context(|| "unrecognized HPKE KEM")
+}
+
 fn parse_id(id_str: &str) -> Result<TaskId> {
     TaskId::try_from_base64url(id_str)
         .ok_or_else(|| anyhow!("failed to decode ID"))
@@ -4,7 +4,7 @@
 //! Definitions and tooling for DAP protocol aborts.
 
 use crate::{
-    messages::{BatchSelector, TaskId, TransitionFailure},
+    messages::{BatchSelector, ReportId, TaskId, TransitionFailure},
     DapError, DapMediaType, DapRequest, DapVersion,
 };
 use prio::codec::CodecError;
@@ -30,10 +30,25 @@ pub enum DapAbort {
     #[error("batchOverlap")]
     BatchOverlap { detail: String, task_id: TaskId },
 
+    /// Batch queried multiple times. Sent in response to a CollectReq or AggregateShareReq for a
+    /// batch that contains reports the Collector has already queried the maximum number of times
+    /// permitted by the task.
+    #[error("batchQueriedMultipleTimes")]
+    BatchQueriedMultipleTimes { detail: String, task_id: TaskId },
+
     /// Internal error.
     #[error("internal error")]
     Internal(#[source] Box<dyn std::error::Error + 'static + Send + Sync>),
 
+    /// The peer aborted a request sent on behalf of the request being handled, e.g., the Helper
+    /// rejected an aggregation job initialized by the Leader. `problem` is the problem details
+    /// document sent by the peer, if any. This is reported as an internal error.
+    #[error("request aborted by peer")]
+    PeerAborted {
+        status: u16,
+        problem: Box<ProblemDetails>,
+    },
+
     /// Invalid batch size (either too small or too large). Sent in response to a CollectReq or
     /// AggregateShareReq.
     #[error("invalidBatchSize")]
@@ -48,20 +63,57 @@ pub enum DapAbort {
     #[error("missingTaskID")]
     MissingTaskId,
 
+    /// The body of the request exceeds the size limit of the endpoint. This is sent with status
+    /// 413.
+    #[error("payloadTooLarge")]
+    PayloadTooLarge { detail: String },
+
     /// Query mismatch. Sent in response to a CollectReq or AggregateShareReq.
     #[error("queryMismatch")]
     QueryMismatch { detail: String, task_id: TaskId },
 
     /// Report rejected. Sent in response to an upload request containing a Report that the Leader
-    /// would reject during the aggregation sub-protocol.
+    /// would reject during the aggregation sub-protocol, for a reason not covered by a more
+    /// specific abort.
     #[error("reportRejected")]
     ReportRejected { detail: String },
 
+    /// Daphne extension: Report replayed. Sent in response to an upload request containing a
+    /// Report with the same ID as a report that was uploaded previously.
+    #[error("reportReplayed")]
+    ReportReplayed,
+
+    /// Daphne extension: Report duplicated. Sent in response to a request that contains more than
+    /// one report with the same ID, e.g., a batched upload or an AggregationJobInitReq.
+    #[error("reportDuplicated")]
+    ReportDuplicated { detail: String },
+
+    /// Daphne extension: Report decryption failed. Sent in response to an upload request
+    /// containing a Report that the Aggregator cannot decrypt, e.g., because it is encrypted to
+    /// an unknown HPKE config.
+    #[error("reportDecryptFailed")]
+    ReportDecryptFailed { detail: String },
+
+    /// Daphne extension: Idempotency key conflict. Sent in response to an upload request whose
+    /// idempotency key was already used for an upload request with a different payload.
+    #[error("idempotencyKeyConflict")]
+    IdempotencyKeyConflict { detail: String },
+
     /// Report too late. Sent in response to an upload request for a task that is known to have
     /// expired.
     #[error("reportTooLate")]
     ReportTooLate,
 
+    /// The request could not be completed at this time, e.g., because the server ran out of time
+    /// to handle it. The client may retry the request later.
+    #[error("retryLater")]
+    RetryLater { detail: String },
+
+    /// The server is overloaded and asks the client to back off for the given number of seconds
+    /// before sending similar requests. This is sent with status 429 and a `Retry-After` header.
+    #[error("overloaded")]
+    Overloaded { detail: String, retry_after: u64 },
+
     /// Round mismatch. The aggregators disagree on the current round of the VDAF preparation protocol.
     /// This abort occurs during the aggregation sub-protocol.
     #[error("roundMismatch")]
@@ -91,6 +143,12 @@ pub enum DapAbort {
         agg_job_id_base64url: String,
     },
 
+    /// Version mismatch. Sent in response to a request whose DAP version does not match the
+    /// version of the task or the version the sender signaled for itself. This usually indicates
+    /// that the peers are configured for different drafts.
+    #[error("versionMismatch")]
+    VersionMismatch { detail: String },
+
     /// Unrecognized message. Sent in response to a malformed or unexpected message.
     #[error("unrecognizedMessage")]
     UnrecognizedMessage,
@@ -101,15 +159,17 @@ pub enum DapAbort {
 }
 
 impl DapAbort {
-    /// Construct a problem details JSON object for this abort. `url` is the URL to which the
-    /// request was targeted and `task_id` is the associated TaskID.
-    pub fn into_problem_details(self) -> ProblemDetails {
+    /// Construct a problem details JSON object for this abort. `instance` identifies the request
+    /// that was aborted, e.g., the path of the request URL.
+    pub fn into_problem_details(self, instance: Option<String>) -> ProblemDetails {
         let (title, typ) = self.title_and_type();
+        let status = self.status_code();
         let (task_id, detail, agg_job_id_base64url) = match self {
             Self::BatchInvalid { detail, task_id }
             | Self::InvalidTask { detail, task_id }
             | Self::BatchMismatch { detail, task_id }
             | Self::BatchOverlap { detail, task_id }
+            | Self::BatchQueriedMultipleTimes { detail, task_id }
             | Self::InvalidBatchSize { detail, task_id }
             | Self::QueryMismatch { detail, task_id }
             | Self::UnauthorizedRequest { detail, task_id } => (Some(task_id), Some(detail), None),
@@ -118,9 +178,15 @@ impl DapAbort {
                 Some("A task ID must be specified in the query parameter of the request.".into()),
                 None,
             ),
-            Self::BadRequest(detail) | Self::ReportRejected { detail } => {
-                (None, Some(detail), None)
-            }
+            Self::BadRequest(detail)
+            | Self::ReportRejected { detail }
+            | Self::ReportDuplicated { detail }
+            | Self::ReportDecryptFailed { detail }
+            | Self::IdempotencyKeyConflict { detail }
+            | Self::RetryLater { detail }
+            | Self::Overloaded { detail, .. }
+            | Self::PayloadTooLarge { detail }
+            | Self::VersionMismatch { detail } => (None, Some(detail), None),
             Self::RoundMismatch {
                 detail,
                 task_id,
@@ -134,19 +200,126 @@ impl DapAbort {
                 Some("The request indicates an aggregation job that does not exist.".into()),
                 Some(agg_job_id_base64url),
             ),
-            Self::ReportTooLate | Self::UnrecognizedMessage | Self::UnrecognizedTask => {
-                (None, None, None)
-            }
+            Self::ReportTooLate => (
+                None,
+                Some("The report pertains to a task that has expired.".into()),
+                None,
+            ),
+            Self::ReportReplayed => (
+                None,
+                Some("A report with the same ID was uploaded previously.".into()),
+                None,
+            ),
+            Self::UnrecognizedMessage => (
+                None,
+                Some("The message is malformed or was not expected.".into()),
+                None,
+            ),
+            Self::UnrecognizedTask => (
+                None,
+                Some("The request indicates a task that does not exist.".into()),
+                None,
+            ),
             Self::Internal(e) => (None, Some(e.to_string()), None),
+            Self::PeerAborted { status, problem } => (
+                None,
+                Some(format!(
+                    "{PEER_ABORT}: status {status}: {}: {}",
+                    problem.typ.as_deref().unwrap_or("unknown"),
+                    problem.detail.unwrap_or_default()
+                )),
+                None,
+            ),
         };
 
         ProblemDetails {
             typ,
             title,
+            status: Some(status),
             task_id: task_id.map(|id| id.to_base64url()),
             agg_job_id: agg_job_id_base64url,
-            instance: None, // TODO interop: Implement as specified.
+            instance,
             detail,
+            correlation_id: None,
+        }
+    }
+
+    /// The HTTP status code of the response that carries this abort.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Self::Internal(..) | Self::PeerAborted { .. } => 500,
+            Self::RetryLater { .. } => 503,
+            Self::Overloaded { .. } => 429,
+            Self::PayloadTooLarge { .. } => 413,
+            _ => 400,
+        }
+    }
+
+    /// The DAP error type of this abort, if any. Aborts that are not defined by DAP, such as
+    /// internal errors, have no type.
+    pub fn abort_type(&self) -> Option<DapAbortType> {
+        match self {
+            Self::BatchInvalid { .. } => Some(DapAbortType::BatchInvalid),
+            Self::BatchMismatch { .. } => Some(DapAbortType::BatchMismatch),
+            Self::BatchOverlap { .. } => Some(DapAbortType::BatchOverlap),
+            Self::BatchQueriedMultipleTimes { .. } => Some(DapAbortType::BatchQueriedMultipleTimes),
+            Self::InvalidBatchSize { .. } => Some(DapAbortType::InvalidBatchSize),
+            Self::InvalidTask { .. } => Some(DapAbortType::InvalidTask),
+            Self::MissingTaskId => Some(DapAbortType::MissingTaskId),
+            Self::QueryMismatch { .. } => Some(DapAbortType::QueryMismatch),
+            Self::ReportRejected { .. } => Some(DapAbortType::ReportRejected),
+            Self::ReportReplayed => Some(DapAbortType::ReportReplayed),
+            Self::ReportDuplicated { .. } => Some(DapAbortType::ReportDuplicated),
+            Self::ReportDecryptFailed { .. } => Some(DapAbortType::ReportDecryptFailed),
+            Self::IdempotencyKeyConflict { .. } => Some(DapAbortType::IdempotencyKeyConflict),
+            Self::ReportTooLate => Some(DapAbortType::ReportTooLate),
+            Self::RoundMismatch { .. } => Some(DapAbortType::RoundMismatch),
+            Self::UnauthorizedRequest { .. } => Some(DapAbortType::UnauthorizedRequest),
+            Self::UnrecognizedAggregationJob { .. } => {
+                Some(DapAbortType::UnrecognizedAggregationJob)
+            }
+            Self::VersionMismatch { .. } => Some(DapAbortType::VersionMismatch),
+            Self::UnrecognizedMessage => Some(DapAbortType::UnrecognizedMessage),
+            Self::UnrecognizedTask => Some(DapAbortType::UnrecognizedTask),
+            Self::BadRequest(..)
+            | Self::RetryLater { .. }
+            | Self::Overloaded { .. }
+            | Self::PayloadTooLarge { .. }
+            | Self::PeerAborted { .. }
+            | Self::Internal(..) => None,
+        }
+    }
+
+    /// Construct the error for a request to the peer that failed with the given HTTP status code.
+    /// `retry_after` is the value of the `Retry-After` header (in seconds), if any, and `problem`
+    /// is the problem details document carried by the response, if any. A 429 response that
+    /// indicates how long to back off signals that the peer is overloaded; any other 429 or 5xx
+    /// response signals a transient error.
+    pub fn from_peer_response(
+        status: u16,
+        retry_after: Option<u64>,
+        problem: Option<ProblemDetails>,
+    ) -> Self {
+        match (status, retry_after) {
+            (429, Some(retry_after)) => Self::Overloaded {
+                detail: format!("{PEER_ABORT}: status {status}"),
+                retry_after,
+            },
+            (429 | 500..=599, _) => Self::RetryLater {
+                detail: format!("{PEER_ABORT}: status {status}"),
+            },
+            _ => Self::PeerAborted {
+                status,
+                problem: Box::new(problem.unwrap_or_default()),
+            },
+        }
+    }
+
+    /// The DAP error type indicated by the peer, if this error is the peer's abort.
+    pub fn peer_abort_type(&self) -> Option<DapAbortType> {
+        match self {
+            Self::PeerAborted { problem, .. } => problem.abort_type(),
+            _ => None,
         }
     }
 
@@ -167,9 +340,20 @@ impl DapAbort {
 
     #[inline]
     pub(crate) fn version_mismatch(indicated: DapVersion, expected: DapVersion) -> Self {
-        DapAbort::BadRequest(format!(
-            "DAP version of request does not match task: got {indicated:?}; want {expected:?}"
-        ))
+        DapAbort::VersionMismatch {
+            detail: format!(
+                "DAP version of request does not match task: got {indicated:?}; want {expected:?}"
+            ),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn sender_version_mismatch(sender: DapVersion, endpoint: DapVersion) -> Self {
+        DapAbort::VersionMismatch {
+            detail: format!(
+                "DAP version signaled by the sender does not match the endpoint: got {sender:?}; want {endpoint:?}"
+            ),
+        }
     }
 
     #[inline]
@@ -188,6 +372,21 @@ impl DapAbort {
         }
     }
 
+    #[inline]
+    pub(crate) fn batch_queried_multiple_times(
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        max_batch_query_count: u64,
+    ) -> Self {
+        Self::BatchQueriedMultipleTimes {
+            detail: format!(
+                "The batch indicated by the request has been queried {max_batch_query_count} times, the maximum permitted by the task: {}",
+                serde_json::to_string(batch_sel).expect("failed to JSON-encode the batch selector while constructing a \"batchQueriedMultipleTimes\" abort"),
+            ),
+            task_id: task_id.clone(),
+        }
+    }
+
     #[inline]
     pub(crate) fn query_mismatch(
         task_id: &TaskId,
@@ -200,72 +399,79 @@ impl DapAbort {
         }
     }
 
+    /// Construct the abort for an upload request containing a report that the Leader would
+    /// reject during the aggregation sub-protocol with the given failure.
     #[inline]
     pub fn report_rejected(failure_reason: TransitionFailure) -> Self {
-        let detail = match failure_reason {
-            TransitionFailure::BatchCollected => {
-                "The report pertains to a batch that has already been collected."
-            }
-            TransitionFailure::ReportReplayed => {
-                "A report with the same ID was uploaded previously."
-            }
-            _ => return DapError::Fatal(format!("Attempted to construct a \"reportRejected\" abort with unexpected transition failure: {failure_reason:?}")).into(),
-        };
-
-        Self::ReportRejected {
-            detail: detail.into(),
+        match failure_reason {
+            TransitionFailure::BatchCollected => Self::ReportRejected {
+                detail: "The report pertains to a batch that has already been collected.".into(),
+            },
+            TransitionFailure::ReportReplayed => Self::ReportReplayed,
+            TransitionFailure::TaskExpired => Self::ReportTooLate,
+            TransitionFailure::HpkeUnknownConfigId => Self::ReportDecryptFailed {
+                detail: "No current HPKE configuration matches the indicated ID.".into(),
+            },
+            TransitionFailure::HpkeDecryptError => Self::ReportDecryptFailed {
+                detail: "The report could not be decrypted.".into(),
+            },
+            _ => DapError::Fatal(format!("Attempted to construct a \"reportRejected\" abort with unexpected transition failure: {failure_reason:?}")).into(),
         }
     }
 
-    fn title_and_type(&self) -> (String, Option<String>) {
-        let (title, dap_abort_type) = match self {
-            Self::BatchInvalid { .. } => ("Batch boundary check failed", Some(self.to_string())),
-            Self::BatchMismatch { .. } => (
-                "Aggregators disagree on the set of reports in the batch",
-                Some(self.to_string()),
-            ),
-            Self::BatchOverlap { .. } => (
-                "The selected batch overlaps with a previous batch",
-                Some(self.to_string()),
-            ),
-            Self::InvalidBatchSize { .. } => ("Batch size is invalid", Some(self.to_string())),
-            Self::InvalidTask { .. } => ("Opted out of Taskprov task", Some(self.to_string())),
-            Self::QueryMismatch { .. } => {
-                ("Query type does not match the task", Some(self.to_string()))
-            }
-            Self::RoundMismatch { .. } => (
-                "Aggregation round indicated by peer does not match host",
-                Some(self.to_string()),
-            ),
-            Self::MissingTaskId => (
-                "Request for HPKE configuration with unspecified task",
-                Some(self.to_string()),
+    #[inline]
+    pub(crate) fn report_duplicated(report_id: &ReportId) -> Self {
+        Self::ReportDuplicated {
+            detail: format!(
+                "The request contains more than one report with ID {}.",
+                report_id.to_base64url()
             ),
-            Self::ReportRejected { .. } => ("Report rejected", Some(self.to_string())),
-            Self::ReportTooLate => (
-                "The requested task expires after report timestamp",
-                Some(self.to_string()),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn idempotency_key_conflict(idempotency_key: &str) -> Self {
+        Self::IdempotencyKeyConflict {
+            detail: format!(
+                "The idempotency key {idempotency_key} was used for a request with a different payload."
             ),
-            Self::UnauthorizedRequest { .. } => {
-                ("Request authorization failed", Some(self.to_string()))
-            }
-            Self::UnrecognizedAggregationJob { .. } => {
-                ("Unrecognized aggregation job", Some(self.to_string()))
-            }
-            Self::UnrecognizedMessage => {
-                ("Failed to parse the request body", Some(self.to_string()))
+        }
+    }
+
+    fn title_and_type(&self) -> (String, Option<String>) {
+        let title = match self {
+            Self::BatchInvalid { .. } => "Batch boundary check failed",
+            Self::BatchMismatch { .. } => "Aggregators disagree on the set of reports in the batch",
+            Self::BatchOverlap { .. } => "The selected batch overlaps with a previous batch",
+            Self::BatchQueriedMultipleTimes { .. } => {
+                "The selected batch was queried too many times"
             }
-            Self::UnrecognizedTask => (
-                "Task indicated by request is not recognized",
-                Some(self.to_string()),
-            ),
-            Self::BadRequest(..) => ("Bad request", None),
-            Self::Internal(..) => ("Internal server error", None),
+            Self::InvalidBatchSize { .. } => "Batch size is invalid",
+            Self::InvalidTask { .. } => "Opted out of Taskprov task",
+            Self::QueryMismatch { .. } => "Query type does not match the task",
+            Self::RoundMismatch { .. } => "Aggregation round indicated by peer does not match host",
+            Self::MissingTaskId => "Request for HPKE configuration with unspecified task",
+            Self::ReportRejected { .. } => "Report rejected",
+            Self::ReportReplayed => "Report replayed",
+            Self::ReportDuplicated { .. } => "Report appears more than once in the request",
+            Self::ReportDecryptFailed { .. } => "Report could not be decrypted",
+            Self::IdempotencyKeyConflict { .. } => "Idempotency key was used for another request",
+            Self::ReportTooLate => "The requested task expires after report timestamp",
+            Self::UnauthorizedRequest { .. } => "Request authorization failed",
+            Self::VersionMismatch { .. } => "DAP version of the request does not match",
+            Self::UnrecognizedAggregationJob { .. } => "Unrecognized aggregation job",
+            Self::UnrecognizedMessage => "Failed to parse the request body",
+            Self::UnrecognizedTask => "Task indicated by request is not recognized",
+            Self::BadRequest(..) => "Bad request",
+            Self::RetryLater { .. } => "Service unavailable, retry later",
+            Self::Overloaded { .. } => "Too many requests, retry later",
+            Self::PayloadTooLarge { .. } => "Request body is too large",
+            Self::Internal(..) | Self::PeerAborted { .. } => "Internal server error",
         };
 
         (
             title.to_string(),
-            dap_abort_type.map(|t| format!("urn:ietf:params:ppm:dap:error:{t}")),
+            self.abort_type().map(|abort_type| abort_type.to_urn()),
         )
     }
 }
@@ -286,21 +492,158 @@ impl From<CodecError> for DapAbort {
     }
 }
 
-/// A problem details document compatible with RFC 7807.
-#[derive(Debug, Deserialize, Serialize)]
+/// Prefix of the detail of an error caused by the peer's response.
+const PEER_ABORT: &str = "request aborted by peer";
+
+/// Prefix of the URN of each DAP error type.
+const DAP_ABORT_TYPE_URN_PREFIX: &str = "urn:ietf:params:ppm:dap:error:";
+
+/// Prefix of the URN of each error type that is a Daphne extension to DAP.
+const DAPHNE_ABORT_TYPE_URN_PREFIX: &str = "urn:daphne:error:";
+
+/// The type of a DAP abort, as indicated by the "type" field of a problem details document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DapAbortType {
+    BatchInvalid,
+    BatchMismatch,
+    BatchOverlap,
+    BatchQueriedMultipleTimes,
+    InvalidBatchSize,
+    InvalidTask,
+    MissingTaskId,
+    QueryMismatch,
+    ReportRejected,
+    ReportReplayed,
+    ReportDuplicated,
+    ReportDecryptFailed,
+    IdempotencyKeyConflict,
+    ReportTooLate,
+    RoundMismatch,
+    UnauthorizedRequest,
+    UnrecognizedAggregationJob,
+    UnrecognizedMessage,
+    UnrecognizedTask,
+    VersionMismatch,
+}
+
+impl DapAbortType {
+    const ALL: [Self; 20] = [
+        Self::BatchInvalid,
+        Self::BatchMismatch,
+        Self::BatchOverlap,
+        Self::BatchQueriedMultipleTimes,
+        Self::InvalidBatchSize,
+        Self::InvalidTask,
+        Self::MissingTaskId,
+        Self::QueryMismatch,
+        Self::ReportRejected,
+        Self::ReportReplayed,
+        Self::ReportDuplicated,
+        Self::ReportDecryptFailed,
+        Self::IdempotencyKeyConflict,
+        Self::ReportTooLate,
+        Self::RoundMismatch,
+        Self::UnauthorizedRequest,
+        Self::UnrecognizedAggregationJob,
+        Self::UnrecognizedMessage,
+        Self::UnrecognizedTask,
+        Self::VersionMismatch,
+    ];
+
+    /// The name of the error type, e.g., "reportRejected".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BatchInvalid => "batchInvalid",
+            Self::BatchMismatch => "batchMismatch",
+            Self::BatchOverlap => "batchOverlap",
+            Self::BatchQueriedMultipleTimes => "batchQueriedMultipleTimes",
+            Self::InvalidBatchSize => "invalidBatchSize",
+            Self::InvalidTask => "invalidTask",
+            Self::MissingTaskId => "missingTaskID",
+            Self::QueryMismatch => "queryMismatch",
+            Self::ReportRejected => "reportRejected",
+            Self::ReportReplayed => "reportReplayed",
+            Self::ReportDuplicated => "reportDuplicated",
+            Self::ReportDecryptFailed => "reportDecryptFailed",
+            Self::IdempotencyKeyConflict => "idempotencyKeyConflict",
+            Self::ReportTooLate => "reportTooLate",
+            Self::RoundMismatch => "roundMismatch",
+            Self::UnauthorizedRequest => "unauthorizedRequest",
+            Self::UnrecognizedAggregationJob => "unrecognizedAggregationJob",
+            Self::UnrecognizedMessage => "unrecognizedMessage",
+            Self::UnrecognizedTask => "unrecognizedTask",
+            Self::VersionMismatch => "versionMismatch",
+        }
+    }
+
+    /// Whether the error type is a Daphne extension rather than one defined by DAP. The extension
+    /// types refine "reportRejected".
+    pub fn is_extension(&self) -> bool {
+        matches!(
+            self,
+            Self::ReportReplayed
+                | Self::ReportDuplicated
+                | Self::ReportDecryptFailed
+                | Self::IdempotencyKeyConflict
+        )
+    }
+
+    /// The URN of the error type, e.g., "urn:ietf:params:ppm:dap:error:reportRejected" or, for an
+    /// extension, "urn:daphne:error:reportReplayed".
+    pub fn to_urn(&self) -> String {
+        let prefix = if self.is_extension() {
+            DAPHNE_ABORT_TYPE_URN_PREFIX
+        } else {
+            DAP_ABORT_TYPE_URN_PREFIX
+        };
+        format!("{prefix}{}", self.as_str())
+    }
+
+    /// Parse the URN of an error type. Returns `None` if the URN does not indicate a DAP error
+    /// type or a Daphne extension.
+    pub fn from_urn(urn: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|abort_type| abort_type.to_urn() == urn)
+    }
+}
+
+/// A problem details document compatible with RFC 7807. Each member is optional when parsing, so
+/// that documents sent by other implementations can be handled.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ProblemDetails {
-    #[serde(rename = "taskid")]
-    pub title: String,
     #[serde(rename = "type")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub typ: Option<String>,
+    #[serde(default)]
+    pub title: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) task_id: Option<String>,
-    #[serde(rename = "aggregationjobid")]
+    pub status: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) agg_job_id: Option<String>,
+    pub detail: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) instance: Option<String>,
+    pub instance: Option<String>,
+    #[serde(rename = "taskid")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub detail: Option<String>,
+    pub task_id: Option<String>,
+    #[serde(rename = "aggregationjobid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agg_job_id: Option<String>,
+    /// Identifies the request, and any requests made on its behalf, in the logs of the
+    /// Aggregators. This is a Daphne extension member.
+    #[serde(rename = "correlationid")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+impl ProblemDetails {
+    /// The DAP error type indicated by the document, if any.
+    pub fn abort_type(&self) -> Option<DapAbortType> {
+        self.typ.as_deref().and_then(DapAbortType::from_urn)
+    }
+
+    /// The task indicated by the document, if any and if well-formed.
+    pub fn parse_task_id(&self) -> Option<TaskId> {
+        self.task_id.as_deref().and_then(TaskId::try_from_base64url)
+    }
 }
@@ -0,0 +1,217 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    aborts::{DapAbort, DapAbortType, ProblemDetails},
+    messages::{TaskId, TransitionFailure},
+    DapError,
+};
+use assert_matches::assert_matches;
+
+#[test]
+fn problem_details_json() {
+    let task_id = TaskId([1; 32]);
+    let problem_details = DapAbort::ReportRejected {
+        detail: "report already collected".into(),
+    }
+    .into_problem_details(Some("/v06/tasks/abc/reports".into()));
+    let json = serde_json::to_value(&problem_details).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "type": "urn:ietf:params:ppm:dap:error:reportRejected",
+            "title": "Report rejected",
+            "status": 400,
+            "detail": "report already collected",
+            "instance": "/v06/tasks/abc/reports",
+        })
+    );
+
+    let problem_details = DapAbort::BatchInvalid {
+        detail: "bad batch".into(),
+        task_id: task_id.clone(),
+    }
+    .into_problem_details(None);
+    let json = serde_json::to_value(&problem_details).unwrap();
+    assert_eq!(json["taskid"], task_id.to_base64url());
+    assert_eq!(json["title"], "Batch boundary check failed");
+    assert!(json.get("instance").is_none());
+}
+
+#[test]
+fn problem_details_roundtrip() {
+    let task_id = TaskId([7; 32]);
+    let json = serde_json::to_string(
+        &DapAbort::UnauthorizedRequest {
+            detail: "missing token".into(),
+            task_id: task_id.clone(),
+        }
+        .into_problem_details(Some("/v06/tasks/xyz/aggregation_jobs/abc".into())),
+    )
+    .unwrap();
+
+    let problem_details: ProblemDetails = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        problem_details.abort_type(),
+        Some(DapAbortType::UnauthorizedRequest)
+    );
+    assert_eq!(problem_details.parse_task_id(), Some(task_id));
+    assert_eq!(problem_details.status, Some(400));
+    assert_eq!(problem_details.detail.as_deref(), Some("missing token"));
+}
+
+#[test]
+fn problem_details_parse_minimal() {
+    let problem_details: ProblemDetails =
+        serde_json::from_str(r#"{"type": "urn:ietf:params:ppm:dap:error:missingTaskID"}"#).unwrap();
+    assert_eq!(
+        problem_details.abort_type(),
+        Some(DapAbortType::MissingTaskId)
+    );
+    assert_eq!(problem_details.title, "");
+    assert_eq!(problem_details.parse_task_id(), None);
+
+    let problem_details: ProblemDetails =
+        serde_json::from_str(r#"{"type": "about:blank", "title": "Not Found"}"#).unwrap();
+    assert_eq!(problem_details.abort_type(), None);
+}
+
+#[test]
+fn abort_type_urn() {
+    assert_eq!(
+        DapAbortType::from_urn("urn:ietf:params:ppm:dap:error:unrecognizedAggregationJob"),
+        Some(DapAbortType::UnrecognizedAggregationJob)
+    );
+    assert_eq!(
+        DapAbortType::from_urn("urn:ietf:params:ppm:dap:error:notAnError"),
+        None
+    );
+    assert_eq!(DapAbortType::from_urn("reportRejected"), None);
+
+    let abort = DapAbort::ReportTooLate;
+    assert_eq!(
+        abort
+            .abort_type()
+            .map(|abort_type| abort_type.as_str().to_string()),
+        Some(abort.to_string())
+    );
+}
+
+#[test]
+fn report_rejected_abort_type() {
+    // The reasons for rejecting an upload that have their own problem type.
+    for (failure, abort_type) in [
+        (
+            TransitionFailure::BatchCollected,
+            DapAbortType::ReportRejected,
+        ),
+        (
+            TransitionFailure::ReportReplayed,
+            DapAbortType::ReportReplayed,
+        ),
+        (TransitionFailure::TaskExpired, DapAbortType::ReportTooLate),
+        (
+            TransitionFailure::HpkeUnknownConfigId,
+            DapAbortType::ReportDecryptFailed,
+        ),
+        (
+            TransitionFailure::HpkeDecryptError,
+            DapAbortType::ReportDecryptFailed,
+        ),
+    ] {
+        let abort = DapAbort::report_rejected(failure);
+        assert_eq!(abort.abort_type(), Some(abort_type));
+        assert_eq!(abort.to_string(), abort_type.as_str());
+    }
+    assert_eq!(
+        DapAbort::report_rejected(TransitionFailure::VdafPrepError).abort_type(),
+        None
+    );
+
+    // Daphne's extensions are not in the DAP namespace.
+    assert_eq!(
+        DapAbortType::ReportDuplicated.to_urn(),
+        "urn:daphne:error:reportDuplicated"
+    );
+    assert_eq!(
+        DapAbortType::from_urn("urn:daphne:error:reportDuplicated"),
+        Some(DapAbortType::ReportDuplicated)
+    );
+    assert_eq!(
+        DapAbortType::from_urn("urn:ietf:params:ppm:dap:error:reportDuplicated"),
+        None
+    );
+}
+
+#[test]
+fn internal_abort_status() {
+    let abort = DapAbort::from(DapError::Fatal("oops".into()));
+    assert_eq!(abort.status_code(), 500);
+    assert_eq!(abort.abort_type(), None);
+    let problem_details = abort.into_problem_details(None);
+    assert_eq!(problem_details.typ, None);
+    assert_eq!(problem_details.status, Some(500));
+}
+
+#[test]
+fn overloaded_abort_status() {
+    let abort = DapAbort::Overloaded {
+        detail: "too many aggregation jobs".into(),
+        retry_after: 60,
+    };
+    assert_eq!(abort.status_code(), 429);
+    assert_eq!(abort.abort_type(), None);
+    let problem_details = abort.into_problem_details(None);
+    assert_eq!(problem_details.typ, None);
+    assert_eq!(problem_details.status, Some(429));
+    assert_eq!(
+        problem_details.detail.as_deref(),
+        Some("too many aggregation jobs")
+    );
+}
+
+#[test]
+fn payload_too_large_abort_status() {
+    let abort = DapAbort::PayloadTooLarge {
+        detail: "request body of 2048 bytes exceeds the limit of 1024".into(),
+    };
+    assert_eq!(abort.status_code(), 413);
+    assert_eq!(abort.abort_type(), None);
+    let problem_details = abort.into_problem_details(None);
+    assert_eq!(problem_details.typ, None);
+    assert_eq!(problem_details.status, Some(413));
+}
+
+#[test]
+fn peer_response_abort() {
+    assert_matches!(
+        DapAbort::from_peer_response(429, Some(60), None),
+        DapAbort::Overloaded {
+            retry_after: 60,
+            ..
+        }
+    );
+    assert_matches!(
+        DapAbort::from_peer_response(429, None, None),
+        DapAbort::RetryLater { .. }
+    );
+    assert_matches!(
+        DapAbort::from_peer_response(503, Some(60), None),
+        DapAbort::RetryLater { .. }
+    );
+
+    let problem = DapAbort::UnrecognizedTask.into_problem_details(None);
+    let abort = DapAbort::from_peer_response(400, None, Some(problem));
+    assert_eq!(
+        abort.peer_abort_type(),
+        Some(DapAbortType::UnrecognizedTask)
+    );
+    assert_eq!(abort.status_code(), 500);
+    assert_eq!(abort.abort_type(), None);
+    let problem_details = abort.into_problem_details(None);
+    assert_eq!(problem_details.typ, None);
+    assert_eq!(
+        problem_details.detail.as_deref(),
+        Some("request aborted by peer: status 400: urn:ietf:params:ppm:dap:error:unrecognizedTask: The request indicates a task that does not exist.")
+    );
+}
@@ -9,6 +9,7 @@ use crate::{
     DapError, DapRequest, DapSender,
 };
 use async_trait::async_trait;
+use ring::hmac;
 use serde::{Deserialize, Serialize};
 
 /// A bearer token used for authorizing DAP requests.
@@ -49,6 +50,122 @@ impl AsRef<BearerToken> for BearerToken {
     }
 }
 
+/// How the Clients of a task authenticate their upload requests to the Leader. See
+/// [`DapTaskConfig::upload_auth`](crate::DapTaskConfig::upload_auth).
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DapUploadAuth {
+    /// Each Client presents this bearer token.
+    BearerToken(String),
+
+    /// Each Client signs its upload request with HMAC-SHA256 under this key. The tag covers the
+    /// task ID followed by the request payload.
+    HmacSha256(#[serde(with = "hex")] Vec<u8>),
+}
+
+/// The credential presented by a Client in an upload request.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DapClientAuth {
+    /// A bearer token.
+    BearerToken(BearerToken),
+
+    /// An HMAC-SHA256 tag over the task ID and request payload.
+    Signature(Vec<u8>),
+}
+
+impl DapUploadAuth {
+    /// Check that the parameters are well-formed.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::BearerToken(token) if token.is_empty() => {
+                Err("upload authentication: bearer token is empty".into())
+            }
+            Self::HmacSha256(key) if key.is_empty() => {
+                Err("upload authentication: HMAC key is empty".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Client: Produce the credential for an upload request with the given task ID and payload.
+    pub fn authorize(&self, task_id: &TaskId, payload: &[u8]) -> DapClientAuth {
+        match self {
+            Self::BearerToken(token) => {
+                DapClientAuth::BearerToken(BearerToken::from(token.as_str()))
+            }
+            Self::HmacSha256(key) => {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+                let mut ctx = hmac::Context::with_key(&key);
+                ctx.update(task_id.as_ref());
+                ctx.update(payload);
+                DapClientAuth::Signature(ctx.sign().as_ref().to_vec())
+            }
+        }
+    }
+
+    /// Leader: Decide whether an upload request with the given task ID, payload, and credential is
+    /// authorized. Return `None` if the request is authorized. Otherwise return `Some(reason)`,
+    /// where `reason` is the reason for the failure.
+    pub fn unauthorized_reason(
+        &self,
+        task_id: &TaskId,
+        payload: &[u8],
+        client_auth: Option<&DapClientAuth>,
+    ) -> Option<String> {
+        match (self, client_auth) {
+            (Self::BearerToken(expected), Some(DapClientAuth::BearerToken(got))) => {
+                if got == &BearerToken::from(expected.as_str()) {
+                    None
+                } else {
+                    Some("The indicated bearer token is incorrect for the Client.".into())
+                }
+            }
+            (Self::HmacSha256(key), Some(DapClientAuth::Signature(tag))) => {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+                let mut message = Vec::with_capacity(task_id.as_ref().len() + payload.len());
+                message.extend_from_slice(task_id.as_ref());
+                message.extend_from_slice(payload);
+                if hmac::verify(&key, &message, tag).is_ok() {
+                    None
+                } else {
+                    Some("The indicated signature is incorrect for the Client.".into())
+                }
+            }
+            (Self::BearerToken(..), _) => {
+                Some("The task requires Clients to present a bearer token.".into())
+            }
+            (Self::HmacSha256(..), _) => {
+                Some("The task requires Clients to sign their requests.".into())
+            }
+        }
+    }
+}
+
+/// A method of authenticating the sender of a request from one DAP party to another. `S` is the
+/// type of the credential carried by the request.
+///
+/// Bearer tokens are supported by [`BearerTokenProvider`], whose methods can be used to implement
+/// this trait. Other methods, such as an identity asserted by a proxy that terminates
+/// mutually-authenticated TLS, a signature over the request, or a JWT issued by an access
+/// gateway, can be plugged in by choosing a different credential type.
+#[async_trait(?Send)]
+pub trait DapSenderAuth<S> {
+    /// Add authorization to an outbound DAP request with the given task ID, media type, and payload.
+    async fn authorize(
+        &self,
+        task_id: &TaskId,
+        media_type: &DapMediaType,
+        payload: &[u8],
+    ) -> Result<S, DapError>;
+
+    /// Decide whether the given inbound DAP request is authorized.
+    ///
+    /// If the return value is `None`, then the request is authorized. If the return value is
+    /// `Some(reason)`, then the request is denied and `reason` conveys details about how the
+    /// decision was reached.
+    async fn unauthorized_reason(&self, req: &DapRequest<S>) -> Result<Option<String>, DapError>;
+}
+
 /// A source of bearer tokens used for authorizing DAP requests.
 #[async_trait(?Send)]
 pub trait BearerTokenProvider<'a> {
@@ -67,6 +184,19 @@ pub trait BearerTokenProvider<'a> {
         task_id: &'a TaskId,
     ) -> Result<Option<Self::WrappedBearerToken>, DapError>;
 
+    /// Fetch the bearer token of the task's Collector with the given ID, if the task and Collector
+    /// are recognized. See
+    /// [`DapTaskConfig::additional_collectors`](crate::DapTaskConfig::additional_collectors).
+    ///
+    /// The default implementation does not recognize any Collector other than the primary one.
+    async fn get_additional_collector_bearer_token_for(
+        &'a self,
+        _task_id: &'a TaskId,
+        _collector_id: &'a str,
+    ) -> Result<Option<BearerToken>, DapError> {
+        Ok(None)
+    }
+
     /// Returns true if the given bearer token matches the leader token configured for the "taskprov" extension.
     fn is_taskprov_leader_bearer_token(&self, token: &BearerToken) -> bool;
 
@@ -134,6 +264,23 @@ pub trait BearerTokenProvider<'a> {
 
         if matches!(req.media_type.sender(), Some(DapSender::Collector)) {
             if let Some(ref got) = req.sender_auth {
+                if let Some(ref collector_id) = req.collector_id {
+                    return Ok(
+                        match self
+                            .get_additional_collector_bearer_token_for(task_id, collector_id)
+                            .await?
+                        {
+                            Some(expected) if got.as_ref() == &expected => None,
+                            Some(_) => Some(format!(
+                                "The indicated bearer token is incorrect for Collector \"{collector_id}\"."
+                            )),
+                            None => Some(format!(
+                                "Collector \"{collector_id}\" is not recognized for this task."
+                            )),
+                        },
+                    );
+                }
+
                 if let Some(expected) = self.get_collector_bearer_token_for(task_id).await? {
                     return Ok(if got.as_ref() == expected.as_ref() {
                         None
@@ -0,0 +1,577 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Client-side tooling for uploading reports.
+//!
+//! [`DapClient`] implements the Client role: it fetches the HPKE configs advertised by the Leader
+//! and Helper for a task, validates and caches them, then produces reports and uploads them to the
+//! Leader. The HTTP transport is provided by the caller via [`DapClientHttpClient`].
+//!
+//! If the Client is configured with an Aggregator's HPKE config verification key, then it refuses
+//! the Aggregator's configs unless they are signed with that key (see
+//! [`sign_hpke_configs`](crate::receipt::sign_hpke_configs)).
+//!
+//! If the Client is configured with a [`DapClientRetryPolicy`], then upload requests that fail
+//! transiently are retried. Each attempt carries the same idempotency key, so that the Leader
+//! accepts a retry of a request it has already accepted instead of rejecting the report as
+//! replayed.
+
+use crate::{
+    aborts::ProblemDetails,
+    auth::DapUploadAuth,
+    constants::DapMediaType,
+    extensions::DapReportExtensions,
+    messages::{
+        encode_base64url, HpkeConfig, HpkeConfigList, HpkeKemId, Report, ReportBatch, TaskId, Time,
+    },
+    receipt::{verify_hpke_configs, DapUploadReceipt},
+    DapError, DapMeasurement, DapReportUploadStatus, DapRequest, DapResource, DapTaskConfig,
+    DapVersion, VdafConfig,
+};
+use async_trait::async_trait;
+use prio::codec::{Decode, Encode, ParameterizedEncode};
+use ring::digest::{digest, SHA256};
+use std::cell::RefCell;
+use tracing::warn;
+use url::Url;
+
+/// HTTP method of a request sent by the Client.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DapClientHttpMethod {
+    Get,
+    Post,
+    Put,
+}
+
+/// Response to an HTTP request sent by the Client.
+#[derive(Debug)]
+pub struct DapClientHttpResponse {
+    /// HTTP status code.
+    pub status: u16,
+
+    /// Response payload.
+    pub payload: Vec<u8>,
+
+    /// Signature over the HPKE configs in the response to `GET /hpke_config`, decoded from the
+    /// URL-safe base64 "x-daphne-hpke-config-signature" header, if present.
+    pub hpke_config_signature: Option<Vec<u8>>,
+}
+
+/// HTTP client used by [`DapClient`] to talk to the Aggregators.
+#[async_trait(?Send)]
+pub trait DapClientHttpClient {
+    /// Send an HTTP request to an Aggregator. For requests with a payload, the "content-type"
+    /// header is determined by the request's media type. If the request carries Client
+    /// authorization, then a bearer token is sent in the "authorization" header and a signature
+    /// is sent hex-encoded in the "dap-client-signature" header. The request's idempotency key, if
+    /// any, is sent in the "dap-idempotency-key" header.
+    async fn send_http(
+        &self,
+        method: DapClientHttpMethod,
+        req: DapRequest<()>,
+    ) -> Result<DapClientHttpResponse, DapError>;
+
+    /// Wait for the given number of milliseconds before a request is retried. The default
+    /// implementation returns immediately; implement it with the runtime's timer in order to back
+    /// off between attempts.
+    async fn sleep(&self, _millis: u64) {}
+}
+
+/// Policy for retrying upload requests that fail transiently, i.e., that could not be sent or to
+/// which the Leader responded with status 429 or 5xx.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DapClientRetryPolicy {
+    /// Maximum number of attempts of each request, including the first.
+    pub max_attempts: u32,
+
+    /// Time to wait before the first retry, in milliseconds. The time doubles with each retry.
+    pub initial_backoff_ms: u64,
+
+    /// Maximum time to wait between attempts, in milliseconds.
+    pub max_backoff_ms: u64,
+}
+
+impl Default for DapClientRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+/// The HPKE configs of the Leader and Helper, as cached by [`DapClient`].
+#[derive(Clone, Debug)]
+struct CachedHpkeConfigs {
+    configs: [HpkeConfig; 2],
+    fetched_at: Time,
+}
+
+/// The Client role for a given task.
+pub struct DapClient {
+    version: DapVersion,
+    task_id: TaskId,
+    leader_url: Url,
+    helper_url: Url,
+    vdaf: VdafConfig,
+    hpke_config_ttl: u64,
+    hpke_configs: RefCell<Option<CachedHpkeConfigs>>,
+    upload_auth: Option<DapUploadAuth>,
+    hpke_config_public_keys: [Option<Vec<u8>>; 2],
+    upload_retry_policy: Option<DapClientRetryPolicy>,
+}
+
+impl DapClient {
+    /// Create a Client for the given task. `leader_url` and `helper_url` are the Aggregators' base
+    /// URLs for the DAP version (e.g., "https://leader.com/v04/").
+    pub fn new(
+        version: DapVersion,
+        task_id: TaskId,
+        leader_url: Url,
+        helper_url: Url,
+        vdaf: VdafConfig,
+    ) -> Self {
+        Self {
+            version,
+            task_id,
+            leader_url,
+            helper_url,
+            vdaf,
+            hpke_config_ttl: 3600,
+            hpke_configs: RefCell::new(None),
+            upload_auth: None,
+            hpke_config_public_keys: [None, None],
+            upload_retry_policy: None,
+        }
+    }
+
+    /// Create a Client for the task with the given configuration. If the task requires upload
+    /// authentication, then the Client authenticates with the task's credential.
+    pub fn from_task_config(task_id: TaskId, task_config: &DapTaskConfig) -> Self {
+        let client = Self::new(
+            task_config.version,
+            task_id,
+            task_config.leader_url.clone(),
+            task_config.helper_url.clone(),
+            task_config.vdaf.clone(),
+        );
+        match &task_config.upload_auth {
+            Some(upload_auth) => client.with_upload_auth(upload_auth.clone()),
+            None => client,
+        }
+    }
+
+    /// Authenticate each upload request to the Leader with the given credential. See
+    /// [`DapTaskConfig::upload_auth`].
+    pub fn with_upload_auth(mut self, upload_auth: DapUploadAuth) -> Self {
+        self.upload_auth = Some(upload_auth);
+        self
+    }
+
+    /// Require the HPKE configs advertised by the Leader to be signed with the given Ed25519 key.
+    pub fn with_leader_hpke_config_public_key(mut self, public_key: Vec<u8>) -> Self {
+        self.hpke_config_public_keys[0] = Some(public_key);
+        self
+    }
+
+    /// Require the HPKE configs advertised by the Helper to be signed with the given Ed25519 key.
+    pub fn with_helper_hpke_config_public_key(mut self, public_key: Vec<u8>) -> Self {
+        self.hpke_config_public_keys[1] = Some(public_key);
+        self
+    }
+
+    /// Refetch the HPKE configs once they have been cached for `ttl` seconds.
+    pub fn with_hpke_config_ttl(mut self, ttl: u64) -> Self {
+        self.hpke_config_ttl = ttl;
+        self
+    }
+
+    /// Retry upload requests that fail transiently according to the given policy. The idempotency
+    /// key of a request is the ID of the report or, for a batched upload, derived from the batch.
+    pub fn with_upload_retry_policy(mut self, policy: DapClientRetryPolicy) -> Self {
+        self.upload_retry_policy = Some(policy);
+        self
+    }
+
+    /// Get the HPKE configs to use for the Leader and Helper, in that order. The configs are
+    /// fetched from the Aggregators unless they were cached less than the TTL ago.
+    pub async fn hpke_configs(
+        &self,
+        http: &impl DapClientHttpClient,
+        now: Time,
+    ) -> Result<[HpkeConfig; 2], DapError> {
+        if let Some(cached) = self.hpke_configs.borrow().as_ref() {
+            if now < cached.fetched_at.saturating_add(self.hpke_config_ttl) {
+                return Ok(cached.configs.clone());
+            }
+        }
+
+        let configs = [
+            self.fetch_hpke_config(
+                http,
+                &self.leader_url,
+                "Leader",
+                self.hpke_config_public_keys[0].as_deref(),
+            )
+            .await?,
+            self.fetch_hpke_config(
+                http,
+                &self.helper_url,
+                "Helper",
+                self.hpke_config_public_keys[1].as_deref(),
+            )
+            .await?,
+        ];
+        *self.hpke_configs.borrow_mut() = Some(CachedHpkeConfigs {
+            configs: configs.clone(),
+            fetched_at: now,
+        });
+        Ok(configs)
+    }
+
+    /// Drop the cached HPKE configs so that they are fetched again before the next report is
+    /// produced, e.g., because an Aggregator has rotated its config.
+    pub fn invalidate_hpke_configs(&self) {
+        *self.hpke_configs.borrow_mut() = None;
+    }
+
+    /// Produce a report for the given measurement with the given extensions. The measurement is
+    /// checked against the task's VDAF before anything is sent (see
+    /// [`VdafConfig::check_measurement`]). A list of extensions
+    /// is seen by both Aggregators; use [`DapReportExtensions`] to address an extension to a single
+    /// Aggregator. Each extension is placed where the task's DAP version expects it.
+    pub async fn produce_report(
+        &self,
+        http: &impl DapClientHttpClient,
+        now: Time,
+        measurement: DapMeasurement,
+        extensions: impl Into<DapReportExtensions>,
+    ) -> Result<Report, DapError> {
+        self.vdaf.check_measurement(&measurement)?;
+        let hpke_configs = self.hpke_configs(http, now).await?;
+        self.vdaf.produce_report_with_extensions(
+            &hpke_configs,
+            now,
+            &self.task_id,
+            measurement,
+            extensions,
+            self.version,
+        )
+    }
+
+    /// Upload a report to the Leader. If the Leader rejects the report, then the cached HPKE
+    /// configs are dropped, since the rejection may be due to a config that is no longer current.
+    pub async fn upload(
+        &self,
+        http: &impl DapClientHttpClient,
+        report: &Report,
+    ) -> Result<(), DapError> {
+        self.upload_report(http, report).await.map(|_resp| ())
+    }
+
+    /// Upload a report to the Leader and verify the receipt the Leader issues for it (see
+    /// [`Self::verify_upload_receipt`]). `public_key` is the Leader's receipt verification key.
+    /// This fails if the Leader does not issue a receipt, in which case the report may still have
+    /// been accepted.
+    pub async fn upload_with_receipt(
+        &self,
+        http: &impl DapClientHttpClient,
+        report: &Report,
+        public_key: &[u8],
+    ) -> Result<DapUploadReceipt, DapError> {
+        let resp = self.upload_report(http, report).await?;
+        if resp.payload.is_empty() {
+            return Err(DapError::fatal("Leader did not issue an upload receipt"));
+        }
+        let receipt: DapUploadReceipt = serde_json::from_slice(&resp.payload).map_err(|e| {
+            DapError::Fatal(format!("failed to parse upload receipt from Leader: {e}"))
+        })?;
+        self.verify_upload_receipt(public_key, report, &receipt)?;
+        Ok(receipt)
+    }
+
+    /// Verify that a receipt was issued by the Leader for the given report of this task.
+    /// `public_key` is the Leader's receipt verification key.
+    pub fn verify_upload_receipt(
+        &self,
+        public_key: &[u8],
+        report: &Report,
+        receipt: &DapUploadReceipt,
+    ) -> Result<(), DapError> {
+        if receipt.version != self.version {
+            return Err(DapError::fatal("receipt does not match report"));
+        }
+        receipt.verify(public_key, &self.task_id, report)
+    }
+
+    async fn upload_report(
+        &self,
+        http: &impl DapClientHttpClient,
+        report: &Report,
+    ) -> Result<DapClientHttpResponse, DapError> {
+        // draft02 compatibility: In draft02, the Client POSTs the report to the "upload" endpoint.
+        // In the latest draft, the Client PUTs the report to the task's "reports" resource.
+        let (method, path) = match self.version {
+            DapVersion::Draft02 => (DapClientHttpMethod::Post, "upload".to_string()),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => (
+                DapClientHttpMethod::Put,
+                format!("tasks/{}/reports", self.task_id.to_base64url()),
+            ),
+            _ => return Err(DapError::fatal("unknown DAP version")),
+        };
+
+        let resp = self
+            .send_upload(
+                http,
+                method,
+                DapMediaType::Report,
+                join_url(&self.leader_url, &path)?,
+                report.get_encoded_with_param(&self.version),
+                report.report_metadata.id.to_base64url(),
+            )
+            .await?;
+        match resp.status {
+            200 | 201 => Ok(resp),
+            _ => {
+                if resp.status == 400 {
+                    self.invalidate_hpke_configs();
+                }
+                Err(unexpected_response("Leader", &resp))
+            }
+        }
+    }
+
+    /// Upload several reports to the Leader in one request. This uses Daphne's batched upload
+    /// extension, which is not part of DAP and is not supported in draft02. The Leader handles
+    /// each report independently and the outcome for each is returned in the order of `reports`.
+    /// If the Leader rejects any of the reports, then the cached HPKE configs are dropped.
+    pub async fn upload_batch(
+        &self,
+        http: &impl DapClientHttpClient,
+        reports: &[Report],
+    ) -> Result<Vec<DapReportUploadStatus>, DapError> {
+        if self.version == DapVersion::Draft02 {
+            return Err(DapError::fatal(
+                "batched upload is not supported in draft02",
+            ));
+        }
+        let path = format!("tasks/{}/reports/batch", self.task_id.to_base64url());
+        let payload = ReportBatch::from_reports(&self.version, reports).get_encoded();
+        let idempotency_key = encode_base64url(digest(&SHA256, &payload));
+
+        let resp = self
+            .send_upload(
+                http,
+                DapClientHttpMethod::Post,
+                DapMediaType::ReportBatch,
+                join_url(&self.leader_url, &path)?,
+                payload,
+                idempotency_key,
+            )
+            .await?;
+        if resp.status != 200 {
+            return Err(unexpected_response("Leader", &resp));
+        }
+
+        let statuses: Vec<DapReportUploadStatus> = serde_json::from_slice(&resp.payload)
+            .map_err(|e| DapError::Fatal(format!("failed to parse response from Leader: {e}")))?;
+        if statuses.len() != reports.len() {
+            return Err(DapError::Fatal(format!(
+                "Leader returned {} statuses for {} reports",
+                statuses.len(),
+                reports.len()
+            )));
+        }
+        if statuses.iter().any(|status| status.problem.is_some()) {
+            self.invalidate_hpke_configs();
+        }
+        Ok(statuses)
+    }
+
+    /// Produce a report for the given measurement and upload it to the Leader.
+    pub async fn produce_and_upload(
+        &self,
+        http: &impl DapClientHttpClient,
+        now: Time,
+        measurement: DapMeasurement,
+        extensions: impl Into<DapReportExtensions>,
+    ) -> Result<Report, DapError> {
+        let report = self
+            .produce_report(http, now, measurement, extensions)
+            .await?;
+        self.upload(http, &report).await?;
+        Ok(report)
+    }
+
+    /// Fetch the HPKE config advertised by an Aggregator for the task. If `public_key` is set,
+    /// then the configs must be signed with it.
+    async fn fetch_hpke_config(
+        &self,
+        http: &impl DapClientHttpClient,
+        base_url: &Url,
+        aggregator: &str,
+        public_key: Option<&[u8]>,
+    ) -> Result<HpkeConfig, DapError> {
+        let mut url = join_url(base_url, "hpke_config")?;
+        url.query_pairs_mut()
+            .append_pair("task_id", &self.task_id.to_base64url());
+
+        let resp = http
+            .send_http(
+                DapClientHttpMethod::Get,
+                self.request(DapMediaType::HpkeConfigList, url, Vec::default()),
+            )
+            .await?;
+        if resp.status != 200 {
+            return Err(unexpected_response(aggregator, &resp));
+        }
+        if let Some(public_key) = public_key {
+            match resp.hpke_config_signature {
+                Some(ref signature) => {
+                    verify_hpke_configs(public_key, self.version, &resp.payload, signature)
+                        .map_err(|e| DapError::Fatal(format!("{aggregator}: {e}")))?
+                }
+                None => {
+                    return Err(DapError::Fatal(format!(
+                        "{aggregator} did not sign its HPKE configs"
+                    )))
+                }
+            }
+        }
+
+        // draft02 compatibility: In draft02, the Aggregator advertises a single config. In the
+        // latest draft, it advertises a list of configs, the first supported of which is used.
+        let hpke_configs = match self.version {
+            DapVersion::Draft02 => vec![HpkeConfig::get_decoded(&resp.payload)?],
+            _ => HpkeConfigList::get_decoded(&resp.payload)?.hpke_configs,
+        };
+        let mut errors = Vec::with_capacity(hpke_configs.len());
+        for hpke_config in hpke_configs {
+            match validate_hpke_config(&hpke_config) {
+                Ok(()) => return Ok(hpke_config),
+                Err(e) => errors.push(format!("config {}: {e}", hpke_config.id)),
+            }
+        }
+        Err(DapError::Fatal(format!(
+            "{aggregator} did not advertise a supported HPKE config: [{}]",
+            errors.join("; ")
+        )))
+    }
+
+    /// Send an upload request to the Leader. If the Client has an upload retry policy, then the
+    /// request carries the given idempotency key and is retried if it fails transiently.
+    async fn send_upload(
+        &self,
+        http: &impl DapClientHttpClient,
+        method: DapClientHttpMethod,
+        media_type: DapMediaType,
+        url: Url,
+        payload: Vec<u8>,
+        idempotency_key: String,
+    ) -> Result<DapClientHttpResponse, DapError> {
+        let policy = match self.upload_retry_policy {
+            Some(ref policy) => policy,
+            None => {
+                return http
+                    .send_http(method, self.request(media_type, url, payload))
+                    .await
+            }
+        };
+
+        let mut backoff_ms = policy.initial_backoff_ms;
+        let mut attempt = 1;
+        loop {
+            let mut req = self.request(media_type.clone(), url.clone(), payload.clone());
+            req.idempotency_key = Some(idempotency_key.clone());
+            let result = http.send_http(method, req).await;
+            let transient = match result {
+                Ok(ref resp) => resp.status == 429 || resp.status >= 500,
+                Err(..) => true,
+            };
+            if !transient || attempt >= policy.max_attempts {
+                return result;
+            }
+            match result {
+                Ok(resp) => warn!(
+                    "upload attempt {attempt} failed with status {}; retrying",
+                    resp.status
+                ),
+                Err(e) => warn!("upload attempt {attempt} failed: {e}; retrying"),
+            }
+            http.sleep(backoff_ms).await;
+            backoff_ms = backoff_ms.saturating_mul(2).min(policy.max_backoff_ms);
+            attempt += 1;
+        }
+    }
+
+    fn request(&self, media_type: DapMediaType, url: Url, payload: Vec<u8>) -> DapRequest<()> {
+        let client_auth = match (&self.upload_auth, &media_type) {
+            (Some(upload_auth), DapMediaType::Report | DapMediaType::ReportBatch) => {
+                Some(upload_auth.authorize(&self.task_id, &payload))
+            }
+            _ => None,
+        };
+        DapRequest {
+            version: self.version,
+            media_type,
+            task_id: Some(self.task_id.clone()),
+            resource: DapResource::Undefined,
+            payload,
+            url,
+            sender_auth: None,
+            sender_version: None,
+            collector_id: None,
+            taskprov: None,
+            vdaf_verify_key_id: None,
+            client_auth,
+            idempotency_key: None,
+        }
+    }
+}
+
+/// Check that the ciphersuite of the HPKE config is implemented and that its public key has the
+/// length expected for the KEM.
+fn validate_hpke_config(hpke_config: &HpkeConfig) -> Result<(), DapError> {
+    hpke_config.check_suite()?;
+    let expected_len = match hpke_config.kem_id {
+        HpkeKemId::X25519HkdfSha256 => 32,
+        HpkeKemId::P256HkdfSha256 => 65,
+        HpkeKemId::NotImplemented(kem_id) => {
+            return Err(DapError::Fatal(format!("KEM {kem_id} not implemented")))
+        }
+    };
+    let len = hpke_config.public_key.as_slice().len();
+    if len != expected_len {
+        return Err(DapError::Fatal(format!(
+            "public key has length {len}, expected {expected_len}"
+        )));
+    }
+    Ok(())
+}
+
+fn join_url(base_url: &Url, path: &str) -> Result<Url, DapError> {
+    base_url
+        .join(path)
+        .map_err(|e| DapError::Fatal(e.to_string()))
+}
+
+fn unexpected_response(aggregator: &str, resp: &DapClientHttpResponse) -> DapError {
+    if resp.status == 400 {
+        if let Ok(ProblemDetails {
+            typ: Some(typ),
+            detail,
+            ..
+        }) = serde_json::from_slice(&resp.payload)
+        {
+            return DapError::Fatal(format!(
+                "request aborted by {aggregator}: {typ}: {}",
+                detail.unwrap_or_default()
+            ));
+        }
+    }
+    DapError::Fatal(format!(
+        "unexpected response status {} from {aggregator}",
+        resp.status
+    ))
+}
@@ -0,0 +1,498 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    async_test_version, async_test_versions,
+    auth::DapUploadAuth,
+    client::{
+        DapClient, DapClientHttpClient, DapClientHttpMethod, DapClientHttpResponse,
+        DapClientRetryPolicy,
+    },
+    constants::DapMediaType,
+    hpke::HpkeReceiverConfig,
+    messages::{HpkeConfig, HpkeConfigList, HpkeKemId, Report, ReportBatch, TaskId},
+    receipt::{sign_hpke_configs, DapReceiptSigningKey, DapUploadReceipt},
+    DapError, DapMeasurement, DapReportUploadStatus, DapRequest, DapVersion, Prio3Config,
+    VdafConfig,
+};
+use assert_matches::assert_matches;
+use async_trait::async_trait;
+use hpke_rs::HpkePublicKey;
+use paste::paste;
+use prio::codec::{Decode, Encode, ParameterizedDecode};
+use rand::prelude::*;
+use std::{cell::RefCell, collections::VecDeque};
+use url::Url;
+
+/// Aggregators that advertise fixed HPKE configs, accept uploads according to a script, and
+/// record the requests they receive.
+struct FakeAggregators {
+    version: DapVersion,
+    leader_hpke_configs: Vec<HpkeConfig>,
+    helper_hpke_configs: Vec<HpkeConfig>,
+    upload_statuses: RefCell<VecDeque<u16>>,
+    receipt_signing_key: Option<DapReceiptSigningKey>,
+    hpke_config_signing_keys: [Option<DapReceiptSigningKey>; 2],
+    reqs: RefCell<Vec<(DapClientHttpMethod, DapRequest<()>)>>,
+    sleeps: RefCell<Vec<u64>>,
+}
+
+impl FakeAggregators {
+    fn new(version: DapVersion) -> Self {
+        Self {
+            version,
+            leader_hpke_configs: vec![hpke_config(1)],
+            helper_hpke_configs: vec![hpke_config(2)],
+            upload_statuses: RefCell::default(),
+            receipt_signing_key: None,
+            hpke_config_signing_keys: [None, None],
+            reqs: RefCell::default(),
+            sleeps: RefCell::default(),
+        }
+    }
+
+    fn hpke_config_fetches(&self) -> usize {
+        self.reqs
+            .borrow()
+            .iter()
+            .filter(|(method, _req)| *method == DapClientHttpMethod::Get)
+            .count()
+    }
+
+    fn encode_hpke_configs(&self, hpke_configs: &[HpkeConfig]) -> Vec<u8> {
+        if self.version == DapVersion::Draft02 {
+            hpke_configs[0].get_encoded()
+        } else {
+            HpkeConfigList {
+                hpke_configs: hpke_configs.to_vec(),
+            }
+            .get_encoded()
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl DapClientHttpClient for FakeAggregators {
+    async fn send_http(
+        &self,
+        method: DapClientHttpMethod,
+        req: DapRequest<()>,
+    ) -> Result<DapClientHttpResponse, DapError> {
+        let resp = match (method, &req.media_type) {
+            (DapClientHttpMethod::Get, DapMediaType::HpkeConfigList) => {
+                let (hpke_configs, signing_key) = match req.url.host_str() {
+                    Some("leader.com") => {
+                        (&self.leader_hpke_configs, &self.hpke_config_signing_keys[0])
+                    }
+                    Some("helper.org") => {
+                        (&self.helper_hpke_configs, &self.hpke_config_signing_keys[1])
+                    }
+                    host => panic!("unexpected host {host:?}"),
+                };
+                let payload = self.encode_hpke_configs(hpke_configs);
+                DapClientHttpResponse {
+                    status: 200,
+                    hpke_config_signature: signing_key
+                        .as_ref()
+                        .map(|signing_key| sign_hpke_configs(signing_key, self.version, &payload)),
+                    payload,
+                }
+            }
+            (_, DapMediaType::Report) => DapClientHttpResponse {
+                status: self.upload_statuses.borrow_mut().pop_front().unwrap_or(200),
+                payload: match self.receipt_signing_key {
+                    Some(ref signing_key) => serde_json::to_vec(&DapUploadReceipt::sign(
+                        signing_key,
+                        self.version,
+                        req.task_id.as_ref().unwrap(),
+                        &Report::get_decoded_with_param(&self.version, &req.payload)
+                            .unwrap()
+                            .report_metadata,
+                    ))
+                    .unwrap(),
+                    None => Vec::default(),
+                },
+                hpke_config_signature: None,
+            },
+            // Accept every report of the batch.
+            (DapClientHttpMethod::Post, DapMediaType::ReportBatch) => {
+                let statuses = ReportBatch::get_decoded(&req.payload)
+                    .unwrap()
+                    .encoded_reports
+                    .iter()
+                    .map(|encoded_report| DapReportUploadStatus {
+                        report_id: Some(
+                            Report::get_decoded_with_param(&self.version, encoded_report)
+                                .unwrap()
+                                .report_metadata
+                                .id
+                                .to_base64url(),
+                        ),
+                        problem: None,
+                    })
+                    .collect::<Vec<_>>();
+                DapClientHttpResponse {
+                    status: 200,
+                    payload: serde_json::to_vec(&statuses).unwrap(),
+                    hpke_config_signature: None,
+                }
+            }
+            _ => panic!("unexpected request: {method:?} {:?}", req.media_type),
+        };
+        self.reqs.borrow_mut().push((method, req));
+        Ok(resp)
+    }
+
+    async fn sleep(&self, millis: u64) {
+        self.sleeps.borrow_mut().push(millis);
+    }
+}
+
+fn hpke_config(id: u8) -> HpkeConfig {
+    HpkeReceiverConfig::gen(id, HpkeKemId::X25519HkdfSha256)
+        .unwrap()
+        .config
+}
+
+fn client(version: DapVersion, task_id: &TaskId) -> DapClient {
+    DapClient::new(
+        version,
+        task_id.clone(),
+        Url::parse(&format!("https://leader.com/{version}/")).unwrap(),
+        Url::parse(&format!("https://helper.org/{version}/")).unwrap(),
+        VdafConfig::Prio3(Prio3Config::Count),
+    )
+    .with_hpke_config_ttl(3600)
+}
+
+async fn produce_and_upload(version: DapVersion) {
+    let task_id = TaskId(thread_rng().gen());
+    let client = client(version, &task_id);
+    let aggregators = FakeAggregators::new(version);
+    let now = 1637364244;
+
+    let report = client
+        .produce_and_upload(&aggregators, now, DapMeasurement::U64(1), Vec::new())
+        .await
+        .unwrap();
+    assert_eq!(report.encrypted_input_shares[0].config_id, 1);
+    assert_eq!(report.encrypted_input_shares[1].config_id, 2);
+
+    let reqs = aggregators.reqs.borrow();
+    assert_eq!(reqs.len(), 3);
+    assert_eq!(
+        reqs[0].1.url.as_str(),
+        format!(
+            "https://leader.com/{version}/hpke_config?task_id={}",
+            task_id.to_base64url()
+        )
+    );
+    assert_eq!(
+        reqs[1].1.url.as_str(),
+        format!(
+            "https://helper.org/{version}/hpke_config?task_id={}",
+            task_id.to_base64url()
+        )
+    );
+    let (method, upload_req) = &reqs[2];
+    if version == DapVersion::Draft02 {
+        assert_eq!(*method, DapClientHttpMethod::Post);
+        assert_eq!(upload_req.url.path(), "/v02/upload");
+    } else {
+        assert_eq!(*method, DapClientHttpMethod::Put);
+        assert_eq!(
+            upload_req.url.path(),
+            format!("/{version}/tasks/{}/reports", task_id.to_base64url())
+        );
+    }
+    assert_eq!(
+        Report::get_decoded_with_param(&version, &upload_req.payload).unwrap(),
+        report
+    );
+}
+
+async_test_versions! { produce_and_upload }
+
+async fn upload_with_auth(version: DapVersion) {
+    let task_id = TaskId(thread_rng().gen());
+    let upload_auth = DapUploadAuth::HmacSha256(vec![1; 32]);
+    let client = client(version, &task_id).with_upload_auth(upload_auth.clone());
+    let aggregators = FakeAggregators::new(version);
+
+    client
+        .produce_and_upload(&aggregators, 1637364244, DapMeasurement::U64(1), Vec::new())
+        .await
+        .unwrap();
+
+    // Only the upload request is authenticated.
+    let reqs = aggregators.reqs.borrow();
+    assert!(reqs[0].1.client_auth.is_none());
+    assert!(reqs[1].1.client_auth.is_none());
+    let upload_req = &reqs[2].1;
+    assert_eq!(
+        upload_auth.unauthorized_reason(
+            &task_id,
+            &upload_req.payload,
+            upload_req.client_auth.as_ref()
+        ),
+        None
+    );
+}
+
+async_test_versions! { upload_with_auth }
+
+async fn upload_with_retry(version: DapVersion) {
+    let task_id = TaskId(thread_rng().gen());
+    let client = client(version, &task_id).with_upload_retry_policy(DapClientRetryPolicy {
+        max_attempts: 3,
+        initial_backoff_ms: 100,
+        max_backoff_ms: 150,
+    });
+    let aggregators = FakeAggregators::new(version);
+
+    // Transient failures are retried with the same idempotency key until the upload succeeds.
+    aggregators
+        .upload_statuses
+        .borrow_mut()
+        .extend([503, 429, 200]);
+    let report = client
+        .produce_and_upload(&aggregators, 1637364244, DapMeasurement::U64(1), Vec::new())
+        .await
+        .unwrap();
+    assert_eq!(*aggregators.sleeps.borrow(), [100, 150]);
+    {
+        let reqs = aggregators.reqs.borrow();
+        let upload_reqs = &reqs[2..];
+        assert_eq!(upload_reqs.len(), 3);
+        for (_method, upload_req) in upload_reqs {
+            assert_eq!(
+                upload_req.idempotency_key,
+                Some(report.report_metadata.id.to_base64url())
+            );
+            assert_eq!(upload_req.payload, upload_reqs[0].1.payload);
+        }
+    }
+
+    // The upload fails once the attempts are exhausted.
+    aggregators
+        .upload_statuses
+        .borrow_mut()
+        .extend([500, 500, 500]);
+    assert_matches!(
+        client
+            .produce_and_upload(&aggregators, 1637364244, DapMeasurement::U64(1), Vec::new())
+            .await,
+        Err(DapError::Fatal(detail)) => assert!(detail.contains("status 500"))
+    );
+
+    // Other failures are not retried.
+    aggregators.reqs.borrow_mut().clear();
+    aggregators.upload_statuses.borrow_mut().push_back(400);
+    assert!(client
+        .produce_and_upload(&aggregators, 1637364244, DapMeasurement::U64(1), Vec::new())
+        .await
+        .is_err());
+    assert_eq!(
+        aggregators
+            .reqs
+            .borrow()
+            .iter()
+            .filter(|(method, _req)| *method != DapClientHttpMethod::Get)
+            .count(),
+        1
+    );
+}
+
+async_test_versions! { upload_with_retry }
+
+async fn upload_with_receipt(version: DapVersion) {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let client = client(version, &task_id);
+    let mut aggregators = FakeAggregators::new(version);
+    let signing_key = DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap();
+    let public_key = signing_key.public_key().to_vec();
+    let now = 1637364244;
+
+    // The Leader does not issue receipts.
+    let report = client
+        .produce_report(&aggregators, now, DapMeasurement::U64(1), Vec::new())
+        .await
+        .unwrap();
+    assert_matches!(
+        client
+            .upload_with_receipt(&aggregators, &report, &public_key)
+            .await,
+        Err(DapError::Fatal(s)) => assert!(s.contains("did not issue"))
+    );
+
+    aggregators.receipt_signing_key = Some(signing_key);
+    let receipt = client
+        .upload_with_receipt(&aggregators, &report, &public_key)
+        .await
+        .unwrap();
+    assert_eq!(receipt.report_id, report.report_metadata.id);
+    assert_eq!(receipt.time, now);
+
+    // The receipt does not verify for another report or under another key.
+    let other_report = client
+        .produce_report(&aggregators, now, DapMeasurement::U64(1), Vec::new())
+        .await
+        .unwrap();
+    assert!(client
+        .verify_upload_receipt(&public_key, &other_report, &receipt)
+        .is_err());
+    let other_key = DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap();
+    assert!(client
+        .verify_upload_receipt(other_key.public_key(), &report, &receipt)
+        .is_err());
+}
+
+async_test_versions! { upload_with_receipt }
+
+async fn hpke_configs_are_cached(version: DapVersion) {
+    let task_id = TaskId(thread_rng().gen());
+    let client = client(version, &task_id);
+    let aggregators = FakeAggregators::new(version);
+    let now = 1637364244;
+
+    client.hpke_configs(&aggregators, now).await.unwrap();
+    client.hpke_configs(&aggregators, now + 3599).await.unwrap();
+    assert_eq!(aggregators.hpke_config_fetches(), 2);
+
+    // The configs are fetched again once the TTL has passed.
+    client.hpke_configs(&aggregators, now + 3600).await.unwrap();
+    assert_eq!(aggregators.hpke_config_fetches(), 4);
+
+    // The configs are fetched again after the Leader rejects a report.
+    aggregators.upload_statuses.borrow_mut().push_back(400);
+    assert_matches!(
+        client
+            .produce_and_upload(&aggregators, now + 3600, DapMeasurement::U64(1), Vec::new())
+            .await,
+        Err(DapError::Fatal(s)) => assert!(s.contains("400"), "{s}")
+    );
+    assert_eq!(aggregators.hpke_config_fetches(), 4);
+    client
+        .produce_and_upload(&aggregators, now + 3600, DapMeasurement::U64(1), Vec::new())
+        .await
+        .unwrap();
+    assert_eq!(aggregators.hpke_config_fetches(), 6);
+}
+
+async_test_versions! { hpke_configs_are_cached }
+
+async fn hpke_configs_are_validated(version: DapVersion) {
+    let task_id = TaskId(thread_rng().gen());
+    let client = client(version, &task_id);
+    let mut aggregators = FakeAggregators::new(version);
+    let mut unsupported = hpke_config(3);
+    unsupported.kem_id = HpkeKemId::NotImplemented(0x0011);
+    let mut truncated = hpke_config(4);
+    truncated.public_key = HpkePublicKey::from(truncated.public_key.as_slice()[..31].to_vec());
+
+    // The first supported config is used. (In draft02, only one config is advertised.)
+    if version != DapVersion::Draft02 {
+        aggregators.leader_hpke_configs =
+            vec![unsupported.clone(), truncated.clone(), hpke_config(5)];
+        let [leader_hpke_config, _] = client.hpke_configs(&aggregators, 0).await.unwrap();
+        assert_eq!(leader_hpke_config.id, 5);
+        client.invalidate_hpke_configs();
+    }
+
+    // The Helper does not advertise a supported config.
+    aggregators.helper_hpke_configs = vec![unsupported, truncated];
+    assert_matches!(
+        client.hpke_configs(&aggregators, 0).await,
+        Err(DapError::Fatal(s)) => assert!(s.starts_with("Helper did not advertise"), "{s}")
+    );
+}
+
+async_test_versions! { hpke_configs_are_validated }
+
+async fn hpke_configs_are_signed(version: DapVersion) {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let helper_seed = rng.gen::<[u8; 32]>();
+    let leader_signing_key = DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap();
+    let helper_signing_key = DapReceiptSigningKey::from_seed(&helper_seed).unwrap();
+    let client = client(version, &task_id)
+        .with_leader_hpke_config_public_key(leader_signing_key.public_key().to_vec())
+        .with_helper_hpke_config_public_key(helper_signing_key.public_key().to_vec());
+    let mut aggregators = FakeAggregators::new(version);
+
+    // The Aggregators do not sign their configs.
+    assert_matches!(
+        client.hpke_configs(&aggregators, 0).await,
+        Err(DapError::Fatal(s)) => assert!(s.starts_with("Leader did not sign"), "{s}")
+    );
+
+    // The Helper's configs are signed with the wrong key, e.g., because they were substituted.
+    aggregators.hpke_config_signing_keys = [
+        Some(leader_signing_key),
+        Some(DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap()),
+    ];
+    assert_matches!(
+        client.hpke_configs(&aggregators, 0).await,
+        Err(DapError::Fatal(s)) => assert!(s.starts_with("Helper: "), "{s}")
+    );
+
+    aggregators.hpke_config_signing_keys[1] =
+        Some(DapReceiptSigningKey::from_seed(&helper_seed).unwrap());
+    let [leader_hpke_config, helper_hpke_config] =
+        client.hpke_configs(&aggregators, 0).await.unwrap();
+    assert_eq!(leader_hpke_config.id, 1);
+    assert_eq!(helper_hpke_config.id, 2);
+}
+
+async_test_versions! { hpke_configs_are_signed }
+
+async fn upload_batch(version: DapVersion) {
+    let task_id = TaskId(thread_rng().gen());
+    let client = client(version, &task_id);
+    let aggregators = FakeAggregators::new(version);
+    let now = 1637364244;
+
+    let mut reports = Vec::new();
+    for measurement in [0, 1] {
+        reports.push(
+            client
+                .produce_report(
+                    &aggregators,
+                    now,
+                    DapMeasurement::U64(measurement),
+                    Vec::new(),
+                )
+                .await
+                .unwrap(),
+        );
+    }
+
+    if version == DapVersion::Draft02 {
+        assert_matches!(
+            client.upload_batch(&aggregators, &reports).await,
+            Err(DapError::Fatal(s)) => assert!(s.contains("not supported"), "{s}")
+        );
+        return;
+    }
+
+    let statuses = client.upload_batch(&aggregators, &reports).await.unwrap();
+    assert_eq!(statuses.len(), 2);
+    for (status, report) in statuses.iter().zip(reports.iter()) {
+        assert_eq!(
+            status.report_id,
+            Some(report.report_metadata.id.to_base64url())
+        );
+        assert!(status.problem.is_none());
+    }
+
+    let reqs = aggregators.reqs.borrow();
+    let (method, upload_req) = reqs.last().unwrap();
+    assert_eq!(*method, DapClientHttpMethod::Post);
+    assert_eq!(
+        upload_req.url.path(),
+        format!("/{version}/tasks/{}/reports/batch", task_id.to_base64url())
+    );
+}
+
+async_test_versions! { upload_batch }
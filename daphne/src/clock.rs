@@ -0,0 +1,77 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Sources of the current time.
+//!
+//! The Aggregators take the current time from a [`Clock`] (see
+//! [`DapAggregator::clock`](crate::roles::DapAggregator::clock)). In production this is the
+//! system clock; tests inject a [`MockClock`] so that expiration, the replay window, and batch
+//! intervals can be exercised deterministically.
+
+use crate::messages::Time;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+/// A source of the current time.
+pub trait Clock {
+    /// The current time in milliseconds since the beginning of UNIX time.
+    fn now_millis(&self) -> u64;
+
+    /// The current time in seconds since the beginning of UNIX time.
+    fn now(&self) -> Time {
+        self.now_millis() / 1000
+    }
+}
+
+/// The system clock. This is not available on targets without a system clock, e.g.,
+/// `wasm32-unknown-unknown`, which need to provide their own [`Clock`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("system time is before the UNIX epoch")
+            .as_millis()
+            .try_into()
+            .expect("system time overflows u64")
+    }
+}
+
+/// A clock that only moves when it is told to.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    millis: AtomicU64,
+}
+
+impl MockClock {
+    /// Create a clock stopped at the given time (in seconds since the beginning of UNIX time).
+    pub fn new(now: Time) -> Self {
+        Self {
+            millis: AtomicU64::new(now.saturating_mul(1000)),
+        }
+    }
+
+    /// Set the clock to the given time (in seconds since the beginning of UNIX time).
+    pub fn set(&self, now: Time) {
+        self.millis
+            .store(now.saturating_mul(1000), Ordering::Relaxed);
+    }
+
+    /// Move the clock forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.millis.fetch_add(
+            duration.as_millis().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::Relaxed)
+    }
+}
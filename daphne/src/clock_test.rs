@@ -0,0 +1,28 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::clock::{Clock, MockClock, SystemClock};
+use std::time::Duration;
+
+#[test]
+fn mock_clock() {
+    let clock = MockClock::new(1_700_000_000);
+    assert_eq!(clock.now(), 1_700_000_000);
+    assert_eq!(clock.now_millis(), 1_700_000_000_000);
+
+    // The clock only moves when told to.
+    clock.advance(Duration::from_millis(1500));
+    assert_eq!(clock.now_millis(), 1_700_000_001_500);
+    assert_eq!(clock.now(), 1_700_000_001);
+
+    clock.set(1_600_000_000);
+    assert_eq!(clock.now(), 1_600_000_000);
+}
+
+#[test]
+fn system_clock() {
+    let clock = SystemClock;
+    let before = clock.now_millis();
+    assert!(clock.now_millis() >= before);
+    assert!(clock.now() >= before / 1000);
+}
@@ -0,0 +1,704 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Collector-side tooling for collection.
+//!
+//! An honest Leader serves the same aggregate result each time the Collector collects a given
+//! batch. [`DapCollectionLog`] keeps a digest of each result consumed by the Collector so that a
+//! Leader that serves differing results for the same batch selector can be detected.
+//!
+//! [`DapQueryPlanner`] keeps track of the batches queried by the Collector so that queries that
+//! the Leader would reject with "batchOverlap" are caught before they are sent.
+//!
+//! [`DapCollector`] implements the Collector role: it creates a collection job, polls the Leader
+//! until the job is complete, then decrypts and unshards the aggregate shares. The HTTP transport
+//! is provided by the caller via [`DapCollectorHttpClient`]. If the aggregate shares cannot be
+//! consumed, then [`DapCollector::try_consume_collection`] reports whether the Leader's or the
+//! Helper's aggregate share is at fault (see [`DapUnshardError`]).
+//!
+//! If the Leader sends the breakdown of a collection's report count into the report count of each
+//! bucket in the batch, then [`verify_report_counts`] checks that the buckets add up to the
+//! collection's report count. [`DapCollector`] does this for each completed collection job.
+
+use crate::{
+    aborts::ProblemDetails,
+    constants::DapMediaType,
+    hpke::HpkeReceiverConfig,
+    messages::{
+        BatchSelector, Collection, CollectionJobId, CollectionReq, Interval, PartialBatchSelector,
+        Query, TaskId,
+    },
+    vdaf::DapUnshardError,
+    DapAggregateResult, DapError, DapReportCountBreakdown, DapRequest, DapResource, DapVersion,
+    VdafConfig,
+};
+use async_trait::async_trait;
+use prio::codec::{Encode, ParameterizedDecode, ParameterizedEncode};
+use rand::prelude::*;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+const CTX_COLLECTION_DIGEST: &[u8] = b"daphne collection digest";
+
+/// Digest of a collection, covering the report count, the batch interval, and the aggregate result.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapCollectionDigest(#[serde(with = "hex")] pub Vec<u8>);
+
+impl DapCollectionDigest {
+    /// Compute the digest of a collection and the aggregate result obtained from it.
+    pub fn new(collection: &Collection, agg_res: &DapAggregateResult) -> Self {
+        let mut data = Vec::new();
+        data.extend_from_slice(CTX_COLLECTION_DIGEST);
+        collection.report_count.encode(&mut data);
+        match collection.interval {
+            Some(ref interval) => {
+                1_u8.encode(&mut data);
+                interval.encode(&mut data);
+            }
+            None => 0_u8.encode(&mut data),
+        }
+        match agg_res {
+            DapAggregateResult::U32Vec(vec) => {
+                0_u8.encode(&mut data);
+                vec.iter()
+                    .for_each(|x| data.extend_from_slice(&x.to_be_bytes()));
+            }
+            DapAggregateResult::U64(x) => {
+                1_u8.encode(&mut data);
+                data.extend_from_slice(&x.to_be_bytes());
+            }
+            DapAggregateResult::U128(x) => {
+                2_u8.encode(&mut data);
+                data.extend_from_slice(&x.to_be_bytes());
+            }
+            DapAggregateResult::U128Vec(vec) => {
+                3_u8.encode(&mut data);
+                vec.iter()
+                    .for_each(|x| data.extend_from_slice(&x.to_be_bytes()));
+            }
+            DapAggregateResult::F64Vec(vec) => {
+                4_u8.encode(&mut data);
+                vec.iter()
+                    .for_each(|x| data.extend_from_slice(&x.to_be_bytes()));
+            }
+        }
+        Self(digest(&SHA256, &data).as_ref().to_vec())
+    }
+}
+
+impl std::fmt::Display for DapCollectionDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+/// An entry of the [`DapCollectionLog`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapCollectionLogEntry {
+    pub task_id: TaskId,
+    pub batch_sel: BatchSelector,
+    pub digest: DapCollectionDigest,
+}
+
+/// Outcome of [`DapCollectionLog::check_and_record`].
+#[derive(Debug, Eq, PartialEq)]
+pub enum DapCollectionCheck {
+    /// The batch selector has not been collected before.
+    New,
+
+    /// The batch selector was collected before and the result is the same.
+    Consistent,
+
+    /// The batch selector was collected before, but the result differs. This indicates that an
+    /// Aggregator misbehaved.
+    Inconsistent {
+        previous: DapCollectionDigest,
+        current: DapCollectionDigest,
+    },
+}
+
+/// Record of the collections consumed by the Collector. The log is serializable so that it can be
+/// persisted across runs.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DapCollectionLog {
+    entries: Vec<DapCollectionLogEntry>,
+}
+
+impl DapCollectionLog {
+    /// Check the collection against the previously consumed collection for the same task and batch
+    /// selector, if any. The collection is recorded if the batch selector is new; an inconsistent
+    /// collection does not replace the one that was recorded first.
+    pub fn check_and_record(
+        &mut self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+        agg_res: &DapAggregateResult,
+    ) -> DapCollectionCheck {
+        let current = DapCollectionDigest::new(collection, agg_res);
+        match self.get(task_id, batch_sel) {
+            Some(previous) if previous == &current => DapCollectionCheck::Consistent,
+            Some(previous) => DapCollectionCheck::Inconsistent {
+                previous: previous.clone(),
+                current,
+            },
+            None => {
+                self.entries.push(DapCollectionLogEntry {
+                    task_id: task_id.clone(),
+                    batch_sel: batch_sel.clone(),
+                    digest: current,
+                });
+                DapCollectionCheck::New
+            }
+        }
+    }
+
+    /// Get the digest of the collection previously consumed for the given task and batch selector.
+    pub fn get(&self, task_id: &TaskId, batch_sel: &BatchSelector) -> Option<&DapCollectionDigest> {
+        self.entries
+            .iter()
+            .find(|entry| &entry.task_id == task_id && &entry.batch_sel == batch_sel)
+            .map(|entry| &entry.digest)
+    }
+
+    /// Return the entries of the log.
+    pub fn entries(&self) -> &[DapCollectionLogEntry] {
+        &self.entries
+    }
+}
+
+/// Reason for which [`DapQueryPlanner`] refused to build a query.
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum DapQueryPlanError {
+    /// The batch overlaps with, but is not the same as, a batch that was queried previously.
+    #[error("batch overlaps with a previously queried batch: {0:?}")]
+    BatchOverlap(BatchSelector),
+
+    /// The batch was already queried the maximum number of times.
+    #[error("batch was already queried {0} time(s)")]
+    QueryCountExceeded(u64),
+}
+
+/// An entry of the [`DapQueryPlanner`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapQueryPlannerEntry {
+    pub task_id: TaskId,
+    pub batch_sel: BatchSelector,
+    pub query_count: u64,
+}
+
+/// Planner for the Collector's queries. The planner keeps track of the batches queried for each
+/// task and refuses to build a query that overlaps with a previously queried batch or that would
+/// query the same batch more than `max_batch_query_count` times.
+///
+/// The planner only knows about the queries it was told about. Its view can be synchronized with
+/// a [`DapCollectionLog`] or with a list of batches obtained from the Leader.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DapQueryPlanner {
+    max_batch_query_count: u64,
+    entries: Vec<DapQueryPlannerEntry>,
+}
+
+impl DapQueryPlanner {
+    /// Create a planner without any history.
+    pub fn new(max_batch_query_count: u64) -> Self {
+        Self {
+            max_batch_query_count,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Build the query for the given batch, checking that it can be collected given the batches
+    /// queried so far. The query is not recorded; call [`Self::record`] once it is issued.
+    pub fn plan(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<Query, DapQueryPlanError> {
+        for entry in self
+            .entries
+            .iter()
+            .filter(|entry| &entry.task_id == task_id)
+        {
+            if &entry.batch_sel == batch_sel {
+                if entry.query_count >= self.max_batch_query_count {
+                    return Err(DapQueryPlanError::QueryCountExceeded(entry.query_count));
+                }
+            } else if batches_overlap(&entry.batch_sel, batch_sel) {
+                return Err(DapQueryPlanError::BatchOverlap(entry.batch_sel.clone()));
+            }
+        }
+
+        Ok(match batch_sel {
+            BatchSelector::TimeInterval { batch_interval } => Query::TimeInterval {
+                batch_interval: batch_interval.clone(),
+            },
+            BatchSelector::FixedSizeByBatchId { batch_id } => Query::FixedSizeByBatchId {
+                batch_id: batch_id.clone(),
+            },
+        })
+    }
+
+    /// Record that the given batch was queried.
+    pub fn record(&mut self, task_id: &TaskId, batch_sel: &BatchSelector) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| &entry.task_id == task_id && &entry.batch_sel == batch_sel)
+        {
+            Some(entry) => entry.query_count += 1,
+            None => self.entries.push(DapQueryPlannerEntry {
+                task_id: task_id.clone(),
+                batch_sel: batch_sel.clone(),
+                query_count: 1,
+            }),
+        }
+    }
+
+    /// Add the batches that are known to have been queried for the given task, e.g., as listed by
+    /// the Leader, to the history. Batches that are already known are left as is.
+    pub fn sync<'a>(
+        &mut self,
+        task_id: &TaskId,
+        batch_sels: impl IntoIterator<Item = &'a BatchSelector>,
+    ) {
+        for batch_sel in batch_sels {
+            if !self
+                .entries
+                .iter()
+                .any(|entry| &entry.task_id == task_id && &entry.batch_sel == batch_sel)
+            {
+                self.record(task_id, batch_sel);
+            }
+        }
+    }
+
+    /// Add the batches recorded in the collection log to the history.
+    pub fn sync_from_log(&mut self, log: &DapCollectionLog) {
+        for entry in log.entries() {
+            self.sync(&entry.task_id, [&entry.batch_sel]);
+        }
+    }
+
+    /// Return the entries of the planner.
+    pub fn entries(&self) -> &[DapQueryPlannerEntry] {
+        &self.entries
+    }
+}
+
+fn batches_overlap(a: &BatchSelector, b: &BatchSelector) -> bool {
+    match (a, b) {
+        (
+            BatchSelector::TimeInterval { batch_interval: a },
+            BatchSelector::TimeInterval { batch_interval: b },
+        ) => intervals_overlap(a, b),
+        (
+            BatchSelector::FixedSizeByBatchId { batch_id: a },
+            BatchSelector::FixedSizeByBatchId { batch_id: b },
+        ) => a == b,
+        _ => false,
+    }
+}
+
+fn intervals_overlap(a: &Interval, b: &Interval) -> bool {
+    a.start < b.end() && b.start < a.end()
+}
+
+/// HTTP method of a request sent by the Collector.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DapCollectorHttpMethod {
+    Get,
+    Post,
+    Put,
+}
+
+/// Response to an HTTP request sent by the Collector.
+#[derive(Debug)]
+pub struct DapCollectorHttpResponse {
+    /// HTTP status code.
+    pub status: u16,
+
+    /// Value of the "Location" header, if any.
+    pub location: Option<String>,
+
+    /// Value of the "Retry-After" header (in seconds), if any.
+    pub retry_after: Option<u64>,
+
+    /// Breakdown of the collection's report count, parsed from the "X-Daphne-Report-Counts"
+    /// header, if any.
+    pub report_counts: Option<DapReportCountBreakdown>,
+
+    /// Response payload.
+    pub payload: Vec<u8>,
+}
+
+/// HTTP client used by [`DapCollector`] to talk to the Leader.
+#[async_trait(?Send)]
+pub trait DapCollectorHttpClient<S> {
+    /// Send an HTTP request to the Leader. The "content-type" header is determined by the
+    /// request's media type and the authorization header by its sender authorization. If the
+    /// request has a Collector ID, then it is sent in the "dap-collector-id" header.
+    async fn send_http(
+        &self,
+        method: DapCollectorHttpMethod,
+        req: DapRequest<S>,
+    ) -> Result<DapCollectorHttpResponse, DapError>;
+
+    /// Wait for the given duration before polling the collection job again.
+    async fn sleep(&self, duration: std::time::Duration);
+}
+
+/// Parameters for polling a collection job with exponential backoff.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapCollectorBackoff {
+    /// Time (in milliseconds) to wait before polling the collection job for the first time.
+    pub initial_delay_ms: u64,
+
+    /// Upper bound on the time (in milliseconds) to wait between polls. The delay doubles after
+    /// each poll until it reaches this value. A "Retry-After" header sent by the Leader takes
+    /// precedence, up to this value.
+    pub max_delay_ms: u64,
+
+    /// Number of times the collection job is polled before giving up.
+    pub max_polls: u64,
+}
+
+impl Default for DapCollectorBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1000,
+            max_delay_ms: 60_000,
+            max_polls: 30,
+        }
+    }
+}
+
+/// Status of a collection job, as returned by [`DapCollector::poll_collection_job`].
+#[derive(Debug)]
+pub enum DapCollectionJobStatus {
+    /// The job is not complete. The Leader may indicate how long (in seconds) to wait before
+    /// polling again.
+    Pending { retry_after: Option<u64> },
+
+    /// The job is complete.
+    Done(Collection),
+}
+
+/// The Collector role for a given task.
+pub struct DapCollector<S> {
+    version: DapVersion,
+    task_id: TaskId,
+    leader_url: Url,
+    vdaf: VdafConfig,
+    hpke_receiver: HpkeReceiverConfig,
+    sender_auth: Option<S>,
+    collector_id: Option<String>,
+    backoff: DapCollectorBackoff,
+}
+
+impl<S: Clone> DapCollector<S> {
+    /// Create a Collector for the given task. `leader_url` is the Leader's base URL for the DAP
+    /// version (e.g., "https://leader.com/v04/") and `hpke_receiver` is the Collector's HPKE
+    /// receiver config for the task.
+    pub fn new(
+        version: DapVersion,
+        task_id: TaskId,
+        leader_url: Url,
+        vdaf: VdafConfig,
+        hpke_receiver: HpkeReceiverConfig,
+    ) -> Self {
+        Self {
+            version,
+            task_id,
+            leader_url,
+            vdaf,
+            hpke_receiver,
+            sender_auth: None,
+            collector_id: None,
+            backoff: DapCollectorBackoff::default(),
+        }
+    }
+
+    /// Authorize each request sent to the Leader with `sender_auth`, e.g., a bearer token.
+    pub fn with_sender_auth(mut self, sender_auth: S) -> Self {
+        self.sender_auth = Some(sender_auth);
+        self
+    }
+
+    /// Identify as the task's Collector with the given ID rather than as its primary Collector.
+    /// See [`DapTaskConfig::additional_collectors`](crate::DapTaskConfig::additional_collectors).
+    pub fn with_collector_id(mut self, collector_id: String) -> Self {
+        self.collector_id = Some(collector_id);
+        self
+    }
+
+    /// Poll collection jobs with the given backoff parameters.
+    pub fn with_backoff(mut self, backoff: DapCollectorBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Build the collection request for the given query.
+    pub fn collection_req(&self, query: Query, agg_param: Vec<u8>) -> CollectionReq {
+        CollectionReq {
+            draft02_task_id: self.task_id.for_request_payload(&self.version),
+            query,
+            agg_param,
+        }
+    }
+
+    /// Create a collection job for the given query and return its URI.
+    pub async fn start_collection_job(
+        &self,
+        http: &impl DapCollectorHttpClient<S>,
+        query: Query,
+        agg_param: Vec<u8>,
+    ) -> Result<Url, DapError> {
+        let payload = self
+            .collection_req(query, agg_param)
+            .get_encoded_with_param(&self.version);
+
+        // draft02 compatibility: In draft02, the Collector POSTs the request to the "collect"
+        // endpoint and the Leader responds with the collection job URI. In the latest draft, the
+        // Collector chooses the collection job ID and PUTs the request to the job's URI.
+        let (method, resource, url) = match self.version {
+            DapVersion::Draft02 => (
+                DapCollectorHttpMethod::Post,
+                DapResource::Undefined,
+                self.join_leader_url("collect")?,
+            ),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                let collect_job_id = CollectionJobId(thread_rng().gen());
+                let url = self.join_leader_url(&format!(
+                    "tasks/{}/collection_jobs/{}",
+                    self.task_id.to_base64url(),
+                    collect_job_id.to_base64url()
+                ))?;
+                (
+                    DapCollectorHttpMethod::Put,
+                    DapResource::CollectionJob(collect_job_id),
+                    url,
+                )
+            }
+            _ => return Err(DapError::fatal("unknown DAP version")),
+        };
+
+        let resp = http
+            .send_http(method, self.request(resource, url.clone(), payload))
+            .await?;
+        match (self.version, resp.status) {
+            (DapVersion::Draft02, 303) => {
+                let location = resp
+                    .location
+                    .ok_or_else(|| DapError::fatal("response is missing Location header"))?;
+                Url::parse(&location).map_err(|e| {
+                    DapError::Fatal(format!("Leader did not respond with a valid URI: {e}"))
+                })
+            }
+            (DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06, 201) => Ok(url),
+            _ => Err(unexpected_response(&resp)),
+        }
+    }
+
+    /// Poll the collection job with the given URI once.
+    pub async fn poll_collection_job(
+        &self,
+        http: &impl DapCollectorHttpClient<S>,
+        uri: &Url,
+    ) -> Result<DapCollectionJobStatus, DapError> {
+        let method = if self.version == DapVersion::Draft02 {
+            DapCollectorHttpMethod::Get
+        } else {
+            DapCollectorHttpMethod::Post
+        };
+        let resp = http
+            .send_http(
+                method,
+                self.request(DapResource::Undefined, uri.clone(), Vec::default()),
+            )
+            .await?;
+        match resp.status {
+            200 => {
+                let collection = Collection::get_decoded_with_param(&self.version, &resp.payload)?;
+                if let Some(ref report_counts) = resp.report_counts {
+                    verify_report_counts(&self.task_id, &collection, report_counts)?;
+                }
+                Ok(DapCollectionJobStatus::Done(collection))
+            }
+            202 => Ok(DapCollectionJobStatus::Pending {
+                retry_after: resp.retry_after,
+            }),
+            _ => Err(unexpected_response(&resp)),
+        }
+    }
+
+    /// Decrypt and unshard the aggregate shares of a completed collection job.
+    pub async fn consume_collection(
+        &self,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+    ) -> Result<DapAggregateResult, DapError> {
+        self.vdaf
+            .consume_encrypted_agg_shares(
+                &self.hpke_receiver,
+                &self.task_id,
+                batch_sel,
+                collection.report_count,
+                collection.encrypted_agg_shares.clone(),
+                self.version,
+            )
+            .await
+    }
+
+    /// Same as [`consume_collection`](Self::consume_collection), except that on failure, the error
+    /// indicates whether the Leader's or the Helper's aggregate share could not be decrypted or
+    /// decoded.
+    pub async fn try_consume_collection(
+        &self,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+    ) -> Result<DapAggregateResult, DapUnshardError> {
+        self.vdaf
+            .try_consume_encrypted_agg_shares(
+                &self.hpke_receiver,
+                &self.task_id,
+                batch_sel,
+                collection.report_count,
+                collection.encrypted_agg_shares.clone(),
+                self.version,
+            )
+            .await
+    }
+
+    /// Collect the aggregate result for the given query: create a collection job, poll it with
+    /// backoff until it is complete, then decrypt and unshard the aggregate shares.
+    pub async fn collect(
+        &self,
+        http: &impl DapCollectorHttpClient<S>,
+        query: Query,
+        agg_param: Vec<u8>,
+    ) -> Result<(Collection, DapAggregateResult), DapError> {
+        let uri = self
+            .start_collection_job(http, query.clone(), agg_param)
+            .await?;
+
+        let mut delay_ms = self.backoff.initial_delay_ms;
+        for _ in 0..self.backoff.max_polls {
+            match self.poll_collection_job(http, &uri).await? {
+                DapCollectionJobStatus::Done(collection) => {
+                    let batch_sel = batch_sel_for_collection(&query, &collection)?;
+                    let agg_res = self.consume_collection(&batch_sel, &collection).await?;
+                    return Ok((collection, agg_res));
+                }
+                DapCollectionJobStatus::Pending { retry_after } => {
+                    let wait_ms = retry_after
+                        .map_or(delay_ms, |secs| secs.saturating_mul(1000))
+                        .min(self.backoff.max_delay_ms);
+                    http.sleep(std::time::Duration::from_millis(wait_ms)).await;
+                    delay_ms = delay_ms.saturating_mul(2).min(self.backoff.max_delay_ms);
+                }
+            }
+        }
+
+        Err(DapError::Fatal(format!(
+            "collection job {uri} is not complete after {} polls",
+            self.backoff.max_polls
+        )))
+    }
+
+    fn join_leader_url(&self, path: &str) -> Result<Url, DapError> {
+        self.leader_url
+            .join(path)
+            .map_err(|e| DapError::Fatal(e.to_string()))
+    }
+
+    fn request(&self, resource: DapResource, url: Url, payload: Vec<u8>) -> DapRequest<S> {
+        DapRequest {
+            version: self.version,
+            media_type: DapMediaType::CollectReq,
+            task_id: Some(self.task_id.clone()),
+            resource,
+            payload,
+            url,
+            sender_auth: self.sender_auth.clone(),
+            sender_version: None,
+            collector_id: self.collector_id.clone(),
+            taskprov: None,
+            vdaf_verify_key_id: None,
+            client_auth: None,
+            idempotency_key: None,
+        }
+    }
+}
+
+/// Determine the batch selector for a completed collection job. For fixed-size queries, the batch
+/// ID is determined by the Leader.
+pub fn batch_sel_for_collection(
+    query: &Query,
+    collection: &Collection,
+) -> Result<BatchSelector, DapError> {
+    match (query, &collection.part_batch_sel) {
+        (Query::TimeInterval { batch_interval }, PartialBatchSelector::TimeInterval) => {
+            Ok(BatchSelector::TimeInterval {
+                batch_interval: batch_interval.clone(),
+            })
+        }
+        (
+            Query::FixedSizeByBatchId { .. } | Query::FixedSizeCurrentBatch,
+            PartialBatchSelector::FixedSizeByBatchId { batch_id },
+        ) => Ok(BatchSelector::FixedSizeByBatchId {
+            batch_id: batch_id.clone(),
+        }),
+        _ => Err(DapError::fatal(
+            "query type of the collection does not match the query",
+        )),
+    }
+}
+
+/// Check the Leader's breakdown of the report count of a collection: the digest must match the
+/// task, the batch, and the buckets, and the sum of the buckets' report counts must be equal to the
+/// report count of the collection.
+pub fn verify_report_counts(
+    task_id: &TaskId,
+    collection: &Collection,
+    report_counts: &DapReportCountBreakdown,
+) -> Result<(), DapError> {
+    if report_counts.digest
+        != DapReportCountBreakdown::compute_digest(
+            task_id,
+            &collection.part_batch_sel,
+            &report_counts.buckets,
+        )
+    {
+        return Err(DapError::fatal(
+            "report count breakdown does not match collection",
+        ));
+    }
+
+    if report_counts.report_count() != collection.report_count {
+        return Err(DapError::Fatal(format!(
+            "report count of collection ({}) does not match the sum of its buckets ({})",
+            collection.report_count,
+            report_counts.report_count()
+        )));
+    }
+    Ok(())
+}
+
+fn unexpected_response(resp: &DapCollectorHttpResponse) -> DapError {
+    if resp.status == 400 {
+        if let Ok(ProblemDetails {
+            typ: Some(typ),
+            detail,
+            ..
+        }) = serde_json::from_slice(&resp.payload)
+        {
+            return DapError::Fatal(format!(
+                "request aborted by Leader: {typ}: {}",
+                detail.unwrap_or_default()
+            ));
+        }
+    }
+    DapError::Fatal(format!("unexpected response status {}", resp.status))
+}
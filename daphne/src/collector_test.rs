@@ -0,0 +1,618 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    async_test_version, async_test_versions,
+    auth::BearerToken,
+    collector::{
+        verify_report_counts, DapCollectionCheck, DapCollectionDigest, DapCollectionJobStatus,
+        DapCollectionLog, DapCollector, DapCollectorBackoff, DapCollectorHttpClient,
+        DapCollectorHttpMethod, DapCollectorHttpResponse, DapQueryPlanError, DapQueryPlanner,
+    },
+    hpke::HpkeReceiverConfig,
+    messages::{
+        BatchId, BatchSelector, Collection, HpkeCiphertext, HpkeKemId, Interval,
+        PartialBatchSelector, Query, TaskId,
+    },
+    vdaf::{DapUnshardError, VdafAggregateShare},
+    DapAggregateResult, DapAggregateShare, DapBucketReportCount, DapError, DapReportCountBreakdown,
+    DapRequest, DapResource, DapSender, DapVersion, Prio3Config, VdafConfig,
+};
+use assert_matches::assert_matches;
+use async_trait::async_trait;
+use paste::paste;
+use prio::{
+    codec::ParameterizedEncode,
+    field::{Field128, Field64},
+    vdaf::{AggregateShare, OutputShare},
+};
+use rand::prelude::*;
+use std::{cell::RefCell, collections::VecDeque};
+use url::Url;
+
+fn collection(report_count: u64) -> Collection {
+    Collection {
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_count,
+        interval: Some(Interval {
+            start: 1637361337,
+            duration: 3600,
+        }),
+        encrypted_agg_shares: vec![HpkeCiphertext {
+            config_id: 1,
+            enc: b"encapsulated key".to_vec(),
+            payload: b"ciphertext".to_vec(),
+        }],
+    }
+}
+
+#[test]
+fn check_and_record() {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 1637361337,
+            duration: 3600,
+        },
+    };
+    let mut log = DapCollectionLog::default();
+
+    assert_eq!(
+        log.check_and_record(
+            &task_id,
+            &batch_sel,
+            &collection(23),
+            &DapAggregateResult::U64(23)
+        ),
+        DapCollectionCheck::New
+    );
+
+    // The encrypted aggregate shares may differ across collections of the same batch.
+    let mut recollection = collection(23);
+    recollection.encrypted_agg_shares[0].payload = b"different ciphertext".to_vec();
+    assert_eq!(
+        log.check_and_record(
+            &task_id,
+            &batch_sel,
+            &recollection,
+            &DapAggregateResult::U64(23)
+        ),
+        DapCollectionCheck::Consistent
+    );
+
+    // Differing report count.
+    assert_matches!(
+        log.check_and_record(
+            &task_id,
+            &batch_sel,
+            &collection(24),
+            &DapAggregateResult::U64(23)
+        ),
+        DapCollectionCheck::Inconsistent { previous, current } => {
+            assert_eq!(previous, DapCollectionDigest::new(&collection(23), &DapAggregateResult::U64(23)));
+            assert_ne!(previous, current);
+        }
+    );
+
+    // Differing aggregate result.
+    assert_matches!(
+        log.check_and_record(
+            &task_id,
+            &batch_sel,
+            &collection(23),
+            &DapAggregateResult::U64(22)
+        ),
+        DapCollectionCheck::Inconsistent { .. }
+    );
+
+    // The same batch selector for a different task is tracked separately.
+    assert_eq!(
+        log.check_and_record(
+            &TaskId(rng.gen()),
+            &batch_sel,
+            &collection(1),
+            &DapAggregateResult::U64(1)
+        ),
+        DapCollectionCheck::New
+    );
+    assert_eq!(log.entries().len(), 2);
+}
+
+#[test]
+fn serialize_roundtrip() {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let batch_sel = BatchSelector::FixedSizeByBatchId {
+        batch_id: BatchId(rng.gen()),
+    };
+    let mut log = DapCollectionLog::default();
+    log.check_and_record(
+        &task_id,
+        &batch_sel,
+        &collection(10),
+        &DapAggregateResult::U128Vec(vec![1, 2, 3]),
+    );
+
+    let mut log: DapCollectionLog =
+        serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+    assert_eq!(
+        log.check_and_record(
+            &task_id,
+            &batch_sel,
+            &collection(10),
+            &DapAggregateResult::U128Vec(vec![1, 2, 3]),
+        ),
+        DapCollectionCheck::Consistent
+    );
+}
+
+fn time_interval(start: u64, duration: u64) -> BatchSelector {
+    BatchSelector::TimeInterval {
+        batch_interval: Interval { start, duration },
+    }
+}
+
+#[test]
+fn query_planner_overlap() {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let mut planner = DapQueryPlanner::new(1);
+
+    let batch_sel = time_interval(3600, 7200);
+    assert_eq!(
+        planner.plan(&task_id, &batch_sel),
+        Ok(Query::TimeInterval {
+            batch_interval: Interval {
+                start: 3600,
+                duration: 7200
+            }
+        })
+    );
+    planner.record(&task_id, &batch_sel);
+
+    // Overlapping batch interval.
+    assert_eq!(
+        planner.plan(&task_id, &time_interval(7200, 7200)),
+        Err(DapQueryPlanError::BatchOverlap(batch_sel.clone()))
+    );
+
+    // Adjacent batch interval.
+    assert!(planner.plan(&task_id, &time_interval(10800, 3600)).is_ok());
+
+    // Same batch interval.
+    assert_eq!(
+        planner.plan(&task_id, &batch_sel),
+        Err(DapQueryPlanError::QueryCountExceeded(1))
+    );
+
+    // Overlapping batch interval for a different task.
+    assert!(planner
+        .plan(&TaskId(rng.gen()), &time_interval(7200, 7200))
+        .is_ok());
+}
+
+#[test]
+fn query_planner_query_count() {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let batch_sel = BatchSelector::FixedSizeByBatchId {
+        batch_id: BatchId(rng.gen()),
+    };
+    let mut planner = DapQueryPlanner::new(2);
+
+    planner.record(&task_id, &batch_sel);
+    assert!(planner.plan(&task_id, &batch_sel).is_ok());
+    planner.record(&task_id, &batch_sel);
+    assert_eq!(
+        planner.plan(&task_id, &batch_sel),
+        Err(DapQueryPlanError::QueryCountExceeded(2))
+    );
+}
+
+#[test]
+fn query_planner_sync_from_log() {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let batch_sel = time_interval(3600, 3600);
+    let mut log = DapCollectionLog::default();
+    log.check_and_record(
+        &task_id,
+        &batch_sel,
+        &collection(1),
+        &DapAggregateResult::U64(1),
+    );
+
+    let mut planner = DapQueryPlanner::new(1);
+    planner.sync_from_log(&log);
+    planner.sync_from_log(&log);
+    assert_eq!(planner.entries().len(), 1);
+    assert_eq!(planner.entries()[0].query_count, 1);
+    assert_eq!(
+        planner.plan(&task_id, &batch_sel),
+        Err(DapQueryPlanError::QueryCountExceeded(1))
+    );
+}
+
+/// Leader that replays a fixed sequence of responses and records the requests it receives.
+#[derive(Default)]
+struct ScriptedLeader {
+    resps: RefCell<VecDeque<DapCollectorHttpResponse>>,
+    reqs: RefCell<Vec<(DapCollectorHttpMethod, DapRequest<BearerToken>)>>,
+    sleeps: RefCell<Vec<std::time::Duration>>,
+}
+
+#[async_trait(?Send)]
+impl DapCollectorHttpClient<BearerToken> for ScriptedLeader {
+    async fn send_http(
+        &self,
+        method: DapCollectorHttpMethod,
+        req: DapRequest<BearerToken>,
+    ) -> Result<DapCollectorHttpResponse, DapError> {
+        self.reqs.borrow_mut().push((method, req));
+        self.resps
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| DapError::fatal("no more responses"))
+    }
+
+    async fn sleep(&self, duration: std::time::Duration) {
+        self.sleeps.borrow_mut().push(duration);
+    }
+}
+
+fn resp(status: u16, payload: Vec<u8>) -> DapCollectorHttpResponse {
+    DapCollectorHttpResponse {
+        status,
+        location: None,
+        retry_after: None,
+        report_counts: None,
+        payload,
+    }
+}
+
+fn count_agg_share(count: u64) -> DapAggregateShare {
+    DapAggregateShare {
+        report_count: count,
+        min_time: 0,
+        max_time: 0,
+        checksum: [0; 32],
+        data: Some(VdafAggregateShare::Field64(AggregateShare::from(
+            OutputShare::from(vec![Field64::from(count)]),
+        ))),
+    }
+}
+
+async fn collect_time_interval(version: DapVersion) {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+    let hpke_receiver = HpkeReceiverConfig::gen(7, HpkeKemId::X25519HkdfSha256).unwrap();
+    let leader_url = Url::parse(&format!("https://leader.com/{version}/")).unwrap();
+    let batch_interval = Interval {
+        start: 1637359200,
+        duration: 3600,
+    };
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: batch_interval.clone(),
+    };
+
+    // The Leader's and Helper's aggregate shares sum to 3.
+    let collection = Collection {
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_count: 3,
+        interval: Some(batch_interval.clone()),
+        encrypted_agg_shares: vec![
+            vdaf.produce_leader_encrypted_agg_share(
+                &hpke_receiver.config,
+                &task_id,
+                &batch_sel,
+                &count_agg_share(2),
+                version,
+            )
+            .unwrap(),
+            vdaf.produce_helper_encrypted_agg_share(
+                &hpke_receiver.config,
+                &task_id,
+                &batch_sel,
+                &count_agg_share(1),
+                version,
+            )
+            .unwrap(),
+        ],
+    };
+
+    let collect_uri = leader_url.join("collect/job").unwrap();
+    let start_resp = if version == DapVersion::Draft02 {
+        DapCollectorHttpResponse {
+            location: Some(collect_uri.to_string()),
+            ..resp(303, Vec::default())
+        }
+    } else {
+        resp(201, Vec::default())
+    };
+    let leader = ScriptedLeader::default();
+    leader.resps.borrow_mut().extend([
+        start_resp,
+        resp(202, Vec::default()),
+        DapCollectorHttpResponse {
+            retry_after: Some(30),
+            ..resp(202, Vec::default())
+        },
+        resp(200, collection.get_encoded_with_param(&version)),
+    ]);
+
+    let collector = DapCollector::new(version, task_id.clone(), leader_url, vdaf, hpke_receiver)
+        .with_sender_auth(BearerToken::from("collector token"))
+        .with_backoff(DapCollectorBackoff {
+            initial_delay_ms: 1000,
+            max_delay_ms: 10_000,
+            max_polls: 3,
+        });
+    let (got, agg_res) = collector
+        .collect(
+            &leader,
+            Query::TimeInterval { batch_interval },
+            Vec::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(got.report_count, 3);
+    assert_eq!(agg_res, DapAggregateResult::U64(3));
+
+    // The delay doubles after each poll. "Retry-After" takes precedence, up to the maximum delay.
+    assert_eq!(
+        *leader.sleeps.borrow(),
+        [
+            std::time::Duration::from_millis(1000),
+            std::time::Duration::from_millis(10_000),
+        ]
+    );
+
+    let reqs = leader.reqs.borrow();
+    assert_eq!(reqs.len(), 4);
+    assert!(reqs
+        .iter()
+        .all(|(_method, req)| req.task_id.as_ref() == Some(&task_id) && req.sender_auth.is_some()));
+    if version == DapVersion::Draft02 {
+        assert_eq!(reqs[0].0, DapCollectorHttpMethod::Post);
+        assert_eq!(reqs[1].0, DapCollectorHttpMethod::Get);
+        assert_eq!(reqs[1].1.url, collect_uri);
+    } else {
+        assert_eq!(reqs[0].0, DapCollectorHttpMethod::Put);
+        assert_matches!(reqs[0].1.resource, DapResource::CollectionJob(..));
+        assert_eq!(reqs[1].0, DapCollectorHttpMethod::Post);
+        assert_eq!(reqs[1].1.url, reqs[0].1.url);
+    }
+}
+
+async_test_versions! { collect_time_interval }
+
+#[tokio::test]
+async fn try_consume_collection_identifies_aggregator() {
+    let version = DapVersion::Draft04;
+    let task_id = TaskId(thread_rng().gen());
+    let vdaf = VdafConfig::Prio3(Prio3Config::Count);
+    let hpke_receiver = HpkeReceiverConfig::gen(7, HpkeKemId::X25519HkdfSha256).unwrap();
+    let batch_interval = Interval {
+        start: 1637359200,
+        duration: 3600,
+    };
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: batch_interval.clone(),
+    };
+    let leader_encrypted_agg_share = vdaf
+        .produce_leader_encrypted_agg_share(
+            &hpke_receiver.config,
+            &task_id,
+            &batch_sel,
+            &count_agg_share(2),
+            version,
+        )
+        .unwrap();
+    let helper_encrypted_agg_share = vdaf
+        .produce_helper_encrypted_agg_share(
+            &hpke_receiver.config,
+            &task_id,
+            &batch_sel,
+            &count_agg_share(1),
+            version,
+        )
+        .unwrap();
+    let collection = |encrypted_agg_shares| Collection {
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_count: 3,
+        interval: Some(batch_interval.clone()),
+        encrypted_agg_shares,
+    };
+    let collector: DapCollector<BearerToken> = DapCollector::new(
+        version,
+        task_id.clone(),
+        Url::parse("https://leader.com/v04/").unwrap(),
+        vdaf.clone(),
+        hpke_receiver.clone(),
+    );
+
+    assert_eq!(
+        collector
+            .try_consume_collection(
+                &batch_sel,
+                &collection(vec![
+                    leader_encrypted_agg_share.clone(),
+                    helper_encrypted_agg_share.clone(),
+                ]),
+            )
+            .await
+            .unwrap(),
+        DapAggregateResult::U64(3)
+    );
+
+    // The Leader's aggregate share is corrupted in transit.
+    let mut corrupted = leader_encrypted_agg_share.clone();
+    corrupted.payload[0] ^= 1;
+    let err = collector
+        .try_consume_collection(
+            &batch_sel,
+            &collection(vec![corrupted, helper_encrypted_agg_share]),
+        )
+        .await
+        .unwrap_err();
+    assert_matches!(err, DapUnshardError::Decrypt(DapSender::Leader, ..));
+    assert_eq!(err.aggregator(), Some(DapSender::Leader));
+
+    // The Helper's aggregate share decrypts, but is not a valid aggregate share for the VDAF.
+    let malformed = vdaf
+        .produce_helper_encrypted_agg_share(
+            &hpke_receiver.config,
+            &task_id,
+            &batch_sel,
+            &DapAggregateShare {
+                data: Some(VdafAggregateShare::Field128(AggregateShare::from(
+                    OutputShare::from(vec![Field128::from(1)]),
+                ))),
+                ..count_agg_share(1)
+            },
+            version,
+        )
+        .unwrap();
+    let err = collector
+        .try_consume_collection(
+            &batch_sel,
+            &collection(vec![leader_encrypted_agg_share.clone(), malformed.clone()]),
+        )
+        .await
+        .unwrap_err();
+    assert_matches!(err, DapUnshardError::Decode(DapSender::Helper, ..));
+
+    // Without the typed error, the failure is reported as before.
+    assert_matches!(
+        collector
+            .consume_collection(
+                &batch_sel,
+                &collection(vec![leader_encrypted_agg_share, malformed]),
+            )
+            .await,
+        Err(DapError::Transition(..))
+    );
+}
+
+#[tokio::test]
+async fn collect_gives_up_after_max_polls() {
+    let version = DapVersion::Draft04;
+    let leader = ScriptedLeader::default();
+    leader.resps.borrow_mut().extend([
+        resp(201, Vec::default()),
+        resp(202, Vec::default()),
+        resp(202, Vec::default()),
+    ]);
+
+    let collector: DapCollector<BearerToken> = DapCollector::new(
+        version,
+        TaskId(thread_rng().gen()),
+        Url::parse("https://leader.com/v04/").unwrap(),
+        VdafConfig::Prio3(Prio3Config::Count),
+        HpkeReceiverConfig::gen(7, HpkeKemId::X25519HkdfSha256).unwrap(),
+    )
+    .with_backoff(DapCollectorBackoff {
+        initial_delay_ms: 1000,
+        max_delay_ms: 1500,
+        max_polls: 2,
+    });
+    assert_matches!(
+        collector
+            .collect(&leader, Query::FixedSizeCurrentBatch, Vec::default())
+            .await,
+        Err(DapError::Fatal(..))
+    );
+    assert_eq!(
+        *leader.sleeps.borrow(),
+        [
+            std::time::Duration::from_millis(1000),
+            std::time::Duration::from_millis(1500),
+        ]
+    );
+}
+
+fn report_counts(task_id: &TaskId, counts: &[u64]) -> DapReportCountBreakdown {
+    DapReportCountBreakdown::new(
+        task_id,
+        &PartialBatchSelector::TimeInterval,
+        counts
+            .iter()
+            .enumerate()
+            .map(|(i, report_count)| DapBucketReportCount {
+                batch_window: Some(1637361337 + 3600 * i as u64),
+                report_count: *report_count,
+            })
+            .collect(),
+    )
+}
+
+#[test]
+fn verify_report_count_breakdown() {
+    let task_id = TaskId([1; 32]);
+    let collection = collection(3);
+
+    verify_report_counts(&task_id, &collection, &report_counts(&task_id, &[2, 1])).unwrap();
+
+    // The buckets do not add up to the report count.
+    assert_matches!(
+        verify_report_counts(&task_id, &collection, &report_counts(&task_id, &[2, 2])),
+        Err(DapError::Fatal(..))
+    );
+
+    // The breakdown was computed for a different task.
+    assert_matches!(
+        verify_report_counts(
+            &task_id,
+            &collection,
+            &report_counts(&TaskId([2; 32]), &[2, 1])
+        ),
+        Err(DapError::Fatal(..))
+    );
+
+    // The buckets were modified after the digest was computed.
+    let mut tampered = report_counts(&task_id, &[2, 2]);
+    tampered.buckets[1].report_count = 1;
+    assert_matches!(
+        verify_report_counts(&task_id, &collection, &tampered),
+        Err(DapError::Fatal(..))
+    );
+}
+
+#[tokio::test]
+async fn poll_collection_job_checks_report_counts() {
+    let version = DapVersion::Draft04;
+    let task_id = TaskId(thread_rng().gen());
+    let leader_url = Url::parse("https://leader.com/v04/").unwrap();
+    let collector: DapCollector<BearerToken> = DapCollector::new(
+        version,
+        task_id.clone(),
+        leader_url.clone(),
+        VdafConfig::Prio3(Prio3Config::Count),
+        HpkeReceiverConfig::gen(7, HpkeKemId::X25519HkdfSha256).unwrap(),
+    );
+    let uri = leader_url.join("collect/job").unwrap();
+    let payload = collection(3).get_encoded_with_param(&version);
+
+    let leader = ScriptedLeader::default();
+    leader.resps.borrow_mut().extend([
+        DapCollectorHttpResponse {
+            report_counts: Some(report_counts(&task_id, &[1, 2])),
+            ..resp(200, payload.clone())
+        },
+        DapCollectorHttpResponse {
+            report_counts: Some(report_counts(&task_id, &[1, 1])),
+            ..resp(200, payload)
+        },
+    ]);
+
+    assert_matches!(
+        collector.poll_collection_job(&leader, &uri).await,
+        Ok(DapCollectionJobStatus::Done(..))
+    );
+    assert_matches!(
+        collector.poll_collection_job(&leader, &uri).await,
+        Err(DapError::Fatal(..))
+    );
+}
@@ -22,6 +22,8 @@ const MEDIA_TYPE_COLLECTION: &str = "application/dap-collection";
 const MEDIA_TYPE_COLLECT_REQ: &str = "application/dap-collect-req";
 const MEDIA_TYPE_HPKE_CONFIG_LIST: &str = "application/dap-hpke-config-list";
 const MEDIA_TYPE_REPORT: &str = "application/dap-report";
+const MEDIA_TYPE_REPORT_BATCH: &str = "application/dap-report-batch";
+const MEDIA_TYPE_JSON: &str = "application/json";
 
 /// Media type for each DAP request. This is included in the "content-type" HTTP header.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -38,6 +40,9 @@ pub enum DapMediaType {
     Collection,
     HpkeConfigList,
     Report,
+    /// A batch of reports uploaded in one request. This is a Daphne extension to DAP and is not
+    /// supported in draft02.
+    ReportBatch,
     /// The content-type does not match a known media type.
     Invalid(String),
     /// No content-type header found.
@@ -57,7 +62,7 @@ impl DapMediaType {
             Self::AggregationJobResp
             | Self::Draft02AggregateContinueResp
             | Self::AggregateShare => Some(DapSender::Helper),
-            Self::Report => Some(DapSender::Client),
+            Self::Report | Self::ReportBatch => Some(DapSender::Client),
             Self::CollectReq => Some(DapSender::Collector),
             Self::Invalid(..) | Self::Missing => None,
         }
@@ -67,30 +72,57 @@ impl DapMediaType {
     pub fn from_str_for_version(version: DapVersion, content_type: Option<&str>) -> Self {
         match (version, content_type) {
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_CONT_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_JOB_CONT_REQ)) => {
-                Self::AggregationJobContinueReq
-            }
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_AGG_JOB_CONT_REQ),
+            ) => Self::AggregationJobContinueReq,
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_CONT_RESP)) => {
                 Self::Draft02AggregateContinueResp
             }
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_INIT_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_JOB_INIT_REQ)) => {
-                Self::AggregationJobInitReq
-            }
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_AGG_JOB_INIT_REQ),
+            ) => Self::AggregationJobInitReq,
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_INIT_RESP))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_JOB_RESP)) => Self::AggregationJobResp,
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_AGG_JOB_RESP),
+            ) => Self::AggregationJobResp,
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_AGG_SHARE_RESP))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_SHARE)) => Self::AggregateShare,
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_AGG_SHARE),
+            ) => Self::AggregateShare,
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_COLLECT_RESP))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_COLLECTION)) => Self::Collection,
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_COLLECTION),
+            ) => Self::Collection,
             (DapVersion::Draft02, Some(DRAFT02_MEDIA_TYPE_HPKE_CONFIG))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_HPKE_CONFIG_LIST)) => Self::HpkeConfigList,
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_HPKE_CONFIG_LIST),
+            ) => Self::HpkeConfigList,
             (DapVersion::Draft02, Some(MEDIA_TYPE_AGG_SHARE_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_AGG_SHARE_REQ)) => Self::AggregateShareReq,
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_AGG_SHARE_REQ),
+            ) => Self::AggregateShareReq,
             (DapVersion::Draft02, Some(MEDIA_TYPE_COLLECT_REQ))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_COLLECT_REQ)) => Self::CollectReq,
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_COLLECT_REQ),
+            ) => Self::CollectReq,
             (DapVersion::Draft02, Some(MEDIA_TYPE_REPORT))
-            | (DapVersion::Draft04, Some(MEDIA_TYPE_REPORT)) => Self::Report,
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_REPORT),
+            ) => Self::Report,
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Some(MEDIA_TYPE_REPORT_BATCH),
+            ) => Self::ReportBatch,
             (_, Some(content_type)) => Self::Invalid(content_type.to_string()),
             (_, None) => Self::Missing,
         }
@@ -102,35 +134,60 @@ impl DapMediaType {
             (DapVersion::Draft02, Self::AggregationJobInitReq) => {
                 Some(DRAFT02_MEDIA_TYPE_AGG_INIT_REQ)
             }
-            (DapVersion::Draft04, Self::AggregationJobInitReq) => Some(MEDIA_TYPE_AGG_JOB_INIT_REQ),
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Self::AggregationJobInitReq,
+            ) => Some(MEDIA_TYPE_AGG_JOB_INIT_REQ),
             (DapVersion::Draft02, Self::AggregationJobResp) => {
                 Some(DRAFT02_MEDIA_TYPE_AGG_INIT_RESP)
             }
-            (DapVersion::Draft04, Self::AggregationJobResp) => Some(MEDIA_TYPE_AGG_JOB_RESP),
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Self::AggregationJobResp,
+            ) => Some(MEDIA_TYPE_AGG_JOB_RESP),
             (DapVersion::Draft02, Self::AggregationJobContinueReq) => {
                 Some(DRAFT02_MEDIA_TYPE_AGG_CONT_REQ)
             }
-            (DapVersion::Draft04, Self::AggregationJobContinueReq) => {
-                Some(MEDIA_TYPE_AGG_JOB_CONT_REQ)
-            }
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Self::AggregationJobContinueReq,
+            ) => Some(MEDIA_TYPE_AGG_JOB_CONT_REQ),
             (DapVersion::Draft02, Self::Draft02AggregateContinueResp) => {
                 Some(DRAFT02_MEDIA_TYPE_AGG_CONT_RESP)
             }
             (_, Self::Draft02AggregateContinueResp) => None,
             (DapVersion::Draft02, Self::AggregateShareReq)
-            | (DapVersion::Draft04, Self::AggregateShareReq) => Some(MEDIA_TYPE_AGG_SHARE_REQ),
+            | (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Self::AggregateShareReq,
+            ) => Some(MEDIA_TYPE_AGG_SHARE_REQ),
             (DapVersion::Draft02, Self::AggregateShare) => Some(DRAFT02_MEDIA_TYPE_AGG_SHARE_RESP),
-            (DapVersion::Draft04, Self::AggregateShare) => Some(MEDIA_TYPE_AGG_SHARE),
-            (DapVersion::Draft02, Self::CollectReq) | (DapVersion::Draft04, Self::CollectReq) => {
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Self::AggregateShare,
+            ) => Some(MEDIA_TYPE_AGG_SHARE),
+            (DapVersion::Draft02, Self::CollectReq)
+            | (DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06, Self::CollectReq) => {
                 Some(MEDIA_TYPE_COLLECT_REQ)
             }
             (DapVersion::Draft02, Self::Collection) => Some(DRAFT02_MEDIA_TYPE_COLLECT_RESP),
-            (DapVersion::Draft04, Self::Collection) => Some(MEDIA_TYPE_COLLECTION),
+            (DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06, Self::Collection) => {
+                Some(MEDIA_TYPE_COLLECTION)
+            }
             (DapVersion::Draft02, Self::HpkeConfigList) => Some(DRAFT02_MEDIA_TYPE_HPKE_CONFIG),
-            (DapVersion::Draft04, Self::HpkeConfigList) => Some(MEDIA_TYPE_HPKE_CONFIG_LIST),
-            (DapVersion::Draft02, Self::Report) | (DapVersion::Draft04, Self::Report) => {
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Self::HpkeConfigList,
+            ) => Some(MEDIA_TYPE_HPKE_CONFIG_LIST),
+            (DapVersion::Draft02, Self::Report)
+            | (DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06, Self::Report) => {
                 Some(MEDIA_TYPE_REPORT)
             }
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                Self::ReportBatch,
+            ) => Some(MEDIA_TYPE_REPORT_BATCH),
+            (DapVersion::Draft02, Self::ReportBatch) => None,
             (_, Self::Invalid(ref content_type)) => Some(content_type),
             (_, Self::Missing) => None,
             (DapVersion::Unknown, _) => unreachable!("unhandled version {version:?}"),
@@ -142,8 +199,48 @@ impl DapMediaType {
     pub(crate) fn agg_job_cont_resp_for_version(version: DapVersion) -> Self {
         match version {
             DapVersion::Draft02 => Self::Draft02AggregateContinueResp,
-            DapVersion::Draft04 => Self::AggregationJobResp,
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                Self::AggregationJobResp
+            }
             _ => unreachable!("unhandled version {version:?}"),
         }
     }
 }
+
+/// Encoding of the result of a collection job, as requested by the Collector in the "accept" HTTP
+/// header of the request polling the job.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DapCollectionFormat {
+    /// The `Collection` message, TLS-encoded as specified by DAP.
+    Tls,
+    /// The `Collection` message encoded as JSON. This is a Daphne extension intended for
+    /// debugging; it is only served to authenticated internal users.
+    Json,
+}
+
+impl DapCollectionFormat {
+    /// Negotiate the format from the accept HTTP header. The media ranges are considered in the
+    /// order in which they are listed, skipping those with "q=0". If the header is missing, then
+    /// the format is [`Self::Tls`]. Returns `None` if no listed media range is supported.
+    pub fn from_accept_for_version(version: DapVersion, accept: Option<&str>) -> Option<Self> {
+        let accept = match accept {
+            Some(accept) => accept,
+            None => return Some(Self::Tls),
+        };
+        let collection = DapMediaType::Collection.as_str_for_version(version);
+        for media_range in accept.split(',') {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            if params.any(|param| matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000")) {
+                continue;
+            }
+            match media_type {
+                "*/*" | "application/*" => return Some(Self::Tls),
+                MEDIA_TYPE_JSON => return Some(Self::Json),
+                _ if Some(media_type) == collection => return Some(Self::Tls),
+                _ => (),
+            }
+        }
+        None
+    }
+}
@@ -1,7 +1,10 @@
 // Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::{constants::DapMediaType, DapVersion};
+use crate::{
+    constants::{DapCollectionFormat, DapMediaType},
+    DapVersion,
+};
 
 #[test]
 fn from_str_for_version() {
@@ -163,6 +166,27 @@ fn round_trip() {
         (DapVersion::Draft04, DapMediaType::HpkeConfigList),
         (DapVersion::Draft02, DapMediaType::Report),
         (DapVersion::Draft04, DapMediaType::Report),
+        (DapVersion::Draft04, DapMediaType::ReportBatch),
+        (DapVersion::Draft05, DapMediaType::AggregationJobInitReq),
+        (DapVersion::Draft05, DapMediaType::AggregationJobResp),
+        (DapVersion::Draft05, DapMediaType::AggregationJobContinueReq),
+        (DapVersion::Draft05, DapMediaType::AggregateShareReq),
+        (DapVersion::Draft05, DapMediaType::AggregateShare),
+        (DapVersion::Draft05, DapMediaType::CollectReq),
+        (DapVersion::Draft05, DapMediaType::Collection),
+        (DapVersion::Draft05, DapMediaType::HpkeConfigList),
+        (DapVersion::Draft05, DapMediaType::Report),
+        (DapVersion::Draft05, DapMediaType::ReportBatch),
+        (DapVersion::Draft06, DapMediaType::AggregationJobInitReq),
+        (DapVersion::Draft06, DapMediaType::AggregationJobResp),
+        (DapVersion::Draft06, DapMediaType::AggregationJobContinueReq),
+        (DapVersion::Draft06, DapMediaType::AggregateShareReq),
+        (DapVersion::Draft06, DapMediaType::AggregateShare),
+        (DapVersion::Draft06, DapMediaType::CollectReq),
+        (DapVersion::Draft06, DapMediaType::Collection),
+        (DapVersion::Draft06, DapMediaType::HpkeConfigList),
+        (DapVersion::Draft06, DapMediaType::Report),
+        (DapVersion::Draft06, DapMediaType::ReportBatch),
     ] {
         assert_eq!(
             DapMediaType::from_str_for_version(version, media_type.as_str_for_version(version)),
@@ -185,4 +209,49 @@ fn media_type_for_agg_cont_req() {
         DapMediaType::AggregationJobResp,
         DapMediaType::agg_job_cont_resp_for_version(DapVersion::Draft04)
     );
+
+    assert_eq!(
+        DapMediaType::AggregationJobResp,
+        DapMediaType::agg_job_cont_resp_for_version(DapVersion::Draft05)
+    );
+}
+
+#[test]
+fn collection_format_from_accept() {
+    let version = DapVersion::Draft04;
+    let negotiate = |accept| DapCollectionFormat::from_accept_for_version(version, accept);
+
+    assert_eq!(negotiate(None), Some(DapCollectionFormat::Tls));
+    assert_eq!(
+        negotiate(Some("application/dap-collection")),
+        Some(DapCollectionFormat::Tls)
+    );
+    assert_eq!(negotiate(Some("*/*")), Some(DapCollectionFormat::Tls));
+    assert_eq!(
+        negotiate(Some("application/json")),
+        Some(DapCollectionFormat::Json)
+    );
+
+    // The first acceptable media range wins.
+    assert_eq!(
+        negotiate(Some(
+            "application/json; charset=utf-8, application/dap-collection"
+        )),
+        Some(DapCollectionFormat::Json)
+    );
+    assert_eq!(
+        negotiate(Some("application/json;q=0, application/dap-collection")),
+        Some(DapCollectionFormat::Tls)
+    );
+
+    // The draft02 media type is not accepted in the latest draft.
+    assert_eq!(negotiate(Some("application/dap-collect-resp")), None);
+    assert_eq!(
+        DapCollectionFormat::from_accept_for_version(
+            DapVersion::Draft02,
+            Some("application/dap-collect-resp")
+        ),
+        Some(DapCollectionFormat::Tls)
+    );
+    assert_eq!(negotiate(Some("text/html")), None);
 }
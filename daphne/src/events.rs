@@ -0,0 +1,174 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Batch lifecycle events.
+//!
+//! An Aggregator emits a [`DapBatchEvent`] to its [`DapEventSink`] whenever a batch changes state,
+//! so that downstream systems can react to it. Events are emitted for each bucket of reports:
+//! For fixed-size tasks, a bucket is a batch; for time-interval tasks, it is the set of reports
+//! whose timestamps fall in the same window of `time_precision` seconds. Emitting an event is
+//! best-effort: A sink that fails to emit an event does not fail the operation that caused it.
+
+use crate::{
+    messages::{BatchSelector, TaskId, Time},
+    DapBatchBucket, DapError, DapReportCountBreakdown, DapTaskConfig,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// The state change of a batch.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DapBatchEventType {
+    /// The first report was aggregated into the bucket.
+    Created,
+
+    /// The number of reports aggregated into the bucket reached the task's minimum batch size.
+    Filled,
+
+    /// The bucket was collected. This event is emitted by the Leader only, and only for buckets
+    /// into which at least one report was aggregated.
+    Collected,
+
+    /// The task expired and its state was deleted. The buckets of the task are not listed
+    /// individually.
+    Expired,
+}
+
+/// An event in the lifecycle of a batch.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapBatchEvent {
+    pub event: DapBatchEventType,
+
+    /// The task ID, encoded as URL-safe base64.
+    pub task_id: String,
+
+    /// The batch ID, encoded as URL-safe base64. This is `None` for time-interval tasks and for
+    /// events that concern the task as a whole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_id: Option<String>,
+
+    /// Start of the bucket's batch window. This is `None` for fixed-size tasks and for events that
+    /// concern the task as a whole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_window: Option<Time>,
+
+    /// The number of reports aggregated into the bucket, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_count: Option<u64>,
+}
+
+impl DapBatchEvent {
+    /// Create an event for the given bucket.
+    pub fn for_bucket(
+        event: DapBatchEventType,
+        task_id: &TaskId,
+        bucket: &DapBatchBucket<'_>,
+        report_count: Option<u64>,
+    ) -> Self {
+        let (batch_id, batch_window) = match bucket {
+            DapBatchBucket::FixedSize { batch_id } => (Some(batch_id.to_base64url()), None),
+            DapBatchBucket::TimeInterval { batch_window } => (None, Some(*batch_window)),
+        };
+        Self {
+            event,
+            task_id: task_id.to_base64url(),
+            batch_id,
+            batch_window,
+            report_count,
+        }
+    }
+
+    /// Create an event that concerns each bucket of the task.
+    pub fn for_task(event: DapBatchEventType, task_id: &TaskId) -> Self {
+        Self {
+            event,
+            task_id: task_id.to_base64url(),
+            batch_id: None,
+            batch_window: None,
+            report_count: None,
+        }
+    }
+
+    /// Compute the events caused by merging reports into a bucket, given the report count of the
+    /// bucket before and after the merge.
+    pub fn for_merge(
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        report_count_before: u64,
+        report_count_after: u64,
+    ) -> Vec<Self> {
+        let mut events = Vec::new();
+        if report_count_before == 0 && report_count_after > 0 {
+            events.push(Self::for_bucket(
+                DapBatchEventType::Created,
+                task_id,
+                bucket,
+                Some(report_count_after),
+            ));
+        }
+        if report_count_before < task_config.min_batch_size
+            && report_count_after >= task_config.min_batch_size
+        {
+            events.push(Self::for_bucket(
+                DapBatchEventType::Filled,
+                task_id,
+                bucket,
+                Some(report_count_after),
+            ));
+        }
+        events
+    }
+
+    /// Compute the events caused by collecting a batch, given the Leader's breakdown of the
+    /// collection's report count.
+    pub fn for_collection(
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        report_counts: &DapReportCountBreakdown,
+    ) -> Vec<Self> {
+        report_counts
+            .buckets
+            .iter()
+            .map(|bucket_count| {
+                let bucket = match (batch_sel, bucket_count.batch_window) {
+                    (BatchSelector::FixedSizeByBatchId { batch_id }, _) => {
+                        DapBatchBucket::FixedSize { batch_id }
+                    }
+                    (BatchSelector::TimeInterval { batch_interval }, batch_window) => {
+                        DapBatchBucket::TimeInterval {
+                            batch_window: batch_window.unwrap_or(batch_interval.start),
+                        }
+                    }
+                };
+                Self::for_bucket(
+                    DapBatchEventType::Collected,
+                    task_id,
+                    &bucket,
+                    Some(bucket_count.report_count),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Destination of batch lifecycle events.
+#[async_trait(?Send)]
+pub trait DapEventSink {
+    /// Emit an event.
+    async fn emit(&self, event: &DapBatchEvent) -> Result<(), DapError>;
+}
+
+/// Event sink that logs each event as a JSON object.
+#[derive(Default)]
+pub struct DapLogEventSink;
+
+#[async_trait(?Send)]
+impl DapEventSink for DapLogEventSink {
+    async fn emit(&self, event: &DapBatchEvent) -> Result<(), DapError> {
+        info!(target: "daphne::events", "{}", serde_json::to_string(event)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Pluggable handling of report extensions.
+//!
+//! The taskprov extension is handled by Daphne itself. Any other extension carried by a report
+//! must be claimed by a [`DapExtensionHandler`] registered with the Aggregator's
+//! [`DapExtensionRegistry`]; reports carrying an extension with no handler are rejected. Handlers
+//! are consulted when the Leader accepts an upload (only for draft02, where extensions are carried
+//! in the report metadata) and when either Aggregator prepares its input share.
+//!
+//! Clients describe the extensions of a report with [`DapReportExtensions`], which places each
+//! extension where the DAP version expects it.
+
+use crate::{
+    messages::{Extension, ReportMetadata, TaskId, TransitionFailure, EXTENSION_TASKPROV},
+    DapError, DapTaskConfig, DapVersion,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Extensions carried by a report, grouped by who gets to see them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DapReportExtensions {
+    /// Extensions seen by both Aggregators. In draft02 these are carried in the report metadata.
+    /// Later versions have no public extensions, so they are copied into the plaintext input
+    /// share of each Aggregator.
+    pub public: Vec<Extension>,
+
+    /// Extensions seen only by the Leader, carried in its plaintext input share. Not supported by
+    /// draft02.
+    pub leader: Vec<Extension>,
+
+    /// Extensions seen only by the Helper, carried in its plaintext input share. Not supported by
+    /// draft02.
+    pub helper: Vec<Extension>,
+}
+
+impl From<Vec<Extension>> for DapReportExtensions {
+    fn from(public: Vec<Extension>) -> Self {
+        Self {
+            public,
+            ..Default::default()
+        }
+    }
+}
+
+impl DapReportExtensions {
+    /// Place the extensions for the given version. The return value is the list of extensions for
+    /// the report metadata followed by the list for each plaintext input share, the Leader's
+    /// first. It is an error for an extension type to be seen more than once by an Aggregator.
+    pub(crate) fn place(
+        &self,
+        version: DapVersion,
+    ) -> Result<(Vec<Extension>, [Vec<Extension>; 2]), DapError> {
+        let (metadata, input_shares) = match version {
+            DapVersion::Draft02 => {
+                if !self.leader.is_empty() || !self.helper.is_empty() {
+                    return Err(DapError::fatal(
+                        "draft02 does not support extensions in the input shares",
+                    ));
+                }
+                (self.public.clone(), [Vec::new(), Vec::new()])
+            }
+            _ => (
+                Vec::new(),
+                [&self.leader, &self.helper]
+                    .map(|private| self.public.iter().chain(private.iter()).cloned().collect()),
+            ),
+        };
+
+        for extensions in [&metadata, &input_shares[0], &input_shares[1]] {
+            let mut seen = HashSet::new();
+            for extension in extensions {
+                if !seen.insert(extension.type_code()) {
+                    return Err(DapError::Fatal(format!(
+                        "extension type {:#06x} is carried more than once",
+                        extension.type_code()
+                    )));
+                }
+            }
+        }
+
+        Ok((metadata, input_shares))
+    }
+}
+
+/// Handler for a report extension type.
+///
+/// Handlers only get to accept or reject a report: Extensions are bound to the report's
+/// ciphertexts and hence can't be modified in transit.
+pub trait DapExtensionHandler: Send + Sync {
+    /// Check the payload of the extension carried by a report. The return value is the reason
+    /// for rejecting the report, if any.
+    fn validate(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        metadata: &ReportMetadata,
+        payload: &[u8],
+    ) -> Result<(), TransitionFailure>;
+}
+
+/// Set of handlers for report extensions, keyed by extension type.
+#[derive(Default)]
+pub struct DapExtensionRegistry {
+    handlers: HashMap<u16, Box<dyn DapExtensionHandler>>,
+}
+
+impl DapExtensionRegistry {
+    /// Register the handler for the given extension type. It is an error to register more than
+    /// one handler for the same type or to register a handler for taskprov.
+    pub fn register(
+        &mut self,
+        typ: u16,
+        handler: impl DapExtensionHandler + 'static,
+    ) -> Result<(), DapError> {
+        if typ == EXTENSION_TASKPROV {
+            return Err(DapError::fatal(
+                "tried to register a handler for the taskprov extension",
+            ));
+        }
+        if self.handlers.contains_key(&typ) {
+            return Err(DapError::Fatal(format!(
+                "tried to register a second handler for extension type {typ:#06x}"
+            )));
+        }
+        self.handlers.insert(typ, Box::new(handler));
+        Ok(())
+    }
+
+    /// Check each of the extensions carried by a report. The report is rejected with
+    /// `UnrecognizedMessage` if an extension has no handler, or with the failure returned by the
+    /// first handler that rejects it.
+    pub(crate) fn validate<'a>(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        metadata: &ReportMetadata,
+        extensions: impl IntoIterator<Item = &'a Extension>,
+    ) -> Result<(), TransitionFailure> {
+        for extension in extensions {
+            match extension {
+                Extension::Taskprov { .. } => (),
+                Extension::Unhandled { typ, payload } => self
+                    .handlers
+                    .get(typ)
+                    .ok_or(TransitionFailure::UnrecognizedMessage)?
+                    .validate(task_id, task_config, metadata, payload)?,
+            }
+        }
+        Ok(())
+    }
+}
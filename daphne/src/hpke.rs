@@ -13,8 +13,8 @@ use hpke_rs_rust_crypto::HpkeRustCrypto as ImplHpkeCrypto;
 
 use crate::{
     messages::{
-        decode_u16_bytes, encode_u16_bytes, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId,
-        HpkeKemId, TaskId, TransitionFailure,
+        decode_u16_bytes, encode_u16_bytes, Duration, HpkeAeadId, HpkeCiphertext, HpkeConfig,
+        HpkeKdfId, HpkeKemId, TaskId, Time, TransitionFailure,
     },
     DapError, DapVersion,
 };
@@ -74,6 +74,12 @@ impl HpkeConfig {
         Ok((enc, ciphertext))
     }
 
+    /// Check that the ciphersuite of this HPKE configuration is implemented.
+    pub fn check_suite(&self) -> Result<(), DapError> {
+        check_suite::<ImplHpkeCrypto>(self.kem_id, self.kdf_id, self.aead_id)?;
+        Ok(())
+    }
+
     pub(crate) fn decrypt(
         &self,
         private_key: &HpkePrivateKey,
@@ -113,6 +119,18 @@ pub trait HpkeDecrypter<'a> {
         aad: &[u8],
         ciphertext: &HpkeCiphertext,
     ) -> Result<Vec<u8>, DapError>;
+
+    /// Look up the list of HPKE configurations to advertise for the given task ID (if specified).
+    /// The first config in the list is the one Clients should prefer. The default implementation
+    /// advertises the config returned by `get_hpke_config_for()`.
+    async fn get_hpke_config_list_for(
+        &'a self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+    ) -> Result<Vec<HpkeConfig>, DapError> {
+        let hpke_config = self.get_hpke_config_for(version, task_id).await?;
+        Ok(vec![hpke_config.as_ref().clone()])
+    }
 }
 
 /// Struct that combines HpkeConfig and HpkeSecretKey
@@ -179,6 +197,108 @@ impl HpkeReceiverConfig {
     }
 }
 
+/// The time window during which an HPKE config is advertised to Clients.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HpkeConfigValidity {
+    /// The first time (inclusive) at which the config may be advertised.
+    pub not_before: Time,
+
+    /// The time (exclusive) after which the config is no longer advertised.
+    pub not_after: Time,
+}
+
+impl HpkeConfigValidity {
+    /// Check whether the config may be advertised at time `now`.
+    pub fn contains(&self, now: Time) -> bool {
+        self.not_before <= now && now < self.not_after
+    }
+}
+
+/// Parameters for rotating an Aggregator's HPKE receiver configs.
+///
+/// A new config is generated every `period` seconds. Each config is advertised for
+/// `period * advertised_count` seconds, so that up to `advertised_count` configs are advertised
+/// at any given time. Once a config is no longer advertised, reports encrypted to it are accepted
+/// for another `grace_period` seconds, after which the config may be deleted.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HpkeRotationConfig {
+    /// Time (in seconds) between rotations.
+    pub period: Duration,
+
+    /// Number of configs advertised at once.
+    pub advertised_count: u64,
+
+    /// Time (in seconds) after a config is no longer advertised during which reports encrypted to
+    /// it are still accepted.
+    pub grace_period: Duration,
+}
+
+impl HpkeRotationConfig {
+    /// The validity window of a config generated at time `now`.
+    pub fn validity_for_new_config(&self, now: Time) -> HpkeConfigValidity {
+        HpkeConfigValidity {
+            not_before: now,
+            not_after: now.saturating_add(self.period.saturating_mul(self.advertised_count)),
+        }
+    }
+
+    /// Check whether a new config needs to be generated at time `now`. `newest_not_before` is the
+    /// start of the validity window of the config that was generated most recently, if any.
+    pub fn is_rotation_due(&self, newest_not_before: Option<Time>, now: Time) -> bool {
+        newest_not_before.is_none_or(|not_before| not_before.saturating_add(self.period) <= now)
+    }
+
+    /// Check whether a config with the given validity window has expired at time `now`, i.e.,
+    /// whether its grace period has elapsed. Reports encrypted to an expired config are rejected.
+    pub fn is_expired(&self, validity: &HpkeConfigValidity, now: Time) -> bool {
+        validity.not_after.saturating_add(self.grace_period) <= now
+    }
+}
+
+/// An HPKE receiver config generated ahead of time along with its validity window.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HpkeReceiverConfigWithValidity {
+    pub receiver_config: HpkeReceiverConfig,
+    pub validity: HpkeConfigValidity,
+}
+
+/// Generate a schedule of `count` HPKE receiver configs for the given KEM. The validity windows are
+/// consecutive, each of length `period`, beginning at `not_before`. Config IDs are assigned by
+/// incrementing `first_config_id`.
+///
+/// This is intended for generating keys offline, e.g., as part of a key ceremony, so that they can
+/// later be imported by the Aggregator.
+pub fn gen_hpke_receiver_config_schedule(
+    kem_id: HpkeKemId,
+    first_config_id: u8,
+    count: usize,
+    not_before: Time,
+    period: Duration,
+) -> Result<Vec<HpkeReceiverConfigWithValidity>, DapError> {
+    if count > 256 {
+        return Err(DapError::fatal(
+            "cannot generate more than 256 HPKE receiver configs at once",
+        ));
+    }
+    if period == 0 {
+        return Err(DapError::fatal("validity period must be positive"));
+    }
+
+    (0..count)
+        .map(|i| {
+            let config_id = first_config_id.wrapping_add(i as u8);
+            let start = not_before + period * i as u64;
+            Ok(HpkeReceiverConfigWithValidity {
+                receiver_config: HpkeReceiverConfig::gen(config_id, kem_id)?,
+                validity: HpkeConfigValidity {
+                    not_before: start,
+                    not_after: start + period,
+                },
+            })
+        })
+        .collect()
+}
+
 impl TryFrom<(HpkeConfig, HpkePrivateKey)> for HpkeReceiverConfig {
     type Error = DapError;
     /// Create a new HPKE receiver context given an HpkeConfig and a corresponding private key.
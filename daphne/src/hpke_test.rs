@@ -1,7 +1,9 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::hpke::HpkeReceiverConfig;
+use crate::hpke::{
+    gen_hpke_receiver_config_schedule, HpkeConfigValidity, HpkeReceiverConfig, HpkeRotationConfig,
+};
 use crate::messages::{HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId};
 use hpke_rs::{Hpke, HpkePrivateKey, HpkePublicKey, Mode};
 use hpke_rs_crypto::types::{AeadAlgorithm, KdfAlgorithm, KemAlgorithm};
@@ -65,3 +67,57 @@ fn hpke_receiver_config_try_from() {
     let bad_private_key = HpkePrivateKey::from(vec![0; 20]);
     assert!(HpkeReceiverConfig::try_from((config, bad_private_key)).is_err());
 }
+
+#[test]
+fn gen_hpke_receiver_config_schedule_windows() {
+    let schedule =
+        gen_hpke_receiver_config_schedule(HpkeKemId::X25519HkdfSha256, 255, 3, 1000, 100).unwrap();
+    assert_eq!(schedule.len(), 3);
+
+    // Config IDs wrap around.
+    let ids = schedule
+        .iter()
+        .map(|c| c.receiver_config.config.id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [255, 0, 1]);
+
+    // Windows are consecutive and do not overlap.
+    assert!(schedule[0].validity.contains(1000));
+    assert!(!schedule[0].validity.contains(1100));
+    assert!(schedule[1].validity.contains(1100));
+    assert_eq!(schedule[2].validity.not_before, 1200);
+    assert_eq!(schedule[2].validity.not_after, 1300);
+
+    assert!(
+        gen_hpke_receiver_config_schedule(HpkeKemId::X25519HkdfSha256, 0, 257, 1000, 100).is_err()
+    );
+}
+
+#[test]
+fn hpke_rotation_overlap_and_grace() {
+    let rotation = HpkeRotationConfig {
+        period: 100,
+        advertised_count: 3,
+        grace_period: 50,
+    };
+
+    // Each config is advertised for three periods.
+    let validity = rotation.validity_for_new_config(1000);
+    assert_eq!(
+        validity,
+        HpkeConfigValidity {
+            not_before: 1000,
+            not_after: 1300,
+        }
+    );
+
+    // A new config is due once per period.
+    assert!(rotation.is_rotation_due(None, 1000));
+    assert!(!rotation.is_rotation_due(Some(1000), 1099));
+    assert!(rotation.is_rotation_due(Some(1000), 1100));
+
+    // Reports encrypted to the config are accepted until the grace period has elapsed.
+    assert!(!validity.contains(1300));
+    assert!(!rotation.is_expired(&validity, 1349));
+    assert!(rotation.is_expired(&validity, 1350));
+}
@@ -11,7 +11,9 @@
 //! Daphne does not provide the complete, end-to-end functionality of any party in the protocol.
 //! Instead, it defines traits for the functionalities that a concrete instantiation of the
 //! protocol is required to implement. For example, the `daphne_worker` crate implements a backend
-//! for the DAP Leader and Helper. See the [`crate::roles`](roles) module for details.
+//! for the DAP Leader and Helper. See the [`crate::roles`](roles) module for details. The
+//! [`crate::client`](client) and [`crate::collector`](collector) modules implement the Client and
+//! Collector on top of an HTTP transport provided by the caller.
 //!
 //! Daphne is not yet feature complete. Known issues include:
 //!
@@ -25,26 +27,25 @@
 //! * Aborts are not handled precisely as specified. In particular, some fields in the "Problem
 //! Details" document are omitted.
 //!
-//! * Daphne does not implement a complete DAP Client or Collector. However, methods are provided
-//! on [`VdafConfig`](crate::VdafConfig) for producing reports and consuming aggregate results.
-//!
 //! * Daphne does not yet support deletion of collection jobs:
 //!
 //!     > The leader MUST remove a collect job's results when the collector sends an HTTP DELETE
 //!     > request to the collect job  URI. The leader responds with HTTP status 204 No Content for
 //!     > requests to a collect job URI whose results have been removed.
 
+#[cfg(feature = "prio2")]
+use crate::vdaf::prio2::prio2_decode_prepare_state;
 use crate::{
-    aborts::DapAbort,
-    hpke::HpkeReceiverConfig,
+    aborts::{DapAbort, ProblemDetails},
+    auth::{DapClientAuth, DapUploadAuth},
+    hpke::{HpkeReceiverConfig, HpkeRotationConfig},
     messages::{
-        AggregationJobId, BatchId, BatchSelector, Collection, CollectionJobId,
+        AggregationJobId, BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq,
         Draft02AggregationJobId, Duration, HpkeConfig, HpkeKemId, Interval, PartialBatchSelector,
         ReportId, ReportMetadata, TaskId, Time, TransitionFailure,
     },
     taskprov::TaskprovVersion,
     vdaf::{
-        prio2::prio2_decode_prepare_state,
         prio3::{prio3_append_prepare_state, prio3_decode_prepare_state},
         VdafAggregateShare, VdafError, VdafMessage, VdafState, VdafVerifyKey,
     },
@@ -131,6 +132,16 @@ pub enum DapVersion {
     #[serde(rename = "v04")]
     Draft04,
 
+    /// Messages are encoded as in draft04; only the domain separation tags differ.
+    #[serde(rename = "v05")]
+    Draft05,
+
+    /// Messages are encoded as in draft05, except that aggregation follows the ping-pong topology:
+    /// The Leader sends its prep share along with each report share, and the Helper finishes
+    /// preparation in its response to the AggregationJobInitReq.
+    #[serde(rename = "v06")]
+    Draft06,
+
     #[serde(other)]
     #[serde(rename = "unknown_version")]
     Unknown,
@@ -141,6 +152,8 @@ impl From<&str> for DapVersion {
         match version {
             "v02" => DapVersion::Draft02,
             "v04" => DapVersion::Draft04,
+            "v05" => DapVersion::Draft05,
+            "v06" => DapVersion::Draft06,
             _ => DapVersion::Unknown,
         }
     }
@@ -151,6 +164,8 @@ impl AsRef<str> for DapVersion {
         match self {
             DapVersion::Draft02 => "v02",
             DapVersion::Draft04 => "v04",
+            DapVersion::Draft05 => "v05",
+            DapVersion::Draft06 => "v06",
             _ => panic!("tried to construct string from unknown DAP version"),
         }
     }
@@ -176,6 +191,23 @@ pub struct DapGlobalConfig {
     /// current time plus this value will be rejected.
     pub report_storage_max_future_time_skew: Duration,
 
+    /// If set, reports with timestamps up to this many seconds beyond
+    /// `report_storage_max_future_time_skew` are not rejected with "reportTooEarly". Instead, for
+    /// the time bounds check, their timestamps are clamped to the current time plus
+    /// `report_storage_max_future_time_skew`. See [`Self::greatest_valid_report_time`].
+    /// Such reports are counted with the "report_too_early" label of the report counter so that
+    /// Clients with skewed clocks can be detected.
+    #[serde(default)]
+    pub report_time_skew_tolerance: Option<Duration>,
+
+    /// If set, the replay-protection state for a report (i.e., its ID) is only retained until the
+    /// end of the report's replay window: this many seconds after the report's timestamp, rounded
+    /// up to the task's time precision, or the task's expiration, whichever comes first. After
+    /// that, the state is eligible for purging and the report is rejected with "reportDropped" so
+    /// that it cannot be replayed. See [`Self::replay_window_end`].
+    #[serde(default)]
+    pub report_replay_window: Option<Duration>,
+
     /// Maximum interval duration permitted in CollectReq.
     /// Prevents Collectors from requesting wide range or reports.
     pub max_batch_duration: Duration,
@@ -194,18 +226,223 @@ pub struct DapGlobalConfig {
     // TODO(cjpatton) Rename this and clarify semantics.
     pub max_batch_interval_end: Duration,
 
+    /// If set, a batch interval requested by the Collector must end no later than the current
+    /// time plus this value. Collect requests for batches that extend further into the future are
+    /// rejected with "batchInvalid", since such collection jobs cannot complete.
+    #[serde(default)]
+    pub batch_interval_future_tolerance: Option<Duration>,
+
+    /// Leader: Maximum time (in seconds) to wait for a connection to the Helper to be established.
+    /// This is only honored by HTTP backends that manage their own connections, i.e., not in
+    /// WebAssembly, where requests are sent with the Fetch API.
+    #[serde(default)]
+    pub helper_connect_timeout: Option<Duration>,
+
+    /// Leader: Maximum time (in seconds) to wait for a request to the Helper to complete, including
+    /// reading the response. Once it elapses, the request is abandoned and treated as a transient
+    /// error (i.e., [`DapAbort::RetryLater`]).
+    #[serde(default)]
+    pub helper_request_timeout: Option<Duration>,
+
+    /// Leader: If set, then an aggregation job whose initialization request fails with a
+    /// transient error (i.e., [`DapAbort::RetryLater`]) is retried with exponential backoff. If
+    /// not set, or once the attempts are exhausted, the job is abandoned and its reports are
+    /// returned to storage to be aggregated in a fresh job.
+    #[serde(default)]
+    pub agg_job_init_retry: Option<DapRetryConfig>,
+
+    /// Leader: If set, then an aggregation job whose continuation request fails with a transient
+    /// error is retried with exponential backoff until the deadline, counted from the start of the
+    /// job, has passed. If not set, or once the deadline has passed, the job is abandoned and its
+    /// reports are returned to storage to be aggregated in a fresh job.
+    #[serde(default)]
+    pub agg_job_continue_retry: Option<DapDeadlineRetryConfig>,
+
+    /// Leader: If set, then the reports of an abandoned aggregation job are only returned to
+    /// storage a limited number of times. Otherwise they are returned to storage each time, until
+    /// they are too old to be aggregated.
+    #[serde(default)]
+    pub agg_job_abandon: Option<DapAggJobAbandonConfig>,
+
+    /// Leader: If set, then the Leader stops sending aggregation jobs to a Helper that keeps
+    /// rejecting them as unauthorized, e.g., because the peers are configured with different
+    /// bearer tokens. While the breaker is open, the aggregation jobs for the Helper are deferred.
+    #[serde(default)]
+    pub helper_circuit_breaker: Option<DapCircuitBreakerConfig>,
+
+    /// Leader: If set, then the metadata of a fraction of the uploaded reports is recorded with
+    /// [`DapLeader::put_report_sample`](crate::roles::DapLeader::put_report_sample), so that
+    /// operators can detect Clients that are misconfigured across the fleet.
+    #[serde(default)]
+    pub report_sampling: Option<DapReportSamplingConfig>,
+
+    /// Leader: If set, then reports whose timestamp is not a multiple of the task's
+    /// `time_precision` are rejected, as Clients are expected to truncate their timestamps. (Either
+    /// way, the timestamps of the output shares are truncated before they are aggregated; see
+    /// [`DapTaskConfig::batch_span_for_out_shares`].)
+    #[serde(default)]
+    pub reject_untruncated_report_time: bool,
+
+    /// Leader: Maximum number of collection jobs that are run concurrently while processing the
+    /// collection job queue. Jobs that select overlapping batches of the same task are never run
+    /// concurrently. If not set, jobs are run one at a time.
+    #[serde(default)]
+    pub max_concurrent_collect_jobs: Option<u64>,
+
+    /// Leader: Maximum number of collection jobs of the same task that are run concurrently. The
+    /// slots of [`Self::max_concurrent_collect_jobs`] are handed out to the tasks in turn, so that a
+    /// task with a large backlog does not starve the others. If not set, then a task may use every
+    /// slot no other task asks for.
+    #[serde(default)]
+    pub max_concurrent_collect_jobs_per_task: Option<u64>,
+
+    /// Leader: If set, Clients may upload batches of up to this many reports in one request (see
+    /// [`DapLeader::http_post_upload_batch`](crate::roles::DapLeader::http_post_upload_batch)).
+    /// If not set, batched uploads are rejected.
+    #[serde(default)]
+    pub max_upload_batch_len: Option<u64>,
+
+    /// Helper: If set, then the number of aggregation jobs that are running at once is limited.
+    /// An aggregation job is running from the time it is initialized until it is continued.
+    #[serde(default)]
+    pub helper_agg_job_limit: Option<DapHelperAggJobLimit>,
+
+    /// Helper: If set, then the aggregation-flow state stored between the initialization and
+    /// continuation of an aggregation job is subject to the given lifetime and size limits.
+    #[serde(default)]
+    pub helper_state_store: Option<DapHelperStateStoreConfig>,
+
+    /// Helper: Maximum number of reports of an aggregation job that are prepared concurrently.
+    /// This allows the decryption of input shares to overlap, e.g., when the HPKE receiver configs
+    /// are read from remote storage, and the reports to be prepared in parallel on this many
+    /// threads. (In WebAssembly, reports are always prepared on the current thread.) If not set,
+    /// reports are prepared one at a time.
+    #[serde(default)]
+    pub helper_prep_concurrency: Option<u64>,
+
+    /// Helper: If set, then the aggregate shares of each aligned run of this many buckets of a
+    /// time-interval task are compacted into a single object once the buckets are too old for
+    /// reports to be aggregated into them (see `report_storage_epoch_duration`). This reduces the
+    /// number of storage reads needed to handle an aggregate share request. See
+    /// [`storage::get_compacted_agg_share`](crate::storage::get_compacted_agg_share).
+    #[serde(default)]
+    pub helper_agg_share_span_buckets: Option<u64>,
+
+    /// If set, requests whose body exceeds the size limit for their endpoint are rejected before
+    /// the body is decoded.
+    #[serde(default)]
+    pub max_request_size: Option<DapRequestSizeLimits>,
+
     /// HPKE KEM types that are supported. Used when generating HPKE
     /// receiver config.
     pub supported_hpke_kems: Vec<HpkeKemId>,
 
+    /// If set, HPKE receiver configs are rotated periodically by
+    /// [`DapAggregator::rotate_hpke_config`](crate::roles::DapAggregator::rotate_hpke_config).
+    #[serde(default)]
+    pub hpke_rotation: Option<HpkeRotationConfig>,
+
     /// Is the taskprov extension allowed?
     pub allow_taskprov: bool,
 
     /// Which taskprov draft should be used?
     pub taskprov_version: TaskprovVersion,
+
+    /// Parameters that are set for a specific DAP version, e.g., so that draft02 and draft04 tasks
+    /// served by the same deployment can be tuned independently. See [`Self::for_version`].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub versions: HashMap<DapVersion, DapVersionConfig>,
+}
+
+/// Parameters of the [`DapGlobalConfig`] that may be set for a specific DAP version. Parameters
+/// that are not set keep the value configured for all versions.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DapVersionConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_duration: Option<Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_batch_interval_start: Option<Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_interval_end: Option<Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_interval_future_tolerance: Option<Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_upload_batch_len: Option<u64>,
 }
 
 impl DapGlobalConfig {
+    /// Return the configuration that applies to the given DAP version, i.e., with the parameters
+    /// set for the version in [`Self::versions`] overwriting those set for all versions.
+    pub fn for_version(&self, version: DapVersion) -> Cow<'_, Self> {
+        let version_config = match self.versions.get(&version) {
+            Some(version_config) => version_config,
+            None => return Cow::Borrowed(self),
+        };
+
+        let mut global_config = self.clone();
+        if let Some(max_batch_duration) = version_config.max_batch_duration {
+            global_config.max_batch_duration = max_batch_duration;
+        }
+        if let Some(min_batch_interval_start) = version_config.min_batch_interval_start {
+            global_config.min_batch_interval_start = min_batch_interval_start;
+        }
+        if let Some(max_batch_interval_end) = version_config.max_batch_interval_end {
+            global_config.max_batch_interval_end = max_batch_interval_end;
+        }
+        if let Some(tolerance) = version_config.batch_interval_future_tolerance {
+            global_config.batch_interval_future_tolerance = Some(tolerance);
+        }
+        if let Some(max_upload_batch_len) = version_config.max_upload_batch_len {
+            global_config.max_upload_batch_len = Some(max_upload_batch_len);
+        }
+        Cow::Owned(global_config)
+    }
+
+    /// Return the time at which the replay window of a report of the given task with the given
+    /// timestamp ends, or `None` if no replay window is configured.
+    pub fn replay_window_end(
+        &self,
+        task_config: &DapTaskConfig,
+        report_time: Time,
+    ) -> Option<Time> {
+        self.report_replay_window.map(|window| {
+            min(
+                task_config.quantized_time_upper_bound(report_time.saturating_add(window)),
+                task_config.expiration,
+            )
+        })
+    }
+
+    /// The greatest timestamp of a report that is accepted at time `now`: the current time plus
+    /// `report_storage_max_future_time_skew` and, if set, `report_time_skew_tolerance`.
+    pub fn greatest_valid_report_time(&self, now: Time) -> Time {
+        now.saturating_add(self.report_storage_max_future_time_skew)
+            .saturating_add(self.report_time_skew_tolerance.unwrap_or(0))
+    }
+
+    /// Check whether a report with the given timestamp is ahead of time `now` by more than
+    /// `report_storage_max_future_time_skew`, but is accepted due to `report_time_skew_tolerance`.
+    pub fn is_report_time_skewed(&self, report_time: Time, now: Time) -> bool {
+        report_time > now.saturating_add(self.report_storage_max_future_time_skew)
+            && report_time <= self.greatest_valid_report_time(now)
+    }
+
+    /// Check whether the replay window of a report of the given task with the given timestamp is
+    /// still open at time `now`. Reports whose window has ended must be rejected.
+    pub fn is_replay_window_open(
+        &self,
+        task_config: &DapTaskConfig,
+        report_time: Time,
+        now: Time,
+    ) -> bool {
+        self.replay_window_end(task_config, report_time)
+            .is_none_or(|end| now < end)
+    }
+
     /// Generate a list of HPKE receiver configurations, one for each element of supported KEM
     /// algorithm. `first_config_id` is used as the first config ID; subsequent IDs are chosen by
     /// incrementing `first_config_id`.
@@ -222,9 +459,118 @@ impl DapGlobalConfig {
     }
 }
 
+/// Parameters for retrying a request with exponential backoff.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapRetryConfig {
+    /// Maximum number of times the request is sent, including the first attempt.
+    pub max_attempts: u32,
+
+    /// Time (in milliseconds) to wait before the first retry.
+    pub initial_delay_ms: u64,
+
+    /// Upper bound on the time (in milliseconds) to wait between attempts. The delay doubles after
+    /// each attempt until it reaches this value.
+    pub max_delay_ms: u64,
+}
+
+/// Parameters for retrying a request with exponential backoff until a deadline.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapDeadlineRetryConfig {
+    /// Time (in milliseconds) after which the request is no longer retried. No attempt is made if
+    /// the deadline would pass while waiting for it.
+    pub deadline_ms: u64,
+
+    /// Time (in milliseconds) to wait before the first retry.
+    pub initial_delay_ms: u64,
+
+    /// Upper bound on the time (in milliseconds) to wait between attempts. The delay doubles after
+    /// each attempt until it reaches this value.
+    pub max_delay_ms: u64,
+}
+
+/// Policy for the reports of the aggregation jobs abandoned by the Leader, e.g., because the Helper
+/// does not respond.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapAggJobAbandonConfig {
+    /// Maximum number of abandoned aggregation jobs a report may be part of. Once a report reaches
+    /// this number, it is rejected rather than returned to storage.
+    pub max_attempts: u32,
+}
+
+/// Parameters of the circuit breaker for the Helpers that reject the Leader's requests as
+/// unauthorized. See [`DapGlobalConfig::helper_circuit_breaker`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapCircuitBreakerConfig {
+    /// Number of consecutive aggregation jobs the Helper must reject as unauthorized for the
+    /// breaker to open.
+    pub failure_threshold: u32,
+
+    /// Time (in seconds) the breaker stays open. Once it closes, the next aggregation job is sent
+    /// to the Helper; if it is rejected as well, the breaker opens again right away.
+    pub open_for: Duration,
+}
+
+/// Sampling of uploaded reports for traffic analysis. See [`DapReportSample`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DapReportSamplingConfig {
+    /// Fraction of the uploaded reports that are sampled, between 0 and 1.
+    pub rate: f64,
+}
+
+/// Limits on the aggregation jobs run by the Helper.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapHelperAggJobLimit {
+    /// Maximum number of aggregation jobs that may be running at once.
+    pub max_running: u64,
+
+    /// Maximum time (in milliseconds) an AggregationJobInitReq waits for a running job to finish
+    /// once the limit is reached. If no job finishes in time, then the request is rejected with
+    /// [`DapAbort::RetryLater`]. If `0`, then the request is rejected immediately.
+    pub queue_timeout_ms: u64,
+
+    /// Time (in milliseconds) to wait between attempts to start a queued aggregation job.
+    pub queue_poll_interval_ms: u64,
+
+    /// If set, then a request that is rejected because the limit is reached is answered with
+    /// [`DapAbort::Overloaded`] instead, asking the Leader to defer its aggregation jobs for this
+    /// long (in seconds).
+    #[serde(default)]
+    pub retry_after: Option<Duration>,
+}
+
+/// Limits on the aggregation-flow state stored by the Helper.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapHelperStateStoreConfig {
+    /// Time (in seconds) for which the state of an aggregation job is retained. Once the state
+    /// expires, the aggregation job can no longer be continued and the Leader's request is
+    /// rejected with "unrecognizedAggregationJob".
+    pub ttl: Duration,
+
+    /// Maximum size (in bytes) of the encoded state of an aggregation job. An AggregationJobInitReq
+    /// whose state would exceed this size, if none of its reports were rejected, is rejected
+    /// before its reports are prepared.
+    pub max_encoded_len: u64,
+}
+
+/// Maximum size (in bytes) of the body of a request to each kind of endpoint. Endpoints whose
+/// limit is not set accept bodies of any size.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapRequestSizeLimits {
+    /// Leader: Limit on the Report (or batch of reports) uploaded by a Client.
+    #[serde(default)]
+    pub upload: Option<u64>,
+
+    /// Helper: Limit on the AggregationJobInitReq or AggregationJobContinueReq sent by the Leader.
+    #[serde(default)]
+    pub aggregate: Option<u64>,
+
+    /// Limit on the CollectReq sent by the Collector to the Leader and on the AggregateShareReq
+    /// sent by the Leader to the Helper.
+    #[serde(default)]
+    pub collect: Option<u64>,
+}
+
 /// DAP Query configuration.
-//
-// TODO(cjpatton) Once we implement maximum batch lifetime, put the parameter here.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DapQueryConfig {
@@ -234,10 +580,70 @@ pub enum DapQueryConfig {
 
     /// The "fixed-size" query type. The Leader partitions the reports into arbitary batches of
     /// roughly the same size.
-    FixedSize { max_batch_size: u64 },
+    FixedSize {
+        max_batch_size: u64,
+
+        /// Optional: What to do with batches that do not fill up. If not set, then a batch stays
+        /// open until it reaches the minimum batch size.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        batch_lifetime: Option<DapBatchLifetimeConfig>,
+    },
+}
+
+/// Lifetime of a fixed-size batch that does not reach the task's minimum batch size.
+///
+/// A batch is stale once `max_batch_age` seconds have passed since its oldest report. When
+/// assigning reports to batches, the Leader approximates the age of a batch by the time since it
+/// created the batch; it then stops assigning reports to the batch and applies the policy.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapBatchLifetimeConfig {
+    pub max_batch_age: Duration,
+    pub policy: DapStaleBatchPolicy,
+}
+
+/// What to do with a stale fixed-size batch.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DapStaleBatchPolicy {
+    /// Close the batch. If it has not reached the minimum batch size, then it is removed from the
+    /// Leader's batch queue: Its reports are rejected rather than padded, and they are never
+    /// collected.
+    Close,
+
+    /// Close the batch and let it be collected even though it has not reached the minimum batch
+    /// size. This trades privacy for timeliness, since the aggregate result may cover very few
+    /// reports.
+    ForceCollectable,
+}
+
+impl DapBatchLifetimeConfig {
+    /// Check whether a batch is stale at time `now`, given the time from which its age is
+    /// measured.
+    pub fn is_stale(&self, since: Time, now: Time) -> bool {
+        now.saturating_sub(since) >= self.max_batch_age
+    }
+
+    /// Check that the parameters are well-formed.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_batch_age == 0 {
+            return Err("batch lifetime: max batch age must be positive".into());
+        }
+        Ok(())
+    }
 }
 
 impl DapQueryConfig {
+    /// Return the lifetime policy for fixed-size batches, if any.
+    pub fn batch_lifetime(&self) -> Option<&DapBatchLifetimeConfig> {
+        match self {
+            Self::FixedSize {
+                batch_lifetime: Some(ref batch_lifetime),
+                ..
+            } => Some(batch_lifetime),
+            _ => None,
+        }
+    }
+
     pub(crate) fn is_valid_part_batch_sel(&self, part_batch_sel: &PartialBatchSelector) -> bool {
         matches!(
             (&self, part_batch_sel),
@@ -308,6 +714,15 @@ pub struct DapTaskConfig {
     /// The smallest batch permitted for this task.
     pub min_batch_size: u64,
 
+    /// The number of times each Collector may query a bucket of reports. If greater than 1, then
+    /// a batch may be collected again, and a time-interval batch may be subdivided into smaller
+    /// batches, as long as none of its buckets has been queried this many times.
+    #[serde(
+        default = "default_max_batch_query_count",
+        skip_serializing_if = "is_default_max_batch_query_count"
+    )]
+    pub max_batch_query_count: u64,
+
     /// The query configuration for this task.
     pub query: DapQueryConfig,
 
@@ -317,11 +732,171 @@ pub struct DapTaskConfig {
     /// VDAF verification key shared by the Aggregators. Used to aggregate reports.
     pub vdaf_verify_key: VdafVerifyKey,
 
+    /// Optional: Identifiers for the VDAF verification key, used to rotate the key without
+    /// creating a new task. If not set, then the key has ID 0 and no other key is accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vdaf_verify_key_rotation: Option<DapVdafVerifyKeyRotation>,
+
     /// The Collector's HPKE configuration for this task.
     pub collector_hpke_config: HpkeConfig,
+
+    /// Collectors permitted to collect this task in addition to the primary Collector. Each
+    /// Collector may collect each batch once.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_collectors: Vec<DapTaskCollector>,
+
+    /// The differential privacy mechanism each Aggregator applies to its aggregate share before
+    /// encrypting it to the Collector.
+    #[serde(default, skip_serializing_if = "DapDpConfig::is_none")]
+    pub dp: DapDpConfig,
+
+    /// draft04 taskprov: The task configuration from which the task was provisioned, encoded as
+    /// URL-safe base64. The Leader advertises it to the Helper in the "dap-taskprov" header of each
+    /// request. Not set for tasks that were not provisioned via draft04 taskprov.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taskprov_advertisement: Option<String>,
+
+    /// Optional: Bloom filter used to speed up the replay check of reports for this task. If not
+    /// set, then each report is checked against the exact replay-protection state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_filter: Option<DapReplayFilterConfig>,
+
+    /// Leader: Optional: The credential Clients must present in each upload request. If not set,
+    /// then the Leader accepts reports from any Client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_auth: Option<DapUploadAuth>,
+}
+
+fn default_max_batch_query_count() -> u64 {
+    1
+}
+
+fn is_default_max_batch_query_count(max_batch_query_count: &u64) -> bool {
+    *max_batch_query_count == default_max_batch_query_count()
+}
+
+/// Identifies the VDAF verification keys of a task. The Leader aggregates with the task's current
+/// key and signals its ID in each aggregation job it initializes; the Helper prepares the reports
+/// of the job with the key that has this ID.
+///
+/// To rotate the key, the operator first adds the new key to `other_keys` of the Helper's task,
+/// then makes it the current key of the Leader's task, and finally makes it the current key of the
+/// Helper's task.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DapVdafVerifyKeyRotation {
+    /// ID of the task's current key, i.e., of [`DapTaskConfig::vdaf_verify_key`].
+    pub id: u8,
+
+    /// Helper: Keys, by ID, with which the Helper also prepares reports.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub other_keys: HashMap<u8, VdafVerifyKey>,
+}
+
+/// Parameters of a Bloom filter that sits in front of the exact replay-protection state. A report
+/// that is not in the filter has not been processed, so only reports that are probably duplicates
+/// are checked against the exact state.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DapReplayFilterConfig {
+    /// Number of reports each filter is sized for. Each filter covers the reports of one unit of
+    /// replay-protection storage.
+    pub expected_report_count: u64,
+
+    /// Target rate of false positives, i.e., of reports that are checked against the exact state
+    /// even though they have not been processed. Must be in the range (0, 1).
+    pub false_positive_rate: f64,
+}
+
+impl DapReplayFilterConfig {
+    /// Check that the parameters are well-formed.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.expected_report_count == 0 {
+            return Err("replay filter: expected report count must be positive".into());
+        }
+        if !(self.false_positive_rate > 0.0 && self.false_positive_rate < 1.0) {
+            return Err(format!(
+                "replay filter: false positive rate ({}) must be in the range (0, 1)",
+                self.false_positive_rate
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A differential privacy mechanism. Each Aggregator adds independently sampled noise to each
+/// element of its aggregate share, so the noise in the aggregate result is the sum of the
+/// Aggregators' noise.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DapDpConfig {
+    /// No noise is added.
+    #[default]
+    None,
+
+    /// Discrete Laplace noise with the given scale.
+    DiscreteLaplace { scale: DapRational },
+
+    /// Discrete Gaussian noise with the given variance.
+    DiscreteGaussian { sigma_squared: DapRational },
+}
+
+/// A positive rational number, used to parameterize a [`DapDpConfig`].
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DapRational {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+/// A Collector of a task other than the task's primary Collector.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DapTaskCollector {
+    /// Identifies the Collector in requests. A request from this Collector carries this ID in the
+    /// "dap-collector-id" header.
+    pub id: String,
+
+    /// The Collector's HPKE configuration for this task.
+    pub hpke_config: HpkeConfig,
 }
 
 impl DapTaskConfig {
+    /// Return the HPKE configuration of the Collector with the given ID, or of the primary
+    /// Collector if `collector_id` is `None`. Returns `None` if the Collector is not recognized.
+    pub fn collector_hpke_config_for(&self, collector_id: Option<&str>) -> Option<&HpkeConfig> {
+        match collector_id {
+            None => Some(&self.collector_hpke_config),
+            Some(collector_id) => self
+                .additional_collectors
+                .iter()
+                .find(|collector| collector.id == collector_id)
+                .map(|collector| &collector.hpke_config),
+        }
+    }
+
+    /// Return the ID of the current VDAF verification key.
+    pub fn vdaf_verify_key_id(&self) -> u8 {
+        self.vdaf_verify_key_rotation
+            .as_ref()
+            .map_or(0, |rotation| rotation.id)
+    }
+
+    /// Return the task configuration with the VDAF verification key that has the given ID, or
+    /// with the current key if `id` is `None`.
+    pub fn with_vdaf_verify_key_id(&self, id: Option<u8>) -> Result<Cow<'_, Self>, DapAbort> {
+        let id = match id {
+            Some(id) if id != self.vdaf_verify_key_id() => id,
+            _ => return Ok(Cow::Borrowed(self)),
+        };
+        let vdaf_verify_key = self
+            .vdaf_verify_key_rotation
+            .as_ref()
+            .and_then(|rotation| rotation.other_keys.get(&id))
+            .ok_or_else(|| {
+                DapAbort::BadRequest(format!("unrecognized VDAF verify key ID ({id})"))
+            })?;
+        let mut task_config = self.clone();
+        task_config.vdaf_verify_key = vdaf_verify_key.clone();
+        Ok(Cow::Owned(task_config))
+    }
+
     /// Convert at timestamp `now` into an [`Interval`] that contains it. The timestamp is the
     /// numbre of seconds since the beginning of UNIX time.
     #[cfg(test)]
@@ -348,6 +923,12 @@ impl DapTaskConfig {
 
     /// Compute the "batch span" of a set of output shares and, for each buckent in the span,
     /// aggregate the output shares into an aggregate share.
+    ///
+    /// The timestamp of each output share is truncated to the task's time precision, so that the
+    /// aggregate shares do not reveal more about the time of a report than the Client meant to.
+    /// (The report itself is stored as uploaded, since its timestamp is bound to the ciphertexts.)
+    /// Fixed-size tasks with a batch lifetime are the exception, since the age of a batch is
+    /// measured from the time of its earliest report.
     pub fn batch_span_for_out_shares<'a>(
         &self,
         part_batch_sel: &'a PartialBatchSelector,
@@ -361,9 +942,13 @@ impl DapTaskConfig {
 
         let mut span: HashMap<DapBatchBucket<'a>, DapAggregateShare> = HashMap::new();
         for out_share in out_shares.into_iter() {
+            let time = match self.query.batch_lifetime() {
+                Some(..) => out_share.time,
+                None => self.quantized_time_lower_bound(out_share.time),
+            };
             let bucket = match part_batch_sel {
                 PartialBatchSelector::TimeInterval => DapBatchBucket::TimeInterval {
-                    batch_window: self.quantized_time_lower_bound(out_share.time),
+                    batch_window: self.quantized_time_lower_bound(time),
                 },
                 PartialBatchSelector::FixedSizeByBatchId { batch_id } => {
                     DapBatchBucket::FixedSize { batch_id }
@@ -373,8 +958,8 @@ impl DapTaskConfig {
             let agg_share = span.entry(bucket).or_default();
             agg_share.merge(DapAggregateShare {
                 report_count: 1,
-                min_time: out_share.time,
-                max_time: out_share.time,
+                min_time: time,
+                max_time: time,
                 checksum: out_share.checksum,
                 data: Some(out_share.data),
             })?;
@@ -443,14 +1028,21 @@ impl DapTaskConfig {
     }
 
     /// Check if the batch size is too small. Returns an error if the report count is too large.
+    /// A non-empty batch is never too small if it is `forced` (see
+    /// [`DapAggregator::mark_batch_forced`](crate::roles::DapAggregator::mark_batch_forced)), nor
+    /// if it is a stale fixed-size batch and the task's policy is
+    /// [`DapStaleBatchPolicy::ForceCollectable`].
     pub(crate) fn is_report_count_compatible(
         &self,
         task_id: &TaskId,
-        report_count: u64,
+        agg_share: &DapAggregateShare,
+        now: Time,
+        forced: bool,
     ) -> Result<bool, DapAbort> {
+        let report_count = agg_share.report_count;
         match self.query {
             DapQueryConfig::TimeInterval => (),
-            DapQueryConfig::FixedSize { max_batch_size } => {
+            DapQueryConfig::FixedSize { max_batch_size, .. } => {
                 if report_count > max_batch_size {
                     return Err(DapAbort::InvalidBatchSize {
                         detail: format!(
@@ -462,6 +1054,19 @@ impl DapTaskConfig {
             }
         };
 
+        if forced && report_count > 0 {
+            return Ok(true);
+        }
+
+        if let Some(batch_lifetime) = self.query.batch_lifetime() {
+            if batch_lifetime.policy == DapStaleBatchPolicy::ForceCollectable
+                && report_count > 0
+                && batch_lifetime.is_stale(agg_share.min_time, now)
+            {
+                return Ok(true);
+            }
+        }
+
         Ok(report_count >= self.min_batch_size)
     }
 }
@@ -473,21 +1078,45 @@ impl AsRef<DapTaskConfig> for DapTaskConfig {
 }
 
 /// A measurement from which a Client generates a report.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DapMeasurement {
     U64(u64),
     U32Vec(Vec<u32>),
+    U64Vec(Vec<u64>),
+    F64Vec(Vec<f64>),
+
+    /// A boolean measurement for [`Prio3Config::Count`].
+    Bool(bool),
+
+    /// The index of the bucket into which the measurement falls, for [`Prio3Config::Histogram`].
+    /// A histogram with `n` bucket boundaries has `n + 1` buckets.
+    Category(u32),
+}
+
+impl DapMeasurement {
+    /// The name of the variant, e.g., "U64".
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Self::U64(..) => "U64",
+            Self::U32Vec(..) => "U32Vec",
+            Self::U64Vec(..) => "U64Vec",
+            Self::F64Vec(..) => "F64Vec",
+            Self::Bool(..) => "Bool",
+            Self::Category(..) => "Category",
+        }
+    }
 }
 
 /// The aggregate result computed by the Collector.
-#[derive(Debug, PartialEq, Eq, Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DapAggregateResult {
     U32Vec(Vec<u32>),
     U64(u64),
     U128(u128),
     U128Vec(Vec<u128>),
+    F64Vec(Vec<f64>),
 }
 
 /// The Leader's state after sending an AggregateInitReq.
@@ -525,9 +1154,11 @@ impl DapHelperState {
                 (VdafConfig::Prio3(prio3_config), _) => {
                     prio3_append_prepare_state(&mut bytes, prio3_config, state)?;
                 }
+                #[cfg(feature = "prio2")]
                 (VdafConfig::Prio2 { .. }, VdafState::Prio2(state)) => {
                     state.encode(&mut bytes);
                 }
+                #[cfg(feature = "prio2")]
                 _ => return Err(DapError::fatal("VDAF config and prep state mismatch")),
             }
             time.encode(&mut bytes);
@@ -536,6 +1167,20 @@ impl DapHelperState {
         Ok(bytes)
     }
 
+    /// Return the length of the encoding (see [`Self::get_encoded`]) of the state of an
+    /// aggregation job with the given number of reports. The prepare state of each report has the
+    /// same length, so this is known before the reports are prepared.
+    pub(crate) fn encoded_len(
+        vdaf_config: &VdafConfig,
+        part_batch_sel: &PartialBatchSelector,
+        report_count: usize,
+    ) -> usize {
+        let report_len = vdaf_config.helper_prepare_state_len()
+            + std::mem::size_of::<Time>()
+            + std::mem::size_of::<ReportId>();
+        part_batch_sel.get_encoded().len() + report_count * report_len
+    }
+
     /// Decode the Helper state from a byte string.
     pub fn get_decoded(vdaf_config: &VdafConfig, data: &[u8]) -> Result<Self, DapError> {
         let mut r = std::io::Cursor::new(data);
@@ -546,6 +1191,7 @@ impl DapHelperState {
                 VdafConfig::Prio3(ref prio3_config) => {
                     prio3_decode_prepare_state(prio3_config, 1, &mut r)?
                 }
+                #[cfg(feature = "prio2")]
                 VdafConfig::Prio2 { dimension } => {
                     prio2_decode_prepare_state(*dimension, 1, &mut r)?
                 }
@@ -637,6 +1283,11 @@ impl DapAggregateShare {
         self.report_count == 0
     }
 
+    /// Return the number of reports aggregated into the aggregate share.
+    pub fn report_count(&self) -> u64 {
+        self.report_count
+    }
+
     /// Set the aggregate share to zero.
     pub fn reset(&mut self) {
         self.report_count = 0;
@@ -674,6 +1325,11 @@ pub enum DapLeaderTransition<M: Debug> {
     /// before committing them.
     Uncommitted(DapLeaderUncommitted, M),
 
+    /// The Leader has computed its output shares and the aggregation flow is complete. In draft06,
+    /// this happens as soon as the Leader handles the Helper's response to the
+    /// AggregationJobInitReq.
+    Finish(Vec<DapOutputShare>),
+
     /// The Leader has completed the aggregation flow without computing an aggregate share.
     Skip,
 }
@@ -697,7 +1353,12 @@ pub enum DapHelperTransition<M: Debug> {
 #[serde(rename_all = "snake_case")]
 pub enum VdafConfig {
     Prio3(Prio3Config),
-    Prio2 { dimension: usize },
+    /// Prio2, as used by ENPA. This is not a standard VDAF and is only supported for
+    /// interoperability with legacy Clients.
+    #[cfg(feature = "prio2")]
+    Prio2 {
+        dimension: usize,
+    },
 }
 
 impl std::str::FromStr for VdafConfig {
@@ -723,10 +1384,42 @@ pub enum Prio3Config {
     /// The sum of 64-bit, unsigned integers. Each measurement is an integer in range `[0,
     /// 2^bits)`.
     Sum { bits: usize },
+
+    /// A vector of sums of 64-bit, unsigned integers. Each measurement is a vector of `len`
+    /// integers, each in range `[0, 2^bits)`. The aggregate is the element-wise sum of the
+    /// measurements.
+    SumVec { bits: usize, len: usize },
+
+    /// A vector of sums of fixed-point numbers, e.g., for aggregating gradients in federated
+    /// learning. Each measurement is a vector of `len` numbers in range `[-1, 1)` whose L2 norm is
+    /// at most `1`. The aggregate is the element-wise sum of the measurements.
+    FixedPointBoundedL2VecSum {
+        bitsize: Prio3FixedPointBitSize,
+        len: usize,
+    },
+}
+
+/// The number of bits of each entry of a [`Prio3Config::FixedPointBoundedL2VecSum`] measurement.
+/// Each entry is a signed fixed-point number with a single integer bit.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Prio3FixedPointBitSize {
+    Fixed16,
+    Fixed32,
+}
+
+impl Prio3FixedPointBitSize {
+    /// The number of bits of each entry.
+    pub fn bits(&self) -> u8 {
+        match self {
+            Self::Fixed16 => 16,
+            Self::Fixed32 => 32,
+        }
+    }
 }
 
 /// DAP sender role.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum DapSender {
     Client,
     Collector,
@@ -783,6 +1476,30 @@ pub struct DapRequest<S> {
 
     /// Sender authorization, e.g., a bearer token.
     pub sender_auth: Option<S>,
+
+    /// Protocol version the sender signaled for itself, if any. The Leader signals the version of
+    /// the task in each request to the Helper so that the Helper can detect when the peers are
+    /// configured for different drafts.
+    pub sender_version: Option<DapVersion>,
+
+    /// ID of the Collector that sent the request, or on whose behalf the Leader sent it, if the
+    /// Collector is not the task's primary Collector. See [`DapTaskConfig::additional_collectors`].
+    pub collector_id: Option<String>,
+
+    /// draft04 taskprov: The task configuration advertised by the sender in the "dap-taskprov"
+    /// header, if any. See [`DapTaskConfig::taskprov_advertisement`].
+    pub taskprov: Option<String>,
+
+    /// ID of the VDAF verification key with which the Leader aggregates, if signaled. See
+    /// [`DapTaskConfig::vdaf_verify_key_rotation`].
+    pub vdaf_verify_key_id: Option<u8>,
+
+    /// Client authorization for an upload request, if any. See [`DapTaskConfig::upload_auth`].
+    pub client_auth: Option<DapClientAuth>,
+
+    /// Idempotency key of an upload request, if any. A Client that retries an upload sends the
+    /// same key and payload with each attempt, so that the Leader can tell a retry from a replay.
+    pub idempotency_key: Option<String>,
 }
 
 impl<S> DapRequest<S> {
@@ -842,6 +1559,279 @@ pub enum DapCollectJob {
     Unknown,
 }
 
+/// Outcome of uploading one of the reports of a [`ReportBatch`](crate::messages::ReportBatch).
+/// This is not defined by the DAP standard.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DapReportUploadStatus {
+    /// The report ID, encoded in base64url. This is `None` if the report could not be decoded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report_id: Option<String>,
+
+    /// The reason the report was rejected. This is `None` if the report was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<ProblemDetails>,
+}
+
+/// Record of an upload request handled under an idempotency key (see
+/// [`DapRequest::idempotency_key`]). A retry of the request is answered from the record. This is
+/// not defined by the DAP standard.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DapUploadRecord {
+    /// SHA-256 digest of the request payload.
+    #[serde(with = "hex")]
+    pub digest: [u8; 32],
+
+    /// Outcome of uploading each report of the request, in the order in which the reports appear
+    /// in the request. The outcome is `None` if the report failed for a transient reason (e.g., a
+    /// storage error) or if its outcome is not known yet, in which case a retry of the request
+    /// uploads the report again.
+    pub statuses: Vec<Option<DapReportUploadStatus>>,
+}
+
+impl DapUploadRecord {
+    /// Fill in the outcomes missing from this record with those of another record of the same
+    /// request. Nothing is changed if the records are for different requests.
+    pub fn merge(&mut self, other: &Self) {
+        if self.digest != other.digest {
+            return;
+        }
+        for (status, other_status) in self.statuses.iter_mut().zip(other.statuses.iter()) {
+            if status.is_none() {
+                *status = other_status.clone();
+            }
+        }
+    }
+}
+
+/// Outcome of a dry run of the aggregation of one report (see
+/// [`DapAggregator::dry_run_aggregation`](crate::roles::DapAggregator::dry_run_aggregation)).
+/// This is not defined by the DAP standard.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapDryRunReportStatus {
+    /// The report ID, encoded in base64url. This is `None` if the report could not be decoded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report_id: Option<String>,
+
+    /// The reason the report would be rejected. This is `None` if the report would be accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<TransitionFailure>,
+
+    /// Optional: More information about the rejection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// A report that was rejected during aggregation because the report itself is invalid, e.g., it
+/// could not be decrypted or its proof did not verify, as opposed to the state of the task, e.g.,
+/// the report was replayed. Rejected reports are captured by
+/// [`DaphneMetrics::with_dead_letters`](crate::metrics::DaphneMetrics::with_dead_letters) so that
+/// operators can debug systematic Client bugs. This is not defined by the DAP standard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DapDeadLetter {
+    pub report_id: ReportId,
+    pub time: Time,
+    pub failure: TransitionFailure,
+}
+
+impl DapDeadLetter {
+    /// Whether the given failure indicates that the report itself is invalid.
+    pub fn is_dead_letter(failure: TransitionFailure) -> bool {
+        matches!(
+            failure,
+            TransitionFailure::HpkeUnknownConfigId
+                | TransitionFailure::HpkeDecryptError
+                | TransitionFailure::VdafPrepError
+                | TransitionFailure::UnrecognizedMessage
+        )
+    }
+}
+
+/// A batch that may be collected now, suggested to the Collector (see
+/// [`DapLeader::http_get_batch_suggestions`](crate::roles::DapLeader::http_get_batch_suggestions)).
+/// This is not defined by the DAP standard.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DapBatchSuggestion {
+    TimeInterval {
+        batch_interval: Interval,
+        report_count: u64,
+    },
+    FixedSize {
+        /// The batch ID, encoded in base64url.
+        batch_id: String,
+        report_count: u64,
+    },
+}
+
+/// The batches that may be collected now. This is not defined by the DAP standard.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DapBatchSuggestions {
+    pub batches: Vec<DapBatchSuggestion>,
+}
+
+/// Metadata of an uploaded report, sampled by the Leader for traffic analysis (see
+/// [`DapGlobalConfig::report_sampling`]). Reports are sampled whether or not they are accepted.
+/// This is not defined by the DAP standard.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DapReportSample {
+    pub report_id: ReportId,
+
+    /// Difference between the report's timestamp and the time it was uploaded, in seconds. This is
+    /// negative for a report timestamped in the past.
+    pub time_skew: i64,
+
+    /// Types of the extensions in the report metadata. In draft04 and later, extensions are carried
+    /// in the encrypted input shares and hence are not sampled.
+    pub extensions: Vec<u16>,
+
+    /// Whether the upload request carried the "dap-taskprov" header.
+    pub taskprov_header: bool,
+
+    /// IDs of the HPKE configs to which the input shares are encrypted, Leader first.
+    pub hpke_config_ids: Vec<u8>,
+
+    /// The type of the problem with which the report was rejected (e.g., "reportRejected"), or
+    /// "other" for a problem with no type. Not set if the report was accepted.
+    pub rejected: Option<String>,
+}
+
+/// Progress of a collect job, reported to the Collector so that it can be displayed while the job
+/// is pending. This is not defined by the DAP standard.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CollectionJobStatus {
+    /// Whether the collect job has completed.
+    pub done: bool,
+
+    /// Number of reports aggregated so far in the batch.
+    pub report_count: u64,
+
+    /// Report count as a percentage of the task's minimum batch size, capped at 100.
+    pub batch_fill_percent: u8,
+
+    /// Estimated time at which the batch will have reached the minimum batch size. This is
+    /// `None` if the job has completed or if the time can't be estimated, e.g., if no reports
+    /// have been aggregated yet or the batch interval is over.
+    pub estimated_ready_time: Option<Time>,
+}
+
+impl CollectionJobStatus {
+    /// Status of a completed collect job.
+    pub fn done(collection: &Collection) -> Self {
+        Self {
+            done: true,
+            report_count: collection.report_count,
+            batch_fill_percent: 100,
+            estimated_ready_time: None,
+        }
+    }
+
+    /// Status of a pending collect job for the given batch. For time-interval batches, the time
+    /// at which the batch fills up is extrapolated from the rate at which reports have been
+    /// aggregated since the start of the batch interval.
+    pub fn pending(
+        task_config: &DapTaskConfig,
+        batch_sel: &BatchSelector,
+        report_count: u64,
+        now: Time,
+    ) -> Self {
+        let min_batch_size = task_config.min_batch_size.max(1);
+        let estimated_ready_time = if report_count >= min_batch_size {
+            Some(now)
+        } else {
+            match batch_sel {
+                BatchSelector::TimeInterval { batch_interval }
+                    if report_count > 0 && now < batch_interval.end() =>
+                {
+                    let elapsed = now.saturating_sub(batch_interval.start);
+                    let remaining =
+                        (min_batch_size - report_count).saturating_mul(elapsed) / report_count;
+                    Some(now.saturating_add(remaining))
+                        .filter(|ready_time| *ready_time <= batch_interval.end())
+                }
+                _ => None,
+            }
+        };
+
+        Self {
+            done: false,
+            report_count,
+            batch_fill_percent: u8::try_from(report_count.saturating_mul(100) / min_batch_size)
+                .unwrap_or(100)
+                .min(100),
+            estimated_ready_time,
+        }
+    }
+}
+
+/// Report count of a bucket of reports in a batch.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapBucketReportCount {
+    /// Start of the bucket's batch window. This is `None` for fixed-size tasks, as each batch
+    /// consists of a single bucket.
+    pub batch_window: Option<Time>,
+    pub report_count: u64,
+}
+
+/// The Leader's breakdown of the report count of a collection into the report count of each
+/// bucket in the batch. The Collector can use this to check that the report count of the
+/// collection is equal to the sum of the buckets' contributions. The breakdown is not defined by
+/// the DAP standard and is sent alongside the collection.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapReportCountBreakdown {
+    pub buckets: Vec<DapBucketReportCount>,
+
+    /// SHA-256 digest of the task ID, the partial batch selector, and the buckets.
+    #[serde(with = "hex")]
+    pub digest: Vec<u8>,
+}
+
+const CTX_REPORT_COUNT_BREAKDOWN: &[u8] = b"daphne report count breakdown";
+
+impl DapReportCountBreakdown {
+    /// Construct the breakdown of the batch with the given partial batch selector.
+    pub fn new(
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        buckets: Vec<DapBucketReportCount>,
+    ) -> Self {
+        let digest = Self::compute_digest(task_id, part_batch_sel, &buckets);
+        Self { buckets, digest }
+    }
+
+    /// Sum of the report counts of the buckets.
+    pub fn report_count(&self) -> u64 {
+        self.buckets.iter().map(|bucket| bucket.report_count).sum()
+    }
+
+    pub(crate) fn compute_digest(
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        buckets: &[DapBucketReportCount],
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(CTX_REPORT_COUNT_BREAKDOWN);
+        task_id.encode(&mut data);
+        part_batch_sel.encode(&mut data);
+        for bucket in buckets {
+            match bucket.batch_window {
+                Some(batch_window) => {
+                    1_u8.encode(&mut data);
+                    batch_window.encode(&mut data);
+                }
+                None => 0_u8.encode(&mut data),
+            }
+            bucket.report_count.encode(&mut data);
+        }
+        ring::digest::digest(&ring::digest::SHA256, &data)
+            .as_ref()
+            .to_vec()
+    }
+}
+
+/// A pending collect job: the task ID, collect ID, collect request, and the ID of the Collector
+/// that issued the request (`None` for the task's primary Collector).
+pub type DapPendingCollectJob = (TaskId, CollectionJobId, CollectionReq, Option<String>);
+
 /// Telemetry information for the leader's processing loop.
 //
 // TODO This is used for tests. Perhaps Prometheus metrics would be sufficient?
@@ -855,6 +1845,11 @@ pub struct DapLeaderProcessTelemetry {
 
     /// The number of reports processed.
     pub reports_processed: u64,
+
+    /// Position at which the next run should resume fetching reports, if supported by the
+    /// Leader's report selector. If not set, then the next run starts from the beginning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
 }
 
 /// draft02 compatibility: A logical aggregation job ID. In the latest draft, this is a 32-byte
@@ -873,7 +1868,9 @@ impl MetaAggregationJobId<'_> {
         let mut rng = thread_rng();
         match version {
             DapVersion::Draft02 => Self::Draft02(Cow::Owned(Draft02AggregationJobId(rng.gen()))),
-            DapVersion::Draft04 => Self::Draft04(Cow::Owned(AggregationJobId(rng.gen()))),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                Self::Draft04(Cow::Owned(AggregationJobId(rng.gen())))
+            }
             DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
         }
     }
@@ -917,18 +1914,48 @@ impl MetaAggregationJobId<'_> {
 }
 
 pub mod aborts;
+#[cfg(test)]
+mod aborts_test;
 pub mod auth;
+pub mod client;
+#[cfg(test)]
+mod client_test;
+pub mod clock;
+#[cfg(test)]
+mod clock_test;
+pub mod collector;
+#[cfg(test)]
+mod collector_test;
 pub mod constants;
 #[cfg(test)]
 mod constants_test;
+pub mod events;
+pub mod extensions;
 pub mod hpke;
 #[cfg(test)]
 mod hpke_test;
+pub mod manifest;
+#[cfg(test)]
+mod manifest_test;
 pub mod messages;
 pub mod metrics;
+#[cfg(test)]
+mod metrics_test;
+pub mod migration;
+#[cfg(test)]
+mod migration_test;
+pub mod receipt;
+#[cfg(test)]
+mod receipt_test;
 pub mod roles;
 #[cfg(test)]
 mod roles_test;
+pub mod scheduler;
+#[cfg(test)]
+mod scheduler_test;
+pub mod storage;
+#[cfg(test)]
+mod storage_test;
 pub mod taskprov;
 #[cfg(test)]
 mod taskprov_test;
@@ -0,0 +1,228 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Declarative task manifests.
+//!
+//! A manifest is a JSON document that lists the tasks an Aggregator is meant to be configured
+//! with, for example a file kept under version control. It is parsed and validated by
+//! [`DapTaskConfig::from_manifest`] and compared with the installed tasks by
+//! [`diff_task_manifest`]. Installing a manifest only creates and overwrites tasks; tasks that are
+//! installed but not listed are reported, but never removed.
+
+use crate::{messages::TaskId, DapError, DapQueryConfig, DapTaskConfig, DapVersion};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+
+/// A list of tasks to install.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DapTaskManifest {
+    pub tasks: Vec<DapTaskManifestEntry>,
+}
+
+/// A task listed in a [`DapTaskManifest`].
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DapTaskManifestEntry {
+    /// The task ID, encoded as URL-safe base64.
+    #[serde(
+        serialize_with = "serialize_task_id",
+        deserialize_with = "deserialize_task_id"
+    )]
+    pub task_id: TaskId,
+
+    pub config: DapTaskConfig,
+
+    /// Optional: The bearer token with which the Leader authenticates to the Helper. Required when
+    /// the task is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leader_authentication_token: Option<String>,
+
+    /// Optional: The bearer token with which the Collector authenticates to the Leader. Required
+    /// when the task is created by the Leader.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collector_authentication_token: Option<String>,
+}
+
+fn serialize_task_id<S: Serializer>(task_id: &TaskId, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&task_id.to_base64url())
+}
+
+fn deserialize_task_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TaskId, D::Error> {
+    let task_id = String::deserialize(deserializer)?;
+    TaskId::try_from_base64url(task_id)
+        .ok_or_else(|| serde::de::Error::custom("task ID is not valid URL-safe base64"))
+}
+
+impl DapTaskConfig {
+    /// Parse and validate a [`DapTaskManifest`]. Each task is checked as it would be when added
+    /// individually; the manifest is rejected as a whole if any task is invalid or if a task ID is
+    /// listed more than once.
+    pub fn from_manifest(manifest: &[u8]) -> Result<Vec<DapTaskManifestEntry>, DapError> {
+        let manifest: DapTaskManifest = serde_json::from_slice(manifest)
+            .map_err(|e| DapError::Fatal(format!("malformed task manifest: {e}")))?;
+
+        let mut task_ids = HashSet::new();
+        for entry in manifest.tasks.iter() {
+            if !task_ids.insert(&entry.task_id) {
+                return Err(DapError::Fatal(format!(
+                    "task manifest lists task {} more than once",
+                    entry.task_id.to_base64url()
+                )));
+            }
+            entry.config.validate_for_manifest().map_err(|e| {
+                DapError::Fatal(format!("task {}: {e}", entry.task_id.to_base64url()))
+            })?;
+        }
+
+        Ok(manifest.tasks)
+    }
+
+    fn validate_for_manifest(&self) -> Result<(), String> {
+        if self.version == DapVersion::Unknown {
+            return Err("unrecognized version".into());
+        }
+        self.vdaf.check_params().map_err(|e| e.to_string())?;
+        self.vdaf
+            .get_decoded_verify_key(self.vdaf_verify_key.as_ref())
+            .map_err(|_| "VDAF verify key does not match the VDAF".to_string())?;
+        if let Some(ref rotation) = self.vdaf_verify_key_rotation {
+            if rotation.other_keys.contains_key(&rotation.id) {
+                return Err(format!(
+                    "VDAF verify key ID {} is assigned to more than one key",
+                    rotation.id
+                ));
+            }
+            for (id, vdaf_verify_key) in rotation.other_keys.iter() {
+                self.vdaf
+                    .get_decoded_verify_key(vdaf_verify_key.as_ref())
+                    .map_err(|_| format!("VDAF verify key {id} does not match the VDAF"))?;
+            }
+        }
+        self.dp.validate()?;
+        if let Some(ref replay_filter) = self.replay_filter {
+            replay_filter.validate()?;
+        }
+        if let Some(ref upload_auth) = self.upload_auth {
+            upload_auth.validate()?;
+        }
+        self.collector_hpke_config
+            .check_suite()
+            .map_err(|e| e.to_string())?;
+        for collector in self.additional_collectors.iter() {
+            collector
+                .hpke_config
+                .check_suite()
+                .map_err(|e| format!("collector {}: {e}", collector.id))?;
+        }
+        if self.time_precision == 0 {
+            return Err("time precision must be positive".into());
+        }
+        if self.min_batch_size == 0 {
+            return Err("min batch size must be positive".into());
+        }
+        if self.max_batch_query_count == 0 {
+            return Err("max batch query count must be positive".into());
+        }
+        if let DapQueryConfig::FixedSize {
+            max_batch_size,
+            ref batch_lifetime,
+        } = self.query
+        {
+            if max_batch_size < self.min_batch_size {
+                return Err(format!(
+                    "max batch size ({max_batch_size}) is less than min batch size ({})",
+                    self.min_batch_size
+                ));
+            }
+            if let Some(batch_lifetime) = batch_lifetime {
+                batch_lifetime.validate()?;
+            }
+        }
+        if self.taskprov_advertisement.is_some() {
+            return Err("tasks provisioned by taskprov cannot be installed from a manifest".into());
+        }
+        Ok(())
+    }
+}
+
+/// How installing a manifest changes a task.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DapTaskManifestChange {
+    /// The task is not installed.
+    Create,
+
+    /// The task is installed with a different configuration.
+    Update,
+
+    /// The task is installed with the same configuration.
+    Unchanged,
+}
+
+/// The change to a task listed in a manifest.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapTaskManifestTaskDiff {
+    /// The task ID, encoded as URL-safe base64.
+    pub task_id: String,
+    pub change: DapTaskManifestChange,
+}
+
+/// The result of comparing a manifest with the installed tasks.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapTaskManifestDiff {
+    /// The tasks listed by the manifest, in order.
+    pub tasks: Vec<DapTaskManifestTaskDiff>,
+
+    /// IDs of the installed tasks that are not listed by the manifest, encoded as URL-safe base64.
+    pub unlisted: Vec<String>,
+
+    /// Whether the changes were applied.
+    pub applied: bool,
+}
+
+impl DapTaskManifestDiff {
+    /// Return `true` if installing the manifest would not change any task.
+    pub fn is_unchanged(&self) -> bool {
+        self.tasks
+            .iter()
+            .all(|task| task.change == DapTaskManifestChange::Unchanged)
+    }
+}
+
+/// Compare the tasks listed by a manifest with the installed tasks. Only the task configurations
+/// are compared; bearer tokens are not.
+pub fn diff_task_manifest(
+    entries: &[DapTaskManifestEntry],
+    installed: &HashMap<TaskId, DapTaskConfig>,
+) -> Result<DapTaskManifestDiff, DapError> {
+    let mut tasks = Vec::with_capacity(entries.len());
+    for entry in entries.iter() {
+        let change = match installed.get(&entry.task_id) {
+            None => DapTaskManifestChange::Create,
+            Some(task_config) => {
+                if serde_json::to_value(task_config)? == serde_json::to_value(&entry.config)? {
+                    DapTaskManifestChange::Unchanged
+                } else {
+                    DapTaskManifestChange::Update
+                }
+            }
+        };
+        tasks.push(DapTaskManifestTaskDiff {
+            task_id: entry.task_id.to_base64url(),
+            change,
+        });
+    }
+
+    let listed: HashSet<&TaskId> = entries.iter().map(|entry| &entry.task_id).collect();
+    let mut unlisted: Vec<String> = installed
+        .keys()
+        .filter(|task_id| !listed.contains(task_id))
+        .map(TaskId::to_base64url)
+        .collect();
+    unlisted.sort();
+
+    Ok(DapTaskManifestDiff {
+        tasks,
+        unlisted,
+        applied: false,
+    })
+}
@@ -0,0 +1,210 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    auth::DapUploadAuth,
+    hpke::HpkeReceiverConfig,
+    manifest::{
+        diff_task_manifest, DapTaskManifestChange, DapTaskManifestDiff, DapTaskManifestTaskDiff,
+    },
+    messages::{HpkeKemId, TaskId},
+    vdaf::VdafVerifyKey,
+    DapBatchLifetimeConfig, DapQueryConfig, DapStaleBatchPolicy, DapTaskConfig,
+    DapVdafVerifyKeyRotation, DapVersion, Prio3Config, VdafConfig,
+};
+use std::collections::HashMap;
+
+fn test_task_config() -> DapTaskConfig {
+    DapTaskConfig {
+        version: DapVersion::Draft04,
+        leader_url: "https://leader.com/v04/".parse().unwrap(),
+        helper_url: "https://helper.com/v04/".parse().unwrap(),
+        time_precision: 3600,
+        expiration: 1_700_000_000,
+        min_batch_size: 10,
+        max_batch_query_count: 1,
+        query: DapQueryConfig::TimeInterval,
+        vdaf: VdafConfig::Prio3(Prio3Config::Count),
+        vdaf_verify_key: VdafVerifyKey::Prio3([1; 16]),
+        collector_hpke_config: HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config,
+        additional_collectors: Vec::new(),
+        dp: Default::default(),
+        taskprov_advertisement: None,
+        replay_filter: None,
+        vdaf_verify_key_rotation: None,
+        upload_auth: None,
+    }
+}
+
+fn manifest_json(tasks: &[(TaskId, &DapTaskConfig)]) -> Vec<u8> {
+    let tasks: Vec<serde_json::Value> = tasks
+        .iter()
+        .map(|(task_id, task_config)| {
+            serde_json::json!({
+                "task_id": task_id.to_base64url(),
+                "config": task_config,
+                "leader_authentication_token": "leader token",
+            })
+        })
+        .collect();
+    serde_json::to_vec(&serde_json::json!({ "tasks": tasks })).unwrap()
+}
+
+#[test]
+fn from_manifest() {
+    let task_config = test_task_config();
+    let entries = DapTaskConfig::from_manifest(&manifest_json(&[
+        (TaskId([1; 32]), &task_config),
+        (TaskId([2; 32]), &task_config),
+    ]))
+    .unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].task_id, TaskId([1; 32]));
+    assert_eq!(
+        entries[0].leader_authentication_token.as_deref(),
+        Some("leader token")
+    );
+    assert_eq!(entries[1].collector_authentication_token, None);
+}
+
+#[test]
+fn from_manifest_invalid() {
+    let task_config = test_task_config();
+
+    // Malformed manifest.
+    assert!(DapTaskConfig::from_manifest(b"{\"tasks\": 1}").is_err());
+    assert!(DapTaskConfig::from_manifest(
+        br#"{"tasks": [{"task_id": "not a task ID", "config": {}}]}"#
+    )
+    .is_err());
+
+    // Duplicate task ID.
+    assert!(DapTaskConfig::from_manifest(&manifest_json(&[
+        (TaskId([1; 32]), &task_config),
+        (TaskId([1; 32]), &task_config),
+    ]))
+    .is_err());
+
+    // Verify key does not match the VDAF.
+    let mut bad_task_config = task_config.clone();
+    bad_task_config.vdaf_verify_key = VdafVerifyKey::Prio2([1; 32]);
+    assert!(
+        DapTaskConfig::from_manifest(&manifest_json(&[(TaskId([1; 32]), &bad_task_config)]))
+            .is_err()
+    );
+
+    // Other VDAF verify key does not match the VDAF.
+    let mut bad_task_config = task_config.clone();
+    bad_task_config.vdaf_verify_key_rotation = Some(DapVdafVerifyKeyRotation {
+        id: 0,
+        other_keys: HashMap::from([(1, VdafVerifyKey::Prio2([1; 32]))]),
+    });
+    assert!(
+        DapTaskConfig::from_manifest(&manifest_json(&[(TaskId([1; 32]), &bad_task_config)]))
+            .is_err()
+    );
+
+    // VDAF verify key ID assigned to two keys.
+    let mut bad_task_config = task_config.clone();
+    bad_task_config.vdaf_verify_key_rotation = Some(DapVdafVerifyKeyRotation {
+        id: 1,
+        other_keys: HashMap::from([(1, task_config.vdaf.gen_verify_key())]),
+    });
+    assert!(
+        DapTaskConfig::from_manifest(&manifest_json(&[(TaskId([1; 32]), &bad_task_config)]))
+            .is_err()
+    );
+
+    // Max batch size less than min batch size.
+    let mut bad_task_config = task_config.clone();
+    bad_task_config.query = DapQueryConfig::FixedSize {
+        max_batch_size: 5,
+        batch_lifetime: None,
+    };
+    assert!(
+        DapTaskConfig::from_manifest(&manifest_json(&[(TaskId([1; 32]), &bad_task_config)]))
+            .is_err()
+    );
+
+    // Batch lifetime with a max batch age of zero.
+    let mut bad_task_config = task_config.clone();
+    bad_task_config.query = DapQueryConfig::FixedSize {
+        max_batch_size: 10,
+        batch_lifetime: Some(DapBatchLifetimeConfig {
+            max_batch_age: 0,
+            policy: DapStaleBatchPolicy::Close,
+        }),
+    };
+    assert!(
+        DapTaskConfig::from_manifest(&manifest_json(&[(TaskId([1; 32]), &bad_task_config)]))
+            .is_err()
+    );
+
+    // Empty upload authentication token.
+    let mut bad_task_config = task_config.clone();
+    bad_task_config.upload_auth = Some(DapUploadAuth::BearerToken(String::new()));
+    assert!(
+        DapTaskConfig::from_manifest(&manifest_json(&[(TaskId([1; 32]), &bad_task_config)]))
+            .is_err()
+    );
+
+    // Task provisioned by taskprov.
+    let mut bad_task_config = task_config;
+    bad_task_config.taskprov_advertisement = Some("advertisement".into());
+    assert!(
+        DapTaskConfig::from_manifest(&manifest_json(&[(TaskId([1; 32]), &bad_task_config)]))
+            .is_err()
+    );
+}
+
+#[test]
+fn diff() {
+    let task_config = test_task_config();
+    let mut updated_task_config = task_config.clone();
+    updated_task_config.min_batch_size = 20;
+    let entries = DapTaskConfig::from_manifest(&manifest_json(&[
+        (TaskId([1; 32]), &task_config),
+        (TaskId([2; 32]), &updated_task_config),
+        (TaskId([3; 32]), &task_config),
+    ]))
+    .unwrap();
+
+    let mut installed = HashMap::new();
+    installed.insert(TaskId([1; 32]), task_config.clone());
+    installed.insert(TaskId([2; 32]), task_config.clone());
+    installed.insert(TaskId([4; 32]), task_config);
+
+    let diff = diff_task_manifest(&entries, &installed).unwrap();
+    assert_eq!(
+        diff,
+        DapTaskManifestDiff {
+            tasks: vec![
+                DapTaskManifestTaskDiff {
+                    task_id: TaskId([1; 32]).to_base64url(),
+                    change: DapTaskManifestChange::Unchanged,
+                },
+                DapTaskManifestTaskDiff {
+                    task_id: TaskId([2; 32]).to_base64url(),
+                    change: DapTaskManifestChange::Update,
+                },
+                DapTaskManifestTaskDiff {
+                    task_id: TaskId([3; 32]).to_base64url(),
+                    change: DapTaskManifestChange::Create,
+                },
+            ],
+            unlisted: vec![TaskId([4; 32]).to_base64url()],
+            applied: false,
+        }
+    );
+    assert!(!diff.is_unchanged());
+
+    // Once applied, the manifest is unchanged.
+    for entry in entries.iter() {
+        installed.insert(entry.task_id.clone(), entry.config.clone());
+    }
+    assert!(diff_task_manifest(&entries, &installed)
+        .unwrap()
+        .is_unchanged());
+}
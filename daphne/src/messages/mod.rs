@@ -33,7 +33,7 @@ const FIXED_SIZE_QUERY_TYPE_BY_BATCH_ID: u8 = 0x00;
 const FIXED_SIZE_QUERY_TYPE_CURRENT_BATCH: u8 = 0x01;
 
 // Known extension types.
-const EXTENSION_TASKPROV: u16 = 0xff00;
+pub(crate) const EXTENSION_TASKPROV: u16 = 0xff00;
 
 // Serde doesn't support derivations from const generics properly, so we have to use a macro.
 macro_rules! id_struct {
@@ -101,7 +101,7 @@ impl TaskId {
     pub fn for_request_payload(&self, version: &DapVersion) -> Option<TaskId> {
         match version {
             DapVersion::Draft02 => Some(self.clone()),
-            DapVersion::Draft04 => None,
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => None,
             DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
         }
     }
@@ -123,7 +123,7 @@ pub enum Extension {
 
 impl Extension {
     /// Return the type code associated with the extension
-    fn type_code(&self) -> u16 {
+    pub(crate) fn type_code(&self) -> u16 {
         match self {
             Self::Taskprov { .. } => EXTENSION_TASKPROV,
             Self::Unhandled { typ, .. } => *typ,
@@ -193,16 +193,13 @@ impl ParameterizedDecode<DapVersion> for ReportMetadata {
                 _ => Vec::new(),
             },
         };
-        // Check for duplicate extensions and unknown extensions.
+        // Check for duplicate extensions. Unknown extensions are checked by the Aggregator's
+        // extension handlers.
         let mut seen: HashSet<u16> = HashSet::new();
         for extension in &metadata.extensions {
             if !seen.insert(extension.type_code()) {
                 return Err(CodecError::UnexpectedValue);
             }
-            if matches!(extension, Extension::Unhandled { .. }) {
-                // Unrecognized extensions are an error.
-                return Err(CodecError::UnexpectedValue);
-            }
         }
         Ok(metadata)
     }
@@ -252,6 +249,103 @@ impl ParameterizedDecode<DapVersion> for Report {
     }
 }
 
+/// A batch of reports uploaded by a Client in one request. This is a Daphne extension to DAP.
+///
+/// Each report is encoded separately, so that a malformed report does not prevent the remaining
+/// reports from being decoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReportBatch {
+    pub encoded_reports: Vec<Vec<u8>>,
+}
+
+impl ReportBatch {
+    /// Encode the given reports as a batch.
+    pub fn from_reports(version: &DapVersion, reports: &[Report]) -> Self {
+        Self {
+            encoded_reports: reports
+                .iter()
+                .map(|report| report.get_encoded_with_param(version))
+                .collect(),
+        }
+    }
+}
+
+/// An encoded [`Report`], as it appears in a [`ReportBatch`].
+struct EncodedReport(Vec<u8>);
+
+impl Encode for EncodedReport {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_u32_bytes(bytes, &self.0);
+    }
+}
+
+impl Decode for EncodedReport {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        Ok(Self(decode_u32_bytes(bytes)?))
+    }
+}
+
+impl Encode for ReportBatch {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let encoded_reports: Vec<EncodedReport> = self
+            .encoded_reports
+            .iter()
+            .cloned()
+            .map(EncodedReport)
+            .collect();
+        encode_u32_items(bytes, &(), &encoded_reports);
+    }
+}
+
+impl Decode for ReportBatch {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        let encoded_reports: Vec<EncodedReport> = decode_u32_items(&(), bytes)?;
+        Ok(Self {
+            encoded_reports: encoded_reports
+                .into_iter()
+                .map(|encoded_report| encoded_report.0)
+                .collect(),
+        })
+    }
+}
+
+/// Decode the task ID (draft02 only) and metadata from the beginning of an encoded [`Report`],
+/// without decoding its public share or encrypted input shares. Returns `Ok(None)` if `bytes` is
+/// too short to contain them, in which case the caller may try again once more of the report has
+/// been received.
+pub fn decode_report_prefix(
+    version: &DapVersion,
+    bytes: &[u8],
+) -> Result<Option<(Option<TaskId>, ReportMetadata)>, CodecError> {
+    // Determine the length of the prefix: the task ID (draft02 only), the report ID, the time,
+    // and the length-prefixed extensions (draft02 only).
+    let mut prefix_len = 16 + 8;
+    if *version == DapVersion::Draft02 {
+        prefix_len += 32;
+        let extensions_len = match bytes.get(prefix_len..prefix_len + 2) {
+            Some(extensions_len) => usize::from(u16::from_be_bytes(
+                extensions_len
+                    .try_into()
+                    .expect("slice has the wrong length"),
+            )),
+            None => return Ok(None),
+        };
+        prefix_len += 2 + extensions_len;
+    }
+    if bytes.len() < prefix_len {
+        return Ok(None);
+    }
+
+    let mut r = Cursor::new(&bytes[..prefix_len]);
+    let draft02_task_id = if *version == DapVersion::Draft02 {
+        Some(TaskId::decode(&mut r)?)
+    } else {
+        None
+    };
+    let report_metadata = ReportMetadata::decode_with_param(version, &mut r)?;
+    Ok(Some((draft02_task_id, report_metadata)))
+}
+
 /// An initial aggregate sub-request sent in an [`AggregationJobInitReq`]. The contents of this
 /// structure pertain to a single report.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -410,6 +504,9 @@ pub struct AggregationJobInitReq {
     pub agg_param: Vec<u8>,
     pub part_batch_sel: PartialBatchSelector,
     pub report_shares: Vec<ReportShare>,
+    /// The Leader's first ping-pong message for each report share. Set in draft06, where each
+    /// report share is encoded along with its message as a [`PrepareInit`].
+    pub draft06_leader_messages: Option<Vec<PingPongMessage>>,
 }
 
 impl ParameterizedEncode<DapVersion> for AggregationJobInitReq {
@@ -426,11 +523,37 @@ impl ParameterizedEncode<DapVersion> for AggregationJobInitReq {
                     .encode(bytes);
                 encode_u16_bytes(bytes, &self.agg_param);
             }
-            DapVersion::Draft04 => encode_u32_bytes(bytes, &self.agg_param),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                encode_u32_bytes(bytes, &self.agg_param)
+            }
             DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
         };
         self.part_batch_sel.encode(bytes);
-        encode_u32_items(bytes, version, &self.report_shares);
+        match version {
+            DapVersion::Draft06 => {
+                let leader_messages = self
+                    .draft06_leader_messages
+                    .as_ref()
+                    .expect("draft06: missing leader messages");
+                assert_eq!(
+                    leader_messages.len(),
+                    self.report_shares.len(),
+                    "draft06: number of leader messages does not match number of report shares"
+                );
+
+                // Encode the sequence of PrepareInit messages, prefixed by its length in bytes.
+                let len_offset = bytes.len();
+                0_u32.encode(bytes);
+                for (report_share, message) in self.report_shares.iter().zip(leader_messages) {
+                    report_share.encode_with_param(version, bytes);
+                    encode_u32_bytes(bytes, &message.get_encoded());
+                }
+                let len =
+                    u32::try_from(bytes.len() - len_offset - 4).expect("length too large for u32");
+                bytes[len_offset..len_offset + 4].copy_from_slice(&len.to_be_bytes());
+            }
+            _ => encode_u32_items(bytes, version, &self.report_shares),
+        }
     }
 }
 
@@ -445,20 +568,122 @@ impl ParameterizedDecode<DapVersion> for AggregationJobInitReq {
                 Some(Draft02AggregationJobId::decode(bytes)?),
                 decode_u16_bytes(bytes)?,
             ),
-            DapVersion::Draft04 => (None, None, decode_u32_bytes(bytes)?),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                (None, None, decode_u32_bytes(bytes)?)
+            }
             DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
         };
 
+        let part_batch_sel = PartialBatchSelector::decode(bytes)?;
+        let (report_shares, draft06_leader_messages) = match version {
+            DapVersion::Draft06 => {
+                let (report_shares, leader_messages) =
+                    decode_u32_items::<_, PrepareInit>(version, bytes)?
+                        .into_iter()
+                        .map(|prep_init| (prep_init.report_share, prep_init.message))
+                        .unzip();
+                (report_shares, Some(leader_messages))
+            }
+            _ => (decode_u32_items(version, bytes)?, None),
+        };
+
         Ok(Self {
             draft02_task_id,
             draft02_agg_job_id,
             agg_param,
-            part_batch_sel: PartialBatchSelector::decode(bytes)?,
-            report_shares: decode_u32_items(version, bytes)?,
+            part_batch_sel,
+            report_shares,
+            draft06_leader_messages,
         })
     }
 }
 
+/// A report share and the Leader's first ping-pong message for it, as sent in the
+/// [`AggregationJobInitReq`] in draft06.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrepareInit {
+    pub report_share: ReportShare,
+    pub message: PingPongMessage,
+}
+
+impl ParameterizedEncode<DapVersion> for PrepareInit {
+    fn encode_with_param(&self, version: &DapVersion, bytes: &mut Vec<u8>) {
+        self.report_share.encode_with_param(version, bytes);
+        encode_u32_bytes(bytes, &self.message.get_encoded());
+    }
+}
+
+impl ParameterizedDecode<DapVersion> for PrepareInit {
+    fn decode_with_param(
+        version: &DapVersion,
+        bytes: &mut Cursor<&[u8]>,
+    ) -> Result<Self, CodecError> {
+        Ok(Self {
+            report_share: ReportShare::decode_with_param(version, bytes)?,
+            message: PingPongMessage::get_decoded(&decode_u32_bytes(bytes)?)?,
+        })
+    }
+}
+
+/// Message exchanged by the Aggregators during preparation in the ping-pong topology (draft06).
+/// For a VDAF with a single round of preparation, such as Prio3, the Leader sends `Initialize`
+/// and the Helper responds with `Finish`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PingPongMessage {
+    /// The Leader's prep share for the first round.
+    Initialize { prep_share: Vec<u8> },
+
+    /// The prep message for the current round and the sender's prep share for the next round.
+    Continue {
+        prep_msg: Vec<u8>,
+        prep_share: Vec<u8>,
+    },
+
+    /// The prep message for the last round.
+    Finish { prep_msg: Vec<u8> },
+}
+
+impl Encode for PingPongMessage {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Self::Initialize { prep_share } => {
+                0_u8.encode(bytes);
+                encode_u32_bytes(bytes, prep_share);
+            }
+            Self::Continue {
+                prep_msg,
+                prep_share,
+            } => {
+                1_u8.encode(bytes);
+                encode_u32_bytes(bytes, prep_msg);
+                encode_u32_bytes(bytes, prep_share);
+            }
+            Self::Finish { prep_msg } => {
+                2_u8.encode(bytes);
+                encode_u32_bytes(bytes, prep_msg);
+            }
+        }
+    }
+}
+
+impl Decode for PingPongMessage {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
+        match u8::decode(bytes)? {
+            0 => Ok(Self::Initialize {
+                prep_share: decode_u32_bytes(bytes)?,
+            }),
+            1 => Ok(Self::Continue {
+                prep_msg: decode_u32_bytes(bytes)?,
+                prep_share: decode_u32_bytes(bytes)?,
+            }),
+            2 => Ok(Self::Finish {
+                prep_msg: decode_u32_bytes(bytes)?,
+            }),
+            _ => Err(CodecError::UnexpectedValue),
+        }
+    }
+}
+
 /// Aggregate continuation request.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AggregationJobContinueReq {
@@ -481,7 +706,7 @@ impl ParameterizedEncode<DapVersion> for AggregationJobContinueReq {
                     .expect("draft02: missing aggregation job ID")
                     .encode(bytes);
             }
-            DapVersion::Draft04 => {
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
                 self.round
                     .as_ref()
                     .expect("draft04: missing round")
@@ -504,7 +729,9 @@ impl ParameterizedDecode<DapVersion> for AggregationJobContinueReq {
                 Some(Draft02AggregationJobId::decode(bytes)?),
                 None,
             ),
-            DapVersion::Draft04 => (None, None, Some(u16::decode(bytes)?)),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                (None, None, Some(u16::decode(bytes)?))
+            }
             DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
         };
         Ok(Self {
@@ -787,13 +1014,15 @@ impl ParameterizedEncode<DapVersion> for CollectionReq {
                     .expect("draft02: missing task ID")
                     .encode(bytes);
             }
-            DapVersion::Draft04 => {}
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {}
             DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
         }
         self.query.encode_with_param(version, bytes);
         match version {
             DapVersion::Draft02 => encode_u16_bytes(bytes, &self.agg_param),
-            DapVersion::Draft04 => encode_u32_bytes(bytes, &self.agg_param),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                encode_u32_bytes(bytes, &self.agg_param)
+            }
             _ => panic!("unimplemented DapVersion"),
         };
     }
@@ -806,7 +1035,7 @@ impl ParameterizedDecode<DapVersion> for CollectionReq {
     ) -> Result<Self, CodecError> {
         let draft02_task_id = match version {
             DapVersion::Draft02 => Some(TaskId::decode(bytes)?),
-            DapVersion::Draft04 => None,
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => None,
             DapVersion::Unknown => unreachable!("unhandled version {version:?}"),
         };
         Ok(Self {
@@ -814,7 +1043,9 @@ impl ParameterizedDecode<DapVersion> for CollectionReq {
             query: Query::decode_with_param(version, bytes)?,
             agg_param: match version {
                 DapVersion::Draft02 => decode_u16_bytes(bytes)?,
-                DapVersion::Draft04 => decode_u32_bytes(bytes)?,
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                    decode_u32_bytes(bytes)?
+                }
                 _ => panic!("unimplemented DapVersion"),
             },
         })
@@ -838,7 +1069,7 @@ impl ParameterizedEncode<DapVersion> for Collection {
         self.report_count.encode(bytes);
         match version {
             DapVersion::Draft02 => {}
-            DapVersion::Draft04 => {
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
                 self.interval
                     .as_ref()
                     .expect("draft04: missing interval")
@@ -860,7 +1091,9 @@ impl ParameterizedDecode<DapVersion> for Collection {
             report_count: u64::decode(bytes)?,
             interval: match version {
                 DapVersion::Draft02 => None,
-                DapVersion::Draft04 => Some(Interval::decode(bytes)?),
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                    Some(Interval::decode(bytes)?)
+                }
                 _ => panic!("unimplemented DapVersion"),
             },
             encrypted_agg_shares: decode_u32_items(&(), bytes)?,
@@ -891,7 +1124,7 @@ impl ParameterizedEncode<DapVersion> for AggregateShareReq {
                 self.batch_sel.encode_with_param(version, bytes);
                 encode_u16_bytes(bytes, &self.agg_param);
             }
-            DapVersion::Draft04 => {
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
                 self.batch_sel.encode_with_param(version, bytes);
                 encode_u32_bytes(bytes, &self.agg_param);
             }
@@ -913,7 +1146,7 @@ impl ParameterizedDecode<DapVersion> for AggregateShareReq {
                 BatchSelector::decode_with_param(version, bytes)?,
                 decode_u16_bytes(bytes)?,
             ),
-            DapVersion::Draft04 => (
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => (
                 None,
                 BatchSelector::decode_with_param(version, bytes)?,
                 decode_u32_bytes(bytes)?,
@@ -1165,15 +1398,13 @@ impl Decode for PlaintextInputShare {
             extensions: decode_u16_items(&(), bytes)?,
             payload: decode_u32_bytes(bytes)?,
         };
-        // Check for duplicate extensions and unknown extensions.
+        // Check for duplicate extensions. Unknown extensions are checked by the Aggregator's
+        // extension handlers.
         let mut seen: HashSet<u16> = HashSet::new();
         for extension in &share.extensions {
             if !seen.insert(extension.type_code()) {
                 return Err(CodecError::UnexpectedValue);
             }
-            if matches!(extension, Extension::Unhandled { .. }) {
-                return Err(CodecError::UnexpectedValue);
-            }
         }
         Ok(share)
     }
@@ -1249,4 +1480,6 @@ pub fn decode_base64url_vec<T: AsRef<[u8]>>(input: T) -> Option<Vec<u8>> {
 
 #[cfg(test)]
 mod mod_test;
+#[cfg(test)]
+mod snapshot_test;
 pub mod taskprov;
@@ -5,11 +5,12 @@ use crate::messages::taskprov::{
     DpConfig, QueryConfig, QueryConfigVar, TaskConfig, UrlBytes, VdafConfig, VdafTypeVar,
 };
 use crate::messages::{
-    decode_base64url, decode_base64url_vec, encode_base64url, AggregateShareReq,
-    AggregationJobContinueReq, AggregationJobId, AggregationJobInitReq, AggregationJobResp,
-    BatchId, BatchSelector, CollectionJobId, DapVersion, Draft02AggregationJobId, Extension,
-    HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, PartialBatchSelector, Report,
-    ReportId, ReportMetadata, ReportShare, TaskId, Transition, TransitionVar,
+    decode_base64url, decode_base64url_vec, decode_report_prefix, encode_base64url,
+    AggregateShareReq, AggregationJobContinueReq, AggregationJobId, AggregationJobInitReq,
+    AggregationJobResp, BatchId, BatchSelector, CollectionJobId, DapVersion,
+    Draft02AggregationJobId, Extension, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId,
+    HpkeKemId, PartialBatchSelector, PingPongMessage, Report, ReportId, ReportMetadata,
+    ReportShare, TaskId, Transition, TransitionVar,
 };
 use crate::taskprov::{compute_task_id, TaskprovVersion};
 use crate::{test_version, test_versions};
@@ -56,6 +57,54 @@ fn read_report(version: DapVersion) {
 
 test_versions! {read_report}
 
+fn read_report_prefix(version: DapVersion) {
+    let report = Report {
+        draft02_task_id: task_id_for_version(version),
+        report_metadata: ReportMetadata {
+            id: ReportId([23; 16]),
+            time: 1637364244,
+            extensions: if version == DapVersion::Draft02 {
+                vec![Extension::Taskprov {
+                    payload: b"some extension".to_vec(),
+                }]
+            } else {
+                vec![]
+            },
+        },
+        public_share: b"public share".to_vec(),
+        encrypted_input_shares: vec![HpkeCiphertext {
+            config_id: 23,
+            enc: b"leader encapsulated key".to_vec(),
+            payload: b"leader ciphertext".to_vec(),
+        }],
+    };
+    let encoded = report.get_encoded_with_param(&version);
+    let prefix_len = report.draft02_task_id.as_ref().map_or(0, |_| 32)
+        + report
+            .report_metadata
+            .get_encoded_with_param(&version)
+            .len();
+
+    // The prefix cannot be decoded until all of it has been received.
+    for len in 0..prefix_len {
+        assert_eq!(
+            decode_report_prefix(&version, &encoded[..len]).unwrap(),
+            None
+        );
+    }
+    for len in [prefix_len, encoded.len()] {
+        assert_eq!(
+            decode_report_prefix(&version, &encoded[..len]).unwrap(),
+            Some((
+                report.draft02_task_id.clone(),
+                report.report_metadata.clone()
+            ))
+        );
+    }
+}
+
+test_versions! {read_report_prefix}
+
 #[test]
 fn read_report_with_unknown_extensions_draft02() {
     let report = Report {
@@ -83,6 +132,18 @@ fn read_report_with_unknown_extensions_draft02() {
         ],
     };
     let version = DapVersion::Draft02;
+    // Unknown extensions are left to the Aggregator's extension handlers.
+    assert_eq!(
+        Report::get_decoded_with_param(&version, &report.get_encoded_with_param(&version)).unwrap(),
+        report
+    );
+
+    // Duplicate extensions are an error.
+    let mut report = report;
+    report
+        .report_metadata
+        .extensions
+        .push(report.report_metadata.extensions[0].clone());
     assert!(
         Report::get_decoded_with_param(&version, &report.get_encoded_with_param(&version)).is_err()
     );
@@ -125,6 +186,7 @@ fn read_agg_job_init_req() {
                 },
             },
         ],
+        draft06_leader_messages: None,
     };
 
     let got = AggregationJobInitReq::get_decoded_with_param(
@@ -169,6 +231,7 @@ fn read_agg_job_init_req() {
                 },
             },
         ],
+        draft06_leader_messages: None,
     };
 
     let got = AggregationJobInitReq::get_decoded_with_param(
@@ -177,6 +240,58 @@ fn read_agg_job_init_req() {
     )
     .unwrap();
     assert_eq!(got, want);
+
+    let want = AggregationJobInitReq {
+        draft02_task_id: None,
+        draft02_agg_job_id: None,
+        agg_param: b"this is an aggregation parameter".to_vec(),
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_shares: vec![ReportShare {
+            report_metadata: ReportMetadata {
+                id: ReportId([99; 16]),
+                time: 1637361337,
+                extensions: Vec::default(),
+            },
+            public_share: b"public share".to_vec(),
+            encrypted_input_share: HpkeCiphertext {
+                config_id: 23,
+                enc: b"encapsulated key".to_vec(),
+                payload: b"ciphertext".to_vec(),
+            },
+        }],
+        draft06_leader_messages: Some(vec![PingPongMessage::Initialize {
+            prep_share: b"prep share".to_vec(),
+        }]),
+    };
+
+    let got = AggregationJobInitReq::get_decoded_with_param(
+        &DapVersion::Draft06,
+        &want.get_encoded_with_param(&DapVersion::Draft06),
+    )
+    .unwrap();
+    assert_eq!(got, want);
+}
+
+#[test]
+fn roundtrip_ping_pong_message() {
+    for want in [
+        PingPongMessage::Initialize {
+            prep_share: b"prep share".to_vec(),
+        },
+        PingPongMessage::Continue {
+            prep_msg: b"prep message".to_vec(),
+            prep_share: b"prep share".to_vec(),
+        },
+        PingPongMessage::Finish {
+            prep_msg: b"prep message".to_vec(),
+        },
+    ] {
+        let got = PingPongMessage::get_decoded(&want.get_encoded()).unwrap();
+        assert_eq!(got, want);
+    }
+
+    // Unknown message type.
+    assert!(PingPongMessage::get_decoded(&[3, 0, 0, 0, 0]).is_err());
 }
 
 #[test]
@@ -348,6 +463,63 @@ fn read_vdaf_config() {
     );
 }
 
+#[test]
+fn roundtrip_vdaf_config_with_dp_config() {
+    let data = [
+        0x03, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    let vdaf_config = VdafConfig::get_decoded(&data).unwrap();
+    assert_eq!(
+        vdaf_config,
+        VdafConfig {
+            dp_config: DpConfig::DiscreteGaussian {
+                sigma_squared_numerator: 7,
+                sigma_squared_denominator: 2,
+            },
+            var: VdafTypeVar::Prio3Aes128Count,
+        }
+    );
+    assert_eq!(vdaf_config.get_encoded(), data);
+
+    let vdaf_config = VdafConfig {
+        dp_config: DpConfig::DiscreteLaplace {
+            scale_numerator: 1,
+            scale_denominator: 10,
+        },
+        var: VdafTypeVar::Prio3Aes128Sum { bit_length: 8 },
+    };
+    assert_eq!(
+        VdafConfig::get_decoded(&vdaf_config.get_encoded()).unwrap(),
+        vdaf_config
+    );
+}
+
+#[test]
+fn roundtrip_vdaf_config_fixed_point_bounded_l2_vec_sum() {
+    let vdaf_config = VdafConfig {
+        dp_config: DpConfig::None,
+        var: VdafTypeVar::Prio3FixedPointBoundedL2VecSum {
+            bitsize: 32,
+            length: 1337,
+        },
+    };
+    assert_eq!(
+        VdafConfig::get_decoded(&vdaf_config.get_encoded()).unwrap(),
+        vdaf_config
+    );
+
+    // Only 16- and 32-bit entries are supported.
+    let vdaf_config = VdafConfig {
+        dp_config: DpConfig::None,
+        var: VdafTypeVar::Prio3FixedPointBoundedL2VecSum {
+            bitsize: 64,
+            length: 1337,
+        },
+    };
+    assert!(VdafConfig::get_decoded(&vdaf_config.get_encoded()).is_err());
+}
+
 #[test]
 fn read_task_config_taskprov_draft02() {
     let data = [
@@ -400,6 +572,46 @@ fn read_task_config_taskprov_draft02() {
     );
 }
 
+#[test]
+fn roundtrip_task_config_taskprov_draft04() {
+    let task_config = TaskConfig {
+        task_info: "Hi".as_bytes().to_vec(),
+        aggregator_endpoints: vec![
+            UrlBytes {
+                bytes: "https://leader.test".as_bytes().to_vec(),
+            },
+            UrlBytes {
+                bytes: "https://helper.test".as_bytes().to_vec(),
+            },
+        ],
+        query_config: QueryConfig {
+            time_precision: 0x01,
+            max_batch_query_count: 128,
+            min_batch_size: 1024,
+            var: QueryConfigVar::TimeInterval,
+        },
+        task_expiration: 0x6352f9a5,
+        vdaf_config: VdafConfig {
+            dp_config: DpConfig::None,
+            var: VdafTypeVar::Prio3Aes128Count,
+        },
+    };
+
+    let encoded = task_config.get_encoded_with_param(&TaskprovVersion::Draft04);
+    assert_eq!(
+        TaskConfig::get_decoded_with_param(&TaskprovVersion::Draft04, &encoded).unwrap(),
+        task_config
+    );
+
+    // The endpoints are not encoded as a length-prefixed list.
+    assert_eq!(
+        encoded.len() + 2,
+        task_config
+            .get_encoded_with_param(&TaskprovVersion::Draft02)
+            .len()
+    );
+}
+
 #[test]
 fn test_base64url() {
     let mut rng = thread_rng();
@@ -0,0 +1,357 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Snapshot tests for the wire encoding of each message.
+//!
+//! The encoding of each message is compared against a golden file in `src/messages/snapshots`,
+//! so that any change to the wire format shows up in review. If a change is intentional, then
+//! regenerate the golden files by running the tests with `DAPHNE_UPDATE_SNAPSHOTS=1`.
+
+use crate::messages::taskprov::{
+    DpConfig, QueryConfig, QueryConfigVar, TaskConfig, UrlBytes, VdafConfig, VdafTypeVar,
+};
+use crate::messages::{
+    AggregateShare, AggregateShareReq, AggregationJobContinueReq, AggregationJobInitReq,
+    AggregationJobResp, BatchId, BatchSelector, Collection, CollectionReq, DapVersion,
+    Draft02AggregationJobId, Extension, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeConfigList,
+    HpkeKdfId, HpkeKemId, Interval, PartialBatchSelector, PingPongMessage, PlaintextInputShare,
+    Query, Report, ReportId, ReportMetadata, ReportShare, TaskId, Transition, TransitionFailure,
+    TransitionVar,
+};
+use crate::taskprov::TaskprovVersion;
+use crate::{test_version, test_versions};
+use hpke_rs::HpkePublicKey;
+use paste::paste;
+use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
+use std::{fs, path::PathBuf};
+
+/// Compare `encoded` against the golden file for `name`.
+fn assert_snapshot(name: &str, encoded: &[u8]) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/messages/snapshots")
+        .join(format!("{name}.hex"));
+    let got = hex::encode(encoded);
+
+    if std::env::var_os("DAPHNE_UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!("{got}\n")).unwrap();
+        return;
+    }
+
+    let want = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read snapshot {}: {e}; run with DAPHNE_UPDATE_SNAPSHOTS=1 to create it",
+            path.display()
+        )
+    });
+    assert_eq!(
+        got,
+        want.trim(),
+        "encoding of {name} changed; if this is intentional, run with DAPHNE_UPDATE_SNAPSHOTS=1"
+    );
+}
+
+/// Check the encoding of a message whose encoding depends on the DAP version. The encoding must
+/// also decode to a message with the same encoding.
+fn check_versioned<M>(name: &str, version: DapVersion, msg: &M)
+where
+    M: ParameterizedEncode<DapVersion> + ParameterizedDecode<DapVersion>,
+{
+    let encoded = msg.get_encoded_with_param(&version);
+    assert_snapshot(&format!("{name}_{version}"), &encoded);
+    let decoded = M::get_decoded_with_param(&version, &encoded).unwrap();
+    assert_eq!(decoded.get_encoded_with_param(&version), encoded);
+}
+
+/// Like [`check_versioned`], but for messages whose encoding does not depend on the DAP version.
+fn check<M: Encode + Decode>(name: &str, msg: &M) {
+    let encoded = msg.get_encoded();
+    assert_snapshot(name, &encoded);
+    assert_eq!(M::get_decoded(&encoded).unwrap().get_encoded(), encoded);
+}
+
+fn task_id_for_version(version: DapVersion) -> Option<TaskId> {
+    (version == DapVersion::Draft02).then_some(TaskId([1; 32]))
+}
+
+fn agg_job_id_for_version(version: DapVersion) -> Option<Draft02AggregationJobId> {
+    (version == DapVersion::Draft02).then_some(Draft02AggregationJobId([2; 32]))
+}
+
+fn leader_messages_for_version(version: DapVersion) -> Option<Vec<PingPongMessage>> {
+    (version == DapVersion::Draft06).then(|| {
+        vec![PingPongMessage::Initialize {
+            prep_share: b"prep share".to_vec(),
+        }]
+    })
+}
+
+fn hpke_ciphertext(config_id: u8) -> HpkeCiphertext {
+    HpkeCiphertext {
+        config_id,
+        enc: b"encapsulated key".to_vec(),
+        payload: b"ciphertext".to_vec(),
+    }
+}
+
+fn hpke_config(id: u8) -> HpkeConfig {
+    HpkeConfig {
+        id,
+        kem_id: HpkeKemId::X25519HkdfSha256,
+        kdf_id: HpkeKdfId::HkdfSha256,
+        aead_id: HpkeAeadId::Aes128Gcm,
+        public_key: HpkePublicKey::from(b"this is a public key".to_vec()),
+    }
+}
+
+fn extensions() -> Vec<Extension> {
+    vec![Extension::Taskprov {
+        payload: b"taskprov".to_vec(),
+    }]
+}
+
+/// Report metadata only carries extensions in draft02.
+fn report_metadata(version: DapVersion) -> ReportMetadata {
+    ReportMetadata {
+        id: ReportId([3; 16]),
+        time: 1637364244,
+        extensions: if version == DapVersion::Draft02 {
+            extensions()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+fn time_interval() -> Interval {
+    Interval {
+        start: 1637359200,
+        duration: 3600,
+    }
+}
+
+fn snapshot_report(version: DapVersion) {
+    check_versioned("report_metadata", version, &report_metadata(version));
+    check_versioned(
+        "report",
+        version,
+        &Report {
+            draft02_task_id: task_id_for_version(version),
+            report_metadata: report_metadata(version),
+            public_share: b"public share".to_vec(),
+            encrypted_input_shares: vec![hpke_ciphertext(23), hpke_ciphertext(119)],
+        },
+    );
+}
+
+test_versions! { snapshot_report }
+
+fn snapshot_agg_job_init_req(version: DapVersion) {
+    let report_share = ReportShare {
+        report_metadata: report_metadata(version),
+        public_share: b"public share".to_vec(),
+        encrypted_input_share: hpke_ciphertext(23),
+    };
+    check_versioned("report_share", version, &report_share);
+    check_versioned(
+        "agg_job_init_req_time_interval",
+        version,
+        &AggregationJobInitReq {
+            draft02_task_id: task_id_for_version(version),
+            draft02_agg_job_id: agg_job_id_for_version(version),
+            agg_param: b"aggregation parameter".to_vec(),
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            report_shares: vec![report_share.clone()],
+            draft06_leader_messages: leader_messages_for_version(version),
+        },
+    );
+    check_versioned(
+        "agg_job_init_req_fixed_size",
+        version,
+        &AggregationJobInitReq {
+            draft02_task_id: task_id_for_version(version),
+            draft02_agg_job_id: agg_job_id_for_version(version),
+            agg_param: Vec::default(),
+            part_batch_sel: PartialBatchSelector::FixedSizeByBatchId {
+                batch_id: BatchId([4; 32]),
+            },
+            report_shares: vec![report_share],
+            draft06_leader_messages: leader_messages_for_version(version),
+        },
+    );
+}
+
+test_versions! { snapshot_agg_job_init_req }
+
+fn snapshot_agg_job_cont_req(version: DapVersion) {
+    check_versioned(
+        "agg_job_cont_req",
+        version,
+        &AggregationJobContinueReq {
+            draft02_task_id: task_id_for_version(version),
+            draft02_agg_job_id: agg_job_id_for_version(version),
+            round: (version != DapVersion::Draft02).then_some(1),
+            transitions: vec![Transition {
+                report_id: ReportId([3; 16]),
+                var: TransitionVar::Continued(b"prep message".to_vec()),
+            }],
+        },
+    );
+}
+
+test_versions! { snapshot_agg_job_cont_req }
+
+fn snapshot_collect(version: DapVersion) {
+    for (name, query) in [
+        (
+            "collect_req_time_interval",
+            Query::TimeInterval {
+                batch_interval: time_interval(),
+            },
+        ),
+        (
+            "collect_req_fixed_size_by_batch_id",
+            Query::FixedSizeByBatchId {
+                batch_id: BatchId([4; 32]),
+            },
+        ),
+    ] {
+        check_versioned(
+            name,
+            version,
+            &CollectionReq {
+                draft02_task_id: task_id_for_version(version),
+                query,
+                agg_param: b"aggregation parameter".to_vec(),
+            },
+        );
+    }
+    if version != DapVersion::Draft02 {
+        check_versioned(
+            "collect_req_fixed_size_current_batch",
+            version,
+            &CollectionReq {
+                draft02_task_id: None,
+                query: Query::FixedSizeCurrentBatch,
+                agg_param: Vec::default(),
+            },
+        );
+    }
+
+    check_versioned(
+        "collection",
+        version,
+        &Collection {
+            part_batch_sel: PartialBatchSelector::FixedSizeByBatchId {
+                batch_id: BatchId([4; 32]),
+            },
+            report_count: 23,
+            interval: (version != DapVersion::Draft02).then_some(time_interval()),
+            encrypted_agg_shares: vec![hpke_ciphertext(1), hpke_ciphertext(2)],
+        },
+    );
+}
+
+test_versions! { snapshot_collect }
+
+fn snapshot_agg_share_req(version: DapVersion) {
+    check_versioned(
+        "agg_share_req",
+        version,
+        &AggregateShareReq {
+            draft02_task_id: task_id_for_version(version),
+            batch_sel: BatchSelector::TimeInterval {
+                batch_interval: time_interval(),
+            },
+            agg_param: b"aggregation parameter".to_vec(),
+            report_count: 23,
+            checksum: [5; 32],
+        },
+    );
+}
+
+test_versions! { snapshot_agg_share_req }
+
+#[test]
+fn snapshot_unversioned() {
+    check(
+        "agg_job_resp",
+        &AggregationJobResp {
+            transitions: vec![
+                Transition {
+                    report_id: ReportId([3; 16]),
+                    var: TransitionVar::Continued(b"prep message".to_vec()),
+                },
+                Transition {
+                    report_id: ReportId([4; 16]),
+                    var: TransitionVar::Finished,
+                },
+                Transition {
+                    report_id: ReportId([5; 16]),
+                    var: TransitionVar::Failed(TransitionFailure::HpkeDecryptError),
+                },
+            ],
+        },
+    );
+    check(
+        "agg_share",
+        &AggregateShare {
+            encrypted_agg_share: hpke_ciphertext(1),
+        },
+    );
+    check(
+        "batch_selector_fixed_size",
+        &BatchSelector::FixedSizeByBatchId {
+            batch_id: BatchId([4; 32]),
+        },
+    );
+    check("hpke_config", &hpke_config(23));
+    check(
+        "hpke_config_list",
+        &HpkeConfigList {
+            hpke_configs: vec![hpke_config(23), hpke_config(24)],
+        },
+    );
+    check(
+        "plaintext_input_share",
+        &PlaintextInputShare {
+            extensions: extensions(),
+            payload: b"input share".to_vec(),
+        },
+    );
+}
+
+#[test]
+fn snapshot_taskprov_task_config() {
+    let version = TaskprovVersion::Draft02;
+    let task_config = TaskConfig {
+        task_info: b"cool task".to_vec(),
+        aggregator_endpoints: vec![
+            UrlBytes {
+                bytes: b"https://leader.com/".to_vec(),
+            },
+            UrlBytes {
+                bytes: b"https://helper.org/".to_vec(),
+            },
+        ],
+        query_config: QueryConfig {
+            time_precision: 3600,
+            max_batch_query_count: 1,
+            min_batch_size: 10,
+            var: QueryConfigVar::FixedSize { max_batch_size: 20 },
+        },
+        task_expiration: 1637364244,
+        vdaf_config: VdafConfig {
+            dp_config: DpConfig::None,
+            var: VdafTypeVar::Prio3Aes128Histogram {
+                buckets: vec![1, 10, 100],
+            },
+        },
+    };
+    let encoded = task_config.get_encoded_with_param(&version);
+    assert_snapshot("taskprov_task_config_v02", &encoded);
+    assert_eq!(
+        TaskConfig::get_decoded_with_param(&version, &encoded).unwrap(),
+        task_config
+    );
+}
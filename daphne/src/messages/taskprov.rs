@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 //! Messages in the taskprov extension to the DAP protocol, as
-//! defined in draft-wang-ppm-dap-taskprov-02.
+//! defined in draft-wang-ppm-dap-taskprov-02 and draft-wang-ppm-dap-taskprov-04.
 
 use crate::messages::{
     decode_u16_bytes, encode_u16_bytes, Duration, Time, QUERY_TYPE_FIXED_SIZE,
@@ -18,13 +18,19 @@ use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 
 // VDAF type codes.
-const VDAF_TYPE_PRIO3_AES128_COUNT: u32 = 0x00000000;
-const VDAF_TYPE_PRIO3_AES128_SUM: u32 = 0x00000001;
-const VDAF_TYPE_PRIO3_AES128_HISTOGRAM: u32 = 0x00000002;
+pub(crate) const VDAF_TYPE_PRIO3_AES128_COUNT: u32 = 0x00000000;
+pub(crate) const VDAF_TYPE_PRIO3_AES128_SUM: u32 = 0x00000001;
+pub(crate) const VDAF_TYPE_PRIO3_AES128_HISTOGRAM: u32 = 0x00000002;
 const VDAF_TYPE_POPLAR1_AES128: u32 = 0x00001000; // The gap from the previous constant is intentional
+                                                  // NOTE This VDAF is not (yet) defined by the taskprov draft. The code point is the private-use
+                                                  // algorithm ID assigned to it by the `prio` crate.
+pub(crate) const VDAF_TYPE_PRIO3_FIXED_POINT_BOUNDED_L2_VEC_SUM: u32 = 0xFFFF0000;
 
 // Differential privacy mechanism types.
 const DP_MECHANISM_NONE: u8 = 0x01;
+// NOTE The following mechanisms are not (yet) defined by the taskprov draft.
+const DP_MECHANISM_DISCRETE_LAPLACE: u8 = 0x02;
+const DP_MECHANISM_DISCRETE_GAUSSIAN: u8 = 0x03;
 
 /// A VDAF type.
 #[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
@@ -34,6 +40,7 @@ pub(crate) enum VdafType {
     Prio3Aes128Sum,
     Prio3Aes128Histogram,
     Poplar1Aes128,
+    Prio3FixedPointBoundedL2VecSum,
     NotImplemented(u32),
 }
 
@@ -44,6 +51,7 @@ impl KeyType for VdafType {
             VdafType::Prio3Aes128Sum => 16,
             VdafType::Prio3Aes128Histogram => 16,
             VdafType::Poplar1Aes128 => 16,
+            VdafType::Prio3FixedPointBoundedL2VecSum => 16,
             _ => panic!("tried to get key length for undefined VDAF"),
         }
     }
@@ -53,9 +61,20 @@ impl KeyType for VdafType {
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
 pub enum VdafTypeVar {
     Prio3Aes128Count,
-    Prio3Aes128Sum { bit_length: u8 },
-    Prio3Aes128Histogram { buckets: Vec<u64> },
-    Poplar1Aes128 { bit_length: u16 },
+    Prio3Aes128Sum {
+        bit_length: u8,
+    },
+    Prio3Aes128Histogram {
+        buckets: Vec<u64>,
+    },
+    Poplar1Aes128 {
+        bit_length: u16,
+    },
+    /// The bit size of each entry must be 16 or 32.
+    Prio3FixedPointBoundedL2VecSum {
+        bitsize: u8,
+        length: u32,
+    },
     NotImplemented(u32),
 }
 
@@ -75,6 +94,11 @@ impl Encode for VdafTypeVar {
                 VDAF_TYPE_POPLAR1_AES128.encode(bytes);
                 bit_length.encode(bytes);
             }
+            VdafTypeVar::Prio3FixedPointBoundedL2VecSum { bitsize, length } => {
+                VDAF_TYPE_PRIO3_FIXED_POINT_BOUNDED_L2_VEC_SUM.encode(bytes);
+                bitsize.encode(bytes);
+                length.encode(bytes);
+            }
             VdafTypeVar::NotImplemented(x) => {
                 x.encode(bytes);
             }
@@ -96,6 +120,16 @@ impl Decode for VdafTypeVar {
             VDAF_TYPE_POPLAR1_AES128 => Ok(Self::Poplar1Aes128 {
                 bit_length: u16::decode(bytes)?,
             }),
+            VDAF_TYPE_PRIO3_FIXED_POINT_BOUNDED_L2_VEC_SUM => {
+                let bitsize = u8::decode(bytes)?;
+                if !matches!(bitsize, 16 | 32) {
+                    return Err(CodecError::UnexpectedValue);
+                }
+                Ok(Self::Prio3FixedPointBoundedL2VecSum {
+                    bitsize,
+                    length: u32::decode(bytes)?,
+                })
+            }
             _ => Err(CodecError::UnexpectedValue),
         }
     }
@@ -108,21 +142,51 @@ impl From<VdafTypeVar> for VdafType {
             VdafTypeVar::Prio3Aes128Histogram { .. } => VdafType::Prio3Aes128Histogram,
             VdafTypeVar::Prio3Aes128Sum { .. } => VdafType::Prio3Aes128Sum,
             VdafTypeVar::Poplar1Aes128 { .. } => VdafType::Poplar1Aes128,
+            VdafTypeVar::Prio3FixedPointBoundedL2VecSum { .. } => {
+                VdafType::Prio3FixedPointBoundedL2VecSum
+            }
             VdafTypeVar::NotImplemented(x) => VdafType::NotImplemented(x),
         }
     }
 }
 
-/// A differential privacy mechanism.
+/// A differential privacy mechanism. Parameters are rational numbers encoded as a numerator
+/// followed by a denominator.
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
 pub enum DpConfig {
     None,
+    /// Discrete Laplace noise with the given scale.
+    DiscreteLaplace {
+        scale_numerator: u32,
+        scale_denominator: u32,
+    },
+    /// Discrete Gaussian noise with the given variance.
+    DiscreteGaussian {
+        sigma_squared_numerator: u32,
+        sigma_squared_denominator: u32,
+    },
 }
 
 impl Encode for DpConfig {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match self {
             Self::None => DP_MECHANISM_NONE.encode(bytes),
+            Self::DiscreteLaplace {
+                scale_numerator,
+                scale_denominator,
+            } => {
+                DP_MECHANISM_DISCRETE_LAPLACE.encode(bytes);
+                scale_numerator.encode(bytes);
+                scale_denominator.encode(bytes);
+            }
+            Self::DiscreteGaussian {
+                sigma_squared_numerator,
+                sigma_squared_denominator,
+            } => {
+                DP_MECHANISM_DISCRETE_GAUSSIAN.encode(bytes);
+                sigma_squared_numerator.encode(bytes);
+                sigma_squared_denominator.encode(bytes);
+            }
         }
     }
 }
@@ -131,6 +195,14 @@ impl Decode for DpConfig {
     fn decode(bytes: &mut Cursor<&[u8]>) -> Result<Self, CodecError> {
         match u8::decode(bytes)? {
             DP_MECHANISM_NONE => Ok(Self::None),
+            DP_MECHANISM_DISCRETE_LAPLACE => Ok(Self::DiscreteLaplace {
+                scale_numerator: u32::decode(bytes)?,
+                scale_denominator: u32::decode(bytes)?,
+            }),
+            DP_MECHANISM_DISCRETE_GAUSSIAN => Ok(Self::DiscreteGaussian {
+                sigma_squared_numerator: u32::decode(bytes)?,
+                sigma_squared_denominator: u32::decode(bytes)?,
+            }),
             _ => Err(CodecError::UnexpectedValue),
         }
     }
@@ -256,6 +328,9 @@ impl ParameterizedDecode<TaskprovVersion> for QueryConfig {
 }
 
 /// A DAP task configuration.
+///
+/// In draft04, the endpoints of the Leader and Helper are encoded as two separate fields rather
+/// than a list, so `aggregator_endpoints` must have exactly two elements.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct TaskConfig {
     pub task_info: Vec<u8>,
@@ -268,7 +343,16 @@ pub struct TaskConfig {
 impl ParameterizedEncode<TaskprovVersion> for TaskConfig {
     fn encode_with_param(&self, encoding_parameter: &TaskprovVersion, bytes: &mut Vec<u8>) {
         encode_u8_items(bytes, &(), &self.task_info);
-        encode_u16_items(bytes, &(), &self.aggregator_endpoints);
+        match encoding_parameter {
+            TaskprovVersion::Draft04 => {
+                for endpoint in &self.aggregator_endpoints {
+                    endpoint.encode(bytes);
+                }
+            }
+            TaskprovVersion::Draft02 | TaskprovVersion::Unknown => {
+                encode_u16_items(bytes, &(), &self.aggregator_endpoints);
+            }
+        }
         self.query_config
             .encode_with_param(encoding_parameter, bytes);
         self.task_expiration.encode(bytes);
@@ -283,7 +367,14 @@ impl ParameterizedDecode<TaskprovVersion> for TaskConfig {
     ) -> Result<Self, CodecError> {
         Ok(TaskConfig {
             task_info: decode_u8_items(&(), bytes)?,
-            aggregator_endpoints: decode_u16_items(&(), bytes)?,
+            aggregator_endpoints: match decoding_parameter {
+                TaskprovVersion::Draft04 => {
+                    vec![UrlBytes::decode(bytes)?, UrlBytes::decode(bytes)?]
+                }
+                TaskprovVersion::Draft02 | TaskprovVersion::Unknown => {
+                    decode_u16_items(&(), bytes)?
+                }
+            },
             query_config: QueryConfig::decode_with_param(decoding_parameter, bytes)?,
             task_expiration: Time::decode(bytes)?,
             vdaf_config: VdafConfig::decode(bytes)?,
@@ -3,12 +3,112 @@
 
 //! Daphne metrics.
 
-use crate::DapError;
+use crate::{
+    messages::{ReportId, TaskId, Time, TransitionFailure},
+    DapDeadLetter, DapError,
+};
 use prometheus::{
-    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry, IntCounterVec,
-    IntGaugeVec, Registry,
+    exponential_buckets, register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry, HistogramVec,
+    IntCounterVec, IntGaugeVec, Registry, DEFAULT_BUCKETS,
+};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
 };
 
+/// Label of per-task metrics for tasks beyond the cardinality limit.
+pub const TASK_LABEL_OTHER: &str = "other";
+
+/// Bucket boundaries for the histograms in [`DaphneMetrics`].
+#[derive(Clone, Debug)]
+pub struct DaphneMetricsBuckets {
+    /// Duration of an aggregation job, in seconds.
+    pub agg_job_duration: Vec<f64>,
+
+    /// Latency of an inbound request, in seconds.
+    pub inbound_request_latency: Vec<f64>,
+
+    /// Number of reports in an aggregation job.
+    pub agg_job_batch_size: Vec<f64>,
+
+    /// Time a collection job waits to be run while processing the collection job queue, in
+    /// seconds.
+    pub collect_job_queue_wait: Vec<f64>,
+}
+
+impl Default for DaphneMetricsBuckets {
+    fn default() -> Self {
+        Self {
+            agg_job_duration: DEFAULT_BUCKETS.to_vec(),
+            inbound_request_latency: DEFAULT_BUCKETS.to_vec(),
+            agg_job_batch_size: exponential_buckets(1.0, 2.0, 16)
+                .expect("failed to construct default batch size buckets"),
+            collect_job_queue_wait: DEFAULT_BUCKETS.to_vec(),
+        }
+    }
+}
+
+/// Configuration of the `task_id` label of per-task metrics.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DaphneMetricsTaskLabelConfig {
+    /// If set, then the label is derived from the SHA-256 hash of the task ID rather than the task
+    /// ID itself, so that task IDs are not exposed to the metrics server.
+    #[serde(default)]
+    pub hash: bool,
+
+    /// Optional: Number of characters of the (hex-encoded hash or URL-safe base64 encoded) task ID
+    /// to keep. If not set, then the label is not truncated.
+    #[serde(default)]
+    pub max_len: Option<usize>,
+
+    /// Maximum number of distinct labels. Metrics for any further task are labeled with
+    /// [`TASK_LABEL_OTHER`].
+    pub max_cardinality: usize,
+}
+
+/// Assigns the `task_id` label of per-task metrics. The labels that have been assigned are
+/// remembered so that the number of distinct labels can be bounded; the labeler may be shared by
+/// several instances of [`DaphneMetrics`] in order to bound the labels across all of them.
+#[derive(Debug)]
+pub struct DaphneMetricsTaskLabeler {
+    config: DaphneMetricsTaskLabelConfig,
+    labels: Mutex<HashSet<String>>,
+}
+
+impl DaphneMetricsTaskLabeler {
+    pub fn new(config: DaphneMetricsTaskLabelConfig) -> Self {
+        Self {
+            config,
+            labels: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Return the label for the given task.
+    pub fn label(&self, task_id: &TaskId) -> String {
+        let mut label = if self.config.hash {
+            hex::encode(digest(&SHA256, task_id.as_ref()))
+        } else {
+            task_id.to_base64url()
+        };
+        if let Some(max_len) = self.config.max_len {
+            label.truncate(max_len);
+        }
+
+        let mut labels = self.labels.lock().expect("task labels: failed to lock");
+        if labels.contains(&label) {
+            label
+        } else if labels.len() < self.config.max_cardinality {
+            labels.insert(label.clone());
+            label
+        } else {
+            TASK_LABEL_OTHER.into()
+        }
+    }
+}
+
 pub struct DaphneMetrics {
     /// Inbound request metrics: Successful requests served, broken down by type.
     inbound_request_counter: IntCounterVec,
@@ -19,12 +119,83 @@ pub struct DaphneMetrics {
 
     /// Helper: Number of running aggregation jobs.
     aggregation_job_gauge: IntGaugeVec,
+
+    /// Helper: Number of aggregation jobs waiting to be initialized because the limit on running
+    /// aggregation jobs was reached.
+    aggregation_job_queue_gauge: IntGaugeVec,
+
+    /// Leader: Number of aggregation jobs abandoned after failing to initialize or continue.
+    agg_job_abandoned: IntCounterVec,
+
+    /// Leader: Number of times an aggregation job was retried after failing to initialize.
+    agg_job_retried: IntCounterVec,
+
+    /// Leader: Number of aggregation jobs deferred because the Helper asked to back off.
+    agg_job_deferred: IntCounterVec,
+
+    /// Leader: Number of aggregation jobs the Helper rejected as unauthorized.
+    helper_auth_failure: IntCounterVec,
+
+    /// Leader: Number of times the circuit breaker for a Helper was opened.
+    helper_circuit_breaker_opened: IntCounterVec,
+
+    /// Leader: Duration of aggregation jobs.
+    agg_job_duration: HistogramVec,
+
+    /// Inbound request metrics: Latency of successful requests, broken down by type.
+    inbound_request_latency: HistogramVec,
+
+    /// Leader: Number of reports in each aggregation job.
+    agg_job_batch_size: HistogramVec,
+
+    /// Leader: Time from the start of processing the collection job queue until a collection job
+    /// is run.
+    collect_job_queue_wait: HistogramVec,
+
+    /// Helper: Number of aggregate share requests rejected because the Leader's report count or
+    /// checksum for the batch did not match the Helper's, broken down by the mismatched field.
+    batch_mismatch_counter: IntCounterVec,
+
+    /// Helper: Number of operations on the aggregation-flow state stored between the
+    /// initialization and continuation of aggregation jobs, broken down by outcome.
+    helper_state_counter: IntCounterVec,
+
+    /// Leader: Number of collect jobs deleted at the request of the Collector, broken down by
+    /// whether the job was pending or completed.
+    collect_job_deleted_counter: IntCounterVec,
+
+    /// Inbound request metrics: Requests rejected because the body exceeds the size limit,
+    /// broken down by type.
+    inbound_request_too_large_counter: IntCounterVec,
+
+    /// Per-task report metrics, if task labels are enabled. Same as `report_counter`, but also
+    /// broken down by task.
+    task_report_counter: IntCounterVec,
+
+    /// Per-task aggregation job metrics, if task labels are enabled. Number of aggregation jobs
+    /// started, retried, abandoned, and completed, broken down by task.
+    task_agg_job_counter: IntCounterVec,
+
+    /// Assigns the label of per-task metrics. If not set, then per-task metrics are not recorded.
+    task_labeler: Option<Arc<DaphneMetricsTaskLabeler>>,
+
+    /// Number of reports counted for each task, broken down by status, since the tally was last
+    /// taken. If not set, then reports are not tallied.
+    report_tally: Option<Mutex<HashMap<TaskId, HashMap<String, u64>>>>,
+
+    /// Reports rejected for each task because they are invalid, since they were last taken. If
+    /// not set, then rejected reports are only counted.
+    dead_letters: Option<Mutex<HashMap<TaskId, Vec<DapDeadLetter>>>>,
 }
 
 impl DaphneMetrics {
     /// Register Daphne metrics with the specified registry. If a prefix is provided, then
-    /// "{prefix_}" is prepended to the name.
-    pub fn register(registry: &Registry, prefix: Option<&str>) -> Result<Self, DapError> {
+    /// "{prefix_}" is prepended to the name. The histograms use the given bucket boundaries.
+    pub fn register(
+        registry: &Registry,
+        prefix: Option<&str>,
+        buckets: &DaphneMetricsBuckets,
+    ) -> Result<Self, DapError> {
         let front = if let Some(prefix) = prefix {
             format!("{prefix}_")
         } else {
@@ -52,17 +223,197 @@ impl DaphneMetrics {
             registry
         )?;
 
+        let aggregation_job_queue_gauge = register_int_gauge_vec_with_registry!(
+            format!("{front}aggregation_job_queue_gauge"),
+            "Number of aggregation jobs waiting to be initialized.",
+            &["host"],
+            registry
+        )?;
+
+        let agg_job_abandoned = register_int_counter_vec_with_registry!(
+            format!("{front}agg_job_abandoned"),
+            "Total number of aggregation jobs abandoned by the Leader.",
+            &["host"],
+            registry
+        )?;
+
+        let agg_job_retried = register_int_counter_vec_with_registry!(
+            format!("{front}agg_job_retried"),
+            "Total number of times the Leader retried initializing an aggregation job.",
+            &["host"],
+            registry
+        )?;
+
+        let agg_job_deferred = register_int_counter_vec_with_registry!(
+            format!("{front}agg_job_deferred"),
+            "Total number of aggregation jobs deferred by the Leader because the Helper is overloaded.",
+            &["host"],
+            registry
+        )?;
+
+        let helper_auth_failure = register_int_counter_vec_with_registry!(
+            format!("{front}helper_auth_failure"),
+            "Total number of aggregation jobs the Helper rejected as unauthorized.",
+            &["host"],
+            registry
+        )?;
+
+        let helper_circuit_breaker_opened = register_int_counter_vec_with_registry!(
+            format!("{front}helper_circuit_breaker_opened"),
+            "Total number of times the Leader stopped sending aggregation jobs to a Helper that rejected them as unauthorized.",
+            &["host"],
+            registry
+        )?;
+
+        let agg_job_duration = register_histogram_vec_with_registry!(
+            format!("{front}agg_job_duration_seconds"),
+            "Duration of aggregation jobs run by the Leader.",
+            &["host"],
+            buckets.agg_job_duration.clone(),
+            registry
+        )?;
+
+        let inbound_request_latency = register_histogram_vec_with_registry!(
+            format!("{front}inbound_request_latency_seconds"),
+            "Latency of successful inbound requests.",
+            &["host", "type"],
+            buckets.inbound_request_latency.clone(),
+            registry
+        )?;
+
+        let agg_job_batch_size = register_histogram_vec_with_registry!(
+            format!("{front}agg_job_batch_size"),
+            "Number of reports in each aggregation job run by the Leader.",
+            &["host"],
+            buckets.agg_job_batch_size.clone(),
+            registry
+        )?;
+
+        let collect_job_queue_wait = register_histogram_vec_with_registry!(
+            format!("{front}collect_job_queue_wait_seconds"),
+            "Time collection jobs wait to be run while the Leader processes its collection job queue.",
+            &["host"],
+            buckets.collect_job_queue_wait.clone(),
+            registry
+        )?;
+
+        let batch_mismatch_counter = register_int_counter_vec_with_registry!(
+            format!("{front}batch_mismatch_counter"),
+            "Total number of aggregate share requests for which the Aggregators disagree on the batch.",
+            &["host", "reason"],
+            registry
+        )?;
+
+        let helper_state_counter = register_int_counter_vec_with_registry!(
+            format!("{front}helper_state_counter"),
+            "Total number of Helper aggregation-flow states stored, consumed, missing, or rejected.",
+            &["host", "status"],
+            registry
+        )?;
+
+        let collect_job_deleted_counter = register_int_counter_vec_with_registry!(
+            format!("{front}collect_job_deleted_counter"),
+            "Total number of collect jobs cancelled while pending or deleted once completed.",
+            &["host", "status"],
+            registry
+        )?;
+
+        let inbound_request_too_large_counter = register_int_counter_vec_with_registry!(
+            format!("{front}inbound_request_too_large_counter"),
+            "Total number of inbound requests rejected because the body is too large.",
+            &["host", "type"],
+            registry
+        )?;
+
+        let task_report_counter = register_int_counter_vec_with_registry!(
+            format!("{front}task_report_counter"),
+            "Total number reports rejected, aggregated, and collected per task.",
+            &["host", "task_id", "status"],
+            registry
+        )?;
+
+        let task_agg_job_counter = register_int_counter_vec_with_registry!(
+            format!("{front}task_agg_job_counter"),
+            "Total number of aggregation jobs started, retried, abandoned, and completed per task.",
+            &["host", "task_id", "status"],
+            registry
+        )?;
+
         Ok(Self {
             inbound_request_counter,
             report_counter,
             aggregation_job_gauge,
+            aggregation_job_queue_gauge,
+            agg_job_abandoned,
+            agg_job_retried,
+            agg_job_deferred,
+            helper_auth_failure,
+            helper_circuit_breaker_opened,
+            agg_job_duration,
+            inbound_request_latency,
+            agg_job_batch_size,
+            collect_job_queue_wait,
+            batch_mismatch_counter,
+            helper_state_counter,
+            collect_job_deleted_counter,
+            inbound_request_too_large_counter,
+            task_report_counter,
+            task_agg_job_counter,
+            task_labeler: None,
+            report_tally: None,
+            dead_letters: None,
         })
     }
 
+    /// Record per-task metrics, labeled by the given labeler. Per-task metrics are disabled by
+    /// default, since the number of tasks may be large.
+    pub fn with_task_labels(mut self, task_labeler: Arc<DaphneMetricsTaskLabeler>) -> Self {
+        self.task_labeler = Some(task_labeler);
+        self
+    }
+
+    /// Tally the reports counted for each task, regardless of whether per-task metrics are
+    /// enabled, so that the counts can be exported with [`Self::take_report_tally`].
+    pub fn with_report_tally(mut self) -> Self {
+        self.report_tally = Some(Mutex::new(HashMap::new()));
+        self
+    }
+
+    /// Return the number of reports counted for each task, broken down by status, and reset the
+    /// tally. Returns nothing if reports are not tallied.
+    pub fn take_report_tally(&self) -> HashMap<TaskId, HashMap<String, u64>> {
+        self.report_tally
+            .as_ref()
+            .map(|report_tally| {
+                std::mem::take(&mut *report_tally.lock().expect("report_tally: lock failed"))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Capture the reports that are rejected because they are invalid (see [`DapDeadLetter`]),
+    /// so that they can be exported with [`Self::take_dead_letters`].
+    pub fn with_dead_letters(mut self) -> Self {
+        self.dead_letters = Some(Mutex::new(HashMap::new()));
+        self
+    }
+
+    /// Return the reports rejected for each task because they are invalid, and reset them.
+    /// Returns nothing if rejected reports are not captured.
+    pub fn take_dead_letters(&self) -> HashMap<TaskId, Vec<DapDeadLetter>> {
+        self.dead_letters
+            .as_ref()
+            .map(|dead_letters| {
+                std::mem::take(&mut *dead_letters.lock().expect("dead_letters: lock failed"))
+            })
+            .unwrap_or_default()
+    }
+
     pub fn with_host<'req>(&'req self, host: &'req str) -> ContextualizedDaphneMetrics<'req> {
         ContextualizedDaphneMetrics {
             metrics: self,
             host,
+            task_label: None,
+            task_id: None,
         }
     }
 }
@@ -70,28 +421,122 @@ impl DaphneMetrics {
 pub struct ContextualizedDaphneMetrics<'req> {
     metrics: &'req DaphneMetrics,
     host: &'req str,
+
+    /// Label of per-task metrics, if enabled.
+    task_label: Option<String>,
+
+    /// Task to which reports are attributed in the tally and the dead letters, if enabled.
+    task_id: Option<TaskId>,
 }
 
 impl ContextualizedDaphneMetrics<'_> {
-    pub fn inbound_req_inc(&self, request_type: DaphneRequestType) {
-        let request_type_str = match request_type {
-            DaphneRequestType::HpkeConfig => "hpke_config",
-            DaphneRequestType::Upload => "upload",
-            DaphneRequestType::Aggregate => "aggregate",
-            DaphneRequestType::Collect => "collect",
-        };
+    /// Attribute subsequent report and aggregation job metrics to the given task. This has no
+    /// effect unless per-task metrics, the report tally, or dead letters are enabled.
+    pub fn with_task(mut self, task_id: &TaskId) -> Self {
+        self.task_label = self
+            .metrics
+            .task_labeler
+            .as_ref()
+            .map(|task_labeler| task_labeler.label(task_id));
+        if self.metrics.report_tally.is_some() || self.metrics.dead_letters.is_some() {
+            self.task_id = Some(task_id.clone());
+        }
+        self
+    }
+
+    fn task_agg_job_inc(&self, status: &str) {
+        if let Some(ref task_label) = self.task_label {
+            self.metrics
+                .task_agg_job_counter
+                .with_label_values(&[self.host, task_label, status])
+                .inc();
+        }
+    }
 
+    pub fn inbound_req_inc(&self, request_type: DaphneRequestType) {
         self.metrics
             .inbound_request_counter
-            .with_label_values(&[self.host, request_type_str])
+            .with_label_values(&[self.host, request_type.as_str()])
             .inc();
     }
 
+    pub fn inbound_req_too_large_inc(&self, request_type: DaphneRequestType) {
+        self.metrics
+            .inbound_request_too_large_counter
+            .with_label_values(&[self.host, request_type.as_str()])
+            .inc();
+    }
+
+    pub fn inbound_req_latency_observe(&self, request_type: DaphneRequestType, latency_ms: u64) {
+        self.metrics
+            .inbound_request_latency
+            .with_label_values(&[self.host, request_type.as_str()])
+            .observe(latency_ms as f64 / 1000.0);
+    }
+
+    pub fn agg_job_duration_observe(&self, duration_ms: u64) {
+        self.metrics
+            .agg_job_duration
+            .with_label_values(&[self.host])
+            .observe(duration_ms as f64 / 1000.0);
+        self.task_agg_job_inc("completed");
+    }
+
+    pub fn collect_job_queue_wait_observe(&self, wait_ms: u64) {
+        self.metrics
+            .collect_job_queue_wait
+            .with_label_values(&[self.host])
+            .observe(wait_ms as f64 / 1000.0);
+    }
+
+    pub fn agg_job_batch_size_observe(&self, report_count: usize) {
+        self.metrics
+            .agg_job_batch_size
+            .with_label_values(&[self.host])
+            .observe(report_count as f64);
+    }
+
     pub fn report_inc_by(&self, status: &str, val: u64) {
         self.metrics
             .report_counter
             .with_label_values(&[self.host, status])
             .inc_by(val);
+        if let Some(ref task_label) = self.task_label {
+            self.metrics
+                .task_report_counter
+                .with_label_values(&[self.host, task_label, status])
+                .inc_by(val);
+        }
+        if let (Some(report_tally), Some(task_id)) = (&self.metrics.report_tally, &self.task_id) {
+            *report_tally
+                .lock()
+                .expect("report_tally: lock failed")
+                .entry(task_id.clone())
+                .or_default()
+                .entry(status.to_string())
+                .or_default() += val;
+        }
+    }
+
+    /// Count a report rejected during aggregation. If the report is invalid and dead letters are
+    /// enabled, then it is also captured.
+    pub fn report_rejected(&self, report_id: &ReportId, time: Time, failure: TransitionFailure) {
+        self.report_inc_by(&format!("rejected_{failure}"), 1);
+        if !DapDeadLetter::is_dead_letter(failure) {
+            return;
+        }
+        if let (Some(dead_letters), Some(task_id)) = (&self.metrics.dead_letters, &self.task_id) {
+            dead_letters
+                .lock()
+                .expect("dead_letters: lock failed")
+                .entry(task_id.clone())
+                .or_default()
+                .push(DapDeadLetter {
+                    report_id: report_id.clone(),
+                    time,
+                    failure,
+                });
+        }
     }
 
     pub fn agg_job_inc(&self) {
@@ -99,6 +544,7 @@ impl ContextualizedDaphneMetrics<'_> {
             .aggregation_job_gauge
             .with_label_values(&[self.host])
             .inc();
+        self.task_agg_job_inc("started");
     }
 
     pub fn agg_job_dec(&self) {
@@ -107,6 +553,83 @@ impl ContextualizedDaphneMetrics<'_> {
             .with_label_values(&[self.host])
             .dec();
     }
+
+    pub fn agg_job_queue_inc(&self) {
+        self.metrics
+            .aggregation_job_queue_gauge
+            .with_label_values(&[self.host])
+            .inc();
+    }
+
+    pub fn agg_job_queue_dec(&self) {
+        self.metrics
+            .aggregation_job_queue_gauge
+            .with_label_values(&[self.host])
+            .dec();
+    }
+
+    pub fn agg_job_abandoned_inc(&self) {
+        self.metrics
+            .agg_job_abandoned
+            .with_label_values(&[self.host])
+            .inc();
+        self.task_agg_job_inc("abandoned");
+    }
+
+    pub fn agg_job_retried_inc(&self) {
+        self.metrics
+            .agg_job_retried
+            .with_label_values(&[self.host])
+            .inc();
+        self.task_agg_job_inc("retried");
+    }
+
+    pub fn agg_job_deferred_inc(&self) {
+        self.metrics
+            .agg_job_deferred
+            .with_label_values(&[self.host])
+            .inc();
+        self.task_agg_job_inc("deferred");
+    }
+
+    pub fn helper_auth_failure_inc(&self) {
+        self.metrics
+            .helper_auth_failure
+            .with_label_values(&[self.host])
+            .inc();
+    }
+
+    pub fn helper_circuit_breaker_opened_inc(&self) {
+        self.metrics
+            .helper_circuit_breaker_opened
+            .with_label_values(&[self.host])
+            .inc();
+    }
+
+    /// The reason is either "report_count" or "checksum".
+    pub fn batch_mismatch_inc(&self, reason: &str) {
+        self.metrics
+            .batch_mismatch_counter
+            .with_label_values(&[self.host, reason])
+            .inc();
+    }
+
+    /// The status is one of "stored", "consumed", "missing", or "rejected".
+    pub fn helper_state_inc(&self, status: &str) {
+        self.metrics
+            .helper_state_counter
+            .with_label_values(&[self.host, status])
+            .inc();
+    }
+
+    /// The status is either "cancelled" (the job was pending) or "deleted" (the job was
+    /// completed).
+    pub fn collect_job_deleted_inc(&self, status: &str) {
+        self.metrics
+            .collect_job_deleted_counter
+            .with_label_values(&[self.host, status])
+            .inc();
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -115,8 +638,22 @@ pub enum DaphneRequestType {
     HpkeConfig,
     /// DAP upload request.
     Upload,
+    /// Batched upload request. This is a Daphne extension to DAP.
+    UploadBatch,
     /// DAP aggregate request.
     Aggregate,
     /// DAP collect request.
     Collect,
 }
+
+impl DaphneRequestType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::HpkeConfig => "hpke_config",
+            Self::Upload => "upload",
+            Self::UploadBatch => "upload_batch",
+            Self::Aggregate => "aggregate",
+            Self::Collect => "collect",
+        }
+    }
+}
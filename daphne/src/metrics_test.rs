@@ -0,0 +1,192 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    assert_metrics_include, assert_metrics_include_auxiliary_function,
+    messages::{ReportId, TaskId, TransitionFailure},
+    metrics::{
+        DaphneMetrics, DaphneMetricsBuckets, DaphneMetricsTaskLabelConfig,
+        DaphneMetricsTaskLabeler, TASK_LABEL_OTHER,
+    },
+    DapDeadLetter,
+};
+use prometheus::Registry;
+use std::{collections::HashMap, sync::Arc};
+
+#[test]
+fn task_label() {
+    let task_id = TaskId([1; 32]);
+
+    let labeler = DaphneMetricsTaskLabeler::new(DaphneMetricsTaskLabelConfig {
+        hash: false,
+        max_len: None,
+        max_cardinality: 10,
+    });
+    assert_eq!(labeler.label(&task_id), task_id.to_base64url());
+
+    let labeler = DaphneMetricsTaskLabeler::new(DaphneMetricsTaskLabelConfig {
+        hash: false,
+        max_len: Some(8),
+        max_cardinality: 10,
+    });
+    assert_eq!(labeler.label(&task_id), task_id.to_base64url()[..8]);
+
+    let labeler = DaphneMetricsTaskLabeler::new(DaphneMetricsTaskLabelConfig {
+        hash: true,
+        max_len: Some(8),
+        max_cardinality: 10,
+    });
+    let label = labeler.label(&task_id);
+    assert_eq!(label.len(), 8);
+    assert!(!task_id.to_base64url().starts_with(&label));
+    assert!(!task_id.to_hex().starts_with(&label));
+}
+
+#[test]
+fn task_label_max_cardinality() {
+    let labeler = DaphneMetricsTaskLabeler::new(DaphneMetricsTaskLabelConfig {
+        hash: false,
+        max_len: None,
+        max_cardinality: 2,
+    });
+    let label_1 = labeler.label(&TaskId([1; 32]));
+    let label_2 = labeler.label(&TaskId([2; 32]));
+    assert_ne!(label_1, TASK_LABEL_OTHER);
+    assert_ne!(label_2, TASK_LABEL_OTHER);
+    assert_eq!(labeler.label(&TaskId([3; 32])), TASK_LABEL_OTHER);
+
+    // Tasks that were labeled before the limit was reached keep their label.
+    assert_eq!(labeler.label(&TaskId([1; 32])), label_1);
+}
+
+#[test]
+fn task_metrics() {
+    let registry = Registry::new();
+    let task_id = TaskId([1; 32]);
+    let labeler = Arc::new(DaphneMetricsTaskLabeler::new(
+        DaphneMetricsTaskLabelConfig {
+            hash: false,
+            max_len: Some(4),
+            max_cardinality: 10,
+        },
+    ));
+    let label = labeler.label(&task_id);
+    let metrics = DaphneMetrics::register(&registry, None, &DaphneMetricsBuckets::default())
+        .unwrap()
+        .with_task_labels(labeler);
+
+    let task_metrics = metrics.with_host("test").with_task(&task_id);
+    task_metrics.report_inc_by("aggregated", 3);
+    task_metrics.agg_job_retried_inc();
+    task_metrics.agg_job_duration_observe(1000);
+
+    // Metrics that are not attributed to a task are not broken down by task.
+    metrics.with_host("test").report_inc_by("aggregated", 1);
+
+    assert_metrics_include!(registry, {
+        r#"report_counter{host="test",status="aggregated"}"#: 4,
+        (format!(r#"task_report_counter{{host="test",status="aggregated",task_id="{label}"}}"#)): 3,
+        (format!(r#"task_agg_job_counter{{host="test",status="retried",task_id="{label}"}}"#)): 1,
+        (format!(r#"task_agg_job_counter{{host="test",status="completed",task_id="{label}"}}"#)): 1,
+    });
+}
+
+#[test]
+fn report_tally() {
+    let registry = Registry::new();
+    let task_id = TaskId([1; 32]);
+    let metrics = DaphneMetrics::register(&registry, None, &DaphneMetricsBuckets::default())
+        .unwrap()
+        .with_report_tally();
+
+    let task_metrics = metrics.with_host("test").with_task(&task_id);
+    task_metrics.report_inc_by("aggregated", 3);
+    task_metrics.report_inc_by("rejected_report_replayed", 1);
+    task_metrics.report_inc_by("aggregated", 2);
+
+    // Reports that are not attributed to a task are not tallied.
+    metrics.with_host("test").report_inc_by("aggregated", 1);
+
+    let tally = metrics.take_report_tally();
+    assert_eq!(tally.len(), 1);
+    assert_eq!(
+        tally[&task_id],
+        HashMap::from([
+            ("aggregated".to_string(), 5),
+            ("rejected_report_replayed".to_string(), 1),
+        ])
+    );
+
+    // Taking the tally resets it.
+    assert!(metrics.take_report_tally().is_empty());
+}
+
+#[test]
+fn dead_letters() {
+    let registry = Registry::new();
+    let task_id = TaskId([1; 32]);
+    let metrics = DaphneMetrics::register(&registry, None, &DaphneMetricsBuckets::default())
+        .unwrap()
+        .with_dead_letters();
+
+    let task_metrics = metrics.with_host("test").with_task(&task_id);
+    task_metrics.report_rejected(
+        &ReportId([1; 16]),
+        1000,
+        TransitionFailure::HpkeDecryptError,
+    );
+    task_metrics.report_rejected(&ReportId([2; 16]), 1001, TransitionFailure::VdafPrepError);
+
+    // Reports rejected because of the state of the task are only counted.
+    task_metrics.report_rejected(&ReportId([3; 16]), 1002, TransitionFailure::ReportReplayed);
+
+    // Reports that are not attributed to a task are not captured.
+    metrics.with_host("test").report_rejected(
+        &ReportId([4; 16]),
+        1003,
+        TransitionFailure::HpkeDecryptError,
+    );
+
+    assert_metrics_include!(registry, {
+        r#"report_counter{host="test",status="rejected_hpke_decrypt_error"}"#: 2,
+        r#"report_counter{host="test",status="rejected_vdaf_prep_error"}"#: 1,
+        r#"report_counter{host="test",status="rejected_report_replayed"}"#: 1,
+    });
+
+    let dead_letters = metrics.take_dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(
+        dead_letters[&task_id],
+        vec![
+            DapDeadLetter {
+                report_id: ReportId([1; 16]),
+                time: 1000,
+                failure: TransitionFailure::HpkeDecryptError,
+            },
+            DapDeadLetter {
+                report_id: ReportId([2; 16]),
+                time: 1001,
+                failure: TransitionFailure::VdafPrepError,
+            },
+        ]
+    );
+
+    // Taking the dead letters resets them.
+    assert!(metrics.take_dead_letters().is_empty());
+}
+
+#[test]
+fn task_metrics_disabled() {
+    let registry = Registry::new();
+    let metrics =
+        DaphneMetrics::register(&registry, None, &DaphneMetricsBuckets::default()).unwrap();
+    metrics
+        .with_host("test")
+        .with_task(&TaskId([1; 32]))
+        .report_inc_by("aggregated", 1);
+
+    let families = registry.gather();
+    assert!(families
+        .iter()
+        .all(|family| !family.get_name().starts_with("task_")));
+}
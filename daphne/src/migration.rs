@@ -0,0 +1,80 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Migration of tasks between DAP versions.
+//!
+//! A task is bound to the DAP version it was configured for. To upgrade a deployment in stages,
+//! each Aggregator re-registers the task for the new version with [`DapTaskConfig::migrate`]. The
+//! migrated task has a new ID, derived from the ID of the original task and the new version, so
+//! that the Leader and Helper agree on it without coordination. The Aggregators' endpoints for the
+//! new version are obtained by replacing the version at the end of the path of each URL, e.g.,
+//! "https://leader.com/v02/" becomes "https://leader.com/v04/". The original task is not changed;
+//! it may be kept until its remaining reports are collected.
+
+use crate::{messages::TaskId, DapTaskConfig, DapVersion};
+use ring::digest;
+use url::Url;
+
+/// Domain separation tag for deriving the ID of a migrated task.
+const MIGRATED_TASK_ID_DST: &[u8] = b"dap-task-migration";
+
+impl DapTaskConfig {
+    /// Re-register the task with the given ID, currently configured for `version_from`, for
+    /// `version_to`. Returns the ID and configuration of the migrated task, or the reason the task
+    /// cannot be migrated.
+    pub fn migrate(
+        &self,
+        task_id: &TaskId,
+        version_from: DapVersion,
+        version_to: DapVersion,
+    ) -> Result<(TaskId, Self), String> {
+        if self.version != version_from {
+            return Err(format!(
+                "task is configured for {}, not {}",
+                self.version.as_ref(),
+                version_from.as_ref()
+            ));
+        }
+        if version_to == DapVersion::Unknown {
+            return Err("unrecognized version".into());
+        }
+        if version_to == version_from {
+            return Err(format!(
+                "task is already configured for {}",
+                version_to.as_ref()
+            ));
+        }
+        if self.taskprov_advertisement.is_some() {
+            return Err("tasks provisioned by taskprov cannot be migrated".into());
+        }
+
+        let mut task_config = self.clone();
+        task_config.version = version_to;
+        task_config.leader_url = migrate_url(&self.leader_url, version_from, version_to)
+            .map_err(|e| format!("Leader URL: {e}"))?;
+        task_config.helper_url = migrate_url(&self.helper_url, version_from, version_to)
+            .map_err(|e| format!("Helper URL: {e}"))?;
+        Ok((migrated_task_id(task_id, version_to), task_config))
+    }
+}
+
+/// Derive the ID of the task migrated from the task with the given ID to `version_to`.
+pub fn migrated_task_id(task_id: &TaskId, version_to: DapVersion) -> TaskId {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(MIGRATED_TASK_ID_DST);
+    ctx.update(version_to.as_ref().as_bytes());
+    ctx.update(task_id.as_ref());
+    TaskId(ctx.finish().as_ref().try_into().unwrap())
+}
+
+/// Replace the version at the end of the path of an Aggregator's URL.
+fn migrate_url(url: &Url, version_from: DapVersion, version_to: DapVersion) -> Result<Url, String> {
+    let suffix = format!("/{}/", version_from.as_ref());
+    let prefix = url
+        .path()
+        .strip_suffix(&suffix)
+        .ok_or_else(|| format!("path does not end with \"{suffix}\""))?;
+    let mut migrated = url.clone();
+    migrated.set_path(&format!("{prefix}/{}/", version_to.as_ref()));
+    Ok(migrated)
+}
@@ -0,0 +1,103 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    hpke::HpkeReceiverConfig,
+    messages::{HpkeKemId, TaskId},
+    migration::migrated_task_id,
+    vdaf::VdafVerifyKey,
+    DapQueryConfig, DapTaskConfig, DapVersion, Prio3Config, VdafConfig,
+};
+
+fn draft02_task_config() -> DapTaskConfig {
+    DapTaskConfig {
+        version: DapVersion::Draft02,
+        leader_url: "https://leader.com/v02/".parse().unwrap(),
+        helper_url: "https://helper.com/dap/v02/".parse().unwrap(),
+        time_precision: 3600,
+        expiration: 1_700_000_000,
+        min_batch_size: 10,
+        max_batch_query_count: 1,
+        query: DapQueryConfig::TimeInterval,
+        vdaf: VdafConfig::Prio3(Prio3Config::Count),
+        vdaf_verify_key: VdafVerifyKey::Prio3([1; 16]),
+        collector_hpke_config: HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config,
+        additional_collectors: Vec::new(),
+        dp: Default::default(),
+        taskprov_advertisement: None,
+        replay_filter: None,
+        vdaf_verify_key_rotation: None,
+        upload_auth: None,
+    }
+}
+
+#[test]
+fn migrate() {
+    let task_id = TaskId([1; 32]);
+    let task_config = draft02_task_config();
+
+    let (new_task_id, new_task_config) = task_config
+        .migrate(&task_id, DapVersion::Draft02, DapVersion::Draft04)
+        .unwrap();
+    assert_eq!(new_task_config.version, DapVersion::Draft04);
+    assert_eq!(
+        new_task_config.leader_url.as_str(),
+        "https://leader.com/v04/"
+    );
+    assert_eq!(
+        new_task_config.helper_url.as_str(),
+        "https://helper.com/dap/v04/"
+    );
+    assert_eq!(
+        new_task_config.vdaf_verify_key.as_ref(),
+        task_config.vdaf_verify_key.as_ref()
+    );
+    assert_eq!(new_task_config.expiration, task_config.expiration);
+
+    // The new task ID is derived deterministically, so that both Aggregators agree on it, and
+    // depends on the old task ID and the new version.
+    assert_ne!(new_task_id, task_id);
+    assert_eq!(new_task_id, migrated_task_id(&task_id, DapVersion::Draft04));
+    assert_ne!(
+        new_task_id,
+        migrated_task_id(&TaskId([2; 32]), DapVersion::Draft04)
+    );
+    assert_ne!(new_task_id, migrated_task_id(&task_id, DapVersion::Draft05));
+}
+
+#[test]
+fn migrate_invalid() {
+    let task_id = TaskId([1; 32]);
+    let task_config = draft02_task_config();
+
+    // Task is not configured for the version migrated from.
+    assert!(task_config
+        .migrate(&task_id, DapVersion::Draft04, DapVersion::Draft05)
+        .is_err());
+
+    // Task is already configured for the version migrated to.
+    assert!(task_config
+        .migrate(&task_id, DapVersion::Draft02, DapVersion::Draft02)
+        .is_err());
+
+    // Unrecognized version.
+    assert!(task_config
+        .migrate(&task_id, DapVersion::Draft02, DapVersion::Unknown)
+        .is_err());
+
+    // URL path does not end with the version.
+    let mut bad_task_config = task_config.clone();
+    bad_task_config.leader_url = "https://leader.com/".parse().unwrap();
+    assert!(bad_task_config
+        .migrate(&task_id, DapVersion::Draft02, DapVersion::Draft04)
+        .is_err());
+
+    // Task provisioned by taskprov.
+    let mut bad_task_config = task_config;
+    bad_task_config.taskprov_advertisement = Some("advertisement".into());
+    assert!(bad_task_config
+        .migrate(&task_id, DapVersion::Draft02, DapVersion::Draft04)
+        .is_err());
+}
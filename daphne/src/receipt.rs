@@ -0,0 +1,253 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Integrity receipts for collection results and uploaded reports.
+//!
+//! A collection receipt is a signature by the deployment's Ed25519 key over the task ID, the batch
+//! selector of the collection job, and the interval, report count, and a digest of the encrypted
+//! aggregate shares of the resulting [`Collection`]. It
+//! provides non-repudiable evidence of what was released to the Collector. Likewise, an upload
+//! receipt is a signature over the task ID, report ID, and timestamp of a [`Report`] accepted by
+//! the Leader, with which the Client can prove that it submitted the report. Receipts are not
+//! defined by the DAP standard.
+//!
+//! The same kind of key is used by an Aggregator to sign the HPKE configs it advertises (see
+//! [`sign_hpke_configs`]). A Client that knows the Aggregator's public key can then detect a
+//! config substituted by a machine-in-the-middle before encrypting a report to it.
+
+use crate::{
+    messages::{
+        BatchSelector, Collection, Interval, PartialBatchSelector, Report, ReportId,
+        ReportMetadata, TaskId, Time,
+    },
+    DapError, DapVersion,
+};
+use prio::codec::Encode;
+use ring::{
+    digest::{digest, SHA256},
+    signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519},
+};
+use serde::{Deserialize, Serialize};
+
+const CTX_COLLECTION_RECEIPT: &[u8] = b"daphne collection receipt";
+const CTX_UPLOAD_RECEIPT: &[u8] = b"daphne upload receipt";
+const CTX_HPKE_CONFIGS: &[u8] = b"daphne hpke configs";
+
+/// A signed receipt for a [`Collection`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapCollectionReceipt {
+    pub version: DapVersion,
+    pub task_id: TaskId,
+
+    /// The batch selector resolved for the collection job, i.e., the batch interval or the ID
+    /// of the batch that was collected.
+    pub batch_sel: BatchSelector,
+    pub interval: Option<Interval>,
+    pub report_count: u64,
+
+    /// SHA-256 digest of the encrypted aggregate shares.
+    #[serde(with = "hex")]
+    pub agg_shares_digest: Vec<u8>,
+
+    /// Ed25519 signature over the above fields.
+    #[serde(with = "hex")]
+    pub signature: Vec<u8>,
+}
+
+impl DapCollectionReceipt {
+    /// Produce a receipt for the collection of the batch with the given selector.
+    pub fn sign(
+        signing_key: &DapReceiptSigningKey,
+        version: DapVersion,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+    ) -> Self {
+        let mut receipt = Self {
+            version,
+            task_id: task_id.clone(),
+            batch_sel: batch_sel.clone(),
+            interval: collection.interval.clone(),
+            report_count: collection.report_count,
+            agg_shares_digest: agg_shares_digest(collection),
+            signature: Vec::new(),
+        };
+        receipt.signature = signing_key
+            .key_pair
+            .sign(&receipt.signed_data())
+            .as_ref()
+            .to_vec();
+        receipt
+    }
+
+    /// Verify the receipt against the batch selector of the collection job, the collection it
+    /// was issued for, and the deployment's public key.
+    pub fn verify(
+        &self,
+        public_key: &[u8],
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+    ) -> Result<(), DapError> {
+        if &self.task_id != task_id
+            || &self.batch_sel != batch_sel
+            || PartialBatchSelector::from(batch_sel.clone()) != collection.part_batch_sel
+            || self.interval != collection.interval
+            || self.report_count != collection.report_count
+            || self.agg_shares_digest != agg_shares_digest(collection)
+        {
+            return Err(DapError::fatal("receipt does not match collection"));
+        }
+
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(&self.signed_data(), &self.signature)
+            .map_err(|_| DapError::fatal("invalid receipt signature"))
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(CTX_COLLECTION_RECEIPT);
+        data.extend_from_slice(self.version.as_ref().as_bytes());
+        self.task_id.encode(&mut data);
+        self.batch_sel.encode(&mut data);
+        match self.interval {
+            Some(ref interval) => {
+                1_u8.encode(&mut data);
+                interval.encode(&mut data);
+            }
+            None => 0_u8.encode(&mut data),
+        }
+        self.report_count.encode(&mut data);
+        data.extend_from_slice(&self.agg_shares_digest);
+        data
+    }
+}
+
+/// A signed receipt for a [`Report`] accepted by the Leader.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DapUploadReceipt {
+    pub version: DapVersion,
+    pub task_id: TaskId,
+    pub report_id: ReportId,
+    pub time: Time,
+
+    /// Ed25519 signature over the above fields.
+    #[serde(with = "hex")]
+    pub signature: Vec<u8>,
+}
+
+impl DapUploadReceipt {
+    /// Produce a receipt for the upload of a report with the given metadata.
+    pub fn sign(
+        signing_key: &DapReceiptSigningKey,
+        version: DapVersion,
+        task_id: &TaskId,
+        metadata: &ReportMetadata,
+    ) -> Self {
+        let mut receipt = Self {
+            version,
+            task_id: task_id.clone(),
+            report_id: metadata.id.clone(),
+            time: metadata.time,
+            signature: Vec::new(),
+        };
+        receipt.signature = signing_key
+            .key_pair
+            .sign(&receipt.signed_data())
+            .as_ref()
+            .to_vec();
+        receipt
+    }
+
+    /// Verify the receipt against the report it was issued for and the deployment's public key.
+    pub fn verify(
+        &self,
+        public_key: &[u8],
+        task_id: &TaskId,
+        report: &Report,
+    ) -> Result<(), DapError> {
+        if &self.task_id != task_id
+            || self.report_id != report.report_metadata.id
+            || self.time != report.report_metadata.time
+        {
+            return Err(DapError::fatal("receipt does not match report"));
+        }
+
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(&self.signed_data(), &self.signature)
+            .map_err(|_| DapError::fatal("invalid receipt signature"))
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(CTX_UPLOAD_RECEIPT);
+        data.extend_from_slice(self.version.as_ref().as_bytes());
+        self.task_id.encode(&mut data);
+        self.report_id.encode(&mut data);
+        self.time.encode(&mut data);
+        data
+    }
+}
+
+/// Sign the HPKE configs advertised by an Aggregator. `payload` is the body of the response to
+/// `GET /hpke_config`, i.e., the encoded `HpkeConfigList` (or `HpkeConfig` in draft02).
+pub fn sign_hpke_configs(
+    signing_key: &DapReceiptSigningKey,
+    version: DapVersion,
+    payload: &[u8],
+) -> Vec<u8> {
+    signing_key
+        .key_pair
+        .sign(&hpke_configs_signed_data(version, payload))
+        .as_ref()
+        .to_vec()
+}
+
+/// Verify the signature over the HPKE configs advertised by an Aggregator against the
+/// Aggregator's public key.
+pub fn verify_hpke_configs(
+    public_key: &[u8],
+    version: DapVersion,
+    payload: &[u8],
+    signature: &[u8],
+) -> Result<(), DapError> {
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&hpke_configs_signed_data(version, payload), signature)
+        .map_err(|_| DapError::fatal("invalid HPKE config signature"))
+}
+
+fn hpke_configs_signed_data(version: DapVersion, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(CTX_HPKE_CONFIGS);
+    data.extend_from_slice(version.as_ref().as_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+fn agg_shares_digest(collection: &Collection) -> Vec<u8> {
+    let mut data = Vec::new();
+    for encrypted_agg_share in collection.encrypted_agg_shares.iter() {
+        encrypted_agg_share.encode(&mut data);
+    }
+    digest(&SHA256, &data).as_ref().to_vec()
+}
+
+/// The deployment's Ed25519 key used to sign receipts or HPKE configs.
+pub struct DapReceiptSigningKey {
+    key_pair: Ed25519KeyPair,
+}
+
+impl DapReceiptSigningKey {
+    /// Construct the signing key from a 32-byte seed.
+    pub fn from_seed(seed: &[u8]) -> Result<Self, DapError> {
+        Ok(Self {
+            key_pair: Ed25519KeyPair::from_seed_unchecked(seed)
+                .map_err(|e| DapError::Fatal(format!("invalid receipt signing key: {e}")))?,
+        })
+    }
+
+    /// Return the public key used to verify receipts.
+    pub fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    messages::{
+        BatchSelector, Collection, HpkeCiphertext, Interval, PartialBatchSelector, Report,
+        ReportId, ReportMetadata, TaskId,
+    },
+    receipt::{DapCollectionReceipt, DapReceiptSigningKey, DapUploadReceipt},
+    DapVersion,
+};
+use rand::prelude::*;
+
+fn batch_sel() -> BatchSelector {
+    BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 1637359200,
+            duration: 7200,
+        },
+    }
+}
+
+fn collection() -> Collection {
+    Collection {
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_count: 23,
+        interval: Some(Interval {
+            start: 1637361337,
+            duration: 3600,
+        }),
+        encrypted_agg_shares: vec![
+            HpkeCiphertext {
+                config_id: 1,
+                enc: b"leader encapsulated key".to_vec(),
+                payload: b"leader ciphertext".to_vec(),
+            },
+            HpkeCiphertext {
+                config_id: 2,
+                enc: b"helper encapsulated key".to_vec(),
+                payload: b"helper ciphertext".to_vec(),
+            },
+        ],
+    }
+}
+
+#[test]
+fn sign_and_verify() {
+    let mut rng = thread_rng();
+    let signing_key = DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap();
+    let task_id = TaskId(rng.gen());
+    let batch_sel = batch_sel();
+    let collection = collection();
+
+    let receipt = DapCollectionReceipt::sign(
+        &signing_key,
+        DapVersion::Draft04,
+        &task_id,
+        &batch_sel,
+        &collection,
+    );
+    receipt
+        .verify(signing_key.public_key(), &task_id, &batch_sel, &collection)
+        .unwrap();
+
+    // Receipt survives JSON serialization.
+    let receipt: DapCollectionReceipt =
+        serde_json::from_str(&serde_json::to_string(&receipt).unwrap()).unwrap();
+    receipt
+        .verify(signing_key.public_key(), &task_id, &batch_sel, &collection)
+        .unwrap();
+}
+
+#[test]
+fn verify_fails_on_mismatch() {
+    let mut rng = thread_rng();
+    let signing_key = DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap();
+    let task_id = TaskId(rng.gen());
+    let batch_sel = batch_sel();
+    let collection = collection();
+    let receipt = DapCollectionReceipt::sign(
+        &signing_key,
+        DapVersion::Draft04,
+        &task_id,
+        &batch_sel,
+        &collection,
+    );
+
+    // Wrong task.
+    assert!(receipt
+        .verify(
+            signing_key.public_key(),
+            &TaskId(rng.gen()),
+            &batch_sel,
+            &collection
+        )
+        .is_err());
+
+    // Different batch interval.
+    let other_batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 1637359200,
+            duration: 3600,
+        },
+    };
+    assert!(receipt
+        .verify(
+            signing_key.public_key(),
+            &task_id,
+            &other_batch_sel,
+            &collection
+        )
+        .is_err());
+    let mut tampered = receipt.clone();
+    tampered.batch_sel = other_batch_sel.clone();
+    assert!(tampered
+        .verify(
+            signing_key.public_key(),
+            &task_id,
+            &other_batch_sel,
+            &collection
+        )
+        .is_err());
+
+    // Different report count.
+    let mut other = collection.clone();
+    other.report_count += 1;
+    assert!(receipt
+        .verify(signing_key.public_key(), &task_id, &batch_sel, &other)
+        .is_err());
+
+    // Tampered aggregate share.
+    let mut other = collection.clone();
+    other.encrypted_agg_shares[1].payload[0] ^= 1;
+    assert!(receipt
+        .verify(signing_key.public_key(), &task_id, &batch_sel, &other)
+        .is_err());
+
+    // Wrong public key.
+    let other_key = DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap();
+    assert!(receipt
+        .verify(other_key.public_key(), &task_id, &batch_sel, &collection)
+        .is_err());
+
+    // Tampered signature.
+    let mut tampered = receipt;
+    tampered.signature[0] ^= 1;
+    assert!(tampered
+        .verify(signing_key.public_key(), &task_id, &batch_sel, &collection)
+        .is_err());
+}
+
+fn report() -> Report {
+    Report {
+        draft02_task_id: None,
+        report_metadata: ReportMetadata {
+            id: ReportId(thread_rng().gen()),
+            time: 1637361337,
+            extensions: Vec::new(),
+        },
+        public_share: b"public share".to_vec(),
+        encrypted_input_shares: Vec::new(),
+    }
+}
+
+#[test]
+fn upload_receipt_sign_and_verify() {
+    let mut rng = thread_rng();
+    let signing_key = DapReceiptSigningKey::from_seed(&rng.gen::<[u8; 32]>()).unwrap();
+    let task_id = TaskId(rng.gen());
+    let report = report();
+
+    let receipt = DapUploadReceipt::sign(
+        &signing_key,
+        DapVersion::Draft04,
+        &task_id,
+        &report.report_metadata,
+    );
+    let receipt: DapUploadReceipt =
+        serde_json::from_str(&serde_json::to_string(&receipt).unwrap()).unwrap();
+    receipt
+        .verify(signing_key.public_key(), &task_id, &report)
+        .unwrap();
+
+    // Wrong task.
+    assert!(receipt
+        .verify(signing_key.public_key(), &TaskId(rng.gen()), &report)
+        .is_err());
+
+    // Different report.
+    let mut other = report.clone();
+    other.report_metadata.time += 1;
+    assert!(receipt
+        .verify(signing_key.public_key(), &task_id, &other)
+        .is_err());
+    assert!(receipt
+        .verify(signing_key.public_key(), &task_id, &self::report())
+        .is_err());
+
+    // Tampered signature.
+    let mut tampered = receipt;
+    tampered.signature[0] ^= 1;
+    assert!(tampered
+        .verify(signing_key.public_key(), &task_id, &report)
+        .is_err());
+}
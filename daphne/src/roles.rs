@@ -4,56 +4,50 @@
 //! Trait definitions for Daphne backends.
 
 use crate::{
+    aborts::DapAbortType,
+    auth::DapSenderAuth,
+    clock::Clock,
     constants::DapMediaType,
+    events::{DapBatchEvent, DapEventSink},
+    extensions::DapExtensionRegistry,
     hpke::HpkeDecrypter,
     messages::{
         constant_time_eq, decode_base64url, AggregateShare, AggregateShareReq,
         AggregationJobContinueReq, AggregationJobInitReq, AggregationJobResp, BatchId,
-        BatchSelector, Collection, CollectionJobId, CollectionReq, HpkeConfigList, Interval,
-        PartialBatchSelector, Query, Report, ReportId, ReportMetadata, TaskId, Time,
-        TransitionFailure, TransitionVar,
+        BatchSelector, Collection, CollectionJobId, CollectionReq, Extension, HpkeConfig,
+        HpkeConfigList, Interval, PartialBatchSelector, Query, Report, ReportBatch, ReportId,
+        ReportMetadata, TaskId, Time, TransitionFailure, TransitionVar,
     },
-    metrics::{DaphneMetrics, DaphneRequestType},
-    DapAbort, DapAggregateShare, DapCollectJob, DapError, DapGlobalConfig, DapHelperState,
-    DapHelperTransition, DapLeaderProcessTelemetry, DapLeaderTransition, DapOutputShare,
-    DapQueryConfig, DapRequest, DapResource, DapResponse, DapTaskConfig, DapVersion,
-    MetaAggregationJobId,
+    metrics::{ContextualizedDaphneMetrics, DaphneMetrics, DaphneRequestType},
+    scheduler::DapCollectJobScheduler,
+    taskprov::resolve_taskprov_version,
+    vdaf::report_id_checksum,
+    CollectionJobStatus, DapAbort, DapAggregateShare, DapBatchSuggestion, DapBatchSuggestions,
+    DapBucketReportCount, DapCollectJob, DapDryRunReportStatus, DapError, DapGlobalConfig,
+    DapHelperState, DapHelperTransition, DapLeaderProcessTelemetry, DapLeaderTransition,
+    DapOutputShare, DapPendingCollectJob, DapQueryConfig, DapReportCountBreakdown, DapReportSample,
+    DapReportUploadStatus, DapRequest, DapResource, DapResponse, DapTaskConfig, DapUploadRecord,
+    DapVersion, MetaAggregationJobId,
 };
 use async_trait::async_trait;
+use futures::future::try_join_all;
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
+use rand::prelude::*;
+use ring::digest::{digest, SHA256};
 use std::borrow::Cow;
-use std::collections::HashMap;
-use tracing::{debug, error};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, error, info, instrument, warn, Span};
 use url::Url;
 
-/// A party in the DAP protocol who is authorized to send requests to another party.
-#[async_trait(?Send)]
-pub trait DapAuthorizedSender<S> {
-    /// Add authorization to an outbound DAP request with the given task ID, media type, and payload.
-    async fn authorize(
-        &self,
-        task_id: &TaskId,
-        media_type: &DapMediaType,
-        payload: &[u8],
-    ) -> Result<S, DapError>;
-}
-
 /// DAP Aggregator functionality.
 #[async_trait(?Send)]
-pub trait DapAggregator<'srv, 'req, S>: HpkeDecrypter<'srv> + Sized
+pub trait DapAggregator<'srv, 'req, S>: HpkeDecrypter<'srv> + DapSenderAuth<S> + Sized
 where
     'srv: 'req,
 {
     /// A refernce to a task configuration stored by the Aggregator.
     type WrappedDapTaskConfig: AsRef<DapTaskConfig>;
 
-    /// Decide whether the given DAP request is authorized.
-    ///
-    /// If the return value is `None`, then the request is authorized. If the return value is
-    /// `Some(reason)`, then the request is denied and `reason` conveys details about how the
-    /// decision was reached.
-    async fn unauthorized_reason(&self, req: &DapRequest<S>) -> Result<Option<String>, DapError>;
-
     /// Look up the DAP global configuration.
     fn get_global_config(&self) -> &DapGlobalConfig;
 
@@ -70,7 +64,8 @@ where
     /// Look up the DAP task configuration for the given task ID.
     ///
     /// If a `report` has been provided, then look for the draft-wang-ppm-dap-taskprov-<nn> extension
-    /// in the report.  If a taskprov task configuration is successfully read from the report,
+    /// in the report. In draft04, the task configuration is read from `taskprov_advertisement`, the
+    /// value of the "dap-taskprov" header of the request.  If a taskprov task configuration is successfully read from the report,
     /// [`DapAggregator::taskprov_opt_in_decision`] will be called, and if it returns Ok(true) the server will opt-in to the task.
     /// if it returns Ok(false) or an error then the server will opt-out or return an appropriate error.
     ///
@@ -80,6 +75,7 @@ where
         &'srv self,
         version: DapVersion,
         task_id: Cow<'req, TaskId>,
+        taskprov_advertisement: Option<&str>,
         report: Option<&ReportMetadata>,
     ) -> Result<Option<Self::WrappedDapTaskConfig>, DapError>;
 
@@ -90,32 +86,77 @@ where
     ) -> Result<Option<Self::WrappedDapTaskConfig>, DapError> {
         // We use DapVersion::Unknown here as we don't know it and we don't need to
         // know it as we will not be doing any taskprov task creation.
-        self.get_task_config_considering_taskprov(DapVersion::Unknown, task_id, None)
+        self.get_task_config_considering_taskprov(DapVersion::Unknown, task_id, None, None)
             .await
     }
 
+    /// The source of the current time. Tests may inject a [`MockClock`](crate::clock::MockClock)
+    /// in order to control time.
+    fn clock(&self) -> &dyn Clock;
+
     /// Get the current time (number of seconds since the beginning of UNIX time).
-    fn get_current_time(&self) -> Time;
+    fn get_current_time(&self) -> Time {
+        self.clock().now()
+    }
+
+    /// Get the current time in milliseconds since the beginning of UNIX time. This is used to
+    /// measure latency for metrics.
+    fn get_current_time_millis(&self) -> u64 {
+        self.clock().now_millis()
+    }
 
-    /// Check whether the batch determined by the collect request would overlap with a previous
-    /// batch.
+    /// Check whether the batch determined by the collect request would overlap with a batch
+    /// previously collected by the given Collector. `collector_id` is `None` for the task's
+    /// primary Collector.
     async fn is_batch_overlapping(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
     ) -> Result<bool, DapError>;
 
+    /// Get the number of times the given Collector (`None` for the task's primary Collector) has
+    /// queried the batch, i.e., the largest number of times any bucket of the batch has been
+    /// marked as collected by the Collector (see [`Self::mark_collected`]). The default
+    /// implementation only tells whether the batch overlaps with a collected batch (see
+    /// [`Self::is_batch_overlapping`]), which is sufficient if each batch is queried once.
+    async fn get_batch_query_count(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+    ) -> Result<u64, DapError> {
+        Ok(self
+            .is_batch_overlapping(task_id, batch_sel, collector_id)
+            .await?
+            .into())
+    }
+
     /// Check whether the given batch ID has been observed before. This is called by the Leader
     /// (resp. Helper) in response to a CollectReq (resp. AggregateShareReq) for fixed-size tasks.
     async fn batch_exists(&self, task_id: &TaskId, batch_id: &BatchId) -> Result<bool, DapError>;
 
-    /// Store a set of output shares.
+    /// Mark the given fixed-size batch as forced, i.e., collectable even if it contains fewer
+    /// reports than the task's minimum batch size. Each Aggregator checks its own record, so the
+    /// batch must be marked by both the Leader and the Helper before it can be collected.
+    async fn mark_batch_forced(&self, task_id: &TaskId, batch_id: &BatchId)
+        -> Result<(), DapError>;
+
+    /// Check whether the given batch has been marked as forced (see [`Self::mark_batch_forced`]).
+    async fn is_batch_forced(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, DapError>;
+
+    /// Store a set of output shares. Returns the lifecycle events of the buckets into which the
+    /// output shares were merged (see [`DapBatchEvent::for_merge`]).
     async fn put_out_shares(
         &self,
         task_id: &TaskId,
         part_batch_sel: &PartialBatchSelector,
         out_shares: Vec<DapOutputShare>,
-    ) -> Result<(), DapError>;
+    ) -> Result<Vec<DapBatchEvent>, DapError>;
 
     /// Fetch the aggregate share for the given batch.
     async fn get_agg_share(
@@ -134,13 +175,61 @@ where
         report_meta: impl Iterator<Item = &'b ReportMetadata>,
     ) -> Result<HashMap<ReportId, TransitionFailure>, DapError>;
 
-    /// Mark a batch as collected.
+    /// Like [`Self::check_early_reject`], except that no state is changed, i.e., the reports are
+    /// not marked as processed. This is used by [`Self::dry_run_aggregation`].
+    async fn peek_early_reject<'b>(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &'b PartialBatchSelector,
+        report_meta: impl Iterator<Item = &'b ReportMetadata>,
+    ) -> Result<HashMap<ReportId, TransitionFailure>, DapError>;
+
+    /// Mark a batch as collected by the given Collector. `collector_id` is `None` for the task's
+    /// primary Collector. Each call counts as one query of the batch by the Collector (see
+    /// [`Self::get_batch_query_count`]). Once a batch is collected by any Collector, no more
+    /// reports may be aggregated into it.
     async fn mark_collected(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+    ) -> Result<(), DapError>;
+
+    /// Get the aggregate share this Aggregator produced when the given Collector (`None` for the
+    /// task's primary Collector) first queried the batch, as stored by
+    /// [`Self::put_collected_agg_share`]. This includes the noise added for differential privacy,
+    /// if any.
+    async fn get_collected_agg_share(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+    ) -> Result<Option<DapAggregateShare>, DapError>;
+
+    /// Store the aggregate share produced for the given Collector's query of the batch. Repeated
+    /// queries of the same batch are answered with this aggregate share so that the Collector
+    /// cannot average away the noise added for differential privacy. If an aggregate share is
+    /// already stored for the batch, then it is left as is.
+    async fn put_collected_agg_share(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+        agg_share: &DapAggregateShare,
     ) -> Result<(), DapError>;
 
+    /// Rotate the HPKE receiver configs for the given DAP version according to
+    /// [`DapGlobalConfig::hpke_rotation`]: generate a new config if one is due and delete configs
+    /// whose grace period has elapsed. Returns the config that was generated, if any.
+    ///
+    /// The default implementation does not rotate configs.
+    async fn rotate_hpke_config(
+        &self,
+        _version: DapVersion,
+    ) -> Result<Option<HpkeConfig>, DapError> {
+        Ok(None)
+    }
+
     /// Handle HTTP GET to `/hpke_config?task_id=<task_id>`.
     async fn http_get_hpke_config(
         &'srv self,
@@ -151,6 +240,7 @@ where
             return Err(DapAbort::version_unknown());
         }
 
+        let start = self.get_current_time_millis();
         let metrics = self.metrics().with_host(req.host());
 
         // Parse the task ID from the query string, ensuring that it is the only query parameter.
@@ -167,7 +257,24 @@ where
             id = Some(TaskId(bytes))
         }
 
-        let hpke_config = self.get_hpke_config_for(req.version, id.as_ref()).await?;
+        let payload = match req.version {
+            DapVersion::Draft02 => self
+                .get_hpke_config_for(req.version, id.as_ref())
+                .await?
+                .as_ref()
+                .get_encoded(),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                let hpke_config_list = HpkeConfigList {
+                    hpke_configs: self
+                        .get_hpke_config_list_for(req.version, id.as_ref())
+                        .await?,
+                };
+                hpke_config_list.get_encoded()
+            }
+            // This is just to keep the compiler happy as we excluded DapVersion::Unknown by
+            // aborting at the top of the function.
+            _ => unreachable!("unhandled version {:?}", req.version),
+        };
 
         if let Some(task_id) = id {
             let task_config = self
@@ -184,20 +291,11 @@ where
             }
         }
 
-        let payload = match req.version {
-            DapVersion::Draft02 => hpke_config.as_ref().get_encoded(),
-            DapVersion::Draft04 => {
-                let hpke_config_list = HpkeConfigList {
-                    hpke_configs: vec![hpke_config.as_ref().clone()],
-                };
-                hpke_config_list.get_encoded()
-            }
-            // This is just to keep the compiler happy as we excluded DapVersion::Unknown by
-            // aborting at the top of the function.
-            _ => unreachable!("unhandled version {:?}", req.version),
-        };
-
         metrics.inbound_req_inc(DaphneRequestType::HpkeConfig);
+        metrics.inbound_req_latency_observe(
+            DaphneRequestType::HpkeConfig,
+            self.get_current_time_millis().saturating_sub(start),
+        );
         Ok(DapResponse {
             version: req.version,
             media_type: DapMediaType::HpkeConfigList,
@@ -209,6 +307,124 @@ where
 
     /// Access the Prometheus metrics.
     fn metrics(&self) -> &DaphneMetrics;
+
+    /// Access the handlers for report extensions.
+    fn extension_registry(&self) -> &DapExtensionRegistry;
+
+    /// Access the destination of batch lifecycle events.
+    fn event_sink(&self) -> &dyn DapEventSink;
+
+    /// Emit a sequence of batch lifecycle events. Events that can't be emitted are logged and
+    /// dropped.
+    async fn emit_batch_events(&self, events: Vec<DapBatchEvent>) {
+        for event in events {
+            if let Err(e) = self.event_sink().emit(&event).await {
+                warn!("failed to emit batch event {event:?}: {e}");
+            }
+        }
+    }
+
+    /// Wait for the given duration, e.g., before retrying a request.
+    async fn sleep(&self, duration: std::time::Duration);
+
+    /// Process a batch of reports as this Aggregator (the Leader if `is_leader` is set, otherwise
+    /// the Helper) would when aggregating them, without changing any state: no report is marked as
+    /// processed and no aggregate share is updated. This is meant for debugging the configuration
+    /// of a task. The outcome for each report is returned in the order in which the reports appear
+    /// in the batch.
+    ///
+    /// Each report is checked against storage and the time bounds (see
+    /// [`Self::peek_early_reject`]), then this Aggregator's input share is decrypted, the
+    /// extensions are checked, and VDAF preparation is initialized. Preparation can't be
+    /// completed without the peer Aggregator, so a report that is accepted here may still be
+    /// rejected by the peer.
+    async fn dry_run_aggregation(
+        &'srv self,
+        task_id: &'req TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        is_leader: bool,
+        report_batch: &ReportBatch,
+    ) -> Result<Vec<DapDryRunReportStatus>, DapError> {
+        let task_config = self
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await?
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
+        let task_config = task_config.as_ref();
+
+        // Decode the reports. Reports that can't be decoded or that repeat the ID of a previous
+        // report are rejected right away.
+        let mut statuses = Vec::with_capacity(report_batch.encoded_reports.len());
+        let mut reports = Vec::with_capacity(report_batch.encoded_reports.len());
+        let mut report_ids = HashSet::with_capacity(report_batch.encoded_reports.len());
+        for encoded_report in report_batch.encoded_reports.iter() {
+            let (report_id, failure, detail) =
+                match Report::get_decoded_with_param(&task_config.version, encoded_report) {
+                    Ok(report) if report.encrypted_input_shares.len() != 2 => (
+                        Some(report.report_metadata.id.to_base64url()),
+                        Some(TransitionFailure::UnrecognizedMessage),
+                        Some("report does not have exactly two input shares".into()),
+                    ),
+                    Ok(report) if !report_ids.insert(report.report_metadata.id.clone()) => (
+                        Some(report.report_metadata.id.to_base64url()),
+                        Some(TransitionFailure::ReportReplayed),
+                        Some("report ID appears more than once in the batch".into()),
+                    ),
+                    Ok(report) => {
+                        let report_id = report.report_metadata.id.to_base64url();
+                        reports.push((statuses.len(), report));
+                        (Some(report_id), None, None)
+                    }
+                    Err(e) => (
+                        None,
+                        Some(TransitionFailure::UnrecognizedMessage),
+                        Some(format!("failed to decode report: {e}")),
+                    ),
+                };
+            statuses.push(DapDryRunReportStatus {
+                report_id,
+                failure,
+                detail,
+            });
+        }
+
+        let early_rejects = self
+            .peek_early_reject(
+                task_id,
+                part_batch_sel,
+                reports
+                    .iter()
+                    .map(|(_index, report)| &report.report_metadata),
+            )
+            .await?;
+
+        for (index, report) in reports.into_iter() {
+            if let Some(failure) = early_rejects.get(&report.report_metadata.id) {
+                statuses[index].failure = Some(*failure);
+                continue;
+            }
+
+            match task_config
+                .vdaf
+                .consume_report_share(
+                    self,
+                    self.extension_registry(),
+                    is_leader,
+                    task_id,
+                    task_config,
+                    &report.report_metadata,
+                    &report.public_share,
+                    &report.encrypted_input_shares[usize::from(!is_leader)],
+                )
+                .await
+            {
+                Ok(..) => (),
+                Err(DapError::Transition(failure)) => statuses[index].failure = Some(failure),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(statuses)
+    }
 }
 
 macro_rules! leader_post {
@@ -221,7 +437,8 @@ macro_rules! leader_post {
         $resp_media_type:expr,
         $resource:expr,
         $req_data:expr,
-        $is_put:expr
+        $is_put:expr,
+        $collector_id:expr
     ) => {{
         let url = $task_config
             .helper_url
@@ -240,6 +457,15 @@ macro_rules! leader_post {
                     .authorize(&$task_id, &$req_media_type, &$req_data)
                     .await?,
             ),
+            sender_version: Some($task_config.version),
+            collector_id: $collector_id,
+            taskprov: $task_config.taskprov_advertisement.clone(),
+            vdaf_verify_key_id: $task_config
+                .vdaf_verify_key_rotation
+                .as_ref()
+                .map(|rotation| rotation.id),
+            client_auth: None,
+            idempotency_key: None,
         };
 
         let resp = if $is_put {
@@ -255,7 +481,7 @@ macro_rules! leader_post {
 
 /// DAP Leader functionality.
 #[async_trait(?Send)]
-pub trait DapLeader<'srv, 'req, S>: DapAuthorizedSender<S> + DapAggregator<'srv, 'req, S>
+pub trait DapLeader<'srv, 'req, S>: DapAggregator<'srv, 'req, S>
 where
     'srv: 'req,
 {
@@ -272,7 +498,76 @@ where
         selector: &Self::ReportSelector,
     ) -> Result<HashMap<TaskId, HashMap<PartialBatchSelector, Vec<Report>>>, DapError>;
 
-    /// Create a collect job.
+    /// Return the reports of an abandoned aggregation job to persistent storage so that they can
+    /// be aggregated in a fresh job. The reports must no longer be considered processed by the
+    /// Leader, i.e., [`DapAggregator::check_early_reject`] must not reject them as replayed.
+    async fn requeue_reports(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        reports: Vec<Report>,
+    ) -> Result<(), DapError>;
+
+    /// Count an abandoned aggregation job towards each of the given reports. Returns, for each
+    /// report in order, the number of abandoned aggregation jobs it has been part of, including
+    /// this one. This is only called if [`DapGlobalConfig::agg_job_abandon`] is set.
+    async fn count_abandoned_reports(
+        &self,
+        task_id: &TaskId,
+        reports: &[Report],
+    ) -> Result<Vec<u32>, DapError>;
+
+    /// Record the metadata of an uploaded report sampled for traffic analysis. This is only called
+    /// if [`DapGlobalConfig::report_sampling`] is set. An error is logged but does not affect the
+    /// outcome of the upload.
+    async fn put_report_sample(
+        &self,
+        task_id: &TaskId,
+        sample: &DapReportSample,
+    ) -> Result<(), DapError>;
+
+    /// Get the record of the upload request that was handled under the given idempotency key, if
+    /// any. See [`DapRequest::idempotency_key`].
+    async fn get_upload_record(
+        &self,
+        task_id: &TaskId,
+        idempotency_key: &str,
+    ) -> Result<Option<DapUploadRecord>, DapError>;
+
+    /// Record the outcome of an upload request that was handled under the given idempotency key.
+    /// If the key already has a record, e.g., because a concurrent attempt of the same request was
+    /// recorded first, then the outcomes missing from it are filled in (see
+    /// [`DapUploadRecord::merge`]) and the record as it was before is returned. The record need
+    /// not be kept once reports with timestamp `report_time` are too old to be uploaded.
+    async fn put_upload_record(
+        &self,
+        task_id: &TaskId,
+        idempotency_key: &str,
+        record: &DapUploadRecord,
+        report_time: Time,
+    ) -> Result<Option<DapUploadRecord>, DapError>;
+
+    /// Defer the aggregation jobs for the Helper with the given URL until the given time, e.g.,
+    /// because the Helper responded with [`DapAbort::Overloaded`].
+    async fn defer_helper(&self, helper_url: &Url, until: Time) -> Result<(), DapError>;
+
+    /// Return the time until which the aggregation jobs for the Helper with the given URL are
+    /// deferred, if any. The time may be in the past.
+    async fn helper_deferred_until(&self, helper_url: &Url) -> Result<Option<Time>, DapError>;
+
+    /// Count an aggregation job that the Helper with the given URL rejected as unauthorized.
+    /// Returns the number of consecutive aggregation jobs the Helper rejected this way, including
+    /// this one. This is only called if [`DapGlobalConfig::helper_circuit_breaker`] is set.
+    async fn count_helper_auth_failure(&self, helper_url: &Url) -> Result<u32, DapError>;
+
+    /// Reset the count of consecutive aggregation jobs the Helper with the given URL rejected as
+    /// unauthorized. This is only called if [`DapGlobalConfig::helper_circuit_breaker`] is set.
+    /// Since this is called for every aggregation job the Helper accepts, implementations should
+    /// avoid accessing storage when the count is known to be zero.
+    async fn reset_helper_auth_failures(&self, helper_url: &Url) -> Result<(), DapError>;
+
+    /// Create a collect job on behalf of the given Collector. `collector_id` is `None` for the
+    /// task's primary Collector.
     //
     // TODO spec: Figure out if the hostname for the collect URI needs to match the Leader.
     async fn init_collect_job(
@@ -280,6 +575,7 @@ where
         task_id: &TaskId,
         collect_job_id: &Option<CollectionJobId>,
         collect_req: &CollectionReq,
+        collector_id: Option<&str>,
     ) -> Result<Url, DapError>;
 
     /// Check the status of a collect job.
@@ -289,97 +585,283 @@ where
         collect_id: &CollectionJobId,
     ) -> Result<DapCollectJob, DapError>;
 
-    /// Fetch the current collect job queue. The result is the sequence of collect ID and request
-    /// pairs, in order of priority.
-    async fn get_pending_collect_jobs(
+    /// Delete a collect job. A pending job is removed from the queue; the result of a completed
+    /// job is deleted. Returns the status of the job before it was deleted.
+    async fn delete_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> Result<DapCollectJob, DapError>;
+
+    /// Fetch the current collect job queue. The result is the sequence of collect jobs, in order
+    /// of priority. Each job is identified by its task ID and collect ID and carries the collect
+    /// request and the ID of the Collector that issued it.
+    async fn get_pending_collect_jobs(&self) -> Result<Vec<DapPendingCollectJob>, DapError>;
+
+    /// Get the report count of each bucket in the given batch, ordered by batch window. Empty
+    /// buckets are omitted.
+    async fn get_report_counts(
         &self,
-    ) -> Result<Vec<(TaskId, CollectionJobId, CollectionReq)>, DapError>;
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<Vec<DapBucketReportCount>, DapError>;
 
-    /// Complete a collect job by assigning it the completed [`CollectResp`](crate::messages::CollectResp).
+    /// Complete a collect job by assigning it the completed [`CollectResp`](crate::messages::CollectResp)
+    /// and the breakdown of its report count. The batch selector resolved for the job is stored
+    /// with it, e.g., so that a receipt can be issued for the collection.
     async fn finish_collect_job(
         &self,
         task_id: &TaskId,
         collect_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
         collect_resp: &Collection,
+        report_counts: &DapReportCountBreakdown,
     ) -> Result<(), DapError>;
 
+    /// Called once a collect job has been completed and its batch has been marked as collected,
+    /// e.g., to export the result to the operator. An error is logged but does not fail the
+    /// collect job, since the Collector can still retrieve the result.
+    ///
+    /// The default implementation does nothing.
+    async fn on_collect_job_finished(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _collect_id: &CollectionJobId,
+        _batch_sel: &BatchSelector,
+        _collection: &Collection,
+    ) -> Result<(), DapError> {
+        Ok(())
+    }
+
+    /// Close the batch currently being filled for the given fixed-size task, so that no more
+    /// reports are assigned to it. Returns the ID of the closed batch, or `None` if no report has
+    /// been assigned to the current batch.
+    async fn close_current_batch(&self, task_id: &TaskId) -> Result<Option<BatchId>, DapError>;
+
+    /// Make the batch currently being filled for the given fixed-size task collectable regardless
+    /// of the task's minimum batch size: the batch is closed (see [`Self::close_current_batch`])
+    /// and marked as forced (see [`DapAggregator::mark_batch_forced`]). Returns the ID of the
+    /// batch, or `None` if there is no report to collect. This is meant for testing and incident
+    /// response; the Helper must mark the same batch as forced before it is collected.
+    async fn force_close_current_batch(
+        &'srv self,
+        task_id: &'req TaskId,
+    ) -> Result<Option<BatchId>, DapError> {
+        let task_config = self
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await?
+            .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))?;
+        if !matches!(task_config.as_ref().query, DapQueryConfig::FixedSize { .. }) {
+            return Err(DapError::Abort(DapAbort::QueryMismatch {
+                detail: "Only fixed-size batches can be force-closed.".into(),
+                task_id: task_id.clone(),
+            }));
+        }
+
+        let batch_id = match self.close_current_batch(task_id).await? {
+            Some(batch_id) => batch_id,
+            None => return Ok(None),
+        };
+        self.mark_batch_forced(task_id, &batch_id).await?;
+        info!("force-closed batch {batch_id} of task {task_id}");
+        Ok(Some(batch_id))
+    }
+
     /// Send an HTTP POST request.
     async fn send_http_post(&self, req: DapRequest<S>) -> Result<DapResponse, DapError>;
 
     /// Send an HTTP PUT request.
     async fn send_http_put(&self, req: DapRequest<S>) -> Result<DapResponse, DapError>;
 
-    /// Handle HTTP POST to `/upload`. The input is the encoded report sent in the body of the HTTP
-    /// request.
-    async fn http_post_upload(&'srv self, req: &'req DapRequest<S>) -> Result<(), DapAbort> {
-        let metrics = self.metrics().with_host(req.host());
-        debug!("upload for task {}", req.task_id()?);
-
-        // Check whether the DAP version indicated by the sender is supported.
-        if req.version == DapVersion::Unknown {
-            return Err(DapAbort::version_unknown());
-        }
-
-        check_request_content_type(req, DapMediaType::Report)?;
-
-        let report = Report::get_decoded_with_param(&req.version, req.payload.as_ref())?;
-        debug!("report id is {}", report.report_metadata.id);
+    /// Check the task and metadata of a report being uploaded. This only requires the prefix of
+    /// the report (see [`decode_report_prefix`](crate::messages::decode_report_prefix)), so it may
+    /// be called before the rest of the report has been received in order to reject the report
+    /// without buffering its input shares. The check is repeated by [`Self::http_post_upload`].
+    async fn check_upload_metadata(
+        &'srv self,
+        version: DapVersion,
+        task_id: &'req TaskId,
+        taskprov_advertisement: Option<&str>,
+        report_metadata: &ReportMetadata,
+    ) -> Result<(), DapAbort> {
         let task_config = self
             .get_task_config_considering_taskprov(
-                req.version,
-                Cow::Borrowed(req.task_id()?),
-                Some(&report.report_metadata),
+                version,
+                Cow::Borrowed(task_id),
+                taskprov_advertisement,
+                Some(report_metadata),
             )
             .await?
             .ok_or(DapAbort::UnrecognizedTask)?;
 
         // Check whether the DAP version in the request matches the task config.
-        if task_config.as_ref().version != req.version {
+        if task_config.as_ref().version != version {
             return Err(DapAbort::version_mismatch(
-                req.version,
+                version,
                 task_config.as_ref().version,
             ));
         }
 
-        if report.encrypted_input_shares.len() != 2 {
-            // TODO spec: Decide if this behavior should be specified.
-            return Err(DapAbort::UnrecognizedMessage);
+        // Check that the task has not expired.
+        if report_metadata.time >= task_config.as_ref().expiration {
+            return Err(DapAbort::ReportTooLate);
         }
 
-        // Check that the indicated HpkeConfig is present.
-        //
-        // TODO spec: It's not clear if this behavior is MUST, SHOULD, or MAY.
-        if !self
-            .can_hpke_decrypt(req.task_id()?, report.encrypted_input_shares[0].config_id)
-            .await?
+        // Check that the Client truncated the timestamp, if required.
+        if self.get_global_config().reject_untruncated_report_time
+            && task_config.as_ref().time_precision != 0
+            && report_metadata.time % task_config.as_ref().time_precision != 0
         {
             return Err(DapAbort::ReportRejected {
-                detail: "No current HPKE configuration matches the indicated ID.".into(),
+                detail: format!(
+                    "The report timestamp is not truncated to the task's time precision ({}s).",
+                    task_config.as_ref().time_precision
+                ),
             });
         }
 
-        // Check that the task has not expired.
-        if report.report_metadata.time >= task_config.as_ref().expiration {
-            return Err(DapAbort::ReportTooLate);
+        // Check the extensions carried by the report. (For draft03 and later, extensions are
+        // encrypted and hence only checked during preparation.)
+        if let Err(failure) = self.extension_registry().validate(
+            task_id,
+            task_config.as_ref(),
+            report_metadata,
+            &report_metadata.extensions,
+        ) {
+            return Err(match failure {
+                TransitionFailure::UnrecognizedMessage => DapAbort::UnrecognizedMessage,
+                failure => DapAbort::ReportRejected {
+                    detail: format!("Report extension was rejected: {failure}."),
+                },
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Handle HTTP POST to `/upload`. The input is the encoded report sent in the body of the HTTP
+    /// request.
+    #[instrument(skip_all, fields(task_id, report_id))]
+    async fn http_post_upload(&'srv self, req: &'req DapRequest<S>) -> Result<(), DapAbort> {
+        let start = self.get_current_time_millis();
+        let metrics = self.metrics().with_host(req.host());
+        debug!("upload for task {}", req.task_id()?);
+        Span::current().record("task_id", req.task_id()?.to_string());
+
+        // Check whether the DAP version indicated by the sender is supported.
+        if req.version == DapVersion::Unknown {
+            return Err(DapAbort::version_unknown());
         }
 
-        // Store the report for future processing. At this point, the report may be rejected if
-        // the Leader detects that the report was replayed or pertains to a batch that has already
-        // been collected.
-        self.put_report(&report, req.task_id()?).await?;
+        check_request_content_type(req, DapMediaType::Report)?;
+        check_request_size(
+            self.get_global_config(),
+            req,
+            DaphneRequestType::Upload,
+            &metrics,
+        )?;
+        check_upload_auth(self, req).await?;
+
+        let report = Report::get_decoded_with_param(&req.version, req.payload.as_ref())?;
+        debug!("report id is {}", report.report_metadata.id);
+        Span::current().record("report_id", report.report_metadata.id.to_string());
+        match check_idempotency_key(self, req).await? {
+            Some(idempotency) => upload_report_idempotent(self, req, &report, &idempotency).await?,
+            None => upload_report(self, req, &report).await?,
+        }
 
         metrics.inbound_req_inc(DaphneRequestType::Upload);
+        metrics.inbound_req_latency_observe(
+            DaphneRequestType::Upload,
+            self.get_current_time_millis().saturating_sub(start),
+        );
         Ok(())
     }
 
+    /// Handle an HTTP POST to the task's batched upload endpoint. This is a Daphne extension to
+    /// DAP: the input is a [`ReportBatch`] and each report in the batch is handled as if it were
+    /// uploaded on its own. The outcome for each report is returned in the order in which the
+    /// reports appear in the batch. The request itself is only aborted if the batch as a whole is
+    /// invalid.
+    #[instrument(skip_all, fields(task_id, report_count))]
+    async fn http_post_upload_batch(
+        &'srv self,
+        req: &'req DapRequest<S>,
+    ) -> Result<Vec<DapReportUploadStatus>, DapAbort> {
+        let start = self.get_current_time_millis();
+        let metrics = self.metrics().with_host(req.host());
+        Span::current().record("task_id", req.task_id()?.to_string());
+
+        // Check whether the DAP version indicated by the sender is supported.
+        match req.version {
+            DapVersion::Unknown => return Err(DapAbort::version_unknown()),
+            DapVersion::Draft02 => {
+                return Err(DapAbort::BadRequest(
+                    "batched upload is not supported in draft02".into(),
+                ))
+            }
+            _ => (),
+        }
+
+        let max_len = self
+            .get_global_config()
+            .for_version(req.version)
+            .max_upload_batch_len
+            .ok_or_else(|| DapAbort::BadRequest("batched upload is not enabled".into()))?;
+
+        check_request_content_type(req, DapMediaType::ReportBatch)?;
+        check_request_size(
+            self.get_global_config(),
+            req,
+            DaphneRequestType::UploadBatch,
+            &metrics,
+        )?;
+        check_upload_auth(self, req).await?;
+
+        let report_batch = ReportBatch::get_decoded(req.payload.as_ref())?;
+        let report_count = u64::try_from(report_batch.encoded_reports.len()).unwrap();
+        Span::current().record("report_count", report_count);
+        if report_count > max_len {
+            return Err(DapAbort::BadRequest(format!(
+                "batch of {report_count} reports exceeds the limit of {max_len}"
+            )));
+        }
+
+        // If the same batch was already uploaded under the idempotency key, then this is a retry
+        // of that request: The outcome of each report is replayed from the record.
+        let statuses = match check_idempotency_key(self, req).await? {
+            Some(idempotency) => {
+                upload_reports_idempotent(self, req, report_batch.encoded_reports, idempotency)
+                    .await?
+            }
+            None => upload_reports(self, req, report_batch.encoded_reports, &[])
+                .await
+                .0
+                .into_iter()
+                .map(|outcome| outcome.status)
+                .collect(),
+        };
+
+        metrics.inbound_req_inc(DaphneRequestType::UploadBatch);
+        metrics.inbound_req_latency_observe(
+            DaphneRequestType::UploadBatch,
+            self.get_current_time_millis().saturating_sub(start),
+        );
+        Ok(statuses)
+    }
+
     /// Handle HTTP POST to `/collect`. The input is a [`CollectReq`](crate::messages::CollectReq).
     /// The return value is a URI that the Collector can poll later on to get the corresponding
     /// [`CollectResp`](crate::messages::CollectResp).
+    #[instrument(skip_all, fields(task_id))]
     async fn http_post_collect(&'srv self, req: &'req DapRequest<S>) -> Result<Url, DapAbort> {
+        let start = self.get_current_time_millis();
         let now = self.get_current_time();
         let metrics = self.metrics().with_host(req.host());
         let task_id = req.task_id()?;
         debug!("collect for task {task_id}");
+        Span::current().record("task_id", task_id.to_string());
 
         // Check whether the DAP version indicated by the sender is supported.
         if req.version == DapVersion::Unknown {
@@ -387,6 +869,12 @@ where
         }
 
         check_request_content_type(req, DapMediaType::CollectReq)?;
+        check_request_size(
+            self.get_global_config(),
+            req,
+            DaphneRequestType::Collect,
+            &metrics,
+        )?;
 
         if let Some(reason) = self.unauthorized_reason(req).await? {
             error!("aborted unauthorized collect request: {reason}");
@@ -409,6 +897,17 @@ where
             return Err(DapAbort::version_mismatch(req.version, task_config.version));
         }
 
+        let collector_id = req.collector_id.as_deref();
+        if task_config
+            .collector_hpke_config_for(collector_id)
+            .is_none()
+        {
+            return Err(DapAbort::UnauthorizedRequest {
+                detail: format!("Collector {collector_id:?} is not recognized for this task."),
+                task_id: task_id.clone(),
+            });
+        }
+
         if collect_req.query == Query::FixedSizeCurrentBatch {
             // This is where we assign the current batch, and convert the
             // Query::FixedSizeCurrentBatch into a Query::FixedSizeByBatchId.
@@ -434,6 +933,7 @@ where
             &batch_selector,
             &collect_req.agg_param,
             now,
+            collector_id,
         )
         .await?;
 
@@ -442,69 +942,420 @@ where
         // from the request path.
         let collect_job_id = match (req.version, &req.resource) {
             (DapVersion::Draft02, DapResource::Undefined) => None,
-            (DapVersion::Draft04, DapResource::CollectionJob(ref collect_job_id)) => {
-                Some(collect_job_id.clone())
-            }
-            (DapVersion::Draft04, DapResource::Undefined) => {
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                DapResource::CollectionJob(ref collect_job_id),
+            ) => Some(collect_job_id.clone()),
+            (
+                DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                DapResource::Undefined,
+            ) => {
                 return Err(DapAbort::BadRequest("undefined resource".into()));
             }
             _ => unreachable!("unhandled resource {:?}", req.resource),
         };
 
         let collect_job_uri = self
-            .init_collect_job(task_id, &collect_job_id, &collect_req)
+            .init_collect_job(task_id, &collect_job_id, &collect_req, collector_id)
             .await?;
+        info!(
+            "collector {} requested batch {batch_selector:?} of task {task_id}",
+            collector_id.unwrap_or("(primary)")
+        );
 
         metrics.inbound_req_inc(DaphneRequestType::Collect);
+        metrics.inbound_req_latency_observe(
+            DaphneRequestType::Collect,
+            self.get_current_time_millis().saturating_sub(start),
+        );
         Ok(collect_job_uri)
     }
 
-    /// Run the aggregation sub-protocol for the given set of reports. Return the number of reports
-    /// that were aggregated successfully.
-    //
-    // TODO Handle non-encodable messages gracefully. The length of `reports` may be too long to
-    // encode in `AggregationJobInitReq`, in which case this method will panic. We should increase
-    // the capacity of this message in the spec. In the meantime, we should at a minimum log this
-    // when it happens.
-    async fn run_agg_job(
-        &self,
-        task_id: &TaskId,
-        task_config: &DapTaskConfig,
-        part_batch_sel: &PartialBatchSelector,
-        reports: Vec<Report>,
-        host: &str,
-    ) -> Result<u64, DapAbort> {
-        let metrics = self.metrics().with_host(host);
+    /// Handle a request from the Collector for the status of a collect job. This is not defined
+    /// by the DAP standard. The request has no body; the collect job is identified by the
+    /// request's resource. Since the media type of the request can't be used to determine the
+    /// sender, the caller is expected to set it to [`DapMediaType::CollectReq`].
+    #[instrument(skip_all, fields(task_id))]
+    async fn http_get_collect_job_status(
+        &'srv self,
+        req: &'req DapRequest<S>,
+    ) -> Result<CollectionJobStatus, DapAbort> {
+        let task_id = req.task_id()?;
+        Span::current().record("task_id", task_id.to_string());
 
-        // Filter out early rejected reports.
-        //
-        // TODO Add a test similar to http_post_aggregate_init_expired_task() in roles_test.rs that
-        // verifies that the Leader properly checks for expiration. This will require extending the
-        // test framework to run run_agg_job() directly.
-        let early_rejects = self
-            .check_early_reject(
-                task_id,
-                part_batch_sel,
-                reports.iter().map(|report| &report.report_metadata),
-            )
-            .await?;
-        let reports = reports
-            .into_iter()
-            .filter(|report| {
-                if let Some(failure) = early_rejects.get(&report.report_metadata.id) {
+        // Check whether the DAP version indicated by the sender is supported.
+        if req.version == DapVersion::Unknown {
+            return Err(DapAbort::version_unknown());
+        }
+
+        check_request_content_type(req, DapMediaType::CollectReq)?;
+
+        if let Some(reason) = self.unauthorized_reason(req).await? {
+            error!("aborted unauthorized collect job status request: {reason}");
+            return Err(DapAbort::UnauthorizedRequest {
+                detail: reason,
+                task_id: task_id.clone(),
+            });
+        }
+
+        let collect_job_id = match req.resource {
+            DapResource::CollectionJob(ref collect_job_id) => collect_job_id,
+            _ => return Err(DapAbort::BadRequest("undefined resource".into())),
+        };
+
+        let wrapped_task_config = self
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await?
+            .ok_or(DapAbort::UnrecognizedTask)?;
+        let task_config = wrapped_task_config.as_ref();
+
+        // Check whether the DAP version in the request matches the task config.
+        if task_config.version != req.version {
+            return Err(DapAbort::version_mismatch(req.version, task_config.version));
+        }
+
+        match self.poll_collect_job(task_id, collect_job_id).await? {
+            DapCollectJob::Done(collection) => return Ok(CollectionJobStatus::done(&collection)),
+            DapCollectJob::Unknown => {
+                return Err(DapAbort::BadRequest("unknown collect id".into()))
+            }
+            DapCollectJob::Pending => (),
+        }
+
+        let collect_req = match self.get_pending_collect_jobs().await?.into_iter().find(
+            |(pending_task_id, pending_collect_job_id, ..)| {
+                pending_task_id == task_id && pending_collect_job_id == collect_job_id
+            },
+        ) {
+            Some((_, _, collect_req, _)) => collect_req,
+            // The job was completed after it was polled.
+            None => {
+                return Err(DapAbort::RetryLater {
+                    detail: "collect job is being completed".into(),
+                })
+            }
+        };
+
+        let batch_sel = BatchSelector::try_from(collect_req.query)?;
+        let report_count = self
+            .get_report_counts(task_id, &batch_sel)
+            .await?
+            .iter()
+            .map(|bucket| bucket.report_count)
+            .sum();
+        Ok(CollectionJobStatus::pending(
+            task_config,
+            &batch_sel,
+            report_count,
+            self.get_current_time(),
+        ))
+    }
+
+    /// Handle a request from the Collector for the batches that may be collected now, i.e., that
+    /// satisfy the task's minimum batch size and don't overlap with a batch the Collector has
+    /// already collected. This is not defined by the DAP standard. As for
+    /// [`Self::http_get_collect_job_status`], the request has no body.
+    ///
+    /// For time-interval tasks, the time windows that have ended and may still be queried are
+    /// grouped, in order, into batch intervals that each reach the minimum batch size. For
+    /// fixed-size tasks, the batch that a query for the current batch would be assigned is
+    /// suggested once it is full enough.
+    #[instrument(skip_all, fields(task_id))]
+    async fn http_get_batch_suggestions(
+        &'srv self,
+        req: &'req DapRequest<S>,
+    ) -> Result<DapBatchSuggestions, DapAbort> {
+        let task_id = req.task_id()?;
+        Span::current().record("task_id", task_id.to_string());
+
+        // Check whether the DAP version indicated by the sender is supported.
+        if req.version == DapVersion::Unknown {
+            return Err(DapAbort::version_unknown());
+        }
+
+        check_request_content_type(req, DapMediaType::CollectReq)?;
+
+        if let Some(reason) = self.unauthorized_reason(req).await? {
+            error!("aborted unauthorized batch suggestions request: {reason}");
+            return Err(DapAbort::UnauthorizedRequest {
+                detail: reason,
+                task_id: task_id.clone(),
+            });
+        }
+
+        let wrapped_task_config = self
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await?
+            .ok_or(DapAbort::UnrecognizedTask)?;
+        let task_config = wrapped_task_config.as_ref();
+
+        // Check whether the DAP version in the request matches the task config.
+        if task_config.version != req.version {
+            return Err(DapAbort::version_mismatch(req.version, task_config.version));
+        }
+
+        let collector_id = req.collector_id.as_deref();
+        let min_batch_size = task_config.min_batch_size.max(1);
+        let now = self.get_current_time();
+        let mut batches = Vec::new();
+        match task_config.query {
+            DapQueryConfig::TimeInterval => {
+                let global_config = self.get_global_config().for_version(task_config.version);
+                let earliest_start = now.saturating_sub(global_config.min_batch_interval_start);
+                let first_window = if earliest_start % task_config.time_precision == 0 {
+                    earliest_start
+                } else {
+                    task_config.quantized_time_upper_bound(earliest_start)
+                };
+                let last_window_end = task_config.quantized_time_lower_bound(now);
+                if last_window_end <= first_window {
+                    return Ok(DapBatchSuggestions { batches });
+                }
+                let span = BatchSelector::TimeInterval {
+                    batch_interval: Interval {
+                        start: first_window,
+                        duration: last_window_end - first_window,
+                    },
+                };
+
+                // The report count of the interval under construction and its start.
+                let mut pending: Option<(Time, u64)> = None;
+                for bucket in self.get_report_counts(task_id, &span).await? {
+                    let batch_window = match bucket.batch_window {
+                        Some(batch_window) => batch_window,
+                        None => continue,
+                    };
+                    let end = batch_window + task_config.time_precision;
+                    let (start, report_count) = match pending {
+                        Some((start, report_count))
+                            if end - start <= global_config.max_batch_duration =>
+                        {
+                            (start, report_count + bucket.report_count)
+                        }
+                        _ => (batch_window, bucket.report_count),
+                    };
+                    if report_count < min_batch_size {
+                        pending = Some((start, report_count));
+                        continue;
+                    }
+                    pending = None;
+
+                    let batch_interval = Interval {
+                        start,
+                        duration: end - start,
+                    };
+                    if now.abs_diff(batch_interval.end()) > global_config.max_batch_interval_end {
+                        continue;
+                    }
+                    let batch_sel = BatchSelector::TimeInterval {
+                        batch_interval: batch_interval.clone(),
+                    };
+                    if self
+                        .is_batch_overlapping(task_id, &batch_sel, collector_id)
+                        .await?
+                    {
+                        continue;
+                    }
+                    batches.push(DapBatchSuggestion::TimeInterval {
+                        batch_interval,
+                        report_count,
+                    });
+                }
+            }
+            DapQueryConfig::FixedSize { .. } => {
+                // There is no current batch if no report has been assigned to a batch since the
+                // last one was collected.
+                let batch_id = match self.current_batch(task_id).await {
+                    Ok(batch_id) => batch_id,
+                    Err(e) => {
+                        debug!("no current batch for task {task_id}: {e}");
+                        return Ok(DapBatchSuggestions { batches });
+                    }
+                };
+                let batch_sel = BatchSelector::FixedSizeByBatchId {
+                    batch_id: batch_id.clone(),
+                };
+                let report_count = self
+                    .get_report_counts(task_id, &batch_sel)
+                    .await?
+                    .iter()
+                    .map(|bucket| bucket.report_count)
+                    .sum();
+                if report_count >= min_batch_size
+                    && !self
+                        .is_batch_overlapping(task_id, &batch_sel, collector_id)
+                        .await?
+                {
+                    batches.push(DapBatchSuggestion::FixedSize {
+                        batch_id: batch_id.to_base64url(),
+                        report_count,
+                    });
+                }
+            }
+        }
+        Ok(DapBatchSuggestions { batches })
+    }
+
+    /// Handle a request from the Collector to delete a collect job. As for
+    /// [`Self::http_get_collect_job_status`], the request has no body and the job is identified
+    /// by the request's resource.
+    ///
+    /// If the job is pending, then it is cancelled: It is removed from the queue and its batch
+    /// remains uncollected, so it may be collected by a later job. If the job is completed, then
+    /// its result is deleted, but its batch remains collected. A job that is being run when it is
+    /// cancelled may still be completed.
+    #[instrument(skip_all, fields(task_id))]
+    async fn http_delete_collect_job(&'srv self, req: &'req DapRequest<S>) -> Result<(), DapAbort> {
+        let metrics = self.metrics().with_host(req.host());
+        let task_id = req.task_id()?;
+        Span::current().record("task_id", task_id.to_string());
+
+        // Check whether the DAP version indicated by the sender is supported.
+        if req.version == DapVersion::Unknown {
+            return Err(DapAbort::version_unknown());
+        }
+
+        check_request_content_type(req, DapMediaType::CollectReq)?;
+
+        if let Some(reason) = self.unauthorized_reason(req).await? {
+            error!("aborted unauthorized collect job deletion request: {reason}");
+            return Err(DapAbort::UnauthorizedRequest {
+                detail: reason,
+                task_id: task_id.clone(),
+            });
+        }
+
+        let collect_job_id = match req.resource {
+            DapResource::CollectionJob(ref collect_job_id) => collect_job_id,
+            _ => return Err(DapAbort::BadRequest("undefined resource".into())),
+        };
+
+        let wrapped_task_config = self
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await?
+            .ok_or(DapAbort::UnrecognizedTask)?;
+        let task_config = wrapped_task_config.as_ref();
+
+        // Check whether the DAP version in the request matches the task config.
+        if task_config.version != req.version {
+            return Err(DapAbort::version_mismatch(req.version, task_config.version));
+        }
+
+        match self.delete_collect_job(task_id, collect_job_id).await? {
+            DapCollectJob::Pending => {
+                info!("cancelled collect job {collect_job_id}");
+                metrics.collect_job_deleted_inc("cancelled");
+            }
+            DapCollectJob::Done(..) => {
+                info!("deleted result of collect job {collect_job_id}");
+                metrics.collect_job_deleted_inc("deleted");
+            }
+            DapCollectJob::Unknown => {
+                return Err(DapAbort::BadRequest("unknown collect id".into()))
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the aggregation sub-protocol for the given set of reports. Return the number of reports
+    /// that were aggregated successfully.
+    //
+    // TODO Handle non-encodable messages gracefully. The length of `reports` may be too long to
+    // encode in `AggregationJobInitReq`, in which case this method will panic. We should increase
+    // the capacity of this message in the spec. In the meantime, we should at a minimum log this
+    // when it happens.
+    #[instrument(
+        skip_all,
+        fields(
+            %task_id,
+            agg_job_id,
+            report_count = reports.len(),
+            reports_aggregated,
+        )
+    )]
+    async fn run_agg_job(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        part_batch_sel: &PartialBatchSelector,
+        reports: Vec<Report>,
+        host: &str,
+    ) -> Result<u64, DapAbort> {
+        let start = self.get_current_time_millis();
+        let metrics = self.metrics().with_host(host).with_task(task_id);
+
+        // If the Helper asked to back off, then defer the job by returning its reports to
+        // storage. They are aggregated by a later job once the deferral has expired.
+        if let Some(until) = self.helper_deferred_until(&task_config.helper_url).await? {
+            if self.get_current_time() < until {
+                debug!(
+                    "deferring aggregation job for {} reports until {until}",
+                    reports.len()
+                );
+                self.requeue_reports(task_id, part_batch_sel, reports)
+                    .await?;
+                metrics.agg_job_deferred_inc();
+                return Ok(0);
+            }
+        }
+        metrics.agg_job_batch_size_observe(reports.len());
+
+        // Reports with the same ID may have been stored more than once (e.g., in different
+        // shards), in which case only the first is aggregated. The Helper would abort an
+        // aggregation job with repeated report IDs.
+        let mut report_ids = HashSet::with_capacity(reports.len());
+        let reports = reports
+            .into_iter()
+            .filter(|report| {
+                if !report_ids.insert(report.report_metadata.id.clone()) {
+                    metrics.report_inc_by("rejected_report_duplicated", 1);
+                    return false;
+                }
+                true
+            })
+            .collect::<Vec<_>>();
+
+        // Filter out early rejected reports.
+        //
+        // TODO Add a test similar to http_post_aggregate_init_expired_task() in roles_test.rs that
+        // verifies that the Leader properly checks for expiration. This will require extending the
+        // test framework to run run_agg_job() directly.
+        let early_rejects = self
+            .check_early_reject(
+                task_id,
+                part_batch_sel,
+                reports.iter().map(|report| &report.report_metadata),
+            )
+            .await?;
+        let reports = reports
+            .into_iter()
+            .filter(|report| {
+                if let Some(failure) = early_rejects.get(&report.report_metadata.id) {
                     metrics.report_inc_by(&format!("rejected_{failure}"), 1);
                     return false;
                 }
                 true
             })
-            .collect();
+            .collect::<Vec<_>>();
+        count_skewed_reports(
+            self.get_global_config(),
+            self.get_current_time(),
+            reports.iter().map(|report| &report.report_metadata),
+            &early_rejects,
+            &metrics,
+        );
+
+        // Keep a copy of the reports in case the job needs to be abandoned.
+        let reports_for_requeue = reports.clone();
 
         // Prepare AggregationJobInitReq.
         let agg_job_id = MetaAggregationJobId::gen_for_version(&task_config.version);
+        Span::current().record("agg_job_id", agg_job_id.to_base64url());
         let transition = task_config
             .vdaf
             .produce_agg_job_init_req(
                 self,
+                self.extension_registry(),
                 task_id,
                 task_config,
                 &agg_job_id,
@@ -519,6 +1370,9 @@ where
             DapLeaderTransition::Uncommitted(..) => {
                 return Err(DapError::fatal("unexpected state transition (uncommitted)").into())
             }
+            DapLeaderTransition::Finish(..) => {
+                return Err(DapError::fatal("unexpected state transition (finish)").into())
+            }
         };
         let is_put = task_config.version != DapVersion::Draft02;
         let url_path = if task_config.version == DapVersion::Draft02 {
@@ -531,21 +1385,118 @@ where
             )
         };
 
-        // Send AggregationJobInitReq and receive AggregationJobResp.
-        let resp = leader_post!(
-            self,
-            task_id,
-            task_config,
-            &url_path,
-            DapMediaType::AggregationJobInitReq,
-            DapMediaType::AggregationJobResp,
-            agg_job_id.for_request_path(),
-            agg_job_init_req.get_encoded_with_param(&task_config.version),
-            is_put
-        );
-        let agg_job_resp = AggregationJobResp::get_decoded(&resp.payload)?;
+        // Send AggregationJobInitReq and receive AggregationJobResp. If the request fails with a
+        // transient error, then retry it with exponential backoff, if configured.
+        let agg_job_init_req_data = agg_job_init_req.get_encoded_with_param(&task_config.version);
+        let retry = self.get_global_config().agg_job_init_retry.clone();
+        let max_attempts = retry.as_ref().map_or(1, |retry| retry.max_attempts.max(1));
+        let mut delay_ms = retry.as_ref().map_or(0, |retry| retry.initial_delay_ms);
+        let mut attempt = 1;
+        let result = loop {
+            let result: Result<AggregationJobResp, DapAbort> = async {
+                let resp = leader_post!(
+                    self,
+                    task_id,
+                    task_config,
+                    &url_path,
+                    DapMediaType::AggregationJobInitReq,
+                    DapMediaType::AggregationJobResp,
+                    agg_job_id.for_request_path(),
+                    agg_job_init_req_data.clone(),
+                    is_put,
+                    None
+                );
+                Ok(AggregationJobResp::get_decoded(&resp.payload)?)
+            }
+            .await;
+            match (result, retry.as_ref()) {
+                (Err(DapAbort::RetryLater { detail }), Some(retry)) if attempt < max_attempts => {
+                    warn!(
+                        "retrying aggregation job {} in {delay_ms}ms (attempt {attempt} of {max_attempts} failed): {detail}",
+                        agg_job_id.to_base64url()
+                    );
+                    metrics.agg_job_retried_inc();
+                    self.sleep(std::time::Duration::from_millis(delay_ms)).await;
+                    delay_ms = delay_ms.saturating_mul(2).min(retry.max_delay_ms);
+                    attempt += 1;
+                }
+                (result, _) => break result,
+            }
+        };
+
+        // If the job cannot be initialized because of a transient error, then abandon it. The
+        // reports that were not rejected while preparing the request are returned to storage so
+        // that they can be aggregated in a fresh job. If the Helper is overloaded, then the job is
+        // deferred instead, along with any other job for this Helper, for as long as the Helper
+        // asked. Any other error (e.g., the Helper rejected the request as unauthorized) would
+        // recur for a fresh job, so it is returned to the caller once the reports are returned to
+        // storage. If the Helper rejected the request as unauthorized, then the reports are not
+        // counted towards the abandonment policy, since the failure is not due to them.
+        let agg_job_resp = match result {
+            Ok(agg_job_resp) => {
+                if self.get_global_config().helper_circuit_breaker.is_some() {
+                    if let Err(e) = self
+                        .reset_helper_auth_failures(&task_config.helper_url)
+                        .await
+                    {
+                        error!("failed to reset circuit breaker for Helper: {e}");
+                    }
+                }
+                agg_job_resp
+            }
+            Err(e) if !is_transient(&e) => {
+                error!("aggregation job {} failed: {e}", agg_job_id.to_base64url());
+                let reports = reports_to_requeue(
+                    reports_for_requeue,
+                    state.seq.iter().map(|(_, _, _, report_id)| report_id),
+                );
+                if e.peer_abort_type() == Some(DapAbortType::UnauthorizedRequest) {
+                    metrics.helper_auth_failure_inc();
+                    if let Err(e) =
+                        trip_helper_circuit_breaker(self, &task_config.helper_url, &metrics).await
+                    {
+                        error!("failed to update circuit breaker for Helper: {e}");
+                    }
+                    self.requeue_reports(task_id, part_batch_sel, reports)
+                        .await?;
+                } else {
+                    requeue_abandoned_reports(self, task_id, part_batch_sel, reports, &metrics)
+                        .await?;
+                }
+                return Err(e);
+            }
+            Err(e) => {
+                let reports = reports_to_requeue(
+                    reports_for_requeue,
+                    state.seq.iter().map(|(_, _, _, report_id)| report_id),
+                );
+                if let DapAbort::Overloaded { retry_after, .. } = &e {
+                    self.requeue_reports(task_id, part_batch_sel, reports)
+                        .await?;
+                    warn!(
+                        "deferring aggregation job {} by {retry_after}s: {e}",
+                        agg_job_id.to_base64url()
+                    );
+                    let until = self.get_current_time().saturating_add(*retry_after);
+                    if let Err(e) = self.defer_helper(&task_config.helper_url, until).await {
+                        error!("failed to defer aggregation jobs for Helper: {e}");
+                    }
+                    metrics.agg_job_deferred_inc();
+                } else {
+                    error!(
+                        "abandoning aggregation job {}: {e}",
+                        agg_job_id.to_base64url()
+                    );
+                    requeue_abandoned_reports(self, task_id, part_batch_sel, reports, &metrics)
+                        .await?;
+                    metrics.agg_job_abandoned_inc();
+                }
+                return Ok(0);
+            }
+        };
 
-        // Prepare AggreagteContinueReq.
+        // Prepare AggreagteContinueReq. In draft06, the aggregation job is complete once the
+        // Helper has responded to the AggregationJobInitReq.
         let transition = task_config.vdaf.handle_agg_job_resp(
             task_id,
             &agg_job_id,
@@ -554,9 +1505,79 @@ where
             task_config.version,
             &metrics,
         )?;
-        let (uncommited, agg_job_cont_req) = match transition {
+        let out_shares = match transition {
+            DapLeaderTransition::Finish(out_shares) => out_shares,
             DapLeaderTransition::Uncommitted(uncommited, agg_job_cont_req) => {
-                (uncommited, agg_job_cont_req)
+                // Send AggregationJobContinueReq and receive AggregationJobResp. If the request
+                // fails with a transient error, then retry it with exponential backoff until the
+                // deadline for the job has passed, if configured.
+                let agg_job_cont_req_data =
+                    agg_job_cont_req.get_encoded_with_param(&task_config.version);
+                let retry = self.get_global_config().agg_job_continue_retry.clone();
+                let mut delay_ms = retry.as_ref().map_or(0, |retry| retry.initial_delay_ms);
+                let result = loop {
+                    let result: Result<AggregationJobResp, DapAbort> = async {
+                        let resp = leader_post!(
+                            self,
+                            task_id,
+                            task_config,
+                            &url_path,
+                            DapMediaType::AggregationJobContinueReq,
+                            DapMediaType::agg_job_cont_resp_for_version(task_config.version),
+                            agg_job_id.for_request_path(),
+                            agg_job_cont_req_data.clone(),
+                            false,
+                            None
+                        );
+                        Ok(AggregationJobResp::get_decoded(&resp.payload)?)
+                    }
+                    .await;
+                    match (result, retry.as_ref()) {
+                        (Err(e), Some(retry))
+                            if is_transient(&e)
+                                && self.get_current_time_millis().saturating_add(delay_ms)
+                                    <= start.saturating_add(retry.deadline_ms) =>
+                        {
+                            warn!(
+                                "retrying continuation of aggregation job {} in {delay_ms}ms: {e}",
+                                agg_job_id.to_base64url()
+                            );
+                            metrics.agg_job_retried_inc();
+                            self.sleep(std::time::Duration::from_millis(delay_ms)).await;
+                            delay_ms = delay_ms.saturating_mul(2).min(retry.max_delay_ms);
+                        }
+                        (result, _) => break result,
+                    }
+                };
+
+                // If the job cannot be continued (e.g., the Helper lost its state or the request
+                // kept timing out), then abandon it. The reports that were not rejected during
+                // initialization are returned to storage so that they can be aggregated in a fresh
+                // job. Note that the Helper may still reject them as replayed.
+                let agg_job_resp = match result {
+                    Ok(agg_job_resp) => agg_job_resp,
+                    Err(e) => {
+                        error!(
+                            "abandoning aggregation job {}: {e}",
+                            agg_job_id.to_base64url()
+                        );
+                        let reports = reports_to_requeue(
+                            reports_for_requeue,
+                            uncommited
+                                .seq
+                                .iter()
+                                .map(|(_out_share, report_id)| report_id),
+                        );
+                        requeue_abandoned_reports(self, task_id, part_batch_sel, reports, &metrics)
+                            .await?;
+                        metrics.agg_job_abandoned_inc();
+                        return Ok(0);
+                    }
+                };
+
+                task_config
+                    .vdaf
+                    .handle_final_agg_job_resp(uncommited, agg_job_resp, &metrics)?
             }
             DapLeaderTransition::Skip => return Ok(0),
             DapLeaderTransition::Continue(..) => {
@@ -564,62 +1585,68 @@ where
             }
         };
 
-        // Send AggregationJobContinueReq and receive AggregationJobResp.
-        let resp = leader_post!(
-            self,
-            task_id,
-            task_config,
-            &url_path,
-            DapMediaType::AggregationJobContinueReq,
-            DapMediaType::agg_job_cont_resp_for_version(task_config.version),
-            agg_job_id.for_request_path(),
-            agg_job_cont_req.get_encoded_with_param(&task_config.version),
-            false
-        );
-        let agg_job_resp = AggregationJobResp::get_decoded(&resp.payload)?;
-
         // Commit the output shares.
-        let out_shares =
-            task_config
-                .vdaf
-                .handle_final_agg_job_resp(uncommited, agg_job_resp, &metrics)?;
         let out_shares_count = out_shares.len() as u64;
-        self.put_out_shares(task_id, part_batch_sel, out_shares)
+        let events = self
+            .put_out_shares(task_id, part_batch_sel, out_shares)
             .await?;
+        self.emit_batch_events(events).await;
 
         metrics.report_inc_by("aggregated", out_shares_count);
+        metrics.agg_job_duration_observe(self.get_current_time_millis().saturating_sub(start));
+        Span::current().record("reports_aggregated", out_shares_count);
         Ok(out_shares_count)
     }
 
     /// Handle a pending collect request. If the results are ready, then compute the aggregate
     /// results and store them to be retrieved by the Collector later. Returns the number of
     /// reports in the batch.
+    #[instrument(skip_all, fields(%task_id, collect_job_id = %collect_id, report_count))]
     async fn run_collect_job(
         &self,
         task_id: &TaskId,
         collect_id: &CollectionJobId,
         task_config: &DapTaskConfig,
         collect_req: &CollectionReq,
+        collector_id: Option<&str>,
         host: &str,
     ) -> Result<u64, DapAbort> {
-        let metrics = self.metrics().with_host(host);
+        let metrics = self.metrics().with_host(host).with_task(task_id);
 
         debug!("collecting id {collect_id}");
         let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
-        let leader_agg_share = self.get_agg_share(task_id, &batch_selector).await?;
+        let mut leader_agg_share = self.get_agg_share(task_id, &batch_selector).await?;
+        let report_counts = self.get_report_counts(task_id, &batch_selector).await?;
+        let forced = self.is_batch_forced(task_id, &batch_selector).await?;
 
         // Check the batch size. If not not ready, then return early.
         //
         // TODO Consider logging this error, as it should never happen.
-        if !task_config.is_report_count_compatible(task_id, leader_agg_share.report_count)? {
+        if !task_config.is_report_count_compatible(
+            task_id,
+            &leader_agg_share,
+            self.get_current_time(),
+            forced,
+        )? {
             return Ok(0);
         }
 
         let batch_selector = BatchSelector::try_from(collect_req.query.clone())?;
-
-        // Prepare the Leader's aggregate share.
+        let collector_hpke_config = task_config
+            .collector_hpke_config_for(collector_id)
+            .ok_or_else(|| DapError::fatal("collect job has unrecognized collector"))?;
+
+        // Prepare the Leader's aggregate share. If the Collector has queried this batch before,
+        // then answer with the same aggregate share, noise included.
+        let collected_agg_share = self
+            .get_collected_agg_share(task_id, &batch_selector, collector_id)
+            .await?;
+        match collected_agg_share {
+            Some(agg_share) => leader_agg_share = agg_share,
+            None => task_config.dp.add_noise(&mut leader_agg_share)?,
+        }
         let leader_enc_agg_share = task_config.vdaf.produce_leader_encrypted_agg_share(
-            &task_config.collector_hpke_config,
+            collector_hpke_config,
             task_id,
             &batch_selector,
             &leader_agg_share,
@@ -651,14 +1678,15 @@ where
             DapMediaType::AggregateShare,
             DapResource::Undefined,
             agg_share_req.get_encoded_with_param(&task_config.version),
-            false
+            false,
+            collector_id.map(str::to_string)
         );
         let agg_share_resp = AggregateShare::get_decoded(&resp.payload)?;
         // For draft04 and later, the Collection message includes the smallest quantized time
         // interval containing all reports in the batch.
         let interval = match task_config.version {
             DapVersion::Draft02 => None,
-            DapVersion::Draft04 => {
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
                 let low = task_config.quantized_time_lower_bound(leader_agg_share.min_time);
                 let high = task_config.quantized_time_upper_bound(leader_agg_share.max_time);
                 Some(Interval {
@@ -674,90 +1702,705 @@ where
             _ => unreachable!("unhandled version {}", task_config.version),
         };
 
-        // Complete the collect job.
-        let collection = Collection {
-            part_batch_sel: batch_selector.into(),
-            report_count: leader_agg_share.report_count,
-            interval,
-            encrypted_agg_shares: vec![leader_enc_agg_share, agg_share_resp.encrypted_agg_share],
-        };
-        self.finish_collect_job(task_id, collect_id, &collection)
-            .await?;
+        // Complete the collect job.
+        let collection = Collection {
+            part_batch_sel: batch_selector.into(),
+            report_count: leader_agg_share.report_count,
+            interval,
+            encrypted_agg_shares: vec![leader_enc_agg_share, agg_share_resp.encrypted_agg_share],
+        };
+        let report_counts =
+            DapReportCountBreakdown::new(task_id, &collection.part_batch_sel, report_counts);
+        if report_counts.report_count() != collection.report_count {
+            warn!(
+                "report count of batch {:?} of task {task_id} ({}) does not match its buckets ({})",
+                agg_share_req.batch_sel,
+                collection.report_count,
+                report_counts.report_count()
+            );
+        }
+        self.put_collected_agg_share(
+            task_id,
+            &agg_share_req.batch_sel,
+            collector_id,
+            &leader_agg_share,
+        )
+        .await?;
+        self.finish_collect_job(
+            task_id,
+            collect_id,
+            &agg_share_req.batch_sel,
+            &collection,
+            &report_counts,
+        )
+        .await?;
+
+        // Mark reports as collected.
+        self.mark_collected(task_id, &agg_share_req.batch_sel, collector_id)
+            .await?;
+        self.emit_batch_events(DapBatchEvent::for_collection(
+            task_id,
+            &agg_share_req.batch_sel,
+            &report_counts,
+        ))
+        .await;
+        info!(
+            "collector {} collected batch {:?} of task {task_id} ({} reports)",
+            collector_id.unwrap_or("(primary)"),
+            agg_share_req.batch_sel,
+            agg_share_req.report_count
+        );
+
+        if let Err(e) = self
+            .on_collect_job_finished(
+                task_id,
+                task_config,
+                collect_id,
+                &agg_share_req.batch_sel,
+                &collection,
+            )
+            .await
+        {
+            warn!("post-collection hook failed for collect job {collect_id}: {e}");
+        }
+
+        metrics.report_inc_by("collected", agg_share_req.report_count);
+        Span::current().record("report_count", agg_share_req.report_count);
+        Ok(agg_share_req.report_count)
+    }
+
+    /// Fetch a set of reports grouped by task, then run an aggregation job for each task. once all
+    /// jobs completed, process the collect job queue. It is not safe to run multiple instances of
+    /// this function in parallel.
+    ///
+    /// This method is geared primarily towards testing. It also demonstrates how to properly
+    /// synchronize collect and aggregation jobs. If used in a large DAP deployment, it is likely
+    /// create a bottleneck. Such deployments can improve throughput by running many aggregation
+    /// jobs in parallel.
+    #[instrument(
+        skip_all,
+        fields(reports_processed, reports_aggregated, reports_collected)
+    )]
+    async fn process(
+        &'srv self,
+        selector: &Self::ReportSelector,
+        host: &str,
+    ) -> Result<DapLeaderProcessTelemetry, DapAbort> {
+        let mut telem = DapLeaderProcessTelemetry::default();
+
+        // Fetch reports and run an aggregation job for each task.
+        for (task_id, reports) in self.get_reports(selector).await?.into_iter() {
+            let task_config = self
+                .get_task_config_for(Cow::Owned(task_id.clone()))
+                .await?
+                .ok_or(DapAbort::UnrecognizedTask)?;
+
+            for (part_batch_sel, reports) in reports.into_iter() {
+                // TODO Consider splitting reports into smaller chunks.
+                // TODO Consider handling tasks in parallel.
+                telem.reports_processed += reports.len() as u64;
+                debug!(
+                    "process {} reports for task {task_id} with selector {part_batch_sel:?}",
+                    reports.len()
+                );
+                if !reports.is_empty() {
+                    // A failed job returns its reports to storage, so move on to the next one
+                    // rather than dropping the reports of the jobs that have not run yet.
+                    match self
+                        .run_agg_job(
+                            &task_id,
+                            task_config.as_ref(),
+                            &part_batch_sel,
+                            reports,
+                            host,
+                        )
+                        .await
+                    {
+                        Ok(reports_aggregated) => telem.reports_aggregated += reports_aggregated,
+                        Err(e) => error!(
+                            "aggregation job for task {task_id} with selector {part_batch_sel:?} failed: {e}"
+                        ),
+                    }
+                }
+            }
+        }
+        // Process pending collect jobs. We wait until all aggregation jobs are finished before
+        // proceeding to this step. This is to prevent a race condition involving an aggregate
+        // share computed during a collect job and any output shares computed during an aggregation
+        // job.
+        //
+        // Jobs are run in rounds, each of which consists of collection jobs for non-overlapping
+        // batches. The slots of each round are shared fairly among the tasks.
+        let metrics = self.metrics().with_host(host);
+        let global_config = self.get_global_config();
+        let mut scheduler = DapCollectJobScheduler::new(
+            self.get_pending_collect_jobs().await?,
+            global_config.max_concurrent_collect_jobs.unwrap_or(1),
+        );
+        if let Some(max_concurrent_per_task) = global_config.max_concurrent_collect_jobs_per_task {
+            scheduler = scheduler.with_max_concurrent_per_task(max_concurrent_per_task);
+        }
+        let start = self.get_current_time_millis();
+        loop {
+            let round = scheduler.next_round();
+            if round.is_empty() {
+                break;
+            }
+            let wait = self.get_current_time_millis().saturating_sub(start);
+            for _ in &round {
+                metrics.collect_job_queue_wait_observe(wait);
+            }
+
+            let reports_collected = try_join_all(round.iter().map(|index| {
+                let (task_id, collect_id, collect_req, collector_id) =
+                    scheduler.job(*index).clone();
+                async move {
+                    let task_config = self
+                        .get_task_config_for(Cow::Owned(task_id.clone()))
+                        .await?
+                        .ok_or(DapAbort::UnrecognizedTask)?;
+
+                    self.run_collect_job(
+                        &task_id,
+                        &collect_id,
+                        task_config.as_ref(),
+                        &collect_req,
+                        collector_id.as_deref(),
+                        host,
+                    )
+                    .await
+                }
+            }))
+            .await?;
+            telem.reports_collected += reports_collected.iter().sum::<u64>();
+
+            for index in round {
+                scheduler.finish(index)?;
+            }
+        }
+
+        let span = Span::current();
+        span.record("reports_processed", telem.reports_processed);
+        span.record("reports_aggregated", telem.reports_aggregated);
+        span.record("reports_collected", telem.reports_collected);
+        Ok(telem)
+    }
+}
+
+/// Leader: Check a report uploaded by a Client and store it for future processing. At this point,
+/// the report may be rejected if the Leader detects that the report was replayed or pertains to a
+/// batch that has already been collected.
+/// Leader: Check that the Client presented the credential the task requires for uploads, if any.
+/// Unrecognized tasks are left to the checks of the report itself.
+async fn check_upload_auth<'srv, 'req, S, L>(
+    leader: &'srv L,
+    req: &'req DapRequest<S>,
+) -> Result<(), DapAbort>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    let task_id = req.task_id()?;
+    let task_config = match leader.get_task_config_for(Cow::Borrowed(task_id)).await? {
+        Some(task_config) => task_config,
+        None => return Ok(()),
+    };
+    if let Some(upload_auth) = &task_config.as_ref().upload_auth {
+        if let Some(reason) =
+            upload_auth.unauthorized_reason(task_id, &req.payload, req.client_auth.as_ref())
+        {
+            error!("aborted unauthorized upload request: {reason}");
+            return Err(DapAbort::UnauthorizedRequest {
+                detail: reason,
+                task_id: task_id.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Leader: Handle a report being uploaded and sample its metadata if
+/// [`DapGlobalConfig::report_sampling`] is set.
+async fn upload_report<'srv, 'req, S, L>(
+    leader: &'srv L,
+    req: &'req DapRequest<S>,
+    report: &Report,
+) -> Result<(), DapAbort>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    let result = check_and_put_report(leader, req, report).await;
+    if let Some(sampling) = leader.get_global_config().report_sampling.as_ref() {
+        if thread_rng().gen_bool(sampling.rate.clamp(0.0, 1.0)) {
+            let task_id = req.task_id()?;
+            let now = leader.get_current_time();
+            let sample = DapReportSample {
+                report_id: report.report_metadata.id.clone(),
+                time_skew: i64::try_from(report.report_metadata.time)
+                    .unwrap_or(i64::MAX)
+                    .saturating_sub(i64::try_from(now).unwrap_or(i64::MAX)),
+                extensions: report
+                    .report_metadata
+                    .extensions
+                    .iter()
+                    .map(Extension::type_code)
+                    .collect(),
+                taskprov_header: req.taskprov.is_some(),
+                hpke_config_ids: report
+                    .encrypted_input_shares
+                    .iter()
+                    .map(|share| share.config_id)
+                    .collect(),
+                rejected: result.as_ref().err().map(|e| match e.abort_type() {
+                    Some(abort_type) => abort_type.as_str().to_string(),
+                    None => "other".to_string(),
+                }),
+            };
+            if let Err(e) = leader.put_report_sample(task_id, &sample).await {
+                error!(
+                    "failed to record sample of report {}: {e}",
+                    sample.report_id
+                );
+            }
+        }
+    }
+    result
+}
+
+/// Leader: The idempotency key of an upload request. See [`DapRequest::idempotency_key`].
+struct UploadIdempotencyKey<'req> {
+    key: &'req str,
+
+    /// SHA-256 digest of the request payload.
+    digest: [u8; 32],
+
+    /// Record of the upload request with the same payload that was already handled under the
+    /// key, if any.
+    record: Option<DapUploadRecord>,
+}
+
+/// Leader: Check the idempotency key of an upload request, if any. The request is aborted if the
+/// key is malformed or if it was used for an upload request with a different payload.
+async fn check_idempotency_key<'srv, 'req, S, L>(
+    leader: &'srv L,
+    req: &'req DapRequest<S>,
+) -> Result<Option<UploadIdempotencyKey<'req>>, DapAbort>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    let key = match req.idempotency_key.as_deref() {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    if key.is_empty()
+        || key.len() > 64
+        || !key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(DapAbort::BadRequest("malformed idempotency key".into()));
+    }
+
+    let digest: [u8; 32] = digest(&SHA256, &req.payload)
+        .as_ref()
+        .try_into()
+        .expect("SHA-256 digest has unexpected length");
+    let record = match leader.get_upload_record(req.task_id()?, key).await? {
+        Some(record) if record.digest == digest => Some(record),
+        Some(_) => return Err(DapAbort::idempotency_key_conflict(key)),
+        None => None,
+    };
+    Ok(Some(UploadIdempotencyKey {
+        key,
+        digest,
+        record,
+    }))
+}
+
+/// Leader: Upload a report under an idempotency key. If the same report was already accepted under
+/// the key, then the upload is a retry and succeeds without storing the report again. The request
+/// is recorded as pending before the report is stored, so that a retry of a request whose outcome
+/// could not be recorded knows that the report may have been stored by the original request. An
+/// upload rejected for any reason other than a replay stores nothing and leaves the request
+/// pending, so a retry of it is handled like a new request.
+async fn upload_report_idempotent<'srv, 'req, S, L>(
+    leader: &'srv L,
+    req: &'req DapRequest<S>,
+    report: &Report,
+    idempotency: &UploadIdempotencyKey<'req>,
+) -> Result<(), DapAbort>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    let task_id = req.task_id()?;
+    let report_id = report.report_metadata.id.to_base64url();
+    let pending_record = DapUploadRecord {
+        digest: idempotency.digest,
+        statuses: vec![None],
+    };
+    let record = match &idempotency.record {
+        Some(record) => Some(record.clone()),
+        None => {
+            match leader
+                .put_upload_record(
+                    task_id,
+                    idempotency.key,
+                    &pending_record,
+                    report.report_metadata.time,
+                )
+                .await?
+            {
+                Some(record) if record.digest != idempotency.digest => {
+                    return Err(DapAbort::idempotency_key_conflict(idempotency.key))
+                }
+                record => record,
+            }
+        }
+    };
+    if record.as_ref().is_some_and(upload_accepted) {
+        debug!(
+            "upload of report {} retried with idempotency key {}",
+            report.report_metadata.id, idempotency.key
+        );
+        return Ok(());
+    }
+
+    // If the outcome of an earlier attempt of the request is not known, then the attempt may have
+    // stored the report before it failed, in which case the report is not rejected as replayed.
+    let retried = record.is_some_and(|record| record.statuses.iter().all(Option::is_none));
+    let result = match upload_report(leader, req, report).await {
+        Err(DapAbort::ReportReplayed) if retried => Ok(()),
+        // A concurrent attempt of the same request may have been accepted in the meantime, in
+        // which case its outcome is replayed. Otherwise the report was uploaded by another request.
+        Err(DapAbort::ReportReplayed) => {
+            match leader.get_upload_record(task_id, idempotency.key).await? {
+                Some(record) if record.digest == idempotency.digest && upload_accepted(&record) => {
+                    return Ok(())
+                }
+                _ => Err(DapAbort::ReportReplayed),
+            }
+        }
+        result => result,
+    };
+
+    // The report has been handled at this point, so failing to record the outcome does not fail
+    // the upload: a retry finds the request pending. Only an accepted or replayed report is
+    // recorded. A report that was rejected otherwise was not stored, so a retry may upload it.
+    let problem = match &result {
+        Ok(()) => None,
+        Err(DapAbort::ReportReplayed) => Some(DapAbort::ReportReplayed.into_problem_details(None)),
+        Err(_) => return result,
+    };
+    let record = DapUploadRecord {
+        digest: idempotency.digest,
+        statuses: vec![Some(DapReportUploadStatus {
+            report_id: Some(report_id),
+            problem,
+        })],
+    };
+    if let Err(e) = leader
+        .put_upload_record(
+            task_id,
+            idempotency.key,
+            &record,
+            report.report_metadata.time,
+        )
+        .await
+    {
+        error!(
+            "failed to record upload of report {} with idempotency key {}: {e}",
+            report.report_metadata.id, idempotency.key
+        );
+    }
+    result
+}
+
+/// Leader: Outcome of uploading one of the reports of a batched upload request.
+struct ReportUploadOutcome {
+    status: DapReportUploadStatus,
+
+    /// Whether the report failed for a transient reason, in which case a retry of the request
+    /// uploads it again.
+    transient: bool,
+}
+
+/// Leader: Upload each of the reports of a batched upload request. Returns the outcome for each
+/// report and the latest timestamp of the reports that were decoded, if any. If the request is a
+/// retry, then `recorded` is the outcome of each report that the original request recorded. A
+/// report whose outcome was recorded is not uploaded again.
+async fn upload_reports<'srv, 'req, S, L>(
+    leader: &'srv L,
+    req: &'req DapRequest<S>,
+    encoded_reports: Vec<Vec<u8>>,
+    recorded: &[Option<DapReportUploadStatus>],
+) -> (Vec<ReportUploadOutcome>, Option<Time>)
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    let mut outcomes = Vec::with_capacity(encoded_reports.len());
+    let mut report_ids = HashSet::with_capacity(encoded_reports.len());
+    let mut latest_report_time = None;
+    for (index, encoded_report) in encoded_reports.into_iter().enumerate() {
+        let (report_id, result) =
+            match Report::get_decoded_with_param(&req.version, &encoded_report) {
+                // Only the first of the reports with the same ID is uploaded.
+                Ok(report) if !report_ids.insert(report.report_metadata.id.clone()) => (
+                    Some(report.report_metadata.id.to_base64url()),
+                    Err(DapAbort::report_duplicated(&report.report_metadata.id)),
+                ),
+                Ok(report) => {
+                    latest_report_time = latest_report_time.max(Some(report.report_metadata.time));
+                    let result = match recorded.get(index) {
+                        Some(Some(status)) => {
+                            outcomes.push(ReportUploadOutcome {
+                                status: status.clone(),
+                                transient: false,
+                            });
+                            continue;
+                        }
+                        // The original request may have stored the report before it failed, in
+                        // which case it is not rejected as replayed.
+                        Some(None) => match upload_report(leader, req, &report).await {
+                            Err(DapAbort::ReportReplayed) => Ok(()),
+                            result => result,
+                        },
+                        None => upload_report(leader, req, &report).await,
+                    };
+                    (Some(report.report_metadata.id.to_base64url()), result)
+                }
+                Err(e) => (None, Err(e.into())),
+            };
+        outcomes.push(ReportUploadOutcome {
+            transient: result.as_ref().is_err_and(upload_failed_transiently),
+            status: DapReportUploadStatus {
+                report_id,
+                problem: result.err().map(|e| e.into_problem_details(None)),
+            },
+        });
+    }
+    (outcomes, latest_report_time)
+}
+
+/// Leader: Upload the reports of a batched upload request under an idempotency key. If the same
+/// request was already handled under the key, then the upload is a retry and the outcome of each
+/// report is replayed from the record without uploading the report again. Only the reports that
+/// failed for a transient reason are uploaded again.
+async fn upload_reports_idempotent<'srv, 'req, S, L>(
+    leader: &'srv L,
+    req: &'req DapRequest<S>,
+    encoded_reports: Vec<Vec<u8>>,
+    idempotency: UploadIdempotencyKey<'req>,
+) -> Result<Vec<DapReportUploadStatus>, DapAbort>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    if let Some(record) = idempotency.record {
+        debug!(
+            "batched upload retried with idempotency key {}",
+            idempotency.key
+        );
+        // The record is not updated by the retry. Each retry uploads the reports that failed for
+        // a transient reason again.
+        let (outcomes, _latest_report_time) =
+            upload_reports(leader, req, encoded_reports, &record.statuses).await;
+        return Ok(outcomes.into_iter().map(|outcome| outcome.status).collect());
+    }
+
+    let (outcomes, latest_report_time) = upload_reports(leader, req, encoded_reports, &[]).await;
+    let report_time = match latest_report_time {
+        Some(report_time) => report_time,
+        // No report was decoded, so none was stored.
+        None => return Ok(outcomes.into_iter().map(|outcome| outcome.status).collect()),
+    };
+    let record = DapUploadRecord {
+        digest: idempotency.digest,
+        statuses: outcomes
+            .iter()
+            .map(|outcome| (!outcome.transient).then(|| outcome.status.clone()))
+            .collect(),
+    };
+    let statuses = outcomes.into_iter().map(|outcome| outcome.status);
+    match leader
+        .put_upload_record(req.task_id()?, idempotency.key, &record, report_time)
+        .await?
+    {
+        // A concurrent attempt of the same request was recorded first. The reports it accepted
+        // were rejected as replayed by this attempt, so its outcome is returned instead, except for
+        // the reports it failed to upload.
+        Some(stored_record) if stored_record.digest == record.digest => Ok(stored_record
+            .statuses
+            .into_iter()
+            .zip(statuses)
+            .map(|(stored_status, status)| stored_status.unwrap_or(status))
+            .collect()),
+        _ => Ok(statuses.collect()),
+    }
+}
+
+/// Leader: Whether each report of the recorded upload request was accepted.
+fn upload_accepted(record: &DapUploadRecord) -> bool {
+    record.statuses.iter().all(|status| {
+        status
+            .as_ref()
+            .is_some_and(|status| status.problem.is_none())
+    })
+}
+
+/// Leader: Check whether a report failed to be uploaded for a reason that may not recur, e.g., a
+/// storage error.
+fn upload_failed_transiently(e: &DapAbort) -> bool {
+    matches!(
+        e,
+        DapAbort::Internal(..) | DapAbort::RetryLater { .. } | DapAbort::Overloaded { .. }
+    )
+}
 
-        // Mark reports as collected.
-        self.mark_collected(task_id, &agg_share_req.batch_sel)
-            .await?;
+/// Leader: Check a report being uploaded and store it if it is valid.
+async fn check_and_put_report<'srv, 'req, S, L>(
+    leader: &'srv L,
+    req: &'req DapRequest<S>,
+    report: &Report,
+) -> Result<(), DapAbort>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    leader
+        .check_upload_metadata(
+            req.version,
+            req.task_id()?,
+            req.taskprov.as_deref(),
+            &report.report_metadata,
+        )
+        .await?;
 
-        metrics.report_inc_by("collected", agg_share_req.report_count);
-        Ok(agg_share_req.report_count)
+    if report.encrypted_input_shares.len() != 2 {
+        // TODO spec: Decide if this behavior should be specified.
+        return Err(DapAbort::UnrecognizedMessage);
     }
 
-    /// Fetch a set of reports grouped by task, then run an aggregation job for each task. once all
-    /// jobs completed, process the collect job queue. It is not safe to run multiple instances of
-    /// this function in parallel.
-    ///
-    /// This method is geared primarily towards testing. It also demonstrates how to properly
-    /// synchronize collect and aggregation jobs. If used in a large DAP deployment, it is likely
-    /// create a bottleneck. Such deployments can improve throughput by running many aggregation
-    /// jobs in parallel.
-    async fn process(
-        &'srv self,
-        selector: &Self::ReportSelector,
-        host: &str,
-    ) -> Result<DapLeaderProcessTelemetry, DapAbort> {
-        let mut telem = DapLeaderProcessTelemetry::default();
+    // Check that the indicated HpkeConfig is present.
+    //
+    // TODO spec: It's not clear if this behavior is MUST, SHOULD, or MAY.
+    if !leader
+        .can_hpke_decrypt(req.task_id()?, report.encrypted_input_shares[0].config_id)
+        .await?
+    {
+        return Err(DapAbort::report_rejected(
+            TransitionFailure::HpkeUnknownConfigId,
+        ));
+    }
 
-        // Fetch reports and run an aggregation job for each task.
-        for (task_id, reports) in self.get_reports(selector).await?.into_iter() {
-            let task_config = self
-                .get_task_config_for(Cow::Owned(task_id.clone()))
-                .await?
-                .ok_or(DapAbort::UnrecognizedTask)?;
+    leader.put_report(report, req.task_id()?).await?;
+    Ok(())
+}
 
-            for (part_batch_sel, reports) in reports.into_iter() {
-                // TODO Consider splitting reports into smaller chunks.
-                // TODO Consider handling tasks in parallel.
-                telem.reports_processed += reports.len() as u64;
-                debug!(
-                    "process {} reports for task {task_id} with selector {part_batch_sel:?}",
-                    reports.len()
-                );
-                if !reports.is_empty() {
-                    telem.reports_aggregated += self
-                        .run_agg_job(
-                            &task_id,
-                            task_config.as_ref(),
-                            &part_batch_sel,
-                            reports,
-                            host,
-                        )
-                        .await?;
-                }
+/// Helper: Reserve a slot for the given aggregation job if the number of running aggregation jobs
+/// is limited. If every slot is taken, then the request is queued until a slot is released or the
+/// queue timeout is reached, in which case the request is rejected with
+/// [`DapAbort::RetryLater`], or [`DapAbort::Overloaded`] if the limit specifies how long the
+/// Leader should back off.
+async fn wait_for_agg_job_slot<'srv, 'req, S, H>(
+    helper: &H,
+    task_id: &TaskId,
+    agg_job_id: &MetaAggregationJobId<'_>,
+    metrics: &ContextualizedDaphneMetrics<'_>,
+) -> Result<(), DapAbort>
+where
+    'srv: 'req,
+    H: DapHelper<'srv, 'req, S>,
+{
+    let limit = match helper.get_global_config().helper_agg_job_limit.as_ref() {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+
+    if helper
+        .try_acquire_agg_job_slot(task_id, agg_job_id, limit.max_running)
+        .await?
+    {
+        return Ok(());
+    }
+
+    let deadline = helper
+        .get_current_time_millis()
+        .saturating_add(limit.queue_timeout_ms);
+    metrics.agg_job_queue_inc();
+    let acquired: Result<bool, DapError> = async {
+        while helper.get_current_time_millis() < deadline {
+            helper
+                .sleep(std::time::Duration::from_millis(
+                    limit.queue_poll_interval_ms,
+                ))
+                .await;
+            if helper
+                .try_acquire_agg_job_slot(task_id, agg_job_id, limit.max_running)
+                .await?
+            {
+                return Ok(true);
             }
         }
-        // Process pending collect jobs. We wait until all aggregation jobs are finished before
-        // proceeding to this step. This is to prevent a race condition involving an aggregate
-        // share computed during a collect job and any output shares computed during an aggregation
-        // job.
-        for (task_id, collect_id, collect_req) in self.get_pending_collect_jobs().await? {
-            let task_config = self
-                .get_task_config_for(Cow::Owned(task_id.clone()))
-                .await?
-                .ok_or(DapAbort::UnrecognizedTask)?;
+        Ok(false)
+    }
+    .await;
+    metrics.agg_job_queue_dec();
 
-            telem.reports_collected += self
-                .run_collect_job(
-                    &task_id,
-                    &collect_id,
-                    task_config.as_ref(),
-                    &collect_req,
-                    host,
-                )
-                .await?;
-        }
+    if acquired? {
+        return Ok(());
+    }
+    let detail = format!(
+        "limit of {} running aggregation jobs reached",
+        limit.max_running
+    );
+    match limit.retry_after {
+        Some(retry_after) => Err(DapAbort::Overloaded {
+            detail,
+            retry_after,
+        }),
+        None => Err(DapAbort::RetryLater { detail }),
+    }
+}
 
-        Ok(telem)
+/// Helper: Check the size of the state that the Helper would store for the aggregation job
+/// initialized by the given request against the limit configured by
+/// [`DapGlobalConfig::helper_state_store`]. This is checked before the reports are prepared,
+/// assuming that none of them is rejected. Return the time at which the state expires, if any. A
+/// request whose state would exceed the size limit is rejected with [`DapAbort::BadRequest`].
+fn check_helper_state<'srv, 'req, S, H>(
+    helper: &H,
+    task_config: &DapTaskConfig,
+    agg_job_init_req: &AggregationJobInitReq,
+    metrics: &ContextualizedDaphneMetrics<'_>,
+) -> Result<Option<Time>, DapAbort>
+where
+    'srv: 'req,
+    H: DapHelper<'srv, 'req, S>,
+{
+    let config = match helper.get_global_config().helper_state_store.as_ref() {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    let encoded_len = u64::try_from(DapHelperState::encoded_len(
+        &task_config.vdaf,
+        &agg_job_init_req.part_batch_sel,
+        agg_job_init_req.report_shares.len(),
+    ))
+    .unwrap();
+    if encoded_len > config.max_encoded_len {
+        metrics.helper_state_inc("rejected");
+        return Err(DapAbort::BadRequest(format!(
+            "aggregation job state of {encoded_len} bytes exceeds the limit of {} bytes",
+            config.max_encoded_len
+        )));
     }
+
+    Ok(Some(helper.get_current_time().saturating_add(config.ttl)))
 }
 
 /// DAP Helper functionality.
@@ -766,38 +2409,68 @@ pub trait DapHelper<'srv, 'req, S>: DapAggregator<'srv, 'req, S>
 where
     'srv: 'req,
 {
-    /// Store the Helper's aggregation-flow state.
+    /// Store the Helper's aggregation-flow state. If `expiration` is set, then the state must not
+    /// be returned by [`Self::get_helper_state`] at or after that time and may be deleted.
     async fn put_helper_state(
         &self,
         task_id: &TaskId,
         agg_job_id: &MetaAggregationJobId,
         helper_state: &DapHelperState,
+        expiration: Option<Time>,
     ) -> Result<(), DapError>;
 
-    /// Fetch the Helper's aggregation-flow state. `None` is returned if the Helper has no state
-    /// associated with the given task and aggregation job.
+    /// Fetch and delete the Helper's aggregation-flow state. `None` is returned if the Helper has
+    /// no state associated with the given task and aggregation job or if the state has expired.
     async fn get_helper_state(
         &self,
         task_id: &TaskId,
         agg_job_id: &MetaAggregationJobId,
     ) -> Result<Option<DapHelperState>, DapError>;
 
+    /// Try to reserve one of the `max_running` slots for running aggregation jobs for the given
+    /// aggregation job. Returns `false` if all of the slots are taken. This is only called if
+    /// [`DapGlobalConfig::helper_agg_job_limit`] is set.
+    async fn try_acquire_agg_job_slot(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
+        max_running: u64,
+    ) -> Result<bool, DapError>;
+
+    /// Release the slot reserved for the given aggregation job, if any.
+    async fn release_agg_job_slot(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
+    ) -> Result<(), DapError>;
+
     /// Handle an HTTP POST to `/aggregate`. The input is either an AggregationJobInitReq or
     /// AggregationJobContinueReq and the response is an AggregationJobResp.
     ///
     /// This is called during the Initialization and Continuation phases.
+    #[instrument(skip_all, fields(task_id, agg_job_id, report_count))]
     async fn http_post_aggregate(
         &'srv self,
         req: &'req DapRequest<S>,
     ) -> Result<DapResponse, DapAbort> {
+        let start = self.get_current_time_millis();
         let metrics = self.metrics().with_host(req.host());
 
         // Check whether the DAP version indicated by the sender is supported.
         if req.version == DapVersion::Unknown {
             return Err(DapAbort::version_unknown());
         }
+        check_sender_version(req)?;
 
         let task_id = req.task_id()?;
+        Span::current().record("task_id", task_id.to_string());
+        let metrics = metrics.with_task(task_id);
+        check_request_size(
+            self.get_global_config(),
+            req,
+            DaphneRequestType::Aggregate,
+            &metrics,
+        )?;
 
         if let Some(reason) = self.unauthorized_reason(req).await? {
             error!("aborted unauthorized collect request: {reason}");
@@ -817,14 +2490,16 @@ where
                 // If taskprov is allowed, ensure that either all of the shares have it or none of them
                 // do (section 6 of draft-wang-ppm-dap-taskprov-02).
                 let global_config = self.get_global_config();
+                let taskprov_version = resolve_taskprov_version(
+                    global_config.taskprov_version,
+                    req.taskprov.as_deref(),
+                );
                 if global_config.allow_taskprov {
                     let using_taskprov = agg_job_init_req
                         .report_shares
                         .iter()
                         .filter(|share| {
-                            share
-                                .report_metadata
-                                .is_taskprov(global_config.taskprov_version, task_id)
+                            share.report_metadata.is_taskprov(taskprov_version, task_id)
                         })
                         .count();
 
@@ -845,6 +2520,7 @@ where
                     .get_task_config_considering_taskprov(
                         req.version,
                         Cow::Borrowed(task_id),
+                        req.taskprov.as_deref(),
                         first_metadata,
                     )
                     .await?
@@ -862,15 +2538,25 @@ where
                     (DapVersion::Draft02, DapResource::Undefined, Some(ref agg_job_id)) => {
                         MetaAggregationJobId::Draft02(Cow::Borrowed(agg_job_id))
                     }
-                    (DapVersion::Draft04, DapResource::AggregationJob(ref agg_job_id), None) => {
-                        MetaAggregationJobId::Draft04(Cow::Borrowed(agg_job_id))
-                    }
-                    (DapVersion::Draft04, DapResource::Undefined, None) => {
+                    (
+                        DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                        DapResource::AggregationJob(ref agg_job_id),
+                        None,
+                    ) => MetaAggregationJobId::Draft04(Cow::Borrowed(agg_job_id)),
+                    (
+                        DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                        DapResource::Undefined,
+                        None,
+                    ) => {
                         return Err(DapAbort::BadRequest("undefined resource".into()));
                     }
                     _ => unreachable!("unhandled resource {:?}", req.resource),
                 };
 
+                let span = Span::current();
+                span.record("agg_job_id", agg_job_id.to_base64url());
+                span.record("report_count", agg_job_init_req.report_shares.len());
+
                 let helper_state = self.get_helper_state(task_id, &agg_job_id);
 
                 // Check whether the DAP version in the request matches the task config.
@@ -878,6 +2564,20 @@ where
                     return Err(DapAbort::version_mismatch(req.version, task_config.version));
                 }
 
+                // Each report may only appear once in the aggregation job.
+                let mut report_ids = HashSet::with_capacity(agg_job_init_req.report_shares.len());
+                for report_share in agg_job_init_req.report_shares.iter() {
+                    if !report_ids.insert(&report_share.report_metadata.id) {
+                        return Err(DapAbort::report_duplicated(
+                            &report_share.report_metadata.id,
+                        ));
+                    }
+                }
+
+                // Prepare the reports with the VDAF verification key selected by the Leader.
+                let keyed_task_config =
+                    task_config.with_vdaf_verify_key_id(req.vdaf_verify_key_id)?;
+
                 // Ensure we know which batch the request pertains to.
                 check_part_batch(
                     task_id,
@@ -895,13 +2595,27 @@ where
                         .map(|report_share| &report_share.report_metadata),
                 );
 
+                // In draft06, the Helper finishes preparation right away, so no state is stored for
+                // the aggregation job.
+                let expiration = if task_config.version == DapVersion::Draft06 {
+                    None
+                } else {
+                    check_helper_state(self, task_config, &agg_job_init_req, &metrics)?
+                };
+
                 let transition = task_config
                     .vdaf
                     .handle_agg_job_init_req(
                         self,
+                        self.extension_registry(),
                         task_id,
-                        task_config,
+                        &keyed_task_config,
                         &agg_job_init_req,
+                        self.get_global_config()
+                            .helper_prep_concurrency
+                            .map_or(1, |concurrency| {
+                                concurrency.try_into().unwrap_or(usize::MAX)
+                            }),
                         &metrics,
                     )
                     .await?;
@@ -915,56 +2629,102 @@ where
                     ));
                 }
 
+                // The aggregation job is running once the Helper's state is stored. If the number
+                // of running jobs is limited, then wait for a slot before the reports are marked
+                // as processed, so that the Leader may send them again if the request is
+                // rejected.
+                let holds_slot = matches!(transition, DapHelperTransition::Continue(..))
+                    && self.get_global_config().helper_agg_job_limit.is_some();
+                if holds_slot {
+                    wait_for_agg_job_slot(self, task_id, &agg_job_id, &metrics).await?;
+                }
+                let early_rejects = match early_rejects_future.await {
+                    Ok(early_rejects) => early_rejects,
+                    Err(e) => {
+                        if holds_slot {
+                            self.release_agg_job_slot(task_id, &agg_job_id).await?;
+                        }
+                        return Err(e.into());
+                    }
+                };
+                count_skewed_reports(
+                    self.get_global_config(),
+                    self.get_current_time(),
+                    agg_job_init_req
+                        .report_shares
+                        .iter()
+                        .map(|report_share| &report_share.report_metadata),
+                    &early_rejects,
+                    &metrics,
+                );
+
                 let agg_job_resp = match transition {
                     DapHelperTransition::Continue(mut state, mut agg_job_resp) => {
-                        // Filter out early rejected reports.
-                        let early_rejects = early_rejects_future.await?;
-                        let mut state_index = 0;
-                        for transition in agg_job_resp.transitions.iter_mut() {
-                            let early_failure = early_rejects.get(&transition.report_id);
-                            if !matches!(transition.var, TransitionVar::Failed(..))
-                                && early_failure.is_some()
-                            {
-                                // NOTE(cjpatton) Clippy wants us to use and `if let` statement to
-                                // unwrap `early_failure`. I don't think this works becauase we
-                                // only want to enter this loop if `early_failure.is_some()` and
-                                // the current `transition` is not a failure. As far as I know, `if
-                                // let` statements can't yet be combined with other conditions.
-                                #[allow(clippy::unnecessary_unwrap)]
-                                let failure = early_failure.unwrap();
-                                transition.var = TransitionVar::Failed(*failure);
-
-                                // Remove VDAF preparation state of reports that were rejected early.
-                                if transition.report_id == state.seq[state_index].2 {
-                                    let _val = state.seq.remove(state_index);
-                                } else {
-                                    // The report ID in the Helper state and Aggregate response
-                                    // must be aligned. If not, handle as an internal error.
-                                    return Err(DapError::fatal("report IDs not aligned").into());
-                                }
-
-                                // NOTE(cjpatton) Unlike the Leader, the Helper filters out early
-                                // rejects after processing all of the reports. (This is an
-                                // optimization intended to reduce latency.) To avoid overcounting
-                                // rejection metrics, the latter rejections take precedence. The
-                                // Leader has the opposite behavior: Early rejections are resolved
-                                // first, so take precedence.
-                                metrics.report_inc_by(&format!("rejected_{failure}"), 1);
-                            } else {
-                                state_index += 1;
+                        let stored: Result<(), DapAbort> = async {
+                            // Filter out early rejected reports.
+                            reject_early(
+                                &mut agg_job_resp,
+                                &mut state.seq,
+                                |(_, _, report_id), transition_report_id| {
+                                    report_id == transition_report_id
+                                },
+                                &early_rejects,
+                                &metrics,
+                            )?;
+
+                            self.put_helper_state(task_id, &agg_job_id, &state, expiration)
+                                .await?;
+                            Ok(())
+                        }
+                        .await;
+                        if let Err(e) = stored {
+                            if holds_slot {
+                                self.release_agg_job_slot(task_id, &agg_job_id).await?;
                             }
+                            return Err(e);
                         }
-
-                        self.put_helper_state(task_id, &agg_job_id, &state).await?;
+                        metrics.helper_state_inc("stored");
                         agg_job_resp
                     }
-                    DapHelperTransition::Finish(..) => {
-                        return Err(DapError::fatal("unexpected transition (finished)").into());
+                    // In draft06, the Helper finishes preparation right away, so its output shares
+                    // are committed without storing any state for the aggregation job.
+                    DapHelperTransition::Finish(mut out_shares, mut agg_job_resp) => {
+                        // Filter out early rejected reports.
+                        reject_early(
+                            &mut agg_job_resp,
+                            &mut out_shares,
+                            |out_share, transition_report_id| {
+                                out_share.checksum == report_id_checksum(transition_report_id)
+                            },
+                            &early_rejects,
+                            &metrics,
+                        )?;
+
+                        let out_shares_count = u64::try_from(out_shares.len()).unwrap();
+                        let events = self
+                            .put_out_shares(task_id, &agg_job_init_req.part_batch_sel, out_shares)
+                            .await?;
+                        self.emit_batch_events(events).await;
+                        metrics.report_inc_by("aggregated", out_shares_count);
+                        metrics.inbound_req_inc(DaphneRequestType::Aggregate);
+                        metrics.inbound_req_latency_observe(
+                            DaphneRequestType::Aggregate,
+                            self.get_current_time_millis().saturating_sub(start),
+                        );
+                        return Ok(DapResponse {
+                            version: req.version,
+                            media_type: DapMediaType::AggregationJobResp,
+                            payload: agg_job_resp.get_encoded(),
+                        });
                     }
                 };
 
                 metrics.agg_job_inc();
                 metrics.inbound_req_inc(DaphneRequestType::Aggregate);
+                metrics.inbound_req_latency_observe(
+                    DaphneRequestType::Aggregate,
+                    self.get_current_time_millis().saturating_sub(start),
+                );
                 Ok(DapResponse {
                     version: req.version,
                     media_type: DapMediaType::AggregationJobResp,
@@ -996,21 +2756,45 @@ where
                     (DapVersion::Draft02, DapResource::Undefined, Some(ref agg_job_id)) => {
                         MetaAggregationJobId::Draft02(Cow::Borrowed(agg_job_id))
                     }
-                    (DapVersion::Draft04, DapResource::AggregationJob(ref agg_job_id), None) => {
-                        MetaAggregationJobId::Draft04(Cow::Borrowed(agg_job_id))
-                    }
-                    (DapVersion::Draft04, DapResource::Undefined, None) => {
+                    (
+                        DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                        DapResource::AggregationJob(ref agg_job_id),
+                        None,
+                    ) => MetaAggregationJobId::Draft04(Cow::Borrowed(agg_job_id)),
+                    (
+                        DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06,
+                        DapResource::Undefined,
+                        None,
+                    ) => {
                         return Err(DapAbort::BadRequest("undefined resource".into()));
                     }
                     _ => unreachable!("unhandled resource {:?}", req.resource),
                 };
 
-                let state = self.get_helper_state(task_id, &agg_job_id).await?.ok_or(
-                    DapAbort::UnrecognizedAggregationJob {
-                        task_id: task_id.clone(),
-                        agg_job_id_base64url: agg_job_id.to_base64url(),
-                    },
-                )?;
+                let span = Span::current();
+                span.record("agg_job_id", agg_job_id.to_base64url());
+                span.record("report_count", agg_job_cont_req.transitions.len());
+
+                let state = self.get_helper_state(task_id, &agg_job_id).await;
+
+                // The Helper's state is consumed, so the aggregation job is no longer running.
+                if self.get_global_config().helper_agg_job_limit.is_some() {
+                    self.release_agg_job_slot(task_id, &agg_job_id).await?;
+                }
+
+                let state = match state? {
+                    Some(state) => {
+                        metrics.helper_state_inc("consumed");
+                        state
+                    }
+                    None => {
+                        metrics.helper_state_inc("missing");
+                        return Err(DapAbort::UnrecognizedAggregationJob {
+                            task_id: task_id.clone(),
+                            agg_job_id_base64url: agg_job_id.to_base64url(),
+                        });
+                    }
+                };
                 let part_batch_sel = state.part_batch_sel.clone();
                 let transition = task_config.vdaf.handle_agg_job_cont_req(
                     task_id,
@@ -1026,8 +2810,10 @@ where
                     }
                     DapHelperTransition::Finish(out_shares, agg_job_resp) => {
                         let out_shares_count = u64::try_from(out_shares.len()).unwrap();
-                        self.put_out_shares(task_id, &part_batch_sel, out_shares)
+                        let events = self
+                            .put_out_shares(task_id, &part_batch_sel, out_shares)
                             .await?;
+                        self.emit_batch_events(events).await;
                         (agg_job_resp, out_shares_count)
                     }
                 };
@@ -1035,6 +2821,10 @@ where
                 metrics.report_inc_by("aggregated", out_shares_count);
                 metrics.agg_job_dec();
                 metrics.inbound_req_inc(DaphneRequestType::Aggregate);
+                metrics.inbound_req_latency_observe(
+                    DaphneRequestType::Aggregate,
+                    self.get_current_time_millis().saturating_sub(start),
+                );
                 Ok(DapResponse {
                     version: req.version,
                     media_type: DapMediaType::agg_job_cont_resp_for_version(task_config.version),
@@ -1050,10 +2840,12 @@ where
     /// response is an AggregateShareResp.
     ///
     /// This is called during the Collection phase.
+    #[instrument(skip_all, fields(task_id, report_count))]
     async fn http_post_aggregate_share(
         &'srv self,
         req: &'req DapRequest<S>,
     ) -> Result<DapResponse, DapAbort> {
+        let start = self.get_current_time_millis();
         let now = self.get_current_time();
         let metrics = self.metrics().with_host(req.host());
 
@@ -1062,9 +2854,18 @@ where
             return Err(DapAbort::version_unknown());
         }
 
+        check_sender_version(req)?;
         check_request_content_type(req, DapMediaType::AggregateShareReq)?;
+        check_request_size(
+            self.get_global_config(),
+            req,
+            DaphneRequestType::Collect,
+            &metrics,
+        )?;
 
         let task_id = req.task_id()?;
+        Span::current().record("task_id", task_id.to_string());
+        let metrics = metrics.with_task(task_id);
 
         if let Some(reason) = self.unauthorized_reason(req).await? {
             error!("aborted unauthorized collect request: {reason}");
@@ -1075,6 +2876,7 @@ where
         }
 
         let agg_share_req = AggregateShareReq::get_decoded_with_param(&req.version, &req.payload)?;
+        Span::current().record("report_count", agg_share_req.report_count);
         let wrapped_task_config = self
             .get_task_config_for(Cow::Borrowed(req.task_id()?))
             .await?
@@ -1086,26 +2888,55 @@ where
             return Err(DapAbort::version_mismatch(req.version, task_config.version));
         }
 
-        // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
-        // collected batches.
-        check_batch(
-            self,
-            task_config,
-            task_id,
-            &agg_share_req.batch_sel,
-            &agg_share_req.agg_param,
-            now,
-        )
-        .await?;
-
-        let agg_share = self
-            .get_agg_share(task_id, &agg_share_req.batch_sel)
+        // The Leader indicates which Collector the aggregate share is for.
+        let collector_id = req.collector_id.as_deref();
+        let collector_hpke_config = task_config
+            .collector_hpke_config_for(collector_id)
+            .ok_or_else(|| {
+                DapAbort::BadRequest(format!(
+                    "Collector {collector_id:?} is not recognized for this task"
+                ))
+            })?;
+
+        // If the Collector has queried this batch before, e.g., because the Leader is retrying its
+        // request, then answer with the same aggregate share, noise included. The batch was checked
+        // and counted as queried when the aggregate share was stored.
+        let collected_agg_share = self
+            .get_collected_agg_share(task_id, &agg_share_req.batch_sel, collector_id)
             .await?;
+        let is_repeated_query = collected_agg_share.is_some();
+
+        let mut agg_share = match collected_agg_share {
+            Some(agg_share) => agg_share,
+            None => {
+                // Ensure the batch boundaries are valid and that the batch doesn't overlap with
+                // previosuly collected batches.
+                check_batch(
+                    self,
+                    task_config,
+                    task_id,
+                    &agg_share_req.batch_sel,
+                    &agg_share_req.agg_param,
+                    now,
+                    collector_id,
+                )
+                .await?;
+
+                self.get_agg_share(task_id, &agg_share_req.batch_sel)
+                    .await?
+            }
+        };
 
         // Check that we have aggreagted the same set of reports as the Leader.
-        if agg_share_req.report_count != agg_share.report_count
-            || !constant_time_eq(&agg_share_req.checksum, &agg_share.checksum)
-        {
+        let mismatch = if agg_share_req.report_count != agg_share.report_count {
+            Some("report_count")
+        } else if !constant_time_eq(&agg_share_req.checksum, &agg_share.checksum) {
+            Some("checksum")
+        } else {
+            None
+        };
+        if let Some(reason) = mismatch {
+            metrics.batch_mismatch_inc(reason);
             return Err(DapAbort::BatchMismatch{
                 detail: format!("Either the report count or checksum does not match: the Leader computed {} and {}; the Helper computed {} and {}.",
                     agg_share_req.report_count,
@@ -1117,8 +2948,11 @@ where
         }
 
         // Check the batch size.
+        let forced = self
+            .is_batch_forced(task_id, &agg_share_req.batch_sel)
+            .await?;
         if !task_config
-            .is_report_count_compatible(task_id, agg_share.report_count)
+            .is_report_count_compatible(task_id, &agg_share, self.get_current_time(), forced)
             .unwrap_or(false)
         {
             return Err(DapAbort::InvalidBatchSize {
@@ -1130,12 +2964,28 @@ where
             });
         }
 
-        // Mark each aggregated report as collected.
-        self.mark_collected(task_id, &agg_share_req.batch_sel)
+        if !is_repeated_query {
+            // Mark each aggregated report as collected.
+            self.mark_collected(task_id, &agg_share_req.batch_sel, collector_id)
+                .await?;
+            info!(
+                "collector {} collected batch {:?} of task {task_id} ({} reports)",
+                collector_id.unwrap_or("(primary)"),
+                agg_share_req.batch_sel,
+                agg_share_req.report_count
+            );
+
+            task_config.dp.add_noise(&mut agg_share)?;
+            self.put_collected_agg_share(
+                task_id,
+                &agg_share_req.batch_sel,
+                collector_id,
+                &agg_share,
+            )
             .await?;
-
+        }
         let encrypted_agg_share = task_config.vdaf.produce_helper_encrypted_agg_share(
-            &task_config.collector_hpke_config,
+            collector_hpke_config,
             task_id,
             &agg_share_req.batch_sel,
             &agg_share,
@@ -1148,6 +2998,10 @@ where
 
         metrics.report_inc_by("collected", agg_share_req.report_count);
         metrics.inbound_req_inc(DaphneRequestType::Collect);
+        metrics.inbound_req_latency_observe(
+            DaphneRequestType::Collect,
+            self.get_current_time_millis().saturating_sub(start),
+        );
         Ok(DapResponse {
             version: req.version,
             media_type: DapMediaType::AggregateShare,
@@ -1186,12 +3040,13 @@ async fn check_batch<'srv, 'req, S>(
     batch_sel: &BatchSelector,
     agg_param: &[u8],
     now: Time,
+    collector_id: Option<&str>,
 ) -> Result<(), DapAbort>
 where
     'srv: 'req,
 {
-    let global_config = agg.get_global_config();
-    let batch_overlapping = agg.is_batch_overlapping(task_id, batch_sel);
+    let global_config = agg.get_global_config().for_version(task_config.version);
+    let batch_query_count = agg.get_batch_query_count(task_id, batch_sel, collector_id);
 
     // Check that the aggreation parameter is suitable for the given VDAF.
     if !task_config.vdaf.is_valid_agg_param(agg_param) {
@@ -1222,6 +3077,16 @@ where
                 ));
             }
 
+            if let Some(tolerance) = global_config.batch_interval_future_tolerance {
+                let horizon = now.saturating_add(tolerance);
+                if batch_interval.end() > horizon {
+                    return Err(DapAbort::BatchInvalid {
+                        detail: format!("The queried batch interval ({batch_interval:?}) ends in the future. The batch interval may end no later than {horizon}, the current time plus {tolerance}s."),
+                        task_id: task_id.clone(),
+                    });
+                }
+            }
+
             if now.abs_diff(batch_interval.end()) > global_config.max_batch_interval_end {
                 return Err(DapAbort::BadRequest(
                     "batch interval too far into future".to_string(),
@@ -1254,9 +3119,19 @@ where
         }
     };
 
-    // Check that the batch does not overlap with any previously collected batch.
-    if batch_overlapping.await? {
-        return Err(DapAbort::batch_overlap(task_id, batch_sel));
+    // Check that none of the buckets of the batch has been queried too many times. If each batch
+    // may only be queried once, then this means the batch may not overlap with any previously
+    // collected batch.
+    if batch_query_count.await? >= task_config.max_batch_query_count {
+        return Err(if task_config.max_batch_query_count == 1 {
+            DapAbort::batch_overlap(task_id, batch_sel)
+        } else {
+            DapAbort::batch_queried_multiple_times(
+                task_id,
+                batch_sel,
+                task_config.max_batch_query_count,
+            )
+        });
     }
 
     Ok(())
@@ -1290,6 +3165,215 @@ pub fn early_metadata_check(
     }
 }
 
+/// Deliver a request from the Leader directly to a Helper hosted in the same process, bypassing
+/// HTTP. This is meant for development setups in which one process plays both roles, so that the
+/// full protocol can be stepped through in a single debugger session. A Leader opts into this
+/// mode by calling this function from [`DapLeader::send_http_post`] and
+/// [`DapLeader::send_http_put`].
+///
+/// Aborts raised by the Helper are converted into problem details and then into an error with
+/// [`DapAbort::from_peer_response`], just as they would be if the Helper's response were sent
+/// over HTTP.
+pub async fn loopback_send_http<'srv, 'req, S, H>(
+    helper: &'srv H,
+    req: &'req DapRequest<S>,
+) -> Result<DapResponse, DapError>
+where
+    'srv: 'req,
+    H: DapHelper<'srv, 'req, S>,
+{
+    let result = match req.media_type {
+        DapMediaType::AggregationJobInitReq | DapMediaType::AggregationJobContinueReq => {
+            helper.http_post_aggregate(req).await
+        }
+        DapMediaType::AggregateShareReq => helper.http_post_aggregate_share(req).await,
+        _ => {
+            return Err(DapError::Fatal(format!(
+                "loopback: unexpected media type {:?}",
+                req.media_type
+            )))
+        }
+    };
+
+    result.map_err(|e| {
+        let status = e.status_code();
+        let retry_after = match e {
+            DapAbort::Overloaded { retry_after, .. } => Some(retry_after),
+            _ => None,
+        };
+        let problem = e.into_problem_details(Some(req.url.path().to_string()));
+        DapError::Abort(DapAbort::from_peer_response(
+            status,
+            retry_after,
+            Some(problem),
+        ))
+    })
+}
+
+/// Mark the reports in the Helper's aggregate response that were rejected early as failed. `seq`
+/// holds the Helper's result (VDAF preparation state or output share) for each report that has
+/// not failed, in the order of the transitions in the response; the result of each report that is
+/// rejected is removed. `matches` checks that a result corresponds to the given report ID.
+fn reject_early<T>(
+    agg_job_resp: &mut AggregationJobResp,
+    seq: &mut Vec<T>,
+    matches: impl Fn(&T, &ReportId) -> bool,
+    early_rejects: &HashMap<ReportId, TransitionFailure>,
+    metrics: &ContextualizedDaphneMetrics<'_>,
+) -> Result<(), DapError> {
+    let mut seq_index = 0;
+    for transition in agg_job_resp.transitions.iter_mut() {
+        let early_failure = early_rejects.get(&transition.report_id);
+        if !matches!(transition.var, TransitionVar::Failed(..)) && early_failure.is_some() {
+            // NOTE(cjpatton) Clippy wants us to use and `if let` statement to unwrap
+            // `early_failure`. I don't think this works becauase we only want to enter this loop
+            // if `early_failure.is_some()` and the current `transition` is not a failure. As far
+            // as I know, `if let` statements can't yet be combined with other conditions.
+            #[allow(clippy::unnecessary_unwrap)]
+            let failure = early_failure.unwrap();
+            transition.var = TransitionVar::Failed(*failure);
+
+            // Remove the result of reports that were rejected early.
+            if seq_index < seq.len() && matches(&seq[seq_index], &transition.report_id) {
+                let _val = seq.remove(seq_index);
+            } else {
+                // The report IDs in the Helper's results and the aggregate response must be
+                // aligned. If not, handle as an internal error.
+                return Err(DapError::fatal("report IDs not aligned"));
+            }
+
+            // NOTE(cjpatton) Unlike the Leader, the Helper filters out early rejects after
+            // processing all of the reports. (This is an optimization intended to reduce
+            // latency.) To avoid overcounting rejection metrics, the latter rejections take
+            // precedence. The Leader has the opposite behavior: Early rejections are resolved
+            // first, so take precedence.
+            metrics.report_inc_by(&format!("rejected_{failure}"), 1);
+        } else if !matches!(transition.var, TransitionVar::Failed(..)) {
+            seq_index += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Count the reports that pass the time bounds check only because their timestamps are within
+/// [`DapGlobalConfig::report_time_skew_tolerance`]. Reports that were rejected early are ignored.
+fn count_skewed_reports<'a>(
+    global_config: &DapGlobalConfig,
+    now: Time,
+    report_meta: impl Iterator<Item = &'a ReportMetadata>,
+    early_rejects: &HashMap<ReportId, TransitionFailure>,
+    metrics: &ContextualizedDaphneMetrics<'_>,
+) {
+    if global_config.report_time_skew_tolerance.is_none() {
+        return;
+    }
+    for metadata in report_meta {
+        if !early_rejects.contains_key(&metadata.id)
+            && global_config.is_report_time_skewed(metadata.time, now)
+        {
+            metrics.report_inc_by("report_too_early", 1);
+        }
+    }
+}
+
+/// Return the reports of an abandoned aggregation job to storage. If
+/// [`DapGlobalConfig::agg_job_abandon`] is set, then the reports that have been part of too many
+/// abandoned jobs are rejected instead. Since they remain marked as processed, they cannot be
+/// uploaded again.
+async fn requeue_abandoned_reports<'srv, 'req, S, L>(
+    leader: &L,
+    task_id: &TaskId,
+    part_batch_sel: &PartialBatchSelector,
+    reports: Vec<Report>,
+    metrics: &ContextualizedDaphneMetrics<'_>,
+) -> Result<(), DapError>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    let max_attempts = match leader.get_global_config().agg_job_abandon {
+        Some(ref agg_job_abandon) => agg_job_abandon.max_attempts,
+        None => {
+            return leader
+                .requeue_reports(task_id, part_batch_sel, reports)
+                .await
+        }
+    };
+
+    let attempts = leader.count_abandoned_reports(task_id, &reports).await?;
+    let (rejected, requeued): (Vec<_>, Vec<_>) = reports
+        .into_iter()
+        .zip(attempts)
+        .partition(|(_report, attempts)| *attempts >= max_attempts);
+    if !rejected.is_empty() {
+        warn!(
+            "rejecting {} reports that were part of {max_attempts} abandoned aggregation jobs",
+            rejected.len()
+        );
+        metrics.report_inc_by("rejected_agg_job_abandoned", rejected.len() as u64);
+    }
+    leader
+        .requeue_reports(
+            task_id,
+            part_batch_sel,
+            requeued
+                .into_iter()
+                .map(|(report, _attempts)| report)
+                .collect(),
+        )
+        .await
+}
+
+/// Leader: Count an aggregation job that the Helper rejected as unauthorized. If
+/// [`DapGlobalConfig::helper_circuit_breaker`] is set and the Helper has rejected enough jobs in a
+/// row, then the aggregation jobs for the Helper are deferred until the breaker closes.
+async fn trip_helper_circuit_breaker<'srv, 'req, S, L>(
+    leader: &L,
+    helper_url: &Url,
+    metrics: &ContextualizedDaphneMetrics<'_>,
+) -> Result<(), DapError>
+where
+    'srv: 'req,
+    L: DapLeader<'srv, 'req, S>,
+{
+    let circuit_breaker = match leader.get_global_config().helper_circuit_breaker {
+        Some(ref circuit_breaker) => circuit_breaker.clone(),
+        None => return Ok(()),
+    };
+
+    let failures = leader.count_helper_auth_failure(helper_url).await?;
+    if failures >= circuit_breaker.failure_threshold {
+        let until = leader
+            .get_current_time()
+            .saturating_add(circuit_breaker.open_for);
+        warn!(
+            "Helper rejected {failures} aggregation jobs in a row as unauthorized; deferring aggregation jobs until {until}"
+        );
+        leader.defer_helper(helper_url, until).await?;
+        metrics.helper_circuit_breaker_opened_inc();
+    }
+    Ok(())
+}
+
+/// Leader: Check whether a request to the Helper failed for a reason that may not recur, e.g.,
+/// the Helper could not be reached or asked the Leader to try again later.
+fn is_transient(e: &DapAbort) -> bool {
+    matches!(e, DapAbort::RetryLater { .. } | DapAbort::Overloaded { .. })
+}
+
+/// Select the reports of an abandoned aggregation job that are to be returned to storage, i.e.,
+/// those whose IDs are listed in the Leader's state for the job.
+fn reports_to_requeue<'a>(
+    reports: Vec<Report>,
+    report_ids: impl Iterator<Item = &'a ReportId>,
+) -> Vec<Report> {
+    let report_ids = report_ids.collect::<HashSet<_>>();
+    reports
+        .into_iter()
+        .filter(|report| report_ids.contains(&report.report_metadata.id))
+        .collect()
+}
+
 fn check_response_content_type(resp: &DapResponse, expected: DapMediaType) -> Result<(), DapError> {
     let want_str = expected
         .as_str_for_version(resp.version)
@@ -1310,6 +3394,49 @@ fn check_response_content_type(resp: &DapResponse, expected: DapMediaType) -> Re
     }
 }
 
+/// Check that the version the sender signaled for itself (if any) matches the version of the
+/// endpoint to which the request was sent. A mismatch means the peers are configured for
+/// different drafts, in which case the payload would likely fail to decode.
+fn check_sender_version<S>(req: &DapRequest<S>) -> Result<(), DapAbort> {
+    match req.sender_version {
+        Some(sender_version) if sender_version != req.version => Err(
+            DapAbort::sender_version_mismatch(sender_version, req.version),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Check that the body of the request does not exceed the size limit configured for its type (see
+/// [`DapGlobalConfig::max_request_size`]). Requests that do are counted and rejected before their
+/// body is decoded.
+fn check_request_size<S>(
+    global_config: &DapGlobalConfig,
+    req: &DapRequest<S>,
+    request_type: DaphneRequestType,
+    metrics: &ContextualizedDaphneMetrics<'_>,
+) -> Result<(), DapAbort> {
+    let limits = match global_config.max_request_size {
+        Some(ref limits) => limits,
+        None => return Ok(()),
+    };
+    let max_size = match request_type {
+        DaphneRequestType::Upload | DaphneRequestType::UploadBatch => limits.upload,
+        DaphneRequestType::Aggregate => limits.aggregate,
+        DaphneRequestType::Collect => limits.collect,
+        DaphneRequestType::HpkeConfig => None,
+    };
+    let size = u64::try_from(req.payload.len()).unwrap();
+    match max_size {
+        Some(max_size) if size > max_size => {
+            metrics.inbound_req_too_large_inc(request_type);
+            Err(DapAbort::PayloadTooLarge {
+                detail: format!("request body of {size} bytes exceeds the limit of {max_size}"),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
 fn check_request_content_type<S>(
     req: &DapRequest<S>,
     expected: DapMediaType,
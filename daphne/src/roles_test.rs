@@ -2,37 +2,55 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{
+    aborts::DapAbortType,
     assert_metrics_include, assert_metrics_include_auxiliary_function, async_test_version,
-    async_test_versions,
-    auth::BearerToken,
+    async_test_versions, async_test_versions_multi_round,
+    auth::{BearerToken, DapClientAuth, DapSenderAuth, DapUploadAuth},
+    clock::MockClock,
+    collector::{verify_report_counts, DapCollectionCheck, DapCollectionLog},
     constants::DapMediaType,
+    events::{DapBatchEvent, DapBatchEventType},
+    extensions::DapExtensionRegistry,
     hpke::{HpkeDecrypter, HpkeReceiverConfig},
     messages::{
-        taskprov, AggregateShareReq, AggregationJobContinueReq, AggregationJobInitReq,
-        AggregationJobResp, BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq,
-        Extension, HpkeKemId, Interval, PartialBatchSelector, Query, Report, ReportId,
-        ReportMetadata, ReportShare, TaskId, Time, Transition, TransitionFailure, TransitionVar,
+        encode_base64url, taskprov, AggregateShareReq, AggregationJobContinueReq,
+        AggregationJobInitReq, AggregationJobResp, BatchId, BatchSelector, Collection,
+        CollectionJobId, CollectionReq, Extension, HpkeKemId, Interval, PartialBatchSelector,
+        PingPongMessage, Query, Report, ReportBatch, ReportId, ReportMetadata, ReportShare, TaskId,
+        Time, Transition, TransitionFailure, TransitionVar,
     },
-    metrics::DaphneMetrics,
-    roles::{early_metadata_check, DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
+    metrics::{DaphneMetrics, DaphneMetricsBuckets},
+    roles::{early_metadata_check, loopback_send_http, DapAggregator, DapHelper, DapLeader},
     taskprov::TaskprovVersion,
     test_version, test_versions,
-    testing::{AggStore, DapBatchBucketOwned, MockAggregator, MockAggregatorReportSelector},
-    vdaf::VdafVerifyKey,
-    DapAbort, DapAggregateShare, DapCollectJob, DapGlobalConfig, DapMeasurement, DapQueryConfig,
-    DapRequest, DapResource, DapTaskConfig, DapVersion, MetaAggregationJobId, Prio3Config,
-    VdafConfig,
+    testing::{
+        AggStore, DapBatchBucketOwned, MockAggregator, MockAggregatorReportSelector, MockFaults,
+        MockOperation, MockOperationFaults,
+    },
+    vdaf::{report_id_checksum, VdafVerifyKey},
+    CollectionJobStatus, DapAbort, DapAggJobAbandonConfig, DapAggregateResult, DapAggregateShare,
+    DapBatchBucket, DapBatchLifetimeConfig, DapBatchSuggestion, DapBatchSuggestions,
+    DapBucketReportCount, DapCircuitBreakerConfig, DapCollectJob, DapDeadlineRetryConfig,
+    DapDpConfig, DapError, DapGlobalConfig, DapHelperAggJobLimit, DapHelperState,
+    DapHelperStateStoreConfig, DapMeasurement, DapQueryConfig, DapRational,
+    DapReportCountBreakdown, DapReportSample, DapReportSamplingConfig, DapReportUploadStatus,
+    DapRequest, DapRequestSizeLimits, DapResource, DapRetryConfig, DapStaleBatchPolicy,
+    DapTaskCollector, DapTaskConfig, DapVdafVerifyKeyRotation, DapVersion, DapVersionConfig,
+    MetaAggregationJobId, Prio3Config, VdafConfig,
 };
 use assert_matches::assert_matches;
 use matchit::Router;
 use paste::paste;
-use prio::codec::{Decode, ParameterizedEncode};
+use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
 use rand::{thread_rng, Rng};
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    sync::{Arc, Mutex},
-    time::SystemTime,
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
     vec,
 };
 use url::Url;
@@ -53,6 +71,8 @@ struct Test {
     leader: Arc<MockAggregator>,
     helper: Arc<MockAggregator>,
     collector_token: BearerToken,
+    auditor_token: BearerToken,
+    auditor_hpke_receiver_config: HpkeReceiverConfig,
     time_interval_task_id: TaskId,
     fixed_size_task_id: TaskId,
     expired_task_id: TaskId,
@@ -73,12 +93,49 @@ impl Test {
         let global_config = DapGlobalConfig {
             report_storage_epoch_duration: 604800,    // one week
             report_storage_max_future_time_skew: 300, // 5 minutes
+            report_time_skew_tolerance: Some(300),    // 5 minutes
+            report_replay_window: Some(1800),         // 30 minutes
             max_batch_duration: 360000,
             min_batch_interval_start: 259200,
             max_batch_interval_end: 259200,
+            batch_interval_future_tolerance: None,
+            helper_connect_timeout: None,
+            helper_request_timeout: None,
             supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+            hpke_rotation: None,
+            agg_job_init_retry: Some(DapRetryConfig {
+                max_attempts: 3,
+                initial_delay_ms: 100,
+                max_delay_ms: 150,
+            }),
+            agg_job_continue_retry: None,
+            agg_job_abandon: Some(DapAggJobAbandonConfig { max_attempts: 2 }),
+            helper_circuit_breaker: None,
+            report_sampling: Some(DapReportSamplingConfig { rate: 1.0 }),
+            reject_untruncated_report_time: false,
+            max_concurrent_collect_jobs: Some(2),
+            max_concurrent_collect_jobs_per_task: Some(1),
+            max_upload_batch_len: Some(4),
+            helper_agg_job_limit: Some(DapHelperAggJobLimit {
+                max_running: 2,
+                queue_timeout_ms: 1000,
+                queue_poll_interval_ms: 100,
+                retry_after: None,
+            }),
+            helper_state_store: Some(DapHelperStateStoreConfig {
+                ttl: 3600,
+                max_encoded_len: 1 << 16,
+            }),
+            helper_prep_concurrency: Some(4),
+            helper_agg_share_span_buckets: None,
+            max_request_size: Some(DapRequestSizeLimits {
+                upload: Some(1 << 12),
+                aggregate: Some(1 << 16),
+                collect: Some(1 << 10),
+            }),
             allow_taskprov: true,
             taskprov_version: TaskprovVersion::Draft02,
+            versions: HashMap::new(),
         };
 
         // Task Parameters that the Leader and Helper must agree on.
@@ -104,9 +161,16 @@ impl Test {
                 time_precision,
                 expiration: now + 3600,
                 min_batch_size: 1,
+                max_batch_query_count: 1,
                 query: DapQueryConfig::TimeInterval,
                 vdaf: vdaf_config.clone(),
                 vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
+                additional_collectors: Vec::new(),
+                dp: DapDpConfig::None,
+                taskprov_advertisement: None,
+                replay_filter: None,
+                vdaf_verify_key_rotation: None,
+                upload_auth: None,
             },
         );
         tasks.insert(
@@ -119,9 +183,19 @@ impl Test {
                 time_precision,
                 expiration: now + 3600,
                 min_batch_size: 1,
-                query: DapQueryConfig::FixedSize { max_batch_size: 2 },
+                max_batch_query_count: 1,
+                query: DapQueryConfig::FixedSize {
+                    max_batch_size: 2,
+                    batch_lifetime: None,
+                },
                 vdaf: vdaf_config.clone(),
                 vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
+                additional_collectors: Vec::new(),
+                dp: DapDpConfig::None,
+                taskprov_advertisement: None,
+                replay_filter: None,
+                vdaf_verify_key_rotation: None,
+                upload_auth: None,
             },
         );
         tasks.insert(
@@ -134,9 +208,16 @@ impl Test {
                 time_precision,
                 expiration: now, // Expires this second
                 min_batch_size: 1,
+                max_batch_query_count: 1,
                 query: DapQueryConfig::TimeInterval,
                 vdaf: vdaf_config,
                 vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
+                additional_collectors: Vec::new(),
+                dp: DapDpConfig::None,
+                taskprov_advertisement: None,
+                replay_filter: None,
+                vdaf_verify_key_rotation: None,
+                upload_auth: None,
             },
         );
 
@@ -144,6 +225,11 @@ impl Test {
         let leader_token = BearerToken::from("this is a bearer token!");
         let collector_token = BearerToken::from("This is a DIFFERENT token.");
 
+        // An additional Collector, which tests may add to a task.
+        let auditor_token = BearerToken::from("This is the auditor's token.");
+        let auditor_hpke_receiver_config =
+            HpkeReceiverConfig::gen(rng.gen(), HpkeKemId::X25519HkdfSha256).unwrap();
+
         // taskprov: VDAF verification key.
         let taskprov_vdaf_verify_key_init = rng.gen::<[u8; 32]>();
 
@@ -158,6 +244,7 @@ impl Test {
             tasks: Arc::new(Mutex::new(tasks.clone())),
             leader_token: leader_token.clone(),
             collector_token: None,
+            additional_collector_tokens: HashMap::new(),
             hpke_receiver_config_list: helper_hpke_receiver_config_list,
             report_store: Arc::new(Mutex::new(HashMap::new())),
             leader_state_store: Arc::new(Mutex::new(HashMap::new())),
@@ -165,8 +252,23 @@ impl Test {
             agg_store: Arc::new(Mutex::new(HashMap::new())),
             collector_hpke_config: collector_hpke_receiver_config.config.clone(),
             taskprov_vdaf_verify_key_init,
-            metrics: DaphneMetrics::register(&prometheus_registry, Some("test_helper")).unwrap(),
+            metrics: DaphneMetrics::register(
+                &prometheus_registry,
+                Some("test_helper"),
+                &DaphneMetricsBuckets::default(),
+            )
+            .unwrap(),
+            extension_registry: DapExtensionRegistry::default(),
             peer: None,
+            drop_helper_state: AtomicBool::new(false),
+            faults: Mutex::new(None),
+            clock: MockClock::new(now),
+            running_agg_jobs: Mutex::new(HashSet::new()),
+            finished_collect_jobs: Mutex::new(Vec::new()),
+            batch_events: Mutex::new(Vec::new()),
+            helper_deferrals: Mutex::new(HashMap::new()),
+            helper_auth_failures: Mutex::new(HashMap::new()),
+            collected_agg_shares: Mutex::new(HashMap::new()),
         });
 
         let leader_hpke_receiver_config_list = global_config
@@ -179,14 +281,33 @@ impl Test {
             hpke_receiver_config_list: leader_hpke_receiver_config_list,
             leader_token,
             collector_token: Some(collector_token.clone()),
+            additional_collector_tokens: HashMap::from([(
+                "auditor".to_string(),
+                auditor_token.clone(),
+            )]),
             report_store: Arc::new(Mutex::new(HashMap::new())),
             leader_state_store: Arc::new(Mutex::new(HashMap::new())),
             helper_state_store: Arc::new(Mutex::new(HashMap::new())),
             agg_store: Arc::new(Mutex::new(HashMap::new())),
             collector_hpke_config: collector_hpke_receiver_config.config,
             taskprov_vdaf_verify_key_init,
-            metrics: DaphneMetrics::register(&prometheus_registry, Some("test_leader")).unwrap(),
+            metrics: DaphneMetrics::register(
+                &prometheus_registry,
+                Some("test_leader"),
+                &DaphneMetricsBuckets::default(),
+            )
+            .unwrap(),
+            extension_registry: DapExtensionRegistry::default(),
             peer: Some(Arc::clone(&helper)),
+            drop_helper_state: AtomicBool::new(false),
+            faults: Mutex::new(None),
+            clock: MockClock::new(now),
+            running_agg_jobs: Mutex::new(HashSet::new()),
+            finished_collect_jobs: Mutex::new(Vec::new()),
+            batch_events: Mutex::new(Vec::new()),
+            helper_deferrals: Mutex::new(HashMap::new()),
+            helper_auth_failures: Mutex::new(HashMap::new()),
+            collected_agg_shares: Mutex::new(HashMap::new()),
         });
 
         Self {
@@ -194,6 +315,8 @@ impl Test {
             leader,
             helper,
             collector_token,
+            auditor_token,
+            auditor_hpke_receiver_config,
             time_interval_task_id,
             fixed_size_task_id,
             expired_task_id,
@@ -218,6 +341,12 @@ impl Test {
             payload: report.get_encoded_with_param(&version),
             url: task_config.leader_url.join("upload").unwrap(),
             sender_auth: None,
+            sender_version: None,
+            collector_id: None,
+            taskprov: None,
+            vdaf_verify_key_id: None,
+            client_auth: None,
+            idempotency_key: None,
         }
     }
 
@@ -225,7 +354,7 @@ impl Test {
         &self,
         task_id: &TaskId,
         version: DapVersion,
-        report_shares: Vec<ReportShare>,
+        reports: Vec<Report>,
     ) -> DapRequest<BearerToken> {
         let mut rng = thread_rng();
         let task_config = self.leader.unchecked_get_task_config(task_id).await;
@@ -236,6 +365,40 @@ impl Test {
             },
         };
 
+        let mut report_shares = Vec::with_capacity(reports.len());
+        let mut leader_messages = Vec::with_capacity(reports.len());
+        for report in reports {
+            if version == DapVersion::Draft06 {
+                // The Helper finishes preparation as soon as it receives the request, so it needs
+                // the Leader's prep share. If the Leader can't compute it, then send an empty one
+                // and let the Helper reject the report.
+                let prep_share = match task_config
+                    .vdaf
+                    .consume_report_share(
+                        &*self.leader,
+                        &self.leader.extension_registry,
+                        true, // is_leader
+                        task_id,
+                        &task_config,
+                        &report.report_metadata,
+                        &report.public_share,
+                        &report.encrypted_input_shares[0],
+                    )
+                    .await
+                {
+                    Ok((_, message)) => task_config.vdaf.encode_prepare_message(&message),
+                    Err(_) => Vec::new(),
+                };
+                leader_messages.push(PingPongMessage::Initialize { prep_share });
+            }
+            report_shares.push(ReportShare {
+                report_metadata: report.report_metadata,
+                public_share: report.public_share,
+                // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
+                encrypted_input_share: report.encrypted_input_shares[1].clone(),
+            });
+        }
+
         let agg_job_id = MetaAggregationJobId::gen_for_version(&version);
         self.leader_authorized_req_with_version(
             task_id,
@@ -248,6 +411,8 @@ impl Test {
                 agg_param: Vec::default(),
                 part_batch_sel,
                 report_shares,
+                draft06_leader_messages: (version == DapVersion::Draft06)
+                    .then_some(leader_messages),
             },
             task_config.helper_url.join("aggregate").unwrap(),
         )
@@ -298,6 +463,16 @@ impl Test {
         &self,
         report_count: u64,
         checksum: [u8; 32],
+    ) -> DapRequest<BearerToken> {
+        self.gen_test_agg_share_req_for_batch(BatchSelector::default(), report_count, checksum)
+            .await
+    }
+
+    async fn gen_test_agg_share_req_for_batch(
+        &self,
+        batch_sel: BatchSelector,
+        report_count: u64,
+        checksum: [u8; 32],
     ) -> DapRequest<BearerToken> {
         let task_id = &self.time_interval_task_id;
         let task_config = self.leader.unchecked_get_task_config(task_id).await;
@@ -315,7 +490,7 @@ impl Test {
             DapMediaType::AggregateShareReq,
             AggregateShareReq {
                 draft02_task_id: task_id.for_request_payload(&task_config.version),
-                batch_sel: BatchSelector::default(),
+                batch_sel,
                 agg_param: Vec::default(),
                 report_count,
                 checksum,
@@ -326,6 +501,10 @@ impl Test {
     }
 
     async fn gen_test_report(&self, task_id: &TaskId) -> Report {
+        self.gen_test_report_at(task_id, self.now).await
+    }
+
+    async fn gen_test_report_at(&self, task_id: &TaskId, time: Time) -> Report {
         let version = self.leader.unchecked_get_task_config(task_id).await.version;
 
         // Construct HPKE config list.
@@ -349,7 +528,7 @@ impl Test {
         vdaf_config
             .produce_report(
                 &hpke_config_list,
-                self.now,
+                time,
                 task_id,
                 DapMeasurement::U64(1),
                 self.version,
@@ -383,6 +562,72 @@ impl Test {
     }
 
     async fn run_col_job(&self, task_id: &TaskId, query: &Query) -> Result<(), DapAbort> {
+        self.run_col_job_for_collector(task_id, query, None)
+            .await
+            .map(|_collect_job| ())
+    }
+
+    /// Add the auditor as an additional Collector of the task.
+    async fn add_auditor(&self, task_id: &TaskId) {
+        for aggregator in [&self.leader, &self.helper] {
+            aggregator
+                .tasks
+                .lock()
+                .unwrap()
+                .get_mut(task_id)
+                .unwrap()
+                .additional_collectors
+                .push(DapTaskCollector {
+                    id: "auditor".into(),
+                    hpke_config: self.auditor_hpke_receiver_config.config.clone(),
+                });
+        }
+    }
+
+    /// Require two reports per batch of the fixed-size task and set its batch lifetime policy.
+    fn set_batch_lifetime(&self, policy: DapStaleBatchPolicy) {
+        for aggregator in [&self.leader, &self.helper] {
+            let mut tasks = aggregator.tasks.lock().unwrap();
+            let task_config = tasks.get_mut(&self.fixed_size_task_id).unwrap();
+            task_config.min_batch_size = 2;
+            task_config.query = DapQueryConfig::FixedSize {
+                max_batch_size: 2,
+                batch_lifetime: Some(DapBatchLifetimeConfig {
+                    max_batch_age: 60,
+                    policy,
+                }),
+            };
+        }
+    }
+
+    /// Advance the clock of both Aggregators by the given number of seconds.
+    fn advance_clock(&self, seconds: u64) {
+        for aggregator in [&self.leader, &self.helper] {
+            aggregator.clock.advance(Duration::from_secs(seconds));
+        }
+    }
+
+    /// Run a collection job on behalf of the given Collector (`None` for the primary Collector)
+    /// and return the job's status.
+    async fn run_col_job_for_collector(
+        &self,
+        task_id: &TaskId,
+        query: &Query,
+        collector_id: Option<&str>,
+    ) -> Result<DapCollectJob, DapAbort> {
+        self.run_col_job_with_id(task_id, query, collector_id)
+            .await
+            .map(|(_collect_id, collect_job)| collect_job)
+    }
+
+    /// Like [`Self::run_col_job_for_collector`], but also return the ID of the collection job. If
+    /// the job is complete, then the Leader's breakdown of its report count is verified.
+    async fn run_col_job_with_id(
+        &self,
+        task_id: &TaskId,
+        query: &Query,
+        collector_id: Option<&str>,
+    ) -> Result<(CollectionJobId, DapCollectJob), DapAbort> {
         let wrapped = self
             .leader
             .get_task_config_for(Cow::Owned(task_id.clone()))
@@ -404,11 +649,19 @@ impl Test {
                 task_config.helper_url.join("collect").unwrap(),
             )
             .await;
+        let req = DapRequest {
+            sender_auth: collector_id
+                .map(|_| self.auditor_token.clone())
+                .or(req.sender_auth),
+            collector_id: collector_id.map(str::to_string),
+            taskprov: None,
+            ..req
+        };
 
         // Leader: Handle request from Collector.
         self.leader.http_post_collect(&req).await?;
         let resp = self.leader.get_pending_collect_jobs().await?;
-        let (task_id, collect_id, collect_req) = &resp[0];
+        let (task_id, collect_id, collect_req, collector_id) = resp.last().unwrap();
 
         // Leader->Helper: Complete collection job.
         let _reports_collected = self
@@ -418,10 +671,19 @@ impl Test {
                 collect_id,
                 task_config,
                 collect_req,
+                collector_id.as_deref(),
                 task_config.leader_url.host_str().unwrap(),
             )
             .await?;
-        Ok(())
+        let collect_job = self.leader.poll_collect_job(task_id, collect_id).await?;
+        if let DapCollectJob::Done(ref collection) = collect_job {
+            let report_counts = self
+                .leader
+                .get_collection_report_counts(task_id, collect_id)
+                .unwrap();
+            verify_report_counts(task_id, collection, &report_counts)?;
+        }
+        Ok((collect_id.clone(), collect_job))
     }
 
     async fn leader_authorized_req<M: ParameterizedEncode<DapVersion>>(
@@ -448,6 +710,12 @@ impl Test {
             payload,
             url,
             sender_auth,
+            sender_version: Some(version),
+            collector_id: None,
+            taskprov: None,
+            vdaf_verify_key_id: None,
+            client_auth: None,
+            idempotency_key: None,
         }
     }
 
@@ -475,6 +743,12 @@ impl Test {
             payload,
             url,
             sender_auth,
+            sender_version: Some(version),
+            collector_id: None,
+            taskprov: None,
+            vdaf_verify_key_id: None,
+            client_auth: None,
+            idempotency_key: None,
         }
     }
 
@@ -500,6 +774,12 @@ impl Test {
             payload: msg.get_encoded_with_param(&version),
             url,
             sender_auth: Some(self.collector_token.clone()),
+            sender_version: None,
+            collector_id: None,
+            taskprov: None,
+            vdaf_verify_key_id: None,
+            client_auth: None,
+            idempotency_key: None,
         }
     }
 }
@@ -527,6 +807,7 @@ async fn http_post_aggregate_invalid_batch_sel(version: DapVersion) {
                     batch_id: BatchId(rng.gen()),
                 },
                 report_shares: Vec::default(),
+                draft06_leader_messages: (version == DapVersion::Draft06).then(Vec::default),
             },
             task_config.helper_url.join("aggregate").unwrap(),
         )
@@ -562,18 +843,57 @@ async fn http_post_aggregate_init_unauthorized_request(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_init_unauthorized_request }
 
+// Test that the Helper detects when the Leader speaks a different draft than the endpoint.
+async fn http_post_aggregate_sender_version_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+    let other_version = if version == DapVersion::Draft02 {
+        DapVersion::Draft04
+    } else {
+        DapVersion::Draft02
+    };
+
+    let mut req = t
+        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, Vec::default())
+        .await;
+    req.sender_version = Some(other_version);
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::VersionMismatch { detail }) => assert!(detail.contains(&format!("{other_version:?}")))
+    );
+
+    let mut req = t.gen_test_agg_share_req(0, [0; 32]).await;
+    req.sender_version = Some(other_version);
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await,
+        Err(DapAbort::VersionMismatch { .. })
+    );
+}
+
+async_test_versions! { http_post_aggregate_sender_version_mismatch }
+
+// Test that the Helper rejects an aggregation job for a VDAF verify key it doesn't have.
+async fn http_post_aggregate_init_unrecognized_vdaf_verify_key_id(version: DapVersion) {
+    let t = Test::new(version);
+
+    let mut req = t
+        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, Vec::default())
+        .await;
+    req.vdaf_verify_key_id = Some(1);
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::BadRequest(detail)) => assert!(detail.contains("VDAF verify key ID (1)"))
+    );
+}
+
+async_test_versions! { http_post_aggregate_init_unrecognized_vdaf_verify_key_id }
+
 // Test that the Helper rejects reports past the expiration date.
 async fn http_post_aggregate_init_expired_task(version: DapVersion) {
     let t = Test::new(version);
 
     let report = t.gen_test_report(&t.expired_task_id).await;
-    let report_share = ReportShare {
-        report_metadata: report.report_metadata,
-        public_share: report.public_share,
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    };
     let req = t
-        .gen_test_agg_job_init_req(&t.expired_task_id, version, vec![report_share])
+        .gen_test_agg_job_init_req(&t.expired_task_id, version, vec![report])
         .await;
 
     let resp = t.helper.http_post_aggregate(&req).await.unwrap();
@@ -587,6 +907,23 @@ async fn http_post_aggregate_init_expired_task(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_init_expired_task }
 
+// Test that the Helper aborts an aggregation job in which a report appears more than once.
+async fn http_post_aggregate_init_duplicate_report(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t
+        .gen_test_agg_job_init_req(task_id, version, vec![report.clone(), report])
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::ReportDuplicated { .. })
+    );
+}
+
+async_test_versions! { http_post_aggregate_init_duplicate_report }
+
 // Test that the Helper rejects reports with a bad round number.
 async fn http_post_aggregate_bad_round(version: DapVersion) {
     let t = Test::new(version);
@@ -596,13 +933,8 @@ async fn http_post_aggregate_bad_round(version: DapVersion) {
     }
 
     let report = t.gen_test_report(&t.time_interval_task_id).await;
-    let report_share = ReportShare {
-        report_metadata: report.report_metadata,
-        public_share: report.public_share,
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    };
     let req = t
-        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, vec![report_share])
+        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, vec![report])
         .await;
     let agg_job_id = match &req.resource {
         DapResource::AggregationJob(agg_job_id) => agg_job_id.clone(),
@@ -626,7 +958,7 @@ async fn http_post_aggregate_bad_round(version: DapVersion) {
     );
 }
 
-async_test_versions! { http_post_aggregate_bad_round }
+async_test_versions_multi_round! { http_post_aggregate_bad_round }
 
 // Test that the Helper rejects reports with a bad round id
 async fn http_post_aggregate_zero_round(version: DapVersion) {
@@ -637,13 +969,8 @@ async fn http_post_aggregate_zero_round(version: DapVersion) {
     }
 
     let report = t.gen_test_report(&t.time_interval_task_id).await;
-    let report_share = ReportShare {
-        report_metadata: report.report_metadata,
-        public_share: report.public_share,
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    };
     let req = t
-        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, vec![report_share])
+        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, vec![report])
         .await;
     let agg_job_id = match &req.resource {
         DapResource::AggregationJob(agg_job_id) => agg_job_id.clone(),
@@ -667,7 +994,7 @@ async fn http_post_aggregate_zero_round(version: DapVersion) {
     );
 }
 
-async_test_versions! { http_post_aggregate_zero_round }
+async_test_versions_multi_round! { http_post_aggregate_zero_round }
 
 async fn http_get_hpke_config_unrecognized_task(version: DapVersion) {
     let t = Test::new(version);
@@ -685,6 +1012,12 @@ async fn http_get_hpke_config_unrecognized_task(version: DapVersion) {
         ))
         .unwrap(),
         sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
     };
 
     assert_matches!(
@@ -705,6 +1038,12 @@ async fn http_get_hpke_config_missing_task_id(version: DapVersion) {
         payload: Vec::new(),
         url: Url::parse("http://aggregator.biz/v02/hpke_config").unwrap(),
         sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
     };
 
     // An Aggregator is permitted to abort an HPKE config request if the task ID is missing. Note
@@ -763,6 +1102,112 @@ async fn http_post_aggregate_share_unauthorized_request(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_share_unauthorized_request }
 
+// Test that the Helper rejects an aggregate share request if the Leader aggregated a different set
+// of reports.
+async fn http_post_aggregate_share_batch_mismatch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let checksum = report_id_checksum(&report.report_metadata.id);
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: task_config.quantized_time_lower_bound(t.now),
+            duration: task_config.time_precision,
+        },
+    };
+
+    // The report count does not match.
+    let req = t
+        .gen_test_agg_share_req_for_batch(batch_sel.clone(), 2, checksum)
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await,
+        Err(DapAbort::BatchMismatch { detail, .. }) => assert!(detail.contains(&hex::encode(checksum)))
+    );
+
+    // The checksum does not match.
+    let req = t
+        .gen_test_agg_share_req_for_batch(batch_sel.clone(), 1, [0; 32])
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await,
+        Err(DapAbort::BatchMismatch { .. })
+    );
+
+    // The Aggregators agree on the batch.
+    let req = t
+        .gen_test_agg_share_req_for_batch(batch_sel, 1, checksum)
+        .await;
+    t.helper.http_post_aggregate_share(&req).await.unwrap();
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_batch_mismatch_counter{host="helper.org",reason="report_count"}"#: 1,
+        r#"test_helper_batch_mismatch_counter{host="helper.org",reason="checksum"}"#: 1,
+    });
+}
+
+async_test_versions! { http_post_aggregate_share_batch_mismatch }
+
+// Test that the Helper answers a retried aggregate share request with the same aggregate share and
+// does not count the retry as another query of the batch.
+async fn http_post_aggregate_share_retried(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let checksum = report_id_checksum(&report.report_metadata.id);
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    let window = task_config.quantized_time_lower_bound(t.now);
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: window,
+            duration: task_config.time_precision,
+        },
+    };
+    let req = t
+        .gen_test_agg_share_req_for_batch(batch_sel.clone(), 1, checksum)
+        .await;
+    t.helper.http_post_aggregate_share(&req).await.unwrap();
+    t.helper.http_post_aggregate_share(&req).await.unwrap();
+    assert_eq!(
+        t.helper
+            .get_batch_query_count(task_id, &batch_sel, None)
+            .await
+            .unwrap(),
+        1
+    );
+
+    // A different batch that overlaps the collected one is still rejected.
+    let req = t
+        .gen_test_agg_share_req_for_batch(
+            BatchSelector::TimeInterval {
+                batch_interval: Interval {
+                    start: window - task_config.time_precision,
+                    duration: 2 * task_config.time_precision,
+                },
+            },
+            1,
+            checksum,
+        )
+        .await;
+    assert_matches!(
+        t.helper.http_post_aggregate_share(&req).await,
+        Err(DapAbort::BatchOverlap { .. })
+    );
+}
+
+async_test_versions! { http_post_aggregate_share_retried }
+
 // Test that the Helper handles the batch selector sent from the Leader properly.
 async fn http_post_aggregate_share_invalid_batch_sel(version: DapVersion) {
     let mut rng = thread_rng();
@@ -859,6 +1304,12 @@ async fn http_post_collect_unauthorized_request(version: DapVersion) {
         .get_encoded_with_param(&task_config.version),
         url: task_config.leader_url.join(&url_path).unwrap(),
         sender_auth: None, // Unauthorized request.
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
     };
 
     // Expect failure due to missing bearer token.
@@ -881,21 +1332,10 @@ async fn http_post_aggregate_failure_hpke_decrypt_error(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
 
-    let report = t.gen_test_report(task_id).await;
-    let (report_metadata, public_share, mut encrypted_input_share) = (
-        report.report_metadata,
-        report.public_share,
-        report.encrypted_input_shares[1].clone(),
-    );
-    encrypted_input_share.payload[0] ^= 0xff; // Cause decryption to fail
-    let report_shares = vec![ReportShare {
-        report_metadata,
-        public_share,
-        encrypted_input_share,
-    }];
-    let req = t
-        .gen_test_agg_job_init_req(task_id, version, report_shares)
-        .await;
+    let mut report = t.gen_test_report(task_id).await;
+    report.encrypted_input_shares[1].payload[0] ^= 0xff; // Cause decryption to fail
+    let reports = vec![report];
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
 
     // Get AggregationJobResp and then extract the transition data from inside.
     let agg_job_resp =
@@ -917,15 +1357,8 @@ async fn http_post_aggregate_transition_continue(version: DapVersion) {
     let task_id = &t.time_interval_task_id;
 
     let report = t.gen_test_report(task_id).await;
-    let report_shares = vec![ReportShare {
-        report_metadata: report.report_metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    }];
-    let req = t
-        .gen_test_agg_job_init_req(task_id, version, report_shares)
-        .await;
+    let reports = vec![report];
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
 
     // Get AggregationJobResp and then extract the transition data from inside.
     let agg_job_resp =
@@ -944,15 +1377,8 @@ async fn http_post_aggregate_failure_report_replayed(version: DapVersion) {
     let task_id = &t.time_interval_task_id;
 
     let report = t.gen_test_report(task_id).await;
-    let report_shares = vec![ReportShare {
-        report_metadata: report.report_metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    }];
-    let req = t
-        .gen_test_agg_job_init_req(task_id, version, report_shares)
-        .await;
+    let reports = vec![report.clone()];
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
 
     // Add dummy data to report store backend. This is done in a new scope so that the lock on the
     // report store is released before running the test.
@@ -963,9 +1389,10 @@ async fn http_post_aggregate_failure_report_replayed(version: DapVersion) {
             .lock()
             .expect("report_store: failed to lock");
         let report_store = guard.entry(task_id.clone()).or_default();
-        report_store
-            .processed
-            .insert(report.report_metadata.id.clone());
+        report_store.processed.insert(
+            report.report_metadata.id.clone(),
+            report.report_metadata.time,
+        );
     }
 
     // Get AggregationJobResp and then extract the transition data from inside.
@@ -983,27 +1410,104 @@ async fn http_post_aggregate_failure_report_replayed(version: DapVersion) {
     assert_metrics_include!(t.prometheus_registry, {
         r#"test_helper_report_counter{host="helper.org",status="rejected_report_replayed"}"#: 1,
         r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 1,
     });
+    if version != DapVersion::Draft06 {
+        // The Helper holds on to the aggregation job until the Leader continues it.
+        assert_metrics_include!(t.prometheus_registry, {
+            r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 1,
+        });
+    }
 }
 
 async_test_versions! { http_post_aggregate_failure_report_replayed }
 
-async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
+async fn http_post_aggregate_init_agg_job_limit(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    // Initialize as many aggregation jobs as the Helper is allowed to run at once.
+    for _ in 0..2 {
+        let report = t.gen_test_report(task_id).await;
+        let reports = vec![report];
+        let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
+        t.helper.http_post_aggregate(&req).await.unwrap();
+    }
+
+    // Expect the next aggregation job to be queued until the queue times out.
+    let report = t.gen_test_report(task_id).await;
+    let reports = vec![report];
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::RetryLater { .. })
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 2,
+        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 2,
+        r#"test_helper_aggregation_job_queue_gauge{host="helper.org"}"#: 0,
+    });
+}
+
+async_test_versions_multi_round! { http_post_aggregate_init_agg_job_limit }
+
+async fn http_post_aggregate_replay_window(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.helper.unchecked_get_task_config(task_id).await;
 
     let report = t.gen_test_report(task_id).await;
-    let report_shares = vec![ReportShare {
-        report_metadata: report.report_metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    }];
+    let reports = vec![report.clone()];
+
+    // The report is within its replay window, so its ID is recorded.
     let req = t
-        .gen_test_agg_job_init_req(task_id, version, report_shares)
+        .gen_test_agg_job_init_req(task_id, version, reports.clone())
+        .await;
+    let agg_job_resp =
+        AggregationJobResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(agg_job_resp.transitions[0].var, TransitionVar::Continued(_));
+    let window_end = t
+        .helper
+        .global_config
+        .replay_window_end(&task_config, report.report_metadata.time)
+        .unwrap();
+    assert!(window_end <= task_config.expiration);
+
+    // Once the window has ended, the report ID is purged and the report is dropped rather than
+    // being reported as replayed.
+    t.helper
+        .sleep(std::time::Duration::from_secs(window_end - t.now))
         .await;
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
+    let agg_job_resp =
+        AggregationJobResp::get_decoded(&t.helper.http_post_aggregate(&req).await.unwrap().payload)
+            .unwrap();
+    assert_matches!(
+        agg_job_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::ReportDropped)
+    );
+    assert!(!t
+        .helper
+        .report_store
+        .lock()
+        .unwrap()
+        .get(task_id)
+        .unwrap()
+        .processed
+        .contains_key(&report.report_metadata.id));
+}
+
+async_test_versions! { http_post_aggregate_replay_window }
+
+async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.helper.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let reports = vec![report];
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
 
     // Add mock data to the aggreagte store backend. This is done in its own scope so that the lock
     // is released before running the test. Otherwise the test will deadlock.
@@ -1022,6 +1526,7 @@ async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
             AggStore {
                 agg_share: DapAggregateShare::default(),
                 collected: true,
+                ..Default::default()
             },
         );
     }
@@ -1041,8 +1546,13 @@ async fn http_post_aggregate_failure_batch_collected(version: DapVersion) {
     assert_metrics_include!(t.prometheus_registry, {
         r#"test_helper_report_counter{host="helper.org",status="rejected_batch_collected"}"#: 1,
         r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 1,
     });
+    if version != DapVersion::Draft06 {
+        // The Helper holds on to the aggregation job until the Leader continues it.
+        assert_metrics_include!(t.prometheus_registry, {
+            r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 1,
+        });
+    }
 }
 
 async_test_versions! { http_post_aggregate_failure_batch_collected }
@@ -1052,15 +1562,8 @@ async fn http_post_aggregate_abort_helper_state_overwritten(version: DapVersion)
     let task_id = &t.time_interval_task_id;
 
     let report = t.gen_test_report(task_id).await;
-    let report_shares = vec![ReportShare {
-        report_metadata: report.report_metadata.clone(),
-        public_share: report.public_share,
-        // 1st share is for Leader and the rest is for Helpers (note that there is only 1 helper).
-        encrypted_input_share: report.encrypted_input_shares[1].clone(),
-    }];
-    let req = t
-        .gen_test_agg_job_init_req(task_id, version, report_shares)
-        .await;
+    let reports = vec![report];
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
 
     // Send aggregate request.
     let _ = t.helper.http_post_aggregate(&req).await;
@@ -1074,7 +1577,7 @@ async fn http_post_aggregate_abort_helper_state_overwritten(version: DapVersion)
     );
 }
 
-async_test_versions! { http_post_aggregate_abort_helper_state_overwritten }
+async_test_versions_multi_round! { http_post_aggregate_abort_helper_state_overwritten }
 
 async fn http_post_aggregate_fail_send_cont_req(version: DapVersion) {
     let t = Test::new(version);
@@ -1092,309 +1595,2341 @@ async fn http_post_aggregate_fail_send_cont_req(version: DapVersion) {
 
 async_test_versions! { http_post_aggregate_fail_send_cont_req }
 
-async fn http_post_upload_fail_send_invalid_report(version: DapVersion) {
+// Test that the Helper forgets the state of an aggregation job once it expires.
+async fn http_post_aggregate_helper_state_expired(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
-
-    // Construct a report payload with an invalid task ID.
-    let report_invalid_task_id = t.gen_test_report(task_id).await;
-    let req = DapRequest {
-        version: task_config.version,
-        media_type: DapMediaType::Report,
-        task_id: Some(TaskId([0; 32])),
-        resource: DapResource::Undefined,
-        payload: report_invalid_task_id.get_encoded_with_param(&task_config.version),
-        url: task_config.leader_url.join("upload").unwrap(),
-        sender_auth: None,
+    let report = t.gen_test_report(task_id).await;
+    let req = t
+        .gen_test_agg_job_init_req(task_id, version, vec![report])
+        .await;
+    let agg_job_id = match (&req.resource, version) {
+        (DapResource::AggregationJob(agg_job_id), _) => {
+            MetaAggregationJobId::Draft04(Cow::Owned(agg_job_id.clone()))
+        }
+        (DapResource::Undefined, DapVersion::Draft02) => {
+            let agg_job_init_req =
+                AggregationJobInitReq::get_decoded_with_param(&version, &req.payload).unwrap();
+            MetaAggregationJobId::Draft02(Cow::Owned(agg_job_init_req.draft02_agg_job_id.unwrap()))
+        }
+        _ => panic!("agg_job_id resource missing!"),
     };
+    t.helper.http_post_aggregate(&req).await.unwrap();
 
-    // Expect failure due to invalid task ID in report.
-    assert_matches!(
-        t.leader.http_post_upload(&req).await,
-        Err(DapAbort::UnrecognizedTask)
-    );
-
-    // Construct an invalid report payload that only has one input share.
-    let mut report_one_input_share = t.gen_test_report(task_id).await;
-    report_one_input_share.encrypted_input_shares =
-        vec![report_one_input_share.encrypted_input_shares[0].clone()];
-    let req = t.gen_test_upload_req(report_one_input_share, task_id).await;
+    let ttl = t
+        .helper
+        .global_config
+        .helper_state_store
+        .as_ref()
+        .unwrap()
+        .ttl;
+    t.helper.sleep(std::time::Duration::from_secs(ttl)).await;
 
-    // Expect failure due to incorrect number of input shares
+    let req = t
+        .gen_test_agg_job_cont_req(&agg_job_id, Vec::default(), version)
+        .await;
     assert_matches!(
-        t.leader.http_post_upload(&req).await,
-        Err(DapAbort::UnrecognizedMessage)
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::UnrecognizedAggregationJob { .. })
     );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_helper_state_counter{host="helper.org",status="stored"}"#: 1,
+        r#"test_helper_helper_state_counter{host="helper.org",status="missing"}"#: 1,
+    });
 }
 
-async_test_versions! { http_post_upload_fail_send_invalid_report }
+async_test_versions_multi_round! { http_post_aggregate_helper_state_expired }
 
-// Test that the Leader rejects reports past the expiration date.
-async fn http_post_upload_task_expired(version: DapVersion) {
-    let t = Test::new(version);
-    let task_id = &t.expired_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+// Test that the Helper rejects an aggregation job whose state exceeds the size limit.
+async fn http_post_aggregate_helper_state_too_large(version: DapVersion) {
+    let mut t = Test::new(version);
+    // The Leader holds a reference to the Helper, which is not needed for this test.
+    Arc::get_mut(&mut t.leader).unwrap().peer = None;
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.helper.unchecked_get_task_config(task_id).await;
+
+    // Allow the state of an aggregation job with a single report.
+    Arc::get_mut(&mut t.helper)
+        .unwrap()
+        .global_config
+        .helper_state_store = Some(DapHelperStateStoreConfig {
+        ttl: 3600,
+        max_encoded_len: u64::try_from(DapHelperState::encoded_len(
+            &task_config.vdaf,
+            &PartialBatchSelector::TimeInterval,
+            1,
+        ))
+        .unwrap(),
+    });
+
+    // The size of the state is checked before the reports are prepared: Although the second
+    // report would be rejected during preparation, the request is rejected.
+    let mut reports = vec![
+        t.gen_test_report(task_id).await,
+        t.gen_test_report(task_id).await,
+    ];
+    reports[1].encrypted_input_shares[1].payload[0] ^= 0xff; // Cause decryption to fail
+    let req = t.gen_test_agg_job_init_req(task_id, version, reports).await;
+    assert_matches!(
+        t.helper.http_post_aggregate(&req).await,
+        Err(DapAbort::BadRequest(detail)) => assert!(detail.contains("exceeds the limit"))
+    );
+    assert!(t.helper.helper_state_store.lock().unwrap().is_empty());
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t
+        .gen_test_agg_job_init_req(task_id, version, vec![report])
+        .await;
+    t.helper.http_post_aggregate(&req).await.unwrap();
+    assert_eq!(t.helper.helper_state_store.lock().unwrap().len(), 1);
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_helper_state_counter{host="helper.org",status="rejected"}"#: 1,
+        r#"test_helper_helper_state_counter{host="helper.org",status="stored"}"#: 1,
+    });
+}
+
+async_test_versions_multi_round! { http_post_aggregate_helper_state_too_large }
+
+async fn run_agg_job_abandon_and_requeue(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Helper: Lose the state of the aggregation job after initialization, causing the
+    // continuation to fail.
+    t.helper.drop_helper_state.store(true, Ordering::Relaxed);
+
+    // Leader: Run aggregation job. The job is abandoned and the report is returned to storage.
+    t.run_agg_job(task_id).await.unwrap();
+    {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(task_id).unwrap();
+        assert!(!report_store.processed.contains_key(&report_id));
+        assert!(report_store
+            .pending
+            .values()
+            .flatten()
+            .any(|report| report.report_metadata.id == report_id));
+    }
+
+    // Leader: Run a fresh aggregation job for the requeued report. The Helper has already seen
+    // the report, so it is rejected as replayed.
+    t.helper.drop_helper_state.store(false, Ordering::Relaxed);
+    t.run_agg_job(task_id).await.unwrap();
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_agg_job_abandoned{host="leader.com"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="rejected_report_replayed"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="rejected_report_replayed"}"#: 1,
+    });
+}
+
+async_test_versions_multi_round! { run_agg_job_abandon_and_requeue }
+
+// Test that the Leader retries the continuation of an aggregation job that fails with a
+// transient error.
+async fn run_agg_job_retry_continue(version: DapVersion) {
+    let mut t = Test::new(version);
+    let task_id = t.time_interval_task_id.clone();
+    Arc::get_mut(&mut t.leader)
+        .unwrap()
+        .global_config
+        .agg_job_continue_retry = Some(DapDeadlineRetryConfig {
+        deadline_ms: 1000,
+        initial_delay_ms: 100,
+        max_delay_ms: 400,
+    });
+
+    let report = t.gen_test_report(&task_id).await;
+    let req = t.gen_test_upload_req(report, &task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Fail to reach the Helper once while continuing the job. The request is retried and
+    // the report is aggregated.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::SendAggJobContinueReq,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            max_failures: Some(1),
+            ..Default::default()
+        },
+    ));
+    t.run_agg_job(&task_id).await.unwrap();
+    assert_eq!(t.leader.get_current_time_millis(), t.now * 1000 + 100);
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_agg_job_retried{host="leader.com"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
+    });
+}
+
+async_test_versions_multi_round! { run_agg_job_retry_continue }
+
+// Test that the Leader abandons an aggregation job once the deadline for continuing it has passed.
+async fn run_agg_job_retry_continue_deadline(version: DapVersion) {
+    let mut t = Test::new(version);
+    let task_id = t.time_interval_task_id.clone();
+    Arc::get_mut(&mut t.leader)
+        .unwrap()
+        .global_config
+        .agg_job_continue_retry = Some(DapDeadlineRetryConfig {
+        deadline_ms: 1000,
+        initial_delay_ms: 100,
+        max_delay_ms: 400,
+    });
+
+    let report = t.gen_test_report(&task_id).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, &task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Keep failing to reach the Helper while continuing the job. The request is retried
+    // after 100, 200, and 400 milliseconds; the next retry would pass the deadline, so the job is
+    // abandoned and the report is returned to storage.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::SendAggJobContinueReq,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            ..Default::default()
+        },
+    ));
+    t.run_agg_job(&task_id).await.unwrap();
+    assert_eq!(
+        t.leader.get_current_time_millis(),
+        t.now * 1000 + 100 + 200 + 400
+    );
+    {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(&task_id).unwrap();
+        assert!(report_store
+            .pending
+            .values()
+            .flatten()
+            .any(|report| report.report_metadata.id == report_id));
+    }
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_agg_job_retried{host="leader.com"}"#: 3,
+        r#"test_leader_agg_job_abandoned{host="leader.com"}"#: 1,
+    });
+}
+
+async_test_versions_multi_round! { run_agg_job_retry_continue_deadline }
+
+async fn run_agg_job_retry_and_abandon_init(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Fail to reach the Helper. The initialization request is retried with backoff, then
+    // the job is abandoned and the report is returned to storage.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::SendHttp,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            ..Default::default()
+        },
+    ));
+    t.run_agg_job(task_id).await.unwrap();
+    assert_eq!(t.leader.get_current_time_millis(), t.now * 1000 + 100 + 150);
+    {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(task_id).unwrap();
+        assert!(!report_store.processed.contains_key(&report_id));
+        assert!(report_store
+            .pending
+            .values()
+            .flatten()
+            .any(|report| report.report_metadata.id == report_id));
+    }
+
+    // Leader: Aggregate the report once the Helper is reachable again.
+    *t.leader.faults.lock().unwrap() = None;
+    t.run_agg_job(task_id).await.unwrap();
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_agg_job_retried{host="leader.com"}"#: 2,
+        r#"test_leader_agg_job_abandoned{host="leader.com"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
+    });
+}
+
+async_test_versions! { run_agg_job_retry_and_abandon_init }
+
+// Test that the Leader does not abandon an aggregation job that the Helper rejects for a reason
+// that would recur for a fresh job, but returns the error instead. The reports are not lost.
+async fn run_agg_job_init_rejected_by_helper(version: DapVersion) {
+    let mut t = Test::new(version);
+    let task_id = t.time_interval_task_id.clone();
+
+    let report = t.gen_test_report(&task_id).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, &task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Helper: Expect a different bearer token than the one the Leader sends.
+    Arc::get_mut(&mut t.leader).unwrap().peer = None;
+    Arc::get_mut(&mut t.helper).unwrap().leader_token =
+        BearerToken::from("this is a DIFFERENT bearer token!");
+    Arc::get_mut(&mut t.leader).unwrap().peer = Some(Arc::clone(&t.helper));
+
+    let err = t.run_agg_job(&task_id).await.unwrap_err();
+    assert_eq!(
+        err.peer_abort_type(),
+        Some(DapAbortType::UnauthorizedRequest)
+    );
+
+    // Leader: The report is returned to storage so that it can be aggregated by a later job.
+    {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(&task_id).unwrap();
+        assert!(!report_store.processed.contains_key(&report_id));
+        assert!(report_store
+            .pending
+            .values()
+            .flatten()
+            .any(|report| report.report_metadata.id == report_id));
+    }
+}
+
+async_test_versions! { run_agg_job_init_rejected_by_helper }
+
+// Test that the Leader stops sending aggregation jobs to a Helper that keeps rejecting them as
+// unauthorized, and resumes once the circuit breaker closes.
+async fn run_agg_job_helper_circuit_breaker(version: DapVersion) {
+    let mut t = Test::new(version);
+    let task_id = t.time_interval_task_id.clone();
+
+    // Helper: Expect a different bearer token than the one the Leader sends.
+    Arc::get_mut(&mut t.leader).unwrap().peer = None;
+    Arc::get_mut(&mut t.leader)
+        .unwrap()
+        .global_config
+        .helper_circuit_breaker = Some(DapCircuitBreakerConfig {
+        failure_threshold: 2,
+        open_for: 60,
+    });
+    let leader_token = std::mem::replace(
+        &mut Arc::get_mut(&mut t.helper).unwrap().leader_token,
+        BearerToken::from("this is a DIFFERENT bearer token!"),
+    );
+    Arc::get_mut(&mut t.leader).unwrap().peer = Some(Arc::clone(&t.helper));
+
+    let report = t.gen_test_report(&task_id).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, &task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    let report_is_pending = |t: &Test| {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(&task_id).unwrap();
+        report_store
+            .pending
+            .values()
+            .flatten()
+            .any(|report| report.report_metadata.id == report_id)
+    };
+
+    // Leader: The breaker opens once the Helper rejects two jobs in a row. The report is returned
+    // to storage each time.
+    for _ in 0..2 {
+        assert!(t.run_agg_job(&task_id).await.is_err());
+        assert!(report_is_pending(&t));
+    }
+
+    // Leader: While the breaker is open, the job is deferred rather than sent to the Helper.
+    t.run_agg_job(&task_id).await.unwrap();
+    assert!(report_is_pending(&t));
+
+    // Leader: Once the breaker closes, the job is sent to the Helper again and the report is
+    // aggregated.
+    Arc::get_mut(&mut t.leader).unwrap().peer = None;
+    Arc::get_mut(&mut t.helper).unwrap().leader_token = leader_token;
+    Arc::get_mut(&mut t.leader).unwrap().peer = Some(Arc::clone(&t.helper));
+    t.leader.clock.advance(std::time::Duration::from_secs(61));
+    t.run_agg_job(&task_id).await.unwrap();
+    assert!(t.leader.helper_auth_failures.lock().unwrap().is_empty());
+    assert!(!report_is_pending(&t));
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_helper_auth_failure{host="leader.com"}"#: 2,
+        r#"test_leader_helper_circuit_breaker_opened{host="leader.com"}"#: 1,
+        r#"test_leader_agg_job_deferred{host="leader.com"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+    });
+}
+
+async_test_versions! { run_agg_job_helper_circuit_breaker }
+
+// Test that the Leader rejects the reports that were part of too many abandoned aggregation jobs
+// rather than returning them to storage.
+async fn run_agg_job_abandon_max_attempts(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Fail to reach the Helper. The first job is abandoned and the report is returned to
+    // storage; once the second job is abandoned, the report is rejected.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::SendHttp,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            ..Default::default()
+        },
+    ));
+    t.run_agg_job(task_id).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+    {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(task_id).unwrap();
+        assert!(report_store.processed.contains_key(&report_id));
+        assert!(report_store.pending.values().flatten().next().is_none());
+    }
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_agg_job_abandoned{host="leader.com"}"#: 2,
+        r#"test_leader_report_counter{host="leader.com",status="rejected_agg_job_abandoned"}"#: 1,
+    });
+}
+
+async_test_versions! { run_agg_job_abandon_max_attempts }
+
+// Test that the Leader defers its aggregation jobs while the Helper is overloaded.
+async fn run_agg_job_defer_for_overloaded_helper(version: DapVersion) {
+    let mut t = Test::new(version);
+    let task_id = t.time_interval_task_id.clone();
+
+    // Helper: Ask the Leader to back off for a minute once the limit on running aggregation jobs
+    // is reached.
+    Arc::get_mut(&mut t.leader).unwrap().peer = None;
+    Arc::get_mut(&mut t.helper)
+        .unwrap()
+        .global_config
+        .helper_agg_job_limit = Some(DapHelperAggJobLimit {
+        max_running: 2,
+        queue_timeout_ms: 0,
+        queue_poll_interval_ms: 100,
+        retry_after: Some(60),
+    });
+    Arc::get_mut(&mut t.leader).unwrap().peer = Some(Arc::clone(&t.helper));
+
+    // Helper: Take every slot for running aggregation jobs.
+    for _ in 0..2 {
+        let report = t.gen_test_report(&task_id).await;
+        let req = t
+            .gen_test_agg_job_init_req(&task_id, version, vec![report])
+            .await;
+        t.helper.http_post_aggregate(&req).await.unwrap();
+    }
+
+    let report = t.gen_test_report(&task_id).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, &task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    let report_is_pending = || {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(&task_id).unwrap();
+        !report_store.processed.contains_key(&report_id)
+            && report_store
+                .pending
+                .values()
+                .flatten()
+                .any(|report| report.report_metadata.id == report_id)
+    };
+
+    // Leader: The Helper is overloaded, so the job is deferred without being retried.
+    t.run_agg_job(&task_id).await.unwrap();
+    assert!(report_is_pending());
+    let helper_url = t
+        .leader
+        .unchecked_get_task_config(&task_id)
+        .await
+        .helper_url;
+    let deferred_until = t
+        .leader
+        .helper_deferred_until(&helper_url)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(deferred_until >= t.leader.get_current_time() + 59);
+
+    // Leader: Defer the next job without contacting the Helper, even though the Helper has
+    // capacity again.
+    t.helper.running_agg_jobs.lock().unwrap().clear();
+    t.run_agg_job(&task_id).await.unwrap();
+    assert!(report_is_pending());
+
+    // Leader: Aggregate the report once the deferral has expired.
+    t.leader.clock.advance(Duration::from_secs(60));
+    t.run_agg_job(&task_id).await.unwrap();
+    assert!(!report_is_pending());
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_agg_job_deferred{host="leader.com"}"#: 2,
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
+    });
+}
+
+async_test_versions_multi_round! { run_agg_job_defer_for_overloaded_helper }
+
+// Test that the Aggregators take the current time from their clock, which only moves when the
+// test advances it.
+async fn mock_clock(version: DapVersion) {
+    let t = Test::new(version);
+    for aggregator in [&t.leader, &t.helper] {
+        assert_eq!(aggregator.get_current_time(), t.now);
+        assert_eq!(aggregator.get_current_time_millis(), t.now * 1000);
+    }
+
+    let replay_window = t.leader.global_config.report_replay_window.unwrap();
+    t.advance_clock(replay_window);
+    for aggregator in [&t.leader, &t.helper] {
+        assert_eq!(aggregator.get_current_time(), t.now + replay_window);
+    }
+}
+
+async_test_versions! { mock_clock }
+
+async fn run_agg_job_with_simulated_faults(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    // Leader: Fail to store the report.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::PutReport,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            ..Default::default()
+        },
+    ));
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::RetryLater { .. })
+    );
+
+    // Leader: Store the report, then aggregate it with a slow Helper.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::SendHttp,
+        MockOperationFaults {
+            latency_millis: 1000,
+            jitter_millis: 500,
+            failure_rate: 0.0,
+            ..Default::default()
+        },
+    ));
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // The aggregation job takes one or two requests, depending on the version.
+    let delay_millis = t.leader.get_current_time_millis() - t.now * 1000;
+    assert!(
+        (1000..=3000).contains(&delay_millis),
+        "unexpected delay: {delay_millis}ms"
+    );
+    assert!(t.leader.get_current_time() > t.now);
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+    });
+}
+
+async_test_versions! { run_agg_job_with_simulated_faults }
+
+async fn http_post_upload_fail_send_invalid_report(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Construct a report payload with an invalid task ID.
+    let report_invalid_task_id = t.gen_test_report(task_id).await;
+    let req = DapRequest {
+        version: task_config.version,
+        media_type: DapMediaType::Report,
+        task_id: Some(TaskId([0; 32])),
+        resource: DapResource::Undefined,
+        payload: report_invalid_task_id.get_encoded_with_param(&task_config.version),
+        url: task_config.leader_url.join("upload").unwrap(),
+        sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
+    };
+
+    // Expect failure due to invalid task ID in report.
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedTask)
+    );
+
+    // Construct an invalid report payload that only has one input share.
+    let mut report_one_input_share = t.gen_test_report(task_id).await;
+    report_one_input_share.encrypted_input_shares =
+        vec![report_one_input_share.encrypted_input_shares[0].clone()];
+    let req = t.gen_test_upload_req(report_one_input_share, task_id).await;
+
+    // Expect failure due to incorrect number of input shares
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
+
+async_test_versions! { http_post_upload_fail_send_invalid_report }
+
+// Test that the Leader rejects reports past the expiration date.
+async fn http_post_upload_task_expired(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.expired_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = DapRequest {
+        version: task_config.version,
+        media_type: DapMediaType::Report,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: report.get_encoded_with_param(&version),
+        url: task_config.leader_url.join("upload").unwrap(),
+        sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
+    };
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await.unwrap_err(),
+        DapAbort::ReportTooLate
+    );
+}
+
+async_test_versions! { http_post_upload_task_expired }
+
+// Test that the Leader handles each report of a batched upload independently.
+async fn http_post_upload_batch(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let mut report_one_input_share = t.gen_test_report(task_id).await;
+    report_one_input_share.encrypted_input_shares.truncate(1);
+    let mut report_batch =
+        ReportBatch::from_reports(&version, &[report.clone(), report_one_input_share.clone()]);
+    report_batch.encoded_reports.push(b"not a report".to_vec());
+    report_batch
+        .encoded_reports
+        .push(report.get_encoded_with_param(&version));
+    let req = DapRequest {
+        version,
+        media_type: DapMediaType::ReportBatch,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: report_batch.get_encoded(),
+        url: task_config.leader_url.join("reports/batch").unwrap(),
+        sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
+    };
+
+    if version == DapVersion::Draft02 {
+        assert_matches!(
+            t.leader.http_post_upload_batch(&req).await,
+            Err(DapAbort::BadRequest(..))
+        );
+        return;
+    }
+
+    let statuses = t.leader.http_post_upload_batch(&req).await.unwrap();
+    assert_eq!(statuses.len(), 4);
+    assert_eq!(
+        statuses[0].report_id,
+        Some(report.report_metadata.id.to_base64url())
+    );
+    assert!(statuses[0].problem.is_none());
+    assert_eq!(
+        statuses[1].report_id,
+        Some(report_one_input_share.report_metadata.id.to_base64url())
+    );
+    assert_eq!(
+        statuses[1].problem.as_ref().unwrap().abort_type(),
+        Some(DapAbortType::UnrecognizedMessage)
+    );
+    assert_eq!(statuses[2].report_id, None);
+    assert_eq!(
+        statuses[2].problem.as_ref().unwrap().abort_type(),
+        Some(DapAbortType::UnrecognizedMessage)
+    );
+    assert_eq!(
+        statuses[3].report_id,
+        Some(report.report_metadata.id.to_base64url())
+    );
+    assert_eq!(
+        statuses[3].problem.as_ref().unwrap().abort_type(),
+        Some(DapAbortType::ReportDuplicated)
+    );
+
+    // The valid report was stored.
+    assert_eq!(
+        t.leader
+            .report_store
+            .lock()
+            .unwrap()
+            .get(task_id)
+            .unwrap()
+            .pending
+            .values()
+            .map(|reports| reports.len())
+            .sum::<usize>(),
+        1
+    );
+
+    // Batches larger than the limit are rejected as a whole.
+    let req = DapRequest {
+        payload: ReportBatch {
+            encoded_reports: vec![report.get_encoded_with_param(&version); 5],
+        }
+        .get_encoded(),
+        ..req
+    };
+    assert_matches!(
+        t.leader.http_post_upload_batch(&req).await,
+        Err(DapAbort::BadRequest(detail)) => assert!(detail.contains("exceeds the limit"))
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_inbound_request_counter{host="leader.com",type="upload_batch"}"#: 1,
+    });
+}
+
+async_test_versions! { http_post_upload_batch }
+
+// Test that a dry run of aggregation reports the outcome for each report without changing any
+// state.
+async fn dry_run_aggregation(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let mut report_corrupted = t.gen_test_report(task_id).await;
+    for encrypted_input_share in report_corrupted.encrypted_input_shares.iter_mut() {
+        encrypted_input_share.payload[0] ^= 1;
+    }
+    let report_too_early = t.gen_test_report_at(task_id, t.now + 86400).await;
+    let mut report_batch = ReportBatch::from_reports(
+        &version,
+        &[
+            report.clone(),
+            report_corrupted,
+            report_too_early,
+            report.clone(),
+        ],
+    );
+    report_batch.encoded_reports.push(b"not a report".to_vec());
+
+    for (aggregator, is_leader) in [(&t.leader, true), (&t.helper, false)] {
+        let statuses = aggregator
+            .dry_run_aggregation(
+                task_id,
+                &PartialBatchSelector::TimeInterval,
+                is_leader,
+                &report_batch,
+            )
+            .await
+            .unwrap();
+        let failures: Vec<_> = statuses.iter().map(|status| status.failure).collect();
+        assert_eq!(
+            failures,
+            [
+                None,
+                Some(TransitionFailure::HpkeDecryptError),
+                Some(TransitionFailure::ReportTooEarly),
+                Some(TransitionFailure::ReportReplayed),
+                Some(TransitionFailure::UnrecognizedMessage),
+            ]
+        );
+        assert_eq!(
+            statuses[0].report_id,
+            Some(report.report_metadata.id.to_base64url())
+        );
+        assert_eq!(statuses[4].report_id, None);
+    }
+
+    // The report was not marked as processed.
+    let early_rejects = t
+        .leader
+        .check_early_reject(
+            task_id,
+            &PartialBatchSelector::TimeInterval,
+            [&report.report_metadata].into_iter(),
+        )
+        .await
+        .unwrap();
+    assert!(early_rejects.is_empty());
+
+    // Once it is, it is rejected as a replay.
+    let statuses = t
+        .leader
+        .dry_run_aggregation(
+            task_id,
+            &PartialBatchSelector::TimeInterval,
+            true,
+            &ReportBatch::from_reports(&version, &[report]),
+        )
+        .await
+        .unwrap();
+    assert_eq!(statuses[0].failure, Some(TransitionFailure::ReportReplayed));
+}
+
+async_test_versions! { dry_run_aggregation }
+
+// Test that the Leader rejects reports carrying an extension for which no handler is registered.
+// (For draft03 and later, extensions are encrypted and hence only checked during preparation.)
+async fn http_post_upload_unrecognized_extension(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let mut report = t.gen_test_report(task_id).await;
+    report.report_metadata.extensions = vec![Extension::Unhandled {
+        typ: 0xfff0,
+        payload: b"some extension".to_vec(),
+    }];
+    let req = t.gen_test_upload_req(report, task_id).await;
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
+
+async_test_version! { http_post_upload_unrecognized_extension, Draft02 }
+
+// Test that the Leader checks the task and metadata of a report before the rest of the report is
+// received.
+async fn check_upload_metadata(version: DapVersion) {
+    let t = Test::new(version);
+
+    let report = t.gen_test_report(&t.time_interval_task_id).await;
+    t.leader
+        .check_upload_metadata(
+            version,
+            &t.time_interval_task_id,
+            None,
+            &report.report_metadata,
+        )
+        .await
+        .unwrap();
+
+    assert_matches!(
+        t.leader
+            .check_upload_metadata(version, &TaskId([0; 32]), None, &report.report_metadata)
+            .await,
+        Err(DapAbort::UnrecognizedTask)
+    );
+
+    let report = t.gen_test_report(&t.expired_task_id).await;
+    assert_matches!(
+        t.leader
+            .check_upload_metadata(version, &t.expired_task_id, None, &report.report_metadata)
+            .await,
+        Err(DapAbort::ReportTooLate)
+    );
+}
+
+async_test_versions! { check_upload_metadata }
+
+async fn get_reports_empty_response(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report.clone(), task_id).await;
+
+    // Upload report.
+    t.leader
+        .http_post_upload(&req)
+        .await
+        .expect("upload failed unexpectedly");
+
+    // Get one report. This should return with the report that was uploaded earlier.
+    // We also check that the task ID associated to the report is the same one we
+    // requested.
+    let report_sel = MockAggregatorReportSelector(task_id.clone());
+    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(&returned_task_id, task_id);
+
+    // Try to get another report. This should not return an error, but simply
+    // an empty vector, as we drained the ReportStore above. The task ID
+    // associated to the report should be the same one we requested.
+    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
+    assert_eq!(reports.len(), 0);
+    assert_eq!(&returned_task_id, task_id);
+}
+
+async_test_versions! { get_reports_empty_response }
+
+async fn poll_collect_job_test_results(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq.
+    let version = task_config.version;
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    t.leader.http_post_collect(&req).await.unwrap();
+
+    // Expect DapCollectJob::Unknown due to invalid collect ID.
+    assert_eq!(
+        t.leader
+            .poll_collect_job(task_id, &CollectionJobId::default())
+            .await
+            .unwrap(),
+        DapCollectJob::Unknown
+    );
+
+    // Leader: Get pending collect job to obtain collect_id
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (_task_id, collect_id, _collect_req, _collector_id) = &resp[0];
+    let collect_resp = Collection {
+        part_batch_sel: PartialBatchSelector::TimeInterval,
+        report_count: 0,
+        interval: if version == DapVersion::Draft02 {
+            None
+        } else {
+            Some(Interval {
+                start: 0,
+                duration: 2000000000,
+            })
+        },
+        encrypted_agg_shares: Vec::default(),
+    };
+
+    // Expect DapCollectJob::Pending due to pending collect job.
+    assert_eq!(
+        t.leader
+            .poll_collect_job(task_id, collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Pending
+    );
+
+    // Leader: Complete the collect job by storing CollectResp in LeaderStore.processed.
+    let report_counts =
+        DapReportCountBreakdown::new(task_id, &collect_resp.part_batch_sel, Vec::default());
+    t.leader
+        .finish_collect_job(
+            task_id,
+            collect_id,
+            &BatchSelector::TimeInterval {
+                batch_interval: Interval {
+                    start: 0,
+                    duration: 2000000000,
+                },
+            },
+            &collect_resp,
+            &report_counts,
+        )
+        .await
+        .unwrap();
+
+    // Expect DapCollectJob::Done due to processed collect job.
+    assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Done(..)
+    );
+}
+
+async_test_versions! { poll_collect_job_test_results }
+
+async fn http_get_collect_job_status(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a collect job before any report has been aggregated.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    t.leader.http_post_collect(&req).await.unwrap();
+    let (_, collect_id, collect_req, collector_id) =
+        t.leader.get_pending_collect_jobs().await.unwrap().remove(0);
+
+    let status_req = |collect_id: &CollectionJobId| DapRequest {
+        version,
+        media_type: DapMediaType::CollectReq,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::CollectionJob(collect_id.clone()),
+        payload: Vec::default(),
+        url: task_config.leader_url.join("status").unwrap(),
+        sender_auth: Some(t.collector_token.clone()),
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
+    };
+
+    assert_eq!(
+        t.leader
+            .http_get_collect_job_status(&status_req(&collect_id))
+            .await
+            .unwrap(),
+        CollectionJobStatus {
+            done: false,
+            report_count: 0,
+            batch_fill_percent: 0,
+            estimated_ready_time: None,
+        }
+    );
+
+    // Leader: Aggregate a report, which is enough to fill the batch.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    assert_eq!(
+        t.leader
+            .http_get_collect_job_status(&status_req(&collect_id))
+            .await
+            .unwrap(),
+        CollectionJobStatus {
+            done: false,
+            report_count: 1,
+            batch_fill_percent: 100,
+            estimated_ready_time: Some(t.leader.get_current_time()),
+        }
+    );
+
+    // Leader: Complete the collect job.
+    t.leader
+        .run_collect_job(
+            task_id,
+            &collect_id,
+            &task_config,
+            &collect_req,
+            collector_id.as_deref(),
+            task_config.leader_url.host_str().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        t.leader
+            .http_get_collect_job_status(&status_req(&collect_id))
+            .await
+            .unwrap(),
+        CollectionJobStatus {
+            done: true,
+            report_count: 1,
+            batch_fill_percent: 100,
+            estimated_ready_time: None,
+        }
+    );
+
+    // Expect failure due to an unknown collect job.
+    assert_matches!(
+        t.leader
+            .http_get_collect_job_status(&status_req(&CollectionJobId::default()))
+            .await,
+        Err(DapAbort::BadRequest(..))
+    );
+
+    // Expect failure due to a missing bearer token.
+    assert_matches!(
+        t.leader
+            .http_get_collect_job_status(&DapRequest {
+                sender_auth: None,
+                ..status_req(&collect_id)
+            })
+            .await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+}
+
+async_test_versions! { http_get_collect_job_status }
+
+async fn http_get_batch_suggestions(version: DapVersion) {
+    let t = Test::new(version);
+    let suggestions_req = |task_id: &TaskId| DapRequest {
+        version,
+        media_type: DapMediaType::CollectReq,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: Vec::default(),
+        url: "https://leader.com/batch_suggestions".parse().unwrap(),
+        sender_auth: Some(t.collector_token.clone()),
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
+    };
+
+    // Fixed size: No batch has been started yet.
+    let task_id = &t.fixed_size_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    assert_eq!(
+        t.leader
+            .http_get_batch_suggestions(&suggestions_req(task_id))
+            .await
+            .unwrap(),
+        DapBatchSuggestions::default()
+    );
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+    let batch_id = t.leader.current_batch_id(task_id, &task_config).unwrap();
+    assert_eq!(
+        t.leader
+            .http_get_batch_suggestions(&suggestions_req(task_id))
+            .await
+            .unwrap(),
+        DapBatchSuggestions {
+            batches: vec![DapBatchSuggestion::FixedSize {
+                batch_id: batch_id.to_base64url(),
+                report_count: 1,
+            }],
+        }
+    );
+
+    t.run_col_job(task_id, &Query::FixedSizeByBatchId { batch_id })
+        .await
+        .unwrap();
+    assert_eq!(
+        t.leader
+            .http_get_batch_suggestions(&suggestions_req(task_id))
+            .await
+            .unwrap(),
+        DapBatchSuggestions::default()
+    );
+
+    // Time interval: Aggregate a report, which is enough to fill the batch.
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // The batch window of the report has not ended yet.
+    assert_eq!(
+        t.leader
+            .http_get_batch_suggestions(&suggestions_req(task_id))
+            .await
+            .unwrap(),
+        DapBatchSuggestions::default()
+    );
+
+    t.advance_clock(task_config.time_precision);
+    let query = task_config.query_for_current_batch_window(t.now);
+    let batch_interval = match &query {
+        Query::TimeInterval { batch_interval } => batch_interval.clone(),
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        t.leader
+            .http_get_batch_suggestions(&suggestions_req(task_id))
+            .await
+            .unwrap(),
+        DapBatchSuggestions {
+            batches: vec![DapBatchSuggestion::TimeInterval {
+                batch_interval,
+                report_count: 1,
+            }],
+        }
+    );
+
+    // Once collected, the batch is no longer suggested.
+    t.run_col_job(task_id, &query).await.unwrap();
+    assert_eq!(
+        t.leader
+            .http_get_batch_suggestions(&suggestions_req(task_id))
+            .await
+            .unwrap(),
+        DapBatchSuggestions::default()
+    );
+
+    // Expect failure due to a missing bearer token.
+    assert_matches!(
+        t.leader
+            .http_get_batch_suggestions(&DapRequest {
+                sender_auth: None,
+                ..suggestions_req(task_id)
+            })
+            .await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+}
+
+async_test_versions! { http_get_batch_suggestions }
+
+async fn http_delete_collect_job(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Leader: Aggregate a report, which is enough to fill the batch.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    let collect_req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    let delete_req = |collect_id: &CollectionJobId| DapRequest {
+        version,
+        media_type: DapMediaType::CollectReq,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::CollectionJob(collect_id.clone()),
+        payload: Vec::default(),
+        url: task_config.leader_url.join("collect").unwrap(),
+        sender_auth: Some(t.collector_token.clone()),
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
+    };
+
+    // Collector: Create a collect job, then cancel it before it is run.
+    t.leader.http_post_collect(&collect_req).await.unwrap();
+    let (_, collect_id, ..) = t.leader.get_pending_collect_jobs().await.unwrap().remove(0);
+    t.leader
+        .http_delete_collect_job(&delete_req(&collect_id))
+        .await
+        .unwrap();
+    assert!(t
+        .leader
+        .get_pending_collect_jobs()
+        .await
+        .unwrap()
+        .is_empty());
+    assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Unknown
+    );
+
+    // Collector: The batch was not collected, so it can be collected by a new job.
+    t.leader.http_post_collect(&collect_req).await.unwrap();
+    let (_, collect_id, job_collect_req, collector_id) =
+        t.leader.get_pending_collect_jobs().await.unwrap().remove(0);
+    t.leader
+        .run_collect_job(
+            task_id,
+            &collect_id,
+            &task_config,
+            &job_collect_req,
+            collector_id.as_deref(),
+            task_config.leader_url.host_str().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Collector: Delete the completed job. Its batch remains collected.
+    t.leader
+        .http_delete_collect_job(&delete_req(&collect_id))
+        .await
+        .unwrap();
+    assert_matches!(
+        t.leader
+            .poll_collect_job(task_id, &collect_id)
+            .await
+            .unwrap(),
+        DapCollectJob::Unknown
+    );
+    assert_matches!(
+        t.leader.http_post_collect(&collect_req).await,
+        Err(DapAbort::BatchOverlap { .. })
+    );
+
+    // Expect failure due to an unknown collect job.
+    assert_matches!(
+        t.leader
+            .http_delete_collect_job(&delete_req(&collect_id))
+            .await,
+        Err(DapAbort::BadRequest(..))
+    );
+
+    // Expect failure due to a missing bearer token.
+    assert_matches!(
+        t.leader
+            .http_delete_collect_job(&DapRequest {
+                sender_auth: None,
+                ..delete_req(&collect_id)
+            })
+            .await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_collect_job_deleted_counter{host="leader.com",status="cancelled"}"#: 1,
+        r#"test_leader_collect_job_deleted_counter{host="leader.com",status="deleted"}"#: 1,
+    });
+}
+
+async_test_versions! { http_delete_collect_job }
+
+async fn http_post_collect_fail_invalid_batch_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq with a very large batch interval.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now),
+                        duration: t.leader.global_config.max_batch_duration
+                            + task_config.time_precision,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch interval is too large.
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too large".to_string()));
+
+    // Collector: Create a CollectReq with a batch interval in the past.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now)
+                            - t.leader.global_config.min_batch_interval_start
+                            - task_config.time_precision,
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.helper_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch interval is too far into the past.
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into past".to_string()));
+
+    // Collector: Create a CollectReq with a batch interval in the future.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now)
+                            + t.leader.global_config.max_batch_interval_end
+                            - task_config.time_precision,
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+
+    // Fails because the requested batch interval is too far into the future.
+    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into future".to_string()));
+}
+
+async_test_versions! { http_post_collect_fail_invalid_batch_interval }
+
+async fn http_post_collect_fail_batch_interval_beyond_future_tolerance(version: DapVersion) {
+    let mut t = Test::new(version);
+    Arc::get_mut(&mut t.leader)
+        .unwrap()
+        .global_config
+        .batch_interval_future_tolerance = Some(3600);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let collect_req = |start| CollectionReq {
+        draft02_task_id: task_id.for_request_payload(&version),
+        query: Query::TimeInterval {
+            batch_interval: Interval {
+                start,
+                duration: task_config.time_precision,
+            },
+        },
+        agg_param: Vec::default(),
+    };
+
+    // Collector: Create a CollectReq with a batch interval that ends within the tolerance.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            collect_req(task_config.quantized_time_lower_bound(t.now)),
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert!(t.leader.http_post_collect(&req).await.is_ok());
+
+    // Collector: Create a CollectReq with a batch interval that ends beyond the tolerance.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            collect_req(task_config.quantized_time_lower_bound(t.now) + 2 * 3600),
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::BatchInvalid { detail, .. } => assert!(detail.contains(&(t.now + 3600).to_string()))
+    );
+}
+
+async_test_versions! { http_post_collect_fail_batch_interval_beyond_future_tolerance }
+
+// Test that the batch interval rules set for the task's DAP version take precedence over the rules
+// set for all versions.
+async fn http_post_collect_version_config(version: DapVersion) {
+    let mut t = Test::new(version);
+    let other_version = if version == DapVersion::Draft02 {
+        DapVersion::Draft04
+    } else {
+        DapVersion::Draft02
+    };
+    let task_id = t.time_interval_task_id.clone();
+    let task_config = t.leader.unchecked_get_task_config(&task_id).await;
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            &task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now),
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // The rules for other versions don't apply.
+    let global_config = &mut Arc::get_mut(&mut t.leader).unwrap().global_config;
+    global_config.versions.insert(
+        other_version,
+        DapVersionConfig {
+            max_batch_duration: Some(task_config.time_precision),
+            ..Default::default()
+        },
+    );
+    assert!(t.leader.http_post_collect(&req).await.is_ok());
+
+    let global_config = &mut Arc::get_mut(&mut t.leader).unwrap().global_config;
+    global_config.versions.insert(
+        version,
+        DapVersionConfig {
+            max_batch_duration: Some(task_config.time_precision),
+            ..Default::default()
+        },
+    );
+    assert_matches!(
+        t.leader.http_post_collect(&req).await,
+        Err(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too large")
+    );
+}
+
+async_test_versions! { http_post_collect_version_config }
+
+async fn http_post_collect_succeed_max_batch_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq with a very large batch interval.
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now)
+                            - t.leader.global_config.max_batch_duration / 2,
+                        duration: t.leader.global_config.max_batch_duration,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let _collect_uri = t.leader.http_post_collect(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_collect_succeed_max_batch_interval }
+
+// Send a collect request with an overlapping batch interval.
+async fn http_post_collect_fail_overlapping_batch_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Create a report.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report.clone(), task_id).await;
+
+    // Client: Send upload request to Leader.
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // Leader: Run aggregation job.
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Run first collect job (expect success).
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    // run a second collect job (expect failure due to overlapping batch).
+    assert_matches!(
+        t.run_col_job(task_id, &query).await.unwrap_err(),
+        DapAbort::BatchOverlap { .. }
+    );
+}
+
+async_test_versions! { http_post_collect_fail_overlapping_batch_interval }
+
+// Test a successful collect request submission.
+// This checks that the Leader reponds with the collect ID with the ID associated to the request.
+async fn http_post_collect_success(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Collector: Create a CollectReq.
+    let collector_collect_req = CollectionReq {
+        draft02_task_id: task_id.for_request_payload(&version),
+        query: task_config.query_for_current_batch_window(t.now),
+        agg_param: Vec::default(),
+    };
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            collector_collect_req.clone(),
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+
+    // Leader: Handle the CollectReq received from Collector.
+    let url = t.leader.http_post_collect(&req).await.unwrap();
+    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
+    let (_leader_task_id, leader_collect_id, leader_collect_req, _collector_id) = &resp[0];
+
+    // Check that the CollectReq sent by Collector is the same that is received by Leader.
+    assert_eq!(&collector_collect_req, leader_collect_req);
+
+    // Check that the collect_id included in the URI is the same with the one received
+    // by Leader.
+    let path = url.path().to_string();
+    let mut router = Router::new();
+    router
+        .insert("/:version/collect/task/:task_id/req/:collect_id", true)
+        .unwrap();
+    let url_match = router.at(&path).unwrap();
+    let collector_collect_id = url_match.params.get("collect_id").unwrap();
+    assert_eq!(
+        collector_collect_id.to_string(),
+        leader_collect_id.to_base64url()
+    );
+}
+
+async_test_versions! { http_post_collect_success }
+
+// Test that the Leader handles queries from the Collector properly.
+async fn http_post_collect_invalid_query(version: DapVersion) {
+    let mut rng = thread_rng();
+    let t = Test::new(version);
+
+    // Leader expects "time_interval" query, but Collector sent "fixed_size".
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.time_interval_task_id)
+        .await;
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            DapMediaType::CollectReq,
+            &t.time_interval_task_id,
+            CollectionReq {
+                draft02_task_id: t.time_interval_task_id.for_request_payload(&version),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: BatchId(rng.gen()),
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::QueryMismatch { .. }
+    );
+
+    // Collector indicates unrecognized batch ID.
+    let task_config = t
+        .leader
+        .unchecked_get_task_config(&t.fixed_size_task_id)
+        .await;
+    let req = t
+        .collector_authorized_req(
+            task_config.version,
+            DapMediaType::CollectReq,
+            &t.fixed_size_task_id,
+            CollectionReq {
+                draft02_task_id: t.fixed_size_task_id.for_request_payload(&version),
+                query: Query::FixedSizeByBatchId {
+                    batch_id: BatchId(rng.gen()), // Unrecognized batch ID
+                },
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    assert_matches!(
+        t.leader.http_post_collect(&req).await.unwrap_err(),
+        DapAbort::BatchInvalid { .. }
+    );
+}
+
+async_test_versions! { http_post_collect_invalid_query }
+
+// Test HTTP POST requests with a wrong DAP version.
+async fn http_post_fail_unknown_version(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    // Send a request with the wrong DAP version.
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    req.version = DapVersion::Unknown;
+    req.url = task_config.leader_url.join("upload").unwrap();
+
+    let err = t.leader.http_post_upload(&req).await.unwrap_err();
+    assert_matches!(err, DapAbort::BadRequest(details) => assert_eq!(details, "DAP version of request is not recognized"));
+}
+
+async_test_versions! { http_post_fail_unknown_version }
+
+async fn http_post_upload(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+
+    t.leader
+        .http_post_upload(&req)
+        .await
+        .expect("upload failed unexpectedly");
+}
+
+async_test_versions! { http_post_upload }
+
+// Test that the Leader rejects a report that was uploaded previously with "reportReplayed".
+async fn http_post_upload_replayed(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    t.leader
+        .report_store
+        .lock()
+        .unwrap()
+        .entry(task_id.clone())
+        .or_default()
+        .processed
+        .insert(
+            report.report_metadata.id.clone(),
+            report.report_metadata.time,
+        );
+    let req = t.gen_test_upload_req(report, task_id).await;
+    let abort = t.leader.http_post_upload(&req).await.unwrap_err();
+    assert_matches!(abort, DapAbort::ReportReplayed);
+    assert_eq!(
+        abort.into_problem_details(None).typ.as_deref(),
+        Some("urn:daphne:error:reportReplayed")
+    );
+}
+
+async_test_versions! { http_post_upload_replayed }
+
+// Test that the Leader accepts a retried upload with the same idempotency key and payload, but
+// rejects a different payload under the same key.
+async fn http_post_upload_idempotency_key(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    req.idempotency_key = Some("retry-1".into());
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // The report is stored once.
+    assert_eq!(
+        t.leader
+            .report_store
+            .lock()
+            .unwrap()
+            .get(task_id)
+            .unwrap()
+            .pending
+            .values()
+            .map(|reports| reports.len())
+            .sum::<usize>(),
+        1
+    );
+
+    // A retry is accepted even after the report has been aggregated, whereas the same request
+    // without the idempotency key is rejected as replayed.
+    t.run_agg_job(task_id).await.unwrap();
+    t.leader.http_post_upload(&req).await.unwrap();
+    let replayed_req = DapRequest {
+        idempotency_key: None,
+        ..t.gen_test_upload_req(
+            Report::get_decoded_with_param(&version, &req.payload).unwrap(),
+            task_id,
+        )
+        .await
+    };
+    assert_matches!(
+        t.leader.http_post_upload(&replayed_req).await,
+        Err(DapAbort::ReportReplayed)
+    );
+
+    // Under another key, the same report is rejected as replayed each time it is retried.
+    let replayed_req = DapRequest {
+        idempotency_key: Some("retry-2".into()),
+        ..replayed_req
+    };
+    for _ in 0..2 {
+        assert_matches!(
+            t.leader.http_post_upload(&replayed_req).await,
+            Err(DapAbort::ReportReplayed)
+        );
+    }
+
+    // A different report under the same key is rejected.
+    let mut conflicting_req = t
+        .gen_test_upload_req(t.gen_test_report(task_id).await, task_id)
+        .await;
+    conflicting_req.idempotency_key = Some("retry-1".into());
+    let abort = t
+        .leader
+        .http_post_upload(&conflicting_req)
+        .await
+        .unwrap_err();
+    assert_matches!(abort, DapAbort::IdempotencyKeyConflict { .. });
+    assert_eq!(
+        abort.into_problem_details(None).typ.as_deref(),
+        Some("urn:daphne:error:idempotencyKeyConflict")
+    );
+
+    // Malformed keys are rejected.
+    conflicting_req.idempotency_key = Some("not/a key".into());
+    assert_matches!(
+        t.leader.http_post_upload(&conflicting_req).await,
+        Err(DapAbort::BadRequest(detail)) => assert!(detail.contains("idempotency key"))
+    );
+}
+
+async_test_versions! { http_post_upload_idempotency_key }
+
+// Test that a retried upload is accepted if the Leader stored the report but failed to record that
+// the original request was accepted.
+async fn http_post_upload_idempotency_key_record_failure(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let pending_reports = |t: &Test| {
+        t.leader
+            .report_store
+            .lock()
+            .unwrap()
+            .get(task_id)
+            .unwrap()
+            .pending
+            .values()
+            .map(|reports| reports.len())
+            .sum::<usize>()
+    };
+    let upload_record = |t: &Test, key: &str| {
+        t.leader
+            .report_store
+            .lock()
+            .unwrap()
+            .get(task_id)
+            .and_then(|report_store| report_store.upload_records.get(key).cloned())
+    };
 
     let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    req.idempotency_key = Some("retry-1".into());
+
+    // Leader: Record the request as pending, but fail to record the outcome once the report is
+    // stored. The upload succeeds regardless.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::PutUploadRecord,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            min_successes: 1,
+            ..Default::default()
+        },
+    ));
+    t.leader.http_post_upload(&req).await.unwrap();
+    assert_matches!(upload_record(&t, "retry-1"), Some(record) => {
+        assert_matches!(record.statuses[..], [None]);
+    });
+
+    // Leader: The retry finds the report stored by the original request, rather than rejecting it
+    // as replayed, and records that it was accepted.
+    *t.leader.faults.lock().unwrap() = None;
+    for _ in 0..2 {
+        t.leader.http_post_upload(&req).await.unwrap();
+    }
+    assert_matches!(upload_record(&t, "retry-1"), Some(record) => {
+        assert_matches!(&record.statuses[..], [Some(status)] => assert!(status.problem.is_none()));
+    });
+    assert_eq!(pending_reports(&t), 1);
+
+    // Leader: If the request cannot be recorded as pending, then the upload fails without storing
+    // the report.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::PutUploadRecord,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            ..Default::default()
+        },
+    ));
+    let mut req = t
+        .gen_test_upload_req(t.gen_test_report(task_id).await, task_id)
+        .await;
+    req.idempotency_key = Some("retry-2".into());
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::RetryLater { .. })
+    );
+    assert!(upload_record(&t, "retry-2").is_none());
+    assert_eq!(pending_reports(&t), 1);
+}
+
+async_test_versions! { http_post_upload_idempotency_key_record_failure }
+
+// Test that a retried batched upload is answered with the outcome of each report in the original
+// request, including reports that were rejected as replayed because another request uploaded them.
+async fn http_post_upload_batch_idempotency_key(version: DapVersion) {
+    if version == DapVersion::Draft02 {
+        return;
+    }
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let reports = [
+        t.gen_test_report(task_id).await,
+        t.gen_test_report(task_id).await,
+        t.gen_test_report(task_id).await,
+    ];
+    let uploaded_req = t.gen_test_upload_req(reports[2].clone(), task_id).await;
+    t.leader.http_post_upload(&uploaded_req).await.unwrap();
+
     let req = DapRequest {
-        version: task_config.version,
-        media_type: DapMediaType::Report,
+        version,
+        media_type: DapMediaType::ReportBatch,
         task_id: Some(task_id.clone()),
         resource: DapResource::Undefined,
-        payload: report.get_encoded_with_param(&version),
-        url: task_config.leader_url.join("upload").unwrap(),
+        payload: ReportBatch::from_reports(&version, &reports).get_encoded(),
+        url: task_config.leader_url.join("reports/batch").unwrap(),
         sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: Some("batch-1".into()),
     };
+    let is_replayed = |status: &DapReportUploadStatus| {
+        status
+            .problem
+            .as_ref()
+            .is_some_and(|problem| problem.abort_type() == Some(DapAbortType::ReportReplayed))
+    };
+    for _ in 0..2 {
+        let statuses = t.leader.http_post_upload_batch(&req).await.unwrap();
+        assert_eq!(statuses.len(), 3);
+        assert!(statuses[0].problem.is_none());
+        assert!(statuses[1].problem.is_none());
+        assert!(is_replayed(&statuses[2]));
+    }
 
-    assert_matches!(
-        t.leader.http_post_upload(&req).await.unwrap_err(),
-        DapAbort::ReportTooLate
-    );
+    // A retry after the reports have been aggregated gets the same outcome, whereas without the
+    // idempotency key, each report is rejected as replayed.
+    t.run_agg_job(task_id).await.unwrap();
+    let statuses = t.leader.http_post_upload_batch(&req).await.unwrap();
+    assert!(statuses[0].problem.is_none());
+    assert!(statuses[1].problem.is_none());
+    assert!(is_replayed(&statuses[2]));
+    let statuses = t
+        .leader
+        .http_post_upload_batch(&DapRequest {
+            idempotency_key: None,
+            ..req
+        })
+        .await
+        .unwrap();
+    assert!(statuses.iter().all(is_replayed));
 }
 
-async_test_versions! { http_post_upload_task_expired }
+async_test_versions! { http_post_upload_batch_idempotency_key }
 
-async fn get_reports_empty_response(version: DapVersion) {
+// Test that the reports of a batched upload that failed for a transient reason are uploaded again
+// when the request is retried with the same idempotency key.
+async fn http_post_upload_batch_idempotency_key_transient_failure(version: DapVersion) {
+    if version == DapVersion::Draft02 {
+        return;
+    }
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    let report = t.gen_test_report(task_id).await;
-    let req = t.gen_test_upload_req(report.clone(), task_id).await;
+    let reports = [
+        t.gen_test_report(task_id).await,
+        t.gen_test_report(task_id).await,
+    ];
+    let req = DapRequest {
+        version,
+        media_type: DapMediaType::ReportBatch,
+        task_id: Some(task_id.clone()),
+        resource: DapResource::Undefined,
+        payload: ReportBatch::from_reports(&version, &reports).get_encoded(),
+        url: task_config.leader_url.join("reports/batch").unwrap(),
+        sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: None,
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: Some("batch-1".into()),
+    };
 
-    // Upload report.
-    t.leader
-        .http_post_upload(&req)
-        .await
-        .expect("upload failed unexpectedly");
+    // Leader: Fail to store the reports.
+    *t.leader.faults.lock().unwrap() = Some(MockFaults::new(1337).with_operation(
+        MockOperation::PutReport,
+        MockOperationFaults {
+            failure_rate: 1.0,
+            ..Default::default()
+        },
+    ));
+    let statuses = t.leader.http_post_upload_batch(&req).await.unwrap();
+    assert!(statuses.iter().all(|status| status.problem.is_some()));
+
+    // Leader: The retry stores the reports. Subsequent retries find them accepted.
+    *t.leader.faults.lock().unwrap() = None;
+    for _ in 0..2 {
+        let statuses = t.leader.http_post_upload_batch(&req).await.unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses.iter().all(|status| status.problem.is_none()));
+    }
+    {
+        let guard = t.leader.report_store.lock().unwrap();
+        let report_store = guard.get(task_id).unwrap();
+        assert_eq!(report_store.pending.values().flatten().count(), 2);
+    }
+}
 
-    // Get one report. This should return with the report that was uploaded earlier.
-    // We also check that the task ID associated to the report is the same one we
-    // requested.
-    let report_sel = MockAggregatorReportSelector(task_id.clone());
-    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
-    assert_eq!(reports.len(), 1);
-    assert_eq!(&returned_task_id, task_id);
+async_test_versions! { http_post_upload_batch_idempotency_key_transient_failure }
 
-    // Try to get another report. This should not return an error, but simply
-    // an empty vector, as we drained the ReportStore above. The task ID
-    // associated to the report should be the same one we requested.
-    let (returned_task_id, _part_batch_sel, reports) = get_reports!(t.leader, &report_sel);
-    assert_eq!(reports.len(), 0);
-    assert_eq!(&returned_task_id, task_id);
+// Test that the Leader rejects uploads whose body exceeds the size limit without decoding them.
+async fn http_post_upload_too_large(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
+    req.payload.resize(1 << 13, 0);
+
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::PayloadTooLarge { .. })
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_inbound_request_too_large_counter{host="leader.com",type="upload"}"#: 1,
+    });
 }
 
-async_test_versions! { get_reports_empty_response }
+async_test_versions! { http_post_upload_too_large }
 
-async fn poll_collect_job_test_results(version: DapVersion) {
+async fn http_post_upload_sampled(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Collector: Create a CollectReq.
-    let version = task_config.version;
-    let req = t
-        .collector_authorized_req(
-            version,
-            DapMediaType::CollectReq,
-            task_id,
-            CollectionReq {
-                draft02_task_id: task_id.for_request_payload(&version),
-                query: task_config.query_for_current_batch_window(t.now),
-                agg_param: Vec::default(),
-            },
-            task_config.helper_url.join("collect").unwrap(),
-        )
-        .await;
+    let report = t.gen_test_report_at(task_id, t.now - 30).await;
+    let report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
 
-    // Leader: Handle the CollectReq received from Collector.
-    t.leader.http_post_collect(&req).await.unwrap();
+    // A report encrypted to an unknown HPKE config is sampled, too.
+    let mut report = t.gen_test_report(task_id).await;
+    report.encrypted_input_shares[0].config_id ^= 0xff;
+    let rejected_report_id = report.report_metadata.id.clone();
+    let req = t.gen_test_upload_req(report, task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::ReportDecryptFailed { .. })
+    );
 
-    // Expect DapCollectJob::Unknown due to invalid collect ID.
+    let leader_hpke_config_id = t
+        .leader
+        .get_hpke_config_for(version, Some(task_id))
+        .await
+        .unwrap()
+        .id;
+    let helper_hpke_config_id = t
+        .helper
+        .get_hpke_config_for(version, Some(task_id))
+        .await
+        .unwrap()
+        .id;
+    let guard = t.leader.report_store.lock().unwrap();
+    let samples = &guard.get(task_id).unwrap().samples;
     assert_eq!(
-        t.leader
-            .poll_collect_job(task_id, &CollectionJobId::default())
-            .await
-            .unwrap(),
-        DapCollectJob::Unknown
+        samples,
+        &[
+            DapReportSample {
+                report_id,
+                time_skew: -30,
+                extensions: Vec::new(),
+                taskprov_header: false,
+                hpke_config_ids: vec![leader_hpke_config_id, helper_hpke_config_id],
+                rejected: None,
+            },
+            DapReportSample {
+                report_id: rejected_report_id,
+                time_skew: 0,
+                extensions: Vec::new(),
+                taskprov_header: false,
+                hpke_config_ids: vec![leader_hpke_config_id ^ 0xff, helper_hpke_config_id],
+                rejected: Some("reportDecryptFailed".into()),
+            },
+        ]
     );
+}
 
-    // Leader: Get pending collect job to obtain collect_id
-    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
-    let (_task_id, collect_id, _collect_req) = &resp[0];
-    let collect_resp = Collection {
-        part_batch_sel: PartialBatchSelector::TimeInterval,
-        report_count: 0,
-        interval: if version == DapVersion::Draft02 {
-            None
-        } else {
-            Some(Interval {
-                start: 0,
-                duration: 2000000000,
-            })
-        },
-        encrypted_agg_shares: Vec::default(),
-    };
+async_test_versions! { http_post_upload_sampled }
 
-    // Expect DapCollectJob::Pending due to pending collect job.
-    assert_eq!(
-        t.leader
-            .poll_collect_job(task_id, collect_id)
-            .await
-            .unwrap(),
-        DapCollectJob::Pending
+// Test that the Leader rejects reports whose timestamp is not truncated, if so configured.
+async fn http_post_upload_untruncated_time(version: DapVersion) {
+    let mut t = Test::new(version);
+    let task_id = &t.time_interval_task_id.clone();
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let time = task_config.quantized_time_lower_bound(t.now);
+
+    // Untruncated timestamps are accepted by default.
+    let report = t.gen_test_report_at(task_id, time + 1).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    Arc::get_mut(&mut t.leader)
+        .unwrap()
+        .global_config
+        .reject_untruncated_report_time = true;
+    let report = t.gen_test_report_at(task_id, time + 1).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::ReportRejected { detail }) => assert!(detail.contains("not truncated"))
     );
 
-    // Leader: Complete the collect job by storing CollectResp in LeaderStore.processed.
+    let report = t.gen_test_report_at(task_id, time).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+}
+
+async_test_versions! { http_post_upload_untruncated_time }
+
+// Test that the Leader only accepts uploads for which the Client presents the task's bearer token.
+async fn http_post_upload_bearer_token_auth(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
     t.leader
-        .finish_collect_job(task_id, collect_id, &collect_resp)
-        .await
-        .unwrap();
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .upload_auth = Some(DapUploadAuth::BearerToken("client token".into()));
 
-    // Expect DapCollectJob::Done due to processed collect job.
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
     assert_matches!(
-        t.leader
-            .poll_collect_job(task_id, collect_id)
-            .await
-            .unwrap(),
-        DapCollectJob::Done(..)
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+
+    req.client_auth = Some(DapClientAuth::BearerToken(BearerToken::from(
+        "incorrect token",
+    )));
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
     );
+
+    req.client_auth = Some(DapClientAuth::BearerToken(BearerToken::from(
+        "client token",
+    )));
+    t.leader
+        .http_post_upload(&req)
+        .await
+        .expect("upload failed unexpectedly");
 }
 
-async_test_versions! { poll_collect_job_test_results }
+async_test_versions! { http_post_upload_bearer_token_auth }
 
-async fn http_post_collect_fail_invalid_batch_interval(version: DapVersion) {
+// Test that the Leader only accepts uploads signed with the task's HMAC key.
+async fn http_post_upload_hmac_auth(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
-    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let upload_auth = DapUploadAuth::HmacSha256(vec![7; 32]);
+    t.leader
+        .tasks
+        .lock()
+        .unwrap()
+        .get_mut(task_id)
+        .unwrap()
+        .upload_auth = Some(upload_auth.clone());
 
-    // Collector: Create a CollectReq with a very large batch interval.
-    let req = t
-        .collector_authorized_req(
-            version,
-            DapMediaType::CollectReq,
-            task_id,
-            CollectionReq {
-                draft02_task_id: task_id.for_request_payload(&version),
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now),
-                        duration: t.leader.global_config.max_batch_duration
-                            + task_config.time_precision,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.helper_url.join("collect").unwrap(),
-        )
-        .await;
+    let report = t.gen_test_report(task_id).await;
+    let mut req = t.gen_test_upload_req(report, task_id).await;
 
-    // Leader: Handle the CollectReq received from Collector.
-    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+    // A bearer token is not accepted in place of a signature.
+    req.client_auth = Some(DapClientAuth::BearerToken(BearerToken::from(
+        "client token",
+    )));
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
 
-    // Fails because the requested batch interval is too large.
-    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too large".to_string()));
+    // A signature under a different key is not accepted.
+    req.client_auth = Some(DapUploadAuth::HmacSha256(vec![8; 32]).authorize(task_id, &req.payload));
+    assert_matches!(
+        t.leader.http_post_upload(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
 
-    // Collector: Create a CollectReq with a batch interval in the past.
-    let req = t
-        .collector_authorized_req(
-            version,
-            DapMediaType::CollectReq,
-            task_id,
-            CollectionReq {
-                draft02_task_id: task_id.for_request_payload(&version),
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now)
-                            - t.leader.global_config.min_batch_interval_start
-                            - task_config.time_precision,
-                        duration: task_config.time_precision * 2,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.helper_url.join("collect").unwrap(),
-        )
-        .await;
+    req.client_auth = Some(upload_auth.authorize(task_id, &req.payload));
+    t.leader
+        .http_post_upload(&req)
+        .await
+        .expect("upload failed unexpectedly");
+}
 
-    // Leader: Handle the CollectReq received from Collector.
-    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+async_test_versions! { http_post_upload_hmac_auth }
 
-    // Fails because the requested batch interval is too far into the past.
-    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into past".to_string()));
+async fn e2e_time_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Collector: Create a CollectReq with a batch interval in the future.
-    let req = t
-        .collector_authorized_req(
-            version,
-            DapMediaType::CollectReq,
-            task_id,
-            CollectionReq {
-                draft02_task_id: task_id.for_request_payload(&version),
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now)
-                            + t.leader.global_config.max_batch_interval_end
-                            - task_config.time_precision,
-                        duration: task_config.time_precision * 2,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
 
-    // Leader: Handle the CollectReq received from Collector.
-    let err = t.leader.http_post_collect(&req).await.unwrap_err();
+    // Client: Send upload request to Leader.
+    t.leader.http_post_upload(&req).await.unwrap();
 
-    // Fails because the requested batch interval is too far into the future.
-    assert_matches!(err, DapAbort::BadRequest(s) => assert_eq!(s, "batch interval too far into future".to_string()));
+    // Leader: Run aggregation job.
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Collector: Create collection job and poll result.
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    // The Helper finishes preparation upon receiving the AggregationJobInitReq in draft06.
+    let agg_reqs = if version == DapVersion::Draft06 { 1 } else { 2 };
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: agg_reqs,
+        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 1,
+        r#"test_helper_inbound_request_latency_seconds_count{host="helper.org",type="aggregate"}"#: agg_reqs,
+        r#"test_leader_agg_job_duration_seconds_count{host="leader.com"}"#: 1,
+        r#"test_leader_agg_job_batch_size_sum{host="leader.com"}"#: 1,
+    });
+    if version != DapVersion::Draft06 {
+        assert_metrics_include!(t.prometheus_registry, {
+            r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 0,
+        });
+    }
 }
 
-async_test_versions! { http_post_collect_fail_invalid_batch_interval }
+async_test_versions! { e2e_time_interval }
 
-async fn http_post_collect_succeed_max_batch_interval(version: DapVersion) {
+// Test that reports are aggregated while the VDAF verify key of a task is being rotated, i.e.,
+// after the Leader switched to the new key but before the Helper did.
+async fn e2e_vdaf_verify_key_rotation(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let new_vdaf_verify_key = task_config.vdaf.gen_verify_key();
+    {
+        let mut tasks = t.helper.tasks.lock().unwrap();
+        tasks.get_mut(task_id).unwrap().vdaf_verify_key_rotation = Some(DapVdafVerifyKeyRotation {
+            id: 0,
+            other_keys: HashMap::from([(1, new_vdaf_verify_key.clone())]),
+        });
+    }
+    {
+        let mut tasks = t.leader.tasks.lock().unwrap();
+        let leader_task_config = tasks.get_mut(task_id).unwrap();
+        leader_task_config.vdaf_verify_key = new_vdaf_verify_key;
+        leader_task_config.vdaf_verify_key_rotation = Some(DapVdafVerifyKeyRotation {
+            id: 1,
+            other_keys: HashMap::default(),
+        });
+    }
 
-    // Collector: Create a CollectReq with a very large batch interval.
-    let req = t
-        .collector_authorized_req(
-            version,
-            DapMediaType::CollectReq,
-            task_id,
-            CollectionReq {
-                draft02_task_id: task_id.for_request_payload(&version),
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now)
-                            - t.leader.global_config.max_batch_duration / 2,
-                        duration: t.leader.global_config.max_batch_duration,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
 
-    // Leader: Handle the CollectReq received from Collector.
-    let _collect_uri = t.leader.http_post_collect(&req).await.unwrap();
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
+    });
 }
 
-async_test_versions! { http_post_collect_succeed_max_batch_interval }
+async_test_versions! { e2e_vdaf_verify_key_rotation }
 
-// Send a collect request with an overlapping batch interval.
-async fn http_post_collect_fail_overlapping_batch_interval(version: DapVersion) {
+async fn e2e_fixed_size(version: DapVersion) {
     let t = Test::new(version);
-    let task_id = &t.time_interval_task_id;
+    let task_id = &t.fixed_size_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Create a report.
     let report = t.gen_test_report(task_id).await;
-    let req = t.gen_test_upload_req(report.clone(), task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
 
     // Client: Send upload request to Leader.
     t.leader.http_post_upload(&req).await.unwrap();
@@ -1402,223 +3937,512 @@ async fn http_post_collect_fail_overlapping_batch_interval(version: DapVersion)
     // Leader: Run aggregation job.
     t.run_agg_job(task_id).await.unwrap();
 
-    // Run first collect job (expect success).
-    let query = task_config.query_for_current_batch_window(t.now);
+    // Collector: Create collection job and poll result.
+    let query = Query::FixedSizeByBatchId {
+        batch_id: t.leader.current_batch_id(task_id, &task_config).unwrap(),
+    };
     t.run_col_job(task_id, &query).await.unwrap();
 
-    // run a second collect job (expect failure due to overlapping batch).
-    assert_matches!(
-        t.run_col_job(task_id, &query).await.unwrap_err(),
-        DapAbort::BatchOverlap { .. }
-    );
+    // The Helper finishes preparation upon receiving the AggregationJobInitReq in draft06.
+    let agg_reqs = if version == DapVersion::Draft06 { 1 } else { 2 };
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: agg_reqs,
+        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 1,
+    });
+    if version != DapVersion::Draft06 {
+        assert_metrics_include!(t.prometheus_registry, {
+            r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 0,
+        });
+    }
 }
 
-async_test_versions! { http_post_collect_fail_overlapping_batch_interval }
+async_test_versions! { e2e_fixed_size }
 
-// Test a successful collect request submission.
-// This checks that the Leader reponds with the collect ID with the ID associated to the request.
-async fn http_post_collect_success(version: DapVersion) {
+async fn e2e_fixed_size_stale_batch_close(version: DapVersion) {
     let t = Test::new(version);
-    let task_id = &t.time_interval_task_id;
+    let task_id = &t.fixed_size_task_id;
+    t.set_batch_lifetime(DapStaleBatchPolicy::Close);
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Collector: Create a CollectReq.
-    let collector_collect_req = CollectionReq {
-        draft02_task_id: task_id.for_request_payload(&version),
-        query: task_config.query_for_current_batch_window(t.now),
-        agg_param: Vec::default(),
-    };
-    let req = t
-        .collector_authorized_req(
-            version,
-            DapMediaType::CollectReq,
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    let stale_batch_id = t.leader.current_batch_id(task_id, &task_config).unwrap();
+
+    // The batch is not filled, so it cannot be collected.
+    t.run_agg_job(task_id).await.unwrap();
+    assert_matches!(
+        t.run_col_job_for_collector(
             task_id,
-            collector_collect_req.clone(),
-            task_config.leader_url.join("collect").unwrap(),
+            &Query::FixedSizeByBatchId {
+                batch_id: stale_batch_id.clone()
+            },
+            None
         )
-        .await;
+        .await,
+        Ok(DapCollectJob::Pending)
+    );
 
-    // Leader: Handle the CollectReq received from Collector.
-    let url = t.leader.http_post_collect(&req).await.unwrap();
-    let resp = t.leader.get_pending_collect_jobs().await.unwrap();
-    let (_leader_task_id, leader_collect_id, leader_collect_req) = &resp[0];
+    // Once the batch is stale, the next report is assigned to a new batch and the stale batch is
+    // removed from the queue.
+    t.advance_clock(60);
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    let batch_id = t.leader.current_batch_id(task_id, &task_config).unwrap();
+    assert_ne!(batch_id, stale_batch_id);
+}
 
-    // Check that the CollectReq sent by Collector is the same that is received by Leader.
-    assert_eq!(&collector_collect_req, leader_collect_req);
+async_test_versions! { e2e_fixed_size_stale_batch_close }
 
-    // Check that the collect_id included in the URI is the same with the one received
-    // by Leader.
-    let path = url.path().to_string();
-    let mut router = Router::new();
-    router
-        .insert("/:version/collect/task/:task_id/req/:collect_id", true)
-        .unwrap();
-    let url_match = router.at(&path).unwrap();
-    let collector_collect_id = url_match.params.get("collect_id").unwrap();
-    assert_eq!(
-        collector_collect_id.to_string(),
-        leader_collect_id.to_base64url()
+async fn e2e_fixed_size_stale_batch_force_collectable(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+    t.set_batch_lifetime(DapStaleBatchPolicy::ForceCollectable);
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+    let query = Query::FixedSizeByBatchId {
+        batch_id: t.leader.current_batch_id(task_id, &task_config).unwrap(),
+    };
+
+    // The batch is not filled, so it cannot be collected until it is stale.
+    assert_matches!(
+        t.run_col_job_for_collector(task_id, &query, None).await,
+        Ok(DapCollectJob::Pending)
+    );
+    t.advance_clock(60);
+    assert_matches!(
+        t.run_col_job_for_collector(task_id, &query, None).await,
+        Ok(DapCollectJob::Done(collection)) => {
+            assert_eq!(collection.report_count, 1);
+        }
     );
 }
 
-async_test_versions! { http_post_collect_success }
+async_test_versions! { e2e_fixed_size_stale_batch_force_collectable }
 
-// Test that the Leader handles queries from the Collector properly.
-async fn http_post_collect_invalid_query(version: DapVersion) {
-    let mut rng = thread_rng();
+async fn e2e_fixed_size_force_close_current_batch(version: DapVersion) {
     let t = Test::new(version);
+    let task_id = &t.fixed_size_task_id;
+    for aggregator in [&t.leader, &t.helper] {
+        let mut tasks = aggregator.tasks.lock().unwrap();
+        let task_config = tasks.get_mut(task_id).unwrap();
+        task_config.min_batch_size = 2;
+        task_config.query = DapQueryConfig::FixedSize {
+            max_batch_size: 2,
+            batch_lifetime: None,
+        };
+    }
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Leader expects "time_interval" query, but Collector sent "fixed_size".
-    let task_config = t
-        .leader
-        .unchecked_get_task_config(&t.time_interval_task_id)
-        .await;
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            DapMediaType::CollectReq,
-            &t.time_interval_task_id,
-            CollectionReq {
-                draft02_task_id: t.time_interval_task_id.for_request_payload(&version),
-                query: Query::FixedSizeByBatchId {
-                    batch_id: BatchId(rng.gen()),
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
+    // There is no report to collect.
+    assert_matches!(t.leader.force_close_current_batch(task_id).await, Ok(None));
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+    let batch_id = t.leader.current_batch_id(task_id, &task_config).unwrap();
+    let query = Query::FixedSizeByBatchId {
+        batch_id: batch_id.clone(),
+    };
+
+    // The batch is not filled, so it cannot be collected until it is forced.
     assert_matches!(
-        t.leader.http_post_collect(&req).await.unwrap_err(),
-        DapAbort::QueryMismatch { .. }
+        t.run_col_job_for_collector(task_id, &query, None).await,
+        Ok(DapCollectJob::Pending)
     );
+    assert_eq!(
+        t.leader.force_close_current_batch(task_id).await.unwrap(),
+        Some(batch_id.clone())
+    );
+    let batch_sel = BatchSelector::FixedSizeByBatchId {
+        batch_id: batch_id.clone(),
+    };
+    assert!(t.leader.is_batch_forced(task_id, &batch_sel).await.unwrap());
 
-    // Collector indicates unrecognized batch ID.
-    let task_config = t
-        .leader
-        .unchecked_get_task_config(&t.fixed_size_task_id)
-        .await;
-    let req = t
-        .collector_authorized_req(
-            task_config.version,
-            DapMediaType::CollectReq,
-            &t.fixed_size_task_id,
-            CollectionReq {
-                draft02_task_id: t.fixed_size_task_id.for_request_payload(&version),
-                query: Query::FixedSizeByBatchId {
-                    batch_id: BatchId(rng.gen()), // Unrecognized batch ID
-                },
-                agg_param: Vec::default(),
-            },
-            task_config.leader_url.join("collect").unwrap(),
-        )
-        .await;
+    // Reports uploaded after the batch is closed are assigned to a new batch.
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+
+    // The Helper must also mark the batch as forced.
+    t.helper
+        .mark_batch_forced(task_id, &batch_id)
+        .await
+        .unwrap();
     assert_matches!(
-        t.leader.http_post_collect(&req).await.unwrap_err(),
-        DapAbort::BatchInvalid { .. }
+        t.run_col_job_for_collector(task_id, &query, None).await,
+        Ok(DapCollectJob::Done(collection)) => {
+            assert_eq!(collection.report_count, 1);
+        }
+    );
+    t.run_agg_job(task_id).await.unwrap();
+    assert_ne!(
+        t.leader.current_batch_id(task_id, &task_config),
+        Some(batch_id)
     );
 }
 
-async_test_versions! { http_post_collect_invalid_query }
+async_test_versions! { e2e_fixed_size_force_close_current_batch }
 
-// Test HTTP POST requests with a wrong DAP version.
-async fn http_post_fail_unknown_version(version: DapVersion) {
+async fn force_close_current_batch_time_interval(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    assert_matches!(
+        t.leader.force_close_current_batch(task_id).await,
+        Err(DapError::Abort(DapAbort::QueryMismatch { .. }))
+    );
+}
+
+async_test_versions! { force_close_current_batch_time_interval }
+
+async fn e2e_report_counts(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-    // Send a request with the wrong DAP version.
-    let report = t.gen_test_report(task_id).await;
-    let mut req = t.gen_test_upload_req(report, task_id).await;
-    req.version = DapVersion::Unknown;
-    req.url = task_config.leader_url.join("upload").unwrap();
+    for _ in 0..2 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
 
-    let err = t.leader.http_post_upload(&req).await.unwrap_err();
-    assert_matches!(err, DapAbort::BadRequest(details) => assert_eq!(details, "DAP version of request is not recognized"));
+    // The Leader records the report count of each bucket in the batch. Both reports fall into the
+    // current batch window.
+    let query = task_config.query_for_current_batch_window(t.now);
+    let (collect_id, collect_job) = t.run_col_job_with_id(task_id, &query, None).await.unwrap();
+    assert_matches!(collect_job, DapCollectJob::Done(collection) => {
+        assert_eq!(collection.report_count, 2);
+    });
+    let report_counts = t
+        .leader
+        .get_collection_report_counts(task_id, &collect_id)
+        .unwrap();
+    assert_eq!(
+        report_counts.buckets,
+        vec![DapBucketReportCount {
+            batch_window: Some(task_config.quantized_time_lower_bound(t.now)),
+            report_count: 2,
+        }]
+    );
 }
 
-async_test_versions! { http_post_fail_unknown_version }
+async_test_versions! { e2e_report_counts }
 
-async fn http_post_upload(version: DapVersion) {
+async fn e2e_collect_job_finished_hook(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
     let report = t.gen_test_report(task_id).await;
     let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
 
-    t.leader
-        .http_post_upload(&req)
-        .await
-        .expect("upload failed unexpectedly");
+    // The Leader calls the hook once the collect job is completed.
+    assert!(t.leader.finished_collect_jobs.lock().unwrap().is_empty());
+    let query = task_config.query_for_current_batch_window(t.now);
+    let (collect_id, _) = t.run_col_job_with_id(task_id, &query, None).await.unwrap();
+    assert_eq!(
+        *t.leader.finished_collect_jobs.lock().unwrap(),
+        vec![collect_id]
+    );
 }
 
-async_test_versions! { http_post_upload }
+async_test_versions! { e2e_collect_job_finished_hook }
 
-async fn e2e_time_interval(version: DapVersion) {
+async fn e2e_batch_events(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .unwrap()
+            .get_mut(task_id)
+            .unwrap()
+            .min_batch_size = 2;
+    }
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let bucket = DapBatchBucket::TimeInterval {
+        batch_window: task_config.quantized_time_lower_bound(t.now),
+    };
+
+    // The first report creates the bucket; the second fills it.
+    for _ in 0..2 {
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &query).await.unwrap();
+
+    let created = DapBatchEvent::for_bucket(DapBatchEventType::Created, task_id, &bucket, Some(1));
+    let filled = DapBatchEvent::for_bucket(DapBatchEventType::Filled, task_id, &bucket, Some(2));
+    let collected =
+        DapBatchEvent::for_bucket(DapBatchEventType::Collected, task_id, &bucket, Some(2));
+    assert_eq!(
+        *t.leader.batch_events.lock().unwrap(),
+        vec![created.clone(), filled.clone(), collected]
+    );
+
+    // Only the Leader emits an event when the bucket is collected.
+    assert_eq!(
+        *t.helper.batch_events.lock().unwrap(),
+        vec![created, filled]
+    );
+}
+
+async_test_versions! { e2e_batch_events }
+
+async fn e2e_report_time_skew_tolerance(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+
+    // The first report is ahead of the current time by more than the maximum future time skew
+    // (300s), but within the tolerance (300s); the second is beyond the tolerance.
+    for time in [t.now + 450, t.now + 700] {
+        let report = t.gen_test_report_at(task_id, time).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        t.leader.http_post_upload(&req).await.unwrap();
+        t.run_agg_job(task_id).await.unwrap();
+    }
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",status="report_too_early"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="rejected_report_too_early"}"#: 1,
+        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="report_too_early"}"#: 1,
+        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
+    });
+}
+
+async_test_versions! { e2e_report_time_skew_tolerance }
+
+async fn e2e_multi_collector(version: DapVersion) {
     let t = Test::new(version);
     let task_id = &t.time_interval_task_id;
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    t.add_auditor(task_id).await;
 
     let report = t.gen_test_report(task_id).await;
     let req = t.gen_test_upload_req(report, task_id).await;
-
-    // Client: Send upload request to Leader.
     t.leader.http_post_upload(&req).await.unwrap();
-
-    // Leader: Run aggregation job.
     t.run_agg_job(task_id).await.unwrap();
 
-    // Collector: Create collection job and poll result.
+    // Each Collector may collect the batch once.
     let query = task_config.query_for_current_batch_window(t.now);
     t.run_col_job(task_id, &query).await.unwrap();
+    let collect_job = t
+        .run_col_job_for_collector(task_id, &query, Some("auditor"))
+        .await
+        .unwrap();
+    assert_matches!(
+        t.run_col_job_for_collector(task_id, &query, Some("auditor"))
+            .await,
+        Err(DapAbort::BatchOverlap { .. })
+    );
+    assert_matches!(
+        t.run_col_job(task_id, &query).await,
+        Err(DapAbort::BatchOverlap { .. })
+    );
+
+    // The aggregate shares are encrypted to the auditor's HPKE config.
+    let collection = match collect_job {
+        DapCollectJob::Done(collection) => collection,
+        _ => panic!("unexpected collect job status: {collect_job:?}"),
+    };
+    let agg_res = task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.auditor_hpke_receiver_config,
+            task_id,
+            &BatchSelector::try_from(query).unwrap(),
+            collection.report_count,
+            collection.encrypted_agg_shares,
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(agg_res, DapAggregateResult::U64(1));
 
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 2,
-        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 0,
+        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 2,
+        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 2,
+        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 2,
     });
 }
 
-async_test_versions! { e2e_time_interval }
+async_test_versions! { e2e_multi_collector }
 
-async fn e2e_fixed_size(version: DapVersion) {
+async fn e2e_max_batch_query_count(version: DapVersion) {
     let t = Test::new(version);
-    let task_id = &t.fixed_size_task_id;
+    let task_id = &t.time_interval_task_id;
+    for aggregator in [&t.leader, &t.helper] {
+        aggregator
+            .tasks
+            .lock()
+            .unwrap()
+            .get_mut(task_id)
+            .unwrap()
+            .max_batch_query_count = 2;
+    }
     let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
     let report = t.gen_test_report(task_id).await;
     let req = t.gen_test_upload_req(report, task_id).await;
-
-    // Client: Send upload request to Leader.
     t.leader.http_post_upload(&req).await.unwrap();
-
-    // Leader: Run aggregation job.
     t.run_agg_job(task_id).await.unwrap();
 
-    // Collector: Create collection job and poll result.
-    let query = Query::FixedSizeByBatchId {
-        batch_id: t.leader.current_batch_id(task_id, &task_config).unwrap(),
+    // Collect the current batch window along with the one before it, then subdivide the interval
+    // to collect the current batch window alone.
+    let window = task_config.quantized_time_lower_bound(t.now);
+    let wide_query = Query::TimeInterval {
+        batch_interval: Interval {
+            start: window - task_config.time_precision,
+            duration: 2 * task_config.time_precision,
+        },
     };
+    let query = task_config.query_for_current_batch_window(t.now);
+    t.run_col_job(task_id, &wide_query).await.unwrap();
     t.run_col_job(task_id, &query).await.unwrap();
 
+    // Each bucket of the current batch window has now been queried twice.
+    assert_matches!(
+        t.run_col_job(task_id, &query).await,
+        Err(DapAbort::BatchQueriedMultipleTimes { .. })
+    );
+    assert_matches!(
+        t.run_col_job(task_id, &wide_query).await,
+        Err(DapAbort::BatchQueriedMultipleTimes { .. })
+    );
+
     assert_metrics_include!(t.prometheus_registry, {
-        r#"test_helper_inbound_request_counter{host="helper.org",type="aggregate"}"#: 2,
-        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="aggregated"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="aggregated"}"#: 1,
-        r#"test_leader_report_counter{host="leader.com",status="collected"}"#: 1,
-        r#"test_helper_report_counter{host="helper.org",status="collected"}"#: 1,
-        r#"test_helper_aggregation_job_gauge{host="helper.org"}"#: 0,
+        r#"test_helper_inbound_request_counter{host="helper.org",type="collect"}"#: 2,
     });
 }
 
-async_test_versions! { e2e_fixed_size }
+async_test_versions! { e2e_max_batch_query_count }
+
+// Test that repeated queries of a batch of a task with differential privacy are answered with the
+// same noised aggregate result, so that the Collector cannot average the noise away.
+async fn e2e_dp_repeated_query(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    t.add_auditor(task_id).await;
+    for aggregator in [&t.leader, &t.helper] {
+        let mut tasks = aggregator.tasks.lock().unwrap();
+        let task_config = tasks.get_mut(task_id).unwrap();
+        task_config.max_batch_query_count = 2;
+        task_config.dp = DapDpConfig::DiscreteLaplace {
+            scale: DapRational {
+                numerator: 100,
+                denominator: 1,
+            },
+        };
+    }
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    let query = task_config.query_for_current_batch_window(t.now);
+    let batch_sel = BatchSelector::try_from(query.clone()).unwrap();
+    let mut log = DapCollectionLog::default();
+    let mut checks = Vec::new();
+    for _ in 0..2 {
+        let collection = match t
+            .run_col_job_for_collector(task_id, &query, Some("auditor"))
+            .await
+            .unwrap()
+        {
+            DapCollectJob::Done(collection) => collection,
+            collect_job => panic!("unexpected collect job status: {collect_job:?}"),
+        };
+        let agg_res = task_config
+            .vdaf
+            .consume_encrypted_agg_shares(
+                &t.auditor_hpke_receiver_config,
+                task_id,
+                &batch_sel,
+                collection.report_count,
+                collection.encrypted_agg_shares.clone(),
+                version,
+            )
+            .await
+            .unwrap();
+        checks.push(log.check_and_record(task_id, &batch_sel, &collection, &agg_res));
+    }
+    assert_eq!(
+        checks,
+        [DapCollectionCheck::New, DapCollectionCheck::Consistent]
+    );
+
+    // The Leader counts each collection job as a query, so the batch may not be queried again.
+    assert_matches!(
+        t.run_col_job_for_collector(task_id, &query, Some("auditor"))
+            .await,
+        Err(DapAbort::BatchQueriedMultipleTimes { .. })
+    );
+}
+
+async_test_versions! { e2e_dp_repeated_query }
+
+async fn http_post_collect_unrecognized_collector(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    let query = task_config.query_for_current_batch_window(t.now);
+
+    // The auditor is not a Collector of this task.
+    assert_matches!(
+        t.run_col_job_for_collector(task_id, &query, Some("auditor"))
+            .await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+
+    // The auditor's token is not valid for the primary Collector.
+    t.add_auditor(task_id).await;
+    let req = t
+        .collector_authorized_req(
+            version,
+            DapMediaType::CollectReq,
+            task_id,
+            CollectionReq {
+                draft02_task_id: task_id.for_request_payload(&version),
+                query,
+                agg_param: Vec::default(),
+            },
+            task_config.leader_url.join("collect").unwrap(),
+        )
+        .await;
+    let req = DapRequest {
+        sender_auth: Some(t.auditor_token.clone()),
+        ..req
+    };
+    assert_matches!(
+        t.leader.http_post_collect(&req).await,
+        Err(DapAbort::UnauthorizedRequest { .. })
+    );
+}
 
-async fn e2e_taskprov(version: DapVersion) {
+async_test_versions! { http_post_collect_unrecognized_collector }
+
+async fn e2e_taskprov(version: DapVersion, taskprov_version: TaskprovVersion) {
     let t = Test::new(version);
     let vdaf = VdafConfig::Prio3(Prio3Config::Count);
 
@@ -1645,12 +4469,15 @@ async fn e2e_taskprov(version: DapVersion) {
             var: taskprov::VdafTypeVar::Prio3Aes128Count,
         },
     }
-    .get_encoded_with_param(&t.helper.global_config.taskprov_version);
-    let taskprov_id = crate::taskprov::compute_task_id(
-        t.helper.global_config.taskprov_version,
-        &taskprov_ext_payload,
-    )
-    .unwrap();
+    .get_encoded_with_param(&taskprov_version);
+    let taskprov_id =
+        crate::taskprov::compute_task_id(taskprov_version, &taskprov_ext_payload).unwrap();
+
+    // In draft04, the task config is advertised in a header rather than the report extension.
+    let (taskprov_ext_payload, taskprov_advertisement) = match taskprov_version {
+        TaskprovVersion::Draft04 => (Vec::new(), Some(encode_base64url(&taskprov_ext_payload))),
+        _ => (taskprov_ext_payload, None),
+    };
 
     // Client: Send upload request to Leader.
     let hpke_config_list = [
@@ -1688,6 +4515,12 @@ async fn e2e_taskprov(version: DapVersion) {
         payload: report.get_encoded_with_param(&version),
         url: Url::parse("https://leader.com/upload").unwrap(),
         sender_auth: None,
+        sender_version: None,
+        collector_id: None,
+        taskprov: taskprov_advertisement.clone(),
+        vdaf_verify_key_id: None,
+        client_auth: None,
+        idempotency_key: None,
     };
     t.leader.http_post_upload(&req).await.unwrap();
 
@@ -1696,6 +4529,7 @@ async fn e2e_taskprov(version: DapVersion) {
 
     // The Leader is now configured with the task.
     let task_config = t.leader.unchecked_get_task_config(&taskprov_id).await;
+    assert_eq!(task_config.taskprov_advertisement, taskprov_advertisement);
 
     // Collector: Create collection job and poll result.
     let query = Query::FixedSizeByBatchId {
@@ -1717,7 +4551,17 @@ async fn e2e_taskprov(version: DapVersion) {
     });
 }
 
-async_test_version! { e2e_taskprov, Draft02 }
+async fn e2e_taskprov_draft02(version: DapVersion) {
+    e2e_taskprov(version, TaskprovVersion::Draft02).await;
+}
+
+async_test_version! { e2e_taskprov_draft02, Draft02 }
+
+async fn e2e_taskprov_draft04(version: DapVersion) {
+    e2e_taskprov(version, TaskprovVersion::Draft04).await;
+}
+
+async_test_version! { e2e_taskprov_draft04, Draft02 }
 
 fn early_metadata_checks(version: DapVersion) {
     let t = Test::new(version);
@@ -1793,3 +4637,103 @@ fn early_metadata_checks(version: DapVersion) {
 }
 
 test_versions! { early_metadata_checks }
+
+async fn loopback_unexpected_media_type(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+
+    // Only requests sent from the Leader to the Helper may be delivered over the loopback.
+    assert_matches!(
+        loopback_send_http(t.helper.as_ref(), &req).await,
+        Err(DapError::Fatal(..))
+    );
+}
+
+async_test_versions! { loopback_unexpected_media_type }
+
+// Test that the Helper's aborts are delivered over the loopback as they would be over HTTP.
+async fn loopback_helper_abort(version: DapVersion) {
+    let t = Test::new(version);
+    let mut req = t
+        .gen_test_agg_job_init_req(&t.time_interval_task_id, version, Vec::default())
+        .await;
+    req.sender_auth = None;
+
+    let abort = match loopback_send_http(t.helper.as_ref(), &req).await {
+        Err(DapError::Abort(abort)) => abort,
+        result => panic!("unexpected result: {result:?}"),
+    };
+    assert_eq!(abort.status_code(), 500);
+    assert_eq!(
+        abort.peer_abort_type(),
+        Some(DapAbortType::UnauthorizedRequest)
+    );
+}
+
+async_test_versions! { loopback_helper_abort }
+
+async fn e2e_concurrent_collect_jobs(version: DapVersion) {
+    let t = Test::new(version);
+    let task_id = &t.time_interval_task_id;
+    let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    t.add_auditor(task_id).await;
+
+    let report = t.gen_test_report(task_id).await;
+    let req = t.gen_test_upload_req(report, task_id).await;
+    t.leader.http_post_upload(&req).await.unwrap();
+    t.run_agg_job(task_id).await.unwrap();
+
+    // Both Collectors collect the current batch window. The preceding window is also collected,
+    // but it contains no reports.
+    let query = task_config.query_for_current_batch_window(t.now);
+    let previous_query = Query::TimeInterval {
+        batch_interval: Interval {
+            start: task_config.quantized_time_lower_bound(t.now) - task_config.time_precision,
+            duration: task_config.time_precision,
+        },
+    };
+    for (query, collector_id) in [
+        (&query, None),
+        (&previous_query, None),
+        (&query, Some("auditor")),
+    ] {
+        let req = t
+            .collector_authorized_req(
+                version,
+                DapMediaType::CollectReq,
+                task_id,
+                CollectionReq {
+                    draft02_task_id: task_id.for_request_payload(&version),
+                    query: query.clone(),
+                    agg_param: Vec::default(),
+                },
+                task_config.leader_url.join("collect").unwrap(),
+            )
+            .await;
+        let req = DapRequest {
+            sender_auth: collector_id
+                .map(|_| t.auditor_token.clone())
+                .or(req.sender_auth),
+            collector_id: collector_id.map(str::to_string),
+            taskprov: None,
+            ..req
+        };
+        t.leader.http_post_collect(&req).await.unwrap();
+    }
+
+    // The jobs for the current window overlap, so they are run in separate rounds of the same
+    // processing run. The job for the empty window remains pending.
+    let telem = t
+        .leader
+        .process(&MockAggregatorReportSelector(task_id.clone()), "leader.com")
+        .await
+        .unwrap();
+    assert_eq!(telem.reports_collected, 2);
+    let pending = t.leader.get_pending_collect_jobs().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].2.query, previous_query);
+}
+
+async_test_versions! { e2e_concurrent_collect_jobs }
@@ -0,0 +1,208 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Scheduling of the Leader's collection jobs.
+//!
+//! The Leader drives its pending collection jobs in rounds. In each round, the scheduler selects
+//! as many jobs as are allowed to run concurrently, subject to the constraint that no two jobs
+//! selecting overlapping batches of the same task are run at the same time. Jobs that conflict
+//! with an earlier job are deferred to a later round, so that jobs for the same batch (e.g., for
+//! different Collectors) are run in the order in which they were queued.
+//!
+//! So that a task with a large backlog does not starve the others, the slots of each round are
+//! handed out to the tasks in turn (round-robin), and the number of jobs of a task that run at
+//! the same time may be limited. The task served first in a round is the one after the task that
+//! was served last in the previous round.
+
+use crate::{
+    messages::{Query, TaskId},
+    DapError, DapPendingCollectJob,
+};
+
+/// The state of a collection job managed by [`DapCollectJobScheduler`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DapCollectJobState {
+    /// The job is waiting to be run.
+    Queued,
+
+    /// The job has been selected to run in the current round.
+    Running,
+
+    /// The job has been run. This does not imply that the collection is complete: the job may
+    /// have been run before the batch was ready, in which case it remains pending in the Leader's
+    /// collection job queue.
+    Finished,
+}
+
+/// Scheduler for a set of pending collection jobs.
+pub struct DapCollectJobScheduler {
+    max_concurrent: usize,
+    max_concurrent_per_task: usize,
+    jobs: Vec<(DapPendingCollectJob, DapCollectJobState)>,
+
+    /// The tasks of the jobs, in the order in which their first job was queued.
+    tasks: Vec<TaskId>,
+
+    /// Index in `tasks` of the task to serve first in the next round.
+    next_task: usize,
+}
+
+impl DapCollectJobScheduler {
+    /// Create a scheduler for the given jobs, listed in the order in which they were queued. At
+    /// most `max_concurrent` jobs are run in the same round; a value of `0` is treated as `1`.
+    pub fn new(jobs: Vec<DapPendingCollectJob>, max_concurrent: u64) -> Self {
+        let mut tasks = Vec::new();
+        for (task_id, ..) in &jobs {
+            if !tasks.contains(task_id) {
+                tasks.push(task_id.clone());
+            }
+        }
+        Self {
+            max_concurrent: to_limit(max_concurrent),
+            max_concurrent_per_task: usize::MAX,
+            jobs: jobs
+                .into_iter()
+                .map(|job| (job, DapCollectJobState::Queued))
+                .collect(),
+            tasks,
+            next_task: 0,
+        }
+    }
+
+    /// Run at most `max_concurrent_per_task` jobs of the same task at the same time; a value of
+    /// `0` is treated as `1`.
+    pub fn with_max_concurrent_per_task(mut self, max_concurrent_per_task: u64) -> Self {
+        self.max_concurrent_per_task = to_limit(max_concurrent_per_task);
+        self
+    }
+
+    /// Return the job with the given index.
+    pub fn job(&self, index: usize) -> &DapPendingCollectJob {
+        &self.jobs[index].0
+    }
+
+    /// Return the state of the job with the given index.
+    pub fn state(&self, index: usize) -> DapCollectJobState {
+        self.jobs[index].1
+    }
+
+    /// Select the jobs to run in the next round and mark them as running. A queued job is
+    /// eligible unless its batch overlaps with the batch of a job that is running or that was
+    /// queued before it, or its task already has as many jobs running as allowed. The eligible
+    /// jobs are selected one task at a time, the oldest job of each task first, until the round is
+    /// full. The indices of the selected jobs are returned in the order in which they were
+    /// queued; the list is empty once every job has finished, or while the jobs of the current
+    /// round are still running.
+    pub fn next_round(&mut self) -> Vec<usize> {
+        let mut selected = Vec::new();
+        let mut running = self
+            .jobs
+            .iter()
+            .filter(|(_, state)| *state == DapCollectJobState::Running)
+            .count();
+        let mut last_served = None;
+        let mut served = true;
+        while served && running < self.max_concurrent {
+            served = false;
+            for offset in 0..self.tasks.len() {
+                if running >= self.max_concurrent {
+                    break;
+                }
+
+                let task = (self.next_task + offset) % self.tasks.len();
+                if let Some(index) = self.next_eligible(&self.tasks[task]) {
+                    self.jobs[index].1 = DapCollectJobState::Running;
+                    selected.push(index);
+                    running += 1;
+                    last_served = Some(task);
+                    served = true;
+                }
+            }
+        }
+        if let Some(task) = last_served {
+            self.next_task = (task + 1) % self.tasks.len();
+        }
+        selected.sort_unstable();
+        selected
+    }
+
+    /// Return the index of the oldest job of the given task that may be run now, if any.
+    fn next_eligible(&self, task_id: &TaskId) -> Option<usize> {
+        let running = self
+            .jobs
+            .iter()
+            .filter(|((other_task_id, ..), state)| {
+                other_task_id == task_id && *state == DapCollectJobState::Running
+            })
+            .count();
+        if running >= self.max_concurrent_per_task {
+            return None;
+        }
+
+        (0..self.jobs.len()).find(|index| {
+            let (job, state) = &self.jobs[*index];
+            &job.0 == task_id
+                && *state == DapCollectJobState::Queued
+                && !self.jobs[..*index].iter().any(|(other, state)| {
+                    *state != DapCollectJobState::Finished && overlaps(other, job)
+                })
+        })
+    }
+
+    /// Mark the running job with the given index as finished.
+    pub fn finish(&mut self, index: usize) -> Result<(), DapError> {
+        match self.jobs.get_mut(index) {
+            Some((_, state)) if *state == DapCollectJobState::Running => {
+                *state = DapCollectJobState::Finished;
+                Ok(())
+            }
+            Some(..) => Err(DapError::fatal(
+                "tried to finish a collection job that is not running",
+            )),
+            None => Err(DapError::fatal("tried to finish an unknown collection job")),
+        }
+    }
+
+    /// Returns `true` if every job has finished.
+    pub fn is_finished(&self) -> bool {
+        self.jobs
+            .iter()
+            .all(|(_, state)| *state == DapCollectJobState::Finished)
+    }
+}
+
+/// Convert a configured limit on the number of jobs to run at once. A value of `0` is treated as
+/// `1`.
+fn to_limit(limit: u64) -> usize {
+    usize::try_from(limit).unwrap_or(usize::MAX).max(1)
+}
+
+/// Decide whether two collection jobs may select some of the same reports. If the batch of
+/// either job is not known yet, then the jobs are assumed to overlap.
+fn overlaps(
+    (task_id, _, collect_req, _): &DapPendingCollectJob,
+    (other_task_id, _, other_collect_req, _): &DapPendingCollectJob,
+) -> bool {
+    if task_id != other_task_id {
+        return false;
+    }
+
+    match (&collect_req.query, &other_collect_req.query) {
+        (
+            Query::TimeInterval { batch_interval },
+            Query::TimeInterval {
+                batch_interval: other_batch_interval,
+            },
+        ) => {
+            batch_interval.start < other_batch_interval.end()
+                && other_batch_interval.start < batch_interval.end()
+        }
+        (
+            Query::FixedSizeByBatchId { batch_id },
+            Query::FixedSizeByBatchId {
+                batch_id: other_batch_id,
+            },
+        ) => batch_id == other_batch_id,
+        _ => true,
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    messages::{BatchId, CollectionJobId, CollectionReq, Interval, Query, TaskId},
+    scheduler::{DapCollectJobScheduler, DapCollectJobState},
+    DapPendingCollectJob,
+};
+use rand::prelude::*;
+
+fn job(task_id: &TaskId, query: Query) -> DapPendingCollectJob {
+    (
+        task_id.clone(),
+        CollectionJobId(thread_rng().gen()),
+        CollectionReq {
+            draft02_task_id: Some(task_id.clone()),
+            query,
+            agg_param: Vec::default(),
+        },
+        None,
+    )
+}
+
+fn time_interval(start: u64, duration: u64) -> Query {
+    Query::TimeInterval {
+        batch_interval: Interval { start, duration },
+    }
+}
+
+#[test]
+fn run_non_overlapping_jobs_concurrently() {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let fixed_size_task_id = TaskId(rng.gen());
+    let batch_id = BatchId(rng.gen());
+    let mut scheduler = DapCollectJobScheduler::new(
+        vec![
+            job(&task_id, time_interval(0, 3600)),
+            job(&task_id, time_interval(3600, 3600)),
+            job(&TaskId(rng.gen()), time_interval(0, 3600)),
+            job(&fixed_size_task_id, Query::FixedSizeByBatchId { batch_id }),
+        ],
+        10,
+    );
+
+    assert_eq!(scheduler.next_round(), vec![0, 1, 2, 3]);
+    assert_eq!(scheduler.state(0), DapCollectJobState::Running);
+    assert!(scheduler.next_round().is_empty());
+    for index in 0..4 {
+        scheduler.finish(index).unwrap();
+    }
+    assert!(scheduler.is_finished());
+    assert!(scheduler.next_round().is_empty());
+}
+
+#[test]
+fn defer_overlapping_jobs() {
+    let mut rng = thread_rng();
+    let task_id = TaskId(rng.gen());
+    let fixed_size_task_id = TaskId(rng.gen());
+    let batch_id = BatchId(rng.gen());
+    let mut scheduler = DapCollectJobScheduler::new(
+        vec![
+            job(&task_id, time_interval(0, 7200)),
+            job(&task_id, time_interval(3600, 3600)),
+            job(
+                &fixed_size_task_id,
+                Query::FixedSizeByBatchId {
+                    batch_id: batch_id.clone(),
+                },
+            ),
+            job(&fixed_size_task_id, Query::FixedSizeByBatchId { batch_id }),
+            job(&task_id, time_interval(7200, 3600)),
+        ],
+        10,
+    );
+
+    assert_eq!(scheduler.next_round(), vec![0, 2, 4]);
+    assert_eq!(scheduler.state(1), DapCollectJobState::Queued);
+    for index in [0, 2, 4] {
+        scheduler.finish(index).unwrap();
+    }
+
+    assert_eq!(scheduler.next_round(), vec![1, 3]);
+    for index in [1, 3] {
+        scheduler.finish(index).unwrap();
+    }
+    assert!(scheduler.is_finished());
+}
+
+#[test]
+fn limit_concurrency() {
+    let task_id = TaskId(thread_rng().gen());
+    let mut scheduler = DapCollectJobScheduler::new(
+        (0..5)
+            .map(|i| job(&task_id, time_interval(i * 3600, 3600)))
+            .collect(),
+        2,
+    );
+
+    assert_eq!(scheduler.next_round(), vec![0, 1]);
+    scheduler.finish(0).unwrap();
+    scheduler.finish(1).unwrap();
+    assert_eq!(scheduler.next_round(), vec![2, 3]);
+    scheduler.finish(2).unwrap();
+    scheduler.finish(3).unwrap();
+    assert_eq!(scheduler.next_round(), vec![4]);
+    scheduler.finish(4).unwrap();
+    assert!(scheduler.is_finished());
+}
+
+#[test]
+fn finish_requires_running_job() {
+    let task_id = TaskId(thread_rng().gen());
+    let mut scheduler = DapCollectJobScheduler::new(vec![job(&task_id, time_interval(0, 3600))], 0);
+
+    assert!(scheduler.finish(0).is_err());
+    assert_eq!(scheduler.next_round(), vec![0]);
+    scheduler.finish(0).unwrap();
+    assert!(scheduler.finish(0).is_err());
+    assert!(scheduler.finish(1).is_err());
+}
+
+#[test]
+fn share_rounds_among_tasks() {
+    let mut rng = thread_rng();
+    let busy_task_id = TaskId(rng.gen());
+    let task_id = TaskId(rng.gen());
+    let mut jobs: Vec<_> = (0..4)
+        .map(|i| job(&busy_task_id, time_interval(i * 3600, 3600)))
+        .collect();
+    jobs.push(job(&task_id, time_interval(0, 3600)));
+    jobs.push(job(&task_id, time_interval(3600, 3600)));
+    let mut scheduler = DapCollectJobScheduler::new(jobs, 2);
+
+    // Although the busy task queued its jobs first, each task gets a slot in each round.
+    assert_eq!(scheduler.next_round(), vec![0, 4]);
+    scheduler.finish(0).unwrap();
+    scheduler.finish(4).unwrap();
+    assert_eq!(scheduler.next_round(), vec![1, 5]);
+    scheduler.finish(1).unwrap();
+    scheduler.finish(5).unwrap();
+
+    // Once the other task is done, the busy task gets every slot.
+    assert_eq!(scheduler.next_round(), vec![2, 3]);
+    scheduler.finish(2).unwrap();
+    scheduler.finish(3).unwrap();
+    assert!(scheduler.is_finished());
+}
+
+#[test]
+fn rotate_first_task() {
+    let mut rng = thread_rng();
+    let task_ids: Vec<TaskId> = (0..3).map(|_| TaskId(rng.gen())).collect();
+    let mut scheduler = DapCollectJobScheduler::new(
+        (0..2)
+            .flat_map(|i| {
+                task_ids
+                    .iter()
+                    .map(move |task_id| job(task_id, time_interval(i * 3600, 3600)))
+            })
+            .collect(),
+        2,
+    );
+
+    // The task that was not served in a round is served first in the next.
+    assert_eq!(scheduler.next_round(), vec![0, 1]);
+    scheduler.finish(0).unwrap();
+    scheduler.finish(1).unwrap();
+    assert_eq!(scheduler.next_round(), vec![2, 3]);
+    scheduler.finish(2).unwrap();
+    scheduler.finish(3).unwrap();
+    assert_eq!(scheduler.next_round(), vec![4, 5]);
+    scheduler.finish(4).unwrap();
+    scheduler.finish(5).unwrap();
+    assert!(scheduler.is_finished());
+}
+
+#[test]
+fn limit_concurrency_per_task() {
+    let mut rng = thread_rng();
+    let busy_task_id = TaskId(rng.gen());
+    let task_id = TaskId(rng.gen());
+    let mut jobs: Vec<_> = (0..3)
+        .map(|i| job(&busy_task_id, time_interval(i * 3600, 3600)))
+        .collect();
+    jobs.push(job(&task_id, time_interval(0, 3600)));
+    let mut scheduler = DapCollectJobScheduler::new(jobs, 10).with_max_concurrent_per_task(2);
+
+    assert_eq!(scheduler.next_round(), vec![0, 1, 3]);
+    assert_eq!(scheduler.state(2), DapCollectJobState::Queued);
+    for index in [0, 1, 3] {
+        scheduler.finish(index).unwrap();
+    }
+    assert_eq!(scheduler.next_round(), vec![2]);
+    scheduler.finish(2).unwrap();
+    assert!(scheduler.is_finished());
+}
@@ -0,0 +1,564 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Storage backend for Aggregators.
+//!
+//! The traits in this module describe the state an Aggregator persists: pending reports, aggregate
+//! shares, collection jobs, and task configurations. A deployment can implement them on top of
+//! any storage system (e.g., Durable Objects, Postgres, or Redis) and use the functions below to
+//! implement the storage-related methods of [`DapAggregator`](crate::roles::DapAggregator) and
+//! [`DapLeader`](crate::roles::DapLeader).
+
+use crate::{
+    events::DapBatchEvent,
+    messages::{
+        BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq, Duration, Interval,
+        PartialBatchSelector, Report, TaskId, Time,
+    },
+    DapAggregateShare, DapBatchBucket, DapBucketReportCount, DapCollectJob, DapError,
+    DapOutputShare, DapPendingCollectJob, DapReportCountBreakdown, DapTaskConfig,
+};
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use std::collections::HashMap;
+use url::Url;
+
+/// Storage for task configurations.
+#[async_trait(?Send)]
+pub trait DapTaskConfigStore {
+    /// Look up the configuration of the given task.
+    async fn get_task_config_by_id(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<Option<DapTaskConfig>, DapError>;
+
+    /// Store the configuration of the given task unless a configuration is already stored for it.
+    async fn put_task_config(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+    ) -> Result<(), DapError>;
+}
+
+/// Leader: Storage for reports that are pending aggregation.
+#[async_trait(?Send)]
+pub trait DapReportStore {
+    /// Data type used to guide selection of a set of reports for aggregation.
+    type ReportSelector;
+
+    /// Store a report for use later on. Returns
+    /// [`TransitionFailure::ReportReplayed`](crate::messages::TransitionFailure::ReportReplayed)
+    /// if the report is known to have been stored before.
+    async fn put_pending_report(&self, task_id: &TaskId, report: &Report) -> Result<(), DapError>;
+
+    /// Remove a sequence of reports from storage, grouped by task ID, then by partial batch
+    /// selector.
+    async fn take_pending_reports(
+        &self,
+        selector: &Self::ReportSelector,
+    ) -> Result<HashMap<TaskId, HashMap<PartialBatchSelector, Vec<Report>>>, DapError>;
+
+    /// Return reports that were taken, but not aggregated, to storage. The reports must no longer
+    /// be considered processed.
+    async fn requeue_pending_reports(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        reports: Vec<Report>,
+    ) -> Result<(), DapError>;
+}
+
+/// Storage for the aggregate share of each bucket of reports.
+#[async_trait(?Send)]
+pub trait DapAggregateStore {
+    /// Merge an aggregate share into the aggregate share stored for the bucket. Returns the number
+    /// of reports aggregated into the bucket after the merge, or `None` if the aggregate share was
+    /// already merged by a previous attempt.
+    async fn merge_bucket_agg_share(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        agg_share: DapAggregateShare,
+    ) -> Result<Option<u64>, DapError>;
+
+    /// Get the aggregate share stored for the bucket. If none is stored, then the result is
+    /// empty.
+    async fn get_bucket_agg_share(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Result<DapAggregateShare, DapError>;
+
+    /// Get the number of reports aggregated into the bucket. The default implementation reads the
+    /// bucket's aggregate share; a backend may override it if the count can be read more cheaply.
+    async fn get_bucket_report_count(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Result<u64, DapError> {
+        Ok(self
+            .get_bucket_agg_share(task_id, task_config, bucket)
+            .await?
+            .report_count)
+    }
+
+    /// Mark the bucket as collected by the given Collector (`None` for the task's primary
+    /// Collector). Each call counts as one query of the bucket by the Collector (see
+    /// [`Self::get_bucket_query_count`]). Once the bucket is collected by any Collector, no more
+    /// reports may be aggregated into it.
+    async fn mark_bucket_collected(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> Result<(), DapError>;
+
+    /// Check whether the bucket has been collected by the given Collector (`None` for the task's
+    /// primary Collector).
+    async fn is_bucket_collected(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> Result<bool, DapError>;
+
+    /// Get the number of times the bucket has been collected by the given Collector (`None` for
+    /// the task's primary Collector). The default implementation only tells whether the bucket has
+    /// been collected, which is sufficient for tasks whose buckets may only be queried once.
+    async fn get_bucket_query_count(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> Result<u64, DapError> {
+        Ok(self
+            .is_bucket_collected(task_id, task_config, bucket, collector_id)
+            .await?
+            .into())
+    }
+
+    /// Mark the bucket as forced, i.e., collectable regardless of the task's minimum batch size.
+    async fn mark_bucket_forced(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Result<(), DapError>;
+
+    /// Check whether the bucket has been marked as forced.
+    async fn is_bucket_forced(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Result<bool, DapError>;
+
+    /// Get the aggregate share stored for the span by [`Self::put_span_agg_share`], if any. The
+    /// default implementation stores no spans.
+    async fn get_span_agg_share(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _span: &DapAggregateSpan,
+    ) -> Result<Option<DapAggregateShare>, DapError> {
+        Ok(None)
+    }
+
+    /// Store the aggregate share of the buckets in the span, i.e., the result of compacting them.
+    /// The default implementation discards it.
+    async fn put_span_agg_share(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _span: &DapAggregateSpan,
+        _agg_share: DapAggregateShare,
+    ) -> Result<(), DapError> {
+        Ok(())
+    }
+}
+
+/// A run of consecutive buckets of a time-interval task whose aggregate shares are compacted into a
+/// single object. Spans are aligned to their duration, which is a multiple of the task's time
+/// precision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DapAggregateSpan {
+    pub start: Time,
+    pub duration: Duration,
+}
+
+impl DapAggregateSpan {
+    /// Return the buckets in the span.
+    pub fn buckets(&self, task_config: &DapTaskConfig) -> Vec<DapBatchBucket<'static>> {
+        (self.start..self.start + self.duration)
+            .step_by(task_config.time_precision as usize)
+            .map(|batch_window| DapBatchBucket::TimeInterval { batch_window })
+            .collect()
+    }
+
+    /// Return the spans of the given length (in buckets) that are contained in the batch interval
+    /// and end no later than `not_after`.
+    fn covered_by(
+        task_config: &DapTaskConfig,
+        batch_interval: &Interval,
+        span_buckets: u64,
+        not_after: Time,
+    ) -> Vec<Self> {
+        let duration = task_config.time_precision * span_buckets;
+        if duration == 0 {
+            return Vec::new();
+        }
+        let end = batch_interval.end().min(not_after);
+        let mut start = batch_interval.start.div_ceil(duration) * duration;
+        let mut spans = Vec::new();
+        while start + duration <= end {
+            spans.push(Self { start, duration });
+            start += duration;
+        }
+        spans
+    }
+}
+
+/// Parameters for [`get_compacted_agg_share`].
+#[derive(Clone, Copy, Debug)]
+pub struct DapAggShareCompaction {
+    /// Number of buckets in each span.
+    pub span_buckets: u64,
+
+    /// Only spans that end no later than this time are compacted. No more reports may be
+    /// aggregated into the buckets of such spans, e.g., because reports this old are rejected.
+    pub not_after: Time,
+}
+
+/// Leader: Storage for collection jobs.
+#[async_trait(?Send)]
+pub trait DapCollectionJobQueue {
+    /// Enqueue a collection job on behalf of the given Collector (`None` for the task's primary
+    /// Collector). Returns the draft02 URI of the collection job.
+    async fn put_collection_job(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        collect_job_id: &Option<CollectionJobId>,
+        collect_req: &CollectionReq,
+        collector_id: Option<&str>,
+    ) -> Result<Url, DapError>;
+
+    /// Get the status of a collection job.
+    async fn get_collection_job(
+        &self,
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> Result<DapCollectJob, DapError>;
+
+    /// Get the collection jobs that are pending, in order of priority.
+    async fn get_pending_collection_jobs(&self) -> Result<Vec<DapPendingCollectJob>, DapError>;
+
+    /// Complete a collection job by storing its result, the batch selector resolved for it, and
+    /// the breakdown of its report count.
+    async fn finish_collection_job(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        collect_job_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+        report_counts: &DapReportCountBreakdown,
+    ) -> Result<(), DapError>;
+
+    /// Get the breakdown of the report count of a completed collection job, if any.
+    async fn get_collection_report_counts(
+        &self,
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> Result<Option<DapReportCountBreakdown>, DapError>;
+
+    /// Get the batch selector resolved for a completed collection job, if any.
+    async fn get_collection_batch_selector(
+        &self,
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> Result<Option<BatchSelector>, DapError>;
+
+    /// Delete a collection job, whether it is pending or completed. Returns the status of the job
+    /// before it was deleted.
+    async fn delete_collection_job(
+        &self,
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> Result<DapCollectJob, DapError>;
+}
+
+/// A complete storage backend for a Leader.
+pub trait DapStorage:
+    DapTaskConfigStore + DapReportStore + DapAggregateStore + DapCollectionJobQueue
+{
+}
+
+impl<T> DapStorage for T where
+    T: DapTaskConfigStore + DapReportStore + DapAggregateStore + DapCollectionJobQueue
+{
+}
+
+/// Aggregate a set of output shares and merge the results into the aggregate store. Returns the
+/// lifecycle events of the buckets into which the output shares were merged.
+pub async fn put_out_shares(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    part_batch_sel: &PartialBatchSelector,
+    out_shares: Vec<DapOutputShare>,
+) -> Result<Vec<DapBatchEvent>, DapError> {
+    let span = task_config.batch_span_for_out_shares(part_batch_sel, out_shares)?;
+    let events = try_join_all(span.into_iter().map(|(bucket, agg_share)| async move {
+        let report_count_delta = agg_share.report_count;
+        let events = match store
+            .merge_bucket_agg_share(task_id, task_config, &bucket, agg_share)
+            .await?
+        {
+            Some(report_count) => DapBatchEvent::for_merge(
+                task_id,
+                task_config,
+                &bucket,
+                report_count.saturating_sub(report_count_delta),
+                report_count,
+            ),
+            None => Vec::new(),
+        };
+        Ok::<_, DapError>(events)
+    }))
+    .await?;
+    Ok(events.into_iter().flatten().collect())
+}
+
+/// Get the aggregate share for the given batch by merging the aggregate shares of each bucket in
+/// the batch.
+pub async fn get_agg_share(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_sel: &BatchSelector,
+) -> Result<DapAggregateShare, DapError> {
+    let buckets: Vec<_> = task_config
+        .batch_span_for_sel(batch_sel)?
+        .into_iter()
+        .collect();
+    merge_bucket_agg_shares(store, task_id, task_config, &buckets).await
+}
+
+/// Get the aggregate share for the given batch, like [`get_agg_share`], but compact the aggregate
+/// shares of the buckets of a time-interval task once no more reports may be aggregated into them.
+///
+/// The batch is split into spans (see [`DapAggregateSpan`]) and the remaining buckets. The
+/// aggregate share of each span is read from a single object; the first time a span is read, it
+/// is computed from its buckets and stored. Buckets that are not in a complete span, or in a span
+/// that is too recent to compact, are read individually.
+pub async fn get_compacted_agg_share(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_sel: &BatchSelector,
+    compaction: &DapAggShareCompaction,
+) -> Result<DapAggregateShare, DapError> {
+    let mut span = task_config.batch_span_for_sel(batch_sel)?;
+    let spans = match batch_sel {
+        BatchSelector::TimeInterval { batch_interval } => DapAggregateSpan::covered_by(
+            task_config,
+            batch_interval,
+            compaction.span_buckets,
+            compaction.not_after,
+        ),
+        BatchSelector::FixedSizeByBatchId { .. } => Vec::new(),
+    };
+    for agg_span in spans.iter() {
+        for bucket in agg_span.buckets(task_config) {
+            span.remove(&bucket);
+        }
+    }
+
+    let span_agg_shares = try_join_all(spans.iter().map(|agg_span| async move {
+        if let Some(agg_share) = store
+            .get_span_agg_share(task_id, task_config, agg_span)
+            .await?
+        {
+            return Ok(agg_share);
+        }
+        let buckets = agg_span.buckets(task_config);
+        let agg_share = merge_bucket_agg_shares(store, task_id, task_config, &buckets).await?;
+        store
+            .put_span_agg_share(task_id, task_config, agg_span, agg_share.clone())
+            .await?;
+        Ok::<_, DapError>(agg_share)
+    }))
+    .await?;
+    let buckets: Vec<_> = span.into_iter().collect();
+
+    let mut agg_share = merge_bucket_agg_shares(store, task_id, task_config, &buckets).await?;
+    for agg_share_delta in span_agg_shares {
+        agg_share.merge(agg_share_delta)?;
+    }
+    Ok(agg_share)
+}
+
+async fn merge_bucket_agg_shares(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    buckets: &[DapBatchBucket<'_>],
+) -> Result<DapAggregateShare, DapError> {
+    let agg_shares = try_join_all(
+        buckets
+            .iter()
+            .map(|bucket| store.get_bucket_agg_share(task_id, task_config, bucket)),
+    )
+    .await?;
+
+    let mut agg_share = DapAggregateShare::default();
+    for agg_share_delta in agg_shares {
+        agg_share.merge(agg_share_delta)?;
+    }
+    Ok(agg_share)
+}
+
+/// Get the report count of each bucket in the given batch, ordered by batch window. Empty buckets
+/// are omitted.
+pub async fn get_report_counts(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_sel: &BatchSelector,
+) -> Result<Vec<DapBucketReportCount>, DapError> {
+    let span = task_config.batch_span_for_sel(batch_sel)?;
+    let report_counts = try_join_all(
+        span.iter()
+            .map(|bucket| store.get_bucket_report_count(task_id, task_config, bucket)),
+    )
+    .await?;
+
+    let mut report_counts: Vec<DapBucketReportCount> = span
+        .iter()
+        .zip(report_counts)
+        .filter(|(_bucket, report_count)| *report_count > 0)
+        .map(|(bucket, report_count)| DapBucketReportCount {
+            batch_window: match bucket {
+                DapBatchBucket::FixedSize { .. } => None,
+                DapBatchBucket::TimeInterval { batch_window } => Some(*batch_window),
+            },
+            report_count,
+        })
+        .collect();
+    report_counts.sort_by_key(|bucket| bucket.batch_window);
+    Ok(report_counts)
+}
+
+/// Check whether any bucket in the given batch has been collected by the given Collector.
+pub async fn is_batch_overlapping(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_sel: &BatchSelector,
+    collector_id: Option<&str>,
+) -> Result<bool, DapError> {
+    let span = task_config.batch_span_for_sel(batch_sel)?;
+    let collected = try_join_all(
+        span.iter()
+            .map(|bucket| store.is_bucket_collected(task_id, task_config, bucket, collector_id)),
+    )
+    .await?;
+    Ok(collected.into_iter().any(|collected| collected))
+}
+
+/// Get the number of times the given Collector has queried the given batch, i.e., the largest
+/// query count of the buckets in the batch.
+pub async fn get_batch_query_count(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_sel: &BatchSelector,
+    collector_id: Option<&str>,
+) -> Result<u64, DapError> {
+    let span = task_config.batch_span_for_sel(batch_sel)?;
+    let query_counts =
+        try_join_all(span.iter().map(|bucket| {
+            store.get_bucket_query_count(task_id, task_config, bucket, collector_id)
+        }))
+        .await?;
+    Ok(query_counts.into_iter().max().unwrap_or_default())
+}
+
+/// Check whether any report has been aggregated in the given fixed-size batch.
+pub async fn batch_exists(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_id: &BatchId,
+) -> Result<bool, DapError> {
+    let agg_share = store
+        .get_bucket_agg_share(
+            task_id,
+            task_config,
+            &DapBatchBucket::FixedSize { batch_id },
+        )
+        .await?;
+    Ok(!agg_share.empty())
+}
+
+/// Mark each bucket in the given batch as collected by the given Collector.
+pub async fn mark_collected(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_sel: &BatchSelector,
+    collector_id: Option<&str>,
+) -> Result<(), DapError> {
+    let span = task_config.batch_span_for_sel(batch_sel)?;
+    try_join_all(
+        span.iter()
+            .map(|bucket| store.mark_bucket_collected(task_id, task_config, bucket, collector_id)),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Mark the given fixed-size batch as forced.
+pub async fn mark_batch_forced(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_id: &BatchId,
+) -> Result<(), DapError> {
+    store
+        .mark_bucket_forced(
+            task_id,
+            task_config,
+            &DapBatchBucket::FixedSize { batch_id },
+        )
+        .await
+}
+
+/// Check whether the given batch has been marked as forced. Only fixed-size batches identified by
+/// their batch ID can be forced.
+pub async fn is_batch_forced(
+    store: &impl DapAggregateStore,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    batch_sel: &BatchSelector,
+) -> Result<bool, DapError> {
+    match batch_sel {
+        BatchSelector::FixedSizeByBatchId { batch_id } => {
+            store
+                .is_bucket_forced(
+                    task_id,
+                    task_config,
+                    &DapBatchBucket::FixedSize { batch_id },
+                )
+                .await
+        }
+        BatchSelector::TimeInterval { .. } => Ok(false),
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    events::DapBatchEventType,
+    hpke::HpkeReceiverConfig,
+    messages::{BatchSelector, HpkeKemId, Interval, PartialBatchSelector, TaskId},
+    storage::{
+        get_agg_share, get_batch_query_count, get_compacted_agg_share, is_batch_overlapping,
+        mark_collected, put_out_shares, DapAggShareCompaction, DapAggregateSpan, DapAggregateStore,
+    },
+    testing::DapBatchBucketOwned,
+    vdaf::{VdafAggregateShare, VdafVerifyKey},
+    DapAggregateShare, DapBatchBucket, DapDpConfig, DapError, DapOutputShare, DapQueryConfig,
+    DapTaskConfig, DapVersion, Prio3Config, VdafConfig,
+};
+use async_trait::async_trait;
+use prio::{
+    field::Field64,
+    vdaf::{AggregateShare, OutputShare},
+};
+use rand::prelude::*;
+use std::{collections::HashMap, sync::Mutex};
+use url::Url;
+
+/// The aggregate share of a bucket, the number of times each Collector has collected it, and whether
+/// it has been forced.
+type Bucket = (DapAggregateShare, HashMap<Option<String>, u64>, bool);
+
+#[derive(Default)]
+struct InMemoryAggregateStore {
+    buckets: Mutex<HashMap<DapBatchBucketOwned, Bucket>>,
+    spans: Mutex<HashMap<DapAggregateSpan, DapAggregateShare>>,
+
+    /// Number of times the aggregate share of a bucket was read.
+    bucket_reads: Mutex<u64>,
+}
+
+#[async_trait(?Send)]
+impl DapAggregateStore for InMemoryAggregateStore {
+    async fn merge_bucket_agg_share(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        agg_share: DapAggregateShare,
+    ) -> Result<Option<u64>, DapError> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let (stored_agg_share, _collected, _forced) =
+            buckets.entry(bucket.to_owned_bucket()).or_default();
+        stored_agg_share.merge(agg_share)?;
+        Ok(Some(stored_agg_share.report_count))
+    }
+
+    async fn get_bucket_agg_share(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Result<DapAggregateShare, DapError> {
+        *self.bucket_reads.lock().unwrap() += 1;
+        Ok(self
+            .buckets
+            .lock()
+            .unwrap()
+            .get(&bucket.to_owned_bucket())
+            .map(|(agg_share, _collected, _forced)| agg_share.clone())
+            .unwrap_or_default())
+    }
+
+    async fn mark_bucket_collected(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> Result<(), DapError> {
+        *self
+            .buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_owned_bucket())
+            .or_default()
+            .1
+            .entry(collector_id.map(str::to_string))
+            .or_default() += 1;
+        Ok(())
+    }
+
+    async fn is_bucket_collected(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> Result<bool, DapError> {
+        Ok(self
+            .buckets
+            .lock()
+            .unwrap()
+            .get(&bucket.to_owned_bucket())
+            .map(|(_agg_share, query_counts, _forced)| {
+                query_counts.contains_key(&collector_id.map(str::to_string))
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_bucket_query_count(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> Result<u64, DapError> {
+        Ok(self
+            .buckets
+            .lock()
+            .unwrap()
+            .get(&bucket.to_owned_bucket())
+            .and_then(|(_agg_share, query_counts, _forced)| {
+                query_counts.get(&collector_id.map(str::to_string)).copied()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn mark_bucket_forced(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Result<(), DapError> {
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_owned_bucket())
+            .or_default()
+            .2 = true;
+        Ok(())
+    }
+
+    async fn is_bucket_forced(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> Result<bool, DapError> {
+        Ok(self
+            .buckets
+            .lock()
+            .unwrap()
+            .get(&bucket.to_owned_bucket())
+            .is_some_and(|(_agg_share, _query_counts, forced)| *forced))
+    }
+
+    async fn get_span_agg_share(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        span: &DapAggregateSpan,
+    ) -> Result<Option<DapAggregateShare>, DapError> {
+        Ok(self.spans.lock().unwrap().get(span).cloned())
+    }
+
+    async fn put_span_agg_share(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        span: &DapAggregateSpan,
+        agg_share: DapAggregateShare,
+    ) -> Result<(), DapError> {
+        self.spans.lock().unwrap().insert(*span, agg_share);
+        Ok(())
+    }
+}
+
+fn out_share(time: u64) -> DapOutputShare {
+    DapOutputShare {
+        time,
+        checksum: thread_rng().gen(),
+        data: VdafAggregateShare::Field64(AggregateShare::from(OutputShare::from(vec![
+            Field64::from(1),
+        ]))),
+    }
+}
+
+fn task_config() -> DapTaskConfig {
+    let mut rng = thread_rng();
+    DapTaskConfig {
+        version: DapVersion::Draft04,
+        leader_url: Url::parse("https://leader.com/v04/").unwrap(),
+        helper_url: Url::parse("https://helper.org/v04/").unwrap(),
+        time_precision: 3600,
+        expiration: 1637364937,
+        min_batch_size: 1,
+        max_batch_query_count: 1,
+        query: DapQueryConfig::TimeInterval,
+        vdaf: VdafConfig::Prio3(Prio3Config::Count),
+        vdaf_verify_key: VdafVerifyKey::Prio3(rng.gen()),
+        collector_hpke_config: HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config,
+        additional_collectors: Vec::new(),
+        dp: DapDpConfig::None,
+        taskprov_advertisement: None,
+        replay_filter: None,
+        vdaf_verify_key_rotation: None,
+        upload_auth: None,
+    }
+}
+
+#[tokio::test]
+async fn aggregate_store_span() {
+    let task_id = TaskId(thread_rng().gen());
+    let task_config = task_config();
+    let store = InMemoryAggregateStore::default();
+
+    // Output shares spanning two buckets.
+    let events = put_out_shares(
+        &store,
+        &task_id,
+        &task_config,
+        &PartialBatchSelector::TimeInterval,
+        vec![out_share(3600), out_share(3601), out_share(7200)],
+    )
+    .await
+    .unwrap();
+    assert_eq!(store.buckets.lock().unwrap().len(), 2);
+
+    // Each bucket is created and, since the minimum batch size is 1, filled.
+    assert_eq!(events.len(), 4);
+    assert!(events
+        .iter()
+        .any(|event| event.event == DapBatchEventType::Filled
+            && event.batch_window == Some(3600)
+            && event.report_count == Some(2)));
+
+    let first_bucket = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 3600,
+            duration: 3600,
+        },
+    };
+    let both_buckets = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 3600,
+            duration: 7200,
+        },
+    };
+    assert_eq!(
+        get_agg_share(&store, &task_id, &task_config, &first_bucket)
+            .await
+            .unwrap()
+            .report_count,
+        2
+    );
+    assert_eq!(
+        get_agg_share(&store, &task_id, &task_config, &both_buckets)
+            .await
+            .unwrap()
+            .report_count,
+        3
+    );
+
+    // The timestamps of the output shares are truncated to the time precision.
+    let agg_share = get_agg_share(&store, &task_id, &task_config, &first_bucket)
+        .await
+        .unwrap();
+    assert_eq!((agg_share.min_time, agg_share.max_time), (3600, 3600));
+
+    // Collecting one bucket makes any batch that contains it overlapping for the same Collector,
+    // but not for other Collectors.
+    assert!(
+        !is_batch_overlapping(&store, &task_id, &task_config, &both_buckets, None)
+            .await
+            .unwrap()
+    );
+    mark_collected(&store, &task_id, &task_config, &first_bucket, None)
+        .await
+        .unwrap();
+    assert!(
+        is_batch_overlapping(&store, &task_id, &task_config, &both_buckets, None)
+            .await
+            .unwrap()
+    );
+    assert!(!is_batch_overlapping(
+        &store,
+        &task_id,
+        &task_config,
+        &both_buckets,
+        Some("auditor")
+    )
+    .await
+    .unwrap());
+
+    // Each collection counts as one query of the buckets in the batch. The query count of a batch
+    // is that of its most queried bucket.
+    mark_collected(&store, &task_id, &task_config, &both_buckets, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_batch_query_count(&store, &task_id, &task_config, &first_bucket, None)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        get_batch_query_count(&store, &task_id, &task_config, &both_buckets, None)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        get_batch_query_count(
+            &store,
+            &task_id,
+            &task_config,
+            &both_buckets,
+            Some("auditor")
+        )
+        .await
+        .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn aggregate_store_compaction() {
+    let task_id = TaskId(thread_rng().gen());
+    let task_config = task_config();
+    let store = InMemoryAggregateStore::default();
+
+    // One report in each of the first ten buckets.
+    put_out_shares(
+        &store,
+        &task_id,
+        &task_config,
+        &PartialBatchSelector::TimeInterval,
+        (0..10).map(|i| out_share(i * 3600)).collect(),
+    )
+    .await
+    .unwrap();
+
+    // Spans are four buckets long and only those that end by the eighth bucket are compacted.
+    let compaction = DapAggShareCompaction {
+        span_buckets: 4,
+        not_after: 8 * 3600,
+    };
+    let batch_sel = BatchSelector::TimeInterval {
+        batch_interval: Interval {
+            start: 3600,
+            duration: 9 * 3600,
+        },
+    };
+    let expected_report_count = get_agg_share(&store, &task_id, &task_config, &batch_sel)
+        .await
+        .unwrap()
+        .report_count;
+    assert_eq!(expected_report_count, 9);
+
+    // The batch covers the span starting at the fourth bucket. The span is compacted the first
+    // time it is read.
+    *store.bucket_reads.lock().unwrap() = 0;
+    let agg_share =
+        get_compacted_agg_share(&store, &task_id, &task_config, &batch_sel, &compaction)
+            .await
+            .unwrap();
+    assert_eq!(agg_share.report_count, expected_report_count);
+    assert_eq!(*store.bucket_reads.lock().unwrap(), 9);
+    assert_eq!(
+        store
+            .spans
+            .lock()
+            .unwrap()
+            .get(&DapAggregateSpan {
+                start: 4 * 3600,
+                duration: 4 * 3600,
+            })
+            .map(|agg_share| agg_share.report_count),
+        Some(4)
+    );
+
+    // Once compacted, the buckets of the span are no longer read.
+    *store.bucket_reads.lock().unwrap() = 0;
+    let agg_share =
+        get_compacted_agg_share(&store, &task_id, &task_config, &batch_sel, &compaction)
+            .await
+            .unwrap();
+    assert_eq!(agg_share.report_count, expected_report_count);
+    assert_eq!(*store.bucket_reads.lock().unwrap(), 5);
+
+    // Spans that are too recent are not compacted.
+    let compaction = DapAggShareCompaction {
+        span_buckets: 4,
+        not_after: 7 * 3600,
+    };
+    store.spans.lock().unwrap().clear();
+    let agg_share =
+        get_compacted_agg_share(&store, &task_id, &task_config, &batch_sel, &compaction)
+            .await
+            .unwrap();
+    assert_eq!(agg_share.report_count, expected_report_count);
+    assert!(store.spans.lock().unwrap().is_empty());
+}
@@ -3,19 +3,21 @@
 
 use crate::{
     messages::{
+        decode_base64url_vec, encode_base64url,
         taskprov::{QueryConfigVar, TaskConfig, VdafType, VdafTypeVar},
         Extension, HpkeConfig, ReportMetadata, TaskId,
     },
     vdaf::VdafVerifyKey,
-    DapAbort, DapError, DapQueryConfig, DapTaskConfig, DapVersion, Prio3Config, VdafConfig,
+    DapAbort, DapDpConfig, DapError, DapQueryConfig, DapTaskConfig, DapVersion, Prio3Config,
+    Prio3FixedPointBitSize, VdafConfig,
 };
-use prio::codec::ParameterizedDecode;
+use prio::codec::{ParameterizedDecode, ParameterizedEncode};
 use ring::{
     digest,
     hkdf::{Prk, Salt, HKDF_SHA256},
 };
 use serde::{Deserialize, Serialize};
-use std::str;
+use std::{borrow::Cow, str};
 use url::Url;
 
 /// DAP taskprov version.
@@ -24,11 +26,16 @@ pub enum TaskprovVersion {
     #[serde(rename = "v02")]
     Draft02,
 
+    /// In draft04, the task configuration is carried in the "dap-taskprov" HTTP header of each
+    /// request rather than in the report extension, whose payload is empty.
+    #[serde(rename = "v04")]
+    Draft04,
+
     #[serde(other)]
     Unknown,
 }
 
-/// SHA-256 of "dap-taskprov"
+/// SHA-256 of "dap-taskprov". This is also used for draft04.
 #[allow(dead_code)]
 pub(crate) const TASK_PROV_SALT_DRAFT02: [u8; 32] = [
     0x28, 0xb9, 0xbb, 0x4f, 0x62, 0x4f, 0x67, 0x9a, 0xc1, 0x98, 0xd9, 0x68, 0xf4, 0xb0, 0x9e, 0xec,
@@ -46,7 +53,10 @@ fn compute_task_id_draft02(serialized: &[u8]) -> TaskId {
 /// Compute the task id of a serialized task config.
 pub fn compute_task_id(version: TaskprovVersion, serialized: &[u8]) -> Result<TaskId, DapError> {
     match version {
-        TaskprovVersion::Draft02 => Ok(compute_task_id_draft02(serialized)),
+        // The task ID is derived the same way in draft04.
+        TaskprovVersion::Draft02 | TaskprovVersion::Draft04 => {
+            Ok(compute_task_id_draft02(serialized))
+        }
         TaskprovVersion::Unknown => Err(DapError::fatal(
             "attempted to resolve taskprov task with unknown version",
         )),
@@ -65,7 +75,7 @@ pub(crate) fn extract_prk_from_verify_key_init(
     // The documentation says computing the Salt is expensive, and we use the same PRK all the
     // time, so we compute it once.
     let value = match version {
-        TaskprovVersion::Draft02 => &TASK_PROV_SALT_DRAFT02,
+        TaskprovVersion::Draft02 | TaskprovVersion::Draft04 => &TASK_PROV_SALT_DRAFT02,
         _ => panic!("unimplemented taskprov version"),
     };
     Salt::new(HKDF_SHA256, value).extract(verify_key_init)
@@ -82,7 +92,10 @@ pub(crate) fn expand_prk_into_verify_key(
     // and it won't be, so we unwrap().
     let okm = prk.expand(&info, vdaf_type).unwrap();
     match &vdaf_type {
-        VdafType::Prio3Aes128Count | VdafType::Prio3Aes128Sum | VdafType::Prio3Aes128Histogram => {
+        VdafType::Prio3Aes128Count
+        | VdafType::Prio3Aes128Sum
+        | VdafType::Prio3Aes128Histogram
+        | VdafType::Prio3FixedPointBoundedL2VecSum => {
             let mut bytes = [0u8; 16];
             okm.fill(&mut bytes[..]).unwrap();
             VdafVerifyKey::Prio3(bytes)
@@ -122,10 +135,27 @@ fn malformed_task_config(task_id: &TaskId, detail: String) -> DapError {
     })
 }
 
-/// Check for a taskprov extension in the report, and return it if found.
+/// Determine the taskprov version of a request. A request that advertises a task configuration in
+/// the "dap-taskprov" header uses the draft04 wire format; otherwise the configured version
+/// applies. This allows an Aggregator configured for draft02 to accept draft04 tasks as well.
+pub fn resolve_taskprov_version(
+    configured: TaskprovVersion,
+    taskprov_advertisement: Option<&str>,
+) -> TaskprovVersion {
+    if taskprov_advertisement.is_some() {
+        TaskprovVersion::Draft04
+    } else {
+        configured
+    }
+}
+
+/// Check for a taskprov extension in the report, and return the task configuration if found. In
+/// draft02, the task configuration is the payload of the extension; in draft04, it is advertised
+/// in the "dap-taskprov" header of the request (`taskprov_advertisement`) as URL-safe base64.
 pub fn get_taskprov_task_config(
     version: TaskprovVersion,
     task_id: &TaskId,
+    taskprov_advertisement: Option<&str>,
     metadata: &ReportMetadata,
 ) -> Result<Option<TaskConfig>, DapError> {
     let taskprovs: Vec<&Extension> = metadata
@@ -133,26 +163,47 @@ pub fn get_taskprov_task_config(
         .iter()
         .filter(|x| matches!(x, Extension::Taskprov { .. }))
         .collect();
-    match taskprovs.len() {
-        0 => Ok(None),
+    let payload = match taskprovs.len() {
+        0 => return Ok(None),
         1 => match &taskprovs[0] {
-            Extension::Taskprov { payload } => {
-                if compute_task_id(version, &payload[..])? != *task_id {
-                    // Return unrecognizedTask following section 5.1 of the taskprov draft.
-                    return Err(DapError::Abort(DapAbort::UnrecognizedTask));
-                }
-                // Return unrecognizedMessage if parsing fails following section 5.1 of the taskprov draft.
-                let task_config = TaskConfig::get_decoded_with_param(&version, payload)
-                    .map_err(|_| DapError::Abort(DapAbort::UnrecognizedMessage))?;
-                Ok(Some(task_config))
-            }
+            Extension::Taskprov { payload } => payload,
             _ => panic!("cannot happen"),
         },
         _ => {
             // The decoder already returns an error if an extension of a give type occurs more than once.
             panic!("should not happen")
         }
+    };
+
+    let encoded_task_config = match version {
+        TaskprovVersion::Draft02 => Cow::Borrowed(payload.as_slice()),
+        TaskprovVersion::Draft04 => {
+            // The extension only signals that the task is configured via taskprov.
+            if !payload.is_empty() {
+                return Err(DapError::Abort(DapAbort::UnrecognizedMessage));
+            }
+            let advertisement =
+                taskprov_advertisement.ok_or(DapError::Abort(DapAbort::UnrecognizedMessage))?;
+            Cow::Owned(
+                decode_base64url_vec(advertisement)
+                    .ok_or(DapError::Abort(DapAbort::UnrecognizedMessage))?,
+            )
+        }
+        TaskprovVersion::Unknown => {
+            return Err(DapError::fatal(
+                "attempted to resolve taskprov task with unknown version",
+            ))
+        }
+    };
+
+    if compute_task_id(version, &encoded_task_config)? != *task_id {
+        // Return unrecognizedTask following section 5.1 of the taskprov draft.
+        return Err(DapError::Abort(DapAbort::UnrecognizedTask));
     }
+    // Return unrecognizedMessage if parsing fails following section 5.1 of the taskprov draft.
+    let task_config = TaskConfig::get_decoded_with_param(&version, &encoded_task_config)
+        .map_err(|_| DapError::Abort(DapAbort::UnrecognizedMessage))?;
+    Ok(Some(task_config))
 }
 
 fn url_from_bytes(task_id: &TaskId, url_bytes: &[u8]) -> Result<Url, DapError> {
@@ -175,6 +226,7 @@ impl From<QueryConfigVar> for DapQueryConfig {
         match var {
             QueryConfigVar::FixedSize { max_batch_size } => DapQueryConfig::FixedSize {
                 max_batch_size: max_batch_size.into(),
+                batch_lifetime: None,
             },
             QueryConfigVar::TimeInterval => DapQueryConfig::TimeInterval,
         }
@@ -191,6 +243,16 @@ impl From<VdafTypeVar> for VdafConfig {
             VdafTypeVar::Prio3Aes128Sum { bit_length } => VdafConfig::Prio3(Prio3Config::Sum {
                 bits: bit_length.into(),
             }),
+            VdafTypeVar::Prio3FixedPointBoundedL2VecSum { bitsize, length } => {
+                VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+                    bitsize: match bitsize {
+                        16 => Prio3FixedPointBitSize::Fixed16,
+                        32 => Prio3FixedPointBitSize::Fixed32,
+                        _ => unreachable!("unsupported fixed-point bit size"),
+                    },
+                    len: length as usize,
+                })
+            }
             VdafTypeVar::Poplar1Aes128 { .. } | VdafTypeVar::NotImplemented(..) => {
                 unreachable!("VDAF not implemented")
             }
@@ -217,6 +279,14 @@ impl DapTaskConfig {
             ));
         }
         let vdaf_type = VdafType::from(task_config.vdaf_config.var.clone());
+        let taskprov_advertisement = (taskprov_version == TaskprovVersion::Draft04)
+            .then(|| encode_base64url(task_config.get_encoded_with_param(&taskprov_version)));
+        let vdaf = VdafConfig::from(task_config.vdaf_config.var);
+        vdaf.check_params()
+            .map_err(|e| malformed_task_config(task_id, e.to_string()))?;
+        let dp = DapDpConfig::from(task_config.vdaf_config.dp_config);
+        dp.validate()
+            .map_err(|detail| malformed_task_config(task_id, detail))?;
         Ok(DapTaskConfig {
             version: dap_version,
             leader_url: url_from_bytes(task_id, &task_config.aggregator_endpoints[0].bytes)?,
@@ -224,8 +294,9 @@ impl DapTaskConfig {
             time_precision: task_config.query_config.time_precision,
             expiration: task_config.task_expiration,
             min_batch_size: task_config.query_config.min_batch_size.into(),
+            max_batch_query_count: task_config.query_config.max_batch_query_count.into(),
             query: DapQueryConfig::from(task_config.query_config.var),
-            vdaf: VdafConfig::from(task_config.vdaf_config.var),
+            vdaf,
             vdaf_verify_key: compute_vdaf_verify_key(
                 taskprov_version,
                 vdaf_verify_key_init,
@@ -233,12 +304,20 @@ impl DapTaskConfig {
                 vdaf_type,
             ),
             collector_hpke_config: collector_hpke_config.clone(),
+            additional_collectors: Vec::new(),
+            dp,
+            taskprov_advertisement,
+            replay_filter: None,
+            vdaf_verify_key_rotation: None,
+            upload_auth: None,
         })
     }
 }
 
 impl ReportMetadata {
-    /// Does this metatdata have a taskprov extension and does it match the specified id?
+    /// Does this metatdata have a taskprov extension and does it match the specified id? In
+    /// draft04, the extension has an empty payload, so the task ID cannot be checked until the
+    /// task configuration advertised by the request is resolved.
     pub fn is_taskprov(&self, version: TaskprovVersion, task_id: &TaskId) -> bool {
         // Don't check for taskprov usage if we don't know the version.
         if matches!(version, TaskprovVersion::Unknown) {
@@ -246,6 +325,9 @@ impl ReportMetadata {
         }
 
         return self.extensions.iter().any(|x| match x {
+            Extension::Taskprov { payload } if version == TaskprovVersion::Draft04 => {
+                payload.is_empty()
+            }
             Extension::Taskprov { payload } => {
                 *task_id == compute_task_id(version, payload).unwrap()
             }
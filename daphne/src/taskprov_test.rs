@@ -2,11 +2,20 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{
-    messages::taskprov::VdafType,
-    messages::TaskId,
-    taskprov::{compute_vdaf_verify_key, TaskprovVersion},
+    messages::taskprov::{
+        DpConfig, QueryConfig, QueryConfigVar, TaskConfig, UrlBytes, VdafConfig, VdafType,
+        VdafTypeVar,
+    },
+    messages::{encode_base64url, Extension, ReportId, ReportMetadata, TaskId},
+    taskprov::{
+        compute_task_id, compute_vdaf_verify_key, get_taskprov_task_config,
+        resolve_taskprov_version, TaskprovVersion,
+    },
     vdaf::VdafVerifyKey,
+    DapAbort, DapError,
 };
+use assert_matches::assert_matches;
+use prio::codec::ParameterizedEncode;
 
 #[test]
 fn check_vdaf_key_computation() {
@@ -35,3 +44,118 @@ fn check_vdaf_key_computation() {
         _ => unreachable!(),
     }
 }
+
+fn task_config() -> TaskConfig {
+    TaskConfig {
+        task_info: "cool task".as_bytes().to_vec(),
+        aggregator_endpoints: vec![
+            UrlBytes {
+                bytes: b"https://leader.com/".to_vec(),
+            },
+            UrlBytes {
+                bytes: b"https://helper.com/".to_vec(),
+            },
+        ],
+        query_config: QueryConfig {
+            time_precision: 3600,
+            max_batch_query_count: 1,
+            min_batch_size: 1,
+            var: QueryConfigVar::TimeInterval,
+        },
+        task_expiration: 1337,
+        vdaf_config: VdafConfig {
+            dp_config: DpConfig::None,
+            var: VdafTypeVar::Prio3Aes128Count,
+        },
+    }
+}
+
+fn report_metadata(payload: Vec<u8>) -> ReportMetadata {
+    ReportMetadata {
+        id: ReportId([1; 16]),
+        time: 1337,
+        extensions: vec![Extension::Taskprov { payload }],
+    }
+}
+
+#[test]
+fn resolve_version_from_advertisement() {
+    assert_eq!(
+        resolve_taskprov_version(TaskprovVersion::Draft02, None),
+        TaskprovVersion::Draft02
+    );
+    assert_eq!(
+        resolve_taskprov_version(TaskprovVersion::Draft02, Some("")),
+        TaskprovVersion::Draft04
+    );
+}
+
+#[test]
+fn get_task_config_draft04() {
+    let version = TaskprovVersion::Draft04;
+    let task_config = task_config();
+    let encoded = task_config.get_encoded_with_param(&version);
+    let task_id = compute_task_id(version, &encoded).unwrap();
+    let advertisement = encode_base64url(&encoded);
+
+    // The task config is read from the advertisement.
+    assert_eq!(
+        get_taskprov_task_config(
+            version,
+            &task_id,
+            Some(&advertisement),
+            &report_metadata(Vec::new())
+        )
+        .unwrap(),
+        Some(task_config)
+    );
+
+    // The report is not configured via taskprov.
+    let metadata = ReportMetadata {
+        extensions: Vec::new(),
+        ..report_metadata(Vec::new())
+    };
+    assert_matches!(
+        get_taskprov_task_config(version, &task_id, Some(&advertisement), &metadata),
+        Ok(None)
+    );
+
+    // The advertisement is missing.
+    assert_matches!(
+        get_taskprov_task_config(version, &task_id, None, &report_metadata(Vec::new())),
+        Err(DapError::Abort(DapAbort::UnrecognizedMessage))
+    );
+
+    // The advertisement is not valid base64url.
+    assert_matches!(
+        get_taskprov_task_config(
+            version,
+            &task_id,
+            Some("not base64url!"),
+            &report_metadata(Vec::new())
+        ),
+        Err(DapError::Abort(DapAbort::UnrecognizedMessage))
+    );
+
+    // The extension payload must be empty.
+    assert_matches!(
+        get_taskprov_task_config(
+            version,
+            &task_id,
+            Some(&advertisement),
+            &report_metadata(encoded)
+        ),
+        Err(DapError::Abort(DapAbort::UnrecognizedMessage))
+    );
+
+    // The advertised task config does not match the task ID.
+    assert_matches!(
+        get_taskprov_task_config(
+            version,
+            &TaskId([0; 32]),
+            Some(&advertisement),
+            &report_metadata(Vec::new())
+        ),
+        Err(DapError::Abort(DapAbort::UnrecognizedTask))
+    );
+}
@@ -4,8 +4,11 @@
 //! Mock backend functionality to test DAP protocol.
 
 use crate::{
-    auth::{BearerToken, BearerTokenProvider},
+    auth::{BearerToken, BearerTokenProvider, DapSenderAuth},
+    clock::{Clock, MockClock},
     constants::DapMediaType,
+    events::{DapBatchEvent, DapEventSink},
+    extensions::DapExtensionRegistry,
     hpke::{HpkeDecrypter, HpkeReceiverConfig},
     messages::{
         AggregationJobId, BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq,
@@ -13,22 +16,26 @@ use crate::{
         ReportId, ReportMetadata, TaskId, Time, TransitionFailure,
     },
     metrics::DaphneMetrics,
-    roles::{DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
-    taskprov, DapAbort, DapAggregateShare, DapBatchBucket, DapCollectJob, DapError,
-    DapGlobalConfig, DapHelperState, DapOutputShare, DapQueryConfig, DapRequest, DapResponse,
-    DapTaskConfig, DapVersion, MetaAggregationJobId,
+    roles::{loopback_send_http, DapAggregator, DapHelper, DapLeader},
+    taskprov, DapAbort, DapAggregateShare, DapBatchBucket, DapBucketReportCount, DapCollectJob,
+    DapError, DapGlobalConfig, DapHelperState, DapOutputShare, DapPendingCollectJob,
+    DapQueryConfig, DapReportCountBreakdown, DapReportSample, DapRequest, DapResponse,
+    DapStaleBatchPolicy, DapTaskConfig, DapUploadRecord, DapVersion, MetaAggregationJobId,
 };
 use assert_matches::assert_matches;
 use async_trait::async_trait;
-use rand::{thread_rng, Rng};
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::{Borrow, Cow},
     collections::{HashMap, HashSet, VecDeque},
     hash::Hash,
     ops::DerefMut,
-    sync::{Arc, Mutex},
-    time::SystemTime,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 use url::Url;
 
@@ -84,26 +91,164 @@ impl<'a> DapBatchBucket<'a> {
 
 pub(crate) struct MockAggregatorReportSelector(pub(crate) TaskId);
 
+/// An operation of [`MockAggregator`] into which faults may be injected.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MockOperation {
+    /// Leader: Send a request to the Helper.
+    SendHttp,
+    /// Leader: Send an AggregationJobContinueReq to the Helper. The faults of
+    /// [`MockOperation::SendHttp`] apply as well.
+    SendAggJobContinueReq,
+    /// Leader: Store a report uploaded by a Client.
+    PutReport,
+    /// Store the output shares of an aggregation job.
+    PutOutShares,
+    /// Get the aggregate share of a batch.
+    GetAggShare,
+    /// Helper: Store the Helper's state for an aggregation job.
+    PutHelperState,
+    /// Leader: Record the outcome of an upload request handled under an idempotency key.
+    PutUploadRecord,
+}
+
+/// Faults injected into an operation.
+#[derive(Clone, Debug, Default)]
+pub struct MockOperationFaults {
+    /// Time (in milliseconds) the operation takes.
+    pub latency_millis: u64,
+
+    /// Maximum amount of time (in milliseconds) added to the latency. The amount is chosen
+    /// uniformly at random each time the operation runs.
+    pub jitter_millis: u64,
+
+    /// Probability that the operation fails with [`DapAbort::RetryLater`].
+    pub failure_rate: f64,
+
+    /// If set, the operation fails at most this many times.
+    pub max_failures: Option<u64>,
+
+    /// Number of times the operation succeeds before it may fail.
+    pub min_successes: u64,
+}
+
+/// Faults injected into the operations of a [`MockAggregator`]. Randomness is derived from a seed,
+/// so a test sees the same sequence of faults each time it runs. Latency is simulated by advancing
+/// the aggregator's clock rather than by sleeping.
+pub struct MockFaults {
+    operations: HashMap<MockOperation, MockOperationFaults>,
+    failures: HashMap<MockOperation, u64>,
+    runs: HashMap<MockOperation, u64>,
+    rng: StdRng,
+}
+
+impl MockFaults {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            operations: HashMap::new(),
+            failures: HashMap::new(),
+            runs: HashMap::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn with_operation(mut self, operation: MockOperation, faults: MockOperationFaults) -> Self {
+        self.operations.insert(operation, faults);
+        self
+    }
+
+    /// Sample the faults for one run of the given operation. Returns the time (in milliseconds)
+    /// the operation takes and whether it fails.
+    pub fn sample(&mut self, operation: MockOperation) -> (u64, bool) {
+        match self.operations.get(&operation) {
+            Some(faults) => {
+                let latency_millis =
+                    faults.latency_millis + self.rng.gen_range(0..=faults.jitter_millis);
+                let runs = self.runs.entry(operation).or_default();
+                *runs += 1;
+                let failures = self.failures.entry(operation).or_default();
+                let fail = self.rng.gen_bool(faults.failure_rate)
+                    && *runs > faults.min_successes
+                    && !matches!(faults.max_failures, Some(max_failures) if *failures >= max_failures);
+                if fail {
+                    *failures += 1;
+                }
+                (latency_millis, fail)
+            }
+            None => (0, false),
+        }
+    }
+}
+
 pub(crate) struct MockAggregator {
     pub(crate) global_config: DapGlobalConfig,
     pub(crate) tasks: Arc<Mutex<HashMap<TaskId, DapTaskConfig>>>,
     pub(crate) hpke_receiver_config_list: Vec<HpkeReceiverConfig>,
     pub(crate) leader_token: BearerToken,
     pub(crate) collector_token: Option<BearerToken>, // Not set by Helper
+    pub(crate) additional_collector_tokens: HashMap<String, BearerToken>, // Not set by Helper
     pub(crate) report_store: Arc<Mutex<HashMap<TaskId, ReportStore>>>,
     pub(crate) leader_state_store: Arc<Mutex<HashMap<TaskId, LeaderState>>>,
-    pub(crate) helper_state_store: Arc<Mutex<HashMap<HelperStateInfo, DapHelperState>>>,
+    pub(crate) helper_state_store: Arc<Mutex<HashMap<HelperStateInfo, HelperStateEntry>>>,
     pub(crate) agg_store: Arc<Mutex<HashMap<TaskId, HashMap<DapBatchBucketOwned, AggStore>>>>,
     pub(crate) collector_hpke_config: HpkeConfig,
     pub(crate) taskprov_vdaf_verify_key_init: [u8; 32],
     pub(crate) metrics: DaphneMetrics,
+    pub(crate) extension_registry: DapExtensionRegistry,
 
     // Leader: Reference to peer. Used to simulate HTTP requests from Leader to Helper, i.e.,
     // implement `DapLeader::send_http_post()` for `MockAggregator`. Not set by the Helper.
     pub(crate) peer: Option<Arc<MockAggregator>>,
+
+    // Helper: If set, then the Helper's state is dropped instead of stored. Used to simulate the
+    // Helper losing its state during an aggregation job.
+    pub(crate) drop_helper_state: AtomicBool,
+
+    // Helper: Aggregation jobs holding one of the slots for running aggregation jobs.
+    pub(crate) running_agg_jobs: Mutex<HashSet<HelperStateInfo>>,
+
+    // If set, then faults are injected into the operations of this aggregator. Used to test how
+    // the Leader copes with a slow or unreliable peer or storage.
+    pub(crate) faults: Mutex<Option<MockFaults>>,
+
+    // Source of the current time. This is advanced by injected latency.
+    pub(crate) clock: MockClock,
+
+    // Leader: Collect jobs for which `DapLeader::on_collect_job_finished()` was called, in order.
+    pub(crate) finished_collect_jobs: Mutex<Vec<CollectionJobId>>,
+
+    // Batch lifecycle events emitted by this aggregator, in order.
+    pub(crate) batch_events: Mutex<Vec<DapBatchEvent>>,
+
+    // Leader: Time until which the aggregation jobs for each Helper are deferred.
+    pub(crate) helper_deferrals: Mutex<HashMap<Url, Time>>,
+
+    // Leader: Number of consecutive aggregation jobs each Helper rejected as unauthorized.
+    pub(crate) helper_auth_failures: Mutex<HashMap<Url, u32>>,
+
+    // Aggregate share produced for each Collector's query of a batch, keyed by task, then by batch
+    // and Collector (`None` for the task's primary Collector).
+    #[allow(clippy::type_complexity)]
+    pub(crate) collected_agg_shares:
+        Mutex<HashMap<TaskId, HashMap<(BatchSelector, Option<String>), DapAggregateShare>>>,
 }
 
 impl MockAggregator {
+    /// Inject the configured faults into the given operation: Advance the clock by the latency of
+    /// the operation, then fail with the configured probability.
+    fn inject_faults(&self, operation: MockOperation) -> Result<(), DapError> {
+        let (delay_millis, fail) = match self.faults.lock().expect("faults: lock failed").as_mut() {
+            Some(faults) => faults.sample(operation),
+            None => return Ok(()),
+        };
+        self.clock.advance(Duration::from_millis(delay_millis));
+        if fail {
+            return Err(DapError::Abort(DapAbort::RetryLater {
+                detail: format!("simulated failure of {operation:?}"),
+            }));
+        }
+        Ok(())
+    }
+
     /// Conducts checks on a received report to see whether:
     /// 1) the report falls into a batch that has been already collected, or
     /// 2) the report has been submitted by the client in the past.
@@ -127,13 +272,77 @@ impl MockAggregator {
             .lock()
             .expect("report_store: failed to lock");
         let report_store = guard.entry(task_id.clone()).or_default();
-        if report_store.processed.contains(&metadata.id) {
+        if report_store.processed.contains_key(&metadata.id) {
             return Some(TransitionFailure::ReportReplayed);
         }
 
         None
     }
 
+    /// Implementation of [`DapAggregator::check_early_reject`]. If `mark` is not set, then no
+    /// state is changed.
+    async fn early_reject<'b>(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &'b PartialBatchSelector,
+        report_meta: impl Iterator<Item = &'b ReportMetadata>,
+        mark: bool,
+    ) -> Result<HashMap<ReportId, TransitionFailure>, DapError> {
+        let task_config = self
+            .get_task_config_for(Cow::Borrowed(task_id))
+            .await
+            .unwrap()
+            .expect("tasks: unrecognized task");
+        let span = task_config.batch_span_for_meta(part_batch_sel, report_meta)?;
+        let now = self.get_current_time();
+        let global_config = self.get_global_config();
+
+        // Purge the IDs of reports whose replay window has ended.
+        if mark {
+            self.report_store
+                .lock()
+                .expect("report_store: failed to lock")
+                .entry(task_id.clone())
+                .or_default()
+                .processed
+                .retain(|_id, time| global_config.is_replay_window_open(&task_config, *time, now));
+        }
+
+        let mut early_fails = HashMap::new();
+        for (bucket, report_meta) in span.iter() {
+            for metadata in report_meta.iter() {
+                // Check whether Report has been collected or replayed.
+                if let Some(transition_failure) = self
+                    .check_report_early_fail(task_id, &bucket.to_owned_bucket(), metadata)
+                    .await
+                {
+                    early_fails.insert(metadata.id.clone(), transition_failure);
+                } else if metadata.time > global_config.greatest_valid_report_time(now) {
+                    early_fails.insert(metadata.id.clone(), TransitionFailure::ReportTooEarly);
+                    continue;
+                } else if !global_config.is_replay_window_open(&task_config, metadata.time, now) {
+                    early_fails.insert(metadata.id.clone(), TransitionFailure::ReportDropped);
+                    continue;
+                }
+
+                // Mark report processed.
+                if !mark {
+                    continue;
+                }
+                let mut guard = self
+                    .report_store
+                    .lock()
+                    .expect("report_store: failed to lock");
+                let report_store = guard.entry(task_id.clone()).or_default();
+                report_store
+                    .processed
+                    .insert(metadata.id.clone(), metadata.time);
+            }
+        }
+
+        Ok(early_fails)
+    }
+
     fn get_hpke_receiver_config_for(&self, hpke_config_id: u8) -> Option<&HpkeReceiverConfig> {
         self.hpke_receiver_config_list
             .iter()
@@ -158,15 +367,42 @@ impl MockAggregator {
         match task_config.query {
             // For fixed-size queries, the bucket corresponds to a single batch.
             DapQueryConfig::FixedSize { .. } => {
+                let now = self.get_current_time();
                 let mut guard = self
                     .leader_state_store
                     .lock()
                     .expect("leader_state_store: failed to lock");
                 let leader_state_store = guard.entry(task_id.clone()).or_default();
 
-                // Assign the report to the first unsaturated batch.
-                for (batch_id, report_count) in leader_state_store.batch_queue.iter_mut() {
-                    if *report_count < task_config.min_batch_size {
+                // Close stale batches. Under the "close" policy, stale batches that have not been
+                // filled are removed from the queue.
+                let batch_lifetime = task_config.query.batch_lifetime();
+                let is_stale = |created_at: Time| {
+                    batch_lifetime
+                        .is_some_and(|batch_lifetime| batch_lifetime.is_stale(created_at, now))
+                };
+                if batch_lifetime.is_some_and(|batch_lifetime| {
+                    batch_lifetime.policy == DapStaleBatchPolicy::Close
+                }) {
+                    leader_state_store.batch_queue.retain(
+                        |(_batch_id, report_count, created_at)| {
+                            *report_count >= task_config.min_batch_size || !is_stale(*created_at)
+                        },
+                    );
+                }
+
+                // Assign the report to the first unsaturated batch that is neither stale nor
+                // closed.
+                let LeaderState {
+                    batch_queue,
+                    closed_batches,
+                    ..
+                } = leader_state_store;
+                for (batch_id, report_count, created_at) in batch_queue.iter_mut() {
+                    if *report_count < task_config.min_batch_size
+                        && !is_stale(*created_at)
+                        && !closed_batches.contains(batch_id)
+                    {
                         *report_count += 1;
                         return Some(DapBatchBucketOwned::FixedSize {
                             batch_id: batch_id.clone(),
@@ -176,9 +412,7 @@ impl MockAggregator {
 
                 // No unsaturated batch exists, so create a new batch.
                 let batch_id = BatchId(rng.gen());
-                leader_state_store
-                    .batch_queue
-                    .push_back((batch_id.clone(), 1));
+                batch_queue.push_back((batch_id.clone(), 1, now));
                 Some(DapBatchBucketOwned::FixedSize { batch_id })
             }
 
@@ -204,15 +438,30 @@ impl MockAggregator {
             .leader_state_store
             .lock()
             .expect("leader_state_store: failed to lock");
-        let leader_state_store = guard
-            .get(task_id)
-            .expect("leader_state_store: unrecognized task");
+        let leader_state_store = guard.get(task_id)?;
 
         leader_state_store
             .batch_queue
             .front()
             .cloned() // TODO(cjpatton) Avoid clone by returning MutexGuard
-            .map(|(batch_id, _report_count)| batch_id)
+            .map(|(batch_id, _report_count, _created_at)| batch_id)
+    }
+
+    /// Return the breakdown of the report count of a completed collect job, if any.
+    #[cfg(test)]
+    pub fn get_collection_report_counts(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> Option<DapReportCountBreakdown> {
+        let guard = self
+            .leader_state_store
+            .lock()
+            .expect("leader_state_store: failed to lock");
+        match guard.get(task_id)?.collect_jobs.get(collect_id)? {
+            CollectJobState::Processed(_collection, report_counts) => Some(report_counts.clone()),
+            CollectJobState::Pending(..) => None,
+        }
     }
 
     pub(crate) async fn unchecked_get_task_config(&self, task_id: &TaskId) -> DapTaskConfig {
@@ -247,6 +496,14 @@ impl<'a> BearerTokenProvider<'a> for MockAggregator {
         }
     }
 
+    async fn get_additional_collector_bearer_token_for(
+        &'a self,
+        _task_id: &'a TaskId,
+        collector_id: &'a str,
+    ) -> Result<Option<BearerToken>, DapError> {
+        Ok(self.additional_collector_tokens.get(collector_id).cloned())
+    }
+
     fn is_taskprov_leader_bearer_token(&self, _token: &BearerToken) -> bool {
         // MockAggregator currently uses the same token for all tasks, regardless of how the task
         // is configured. As a result, we don't expect BearerTokenProver::bearer_token_authorized()
@@ -309,7 +566,7 @@ impl<'a> HpkeDecrypter<'a> for MockAggregator {
 }
 
 #[async_trait(?Send)]
-impl DapAuthorizedSender<BearerToken> for MockAggregator {
+impl DapSenderAuth<BearerToken> for MockAggregator {
     async fn authorize(
         &self,
         task_id: &TaskId,
@@ -321,6 +578,24 @@ impl DapAuthorizedSender<BearerToken> for MockAggregator {
             .await?
             .clone())
     }
+
+    async fn unauthorized_reason(
+        &self,
+        req: &DapRequest<BearerToken>,
+    ) -> Result<Option<String>, DapError> {
+        self.bearer_token_authorized(req).await
+    }
+}
+
+#[async_trait(?Send)]
+impl DapEventSink for MockAggregator {
+    async fn emit(&self, event: &DapBatchEvent) -> Result<(), DapError> {
+        self.batch_events
+            .lock()
+            .expect("batch_events: failed to lock")
+            .push(event.clone());
+        Ok(())
+    }
 }
 
 #[async_trait(?Send)]
@@ -333,13 +608,6 @@ where
     // clones the task config as needed.
     type WrappedDapTaskConfig = DapTaskConfig;
 
-    async fn unauthorized_reason(
-        &self,
-        req: &DapRequest<BearerToken>,
-    ) -> Result<Option<String>, DapError> {
-        self.bearer_token_authorized(req).await
-    }
-
     fn get_global_config(&self) -> &DapGlobalConfig {
         &self.global_config
     }
@@ -356,9 +624,13 @@ where
         &'srv self,
         version: DapVersion,
         task_id: Cow<'req, TaskId>,
+        taskprov_advertisement: Option<&str>,
         metadata: Option<&ReportMetadata>,
     ) -> Result<Option<DapTaskConfig>, DapError> {
-        let taskprov_version = self.global_config.taskprov_version;
+        let taskprov_version = taskprov::resolve_taskprov_version(
+            self.global_config.taskprov_version,
+            taskprov_advertisement,
+        );
 
         // Before looking up the task configuration, first check if it needs to be configured from
         // the current request.
@@ -369,11 +641,12 @@ where
             if let Some(taskprov_task_config) = taskprov::get_taskprov_task_config(
                 taskprov_version,
                 task_id.as_ref(),
+                taskprov_advertisement,
                 metadata.unwrap(),
             )? {
                 let task_config = DapTaskConfig::try_from_taskprov(
                     version,
-                    self.global_config.taskprov_version,
+                    taskprov_version,
                     task_id.as_ref(),
                     taskprov_task_config,
                     &self.taskprov_vdaf_verify_key_init,
@@ -403,17 +676,15 @@ where
         Ok(tasks.get(task_id.as_ref()).cloned())
     }
 
-    fn get_current_time(&self) -> Time {
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+    fn clock(&self) -> &dyn Clock {
+        &self.clock
     }
 
     async fn is_batch_overlapping(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
     ) -> Result<bool, DapError> {
         let task_config = self
             .get_task_config_for(Cow::Borrowed(task_id))
@@ -429,7 +700,10 @@ where
 
         for bucket in task_config.batch_span_for_sel(batch_sel)? {
             if let Some(inner_agg_store) = agg_store.get(&bucket.to_owned_bucket()) {
-                if inner_agg_store.collected {
+                if inner_agg_store
+                    .query_counts
+                    .contains_key(&collector_id.map(str::to_string))
+                {
                     return Ok(true);
                 }
             }
@@ -438,6 +712,37 @@ where
         Ok(false)
     }
 
+    async fn get_batch_query_count(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+    ) -> Result<u64, DapError> {
+        let task_config = self.unchecked_get_task_config(task_id).await;
+        let guard = self.agg_store.lock().expect("agg_store: failed to lock");
+        let agg_store = if let Some(agg_store) = guard.get(task_id) {
+            agg_store
+        } else {
+            return Ok(0);
+        };
+
+        let collector_id = collector_id.map(str::to_string);
+        let mut query_count = 0;
+        for bucket in task_config.batch_span_for_sel(batch_sel)? {
+            if let Some(inner_agg_store) = agg_store.get(&bucket.to_owned_bucket()) {
+                query_count = query_count.max(
+                    inner_agg_store
+                        .query_counts
+                        .get(&collector_id)
+                        .copied()
+                        .unwrap_or_default(),
+                );
+            }
+        }
+
+        Ok(query_count)
+    }
+
     async fn batch_exists(&self, task_id: &TaskId, batch_id: &BatchId) -> Result<bool, DapError> {
         let guard = self.agg_store.lock().expect("agg_store: failed to lock");
         if let Some(agg_store) = guard.get(task_id) {
@@ -445,18 +750,56 @@ where
                 .get(&DapBatchBucketOwned::FixedSize {
                     batch_id: batch_id.clone(),
                 })
-                .is_some())
+                .is_some_and(|inner_agg_store| !inner_agg_store.agg_share.empty()))
         } else {
             Ok(false)
         }
     }
 
+    async fn mark_batch_forced(
+        &self,
+        task_id: &TaskId,
+        batch_id: &BatchId,
+    ) -> Result<(), DapError> {
+        let mut guard = self.agg_store.lock().expect("agg_store: failed to lock");
+        guard
+            .entry(task_id.clone())
+            .or_default()
+            .entry(DapBatchBucketOwned::FixedSize {
+                batch_id: batch_id.clone(),
+            })
+            .or_default()
+            .forced = true;
+        Ok(())
+    }
+
+    async fn is_batch_forced(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<bool, DapError> {
+        let batch_id = match batch_sel {
+            BatchSelector::FixedSizeByBatchId { batch_id } => batch_id,
+            BatchSelector::TimeInterval { .. } => return Ok(false),
+        };
+        let guard = self.agg_store.lock().expect("agg_store: failed to lock");
+        Ok(guard
+            .get(task_id)
+            .and_then(|agg_store| {
+                agg_store.get(&DapBatchBucketOwned::FixedSize {
+                    batch_id: batch_id.clone(),
+                })
+            })
+            .is_some_and(|inner_agg_store| inner_agg_store.forced))
+    }
+
     async fn put_out_shares(
         &self,
         task_id: &TaskId,
         part_batch_sel: &PartialBatchSelector,
         out_shares: Vec<DapOutputShare>,
-    ) -> Result<(), DapError> {
+    ) -> Result<Vec<DapBatchEvent>, DapError> {
+        self.inject_faults(MockOperation::PutOutShares)?;
         let task_config = self
             .get_task_config_for(Cow::Borrowed(task_id))
             .await?
@@ -464,15 +807,24 @@ where
 
         let mut guard = self.agg_store.lock().expect("agg_store: failed to lock");
         let agg_store = guard.entry(task_id.clone()).or_default();
+        let mut events = Vec::new();
         for (bucket, agg_share_delta) in task_config
             .batch_span_for_out_shares(part_batch_sel, out_shares)?
             .into_iter()
         {
             let inner_agg_store = agg_store.entry(bucket.to_owned_bucket()).or_default();
+            let report_count_before = inner_agg_store.agg_share.report_count;
             inner_agg_store.agg_share.merge(agg_share_delta)?;
+            events.extend(DapBatchEvent::for_merge(
+                task_id,
+                task_config.as_ref(),
+                &bucket,
+                report_count_before,
+                inner_agg_store.agg_share.report_count,
+            ));
         }
 
-        Ok(())
+        Ok(events)
     }
 
     async fn get_agg_share(
@@ -480,6 +832,7 @@ where
         task_id: &TaskId,
         batch_sel: &BatchSelector,
     ) -> Result<DapAggregateShare, DapError> {
+        self.inject_faults(MockOperation::GetAggShare)?;
         let task_config = self
             .get_task_config_for(Cow::Borrowed(task_id))
             .await
@@ -492,7 +845,12 @@ where
         let mut agg_share = DapAggregateShare::default();
         for bucket in task_config.batch_span_for_sel(batch_sel)? {
             if let Some(inner_agg_store) = agg_store.get(&bucket.to_owned_bucket()) {
-                if inner_agg_store.collected {
+                // A batch may be collected once by each of the task's Collectors, unless the task
+                // permits querying it multiple times.
+                if inner_agg_store.collected
+                    && task_config.additional_collectors.is_empty()
+                    && task_config.max_batch_query_count == 1
+                {
                     return Err(DapError::Abort(DapAbort::batch_overlap(task_id, batch_sel)));
                 } else {
                     agg_share.merge(inner_agg_store.agg_share.clone())?;
@@ -509,40 +867,25 @@ where
         part_batch_sel: &'b PartialBatchSelector,
         report_meta: impl Iterator<Item = &'b ReportMetadata>,
     ) -> Result<HashMap<ReportId, TransitionFailure>, DapError> {
-        let task_config = self
-            .get_task_config_for(Cow::Borrowed(task_id))
+        self.early_reject(task_id, part_batch_sel, report_meta, true)
             .await
-            .unwrap()
-            .expect("tasks: unrecognized task");
-        let span = task_config.batch_span_for_meta(part_batch_sel, report_meta)?;
-        let mut early_fails = HashMap::new();
-        for (bucket, report_meta) in span.iter() {
-            for metadata in report_meta.iter() {
-                // Check whether Report has been collected or replayed.
-                if let Some(transition_failure) = self
-                    .check_report_early_fail(task_id, &bucket.to_owned_bucket(), metadata)
-                    .await
-                {
-                    early_fails.insert(metadata.id.clone(), transition_failure);
-                };
-
-                // Mark report processed.
-                let mut guard = self
-                    .report_store
-                    .lock()
-                    .expect("report_store: failed to lock");
-                let report_store = guard.entry(task_id.clone()).or_default();
-                report_store.processed.insert(metadata.id.clone());
-            }
-        }
+    }
 
-        Ok(early_fails)
+    async fn peek_early_reject<'b>(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &'b PartialBatchSelector,
+        report_meta: impl Iterator<Item = &'b ReportMetadata>,
+    ) -> Result<HashMap<ReportId, TransitionFailure>, DapError> {
+        self.early_reject(task_id, part_batch_sel, report_meta, false)
+            .await
     }
 
     async fn mark_collected(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
     ) -> Result<(), DapError> {
         let task_config = self.unchecked_get_task_config(task_id).await;
         let mut guard = self.agg_store.lock().expect("agg_store: failed to lock");
@@ -551,12 +894,50 @@ where
         for bucket in task_config.batch_span_for_sel(batch_sel)? {
             if let Some(inner_agg_store) = agg_store.get_mut(&bucket.to_owned_bucket()) {
                 inner_agg_store.collected = true;
+                *inner_agg_store
+                    .query_counts
+                    .entry(collector_id.map(str::to_string))
+                    .or_default() += 1;
             }
         }
 
         Ok(())
     }
 
+    async fn get_collected_agg_share(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+    ) -> Result<Option<DapAggregateShare>, DapError> {
+        Ok(self
+            .collected_agg_shares
+            .lock()
+            .expect("collected_agg_shares: failed to lock")
+            .get(task_id)
+            .and_then(|agg_shares| {
+                agg_shares.get(&(batch_sel.clone(), collector_id.map(str::to_string)))
+            })
+            .cloned())
+    }
+
+    async fn put_collected_agg_share(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+        agg_share: &DapAggregateShare,
+    ) -> Result<(), DapError> {
+        self.collected_agg_shares
+            .lock()
+            .expect("collected_agg_shares: failed to lock")
+            .entry(task_id.clone())
+            .or_default()
+            .entry((batch_sel.clone(), collector_id.map(str::to_string)))
+            .or_insert_with(|| agg_share.clone());
+        Ok(())
+    }
+
     async fn current_batch(&self, task_id: &TaskId) -> std::result::Result<BatchId, DapError> {
         let task_config = self.unchecked_get_task_config(task_id).await;
         if let Some(id) = self.current_batch_id(task_id, &task_config) {
@@ -571,6 +952,19 @@ where
     fn metrics(&self) -> &DaphneMetrics {
         &self.metrics
     }
+
+    fn extension_registry(&self) -> &DapExtensionRegistry {
+        &self.extension_registry
+    }
+
+    fn event_sink(&self) -> &dyn DapEventSink {
+        self
+    }
+
+    async fn sleep(&self, duration: std::time::Duration) {
+        // Advance the clock rather than sleeping.
+        self.clock.advance(duration);
+    }
 }
 
 #[async_trait(?Send)]
@@ -583,7 +977,13 @@ where
         task_id: &TaskId,
         agg_job_id: &MetaAggregationJobId,
         helper_state: &DapHelperState,
+        expiration: Option<Time>,
     ) -> Result<(), DapError> {
+        self.inject_faults(MockOperation::PutHelperState)?;
+        if self.drop_helper_state.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
         let helper_state_info = HelperStateInfo {
             task_id: task_id.clone(),
             agg_job_id_owned: agg_job_id.into(),
@@ -604,7 +1004,13 @@ where
 
         // NOTE: This code is only correct for VDAFs with exactly one round of preparation.
         // For VDAFs with more rounds, the helper state blob will need to be updated here.
-        helper_state_store.insert(helper_state_info, helper_state.clone());
+        helper_state_store.insert(
+            helper_state_info,
+            HelperStateEntry {
+                helper_state: helper_state.clone(),
+                expiration,
+            },
+        );
 
         Ok(())
     }
@@ -628,13 +1034,54 @@ where
 
         // NOTE: This code is only correct for VDAFs with exactly one round of preparation.
         // For VDAFs with more rounds, the helper state blob will need to be updated here.
-        if helper_state_store.contains_key(&helper_state_info) {
-            let helper_state = helper_state_store.remove(&helper_state_info);
+        let now = self.get_current_time();
+        Ok(helper_state_store
+            .remove(&helper_state_info)
+            .filter(|entry| entry.expiration.is_none_or(|expiration| now < expiration))
+            .map(|entry| entry.helper_state))
+    }
 
-            return Ok(helper_state);
+    async fn try_acquire_agg_job_slot(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
+        max_running: u64,
+    ) -> Result<bool, DapError> {
+        let helper_state_info = HelperStateInfo {
+            task_id: task_id.clone(),
+            agg_job_id_owned: agg_job_id.into(),
+        };
+
+        let mut running_agg_jobs = self
+            .running_agg_jobs
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+
+        if running_agg_jobs.contains(&helper_state_info) {
+            return Ok(true);
+        }
+        if u64::try_from(running_agg_jobs.len()).unwrap() >= max_running {
+            return Ok(false);
         }
+        running_agg_jobs.insert(helper_state_info);
+        Ok(true)
+    }
 
-        Ok(None)
+    async fn release_agg_job_slot(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
+    ) -> Result<(), DapError> {
+        let helper_state_info = HelperStateInfo {
+            task_id: task_id.clone(),
+            agg_job_id_owned: agg_job_id.into(),
+        };
+
+        self.running_agg_jobs
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?
+            .remove(&helper_state_info);
+        Ok(())
     }
 }
 
@@ -646,6 +1093,7 @@ where
     type ReportSelector = MockAggregatorReportSelector;
 
     async fn put_report(&self, report: &Report, task_id: &TaskId) -> Result<(), DapError> {
+        self.inject_faults(MockOperation::PutReport)?;
         let bucket = self
             .assign_report_to_bucket(report, task_id)
             .await
@@ -664,13 +1112,20 @@ where
             .report_store
             .lock()
             .expect("report_store: failed to lock");
-        let queue = guard
+        let pending = &mut guard
             .get_mut(task_id)
             .expect("report_store: unrecognized task")
-            .pending
-            .entry(bucket)
-            .or_default();
-        queue.push_back(report.clone());
+            .pending;
+
+        // Like Daphne-Worker, reject a report with the same ID as a pending report.
+        if pending
+            .values()
+            .flatten()
+            .any(|pending_report| pending_report.report_metadata.id == report.report_metadata.id)
+        {
+            return Err(DapError::Transition(TransitionFailure::ReportReplayed));
+        }
+        pending.entry(bucket).or_default().push_back(report.clone());
         Ok(())
     }
 
@@ -724,12 +1179,155 @@ where
         }
     }
 
+    async fn requeue_reports(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        reports: Vec<Report>,
+    ) -> Result<(), DapError> {
+        let task_config = self.unchecked_get_task_config(task_id).await;
+        let mut guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let report_store = guard.entry(task_id.clone()).or_default();
+        for report in reports {
+            let bucket = match part_batch_sel {
+                PartialBatchSelector::FixedSizeByBatchId { batch_id } => {
+                    DapBatchBucketOwned::FixedSize {
+                        batch_id: batch_id.clone(),
+                    }
+                }
+                PartialBatchSelector::TimeInterval => DapBatchBucketOwned::TimeInterval {
+                    batch_window: task_config
+                        .quantized_time_lower_bound(report.report_metadata.time),
+                },
+            };
+            report_store.processed.remove(&report.report_metadata.id);
+            report_store
+                .pending
+                .entry(bucket)
+                .or_default()
+                .push_back(report);
+        }
+        Ok(())
+    }
+
+    async fn count_abandoned_reports(
+        &self,
+        task_id: &TaskId,
+        reports: &[Report],
+    ) -> Result<Vec<u32>, DapError> {
+        let mut guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let report_store = guard.entry(task_id.clone()).or_default();
+        Ok(reports
+            .iter()
+            .map(|report| {
+                let attempts = report_store
+                    .abandoned
+                    .entry(report.report_metadata.id.clone())
+                    .or_default();
+                *attempts += 1;
+                *attempts
+            })
+            .collect())
+    }
+
+    async fn put_report_sample(
+        &self,
+        task_id: &TaskId,
+        sample: &DapReportSample,
+    ) -> Result<(), DapError> {
+        self.report_store
+            .lock()
+            .expect("report_store: failed to lock")
+            .entry(task_id.clone())
+            .or_default()
+            .samples
+            .push(sample.clone());
+        Ok(())
+    }
+
+    async fn get_upload_record(
+        &self,
+        task_id: &TaskId,
+        idempotency_key: &str,
+    ) -> Result<Option<DapUploadRecord>, DapError> {
+        Ok(self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock")
+            .get(task_id)
+            .and_then(|report_store| report_store.upload_records.get(idempotency_key).cloned()))
+    }
+
+    async fn put_upload_record(
+        &self,
+        task_id: &TaskId,
+        idempotency_key: &str,
+        record: &DapUploadRecord,
+        _report_time: Time,
+    ) -> Result<Option<DapUploadRecord>, DapError> {
+        self.inject_faults(MockOperation::PutUploadRecord)?;
+        let mut guard = self
+            .report_store
+            .lock()
+            .expect("report_store: failed to lock");
+        let upload_records = &mut guard.entry(task_id.clone()).or_default().upload_records;
+        if let Some(stored_record) = upload_records.get_mut(idempotency_key) {
+            let prev_record = stored_record.clone();
+            stored_record.merge(record);
+            return Ok(Some(prev_record));
+        }
+        upload_records.insert(idempotency_key.to_string(), record.clone());
+        Ok(None)
+    }
+
+    async fn defer_helper(&self, helper_url: &Url, until: Time) -> Result<(), DapError> {
+        self.helper_deferrals
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?
+            .insert(helper_url.clone(), until);
+        Ok(())
+    }
+
+    async fn helper_deferred_until(&self, helper_url: &Url) -> Result<Option<Time>, DapError> {
+        Ok(self
+            .helper_deferrals
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?
+            .get(helper_url)
+            .copied())
+    }
+
+    async fn count_helper_auth_failure(&self, helper_url: &Url) -> Result<u32, DapError> {
+        let mut helper_auth_failures = self
+            .helper_auth_failures
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        let failures = helper_auth_failures.entry(helper_url.clone()).or_default();
+        *failures += 1;
+        Ok(*failures)
+    }
+
+    async fn reset_helper_auth_failures(&self, helper_url: &Url) -> Result<(), DapError> {
+        self.helper_auth_failures
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?
+            .remove(helper_url);
+        Ok(())
+    }
+
     // Called after receiving a CollectReq from Collector.
     async fn init_collect_job(
         &self,
         task_id: &TaskId,
         collect_job_id: &Option<CollectionJobId>,
         collect_req: &CollectionReq,
+        collector_id: Option<&str>,
     ) -> Result<Url, DapError> {
         let mut rng = thread_rng();
         let task_config = self
@@ -759,7 +1357,8 @@ where
         // Store Collect ID and CollectReq into LeaderState.
         let leader_state = leader_state_store.entry(task_id.clone()).or_default();
         leader_state.collect_ids.push_back(collect_id.clone());
-        let collect_job_state = CollectJobState::Pending(collect_req.clone());
+        let collect_job_state =
+            CollectJobState::Pending(collect_req.clone(), collector_id.map(str::to_string));
         leader_state
             .collect_jobs
             .insert(collect_id, collect_job_state);
@@ -784,18 +1383,41 @@ where
             .ok_or_else(|| DapError::fatal("collect job not found for task_id"))?;
         if let Some(collect_job_state) = leader_state.collect_jobs.get(collect_id) {
             match collect_job_state {
-                CollectJobState::Pending(_) => Ok(DapCollectJob::Pending),
-                CollectJobState::Processed(resp) => Ok(DapCollectJob::Done(resp.clone())),
+                CollectJobState::Pending(..) => Ok(DapCollectJob::Pending),
+                CollectJobState::Processed(resp, _report_counts) => {
+                    Ok(DapCollectJob::Done(resp.clone()))
+                }
             }
         } else {
             Ok(DapCollectJob::Unknown)
         }
     }
 
-    // Called to retrieve pending CollectReq.
-    async fn get_pending_collect_jobs(
+    async fn delete_collect_job(
         &self,
-    ) -> Result<Vec<(TaskId, CollectionJobId, CollectionReq)>, DapError> {
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> Result<DapCollectJob, DapError> {
+        let mut leader_state_store_mutex_guard = self
+            .leader_state_store
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        let leader_state_store = leader_state_store_mutex_guard.deref_mut();
+
+        let leader_state = match leader_state_store.get_mut(task_id) {
+            Some(leader_state) => leader_state,
+            None => return Ok(DapCollectJob::Unknown),
+        };
+        leader_state.collect_ids.retain(|id| id != collect_id);
+        match leader_state.collect_jobs.remove(collect_id) {
+            Some(CollectJobState::Pending(..)) => Ok(DapCollectJob::Pending),
+            Some(CollectJobState::Processed(resp, _report_counts)) => Ok(DapCollectJob::Done(resp)),
+            None => Ok(DapCollectJob::Unknown),
+        }
+    }
+
+    // Called to retrieve pending CollectReq.
+    async fn get_pending_collect_jobs(&self) -> Result<Vec<DapPendingCollectJob>, DapError> {
         let mut leader_state_store_mutex_guard = self
             .leader_state_store
             .lock()
@@ -806,21 +1428,59 @@ where
         for (task_id, leader_state) in leader_state_store.iter() {
             // Iterate over collect IDs and copy them and their associated requests to the response.
             for collect_id in leader_state.collect_ids.iter() {
-                if let CollectJobState::Pending(collect_req) =
+                if let CollectJobState::Pending(collect_req, collector_id) =
                     leader_state.collect_jobs.get(collect_id).unwrap()
                 {
-                    res.push((task_id.clone(), collect_id.clone(), collect_req.clone()));
+                    res.push((
+                        task_id.clone(),
+                        collect_id.clone(),
+                        collect_req.clone(),
+                        collector_id.clone(),
+                    ));
                 }
             }
         }
         Ok(res)
     }
 
+    async fn get_report_counts(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> Result<Vec<DapBucketReportCount>, DapError> {
+        let task_config = self.unchecked_get_task_config(task_id).await;
+        let guard = self.agg_store.lock().expect("agg_store: failed to lock");
+        let agg_store = match guard.get(task_id) {
+            Some(agg_store) => agg_store,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut report_counts = Vec::new();
+        for bucket in task_config.batch_span_for_sel(batch_sel)? {
+            let report_count = agg_store
+                .get(&bucket.to_owned_bucket())
+                .map_or(0, |inner_agg_store| inner_agg_store.agg_share.report_count);
+            if report_count > 0 {
+                report_counts.push(DapBucketReportCount {
+                    batch_window: match bucket {
+                        DapBatchBucket::FixedSize { .. } => None,
+                        DapBatchBucket::TimeInterval { batch_window } => Some(batch_window),
+                    },
+                    report_count,
+                });
+            }
+        }
+        report_counts.sort_by_key(|bucket| bucket.batch_window);
+        Ok(report_counts)
+    }
+
     async fn finish_collect_job(
         &self,
         task_id: &TaskId,
         collect_id: &CollectionJobId,
+        _batch_sel: &BatchSelector,
         collect_resp: &Collection,
+        report_counts: &DapReportCountBreakdown,
     ) -> Result<(), DapError> {
         let mut leader_state_store_mutex_guard = self
             .leader_state_store
@@ -842,13 +1502,14 @@ where
         {
             leader_state
                 .batch_queue
-                .retain(|(id, _report_count)| id != batch_id);
+                .retain(|(id, _report_count, _created_at)| id != batch_id);
         }
 
         match collect_job {
-            CollectJobState::Pending(_) => {
+            CollectJobState::Pending(..) => {
                 // Mark collect job as Processed.
-                *collect_job = CollectJobState::Processed(collect_resp.clone());
+                *collect_job =
+                    CollectJobState::Processed(collect_resp.clone(), report_counts.clone());
 
                 // Remove collect ID from queue.
                 let index = leader_state
@@ -860,46 +1521,68 @@ where
 
                 Ok(())
             }
-            CollectJobState::Processed(_) => {
+            CollectJobState::Processed(..) => {
                 Err(DapError::fatal("tried to overwrite collect response"))
             }
         }
     }
 
+    async fn on_collect_job_finished(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        collect_id: &CollectionJobId,
+        _batch_sel: &BatchSelector,
+        _collection: &Collection,
+    ) -> Result<(), DapError> {
+        self.finished_collect_jobs
+            .lock()
+            .map_err(|e| DapError::Fatal(e.to_string()))?
+            .push(collect_id.clone());
+        Ok(())
+    }
+
+    async fn close_current_batch(&self, task_id: &TaskId) -> Result<Option<BatchId>, DapError> {
+        let task_config = self.unchecked_get_task_config(task_id).await;
+        let now = self.get_current_time();
+        let batch_lifetime = task_config.query.batch_lifetime();
+        let mut guard = self
+            .leader_state_store
+            .lock()
+            .expect("leader_state_store: failed to lock");
+        let leader_state = guard.entry(task_id.clone()).or_default();
+
+        // The current batch is the one to which the next report would be assigned.
+        let LeaderState {
+            batch_queue,
+            closed_batches,
+            ..
+        } = leader_state;
+        let current = batch_queue
+            .iter()
+            .find(|(batch_id, report_count, created_at)| {
+                *report_count < task_config.min_batch_size
+                    && !batch_lifetime
+                        .is_some_and(|batch_lifetime| batch_lifetime.is_stale(*created_at, now))
+                    && !closed_batches.contains(batch_id)
+            });
+        Ok(current.map(|(batch_id, _report_count, _created_at)| {
+            closed_batches.insert(batch_id.clone());
+            batch_id.clone()
+        }))
+    }
+
     async fn send_http_post(&self, req: DapRequest<BearerToken>) -> Result<DapResponse, DapError> {
-        match req.media_type {
-            DapMediaType::AggregationJobInitReq | DapMediaType::AggregationJobContinueReq => {
-                Ok(self
-                    .peer
-                    .as_ref()
-                    .expect("peer not configured")
-                    .http_post_aggregate(&req)
-                    .await
-                    .expect("peer aborted unexpectedly"))
-            }
-            DapMediaType::AggregateShareReq => Ok(self
-                .peer
-                .as_ref()
-                .expect("peer not configured")
-                .http_post_aggregate_share(&req)
-                .await
-                .expect("peer aborted unexpectedly")),
-            _ => unreachable!("unhandled media type: {:?}", req.media_type),
+        self.inject_faults(MockOperation::SendHttp)?;
+        if req.media_type == DapMediaType::AggregationJobContinueReq {
+            self.inject_faults(MockOperation::SendAggJobContinueReq)?;
         }
+        loopback_send_http(self.peer.as_deref().expect("peer not configured"), &req).await
     }
 
     async fn send_http_put(&self, req: DapRequest<BearerToken>) -> Result<DapResponse, DapError> {
-        if req.media_type == DapMediaType::AggregationJobInitReq {
-            Ok(self
-                .peer
-                .as_ref()
-                .expect("peer not configured")
-                .http_post_aggregate(&req)
-                .await
-                .expect("peer aborted unexpectedly"))
-        } else {
-            unreachable!("unhandled media type: {:?}", req.media_type)
-        }
+        self.inject_faults(MockOperation::SendHttp)?;
+        loopback_send_http(self.peer.as_deref().expect("peer not configured"), &req).await
     }
 }
 
@@ -910,17 +1593,31 @@ pub(crate) struct HelperStateInfo {
     agg_job_id_owned: MetaAggregationJobIdOwned,
 }
 
+/// Helper state stored for a given task ID and aggregate job ID.
+pub(crate) struct HelperStateEntry {
+    helper_state: DapHelperState,
+    expiration: Option<Time>,
+}
+
 /// Stores the reports received from Clients.
 #[derive(Default)]
 pub(crate) struct ReportStore {
     pub(crate) pending: HashMap<DapBatchBucketOwned, VecDeque<Report>>,
-    pub(crate) processed: HashSet<ReportId>,
+    /// IDs of the reports that have been processed, mapped to their timestamps.
+    pub(crate) processed: HashMap<ReportId, Time>,
+    /// Number of abandoned aggregation jobs each report has been part of.
+    pub(crate) abandoned: HashMap<ReportId, u32>,
+    /// Metadata of the sampled reports.
+    pub(crate) samples: Vec<DapReportSample>,
+    /// Records of the upload requests handled under each idempotency key.
+    pub(crate) upload_records: HashMap<String, DapUploadRecord>,
 }
 
-/// Stores the state of the collect job.
+/// Stores the state of the collect job. A pending job carries the ID of the Collector that
+/// issued it; a processed job carries the breakdown of its report count.
 pub(crate) enum CollectJobState {
-    Pending(CollectionReq),
-    Processed(Collection),
+    Pending(CollectionReq, Option<String>),
+    Processed(Collection, DapReportCountBreakdown),
 }
 
 /// LeaderState keeps track of the following:
@@ -930,16 +1627,21 @@ pub(crate) enum CollectJobState {
 pub(crate) struct LeaderState {
     collect_ids: VecDeque<CollectionJobId>,
     collect_jobs: HashMap<CollectionJobId, CollectJobState>,
-    batch_queue: VecDeque<(BatchId, u64)>, // Batch ID, batch size
+    batch_queue: VecDeque<(BatchId, u64, Time)>, // Batch ID, batch size, creation time
+    closed_batches: HashSet<BatchId>,
 }
 
 /// AggStore keeps track of the following:
 /// * Aggregate share
 /// * Whether this aggregate share has been collected
+/// * How many times each Collector has queried it (`None` for the task's primary Collector)
+/// * Whether it has been forced to be collectable
 #[derive(Default)]
 pub(crate) struct AggStore {
     pub(crate) agg_share: DapAggregateShare,
     pub(crate) collected: bool,
+    pub(crate) query_counts: HashMap<Option<String>, u64>,
+    pub(crate) forced: bool,
 }
 
 // These are declarative macros which let us generate a test point for
@@ -980,6 +1682,8 @@ macro_rules! test_versions {
         $(
             test_version! { $fname, Draft02 }
             test_version! { $fname, Draft04 }
+            test_version! { $fname, Draft05 }
+            test_version! { $fname, Draft06 }
         )*
     };
 }
@@ -1002,6 +1706,22 @@ macro_rules! async_test_versions {
         $(
             async_test_version! { $fname, Draft02 }
             async_test_version! { $fname, Draft04 }
+            async_test_version! { $fname, Draft05 }
+            async_test_version! { $fname, Draft06 }
+        )*
+    };
+}
+
+/// Like `async_test_versions`, but skips the versions in which the Helper finishes preparation
+/// upon receiving the `AggregationJobInitReq`. Use this for tests that exercise the Helper's
+/// state or the `AggregationJobContinueReq`.
+#[macro_export]
+macro_rules! async_test_versions_multi_round {
+    ($($fname:ident),*) => {
+        $(
+            async_test_version! { $fname, Draft02 }
+            async_test_version! { $fname, Draft04 }
+            async_test_version! { $fname, Draft05 }
         )*
     };
 }
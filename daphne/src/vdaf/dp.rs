@@ -0,0 +1,227 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Differential privacy (DP) mechanisms applied by the Aggregators to their aggregate shares.
+//!
+//! Noise is sampled exactly, using only integer arithmetic, following Canonne, Kamath, and Steinke
+//! ([CKS20](https://arxiv.org/abs/2004.00010)).
+
+use crate::{
+    messages::taskprov::DpConfig, vdaf::VdafAggregateShare, DapAggregateShare, DapDpConfig,
+    DapError, DapRational,
+};
+use prio::{field::FieldElementWithInteger, vdaf::AggregateShare, vdaf::OutputShare};
+use rand::prelude::*;
+
+impl DapDpConfig {
+    /// Returns `true` if no noise is added.
+    pub fn is_none(&self) -> bool {
+        matches!(self, Self::None)
+    }
+
+    /// Check that the parameters of the mechanism are well-formed, i.e., that each is a positive
+    /// rational number.
+    pub fn validate(&self) -> Result<(), String> {
+        let (name, param) = match self {
+            Self::None => return Ok(()),
+            Self::DiscreteLaplace { scale } => ("scale", scale),
+            Self::DiscreteGaussian { sigma_squared } => ("sigma_squared", sigma_squared),
+        };
+        if param.numerator == 0 || param.denominator == 0 {
+            return Err(format!(
+                "DP parameter {name} must be positive (got {}/{})",
+                param.numerator, param.denominator
+            ));
+        }
+        Ok(())
+    }
+
+    /// Add independently sampled noise to each element of the aggregate share. This method is run
+    /// by each Aggregator just before encrypting its aggregate share to the Collector.
+    pub(crate) fn add_noise(&self, agg_share: &mut DapAggregateShare) -> Result<(), DapError> {
+        if self.is_none() {
+            return Ok(());
+        }
+        self.validate().map_err(DapError::Fatal)?;
+
+        let mut rng = thread_rng();
+        let mut sample = || self.sample(&mut rng);
+        agg_share.data = match agg_share.data.take() {
+            None => None,
+            Some(VdafAggregateShare::Field64(data)) => Some(VdafAggregateShare::Field64(
+                add_noise_to_field_vec(&data, &mut sample)?,
+            )),
+            Some(VdafAggregateShare::Field128(data)) => Some(VdafAggregateShare::Field128(
+                add_noise_to_field_vec(&data, &mut sample)?,
+            )),
+            Some(VdafAggregateShare::FieldPrio2(data)) => Some(VdafAggregateShare::FieldPrio2(
+                add_noise_to_field_vec(&data, &mut sample)?,
+            )),
+        };
+        Ok(())
+    }
+
+    /// Sample a noise value. The parameters are assumed to be valid.
+    pub(crate) fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> i64 {
+        match self {
+            Self::None => 0,
+            Self::DiscreteLaplace { scale } => {
+                sample_discrete_laplace(rng, scale.numerator.into(), scale.denominator.into())
+            }
+            Self::DiscreteGaussian { sigma_squared } => sample_discrete_gaussian(
+                rng,
+                sigma_squared.numerator.into(),
+                sigma_squared.denominator.into(),
+            ),
+        }
+    }
+}
+
+impl From<DpConfig> for DapDpConfig {
+    fn from(dp_config: DpConfig) -> Self {
+        match dp_config {
+            DpConfig::None => Self::None,
+            DpConfig::DiscreteLaplace {
+                scale_numerator,
+                scale_denominator,
+            } => Self::DiscreteLaplace {
+                scale: DapRational {
+                    numerator: scale_numerator,
+                    denominator: scale_denominator,
+                },
+            },
+            DpConfig::DiscreteGaussian {
+                sigma_squared_numerator,
+                sigma_squared_denominator,
+            } => Self::DiscreteGaussian {
+                sigma_squared: DapRational {
+                    numerator: sigma_squared_numerator,
+                    denominator: sigma_squared_denominator,
+                },
+            },
+        }
+    }
+}
+
+fn add_noise_to_field_vec<F>(
+    agg_share: &AggregateShare<F>,
+    sample: &mut impl FnMut() -> i64,
+) -> Result<AggregateShare<F>, DapError>
+where
+    F: FieldElementWithInteger,
+    F::Integer: TryFrom<u64>,
+{
+    let noised = agg_share
+        .as_ref()
+        .iter()
+        .map(|x| {
+            let noise = sample();
+            let magnitude = F::Integer::try_from(noise.unsigned_abs())
+                .map_err(|_| DapError::fatal("DP noise exceeds the field size"))?;
+            if noise < 0 {
+                Ok(*x - F::from(magnitude))
+            } else {
+                Ok(*x + F::from(magnitude))
+            }
+        })
+        .collect::<Result<Vec<F>, DapError>>()?;
+    Ok(OutputShare::from(noised).into())
+}
+
+/// Sample from `Bernoulli(numerator / denominator)`. The probability must be at most `1`.
+fn sample_bernoulli<R: Rng + ?Sized>(rng: &mut R, numerator: u128, denominator: u128) -> bool {
+    rng.gen_range(0..denominator) < numerator
+}
+
+/// Sample from `Bernoulli(exp(-gamma))`, where `gamma = numerator / denominator` (CKS20,
+/// Algorithm 1).
+pub(crate) fn sample_bernoulli_exp<R: Rng + ?Sized>(
+    rng: &mut R,
+    numerator: u128,
+    denominator: u128,
+) -> bool {
+    if numerator <= denominator {
+        let mut k: u128 = 1;
+        while sample_bernoulli(rng, numerator, denominator.saturating_mul(k)) {
+            k += 1;
+        }
+        k % 2 == 1
+    } else {
+        for _ in 0..numerator / denominator {
+            if !sample_bernoulli_exp(rng, 1, 1) {
+                return false;
+            }
+        }
+        sample_bernoulli_exp(rng, numerator % denominator, denominator)
+    }
+}
+
+/// Sample from the discrete Laplace distribution with scale `numerator / denominator` (CKS20,
+/// Algorithm 2).
+pub(crate) fn sample_discrete_laplace<R: Rng + ?Sized>(
+    rng: &mut R,
+    numerator: u64,
+    denominator: u64,
+) -> i64 {
+    loop {
+        let u = rng.gen_range(0..numerator);
+        if !sample_bernoulli_exp(rng, u.into(), numerator.into()) {
+            continue;
+        }
+
+        let mut v: u64 = 0;
+        while sample_bernoulli_exp(rng, 1, 1) {
+            v += 1;
+        }
+
+        let y = u.saturating_add(numerator.saturating_mul(v)) / denominator;
+        let negative = rng.gen::<bool>();
+        if negative && y == 0 {
+            continue;
+        }
+
+        let y = i64::try_from(y).unwrap_or(i64::MAX);
+        return if negative { -y } else { y };
+    }
+}
+
+/// Sample from the discrete Gaussian distribution with variance `sigma^2 = numerator /
+/// denominator` (CKS20, Algorithm 3).
+pub(crate) fn sample_discrete_gaussian<R: Rng + ?Sized>(
+    rng: &mut R,
+    numerator: u64,
+    denominator: u64,
+) -> i64 {
+    // t = floor(sigma) + 1. The floating point estimate of sigma is corrected so that t is exact.
+    let (n, d) = (u128::from(numerator), u128::from(denominator));
+    let mut t = (numerator as f64 / denominator as f64).sqrt() as u128;
+    while t * t * d > n {
+        t -= 1;
+    }
+    while (t + 1) * (t + 1) * d <= n {
+        t += 1;
+    }
+    let t = t + 1;
+
+    loop {
+        let y = sample_discrete_laplace(rng, t.try_into().unwrap(), 1);
+
+        // Accept with probability exp(-gamma), where
+        //
+        //   gamma = (|y| - sigma^2 / t)^2 / (2 * sigma^2)
+        //         = (|y| * d * t - n)^2 / (2 * n * d * t^2).
+        //
+        // The denominator fits in a u128 because the parameters are 32-bit integers. If the
+        // numerator does not, then gamma is so large that acceptance is negligibly likely.
+        let gamma_numerator = u128::from(y.unsigned_abs())
+            .checked_mul(d * t)
+            .map(|x| x.abs_diff(n))
+            .and_then(|x| x.checked_mul(x));
+        let gamma_denominator = 2 * n * d * t * t;
+        if gamma_numerator.is_some_and(|gamma_numerator| {
+            sample_bernoulli_exp(rng, gamma_numerator, gamma_denominator)
+        }) {
+            return y;
+        }
+    }
+}
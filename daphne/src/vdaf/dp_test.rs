@@ -0,0 +1,142 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    vdaf::{
+        dp::{sample_bernoulli_exp, sample_discrete_gaussian, sample_discrete_laplace},
+        VdafAggregateShare,
+    },
+    DapAggregateShare, DapDpConfig, DapRational,
+};
+use prio::{
+    field::{Field64, FieldElementWithInteger},
+    vdaf::{AggregateShare, OutputShare},
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+const SAMPLES: usize = 100_000;
+
+fn assert_close(got: f64, want: f64) {
+    assert!(
+        (got - want).abs() < want * 0.05,
+        "got {got}, want approximately {want}"
+    );
+}
+
+fn mean_and_variance(samples: &[i64]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    let mean = samples.iter().map(|x| *x as f64).sum::<f64>() / n;
+    let variance = samples
+        .iter()
+        .map(|x| (*x as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    (mean, variance)
+}
+
+#[test]
+fn bernoulli_exp() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for (numerator, denominator) in [(0, 1), (1, 2), (1, 1), (5, 2)] {
+        let hits = (0..SAMPLES)
+            .filter(|_| sample_bernoulli_exp(&mut rng, numerator, denominator))
+            .count();
+        let want = (-(numerator as f64) / denominator as f64).exp();
+        assert_close(hits as f64 / SAMPLES as f64, want);
+    }
+}
+
+#[test]
+fn discrete_laplace() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let samples: Vec<i64> = (0..SAMPLES)
+        .map(|_| sample_discrete_laplace(&mut rng, 5, 2))
+        .collect();
+
+    // The variance of the discrete Laplace distribution with scale b is
+    // 2 * exp(-1/b) / (1 - exp(-1/b))^2.
+    let e = (-2.0_f64 / 5.0).exp();
+    let (mean, variance) = mean_and_variance(&samples);
+    assert!(mean.abs() < 0.1, "mean is {mean}");
+    assert_close(variance, 2.0 * e / (1.0 - e).powi(2));
+}
+
+#[test]
+fn discrete_gaussian() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let samples: Vec<i64> = (0..SAMPLES)
+        .map(|_| sample_discrete_gaussian(&mut rng, 49, 4))
+        .collect();
+
+    let (mean, variance) = mean_and_variance(&samples);
+    assert!(mean.abs() < 0.1, "mean is {mean}");
+    assert_close(variance, 49.0 / 4.0);
+}
+
+#[test]
+fn add_noise() {
+    let dp = DapDpConfig::DiscreteGaussian {
+        sigma_squared: DapRational {
+            numerator: 100,
+            denominator: 1,
+        },
+    };
+    let mut agg_share = DapAggregateShare {
+        report_count: 1,
+        min_time: 0,
+        max_time: 0,
+        checksum: [0; 32],
+        data: Some(VdafAggregateShare::Field64(AggregateShare::from(
+            OutputShare::from(vec![Field64::from(1337); 1000]),
+        ))),
+    };
+    dp.add_noise(&mut agg_share).unwrap();
+
+    let data = match agg_share.data {
+        Some(VdafAggregateShare::Field64(data)) => data,
+        _ => panic!("unexpected aggregate share type"),
+    };
+    let samples: Vec<i64> = data
+        .as_ref()
+        .iter()
+        .map(|x| {
+            let x = u64::from(*x);
+            if x > Field64::modulus() / 2 {
+                -i64::try_from(Field64::modulus() - x).unwrap()
+            } else {
+                i64::try_from(x).unwrap()
+            }
+        })
+        .collect();
+    assert!(samples.iter().any(|x| *x != 1337));
+    assert!(samples.iter().all(|x| (1337 - 100..1337 + 100).contains(x)));
+}
+
+#[test]
+fn validate() {
+    assert!(DapDpConfig::None.validate().is_ok());
+    assert!(DapDpConfig::DiscreteLaplace {
+        scale: DapRational {
+            numerator: 1,
+            denominator: 3,
+        },
+    }
+    .validate()
+    .is_ok());
+    assert!(DapDpConfig::DiscreteLaplace {
+        scale: DapRational {
+            numerator: 1,
+            denominator: 0,
+        },
+    }
+    .validate()
+    .is_err());
+    assert!(DapDpConfig::DiscreteGaussian {
+        sigma_squared: DapRational {
+            numerator: 0,
+            denominator: 1,
+        },
+    }
+    .validate()
+    .is_err());
+}
@@ -4,36 +4,42 @@
 //! Verifiable, Distributed Aggregation Functions
 //! ([VDAFs](https://datatracker.ietf.org/doc/draft-irtf-cfrg-vdaf/)).
 
+#[cfg(feature = "prio2")]
+use crate::vdaf::prio2::{
+    prio2_encode_prepare_message, prio2_helper_prepare_state_len, prio2_prepare_finish,
+    prio2_prepare_finish_from_shares, prio2_prepare_init, prio2_shard, prio2_unshard,
+};
 use crate::{
+    extensions::{DapExtensionRegistry, DapReportExtensions},
     hpke::HpkeDecrypter,
     messages::{
-        encode_u32_bytes, AggregationJobContinueReq, AggregationJobInitReq, AggregationJobResp,
-        BatchSelector, Extension, HpkeCiphertext, HpkeConfig, PartialBatchSelector,
-        PlaintextInputShare, Report, ReportId, ReportMetadata, ReportShare, TaskId, Time,
-        Transition, TransitionFailure, TransitionVar,
+        encode_u32_bytes,
+        taskprov::{
+            VDAF_TYPE_PRIO3_AES128_COUNT, VDAF_TYPE_PRIO3_AES128_HISTOGRAM,
+            VDAF_TYPE_PRIO3_AES128_SUM, VDAF_TYPE_PRIO3_FIXED_POINT_BOUNDED_L2_VEC_SUM,
+        },
+        AggregationJobContinueReq, AggregationJobInitReq, AggregationJobResp, BatchSelector,
+        HpkeCiphertext, HpkeConfig, PartialBatchSelector, PingPongMessage, PlaintextInputShare,
+        Report, ReportId, ReportMetadata, ReportShare, TaskId, Time, Transition, TransitionFailure,
+        TransitionVar,
     },
     metrics::ContextualizedDaphneMetrics,
-    vdaf::{
-        prio2::{
-            prio2_encode_prepare_message, prio2_helper_prepare_finish, prio2_leader_prepare_finish,
-            prio2_prepare_init, prio2_shard, prio2_unshard,
-        },
-        prio3::{
-            prio3_encode_prepare_message, prio3_helper_prepare_finish, prio3_leader_prepare_finish,
-            prio3_prepare_init, prio3_shard, prio3_unshard,
-        },
+    vdaf::prio3::{
+        prio3_encode_prepare_message, prio3_helper_prepare_state_len, prio3_prepare_finish,
+        prio3_prepare_finish_from_shares, prio3_prepare_init, prio3_shard, prio3_unshard,
     },
     DapAbort, DapAggregateResult, DapAggregateShare, DapError, DapHelperState, DapHelperTransition,
     DapLeaderState, DapLeaderTransition, DapLeaderUncommitted, DapMeasurement, DapOutputShare,
-    DapTaskConfig, DapVersion, MetaAggregationJobId, VdafConfig,
+    DapSender, DapTaskConfig, DapVersion, MetaAggregationJobId, Prio3Config,
+    Prio3FixedPointBitSize, VdafConfig,
 };
+use futures::{stream, StreamExt, TryStreamExt};
+#[cfg(feature = "prio2")]
+use prio::vdaf::prio2::{Prio2PrepareShare, Prio2PrepareState};
 use prio::{
     codec::{CodecError, Decode, Encode, ParameterizedEncode},
     field::{Field128, Field64, FieldPrio2},
-    vdaf::{
-        prio2::{Prio2PrepareShare, Prio2PrepareState},
-        prio3::{Prio3PrepareShare, Prio3PrepareState},
-    },
+    vdaf::prio3::{Prio3PrepareShare, Prio3PrepareState},
 };
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -41,8 +47,12 @@ use std::{collections::HashSet, convert::TryInto};
 
 const CTX_INPUT_SHARE_DRAFT02: &[u8] = b"dap-02 input share";
 const CTX_INPUT_SHARE_DRAFT04: &[u8] = b"dap-04 input share";
+const CTX_INPUT_SHARE_DRAFT05: &[u8] = b"dap-05 input share";
+const CTX_INPUT_SHARE_DRAFT06: &[u8] = b"dap-06 input share";
 const CTX_AGG_SHARE_DRAFT02: &[u8] = b"dap-02 aggregate share";
 const CTX_AGG_SHARE_DRAFT04: &[u8] = b"dap-04 aggregate share";
+const CTX_AGG_SHARE_DRAFT05: &[u8] = b"dap-05 aggregate share";
+const CTX_AGG_SHARE_DRAFT06: &[u8] = b"dap-06 aggregate share";
 const CTX_ROLE_COLLECTOR: u8 = 0;
 const CTX_ROLE_CLIENT: u8 = 1;
 const CTX_ROLE_LEADER: u8 = 2;
@@ -56,6 +66,55 @@ pub(crate) enum VdafError {
     Vdaf(#[from] prio::vdaf::VdafError),
 }
 
+/// Reason for which the Collector could not consume the encrypted aggregate shares of a
+/// collection. Where possible, the Aggregator whose aggregate share is at fault is identified, so
+/// that the Collector can decide whom to retry with.
+#[derive(Debug, thiserror::Error)]
+pub enum DapUnshardError {
+    /// The aggregate share sent by the Aggregator could not be decrypted.
+    #[error("failed to decrypt aggregate share of {0:?}: {1}")]
+    Decrypt(DapSender, DapError),
+
+    /// The aggregate share sent by the Aggregator was decrypted, but could not be decoded.
+    #[error("failed to decode aggregate share of {0:?}: {1}")]
+    Decode(DapSender, String),
+
+    /// The aggregate shares were well-formed, but could not be combined.
+    #[error("failed to unshard aggregate shares: {0}")]
+    Unshard(String),
+
+    /// Any other error.
+    #[error("{0}")]
+    Other(DapError),
+}
+
+impl DapUnshardError {
+    /// Return the Aggregator whose aggregate share is at fault, if known.
+    pub fn aggregator(&self) -> Option<DapSender> {
+        match self {
+            Self::Decrypt(sender, ..) | Self::Decode(sender, ..) => Some(*sender),
+            Self::Unshard(..) | Self::Other(..) => None,
+        }
+    }
+}
+
+impl From<prio::vdaf::VdafError> for DapUnshardError {
+    fn from(e: prio::vdaf::VdafError) -> Self {
+        Self::Unshard(e.to_string())
+    }
+}
+
+impl From<DapUnshardError> for DapError {
+    fn from(e: DapUnshardError) -> Self {
+        match e {
+            DapUnshardError::Decrypt(_, e) | DapUnshardError::Other(e) => e,
+            DapUnshardError::Decode(..) | DapUnshardError::Unshard(..) => {
+                Self::Transition(TransitionFailure::VdafPrepError)
+            }
+        }
+    }
+}
+
 /// A VDAF verification key.
 #[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -75,6 +134,7 @@ impl AsRef<[u8]> for VdafVerifyKey {
 
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum VdafState {
+    #[cfg(feature = "prio2")]
     Prio2(Prio2PrepareState),
     Prio3Field64(Prio3PrepareState<Field64, 16>),
     Prio3Field128(Prio3PrepareState<Field128, 16>),
@@ -82,11 +142,50 @@ pub(crate) enum VdafState {
 
 #[derive(Clone, Debug)]
 pub(crate) enum VdafMessage {
+    #[cfg(feature = "prio2")]
     Prio2Share(Prio2PrepareShare),
     Prio3ShareField64(Prio3PrepareShare<Field64, 16>),
     Prio3ShareField128(Prio3PrepareShare<Field128, 16>),
 }
 
+/// Apply `f` to each item, splitting the items among up to `concurrency` threads. In WebAssembly,
+/// where threads are not available, the items are processed one at a time. The results are in the
+/// order of the items.
+fn par_map<T, U, F>(items: &[T], concurrency: usize, f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use rayon::prelude::*;
+        let chunk_len = items.len().div_ceil(concurrency.max(1)).max(1);
+        items
+            .par_chunks(chunk_len)
+            .flat_map_iter(|chunk| chunk.iter().map(&f))
+            .collect()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = concurrency;
+        items.iter().map(f).collect()
+    }
+}
+
+/// Outcome of the Helper's preparation of a single report of an aggregation job.
+enum HelperPrepOutcome {
+    /// Preparation is finished: the Helper's output share and the prep message (draft06).
+    Finished(DapOutputShare, Vec<u8>),
+
+    /// Preparation continues with the Helper's state and prep share.
+    Continued(VdafState, VdafMessage),
+
+    /// The report was rejected.
+    Failed(TransitionFailure),
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum VdafAggregateShare {
@@ -105,6 +204,181 @@ impl Encode for VdafAggregateShare {
     }
 }
 
+/// The code point assigned to a VDAF for a given version of DAP. This is the value used to
+/// identify the VDAF in the taskprov extension.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VdafCodePoint {
+    pub version: DapVersion,
+    pub code_point: u32,
+}
+
+/// The range of values (inclusive) that a VDAF parameter may take.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VdafParamRange {
+    pub name: &'static str,
+    pub min: u64,
+    pub max: u64,
+}
+
+/// Describes a VDAF supported by Daphne.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VdafDescriptor {
+    /// The name of the VDAF, as used by draft-dcook-ppm-dap-interop-test-design-02.
+    pub name: &'static str,
+
+    /// The code points of the VDAF for each DAP version. This is empty if the VDAF has not been
+    /// assigned a code point.
+    pub code_points: Vec<VdafCodePoint>,
+
+    /// The parameters of the VDAF and the range of values supported for each.
+    pub params: Vec<VdafParamRange>,
+
+    /// The variant of [`DapMeasurement`] consumed by the VDAF.
+    pub measurement_type: &'static str,
+
+    /// The variant of [`DapAggregateResult`] produced by the VDAF.
+    pub result_type: &'static str,
+}
+
+// Maximum number of buckets for Prio3Histogram. This is bounded by the taskprov encoding of the
+// bucket boundaries.
+const PRIO3_HISTOGRAM_MAX_BUCKETS: u64 = 0xffffff / 8;
+
+// Maximum vector length for Prio3SumVec.
+const PRIO3_SUM_VEC_MAX_LEN: u64 = 1 << 16;
+
+// Maximum vector length for Prio3FixedPointBoundedL2VecSum.
+const PRIO3_FIXED_POINT_VEC_MAX_LEN: u64 = 1 << 16;
+
+// Maximum dimension for Prio2. This is bounded by the size of the field.
+#[cfg(feature = "prio2")]
+const PRIO2_MAX_DIMENSION: u64 = (1 << 19) - 1;
+
+fn code_points_for_all_versions(code_point: u32) -> Vec<VdafCodePoint> {
+    [
+        DapVersion::Draft02,
+        DapVersion::Draft04,
+        DapVersion::Draft05,
+        DapVersion::Draft06,
+    ]
+    .into_iter()
+    .map(|version| VdafCodePoint {
+        version,
+        code_point,
+    })
+    .collect()
+}
+
+/// Check that an integer measurement is in range `[0, 2^bits)`.
+fn check_bits(bits: usize, x: u64) -> Result<(), String> {
+    if bits < 64 && x >> bits != 0 {
+        return Err(format!("{x} is out of range for {bits} bits"));
+    }
+    Ok(())
+}
+
+/// Return the list of VDAFs supported by Daphne.
+pub fn supported_vdafs() -> Vec<VdafDescriptor> {
+    vec![
+        VdafDescriptor {
+            name: "Prio3Count",
+            code_points: code_points_for_all_versions(VDAF_TYPE_PRIO3_AES128_COUNT),
+            params: vec![],
+            measurement_type: "U64",
+            result_type: "U64",
+        },
+        VdafDescriptor {
+            name: "Prio3Sum",
+            code_points: code_points_for_all_versions(VDAF_TYPE_PRIO3_AES128_SUM),
+            params: vec![VdafParamRange {
+                name: "bits",
+                min: 1,
+                max: 64,
+            }],
+            measurement_type: "U64",
+            result_type: "U128",
+        },
+        VdafDescriptor {
+            name: "Prio3Histogram",
+            code_points: code_points_for_all_versions(VDAF_TYPE_PRIO3_AES128_HISTOGRAM),
+            params: vec![VdafParamRange {
+                name: "buckets",
+                min: 1,
+                max: PRIO3_HISTOGRAM_MAX_BUCKETS,
+            }],
+            measurement_type: "U64",
+            result_type: "U128Vec",
+        },
+        VdafDescriptor {
+            name: "Prio3SumVec",
+            code_points: vec![],
+            params: vec![
+                VdafParamRange {
+                    name: "bits",
+                    min: 1,
+                    max: 64,
+                },
+                VdafParamRange {
+                    name: "len",
+                    min: 1,
+                    max: PRIO3_SUM_VEC_MAX_LEN,
+                },
+            ],
+            measurement_type: "U64Vec",
+            result_type: "U128Vec",
+        },
+        VdafDescriptor {
+            name: "Prio3FixedPoint16BitBoundedL2VecSum",
+            code_points: code_points_for_all_versions(
+                VDAF_TYPE_PRIO3_FIXED_POINT_BOUNDED_L2_VEC_SUM,
+            ),
+            params: vec![VdafParamRange {
+                name: "len",
+                min: 1,
+                max: PRIO3_FIXED_POINT_VEC_MAX_LEN,
+            }],
+            measurement_type: "F64Vec",
+            result_type: "F64Vec",
+        },
+        VdafDescriptor {
+            name: "Prio3FixedPoint32BitBoundedL2VecSum",
+            code_points: code_points_for_all_versions(
+                VDAF_TYPE_PRIO3_FIXED_POINT_BOUNDED_L2_VEC_SUM,
+            ),
+            params: vec![VdafParamRange {
+                name: "len",
+                min: 1,
+                max: PRIO3_FIXED_POINT_VEC_MAX_LEN,
+            }],
+            measurement_type: "F64Vec",
+            result_type: "F64Vec",
+        },
+        #[cfg(feature = "prio2")]
+        VdafDescriptor {
+            name: "Prio2",
+            code_points: vec![],
+            params: vec![VdafParamRange {
+                name: "dimension",
+                min: 1,
+                max: PRIO2_MAX_DIMENSION,
+            }],
+            measurement_type: "U32Vec",
+            result_type: "U32Vec",
+        },
+    ]
+}
+
+/// Compute the contribution of a report to the checksum of the batch it is aggregated into. The
+/// checksum of a batch is the XOR of the SHA-256 hashes of the IDs of its reports; the Leader
+/// sends its checksum to the Helper in the `AggregateShareReq` so that the Aggregators can verify
+/// that they aggregated the same set of reports.
+pub(crate) fn report_id_checksum(report_id: &ReportId) -> [u8; 32] {
+    ring::digest::digest(&ring::digest::SHA256, &report_id.get_encoded())
+        .as_ref()
+        .try_into()
+        .expect("SHA-256 digest is not 32 bytes long")
+}
+
 fn unimplemented_version_abort() -> DapAbort {
     DapAbort::BadRequest("unimplemented version".to_string())
 }
@@ -114,12 +388,71 @@ fn unimplemented_version() -> DapError {
 }
 
 impl VdafConfig {
+    /// Return the name of the VDAF, as listed by [`supported_vdafs`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Prio3(Prio3Config::Count) => "Prio3Count",
+            Self::Prio3(Prio3Config::Sum { .. }) => "Prio3Sum",
+            Self::Prio3(Prio3Config::Histogram { .. }) => "Prio3Histogram",
+            Self::Prio3(Prio3Config::SumVec { .. }) => "Prio3SumVec",
+            Self::Prio3(Prio3Config::FixedPointBoundedL2VecSum { bitsize, .. }) => match bitsize {
+                Prio3FixedPointBitSize::Fixed16 => "Prio3FixedPoint16BitBoundedL2VecSum",
+                Prio3FixedPointBitSize::Fixed32 => "Prio3FixedPoint32BitBoundedL2VecSum",
+            },
+            #[cfg(feature = "prio2")]
+            Self::Prio2 { .. } => "Prio2",
+        }
+    }
+
+    fn params(&self) -> Vec<(&'static str, u64)> {
+        match self {
+            Self::Prio3(Prio3Config::Count) => vec![],
+            Self::Prio3(Prio3Config::Sum { bits }) => vec![("bits", *bits as u64)],
+            Self::Prio3(Prio3Config::Histogram { buckets }) => {
+                vec![("buckets", buckets.len() as u64)]
+            }
+            Self::Prio3(Prio3Config::SumVec { bits, len }) => {
+                vec![("bits", *bits as u64), ("len", *len as u64)]
+            }
+            Self::Prio3(Prio3Config::FixedPointBoundedL2VecSum { len, .. }) => {
+                vec![("len", *len as u64)]
+            }
+            #[cfg(feature = "prio2")]
+            Self::Prio2 { dimension } => vec![("dimension", *dimension as u64)],
+        }
+    }
+
+    /// Check that the parameters of the VDAF are in the ranges listed by [`supported_vdafs`].
+    pub fn check_params(&self) -> Result<(), DapError> {
+        let name = self.name();
+        let descriptor = supported_vdafs()
+            .into_iter()
+            .find(|descriptor| descriptor.name == name)
+            .ok_or_else(|| DapError::Fatal(format!("unsupported VDAF: {name}")))?;
+
+        for (param, value) in self.params() {
+            let range = descriptor
+                .params
+                .iter()
+                .find(|range| range.name == param)
+                .ok_or_else(|| DapError::Fatal(format!("{name}: unexpected parameter {param}")))?;
+            if value < range.min || value > range.max {
+                return Err(DapError::Fatal(format!(
+                    "{name}: {param} ({value}) is out of range [{}, {}]",
+                    range.min, range.max
+                )));
+            }
+        }
+        Ok(())
+    }
+
     /// Parse a verification key from raw bytes.
     pub fn get_decoded_verify_key(&self, bytes: &[u8]) -> Result<VdafVerifyKey, DapError> {
         match self {
             Self::Prio3(..) => Ok(VdafVerifyKey::Prio3(
                 <[u8; 16]>::try_from(bytes).map_err(|e| CodecError::Other(Box::new(e)))?,
             )),
+            #[cfg(feature = "prio2")]
             Self::Prio2 { .. } => Ok(VdafVerifyKey::Prio2(
                 <[u8; 32]>::try_from(bytes).map_err(|e| CodecError::Other(Box::new(e)))?,
             )),
@@ -130,7 +463,9 @@ impl VdafConfig {
     /// executed.
     pub fn is_valid_agg_param(&self, agg_param: &[u8]) -> bool {
         match self {
-            Self::Prio3(..) | Self::Prio2 { .. } => agg_param.is_empty(),
+            Self::Prio3(..) => agg_param.is_empty(),
+            #[cfg(feature = "prio2")]
+            Self::Prio2 { .. } => agg_param.is_empty(),
         }
     }
 
@@ -139,10 +474,29 @@ impl VdafConfig {
         let mut rng = thread_rng();
         match self {
             Self::Prio3(..) => VdafVerifyKey::Prio3(rng.gen()),
+            #[cfg(feature = "prio2")]
             Self::Prio2 { .. } => VdafVerifyKey::Prio2(rng.gen()),
         }
     }
 
+    /// Encode the prep share output by VDAF preparation.
+    pub(crate) fn encode_prepare_message(&self, message: &VdafMessage) -> Vec<u8> {
+        match self {
+            Self::Prio3(..) => prio3_encode_prepare_message(message),
+            #[cfg(feature = "prio2")]
+            Self::Prio2 { .. } => prio2_encode_prepare_message(message),
+        }
+    }
+
+    /// Length of the Helper's encoded prepare state for a report. This is the same for each report.
+    pub(crate) fn helper_prepare_state_len(&self) -> usize {
+        match self {
+            Self::Prio3(prio3_config) => prio3_helper_prepare_state_len(prio3_config),
+            #[cfg(feature = "prio2")]
+            Self::Prio2 { .. } => prio2_helper_prepare_state_len(),
+        }
+    }
+
     /// Generate a report for a measurement. This method is run by the Client.
     ///
     /// # Inputs
@@ -159,7 +513,9 @@ impl VdafConfig {
     ///
     /// * `measurement` is the measurement.
     ///
-    /// * `extensions` are the extensions.
+    /// * `extensions` are the extensions. A list of extensions is treated as public, i.e., seen by
+    ///   both Aggregators. Each extension is placed where `version` expects it (see
+    ///   [`DapReportExtensions`]).
     ///
     /// * `version` is the DapVersion to use.
     //
@@ -170,7 +526,7 @@ impl VdafConfig {
         time: Time,
         task_id: &TaskId,
         measurement: DapMeasurement,
-        extensions: Vec<Extension>,
+        extensions: impl Into<DapReportExtensions>,
         version: DapVersion,
     ) -> Result<Report, DapError> {
         let mut rng = thread_rng();
@@ -183,7 +539,7 @@ impl VdafConfig {
             time,
             task_id,
             &report_id,
-            extensions,
+            &extensions.into(),
             version,
         )
     }
@@ -198,13 +554,14 @@ impl VdafConfig {
         time: Time,
         task_id: &TaskId,
         report_id: &ReportId,
-        extensions: Vec<Extension>,
+        extensions: &DapReportExtensions,
         version: DapVersion,
     ) -> Result<Report, DapError> {
-        let report_extensions = match version {
-            DapVersion::Draft02 => extensions.clone(),
-            _ => vec![],
-        };
+        if hpke_config_list.len() != input_shares.len() || input_shares.len() != 2 {
+            return Err(DapError::Fatal("unexpected number of HPKE configs".into()));
+        }
+
+        let (report_extensions, input_share_extensions) = extensions.place(version)?;
         let metadata = ReportMetadata {
             id: report_id.clone(),
             time,
@@ -212,24 +569,23 @@ impl VdafConfig {
         };
 
         if version != DapVersion::Draft02 {
-            let mut encoded: Vec<Vec<u8>> = Vec::new();
-            for share in input_shares.into_iter() {
-                let input_share = PlaintextInputShare {
-                    extensions: extensions.clone(),
-                    payload: share,
-                };
-                encoded.push(PlaintextInputShare::get_encoded(&input_share));
-            }
-            input_shares = encoded;
-        }
-
-        if hpke_config_list.len() != input_shares.len() {
-            return Err(DapError::Fatal("unexpected number of HPKE configs".into()));
+            input_shares = input_shares
+                .into_iter()
+                .zip(input_share_extensions)
+                .map(|(payload, extensions)| {
+                    PlaintextInputShare::get_encoded(&PlaintextInputShare {
+                        extensions,
+                        payload,
+                    })
+                })
+                .collect();
         }
 
         let input_share_text = match version {
             DapVersion::Draft02 => CTX_INPUT_SHARE_DRAFT02,
             DapVersion::Draft04 => CTX_INPUT_SHARE_DRAFT04,
+            DapVersion::Draft05 => CTX_INPUT_SHARE_DRAFT05,
+            DapVersion::Draft06 => CTX_INPUT_SHARE_DRAFT06,
             _ => return Err(unimplemented_version()),
         };
         let n: usize = input_share_text.len();
@@ -274,14 +630,83 @@ impl VdafConfig {
         })
     }
 
+    /// Check that the measurement is of a type consumed by the VDAF and within the range allowed
+    /// by its parameters. This is done before a report is produced, since an invalid measurement
+    /// would otherwise only be detected by the Aggregators, which reject the report as invalid.
+    pub fn check_measurement(&self, measurement: &DapMeasurement) -> Result<(), DapError> {
+        let invalid =
+            |detail: String| Err(DapError::Fatal(format!("invalid measurement: {detail}")));
+        match (self, measurement) {
+            (Self::Prio3(Prio3Config::Count), DapMeasurement::Bool(..))
+            | (Self::Prio3(Prio3Config::Count), DapMeasurement::U64(0 | 1)) => Ok(()),
+            (Self::Prio3(Prio3Config::Count), DapMeasurement::U64(x)) => {
+                invalid(format!("count must be 0 or 1, got {x}"))
+            }
+            (Self::Prio3(Prio3Config::Histogram { .. }), DapMeasurement::U64(..)) => Ok(()),
+            (Self::Prio3(Prio3Config::Histogram { buckets }), DapMeasurement::Category(i)) => {
+                if usize::try_from(*i).map_or(true, |i| i > buckets.len()) {
+                    return invalid(format!(
+                        "category {i} is out of range for a histogram with {} buckets",
+                        buckets.len() + 1
+                    ));
+                }
+                Ok(())
+            }
+            (Self::Prio3(Prio3Config::Sum { bits }), DapMeasurement::U64(x)) => {
+                check_bits(*bits, *x).or_else(invalid)
+            }
+            (Self::Prio3(Prio3Config::SumVec { bits, len }), DapMeasurement::U64Vec(v)) => {
+                if v.len() != *len {
+                    return invalid(format!("expected {len} entries, got {}", v.len()));
+                }
+                for x in v {
+                    check_bits(*bits, *x).or_else(invalid)?;
+                }
+                Ok(())
+            }
+            (
+                Self::Prio3(Prio3Config::FixedPointBoundedL2VecSum { len, .. }),
+                DapMeasurement::F64Vec(v),
+            ) => {
+                if v.len() != *len {
+                    return invalid(format!("expected {len} entries, got {}", v.len()));
+                }
+                if let Some(x) = v.iter().find(|x| !(-1.0..1.0).contains(*x)) {
+                    return invalid(format!("entry {x} is not in range [-1, 1)"));
+                }
+                let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                if norm > 1.0 {
+                    return invalid(format!("L2 norm {norm} exceeds 1"));
+                }
+                Ok(())
+            }
+            #[cfg(feature = "prio2")]
+            (Self::Prio2 { dimension }, DapMeasurement::U32Vec(v)) => {
+                if v.len() != *dimension {
+                    return invalid(format!("expected {dimension} entries, got {}", v.len()));
+                }
+                if let Some(x) = v.iter().find(|x| **x > 1) {
+                    return invalid(format!("entry must be 0 or 1, got {x}"));
+                }
+                Ok(())
+            }
+            _ => invalid(format!(
+                "{} is not consumed by {self:?}",
+                measurement.type_name()
+            )),
+        }
+    }
+
     /// Generate shares for a measurement.
     pub(crate) fn produce_input_shares(
         &self,
         measurement: DapMeasurement,
         nonce: &[u8; 16],
     ) -> Result<(Vec<u8>, Vec<Vec<u8>>), DapError> {
+        self.check_measurement(&measurement)?;
         match self {
             Self::Prio3(prio3_config) => Ok(prio3_shard(prio3_config, measurement, nonce)?),
+            #[cfg(feature = "prio2")]
             Self::Prio2 { dimension } => Ok(prio2_shard(*dimension, measurement, nonce)?),
         }
     }
@@ -328,6 +753,8 @@ impl VdafConfig {
     ///
     /// * `decryptor` is used to decrypt the input share.
     ///
+    /// * `extensions` is used to check the extensions carried by the report.
+    ///
     /// * `verify_key` is the secret VDAF verification key shared by the Aggregators.
     ///
     /// * `task_id` is the DAP task ID indicated by the report.
@@ -341,6 +768,7 @@ impl VdafConfig {
     pub(crate) async fn consume_report_share(
         &self,
         decrypter: &impl HpkeDecrypter<'_>,
+        extensions: &DapExtensionRegistry,
         is_leader: bool,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
@@ -348,13 +776,50 @@ impl VdafConfig {
         public_share: &[u8],
         encrypted_input_share: &HpkeCiphertext,
     ) -> Result<(VdafState, VdafMessage), DapError> {
+        let input_share = self
+            .decrypt_report_share(
+                decrypter,
+                extensions,
+                is_leader,
+                task_id,
+                task_config,
+                metadata,
+                public_share,
+                encrypted_input_share,
+            )
+            .await?;
+        self.prep_init_report_share(is_leader, task_config, metadata, public_share, &input_share)
+    }
+
+    /// Decrypt the input share of a report share sent by the Client and check the extensions
+    /// carried by the report. The output is the VDAF-specific payload of the input share. See
+    /// [`Self::consume_report_share`] for the inputs.
+    #[allow(clippy::too_many_arguments)]
+    async fn decrypt_report_share(
+        &self,
+        decrypter: &impl HpkeDecrypter<'_>,
+        extensions: &DapExtensionRegistry,
+        is_leader: bool,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        metadata: &ReportMetadata,
+        public_share: &[u8],
+        encrypted_input_share: &HpkeCiphertext,
+    ) -> Result<Vec<u8>, DapError> {
         if metadata.time >= task_config.expiration {
             return Err(DapError::Transition(TransitionFailure::TaskExpired));
         }
 
+        // Starting with draft03, extensions may only be carried by the plaintext input share.
+        if task_config.version != DapVersion::Draft02 && !metadata.extensions.is_empty() {
+            return Err(DapError::Transition(TransitionFailure::UnrecognizedMessage));
+        }
+
         let input_share_text = match task_config.version {
             DapVersion::Draft02 => CTX_INPUT_SHARE_DRAFT02,
             DapVersion::Draft04 => CTX_INPUT_SHARE_DRAFT04,
+            DapVersion::Draft05 => CTX_INPUT_SHARE_DRAFT05,
+            DapVersion::Draft06 => CTX_INPUT_SHARE_DRAFT06,
             _ => return Err(unimplemented_version()),
         };
         let n: usize = input_share_text.len();
@@ -388,6 +853,28 @@ impl VdafConfig {
             _ => PlaintextInputShare::get_decoded(&encoded_input_share)?,
         };
 
+        extensions
+            .validate(
+                task_id,
+                task_config,
+                metadata,
+                metadata.extensions.iter().chain(&input_share.extensions),
+            )
+            .map_err(DapError::Transition)?;
+
+        Ok(input_share.payload)
+    }
+
+    /// Compute the initial Prepare step from the decrypted input share of a report (see
+    /// [`Self::decrypt_report_share`]). This is CPU-bound and does not block on I/O.
+    fn prep_init_report_share(
+        &self,
+        is_leader: bool,
+        task_config: &DapTaskConfig,
+        metadata: &ReportMetadata,
+        public_share: &[u8],
+        input_share: &[u8],
+    ) -> Result<(VdafState, VdafMessage), DapError> {
         let agg_id = usize::from(!is_leader);
         match (self, &task_config.vdaf_verify_key) {
             (Self::Prio3(ref prio3_config), VdafVerifyKey::Prio3(ref verify_key)) => {
@@ -397,9 +884,10 @@ impl VdafConfig {
                     agg_id,
                     &metadata.id.0,
                     public_share,
-                    &input_share.payload,
+                    input_share,
                 )?)
             }
+            #[cfg(feature = "prio2")]
             (Self::Prio2 { dimension }, VdafVerifyKey::Prio2(ref verify_key)) => {
                 Ok(prio2_prepare_init(
                     *dimension,
@@ -407,7 +895,7 @@ impl VdafConfig {
                     agg_id,
                     &metadata.id.0,
                     public_share,
-                    &input_share.payload,
+                    input_share,
                 )?)
             }
             _ => Err(DapError::fatal("VDAF verify key does not match config")),
@@ -425,6 +913,8 @@ impl VdafConfig {
     ///
     /// * `decrypter` is used to decrypt the Leader's report shares.
     ///
+    /// * `extensions` is used to check the extensions carried by the reports.
+    ///
     /// * `verify_key` is the secret VDAF verification key shared by the Aggregators.
     ///
     /// * `task_id` indicates the DAP task for which the set of reports are being aggregated.
@@ -436,6 +926,7 @@ impl VdafConfig {
     pub(crate) async fn produce_agg_job_init_req(
         &self,
         decrypter: &impl HpkeDecrypter<'_>,
+        extensions: &DapExtensionRegistry,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
         agg_job_id: &MetaAggregationJobId<'_>,
@@ -463,6 +954,7 @@ impl VdafConfig {
             match self
                 .consume_report_share(
                     decrypter,
+                    extensions,
                     true, // is_leader
                     task_id,
                     task_config,
@@ -487,9 +979,11 @@ impl VdafConfig {
                 }
 
                 // Skip report that can't be processed any further.
-                Err(DapError::Transition(failure)) => {
-                    metrics.report_inc_by(&format!("rejected_{failure}"), 1)
-                }
+                Err(DapError::Transition(failure)) => metrics.report_rejected(
+                    &report.report_metadata.id,
+                    report.report_metadata.time,
+                    failure,
+                ),
 
                 Err(e) => return Err(DapAbort::Internal(Box::new(e))),
             };
@@ -499,6 +993,16 @@ impl VdafConfig {
             return Ok(DapLeaderTransition::Skip);
         }
 
+        // In draft06, the Leader sends its prep share along with each report share.
+        let draft06_leader_messages = (task_config.version == DapVersion::Draft06).then(|| {
+            states
+                .iter()
+                .map(|(_, message, _, _)| PingPongMessage::Initialize {
+                    prep_share: self.encode_prepare_message(message),
+                })
+                .collect()
+        });
+
         Ok(DapLeaderTransition::Continue(
             DapLeaderState { seq: states },
             AggregationJobInitReq {
@@ -507,6 +1011,7 @@ impl VdafConfig {
                 agg_param: Vec::default(),
                 part_batch_sel: part_batch_sel.clone(),
                 report_shares: seq,
+                draft06_leader_messages,
             },
         ))
     }
@@ -515,6 +1020,10 @@ impl VdafConfig {
     /// for the aggregation flow and the aggregate response to send to the Leader.  This method is
     /// run by the Helper.
     ///
+    /// In draft06, the request carries the Leader's prep shares, so the Helper finishes
+    /// preparation right away: The outputs are the Helper's output shares, one for each report
+    /// that is not rejected, and the final aggregate response.
+    ///
     /// Note: The helper state parameter of the aggregate response is left empty. The caller may
     /// wish to encrypt the state and insert it into the aggregate response structure.
     ///
@@ -525,62 +1034,137 @@ impl VdafConfig {
     ///
     /// * `decrypter` is used to decrypt the Helper's report shares.
     ///
+    /// * `extensions` is used to check the extensions carried by the reports.
+    ///
     /// * `verify_key` is the secret VDAF verification key shared by the Aggregators.
     ///
     /// * `task_id` indicates the DAP task for which the reports are being processed.
     ///
     /// * `agg_job_init_req` is the request sent by the Leader.
     ///
+    /// * `concurrency` is the maximum number of reports that are prepared concurrently. Up to this
+    ///   many input shares are decrypted at a time, so that waiting on I/O (e.g., to read an HPKE
+    ///   receiver config) overlaps. The reports are then prepared in parallel on up to this many
+    ///   threads, except in WebAssembly, where they are prepared one at a time. The transitions are
+    ///   in the order of the request regardless.
+    ///
     /// * `version` is the DapVersion to use.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn handle_agg_job_init_req(
         &self,
         decrypter: &impl HpkeDecrypter<'_>,
+        extensions: &DapExtensionRegistry,
         task_id: &TaskId,
         task_config: &DapTaskConfig,
         agg_job_init_req: &AggregationJobInitReq,
+        concurrency: usize,
         metrics: &ContextualizedDaphneMetrics<'_>,
     ) -> Result<DapHelperTransition<AggregationJobResp>, DapAbort> {
         let num_reports = agg_job_init_req.report_shares.len();
+        let leader_messages = match (
+            task_config.version,
+            &agg_job_init_req.draft06_leader_messages,
+        ) {
+            (DapVersion::Draft06, Some(leader_messages))
+                if leader_messages.len() == num_reports =>
+            {
+                Some(leader_messages)
+            }
+            (DapVersion::Draft06, _) => return Err(DapAbort::UnrecognizedMessage),
+            _ => None,
+        };
+
+        // Check the request before any report is prepared.
         let mut processed = HashSet::with_capacity(num_reports);
-        let mut states = Vec::with_capacity(num_reports);
-        let mut transitions = Vec::with_capacity(num_reports);
-        for report_share in agg_job_init_req.report_shares.iter() {
+        let mut prep_inputs = Vec::with_capacity(num_reports);
+        for (i, report_share) in agg_job_init_req.report_shares.iter().enumerate() {
             if processed.contains(&report_share.report_metadata.id) {
                 return Err(DapAbort::UnrecognizedMessage);
             }
             processed.insert(report_share.report_metadata.id.clone());
 
-            let var = match self
-                .consume_report_share(
-                    decrypter,
-                    false, // is_leader
-                    task_id,
-                    task_config,
-                    &report_share.report_metadata,
-                    &report_share.public_share,
-                    &report_share.encrypted_input_share,
-                )
-                .await
-            {
-                Ok((step, message)) => {
-                    let message_data = match self {
-                        Self::Prio3(..) => prio3_encode_prepare_message(&message),
-                        Self::Prio2 { .. } => prio2_encode_prepare_message(&message),
-                    };
+            let leader_share = match leader_messages.map(|leader_messages| &leader_messages[i]) {
+                Some(PingPongMessage::Initialize { prep_share }) => Some(prep_share.as_slice()),
+                Some(_) => return Err(DapAbort::UnrecognizedMessage),
+                None => None,
+            };
+            prep_inputs.push((report_share, leader_share));
+        }
+
+        // Decrypt the input shares, keeping up to `concurrency` reports in flight: as soon as one
+        // input share is decrypted, the next one is started.
+        let concurrency = concurrency.max(1);
+        let input_shares: Vec<Result<Vec<u8>, TransitionFailure>> = stream::iter(
+            prep_inputs
+                .iter()
+                .map(|(report_share, _leader_share)| async move {
+                    match self
+                        .decrypt_report_share(
+                            decrypter,
+                            extensions,
+                            false, // is_leader
+                            task_id,
+                            task_config,
+                            &report_share.report_metadata,
+                            &report_share.public_share,
+                            &report_share.encrypted_input_share,
+                        )
+                        .await
+                    {
+                        Ok(input_share) => Ok(Ok(input_share)),
+                        Err(DapError::Transition(failure)) => Ok(Err(failure)),
+                        Err(e) => Err(DapAbort::Internal(Box::new(e))),
+                    }
+                }),
+        )
+        .buffered(concurrency)
+        .try_collect()
+        .await?;
+
+        // Prepare the reports. This is CPU-bound, so the reports are split among up to
+        // `concurrency` threads.
+        let prep_inputs = prep_inputs
+            .into_iter()
+            .zip(input_shares)
+            .map(|((report_share, leader_share), input_share)| {
+                (report_share, leader_share, input_share)
+            })
+            .collect::<Vec<_>>();
+        let outcomes = par_map(
+            &prep_inputs,
+            concurrency,
+            |(report_share, leader_share, input_share)| {
+                self.helper_prep_report(task_config, report_share, *leader_share, input_share)
+            },
+        )
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let mut states = Vec::with_capacity(num_reports);
+        let mut out_shares = Vec::with_capacity(num_reports);
+        let mut transitions = Vec::with_capacity(num_reports);
+        for (report_share, outcome) in agg_job_init_req.report_shares.iter().zip(outcomes) {
+            let var = match outcome {
+                HelperPrepOutcome::Finished(out_share, prep_msg) => {
+                    out_shares.push(out_share);
+                    TransitionVar::Continued(PingPongMessage::Finish { prep_msg }.get_encoded())
+                }
+                HelperPrepOutcome::Continued(step, message) => {
                     states.push((
                         step,
                         report_share.report_metadata.time,
                         report_share.report_metadata.id.clone(),
                     ));
-                    TransitionVar::Continued(message_data)
+                    TransitionVar::Continued(self.encode_prepare_message(&message))
                 }
-
-                Err(DapError::Transition(failure)) => {
-                    metrics.report_inc_by(&format!("rejected_{failure}"), 1);
+                HelperPrepOutcome::Failed(failure) => {
+                    metrics.report_rejected(
+                        &report_share.report_metadata.id,
+                        report_share.report_metadata.time,
+                        failure,
+                    );
                     TransitionVar::Failed(failure)
                 }
-
-                Err(e) => return Err(DapAbort::Internal(Box::new(e))),
             };
 
             transitions.push(Transition {
@@ -589,6 +1173,13 @@ impl VdafConfig {
             });
         }
 
+        if leader_messages.is_some() {
+            return Ok(DapHelperTransition::Finish(
+                out_shares,
+                AggregationJobResp { transitions },
+            ));
+        }
+
         Ok(DapHelperTransition::Continue(
             DapHelperState {
                 part_batch_sel: agg_job_init_req.part_batch_sel.clone(),
@@ -598,8 +1189,68 @@ impl VdafConfig {
         ))
     }
 
+    /// Prepare a report on behalf of the Helper (see [`Self::handle_agg_job_init_req`]), given
+    /// its decrypted input share. If the Leader's prep share is given (draft06), then preparation
+    /// is finished.
+    fn helper_prep_report(
+        &self,
+        task_config: &DapTaskConfig,
+        report_share: &ReportShare,
+        leader_share: Option<&[u8]>,
+        input_share: &Result<Vec<u8>, TransitionFailure>,
+    ) -> Result<HelperPrepOutcome, DapAbort> {
+        let input_share = match input_share {
+            Ok(input_share) => input_share,
+            Err(failure) => return Ok(HelperPrepOutcome::Failed(*failure)),
+        };
+        let (step, message) = match self.prep_init_report_share(
+            false, // is_leader
+            task_config,
+            &report_share.report_metadata,
+            &report_share.public_share,
+            input_share,
+        ) {
+            Ok(prep) => prep,
+            Err(DapError::Transition(failure)) => return Ok(HelperPrepOutcome::Failed(failure)),
+            Err(e) => return Err(DapAbort::Internal(Box::new(e))),
+        };
+
+        // Combine the prep shares and compute the Helper's output share.
+        let leader_share = match leader_share {
+            Some(leader_share) => leader_share,
+            None => return Ok(HelperPrepOutcome::Continued(step, message)),
+        };
+        let res = match self {
+            Self::Prio3(prio3_config) => {
+                prio3_prepare_finish_from_shares(prio3_config, 1, step, message, leader_share)
+            }
+            #[cfg(feature = "prio2")]
+            Self::Prio2 { dimension } => {
+                prio2_prepare_finish_from_shares(*dimension, 1, step, message, leader_share)
+            }
+        };
+
+        match res {
+            Ok((data, prep_msg)) => Ok(HelperPrepOutcome::Finished(
+                DapOutputShare {
+                    time: report_share.report_metadata.time,
+                    checksum: report_id_checksum(&report_share.report_metadata.id),
+                    data,
+                },
+                prep_msg,
+            )),
+
+            Err(VdafError::Codec(..)) | Err(VdafError::Vdaf(..)) => {
+                Ok(HelperPrepOutcome::Failed(TransitionFailure::VdafPrepError))
+            }
+        }
+    }
+
     /// Handle an aggregate response from the Helper. This method is run by the Leader.
     ///
+    /// In draft06, the response carries the prep message for each report, so the Leader finishes
+    /// preparation and the output is its output shares.
+    ///
     /// Note: This method does not compute the message authentication tag. It is up to the caller
     /// to do so.
     ///
@@ -625,6 +1276,7 @@ impl VdafConfig {
 
         let mut seq = Vec::with_capacity(state.seq.len());
         let mut states = Vec::with_capacity(state.seq.len());
+        let mut out_shares = Vec::with_capacity(state.seq.len());
         for (helper, (leader_step, leader_message, leader_time, leader_report_id)) in agg_job_resp
             .transitions
             .into_iter()
@@ -640,7 +1292,7 @@ impl VdafConfig {
 
                 // Skip report that can't be processed any further.
                 TransitionVar::Failed(failure) => {
-                    metrics.report_inc_by(&format!("rejected_{failure}"), 1);
+                    metrics.report_rejected(&leader_report_id, leader_time, *failure);
                     continue;
                 }
 
@@ -648,15 +1300,52 @@ impl VdafConfig {
                 TransitionVar::Finished => return Err(DapAbort::UnrecognizedMessage),
             };
 
+            // In draft06, the Helper has already combined the prep shares, so compute the
+            // Leader's output share from the prep message.
+            if version == DapVersion::Draft06 {
+                let prep_msg = match PingPongMessage::get_decoded(helper_message)? {
+                    PingPongMessage::Finish { prep_msg } => prep_msg,
+                    _ => return Err(DapAbort::UnrecognizedMessage),
+                };
+
+                let res = match self {
+                    Self::Prio3(prio3_config) => {
+                        prio3_prepare_finish(prio3_config, leader_step, &prep_msg)
+                    }
+                    #[cfg(feature = "prio2")]
+                    Self::Prio2 { dimension } => {
+                        prio2_prepare_finish(*dimension, leader_step, &prep_msg)
+                    }
+                };
+
+                match res {
+                    Ok(data) => out_shares.push(DapOutputShare {
+                        time: leader_time,
+                        checksum: report_id_checksum(&leader_report_id),
+                        data,
+                    }),
+
+                    // Skip report that can't be processed any further.
+                    Err(VdafError::Codec(..)) | Err(VdafError::Vdaf(..)) => {
+                        let failure = TransitionFailure::VdafPrepError;
+                        metrics.report_rejected(&leader_report_id, leader_time, failure);
+                    }
+                };
+                continue;
+            }
+
             let res = match self {
-                Self::Prio3(prio3_config) => prio3_leader_prepare_finish(
+                Self::Prio3(prio3_config) => prio3_prepare_finish_from_shares(
                     prio3_config,
+                    0,
                     leader_step,
                     leader_message,
                     helper_message,
                 ),
-                Self::Prio2 { dimension } => prio2_leader_prepare_finish(
+                #[cfg(feature = "prio2")]
+                Self::Prio2 { dimension } => prio2_prepare_finish_from_shares(
                     *dimension,
+                    0,
                     leader_step,
                     leader_message,
                     helper_message,
@@ -665,15 +1354,10 @@ impl VdafConfig {
 
             match res {
                 Ok((data, message)) => {
-                    let checksum = ring::digest::digest(
-                        &ring::digest::SHA256,
-                        &leader_report_id.get_encoded(),
-                    );
-
                     states.push((
                         DapOutputShare {
                             time: leader_time,
-                            checksum: checksum.as_ref().try_into().unwrap(),
+                            checksum: report_id_checksum(&leader_report_id),
                             data,
                         },
                         leader_report_id.clone(),
@@ -688,15 +1372,19 @@ impl VdafConfig {
                 // Skip report that can't be processed any further.
                 Err(VdafError::Codec(..)) | Err(VdafError::Vdaf(..)) => {
                     let failure = TransitionFailure::VdafPrepError;
-                    metrics.report_inc_by(&format!("rejected_{failure}"), 1);
+                    metrics.report_rejected(&leader_report_id, leader_time, failure);
                 }
             };
         }
 
-        if seq.is_empty() {
+        if seq.is_empty() && out_shares.is_empty() {
             return Ok(DapLeaderTransition::Skip);
         }
 
+        if version == DapVersion::Draft06 {
+            return Ok(DapLeaderTransition::Finish(out_shares));
+        }
+
         Ok(DapLeaderTransition::Uncommitted(
             DapLeaderUncommitted { seq: states },
             AggregationJobContinueReq {
@@ -783,23 +1471,19 @@ impl VdafConfig {
 
                 let res = match self {
                     Self::Prio3(prio3_config) => {
-                        prio3_helper_prepare_finish(prio3_config, helper_step, leader_message)
+                        prio3_prepare_finish(prio3_config, helper_step, leader_message)
                     }
+                    #[cfg(feature = "prio2")]
                     Self::Prio2 { dimension } => {
-                        prio2_helper_prepare_finish(*dimension, helper_step, leader_message)
+                        prio2_prepare_finish(*dimension, helper_step, leader_message)
                     }
                 };
 
                 let var = match res {
                     Ok(data) => {
-                        let checksum = ring::digest::digest(
-                            &ring::digest::SHA256,
-                            &helper_report_id.get_encoded(),
-                        );
-
                         out_shares.push(DapOutputShare {
                             time: helper_time,
-                            checksum: checksum.as_ref().try_into().unwrap(),
+                            checksum: report_id_checksum(&helper_report_id),
                             data,
                         });
                         TransitionVar::Finished
@@ -807,7 +1491,7 @@ impl VdafConfig {
 
                     Err(VdafError::Codec(..)) | Err(VdafError::Vdaf(..)) => {
                         let failure = TransitionFailure::VdafPrepError;
-                        metrics.report_inc_by(&format!("rejected_{failure}"), 1);
+                        metrics.report_rejected(&helper_report_id, helper_time, failure);
                         TransitionVar::Failed(failure)
                     }
                 };
@@ -867,7 +1551,7 @@ impl VdafConfig {
 
                 // Skip report that can't be processed any further.
                 TransitionVar::Failed(failure) => {
-                    metrics.report_inc_by(&format!("rejected_{failure}"), 1);
+                    metrics.report_rejected(&leader_report_id, out_share.time, *failure);
                     continue;
                 }
 
@@ -944,10 +1628,36 @@ impl VdafConfig {
         encrypted_agg_shares: Vec<HpkeCiphertext>,
         version: DapVersion,
     ) -> Result<DapAggregateResult, DapError> {
+        Ok(self
+            .try_consume_encrypted_agg_shares(
+                decrypter,
+                task_id,
+                batch_sel,
+                report_count,
+                encrypted_agg_shares,
+                version,
+            )
+            .await?)
+    }
+
+    /// Same as [`consume_encrypted_agg_shares`](Self::consume_encrypted_agg_shares), except that
+    /// on failure, the error indicates which Aggregator's aggregate share could not be decrypted
+    /// or decoded.
+    pub async fn try_consume_encrypted_agg_shares(
+        &self,
+        decrypter: &impl HpkeDecrypter<'_>,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        report_count: u64,
+        encrypted_agg_shares: Vec<HpkeCiphertext>,
+        version: DapVersion,
+    ) -> Result<DapAggregateResult, DapUnshardError> {
         let agg_share_text = match version {
             DapVersion::Draft02 => CTX_AGG_SHARE_DRAFT02,
             DapVersion::Draft04 => CTX_AGG_SHARE_DRAFT04,
-            _ => return Err(unimplemented_version()),
+            DapVersion::Draft05 => CTX_AGG_SHARE_DRAFT05,
+            DapVersion::Draft06 => CTX_AGG_SHARE_DRAFT06,
+            _ => return Err(DapUnshardError::Other(unimplemented_version())),
         };
         let n: usize = agg_share_text.len();
         let mut info = Vec::new();
@@ -970,28 +1680,36 @@ impl VdafConfig {
 
             let agg_share_data = decrypter
                 .hpke_decrypt(task_id, &info, &aad, agg_share_ciphertext)
-                .await?;
+                .await
+                .map_err(|e| DapUnshardError::Decrypt(agg_share_sender(i), e))?;
             agg_shares.push(agg_share_data);
         }
 
         if agg_shares.len() != encrypted_agg_shares.len() {
-            return Err(DapError::Fatal(
+            return Err(DapUnshardError::Other(DapError::Fatal(
                 "one or more HPKE ciphertexts with unrecognized config ID".into(),
-            ));
+            )));
         }
 
         let num_measurements = usize::try_from(report_count).unwrap();
         match self {
-            Self::Prio3(prio3_config) => {
-                Ok(prio3_unshard(prio3_config, num_measurements, agg_shares)?)
-            }
-            Self::Prio2 { dimension } => {
-                Ok(prio2_unshard(*dimension, num_measurements, agg_shares)?)
-            }
+            Self::Prio3(prio3_config) => prio3_unshard(prio3_config, num_measurements, agg_shares),
+            #[cfg(feature = "prio2")]
+            Self::Prio2 { dimension } => prio2_unshard(*dimension, num_measurements, agg_shares),
         }
     }
 }
 
+/// The Aggregator that sent the aggregate share at the given position of a collection. The first
+/// aggregate share is the Leader's.
+pub(crate) fn agg_share_sender(i: usize) -> DapSender {
+    if i == 0 {
+        DapSender::Leader
+    } else {
+        DapSender::Helper
+    }
+}
+
 fn produce_encrypted_agg_share(
     is_leader: bool,
     hpke_config: &HpkeConfig,
@@ -1009,6 +1727,8 @@ fn produce_encrypted_agg_share(
     let agg_share_text = match version {
         DapVersion::Draft02 => CTX_AGG_SHARE_DRAFT02,
         DapVersion::Draft04 => CTX_AGG_SHARE_DRAFT04,
+        DapVersion::Draft05 => CTX_AGG_SHARE_DRAFT05,
+        DapVersion::Draft06 => CTX_AGG_SHARE_DRAFT06,
         _ => return Err(unimplemented_version_abort()),
     };
     let n: usize = agg_share_text.len();
@@ -1037,10 +1757,14 @@ fn produce_encrypted_agg_share(
     })
 }
 
+pub mod dp;
+#[cfg(test)]
+mod dp_test;
 #[cfg(test)]
 mod mod_test;
+#[cfg(feature = "prio2")]
 pub mod prio2;
-#[cfg(test)]
+#[cfg(all(test, feature = "prio2"))]
 mod prio2_test;
 pub mod prio3;
 #[cfg(test)]
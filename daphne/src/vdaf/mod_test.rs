@@ -3,21 +3,25 @@
 
 use crate::{
     assert_metrics_include, assert_metrics_include_auxiliary_function, async_test_version,
-    async_test_versions,
-    hpke::HpkeReceiverConfig,
+    async_test_versions, async_test_versions_multi_round,
+    extensions::{DapExtensionHandler, DapExtensionRegistry, DapReportExtensions},
+    hpke::{HpkeDecrypter, HpkeReceiverConfig},
     messages::{
         AggregationJobContinueReq, AggregationJobInitReq, AggregationJobResp, BatchSelector,
-        HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, Interval,
-        PartialBatchSelector, Report, ReportId, ReportShare, TaskId, Time, Transition,
-        TransitionFailure, TransitionVar,
+        Extension, HpkeAeadId, HpkeCiphertext, HpkeConfig, HpkeKdfId, HpkeKemId, Interval,
+        PartialBatchSelector, PingPongMessage, Report, ReportId, ReportMetadata, ReportShare,
+        TaskId, Time, Transition, TransitionFailure, TransitionVar,
     },
-    metrics::DaphneMetrics,
-    test_version, test_versions, DapAbort, DapAggregateResult, DapAggregateShare, DapError,
-    DapHelperState, DapHelperTransition, DapLeaderState, DapLeaderTransition, DapLeaderUncommitted,
-    DapMeasurement, DapOutputShare, DapQueryConfig, DapTaskConfig, DapVersion,
-    MetaAggregationJobId, Prio3Config, VdafAggregateShare, VdafConfig, VdafMessage, VdafState,
+    metrics::{DaphneMetrics, DaphneMetricsBuckets},
+    test_version, test_versions,
+    vdaf::supported_vdafs,
+    DapAbort, DapAggregateResult, DapAggregateShare, DapDpConfig, DapError, DapHelperState,
+    DapHelperTransition, DapLeaderState, DapLeaderTransition, DapLeaderUncommitted, DapMeasurement,
+    DapOutputShare, DapQueryConfig, DapTaskConfig, DapVersion, MetaAggregationJobId, Prio3Config,
+    Prio3FixedPointBitSize, VdafAggregateShare, VdafConfig, VdafMessage, VdafState,
 };
 use assert_matches::assert_matches;
+use async_trait::async_trait;
 use hpke_rs::HpkePublicKey;
 use paste::paste;
 use prio::{
@@ -28,7 +32,10 @@ use prio::{
     },
 };
 use rand::prelude::*;
-use std::{fmt::Debug, time::SystemTime};
+use std::{
+    fmt::Debug,
+    time::{Instant, SystemTime},
+};
 use url::Url;
 
 impl<M: Debug> DapLeaderTransition<M> {
@@ -49,6 +56,15 @@ impl<M: Debug> DapLeaderTransition<M> {
             }
         }
     }
+
+    pub(crate) fn unwrap_finish(self) -> Vec<DapOutputShare> {
+        match self {
+            DapLeaderTransition::Finish(out_shares) => out_shares,
+            _ => {
+                panic!("unexpected transition: got {:?}", self);
+            }
+        }
+    }
 }
 
 impl<M: Debug> DapHelperTransition<M> {
@@ -69,6 +85,16 @@ impl<M: Debug> DapHelperTransition<M> {
             }
         }
     }
+
+    /// Return the message sent to the Leader, regardless of whether the Helper expects another
+    /// round.
+    pub(crate) fn unwrap_resp(self) -> M {
+        match self {
+            DapHelperTransition::Continue(_, message) | DapHelperTransition::Finish(_, message) => {
+                message
+            }
+        }
+    }
 }
 
 // TODO Exercise all of the Prio3 variants and not just Count.
@@ -91,6 +117,7 @@ async fn roundtrip_report(version: DapVersion) {
     let (leader_step, leader_share) = TEST_VDAF
         .consume_report_share(
             &t.leader_hpke_receiver_config,
+            &t.extension_registry,
             true, // is_leader
             &t.task_id,
             &t.task_config,
@@ -104,6 +131,7 @@ async fn roundtrip_report(version: DapVersion) {
     let (helper_step, helper_share) = TEST_VDAF
         .consume_report_share(
             &t.helper_hpke_receiver_config,
+            &t.extension_registry,
             false, // is_leader
             &t.task_id,
             &t.task_config,
@@ -202,11 +230,17 @@ async fn produce_agg_job_init_req(version: DapVersion) {
         assert_eq!(report_shares.report_metadata.id, report.report_metadata.id);
     }
 
-    let (helper_state, agg_job_resp) = t
-        .handle_agg_job_init_req(agg_job_init_req)
-        .await
-        .unwrap_continue();
-    assert_eq!(helper_state.seq.len(), 3);
+    let agg_job_resp = match t.handle_agg_job_init_req(agg_job_init_req).await {
+        DapHelperTransition::Continue(helper_state, agg_job_resp) => {
+            assert_eq!(helper_state.seq.len(), 3);
+            agg_job_resp
+        }
+        DapHelperTransition::Finish(helper_out_shares, agg_job_resp) => {
+            assert_eq!(version, DapVersion::Draft06);
+            assert_eq!(helper_out_shares.len(), 3);
+            agg_job_resp
+        }
+    };
     assert_eq!(agg_job_resp.transitions.len(), 3);
     for (sub, report) in agg_job_resp.transitions.iter().zip(reports.iter()) {
         assert_eq!(sub.report_id, report.report_metadata.id);
@@ -253,6 +287,209 @@ async fn produce_agg_job_init_req_skip_hpke_unknown_config_id(version: DapVersio
 
 async_test_versions! { produce_agg_job_init_req_skip_hpke_unknown_config_id }
 
+// Extension handler that accepts a report if the payload of its extension is "ok".
+struct TestExtensionHandler;
+
+impl DapExtensionHandler for TestExtensionHandler {
+    fn validate(
+        &self,
+        _task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        _metadata: &ReportMetadata,
+        payload: &[u8],
+    ) -> Result<(), TransitionFailure> {
+        if payload == b"ok" {
+            Ok(())
+        } else {
+            Err(TransitionFailure::ReportDropped)
+        }
+    }
+}
+
+async fn produce_agg_job_init_req_skip_unrecognized_extension(version: DapVersion) {
+    let t = Test::new(TEST_VDAF, version);
+    let reports = vec![t.produce_report_with_extension(b"ok")];
+
+    assert_matches!(
+        t.produce_agg_job_init_req(reports).await,
+        DapLeaderTransition::Skip
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",status="rejected_unrecognized_message"}"#: 1,
+    });
+}
+
+async_test_versions! { produce_agg_job_init_req_skip_unrecognized_extension }
+
+async fn agg_job_init_req_extension_handler(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    t.extension_registry
+        .register(0xfff0, TestExtensionHandler)
+        .unwrap();
+    let reports = vec![
+        t.produce_report_with_extension(b"ok"),
+        t.produce_report_with_extension(b"not ok"),
+    ];
+
+    let (leader_state, agg_job_init_req) = t
+        .produce_agg_job_init_req(reports.clone())
+        .await
+        .unwrap_continue();
+    assert_eq!(leader_state.seq.len(), 1);
+    assert_eq!(
+        agg_job_init_req.report_shares[0].report_metadata.id,
+        reports[0].report_metadata.id
+    );
+
+    let agg_job_resp = t
+        .handle_agg_job_init_req(agg_job_init_req)
+        .await
+        .unwrap_resp();
+    assert_eq!(agg_job_resp.transitions.len(), 1);
+    assert_matches!(
+        agg_job_resp.transitions[0].var,
+        TransitionVar::Continued(..)
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",status="rejected_report_dropped"}"#: 1,
+    });
+}
+
+async_test_versions! { agg_job_init_req_extension_handler }
+
+#[test]
+fn extension_registry_register() {
+    let mut registry = DapExtensionRegistry::default();
+    registry.register(0xfff0, TestExtensionHandler).unwrap();
+
+    // Each extension type has at most one handler.
+    assert!(registry.register(0xfff0, TestExtensionHandler).is_err());
+
+    // Taskprov is handled by Daphne itself.
+    assert!(registry.register(0xff00, TestExtensionHandler).is_err());
+}
+
+#[test]
+fn report_extensions_place() {
+    let ext = |typ: u16| Extension::Unhandled {
+        typ,
+        payload: b"ok".to_vec(),
+    };
+    let extensions = DapReportExtensions {
+        public: vec![ext(0xfff0)],
+        leader: vec![ext(0xfff1)],
+        helper: vec![ext(0xfff2)],
+    };
+
+    // Draft02 only supports public extensions, which are carried in the report metadata.
+    assert!(extensions.place(DapVersion::Draft02).is_err());
+    assert_eq!(
+        DapReportExtensions::from(vec![ext(0xfff0)])
+            .place(DapVersion::Draft02)
+            .unwrap(),
+        (vec![ext(0xfff0)], [vec![], vec![]])
+    );
+
+    // Later versions carry each extension in the plaintext input shares.
+    assert_eq!(
+        extensions.place(DapVersion::Draft04).unwrap(),
+        (
+            vec![],
+            [
+                vec![ext(0xfff0), ext(0xfff1)],
+                vec![ext(0xfff0), ext(0xfff2)]
+            ]
+        )
+    );
+
+    // An Aggregator must not see an extension type more than once.
+    let extensions = DapReportExtensions {
+        public: vec![ext(0xfff0)],
+        leader: vec![],
+        helper: vec![ext(0xfff0)],
+    };
+    assert!(extensions.place(DapVersion::Draft04).is_err());
+}
+
+async fn agg_job_init_req_private_extensions(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    t.extension_registry
+        .register(0xfff0, TestExtensionHandler)
+        .unwrap();
+    let ext = |payload: &[u8]| Extension::Unhandled {
+        typ: 0xfff0,
+        payload: payload.to_vec(),
+    };
+
+    // The Leader accepts its extension, but the Helper rejects its own.
+    let report = t
+        .task_config
+        .vdaf
+        .produce_report_with_extensions(
+            &t.client_hpke_config_list,
+            t.now,
+            &t.task_id,
+            DapMeasurement::U64(1),
+            DapReportExtensions {
+                public: vec![],
+                leader: vec![ext(b"ok")],
+                helper: vec![ext(b"not ok")],
+            },
+            version,
+        )
+        .unwrap();
+
+    let (_, agg_job_init_req) = t
+        .produce_agg_job_init_req(vec![report])
+        .await
+        .unwrap_continue();
+    let agg_job_resp = t
+        .handle_agg_job_init_req(agg_job_init_req)
+        .await
+        .unwrap_resp();
+    assert_eq!(agg_job_resp.transitions.len(), 1);
+    assert_matches!(
+        agg_job_resp.transitions[0].var,
+        TransitionVar::Failed(TransitionFailure::ReportDropped)
+    );
+}
+
+async_test_version! { agg_job_init_req_private_extensions, Draft04 }
+async_test_version! { agg_job_init_req_private_extensions, Draft05 }
+async_test_version! { agg_job_init_req_private_extensions, Draft06 }
+
+async fn produce_agg_job_init_req_skip_misplaced_extension(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    t.extension_registry
+        .register(0xfff0, TestExtensionHandler)
+        .unwrap();
+    let mut reports = t.produce_reports(vec![DapMeasurement::U64(1)]);
+
+    // Starting with draft03, the report metadata has no extensions.
+    reports[0]
+        .report_metadata
+        .extensions
+        .push(Extension::Unhandled {
+            typ: 0xfff0,
+            payload: b"ok".to_vec(),
+        });
+
+    assert_matches!(
+        t.produce_agg_job_init_req(reports).await,
+        DapLeaderTransition::Skip
+    );
+
+    assert_metrics_include!(t.prometheus_registry, {
+        r#"test_leader_report_counter{host="leader.com",status="rejected_unrecognized_message"}"#: 1,
+    });
+}
+
+async_test_version! { produce_agg_job_init_req_skip_misplaced_extension, Draft04 }
+async_test_version! { produce_agg_job_init_req_skip_misplaced_extension, Draft05 }
+async_test_version! { produce_agg_job_init_req_skip_misplaced_extension, Draft06 }
+
 async fn produce_agg_job_init_req_skip_vdaf_prep_error(version: DapVersion) {
     let t = Test::new(TEST_VDAF, version);
     let reports = vec![
@@ -283,7 +520,7 @@ async fn handle_agg_job_init_req_hpke_decrypt_err(version: DapVersion) {
         .produce_agg_job_init_req(reports.clone())
         .await
         .unwrap_continue();
-    let (_, agg_job_resp) = t.handle_agg_job_init_req(agg_req).await.unwrap_continue();
+    let agg_job_resp = t.handle_agg_job_init_req(agg_req).await.unwrap_resp();
 
     assert_eq!(agg_job_resp.transitions.len(), 1);
     assert_matches!(
@@ -309,7 +546,7 @@ async fn handle_agg_job_init_req_hpke_unknown_config_id(version: DapVersion) {
         .produce_agg_job_init_req(reports.clone())
         .await
         .unwrap_continue();
-    let (_, agg_job_resp) = t.handle_agg_job_init_req(agg_req).await.unwrap_continue();
+    let agg_job_resp = t.handle_agg_job_init_req(agg_req).await.unwrap_resp();
 
     assert_eq!(agg_job_resp.transitions.len(), 1);
     assert_matches!(
@@ -348,9 +585,17 @@ async fn handle_agg_job_init_req_vdaf_prep_error(version: DapVersion) {
                 encrypted_input_share: report1.encrypted_input_shares[1].clone(),
             },
         ],
+        draft06_leader_messages: (version == DapVersion::Draft06).then(|| {
+            vec![
+                PingPongMessage::Initialize {
+                    prep_share: Vec::new(),
+                };
+                2
+            ]
+        }),
     };
 
-    let (_, agg_job_resp) = t.handle_agg_job_init_req(agg_req).await.unwrap_continue();
+    let agg_job_resp = t.handle_agg_job_init_req(agg_req).await.unwrap_resp();
 
     assert_eq!(agg_job_resp.transitions.len(), 2);
     assert_matches!(
@@ -369,15 +614,184 @@ async fn handle_agg_job_init_req_vdaf_prep_error(version: DapVersion) {
 
 async_test_versions! { handle_agg_job_init_req_vdaf_prep_error }
 
+async fn handle_agg_job_init_req_concurrent(version: DapVersion) {
+    let mut t = Test::new(TEST_VDAF, version);
+    let mut reports = t.produce_reports((0..5).map(|_| DapMeasurement::U64(1)).collect());
+
+    // Simulate HPKE decryption error of helper's report share.
+    reports[2].encrypted_input_shares[1].payload[0] ^= 1;
+
+    let (_, agg_req) = t
+        .produce_agg_job_init_req(reports.clone())
+        .await
+        .unwrap_continue();
+    let agg_job_resp = t
+        .handle_agg_job_init_req(agg_req.clone())
+        .await
+        .unwrap_resp();
+    assert_eq!(agg_job_resp.transitions.len(), 5);
+    for (report, transition) in reports.iter().zip(agg_job_resp.transitions.iter()) {
+        assert_eq!(transition.report_id, report.report_metadata.id);
+    }
+    assert_matches!(
+        agg_job_resp.transitions[2].var,
+        TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+    );
+
+    // Reports may finish preparation in any order, but the transitions are in the order of the
+    // request regardless of how many reports are prepared concurrently.
+    let decrypter = ShuffledHpkeDecrypter(t.helper_hpke_receiver_config.clone());
+    let metrics = t
+        .helper_metrics
+        .with_host(t.task_config.helper_url.host_str().unwrap());
+    for concurrency in [0, 1, 2, 3, 5, 100] {
+        let resp = t
+            .task_config
+            .vdaf
+            .handle_agg_job_init_req(
+                &decrypter,
+                &t.extension_registry,
+                &t.task_id,
+                &t.task_config,
+                &agg_req,
+                concurrency,
+                &metrics,
+            )
+            .await
+            .unwrap()
+            .unwrap_resp();
+        assert_eq!(resp, agg_job_resp, "concurrency {concurrency}");
+    }
+}
+
+async_test_versions! { handle_agg_job_init_req_concurrent }
+
+/// HPKE decrypter that yields to the executor a number of times determined by the ciphertext
+/// before decrypting it, so that reports prepared concurrently finish out of order.
+struct ShuffledHpkeDecrypter(HpkeReceiverConfig);
+
+#[async_trait(?Send)]
+impl<'a> HpkeDecrypter<'a> for ShuffledHpkeDecrypter {
+    type WrappedHpkeConfig = HpkeConfig;
+
+    async fn get_hpke_config_for(
+        &'a self,
+        _version: DapVersion,
+        _task_id: Option<&TaskId>,
+    ) -> Result<Self::WrappedHpkeConfig, DapError> {
+        Ok(self.0.config.clone())
+    }
+
+    async fn can_hpke_decrypt(&self, task_id: &TaskId, config_id: u8) -> Result<bool, DapError> {
+        self.0.can_hpke_decrypt(task_id, config_id).await
+    }
+
+    async fn hpke_decrypt(
+        &self,
+        task_id: &TaskId,
+        info: &[u8],
+        aad: &[u8],
+        ciphertext: &HpkeCiphertext,
+    ) -> Result<Vec<u8>, DapError> {
+        for _ in 0..ciphertext.payload[0] % 8 {
+            tokio::task::yield_now().await;
+        }
+        self.0.hpke_decrypt(task_id, info, aad, ciphertext).await
+    }
+}
+
+// Benchmark for the Helper's preparation of a 1,000-report aggregation job, sequentially and in
+// parallel on each available thread. Run with
+//
+//     cargo test -p daphne --release handle_agg_job_init_req_throughput -- --ignored --nocapture
+#[tokio::test]
+#[ignore]
+async fn handle_agg_job_init_req_throughput() {
+    let t = Test::new(
+        &VdafConfig::Prio3(Prio3Config::SumVec { bits: 8, len: 100 }),
+        DapVersion::Draft04,
+    );
+    let reports = t.produce_reports(
+        (0..1000)
+            .map(|_| DapMeasurement::U64Vec(vec![1; 100]))
+            .collect(),
+    );
+    let (_, agg_req) = t.produce_agg_job_init_req(reports).await.unwrap_continue();
+    let metrics = t
+        .helper_metrics
+        .with_host(t.task_config.helper_url.host_str().unwrap());
+
+    let threads = std::thread::available_parallelism().map_or(1, usize::from);
+    let mut elapsed = Vec::new();
+    for concurrency in [1, threads] {
+        let start = Instant::now();
+        t.task_config
+            .vdaf
+            .handle_agg_job_init_req(
+                &t.helper_hpke_receiver_config,
+                &t.extension_registry,
+                &t.task_id,
+                &t.task_config,
+                &agg_req,
+                concurrency,
+                &metrics,
+            )
+            .await
+            .unwrap();
+        let duration = start.elapsed();
+        println!(
+            "concurrency {concurrency}: {duration:?} ({:.0} reports/s)",
+            1000.0 / duration.as_secs_f64()
+        );
+        elapsed.push(duration);
+    }
+    if threads > 1 {
+        assert!(elapsed[1] < elapsed[0]);
+    }
+}
+
+async fn handle_agg_job_init_req_abort_missing_leader_messages(version: DapVersion) {
+    let t = Test::new(TEST_VDAF, version);
+    let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(0)]);
+    let (_, mut agg_job_init_req) = t.produce_agg_job_init_req(reports).await.unwrap_continue();
+    let metrics = t
+        .helper_metrics
+        .with_host(t.task_config.helper_url.host_str().unwrap());
+
+    // Leader omits its prep share for one of the reports.
+    agg_job_init_req
+        .draft06_leader_messages
+        .as_mut()
+        .unwrap()
+        .pop();
+    assert_matches!(
+        t.task_config
+            .vdaf
+            .handle_agg_job_init_req(
+                &t.helper_hpke_receiver_config,
+                &t.extension_registry,
+                &t.task_id,
+                &t.task_config,
+                &agg_job_init_req,
+                1,
+                &metrics,
+            )
+            .await,
+        Err(DapAbort::UnrecognizedMessage)
+    );
+}
+
+async_test_version! { handle_agg_job_init_req_abort_missing_leader_messages, Draft06 }
+
 async fn agg_job_resp_abort_transition_out_of_order(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
     let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
     let (leader_state, agg_job_init_req) =
         t.produce_agg_job_init_req(reports).await.unwrap_continue();
-    let (_, mut agg_job_resp) = t
+    let mut agg_job_resp = t
         .handle_agg_job_init_req(agg_job_init_req)
         .await
-        .unwrap_continue();
+        .unwrap_resp();
 
     // Helper sends transitions out of order.
     let tmp = agg_job_resp.transitions[0].clone();
@@ -397,10 +811,10 @@ async fn agg_job_resp_abort_report_id_repeated(version: DapVersion) {
     let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
     let (leader_state, agg_job_init_req) =
         t.produce_agg_job_init_req(reports).await.unwrap_continue();
-    let (_, mut agg_job_resp) = t
+    let mut agg_job_resp = t
         .handle_agg_job_init_req(agg_job_init_req)
         .await
-        .unwrap_continue();
+        .unwrap_resp();
 
     // Helper sends a transition twice.
     let repeated_transition = agg_job_resp.transitions[0].clone();
@@ -420,10 +834,10 @@ async fn agg_job_resp_abort_unrecognized_report_id(version: DapVersion) {
     let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
     let (leader_state, agg_job_init_req) =
         t.produce_agg_job_init_req(reports).await.unwrap_continue();
-    let (_, mut agg_job_resp) = t
+    let mut agg_job_resp = t
         .handle_agg_job_init_req(agg_job_init_req)
         .await
-        .unwrap_continue();
+        .unwrap_resp();
 
     // Helper sent a transition with an unrecognized report ID.
     agg_job_resp.transitions.push(Transition {
@@ -444,10 +858,10 @@ async fn agg_job_resp_abort_invalid_transition(version: DapVersion) {
     let reports = t.produce_reports(vec![DapMeasurement::U64(1)]);
     let (leader_state, agg_job_init_req) =
         t.produce_agg_job_init_req(reports).await.unwrap_continue();
-    let (_, mut agg_job_resp) = t
+    let mut agg_job_resp = t
         .handle_agg_job_init_req(agg_job_init_req)
         .await
-        .unwrap_continue();
+        .unwrap_resp();
 
     // Helper sent a transition with an unrecognized report ID.
     agg_job_resp.transitions[0].var = TransitionVar::Finished;
@@ -522,7 +936,7 @@ async fn agg_job_cont_req(version: DapVersion) {
     );
 }
 
-async_test_versions! { agg_job_cont_req }
+async_test_versions_multi_round! { agg_job_cont_req }
 
 async fn agg_job_cont_req_skip_vdaf_prep_error(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
@@ -563,7 +977,7 @@ async fn agg_job_cont_req_skip_vdaf_prep_error(version: DapVersion) {
     });
 }
 
-async_test_versions! { agg_job_cont_req_skip_vdaf_prep_error }
+async_test_versions_multi_round! { agg_job_cont_req_skip_vdaf_prep_error }
 
 async fn agg_cont_abort_unrecognized_report_id(version: DapVersion) {
     let mut rng = thread_rng();
@@ -594,7 +1008,7 @@ async fn agg_cont_abort_unrecognized_report_id(version: DapVersion) {
     );
 }
 
-async_test_versions! { agg_cont_abort_unrecognized_report_id }
+async_test_versions_multi_round! { agg_cont_abort_unrecognized_report_id }
 
 async fn agg_job_cont_req_abort_transition_out_of_order(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
@@ -620,7 +1034,7 @@ async fn agg_job_cont_req_abort_transition_out_of_order(version: DapVersion) {
     );
 }
 
-async_test_versions! { agg_job_cont_req_abort_transition_out_of_order }
+async_test_versions_multi_round! { agg_job_cont_req_abort_transition_out_of_order }
 
 async fn agg_job_cont_req_abort_report_id_repeated(version: DapVersion) {
     let mut t = Test::new(TEST_VDAF, version);
@@ -645,7 +1059,7 @@ async fn agg_job_cont_req_abort_report_id_repeated(version: DapVersion) {
     );
 }
 
-async_test_versions! { agg_job_cont_req_abort_report_id_repeated }
+async_test_versions_multi_round! { agg_job_cont_req_abort_report_id_repeated }
 
 async fn encrypted_agg_share(version: DapVersion) {
     let t = Test::new(TEST_VDAF, version);
@@ -713,7 +1127,7 @@ async fn helper_state_serialization(version: DapVersion) {
     assert!(DapHelperState::get_decoded(TEST_VDAF, b"invalid helper state").is_err())
 }
 
-async_test_versions! { helper_state_serialization }
+async_test_versions_multi_round! { helper_state_serialization }
 
 pub(crate) struct Test {
     now: Time,
@@ -727,6 +1141,7 @@ pub(crate) struct Test {
     prometheus_registry: prometheus::Registry,
     leader_metrics: DaphneMetrics,
     helper_metrics: DaphneMetrics,
+    extension_registry: DapExtensionRegistry,
 }
 
 impl Test {
@@ -749,10 +1164,18 @@ impl Test {
         let helper_hpke_config = helper_hpke_receiver_config.clone().config;
         let collector_hpke_config = collector_hpke_receiver_config.clone().config;
         let prometheus_registry = prometheus::Registry::new();
-        let leader_metrics =
-            DaphneMetrics::register(&prometheus_registry, Some("test_leader")).unwrap();
-        let helper_metrics =
-            DaphneMetrics::register(&prometheus_registry, Some("test_helper")).unwrap();
+        let leader_metrics = DaphneMetrics::register(
+            &prometheus_registry,
+            Some("test_leader"),
+            &DaphneMetricsBuckets::default(),
+        )
+        .unwrap();
+        let helper_metrics = DaphneMetrics::register(
+            &prometheus_registry,
+            Some("test_helper"),
+            &DaphneMetricsBuckets::default(),
+        )
+        .unwrap();
 
         Test {
             now,
@@ -769,14 +1192,22 @@ impl Test {
                 time_precision: 500,
                 expiration: now + 500,
                 min_batch_size: 10,
+                max_batch_query_count: 1,
                 query: DapQueryConfig::TimeInterval,
                 vdaf: vdaf.clone(),
                 vdaf_verify_key,
                 collector_hpke_config,
+                additional_collectors: Vec::new(),
+                dp: DapDpConfig::None,
+                taskprov_advertisement: None,
+                replay_filter: None,
+                vdaf_verify_key_rotation: None,
+                upload_auth: None,
             },
             prometheus_registry,
             leader_metrics,
             helper_metrics,
+            extension_registry: DapExtensionRegistry::default(),
         }
     }
 
@@ -800,6 +1231,24 @@ impl Test {
         reports
     }
 
+    // Produce a report carrying an extension of type 0xfff0 with the given payload.
+    fn produce_report_with_extension(&self, payload: &[u8]) -> Report {
+        self.task_config
+            .vdaf
+            .produce_report_with_extensions(
+                &self.client_hpke_config_list,
+                self.now,
+                &self.task_id,
+                DapMeasurement::U64(1),
+                vec![Extension::Unhandled {
+                    typ: 0xfff0,
+                    payload: payload.to_vec(),
+                }],
+                self.task_config.version,
+            )
+            .unwrap()
+    }
+
     // Tweak the Helper's share so that decoding succeeds but preparation fails.
     fn produce_invalid_report_vdaf_prep_failure(
         &self,
@@ -822,7 +1271,7 @@ impl Test {
                 self.now,
                 &self.task_id,
                 &report_id,
-                Vec::new(), // extensions
+                &DapReportExtensions::default(),
                 version,
             )
             .unwrap()
@@ -850,7 +1299,7 @@ impl Test {
                 self.now,
                 &self.task_id,
                 &report_id,
-                Vec::new(), // extensions
+                &DapReportExtensions::default(),
                 version,
             )
             .unwrap()
@@ -879,7 +1328,7 @@ impl Test {
                 self.now,
                 &self.task_id,
                 &report_id,
-                Vec::new(), // extensions
+                &DapReportExtensions::default(),
                 version,
             )
             .unwrap()
@@ -896,6 +1345,7 @@ impl Test {
             .vdaf
             .produce_agg_job_init_req(
                 &self.leader_hpke_receiver_config,
+                &self.extension_registry,
                 &self.task_id,
                 &self.task_config,
                 &self.agg_job_id,
@@ -918,9 +1368,11 @@ impl Test {
             .vdaf
             .handle_agg_job_init_req(
                 &self.helper_hpke_receiver_config,
+                &self.extension_registry,
                 &self.task_id,
                 &self.task_config,
                 &agg_job_init_req,
+                1,
                 &metrics,
             )
             .await
@@ -1096,26 +1548,35 @@ impl Test {
             .produce_agg_job_init_req(reports)
             .await
             .unwrap_continue();
-        let (helper_state, agg_job_resp) = self
-            .handle_agg_job_init_req(agg_init)
-            .await
-            .unwrap_continue();
-        let got = DapHelperState::get_decoded(
-            &self.task_config.vdaf,
-            &helper_state
-                .get_encoded(&self.task_config.vdaf)
-                .expect("failed to encode helper state"),
-        )
-        .expect("failed to decode helper state");
-        assert_eq!(got, helper_state);
-
-        let (uncommitted, agg_cont) = self
-            .handle_agg_job_resp(leader_state, agg_job_resp)
-            .unwrap_uncommitted();
-        let (helper_out_shares, agg_job_resp) = self
-            .handle_agg_job_cont_req(helper_state, &agg_cont)
-            .unwrap_finish();
-        let leader_out_shares = self.handle_final_agg_job_resp(uncommitted, agg_job_resp);
+        let (leader_out_shares, helper_out_shares) =
+            match self.handle_agg_job_init_req(agg_init).await {
+                DapHelperTransition::Continue(helper_state, agg_job_resp) => {
+                    let got = DapHelperState::get_decoded(
+                        &self.task_config.vdaf,
+                        &helper_state
+                            .get_encoded(&self.task_config.vdaf)
+                            .expect("failed to encode helper state"),
+                    )
+                    .expect("failed to decode helper state");
+                    assert_eq!(got, helper_state);
+
+                    let (uncommitted, agg_cont) = self
+                        .handle_agg_job_resp(leader_state, agg_job_resp)
+                        .unwrap_uncommitted();
+                    let (helper_out_shares, agg_job_resp) = self
+                        .handle_agg_job_cont_req(helper_state, &agg_cont)
+                        .unwrap_finish();
+                    let leader_out_shares =
+                        self.handle_final_agg_job_resp(uncommitted, agg_job_resp);
+                    (leader_out_shares, helper_out_shares)
+                }
+                DapHelperTransition::Finish(helper_out_shares, agg_job_resp) => {
+                    let leader_out_shares = self
+                        .handle_agg_job_resp(leader_state, agg_job_resp)
+                        .unwrap_finish();
+                    (leader_out_shares, helper_out_shares)
+                }
+            };
         let report_count = u64::try_from(leader_out_shares.len()).unwrap();
 
         // Leader: Aggregation
@@ -1137,3 +1598,116 @@ impl Test {
         .await
     }
 }
+
+#[test]
+fn supported_vdafs_check_params() {
+    for vdaf in [
+        VdafConfig::Prio3(Prio3Config::Count),
+        VdafConfig::Prio3(Prio3Config::Sum { bits: 64 }),
+        VdafConfig::Prio3(Prio3Config::Histogram {
+            buckets: vec![0, 1, 2],
+        }),
+        VdafConfig::Prio3(Prio3Config::SumVec { bits: 8, len: 10 }),
+        VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+            bitsize: Prio3FixedPointBitSize::Fixed16,
+            len: 10,
+        }),
+        VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+            bitsize: Prio3FixedPointBitSize::Fixed32,
+            len: 10,
+        }),
+        #[cfg(feature = "prio2")]
+        VdafConfig::Prio2 { dimension: 10 },
+    ] {
+        assert!(supported_vdafs()
+            .iter()
+            .any(|descriptor| descriptor.name == vdaf.name()));
+        vdaf.check_params().unwrap();
+    }
+
+    assert_matches!(
+        VdafConfig::Prio3(Prio3Config::Sum { bits: 65 }).check_params(),
+        Err(DapError::Fatal(..))
+    );
+    assert_matches!(
+        VdafConfig::Prio3(Prio3Config::Histogram { buckets: vec![] }).check_params(),
+        Err(DapError::Fatal(..))
+    );
+    assert_matches!(
+        VdafConfig::Prio3(Prio3Config::SumVec { bits: 8, len: 0 }).check_params(),
+        Err(DapError::Fatal(..))
+    );
+    assert_matches!(
+        VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+            bitsize: Prio3FixedPointBitSize::Fixed32,
+            len: 0,
+        })
+        .check_params(),
+        Err(DapError::Fatal(..))
+    );
+    #[cfg(feature = "prio2")]
+    assert_matches!(
+        VdafConfig::Prio2 { dimension: 0 }.check_params(),
+        Err(DapError::Fatal(..))
+    );
+}
+
+// Test that the length of the Helper's state for an aggregation job is known before the reports
+// are prepared.
+async fn helper_state_encoded_len(version: DapVersion) {
+    for (vdaf, measurement) in [
+        (
+            VdafConfig::Prio3(Prio3Config::Count),
+            DapMeasurement::U64(1),
+        ),
+        (
+            VdafConfig::Prio3(Prio3Config::Sum { bits: 64 }),
+            DapMeasurement::U64(1337),
+        ),
+        (
+            VdafConfig::Prio3(Prio3Config::Histogram {
+                buckets: vec![0, 1, 2],
+            }),
+            DapMeasurement::U64(1),
+        ),
+        (
+            VdafConfig::Prio3(Prio3Config::SumVec { bits: 8, len: 3 }),
+            DapMeasurement::U64Vec(vec![1, 2, 3]),
+        ),
+        (
+            VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+                bitsize: Prio3FixedPointBitSize::Fixed16,
+                len: 3,
+            }),
+            DapMeasurement::F64Vec(vec![0.5, -0.25, 0.0]),
+        ),
+        (
+            VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+                bitsize: Prio3FixedPointBitSize::Fixed32,
+                len: 3,
+            }),
+            DapMeasurement::F64Vec(vec![0.5, -0.25, 0.0]),
+        ),
+        #[cfg(feature = "prio2")]
+        (
+            VdafConfig::Prio2 { dimension: 3 },
+            DapMeasurement::U32Vec(vec![1, 0, 1]),
+        ),
+    ] {
+        let mut t = Test::new(&vdaf, version);
+        let reports = t.produce_reports(vec![measurement.clone(), measurement]);
+        let (_, agg_job_init_req) = t.produce_agg_job_init_req(reports).await.unwrap_continue();
+        let (helper_state, _) = t
+            .handle_agg_job_init_req(agg_job_init_req)
+            .await
+            .unwrap_continue();
+        assert_eq!(
+            helper_state.get_encoded(&vdaf).unwrap().len(),
+            DapHelperState::encoded_len(&vdaf, &helper_state.part_batch_sel, 2),
+            "{}",
+            vdaf.name()
+        );
+    }
+}
+
+async_test_versions_multi_round! { helper_state_encoded_len }
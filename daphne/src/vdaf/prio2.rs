@@ -5,7 +5,8 @@
 //! [VDAF](https://datatracker.ietf.org/doc/draft-patton-cfrg-vdaf/).
 
 use crate::{
-    vdaf::VdafError, DapAggregateResult, DapMeasurement, VdafAggregateShare, VdafMessage, VdafState,
+    vdaf::{agg_share_sender, DapUnshardError, VdafError},
+    DapAggregateResult, DapMeasurement, VdafAggregateShare, VdafMessage, VdafState,
 };
 use prio::{
     codec::{Decode, Encode, ParameterizedDecode},
@@ -56,52 +57,63 @@ pub(crate) fn prio2_prepare_init(
 }
 
 /// Consume the verifier shares and return the output share and serialized outbound message.
-pub(crate) fn prio2_leader_prepare_finish(
+/// `agg_id` is the aggregator ID of the caller, i.e., `0` for the Leader and `1` for the Helper.
+pub(crate) fn prio2_prepare_finish_from_shares(
     dimension: usize,
-    leader_state: VdafState,
-    leader_share: VdafMessage,
-    helper_share_data: &[u8],
+    agg_id: usize,
+    state: VdafState,
+    share: VdafMessage,
+    peer_share_data: &[u8],
 ) -> Result<(VdafAggregateShare, Vec<u8>), VdafError> {
     let vdaf = Prio2::new(dimension)?;
-    let (out_share, outbound) = match (leader_state, leader_share) {
+    let (out_share, outbound) = match (state, share) {
         (VdafState::Prio2(state), VdafMessage::Prio2Share(share)) => {
-            let helper_share =
-                Prio2PrepareShare::get_decoded_with_param(&state, helper_share_data)?;
-            vdaf.prepare_preprocess([share, helper_share])?;
+            let peer_share = Prio2PrepareShare::get_decoded_with_param(&state, peer_share_data)?;
+            let shares = if agg_id == 0 {
+                [share, peer_share]
+            } else {
+                [peer_share, share]
+            };
+            vdaf.prepare_preprocess(shares)?;
             match vdaf.prepare_step(state, ())? {
                 PrepareTransition::Continue(..) => {
-                    panic!("prio2_leader_prepare_finish: unexpected transition (continued)")
+                    panic!("prio2_prepare_finish_from_shares: unexpected transition (continued)")
                 }
                 PrepareTransition::Finish(out_share) => (out_share, Vec::new()),
             }
         }
-        _ => panic!("prio2_leader_preapre_finish: leader state does not match share"),
+        _ => panic!("prio2_prepare_finish_from_shares: state does not match share"),
     };
     let agg_share = VdafAggregateShare::FieldPrio2(vdaf.aggregate(&(), [out_share])?);
     Ok((agg_share, outbound))
 }
 
 /// Consume the peer's prepare message and return an output share.
-pub(crate) fn prio2_helper_prepare_finish(
+pub(crate) fn prio2_prepare_finish(
     dimension: usize,
-    helper_state: VdafState,
-    leader_message_data: &[u8],
+    state: VdafState,
+    peer_message_data: &[u8],
 ) -> Result<VdafAggregateShare, VdafError> {
     let vdaf = Prio2::new(dimension)?;
-    <()>::get_decoded(leader_message_data)?;
-    let out_share = match helper_state {
+    <()>::get_decoded(peer_message_data)?;
+    let out_share = match state {
         VdafState::Prio2(state) => match vdaf.prepare_step(state, ())? {
             PrepareTransition::Continue(..) => {
-                panic!("prio2_helper_prepare_finish: unexpected transition (continued)")
+                panic!("prio2_prepare_finish: unexpected transition (continued)")
             }
             PrepareTransition::Finish(out_share) => out_share,
         },
-        _ => panic!("prio2_helper_prepare_finish: unexpected helper state type"),
+        _ => panic!("prio2_prepare_finish: unexpected state type"),
     };
     let agg_share = VdafAggregateShare::FieldPrio2(vdaf.aggregate(&(), [out_share])?);
     Ok(agg_share)
 }
 
+/// Length of the Helper's encoded prepare state, which is the seed of its input share.
+pub(crate) fn prio2_helper_prepare_state_len() -> usize {
+    32
+}
+
 /// Parse a prio2 prepare message from the front of `reader` whose type is compatible with `param`.
 pub(crate) fn prio2_decode_prepare_state(
     dimension: usize,
@@ -128,11 +140,12 @@ pub(crate) fn prio2_unshard<M: IntoIterator<Item = Vec<u8>>>(
     dimension: usize,
     num_measurements: usize,
     encoded_agg_shares: M,
-) -> Result<DapAggregateResult, VdafError> {
+) -> Result<DapAggregateResult, DapUnshardError> {
     let vdaf = Prio2::new(dimension)?;
     let mut agg_shares = Vec::with_capacity(vdaf.num_aggregators());
-    for encoded in encoded_agg_shares.into_iter() {
-        let agg_share = AggregateShare::get_decoded_with_param(&(&vdaf, &()), encoded.as_ref())?;
+    for (i, encoded) in encoded_agg_shares.into_iter().enumerate() {
+        let agg_share = AggregateShare::get_decoded_with_param(&(&vdaf, &()), encoded.as_ref())
+            .map_err(|e| DapUnshardError::Decode(agg_share_sender(i), e.to_string()))?;
         agg_shares.push(agg_share)
     }
     let agg_res = vdaf.unshard(&(), agg_shares, num_measurements)?;
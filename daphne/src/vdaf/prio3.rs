@@ -4,15 +4,21 @@
 //! Parameters for the [Prio3 VDAF](https://datatracker.ietf.org/doc/draft-patton-cfrg-vdaf/).
 
 use crate::{
-    vdaf::VdafError, DapAggregateResult, DapMeasurement, Prio3Config, VdafAggregateShare,
+    vdaf::{agg_share_sender, DapUnshardError, VdafError},
+    DapAggregateResult, DapMeasurement, Prio3Config, Prio3FixedPointBitSize, VdafAggregateShare,
     VdafMessage, VdafState,
 };
+use fixed::{
+    traits::Fixed,
+    types::extra::{U15, U31},
+    FixedI16, FixedI32,
+};
 use prio::{
     codec::{Encode, ParameterizedDecode},
     vdaf::{
         prio3::{
-            Prio3, Prio3InputShare, Prio3PrepareMessage, Prio3PrepareShare, Prio3PrepareState,
-            Prio3PublicShare,
+            Prio3, Prio3FixedPointBoundedL2VecSum, Prio3InputShare, Prio3PrepareMessage,
+            Prio3PrepareShare, Prio3PrepareState, Prio3PublicShare,
         },
         AggregateShare, Aggregator, Client, Collector, PrepareTransition, Vdaf,
     },
@@ -22,6 +28,47 @@ use std::io::Cursor;
 const ERR_EXPECT_FINISH: &str = "unexpected transition (continued)";
 const ERR_FIELD_TYPE: &str = "unexpected field type for step or message";
 
+type Prio3FixedPoint16BitBoundedL2VecSum = Prio3FixedPointBoundedL2VecSum<FixedI16<U15>>;
+type Prio3FixedPoint32BitBoundedL2VecSum = Prio3FixedPointBoundedL2VecSum<FixedI32<U31>>;
+
+/// Bind `$vdaf` to the instance of Prio3FixedPointBoundedL2VecSum for the given bit size and
+/// vector length, then evaluate `$body`.
+macro_rules! with_fixed_point_vdaf {
+    (
+        $bitsize:expr,
+        $len:expr,
+        |$vdaf:ident| $body:expr
+    ) => {{
+        match $bitsize {
+            Prio3FixedPointBitSize::Fixed16 => {
+                let $vdaf =
+                    Prio3FixedPoint16BitBoundedL2VecSum::new_fixedpoint_boundedl2_vec_sum(2, $len)?;
+                $body
+            }
+            Prio3FixedPointBitSize::Fixed32 => {
+                let $vdaf =
+                    Prio3FixedPoint32BitBoundedL2VecSum::new_fixedpoint_boundedl2_vec_sum(2, $len)?;
+                $body
+            }
+        }
+    }};
+}
+
+/// Convert each entry of a measurement to a fixed-point number. Each entry must be in range
+/// `[-1, 1)`.
+fn fixed_point_measurement<Fx: Fixed>(measurement: Vec<f64>) -> Result<Vec<Fx>, VdafError> {
+    measurement
+        .into_iter()
+        .map(|x| {
+            Fx::checked_from_num(x).ok_or_else(|| {
+                VdafError::Vdaf(prio::vdaf::VdafError::Uncategorized(format!(
+                    "measurement entry {x} is not representable as a fixed-point number"
+                )))
+            })
+        })
+        .collect()
+}
+
 macro_rules! shard {
     (
         $vdaf:ident,
@@ -52,14 +99,42 @@ pub(crate) fn prio3_shard(
             let vdaf = Prio3::new_count(2)?;
             Ok(shard!(vdaf, &measurement, nonce))
         }
+        (Prio3Config::Count, DapMeasurement::Bool(measurement)) => {
+            let vdaf = Prio3::new_count(2)?;
+            Ok(shard!(vdaf, &u64::from(measurement), nonce))
+        }
         (Prio3Config::Histogram { buckets }, DapMeasurement::U64(measurement)) => {
             let vdaf = Prio3::new_histogram(2, buckets)?;
             Ok(shard!(vdaf, &(measurement as u128), nonce))
         }
+        (Prio3Config::Histogram { buckets }, DapMeasurement::Category(category)) => {
+            // A measurement falls into the first bucket whose boundary is not less than it, or
+            // into the last bucket if it exceeds each boundary.
+            let measurement = match buckets.get(category as usize) {
+                Some(boundary) => u128::from(*boundary),
+                None => buckets
+                    .last()
+                    .map_or(0, |boundary| u128::from(*boundary) + 1),
+            };
+            let vdaf = Prio3::new_histogram(2, buckets)?;
+            Ok(shard!(vdaf, &measurement, nonce))
+        }
         (Prio3Config::Sum { bits }, DapMeasurement::U64(measurement)) => {
             let vdaf = Prio3::new_sum(2, *bits)?;
             Ok(shard!(vdaf, &(measurement as u128), nonce))
         }
+        (Prio3Config::SumVec { bits, len }, DapMeasurement::U64Vec(measurement)) => {
+            let vdaf = Prio3::new_sum_vec(2, *bits, *len)?;
+            let measurement: Vec<u128> = measurement.into_iter().map(u128::from).collect();
+            Ok(shard!(vdaf, &measurement, nonce))
+        }
+        (
+            Prio3Config::FixedPointBoundedL2VecSum { bitsize, len },
+            DapMeasurement::F64Vec(measurement),
+        ) => with_fixed_point_vdaf!(bitsize, *len, |vdaf| {
+            let measurement = fixed_point_measurement(measurement)?;
+            Ok(shard!(vdaf, &measurement, nonce))
+        }),
         _ => panic!("prio3_shard: unexpected VDAF config"),
     }
 }
@@ -147,28 +222,64 @@ pub(crate) fn prio3_prepare_init(
                 VdafMessage::Prio3ShareField128(share),
             ))
         }
+        Prio3Config::SumVec { bits, len } => {
+            let vdaf = Prio3::new_sum_vec(2, *bits, *len)?;
+            let (state, share) = prep_init!(
+                vdaf,
+                verify_key,
+                agg_id,
+                nonce,
+                public_share_data,
+                input_share_data
+            );
+            Ok((
+                VdafState::Prio3Field128(state),
+                VdafMessage::Prio3ShareField128(share),
+            ))
+        }
+        Prio3Config::FixedPointBoundedL2VecSum { bitsize, len } => {
+            with_fixed_point_vdaf!(bitsize, *len, |vdaf| {
+                let (state, share) = prep_init!(
+                    vdaf,
+                    verify_key,
+                    agg_id,
+                    nonce,
+                    public_share_data,
+                    input_share_data
+                );
+                Ok((
+                    VdafState::Prio3Field128(state),
+                    VdafMessage::Prio3ShareField128(share),
+                ))
+            })
+        }
     }
 }
 
-macro_rules! leader_prep_fin {
+macro_rules! prep_fin_from_shares {
     (
         $vdaf:ident,
-        $leader_state:expr,
-        $leader_share:expr,
-        $helper_share_data:expr
+        $agg_id:expr,
+        $state:expr,
+        $share:expr,
+        $peer_share_data:expr
     ) => {{
-        // Decode the Helper's inbound message.
-        let helper_share =
-            Prio3PrepareShare::get_decoded_with_param(&$leader_state, $helper_share_data)?;
+        // Decode the peer's inbound message.
+        let peer_share = Prio3PrepareShare::get_decoded_with_param(&$state, $peer_share_data)?;
 
-        // Preprocess the inbound messages.
-        let message = $vdaf.prepare_preprocess([$leader_share, helper_share])?;
+        // Preprocess the inbound messages. The shares are combined in order of aggregator ID.
+        let shares = if $agg_id == 0 {
+            [$share, peer_share]
+        } else {
+            [peer_share, $share]
+        };
+        let message = $vdaf.prepare_preprocess(shares)?;
         let message_data = message.get_encoded();
 
-        // Compute the leader's output share.
-        match $vdaf.prepare_step($leader_state, message)? {
+        // Compute the output share.
+        match $vdaf.prepare_step($state, message)? {
             PrepareTransition::Continue(..) => {
-                panic!("prio3_leader_prepare_finish: {ERR_EXPECT_FINISH}")
+                panic!("prio3_prepare_finish_from_shares: {ERR_EXPECT_FINISH}")
             }
             PrepareTransition::Finish(out_share) => (out_share, message_data),
         }
@@ -176,20 +287,23 @@ macro_rules! leader_prep_fin {
 }
 
 /// Consume the verifier shares and return the output share and serialized outbound message.
-pub(crate) fn prio3_leader_prepare_finish(
+/// `agg_id` is the aggregator ID of the caller, i.e., `0` for the Leader and `1` for the Helper.
+pub(crate) fn prio3_prepare_finish_from_shares(
     config: &Prio3Config,
-    leader_state: VdafState,
-    leader_share: VdafMessage,
-    helper_share_data: &[u8],
+    agg_id: usize,
+    state: VdafState,
+    share: VdafMessage,
+    peer_share_data: &[u8],
 ) -> Result<(VdafAggregateShare, Vec<u8>), VdafError> {
-    let (agg_share, outbound) = match (&config, leader_state, leader_share) {
+    let (agg_share, outbound) = match (&config, state, share) {
         (
             Prio3Config::Count,
             VdafState::Prio3Field64(state),
             VdafMessage::Prio3ShareField64(share),
         ) => {
             let vdaf = Prio3::new_count(2)?;
-            let (out_share, outbound) = leader_prep_fin!(vdaf, state, share, helper_share_data);
+            let (out_share, outbound) =
+                prep_fin_from_shares!(vdaf, agg_id, state, share, peer_share_data);
             let agg_share = VdafAggregateShare::Field64(vdaf.aggregate(&(), [out_share])?);
             (agg_share, outbound)
         }
@@ -199,7 +313,8 @@ pub(crate) fn prio3_leader_prepare_finish(
             VdafMessage::Prio3ShareField128(share),
         ) => {
             let vdaf = Prio3::new_histogram(2, buckets)?;
-            let (out_share, outbound) = leader_prep_fin!(vdaf, state, share, helper_share_data);
+            let (out_share, outbound) =
+                prep_fin_from_shares!(vdaf, agg_id, state, share, peer_share_data);
             let agg_share = VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?);
             (agg_share, outbound)
         }
@@ -209,31 +324,53 @@ pub(crate) fn prio3_leader_prepare_finish(
             VdafMessage::Prio3ShareField128(share),
         ) => {
             let vdaf = Prio3::new_sum(2, *bits)?;
-            let (out_share, outbound) = leader_prep_fin!(vdaf, state, share, helper_share_data);
+            let (out_share, outbound) =
+                prep_fin_from_shares!(vdaf, agg_id, state, share, peer_share_data);
             let agg_share = VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?);
             (agg_share, outbound)
         }
-        _ => panic!("prio3_leader_prepare_finish: {ERR_FIELD_TYPE}"),
+        (
+            Prio3Config::SumVec { bits, len },
+            VdafState::Prio3Field128(state),
+            VdafMessage::Prio3ShareField128(share),
+        ) => {
+            let vdaf = Prio3::new_sum_vec(2, *bits, *len)?;
+            let (out_share, outbound) =
+                prep_fin_from_shares!(vdaf, agg_id, state, share, peer_share_data);
+            let agg_share = VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?);
+            (agg_share, outbound)
+        }
+        (
+            Prio3Config::FixedPointBoundedL2VecSum { bitsize, len },
+            VdafState::Prio3Field128(state),
+            VdafMessage::Prio3ShareField128(share),
+        ) => with_fixed_point_vdaf!(bitsize, *len, |vdaf| {
+            let (out_share, outbound) =
+                prep_fin_from_shares!(vdaf, agg_id, state, share, peer_share_data);
+            let agg_share = VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?);
+            (agg_share, outbound)
+        }),
+        _ => panic!("prio3_prepare_finish_from_shares: {ERR_FIELD_TYPE}"),
     };
 
     Ok((agg_share, outbound))
 }
 
-macro_rules! helper_prep_fin {
+macro_rules! prep_fin {
     (
         $vdaf:ident,
-        $helper_state:expr,
-        $leader_message_data:expr
+        $state:expr,
+        $peer_message_data:expr
     ) => {{
-        // Decode the inbound message from the Leader, which contains the preprocessed prepare
+        // Decode the inbound message from the peer, which contains the preprocessed prepare
         // message.
-        let leader_message =
-            Prio3PrepareMessage::get_decoded_with_param(&$helper_state, $leader_message_data)?;
+        let peer_message =
+            Prio3PrepareMessage::get_decoded_with_param(&$state, $peer_message_data)?;
 
-        // Compute the Helper's output share.
-        match $vdaf.prepare_step($helper_state, leader_message)? {
+        // Compute the output share.
+        match $vdaf.prepare_step($state, peer_message)? {
             PrepareTransition::Continue(..) => {
-                panic!("prio3_helper_prepare_finish: {ERR_EXPECT_FINISH}")
+                panic!("prio3_prepare_finish: {ERR_EXPECT_FINISH}")
             }
             PrepareTransition::Finish(out_share) => out_share,
         }
@@ -241,7 +378,7 @@ macro_rules! helper_prep_fin {
 }
 
 /// Consume the peer's prepare message and return an output share.
-pub(crate) fn prio3_helper_prepare_finish(
+pub(crate) fn prio3_prepare_finish(
     config: &Prio3Config,
     state: VdafState,
     peer_message_data: &[u8],
@@ -249,20 +386,32 @@ pub(crate) fn prio3_helper_prepare_finish(
     let agg_share = match (&config, state) {
         (Prio3Config::Count, VdafState::Prio3Field64(state)) => {
             let vdaf = Prio3::new_count(2)?;
-            let out_share = helper_prep_fin!(vdaf, state, peer_message_data);
+            let out_share = prep_fin!(vdaf, state, peer_message_data);
             VdafAggregateShare::Field64(vdaf.aggregate(&(), [out_share])?)
         }
         (Prio3Config::Histogram { buckets }, VdafState::Prio3Field128(state)) => {
             let vdaf = Prio3::new_histogram(2, buckets)?;
-            let out_share = helper_prep_fin!(vdaf, state, peer_message_data);
+            let out_share = prep_fin!(vdaf, state, peer_message_data);
             VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?)
         }
         (Prio3Config::Sum { bits }, VdafState::Prio3Field128(state)) => {
             let vdaf = Prio3::new_sum(2, *bits)?;
-            let out_share = helper_prep_fin!(vdaf, state, peer_message_data);
+            let out_share = prep_fin!(vdaf, state, peer_message_data);
+            VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?)
+        }
+        (Prio3Config::SumVec { bits, len }, VdafState::Prio3Field128(state)) => {
+            let vdaf = Prio3::new_sum_vec(2, *bits, *len)?;
+            let out_share = prep_fin!(vdaf, state, peer_message_data);
             VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?)
         }
-        _ => panic!("prio3_helper_prepare_finish: {ERR_FIELD_TYPE}"),
+        (
+            Prio3Config::FixedPointBoundedL2VecSum { bitsize, len },
+            VdafState::Prio3Field128(state),
+        ) => with_fixed_point_vdaf!(bitsize, *len, |vdaf| {
+            let out_share = prep_fin!(vdaf, state, peer_message_data);
+            VdafAggregateShare::Field128(vdaf.aggregate(&(), [out_share])?)
+        }),
+        _ => panic!("prio3_prepare_finish: {ERR_FIELD_TYPE}"),
     };
 
     Ok(agg_share)
@@ -280,7 +429,9 @@ pub(crate) fn prio3_append_prepare_state(
             state.encode(bytes);
         }
         (Prio3Config::Histogram { buckets: _ }, VdafState::Prio3Field128(state))
-        | (Prio3Config::Sum { bits: _ }, VdafState::Prio3Field128(state)) => {
+        | (Prio3Config::Sum { bits: _ }, VdafState::Prio3Field128(state))
+        | (Prio3Config::SumVec { .. }, VdafState::Prio3Field128(state))
+        | (Prio3Config::FixedPointBoundedL2VecSum { .. }, VdafState::Prio3Field128(state)) => {
             state.encode(bytes);
         }
         _ => panic!("prio3_append_prepare_state: {ERR_FIELD_TYPE}"),
@@ -288,6 +439,20 @@ pub(crate) fn prio3_append_prepare_state(
     Ok(())
 }
 
+/// Length of the Helper's encoded prepare state. The Helper's measurement share is expanded from a
+/// seed, so the state consists of that seed and, if the VDAF uses joint randomness, the joint
+/// randomness seed.
+pub(crate) fn prio3_helper_prepare_state_len(config: &Prio3Config) -> usize {
+    const SEED_SIZE: usize = 16;
+    match config {
+        Prio3Config::Count => SEED_SIZE,
+        Prio3Config::Histogram { .. }
+        | Prio3Config::Sum { .. }
+        | Prio3Config::SumVec { .. }
+        | Prio3Config::FixedPointBoundedL2VecSum { .. } => 2 * SEED_SIZE,
+    }
+}
+
 /// Parse a prio3 prepare message from the front of `reader` whose type is compatible with `param`.
 pub(crate) fn prio3_decode_prepare_state(
     config: &Prio3Config,
@@ -313,6 +478,19 @@ pub(crate) fn prio3_decode_prepare_state(
                 Prio3PrepareState::decode_with_param(&(&vdaf, agg_id), bytes)?,
             ))
         }
+        Prio3Config::SumVec { bits, len } => {
+            let vdaf = Prio3::new_sum_vec(2, *bits, *len)?;
+            Ok(VdafState::Prio3Field128(
+                Prio3PrepareState::decode_with_param(&(&vdaf, agg_id), bytes)?,
+            ))
+        }
+        Prio3Config::FixedPointBoundedL2VecSum { bitsize, len } => {
+            with_fixed_point_vdaf!(bitsize, *len, |vdaf| {
+                Ok(VdafState::Prio3Field128(
+                    Prio3PrepareState::decode_with_param(&(&vdaf, agg_id), bytes)?,
+                ))
+            })
+        }
     }
 }
 
@@ -321,6 +499,7 @@ pub(crate) fn prio3_encode_prepare_message(message: &VdafMessage) -> Vec<u8> {
     match message {
         VdafMessage::Prio3ShareField64(message) => message.get_encoded(),
         VdafMessage::Prio3ShareField128(message) => message.get_encoded(),
+        #[cfg(feature = "prio2")]
         _ => panic!("prio3_encode_prepare_message: unexpected message type"),
     }
 }
@@ -332,8 +511,10 @@ macro_rules! unshard {
         $agg_shares:expr
     ) => {{
         let mut agg_shares = Vec::with_capacity($vdaf.num_aggregators());
-        for data in $agg_shares.into_iter() {
-            let agg_share = AggregateShare::get_decoded_with_param(&(&$vdaf, &()), data.as_ref())?;
+        for (i, data) in $agg_shares.into_iter().enumerate() {
+            let agg_share =
+                AggregateShare::get_decoded_with_param(&(&$vdaf, &()), data.as_ref())
+                    .map_err(|e| DapUnshardError::Decode(agg_share_sender(i), e.to_string()))?;
             agg_shares.push(agg_share)
         }
         $vdaf.unshard(&(), agg_shares, $num_measurements)
@@ -345,7 +526,7 @@ pub(crate) fn prio3_unshard<M: IntoIterator<Item = Vec<u8>>>(
     config: &Prio3Config,
     num_measurements: usize,
     agg_shares: M,
-) -> Result<DapAggregateResult, VdafError> {
+) -> Result<DapAggregateResult, DapUnshardError> {
     match &config {
         Prio3Config::Count => {
             let vdaf = Prio3::new_count(2)?;
@@ -362,5 +543,16 @@ pub(crate) fn prio3_unshard<M: IntoIterator<Item = Vec<u8>>>(
             let agg_res = unshard!(vdaf, num_measurements, agg_shares)?;
             Ok(DapAggregateResult::U128(agg_res))
         }
+        Prio3Config::SumVec { bits, len } => {
+            let vdaf = Prio3::new_sum_vec(2, *bits, *len)?;
+            let agg_res = unshard!(vdaf, num_measurements, agg_shares)?;
+            Ok(DapAggregateResult::U128Vec(agg_res))
+        }
+        Prio3Config::FixedPointBoundedL2VecSum { bitsize, len } => {
+            with_fixed_point_vdaf!(bitsize, *len, |vdaf| {
+                let agg_res = unshard!(vdaf, num_measurements, agg_shares)?;
+                Ok(DapAggregateResult::F64Vec(agg_res))
+            })
+        }
     }
 }
@@ -2,15 +2,20 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{
+    async_test_version, async_test_versions,
     vdaf::{
+        mod_test::Test,
         prio3::{
-            prio3_encode_prepare_message, prio3_helper_prepare_finish, prio3_leader_prepare_finish,
+            prio3_encode_prepare_message, prio3_prepare_finish, prio3_prepare_finish_from_shares,
             prio3_prepare_init, prio3_shard, prio3_unshard,
         },
         VdafError,
     },
-    DapAggregateResult, DapMeasurement, Prio3Config,
+    DapAggregateResult, DapMeasurement, DapVersion, Prio3Config, Prio3FixedPointBitSize,
+    VdafConfig,
 };
+use assert_matches::assert_matches;
+use paste::paste;
 use prio::codec::Encode;
 use rand::prelude::*;
 
@@ -24,6 +29,16 @@ fn prepare_count() {
     .unwrap();
 }
 
+#[test]
+fn prepare_count_bool() {
+    test_prepare(
+        &Prio3Config::Count,
+        DapMeasurement::Bool(true),
+        DapAggregateResult::U64(1),
+    )
+    .unwrap();
+}
+
 #[test]
 fn prepare_sum() {
     test_prepare(
@@ -46,6 +61,148 @@ fn prepare_histogram() {
     .unwrap();
 }
 
+#[test]
+fn prepare_histogram_category() {
+    let config = Prio3Config::Histogram {
+        buckets: vec![0, 23, 9999999],
+    };
+    for (category, expected) in [
+        (0, vec![1, 0, 0, 0]),
+        (1, vec![0, 1, 0, 0]),
+        (2, vec![0, 0, 1, 0]),
+        (3, vec![0, 0, 0, 1]),
+    ] {
+        test_prepare(
+            &config,
+            DapMeasurement::Category(category),
+            DapAggregateResult::U128Vec(expected),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn check_measurement() {
+    let check = |config, measurement| {
+        VdafConfig::Prio3(config)
+            .check_measurement(&measurement)
+            .map_err(|e| e.to_string())
+    };
+
+    assert!(check(Prio3Config::Count, DapMeasurement::Bool(false)).is_ok());
+    assert!(check(Prio3Config::Count, DapMeasurement::U64(1)).is_ok());
+    assert_matches!(
+        check(Prio3Config::Count, DapMeasurement::U64(2)),
+        Err(s) => assert!(s.contains("count must be 0 or 1"), "{s}")
+    );
+    assert_matches!(
+        check(Prio3Config::Count, DapMeasurement::Category(1)),
+        Err(s) => assert!(s.contains("Category is not consumed by"), "{s}")
+    );
+
+    let histogram = || Prio3Config::Histogram {
+        buckets: vec![0, 23],
+    };
+    assert!(check(histogram(), DapMeasurement::Category(2)).is_ok());
+    assert_matches!(
+        check(histogram(), DapMeasurement::Category(3)),
+        Err(s) => assert!(s.contains("category 3 is out of range for a histogram with 3 buckets"), "{s}")
+    );
+
+    assert!(check(Prio3Config::Sum { bits: 8 }, DapMeasurement::U64(255)).is_ok());
+    assert_matches!(
+        check(Prio3Config::Sum { bits: 8 }, DapMeasurement::U64(256)),
+        Err(s) => assert!(s.contains("256 is out of range for 8 bits"), "{s}")
+    );
+    assert!(check(Prio3Config::Sum { bits: 64 }, DapMeasurement::U64(u64::MAX)).is_ok());
+
+    assert_matches!(
+        check(
+            Prio3Config::SumVec { bits: 1, len: 3 },
+            DapMeasurement::U64Vec(vec![1, 0])
+        ),
+        Err(s) => assert!(s.contains("expected 3 entries, got 2"), "{s}")
+    );
+    assert_matches!(
+        check(
+            Prio3Config::SumVec { bits: 1, len: 2 },
+            DapMeasurement::U64Vec(vec![1, 2])
+        ),
+        Err(s) => assert!(s.contains("2 is out of range for 1 bits"), "{s}")
+    );
+
+    let fixed_point = || Prio3Config::FixedPointBoundedL2VecSum {
+        bitsize: Prio3FixedPointBitSize::Fixed16,
+        len: 2,
+    };
+    assert!(check(fixed_point(), DapMeasurement::F64Vec(vec![0.5, -0.5])).is_ok());
+    assert_matches!(
+        check(fixed_point(), DapMeasurement::F64Vec(vec![1.0, 0.0])),
+        Err(s) => assert!(s.contains("not in range"), "{s}")
+    );
+    assert_matches!(
+        check(fixed_point(), DapMeasurement::F64Vec(vec![0.75, 0.75])),
+        Err(s) => assert!(s.contains("L2 norm"), "{s}")
+    );
+}
+
+#[test]
+fn prepare_sum_vec() {
+    test_prepare(
+        &Prio3Config::SumVec { bits: 23, len: 3 },
+        DapMeasurement::U64Vec(vec![1337, 0, 999]),
+        DapAggregateResult::U128Vec(vec![1337, 0, 999]),
+    )
+    .unwrap();
+}
+
+async fn roundtrip_sum_vec(version: DapVersion) {
+    let mut t = Test::new(
+        &VdafConfig::Prio3(Prio3Config::SumVec { bits: 1, len: 3 }),
+        version,
+    );
+    let got = t
+        .roundtrip(vec![
+            DapMeasurement::U64Vec(vec![1, 0, 1]),
+            DapMeasurement::U64Vec(vec![1, 1, 0]),
+            DapMeasurement::U64Vec(vec![0, 0, 1]),
+        ])
+        .await;
+    assert_eq!(got, DapAggregateResult::U128Vec(vec![2, 1, 2]));
+}
+
+async_test_versions! { roundtrip_sum_vec }
+
+#[test]
+fn prepare_fixed_point_bounded_l2_vec_sum() {
+    for bitsize in [
+        Prio3FixedPointBitSize::Fixed16,
+        Prio3FixedPointBitSize::Fixed32,
+    ] {
+        test_prepare(
+            &Prio3Config::FixedPointBoundedL2VecSum { bitsize, len: 3 },
+            DapMeasurement::F64Vec(vec![0.5, -0.25, 0.0]),
+            DapAggregateResult::F64Vec(vec![0.5, -0.25, 0.0]),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn shard_fixed_point_bounded_l2_vec_sum_out_of_range() {
+    assert_matches!(
+        prio3_shard(
+            &Prio3Config::FixedPointBoundedL2VecSum {
+                bitsize: Prio3FixedPointBitSize::Fixed16,
+                len: 1,
+            },
+            DapMeasurement::F64Vec(vec![1.5]),
+            &[0; 16],
+        ),
+        Err(VdafError::Vdaf(prio::vdaf::VdafError::Uncategorized(s))) if s.contains("not representable")
+    );
+}
+
 fn test_prepare(
     config: &Prio3Config,
     measurement: DapMeasurement,
@@ -81,10 +238,15 @@ fn test_prepare(
 
     let helper_share_data = prio3_encode_prepare_message(&helper_share);
 
-    let (leader_out_share, leader_message_data) =
-        prio3_leader_prepare_finish(config, leader_state, leader_share, &helper_share_data)?;
+    let (leader_out_share, leader_message_data) = prio3_prepare_finish_from_shares(
+        config,
+        0,
+        leader_state,
+        leader_share,
+        &helper_share_data,
+    )?;
 
-    let helper_out_share = prio3_helper_prepare_finish(config, helper_state, &leader_message_data)?;
+    let helper_out_share = prio3_prepare_finish(config, helper_state, &leader_message_data)?;
 
     // Unshard
     let agg_res = prio3_unshard(
@@ -8,8 +8,8 @@ use serde::{Deserialize, Serialize};
 
 /// HTTP client authorization for Daphne-Worker.
 pub(crate) enum DaphneWorkerAuth {
-    /// Bearer token, expected to appear in the "dap-auth-token" header.
-    BearerToken(BearerToken),
+    /// Bearer token and the header in which it appears.
+    BearerToken(BearerToken, DaphneWorkerAuthHeader),
 
     /// TLS client authentication. The client uses a certificate when establishing the TLS
     /// connection with the expected issuer and subject. This authorization method is
@@ -70,7 +70,7 @@ pub(crate) enum DaphneWorkerAuth {
 impl AsRef<BearerToken> for DaphneWorkerAuth {
     fn as_ref(&self) -> &BearerToken {
         match self {
-            Self::BearerToken(bearer_token) => bearer_token,
+            Self::BearerToken(bearer_token, _) => bearer_token,
             Self::CfTlsClientAuth { .. } => {
                 panic!("tried to use TLS client authorization as bearer token")
             }
@@ -78,6 +78,51 @@ impl AsRef<BearerToken> for DaphneWorkerAuth {
     }
 }
 
+/// HTTP header in which a bearer token is carried.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DaphneWorkerAuthHeader {
+    /// "DAP-Auth-Token: <token>", as specified by DAP.
+    #[default]
+    DapAuthToken,
+
+    /// "Authorization: Bearer <token>", as used by some versions of Janus.
+    AuthorizationBearer,
+}
+
+impl DaphneWorkerAuthHeader {
+    /// Parse the bearer token from the value of the "Authorization" header. The authentication
+    /// scheme is case-insensitive.
+    pub(crate) fn parse_authorization(value: &str) -> Option<BearerToken> {
+        match value.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                Some(BearerToken::from(token.trim()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Which bearer token headers are accepted from, and emitted to, a peer. This allows Daphne-Worker
+/// to interoperate with deployments that use a different header than it does.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct DaphneWorkerAuthHeaderConfig {
+    /// Headers accepted in requests from the peer.
+    pub(crate) accept: Vec<DaphneWorkerAuthHeader>,
+
+    /// Header used in requests to the peer.
+    pub(crate) emit: DaphneWorkerAuthHeader,
+}
+
+impl Default for DaphneWorkerAuthHeaderConfig {
+    fn default() -> Self {
+        Self {
+            accept: vec![DaphneWorkerAuthHeader::DapAuthToken],
+            emit: DaphneWorkerAuthHeader::DapAuthToken,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(try_from = "SerializedDaphneWorkerAuthMethod")]
 pub(crate) enum DaphneWorkerAuthMethod {
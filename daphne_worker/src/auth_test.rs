@@ -1,7 +1,7 @@
 // Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::auth::DaphneWorkerAuthMethod;
+use crate::auth::{DaphneWorkerAuthHeader, DaphneWorkerAuthHeaderConfig, DaphneWorkerAuthMethod};
 use assert_matches::assert_matches;
 use daphne::auth::BearerToken;
 
@@ -37,3 +37,44 @@ fn daphne_worker_auth_method_json_serialiation() {
         }
     );
 }
+
+#[test]
+fn daphne_worker_auth_header_parse_authorization() {
+    assert_eq!(
+        DaphneWorkerAuthHeader::parse_authorization("Bearer the bearer token"),
+        Some(BearerToken::from("the bearer token"))
+    );
+    assert_eq!(
+        DaphneWorkerAuthHeader::parse_authorization("bearer the-bearer-token"),
+        Some(BearerToken::from("the-bearer-token"))
+    );
+    assert_eq!(
+        DaphneWorkerAuthHeader::parse_authorization("Basic dXNlcjpwYXNz"),
+        None
+    );
+    assert_eq!(
+        DaphneWorkerAuthHeader::parse_authorization("the-bearer-token"),
+        None
+    );
+}
+
+#[test]
+fn daphne_worker_auth_header_config_json_serialization() {
+    let config: DaphneWorkerAuthHeaderConfig = serde_json::from_str(
+        r#"{
+            "accept": ["dap_auth_token", "authorization_bearer"],
+            "emit": "authorization_bearer"
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config,
+        DaphneWorkerAuthHeaderConfig {
+            accept: vec![
+                DaphneWorkerAuthHeader::DapAuthToken,
+                DaphneWorkerAuthHeader::AuthorizationBearer,
+            ],
+            emit: DaphneWorkerAuthHeader::AuthorizationBearer,
+        }
+    );
+}
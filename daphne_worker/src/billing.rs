@@ -0,0 +1,82 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Per-task usage reports for billing.
+//!
+//! When `DAP_BILLING_ENABLED` is set, usage is counted per task and per day by the
+//! `TaskUsageStore` DO. The rollup job reads the counts for recent days and persists a
+//! [`TaskBillingReport`] to KV, where it can be retrieved by the administrator.
+
+use crate::durable::task_usage_store::TaskUsage;
+use chrono::NaiveDateTime;
+use daphne::messages::Time;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub(crate) const SECONDS_PER_DAY: u64 = 86400;
+
+/// Number of days for which daily usage is retained in a [`TaskBillingReport`]. This is also the
+/// number of days read by the first rollup for a task.
+pub(crate) const TASK_BILLING_DAILY_RETENTION_DAYS: u64 = 62;
+
+/// Start of the day (UTC) that contains the given time.
+pub(crate) fn day_start(time: Time) -> Time {
+    time - (time % SECONDS_PER_DAY)
+}
+
+/// Start of the earliest day for which daily usage is retained.
+fn earliest_retained_day(now: Time) -> Time {
+    day_start(now).saturating_sub((TASK_BILLING_DAILY_RETENTION_DAYS - 1) * SECONDS_PER_DAY)
+}
+
+/// Calendar month (UTC) that contains the given time, formatted as "YYYY-MM".
+fn month_of(time: Time) -> String {
+    NaiveDateTime::from_timestamp_opt(time.try_into().unwrap_or(i64::MAX), 0)
+        .map_or_else(|| "invalid".into(), |dt| dt.format("%Y-%m").to_string())
+}
+
+/// Usage report for a task.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct TaskBillingReport {
+    /// Time at which the report was last rolled up.
+    pub(crate) updated_at: Time,
+
+    /// Usage per day, keyed by the start of the day. Only the last
+    /// [`TASK_BILLING_DAILY_RETENTION_DAYS`] days are retained.
+    pub(crate) daily: BTreeMap<Time, TaskUsage>,
+
+    /// Usage per calendar month, keyed by "YYYY-MM".
+    pub(crate) monthly: BTreeMap<String, TaskUsage>,
+}
+
+impl TaskBillingReport {
+    /// The first day that the next rollup needs to read. The last day that was rolled up is read
+    /// again, since its usage may have increased in the meantime.
+    pub(crate) fn rollup_start(&self, now: Time) -> Time {
+        let earliest = earliest_retained_day(now);
+        self.daily
+            .keys()
+            .next_back()
+            .map_or(earliest, |last| (*last).max(earliest))
+    }
+
+    /// Replace the usage recorded for the given day, updating the monthly total accordingly.
+    /// Usage counts only ever increase, so the new usage is expected to cover the previous one.
+    pub(crate) fn record_day(&mut self, day: Time, usage: TaskUsage) {
+        let prev = self.daily.get(&day).cloned().unwrap_or_default();
+        if usage.is_empty() && prev.is_empty() {
+            return;
+        }
+        self.monthly
+            .entry(month_of(day))
+            .or_default()
+            .merge(&usage.delta_since(&prev));
+        self.daily.insert(day, usage);
+    }
+
+    /// Drop the daily usage that is past the retention period. Monthly totals are kept.
+    pub(crate) fn prune(&mut self, now: Time) {
+        let earliest = earliest_retained_day(now);
+        self.daily.retain(|day, _usage| *day >= earliest);
+    }
+}
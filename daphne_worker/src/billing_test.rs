@@ -0,0 +1,67 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    billing::{TaskBillingReport, SECONDS_PER_DAY, TASK_BILLING_DAILY_RETENTION_DAYS},
+    durable::task_usage_store::TaskUsage,
+};
+
+// 2023-05-30T00:00:00Z
+const MAY_30: u64 = 1685404800;
+
+fn uploads(uploads_accepted: u64) -> TaskUsage {
+    TaskUsage {
+        uploads_accepted,
+        bytes_stored: 100 * uploads_accepted,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn record_day_across_months() {
+    let mut report = TaskBillingReport::default();
+    report.record_day(MAY_30, uploads(10));
+    report.record_day(MAY_30 + SECONDS_PER_DAY, uploads(5));
+    report.record_day(MAY_30 + 2 * SECONDS_PER_DAY, uploads(7));
+
+    assert_eq!(report.daily.len(), 3);
+    assert_eq!(report.monthly.len(), 2);
+    assert_eq!(report.monthly["2023-05"], uploads(15));
+    assert_eq!(report.monthly["2023-06"], uploads(7));
+}
+
+#[test]
+fn record_day_again() {
+    let mut report = TaskBillingReport::default();
+    report.record_day(MAY_30, uploads(10));
+
+    // Rolling up the same day again only adds the usage counted since the last rollup.
+    report.record_day(MAY_30, uploads(12));
+    assert_eq!(report.daily[&MAY_30], uploads(12));
+    assert_eq!(report.monthly["2023-05"], uploads(12));
+
+    // Days without usage are not recorded.
+    report.record_day(MAY_30 - SECONDS_PER_DAY, TaskUsage::default());
+    assert_eq!(report.daily.len(), 1);
+}
+
+#[test]
+fn rollup_start_and_prune() {
+    let mut report = TaskBillingReport::default();
+    let now = MAY_30 + 3600;
+    let earliest = MAY_30 - (TASK_BILLING_DAILY_RETENTION_DAYS - 1) * SECONDS_PER_DAY;
+
+    // The first rollup reads the entire retention period.
+    assert_eq!(report.rollup_start(now), earliest);
+
+    // Subsequent rollups start from the last day rolled up.
+    report.record_day(earliest - SECONDS_PER_DAY, uploads(1));
+    report.record_day(MAY_30 - SECONDS_PER_DAY, uploads(2));
+    assert_eq!(report.rollup_start(now), MAY_30 - SECONDS_PER_DAY);
+
+    // Daily usage past the retention period is dropped, but the monthly totals are kept.
+    report.prune(now);
+    assert_eq!(report.daily.len(), 1);
+    assert_eq!(report.monthly["2023-05"], uploads(2));
+    assert_eq!(report.monthly["2023-03"], uploads(1));
+}
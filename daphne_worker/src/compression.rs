@@ -0,0 +1,119 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Compression of the aggregation messages exchanged by the Aggregators.
+//!
+//! When `DAP_AGGREGATION_CONTENT_ENCODING` is set, the Leader compresses the body of each request
+//! it sends to the Helper for an aggregation job or aggregate share and indicates the content
+//! coding in the `Content-Encoding` header. The Helper decompresses any request body carrying a
+//! supported content coding. If the Leader's `Accept-Encoding` header lists a supported content
+//! coding, then the Helper sets it as the `Content-Encoding` of its response, which the Workers
+//! runtime then compresses accordingly.
+
+use daphne::{constants::DapMediaType, DapError};
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use std::io::{Read, Write};
+
+/// Maximum length of a decompressed request body. Bodies that would exceed this are rejected
+/// rather than decompressed in full.
+pub(crate) const MAX_DECOMPRESSED_LEN: u64 = 64 << 20;
+
+/// An HTTP content coding supported for aggregation messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ContentEncoding {
+    Gzip,
+    /// The "zlib" format (RFC 1950), as specified for the "deflate" content coding.
+    Deflate,
+}
+
+impl ContentEncoding {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Parse the value of a `Content-Encoding` header. The return value is `None` if the header
+    /// indicates the "identity" coding.
+    pub(crate) fn from_content_encoding(value: &str) -> Result<Option<Self>, DapError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(None),
+            "gzip" | "x-gzip" => Ok(Some(Self::Gzip)),
+            "deflate" => Ok(Some(Self::Deflate)),
+            _ => Err(DapError::Fatal(format!(
+                "unsupported content encoding: {value}"
+            ))),
+        }
+    }
+
+    /// Select the first supported content coding listed in the value of an `Accept-Encoding`
+    /// header, skipping any coding whose quality value is zero.
+    pub(crate) fn from_accept_encoding(value: &str) -> Option<Self> {
+        value.split(',').find_map(|item| {
+            let mut params = item.split(';');
+            let coding = params.next()?.trim();
+            let refused = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    == Some(0.0)
+            });
+            if refused {
+                return None;
+            }
+            Self::from_content_encoding(coding).ok().flatten()
+        })
+    }
+
+    /// Compress the given data.
+    pub(crate) fn encode(&self, data: &[u8]) -> Result<Vec<u8>, DapError> {
+        let encoded = match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).and_then(|()| encoder.finish())
+            }
+            Self::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).and_then(|()| encoder.finish())
+            }
+        };
+        encoded.map_err(|e| DapError::Fatal(format!("failed to compress message: {e}")))
+    }
+
+    /// Decompress the given data. It is an error if the decompressed data would be longer than
+    /// [`MAX_DECOMPRESSED_LEN`].
+    pub(crate) fn decode(&self, data: &[u8]) -> Result<Vec<u8>, DapError> {
+        let reader: Box<dyn Read> = match self {
+            Self::Gzip => Box::new(GzDecoder::new(data)),
+            Self::Deflate => Box::new(ZlibDecoder::new(data)),
+        };
+        let mut decoded = Vec::new();
+        reader
+            .take(MAX_DECOMPRESSED_LEN + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|e| DapError::Fatal(format!("failed to decompress message: {e}")))?;
+        if u64::try_from(decoded.len()).unwrap() > MAX_DECOMPRESSED_LEN {
+            return Err(DapError::fatal("decompressed message is too long"));
+        }
+        Ok(decoded)
+    }
+}
+
+/// Returns `true` if the body of a message with the given media type may be compressed.
+pub(crate) fn is_compressible(media_type: &DapMediaType) -> bool {
+    matches!(
+        media_type,
+        DapMediaType::AggregationJobInitReq
+            | DapMediaType::AggregationJobContinueReq
+            | DapMediaType::AggregateShareReq
+            | DapMediaType::AggregationJobResp
+            | DapMediaType::Draft02AggregateContinueResp
+            | DapMediaType::AggregateShare
+    )
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::compression::{ContentEncoding, MAX_DECOMPRESSED_LEN};
+
+#[test]
+fn roundtrip() {
+    let data = b"aggregation job".repeat(100);
+    for encoding in [ContentEncoding::Gzip, ContentEncoding::Deflate] {
+        let encoded = encoding.encode(&data).unwrap();
+        assert!(encoded.len() < data.len());
+        assert_eq!(encoding.decode(&encoded).unwrap(), data);
+    }
+
+    // The content codings are not interchangeable.
+    let encoded = ContentEncoding::Gzip.encode(&data).unwrap();
+    assert!(ContentEncoding::Deflate.decode(&encoded).is_err());
+}
+
+#[test]
+fn decode_too_long() {
+    let data = vec![0; usize::try_from(MAX_DECOMPRESSED_LEN).unwrap() + 1];
+    let encoded = ContentEncoding::Gzip.encode(&data).unwrap();
+    assert!(ContentEncoding::Gzip.decode(&encoded).is_err());
+}
+
+#[test]
+fn parse_content_encoding() {
+    assert_eq!(
+        ContentEncoding::from_content_encoding("gzip").unwrap(),
+        Some(ContentEncoding::Gzip)
+    );
+    assert_eq!(
+        ContentEncoding::from_content_encoding(" Deflate").unwrap(),
+        Some(ContentEncoding::Deflate)
+    );
+    assert_eq!(
+        ContentEncoding::from_content_encoding("identity").unwrap(),
+        None
+    );
+    assert!(ContentEncoding::from_content_encoding("br").is_err());
+}
+
+#[test]
+fn parse_accept_encoding() {
+    assert_eq!(
+        ContentEncoding::from_accept_encoding("br, deflate;q=0.5, gzip"),
+        Some(ContentEncoding::Deflate)
+    );
+    assert_eq!(
+        ContentEncoding::from_accept_encoding("gzip;q=0, deflate"),
+        Some(ContentEncoding::Deflate)
+    );
+    assert_eq!(ContentEncoding::from_accept_encoding("br, *"), None);
+    assert_eq!(ContentEncoding::from_accept_encoding(""), None);
+}
@@ -6,40 +6,78 @@
 //! Daphne-Worker configuration.
 
 use crate::{
-    auth::{DaphneWorkerAuth, DaphneWorkerAuthMethod},
+    auth::{
+        DaphneWorkerAuth, DaphneWorkerAuthHeader, DaphneWorkerAuthHeaderConfig,
+        DaphneWorkerAuthMethod,
+    },
+    billing::{day_start, TaskBillingReport, SECONDS_PER_DAY},
+    cmd_err,
+    compression::{is_compressible, ContentEncoding},
     dap_err,
     durable::{
-        durable_name_report_store, durable_name_task,
-        leader_batch_queue::{LeaderBatchQueueResult, DURABLE_LEADER_BATCH_QUEUE_CURRENT},
-        DurableConnector, BINDING_DAP_GARBAGE_COLLECTOR, BINDING_DAP_LEADER_BATCH_QUEUE,
-        DURABLE_DELETE_ALL,
+        aggregate_store::{
+            AggregateStoreMergeReq, AggregateStoreMergeResp, AggregateStoreVersion,
+            DURABLE_AGGREGATE_STORE_GET_VERSION, DURABLE_AGGREGATE_STORE_MERGE,
+        },
+        durable_name_queue, durable_name_report_store, durable_name_task, durable_name_task_usage,
+        garbage_collector::DURABLE_GARBAGE_COLLECTOR_DELETE_TASK,
+        leader_batch_queue::{
+            LeaderBatchQueueResult, DURABLE_LEADER_BATCH_QUEUE_CLOSE,
+            DURABLE_LEADER_BATCH_QUEUE_CURRENT,
+        },
+        leader_col_job_queue::DURABLE_LEADER_COL_JOB_QUEUE_DELETE_TASK,
+        task_usage_store::{TaskUsage, DURABLE_TASK_USAGE_STORE_ADD, DURABLE_TASK_USAGE_STORE_GET},
+        DurableConnector, BINDING_DAP_AGGREGATE_STORE, BINDING_DAP_GARBAGE_COLLECTOR,
+        BINDING_DAP_LEADER_BATCH_QUEUE, BINDING_DAP_LEADER_COL_JOB_QUEUE,
+        BINDING_DAP_REPORTS_PENDING, BINDING_DAP_TASK_USAGE_STORE, DURABLE_DELETE_ALL,
     },
+    export::CollectionExportConfig,
+    hpke::DaphneWorkerHpkeProvider,
     int_err,
+    internal_api::{InternalErrorCode, InternalResponse, InternalTestEndpoint, StorageMigration},
     metrics::DaphneWorkerMetrics,
-    InternalTestAddTask, InternalTestEndpointForTask, InternalTestRole,
+    now,
+    storage_migration::KV_KEY_STORAGE_MIGRATION,
+    tracing_utils::{correlation_id_for_request, new_correlation_id},
+    InternalTestAddTask, InternalTestCollector, InternalTestCorruptLeaderBearerToken,
+    InternalTestEndpointForTask, InternalTestRole,
 };
 use daphne::{
-    aborts::DapAbort,
-    auth::BearerToken,
+    aborts::{DapAbort, ProblemDetails},
+    auth::{BearerToken, DapClientAuth},
+    clock::Clock,
     constants::DapMediaType,
-    hpke::HpkeReceiverConfig,
+    events::{DapBatchEvent, DapBatchEventType},
+    extensions::DapExtensionRegistry,
+    hpke::{HpkeConfigValidity, HpkeReceiverConfig, HpkeReceiverConfigWithValidity},
+    manifest::{
+        diff_task_manifest, DapTaskManifestChange, DapTaskManifestDiff, DapTaskManifestEntry,
+    },
     messages::{
-        decode_base64url_vec, AggregationJobId, BatchId, CollectionJobId, HpkeConfig,
-        ReportMetadata, TaskId,
+        decode_base64url_vec, decode_report_prefix, AggregationJobId, BatchId, CollectionJobId,
+        HpkeConfig, PartialBatchSelector, Report, ReportBatch, ReportMetadata, TaskId, Time,
     },
-    DapError, DapGlobalConfig, DapQueryConfig, DapRequest, DapResource, DapResponse, DapTaskConfig,
-    DapVersion, Prio3Config, VdafConfig,
+    metrics::DaphneMetricsTaskLabeler,
+    receipt::DapReceiptSigningKey,
+    roles::{DapAggregator, DapLeader},
+    taskprov::TaskprovVersion,
+    DapAggregateShare, DapDpConfig, DapDryRunReportStatus, DapError, DapGlobalConfig,
+    DapQueryConfig, DapRequest, DapResource, DapResponse, DapSender, DapTaskCollector,
+    DapTaskConfig, DapVersion, Prio3Config, Prio3FixedPointBitSize, VdafConfig,
 };
+use futures::{future::try_join_all, StreamExt};
 use matchit::Router;
 use prio::{
     codec::Decode,
     vdaf::prg::{Prg, PrgSha3, Seed, SeedStream},
 };
 use prometheus::{Encoder, Registry};
+use ring::hmac;
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
     io::Cursor,
     sync::{Arc, RwLock, RwLockReadGuard},
     time::Duration,
@@ -50,12 +88,50 @@ use worker::{kv::KvStore, *};
 pub(crate) const KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG: &str = "hpke_receiver_config";
 pub(crate) const KV_KEY_PREFIX_BEARER_TOKEN_LEADER: &str = "bearer_token/leader/task";
 pub(crate) const KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR: &str = "bearer_token/collector/task";
+pub(crate) const KV_KEY_PREFIX_BEARER_TOKEN_ADDITIONAL_COLLECTOR: &str =
+    "bearer_token/additional_collector/task";
 pub(crate) const KV_KEY_PREFIX_TASK_CONFIG: &str = "config/task";
+pub(crate) const KV_KEY_PREFIX_AUTH_HEADER: &str = "auth_header/task";
+pub(crate) const KV_KEY_QUARANTINE: &str = "quarantine";
+pub(crate) const KV_KEY_GLOBAL_CONFIG_OVERRIDE: &str = "global_config_override";
+pub(crate) const KV_KEY_PREFIX_TASK_BILLING: &str = "billing/task";
+pub(crate) const KV_KEY_PREFIX_TASKPROV_TASK: &str = "taskprov/task";
+pub(crate) const KV_KEY_PREFIX_HELPER_DEFERRAL: &str = "helper_deferral";
+pub(crate) const KV_KEY_PREFIX_HELPER_AUTH_FAILURES: &str = "helper_auth_failures";
+pub(crate) const KV_KEY_PREFIX_ABANDONED_REPORT: &str = "abandoned_report/task";
 pub(crate) const KV_BINDING_DAP_CONFIG: &str = "DAP_CONFIG";
 
 const DAP_BASE_URL: &str = "DAP_BASE_URL";
 
-const INT_ERR_PEER_ABORT: &str = "request aborted by peer";
+/// Maximum number of expired tasks purged by each run of the task garbage collector.
+const MAX_GARBAGE_COLLECTED_TASKS_PER_RUN: usize = 16;
+
+/// Maximum number of attempts to merge an aggregate share into an aggregate store.
+const MAX_AGG_STORE_MERGE_ATTEMPTS: usize = 8;
+
+/// Appended to the Leader's bearer token by [`DaphneWorker::internal_corrupt_leader_bearer_token`].
+const CORRUPTED_TOKEN_SUFFIX: &str = "-corrupted";
+
+/// Value of the Retry-After header sent with "retryLater" aborts.
+const RETRY_AFTER_SECS: &str = "1";
+
+/// Maximum number of ReportsPending instances that may be quarantined at once.
+const MAX_QUARANTINED_REPORT_STORES: u64 = 1024;
+
+/// How long (in seconds) an isolate assumes that a Helper's count of consecutive unauthorized
+/// aggregation jobs is still zero after it has reset the count.
+const HELPER_AUTH_FAILURES_CLEARED_SECS: u64 = 60;
+
+/// How often (in seconds) each isolate reads the override of the global DAP configuration from KV.
+const GLOBAL_CONFIG_OVERRIDE_REFRESH_SECS: u64 = 60;
+
+/// How often (in seconds) each isolate reads the storage migration from KV.
+pub(crate) const STORAGE_MIGRATION_REFRESH_SECS: u64 = 60;
+
+/// Maximum number of bytes allocated for the body of an upload request before the body is read,
+/// based on the Content-Length header.
+const MAX_UPLOAD_PREALLOCATION: usize = 1 << 20;
+
 const INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE: &str = "peer response is missing media type";
 
 /// Long-lived parameters for tasks using draft-wang-ppm-dap-taskprov-02 ("taskprov").
@@ -71,6 +147,216 @@ pub(crate) struct TaskprovConfig {
 
     /// Leader: Method for authorizing Collector requests.
     pub(crate) collector_auth: Option<DaphneWorkerAuthMethod>,
+
+    /// Optional: Where to notify the operator of taskprov tasks that are about to expire. If not
+    /// configured, then no notifications are sent.
+    pub(crate) expiry_notification: Option<TaskprovExpiryNotificationConfig>,
+}
+
+/// Parameters for notifying the operator of taskprov tasks that are about to expire.
+pub(crate) struct TaskprovExpiryNotificationConfig {
+    /// URL to which each notification is POSTed.
+    url: Url,
+
+    /// Optional bearer token to present to the server in the HTTP request.
+    bearer_token: Option<BearerToken>,
+
+    /// How long before the expiration of a task to notify the operator.
+    window: Duration,
+}
+
+/// The state of a task configured by taskprov, stored in KV alongside the task config.
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct TaskprovTask {
+    /// Time at which the operator was notified that the task is about to expire.
+    #[serde(default)]
+    pub(crate) expiry_notified_at: Option<Time>,
+}
+
+/// A notification that a taskprov task is about to expire. This lists the Aggregators of the task
+/// so that the operator can coordinate with the peer about renewing the task.
+#[derive(Debug, Serialize)]
+pub(crate) struct TaskprovExpiryNotification {
+    /// Always "taskprov_task_expiring".
+    pub(crate) event: &'static str,
+
+    /// The task ID (base64url).
+    pub(crate) task_id: String,
+    pub(crate) version: DapVersion,
+
+    /// Our role in the task, either "leader" or "helper".
+    pub(crate) role: &'static str,
+    pub(crate) expiration: Time,
+    pub(crate) leader_url: Url,
+    pub(crate) helper_url: Url,
+}
+
+/// Leader: A time range of reports of a task that are excluded from aggregation until the entry
+/// expires. This is used to temporarily skip buckets of reports that repeatedly cause the Helper to
+/// fail.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct QuarantinedBuckets {
+    /// The task ID (base64url).
+    pub(crate) task_id: String,
+
+    /// Start of the time range (inclusive). All reports in the same report storage epoch as `start`
+    /// are excluded.
+    pub(crate) start: Time,
+
+    /// End of the time range (exclusive).
+    pub(crate) end: Time,
+
+    /// Time at which the entry stops being enforced.
+    pub(crate) expiration: Time,
+}
+
+/// Parameters of the global DAP configuration that may be updated at runtime by the administrator,
+/// overriding the value of `DAP_GLOBAL_CONFIG`. Parameters that are not set keep their configured
+/// value. The override is stored in KV as a single value so that all of its parameters take effect
+/// together.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct GlobalConfigOverride {
+    /// Version stamp. Each update must increment the stamp of the override it replaces; the
+    /// initial stamp is 0. An isolate never replaces an override with one that has an older stamp.
+    pub(crate) version: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_batch_duration: Option<daphne::messages::Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) min_batch_interval_start: Option<daphne::messages::Duration>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_batch_interval_end: Option<daphne::messages::Duration>,
+
+    /// May only be set if taskprov is configured by the environment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) allow_taskprov: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) taskprov_version: Option<TaskprovVersion>,
+
+    /// Overrides `DAP_READ_ONLY`. This is not a parameter of the global DAP configuration, but is
+    /// set along with it so that the deployment can be frozen without a redeploy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) read_only: Option<bool>,
+}
+
+impl GlobalConfigOverride {
+    /// Overwrite the parameters of `global` that are set by the override.
+    pub(crate) fn apply(&self, global: &mut DapGlobalConfig) {
+        if let Some(max_batch_duration) = self.max_batch_duration {
+            global.max_batch_duration = max_batch_duration;
+        }
+        if let Some(min_batch_interval_start) = self.min_batch_interval_start {
+            global.min_batch_interval_start = min_batch_interval_start;
+        }
+        if let Some(max_batch_interval_end) = self.max_batch_interval_end {
+            global.max_batch_interval_end = max_batch_interval_end;
+        }
+        if let Some(allow_taskprov) = self.allow_taskprov {
+            global.allow_taskprov = allow_taskprov;
+        }
+        if let Some(taskprov_version) = self.taskprov_version {
+            global.taskprov_version = taskprov_version;
+        }
+    }
+}
+
+/// The override of the global DAP configuration cached by an isolate.
+struct CachedGlobalConfigOverride {
+    /// Time at which the override was last read from KV.
+    fetched_at: Time,
+    global_config_override: GlobalConfigOverride,
+}
+
+/// The storage migration cached by an isolate.
+struct CachedStorageMigration {
+    /// Time at which the migration was last read from KV.
+    fetched_at: Time,
+    storage_migration: Option<StorageMigration>,
+}
+
+/// A task as reported by the admin API. The VDAF verification key is omitted.
+#[derive(Clone, Serialize)]
+pub(crate) struct AdminTask {
+    /// The task ID (base64url).
+    pub(crate) task_id: String,
+    pub(crate) version: DapVersion,
+    pub(crate) leader_url: Url,
+    pub(crate) helper_url: Url,
+    pub(crate) time_precision: daphne::messages::Duration,
+    pub(crate) expiration: Time,
+    pub(crate) min_batch_size: u64,
+    pub(crate) max_batch_query_count: u64,
+    pub(crate) query: DapQueryConfig,
+    pub(crate) vdaf: VdafConfig,
+    pub(crate) collector_hpke_config: HpkeConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) additional_collectors: Vec<DapTaskCollector>,
+    #[serde(skip_serializing_if = "DapDpConfig::is_none")]
+    pub(crate) dp: DapDpConfig,
+}
+
+impl AdminTask {
+    fn new(task_id: &TaskId, task_config: &DapTaskConfig) -> Self {
+        Self {
+            task_id: task_id.to_base64url(),
+            version: task_config.version,
+            leader_url: task_config.leader_url.clone(),
+            helper_url: task_config.helper_url.clone(),
+            time_precision: task_config.time_precision,
+            expiration: task_config.expiration,
+            min_batch_size: task_config.min_batch_size,
+            max_batch_query_count: task_config.max_batch_query_count,
+            query: task_config.query.clone(),
+            vdaf: task_config.vdaf.clone(),
+            collector_hpke_config: task_config.collector_hpke_config.clone(),
+            additional_collectors: task_config.additional_collectors.clone(),
+            dp: task_config.dp.clone(),
+        }
+    }
+}
+
+/// The state deleted by a run of the task garbage collector.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct TaskGarbageCollection {
+    /// The expired tasks that were purged.
+    pub(crate) tasks: Vec<GarbageCollectedTask>,
+
+    /// Number of expired tasks left to be purged by the next run.
+    pub(crate) remaining: usize,
+}
+
+/// The state deleted for an expired task.
+#[derive(Debug, Serialize)]
+pub(crate) struct GarbageCollectedTask {
+    /// The task ID (base64url).
+    pub(crate) task_id: String,
+    pub(crate) expiration: Time,
+
+    /// Number of DO instances deleted, by binding.
+    pub(crate) durable_objects: BTreeMap<String, u64>,
+
+    /// Leader: Number of pending or completed collection jobs deleted.
+    pub(crate) collection_jobs: u64,
+
+    /// Number of KV keys deleted.
+    pub(crate) kv_keys: u64,
+}
+
+/// KV key of the bearer token of one of the task's additional Collectors.
+fn additional_collector_bearer_token_kv_key(task_id: &TaskId, collector_id: &str) -> String {
+    format!("{KV_KEY_PREFIX_BEARER_TOKEN_ADDITIONAL_COLLECTOR}/{task_id}/{collector_id}")
+}
+
+/// Check that a Collector ID can be carried in an HTTP header and used in a KV key.
+fn is_valid_collector_id(collector_id: &str) -> bool {
+    !collector_id.is_empty()
+        && collector_id.len() <= 64
+        && collector_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
 }
 
 /// Parameters required for pushing Prometheus metrics.
@@ -85,7 +371,7 @@ struct MetricsPushConfig {
 /// Daphne-Worker configuration, including long-lived parameters used across DAP tasks.
 pub(crate) struct DaphneWorkerConfig {
     /// Indicates if DaphneWorker is used as the Leader.
-    is_leader: bool,
+    pub(crate) is_leader: bool,
 
     /// Global DAP configuration.
     pub(crate) global: DapGlobalConfig,
@@ -100,8 +386,10 @@ pub(crate) struct DaphneWorkerConfig {
     /// to (based on the report ID).
     report_shard_key: Seed<16>,
 
-    /// Shard count, the number of report storage shards. This should be a power of 2.
-    report_shard_count: u64,
+    /// Shard count, the number of report storage shards. This should be a power of 2. The shard
+    /// count in use may differ while the report storage is migrated (see
+    /// [`crate::storage_migration`]).
+    pub(crate) report_shard_count: u64,
 
     /// draft-dcook-ppm-dap-interop-test-design: Base URL of the Aggregator (unversioned). If set,
     /// this field is used for endpoint configuration for interop testing.
@@ -125,8 +413,83 @@ pub(crate) struct DaphneWorkerConfig {
     /// of the `report_storage_epoch_duration` field of the global DAP configuration.
     pub(crate) processed_alarm_safety_interval: Duration,
 
+    /// Optional: Time to wait after a task has expired before deleting its state. If not
+    /// configured, then expired tasks are not garbage collected.
+    pub(crate) task_garbage_collect_after_secs: Option<Duration>,
+
+    /// Optional: Time to wait after an instance of ReportsProcessed is first used before compacting
+    /// its replay-protection state. This should be long enough for the instance's report storage
+    /// epoch to be closed to new reports. If not configured, or if not less than the lifetime of
+    /// the instance, then the state is not compacted.
+    pub(crate) processed_compaction_delay: Option<Duration>,
+
     /// Metrics push configuration.
     metrics_push_config: Option<MetricsPushConfig>,
+
+    /// Optional: Assigns the label of per-task metrics. If not configured, then per-task metrics
+    /// are not recorded. The labeler is shared by all requests handled by the isolate.
+    pub(crate) metrics_task_labeler: Option<Arc<DaphneMetricsTaskLabeler>>,
+
+    /// Leader: Optional key used to sign receipts for completed collections. If not configured,
+    /// then receipts are not issued.
+    pub(crate) collection_receipt_signing_key: Option<DapReceiptSigningKey>,
+
+    /// Leader: Optional key used to sign receipts for uploaded reports. If not configured, then
+    /// receipts are not issued.
+    pub(crate) upload_receipt_signing_key: Option<DapReceiptSigningKey>,
+
+    /// Optional key used to sign the advertised HPKE configs. If not configured, then the configs
+    /// are not signed.
+    pub(crate) hpke_config_signing_key: Option<DapReceiptSigningKey>,
+
+    /// Leader: Optional configuration for exporting collection results to the operator. If not
+    /// configured, then results are not exported.
+    pub(crate) collection_export: Option<CollectionExportConfig>,
+
+    /// Optional: Binding of the Workers queue to which batch lifecycle events are sent. If not
+    /// configured, then events are logged.
+    pub(crate) batch_events_queue: Option<String>,
+
+    /// If set, then requests that would mutate storage are refused. This is used to freeze the
+    /// state of the deployment while it is being inspected.
+    pub(crate) read_only: bool,
+
+    /// If set, then the usage of each task is counted for billing.
+    pub(crate) billing_enabled: bool,
+
+    /// If set, then the reports of each task are counted by outcome for
+    /// `GET /internal/telemetry/aggregation`.
+    pub(crate) aggregation_telemetry_enabled: bool,
+
+    /// If set, then the reports rejected during aggregation because they are invalid are stored
+    /// for this long (in seconds) for `GET /internal/dead_letters/task/:task_id`.
+    pub(crate) dead_letter_ttl_secs: Option<u64>,
+
+    /// Amount of time each request is allowed to take. If set, then sub-requests to DOs are
+    /// refused once the remaining time is too short for them to complete.
+    pub(crate) request_time_budget: Option<Duration>,
+
+    /// Bearer token headers accepted from, and emitted to, each peer Aggregator, keyed by the host
+    /// of the peer's URL. A task's own configuration, if any, takes precedence.
+    pub(crate) auth_header_by_peer: HashMap<String, DaphneWorkerAuthHeaderConfig>,
+
+    /// Leader: Optional content coding with which to compress aggregation requests sent to the
+    /// Helper. If not configured, then requests are sent uncompressed.
+    pub(crate) aggregation_content_encoding: Option<ContentEncoding>,
+
+    /// Leader: Maximum number of reports pending aggregation in each instance of `ReportsPending`,
+    /// i.e., per task, report storage epoch, and shard. Once reached, uploads to the instance are
+    /// rejected until reports are drained. If not set, then the number is unbounded.
+    pub(crate) max_pending_reports_per_bucket: Option<u64>,
+
+    /// Depth of the aggregation job queue (Leader) or number of running aggregation jobs (Helper)
+    /// above which the Aggregator is reported as not ready. If not set, then the depth is reported
+    /// but not checked.
+    pub(crate) health_max_queue_depth: Option<u64>,
+
+    /// Latency (in milliseconds) of DO storage above which the Aggregator is reported as
+    /// unhealthy. If not set, then the latency is reported but not checked.
+    pub(crate) health_max_durable_latency_ms: Option<u64>,
 }
 
 impl DaphneWorkerConfig {
@@ -249,11 +612,43 @@ impl DaphneWorkerConfig {
                 None
             };
 
+            const DAP_TASKPROV_EXPIRY_NOTIFICATION_URL: &str =
+                "DAP_TASKPROV_EXPIRY_NOTIFICATION_URL";
+            const DAP_TASKPROV_EXPIRY_NOTIFICATION_WINDOW_SECS: &str =
+                "DAP_TASKPROV_EXPIRY_NOTIFICATION_WINDOW_SECS";
+            const DAP_TASKPROV_EXPIRY_NOTIFICATION_BEARER_TOKEN: &str =
+                "DAP_TASKPROV_EXPIRY_NOTIFICATION_BEARER_TOKEN";
+            let expiry_notification = match env.var(DAP_TASKPROV_EXPIRY_NOTIFICATION_URL) {
+                Ok(url) => Some(TaskprovExpiryNotificationConfig {
+                    url: url.to_string().parse().map_err(|err| {
+                        Error::RustError(format!(
+                            "Failed to parse {DAP_TASKPROV_EXPIRY_NOTIFICATION_URL}: {err:?}"
+                        ))
+                    })?,
+                    bearer_token: env
+                        .secret(DAP_TASKPROV_EXPIRY_NOTIFICATION_BEARER_TOKEN)
+                        .ok()
+                        .map(|token| BearerToken::from(token.to_string())),
+                    window: Duration::from_secs(
+                        env.var(DAP_TASKPROV_EXPIRY_NOTIFICATION_WINDOW_SECS)?
+                            .to_string()
+                            .parse()
+                            .map_err(|err| {
+                                Error::RustError(format!(
+                                    "Failed to parse {DAP_TASKPROV_EXPIRY_NOTIFICATION_WINDOW_SECS}: {err}"
+                                ))
+                            })?,
+                    ),
+                }),
+                Err(..) => None,
+            };
+
             Some(TaskprovConfig {
                 hpke_collector_config,
                 vdaf_verify_key_init,
                 leader_auth,
                 collector_auth,
+                expiry_notification,
             })
         } else {
             None
@@ -320,6 +715,270 @@ impl DaphneWorkerConfig {
             }
         };
 
+        const DAP_METRICS_TASK_LABELS: &str = "DAP_METRICS_TASK_LABELS";
+        let metrics_task_labeler = match env.var(DAP_METRICS_TASK_LABELS) {
+            Ok(config) => Some(Arc::new(DaphneMetricsTaskLabeler::new(
+                serde_json::from_str(&config.to_string()).map_err(|err| {
+                    Error::RustError(format!("Failed to parse {DAP_METRICS_TASK_LABELS}: {err}"))
+                })?,
+            ))),
+            Err(..) => None,
+        };
+
+        const DAP_COLLECTION_RECEIPT_SIGNING_KEY: &str = "DAP_COLLECTION_RECEIPT_SIGNING_KEY";
+        let collection_receipt_signing_key = match env.secret(DAP_COLLECTION_RECEIPT_SIGNING_KEY) {
+            Ok(seed_hex) if is_leader => Some(
+                DapReceiptSigningKey::from_seed(&hex::decode(seed_hex.to_string()).map_err(
+                    |e| {
+                        Error::RustError(format!(
+                            "{DAP_COLLECTION_RECEIPT_SIGNING_KEY}: Failed to decode hex: {e}"
+                        ))
+                    },
+                )?)
+                .map_err(|e| {
+                    Error::RustError(format!("{DAP_COLLECTION_RECEIPT_SIGNING_KEY}: {e}"))
+                })?,
+            ),
+            Ok(..) => {
+                return Err(Error::RustError(format!(
+                    "{DAP_COLLECTION_RECEIPT_SIGNING_KEY} is only used by the Leader"
+                )))
+            }
+            Err(err) => {
+                trace!("{DAP_COLLECTION_RECEIPT_SIGNING_KEY} not configured: {err:?}");
+                None
+            }
+        };
+
+        const DAP_UPLOAD_RECEIPT_SIGNING_KEY: &str = "DAP_UPLOAD_RECEIPT_SIGNING_KEY";
+        let upload_receipt_signing_key = match env.secret(DAP_UPLOAD_RECEIPT_SIGNING_KEY) {
+            Ok(seed_hex) if is_leader => Some(
+                DapReceiptSigningKey::from_seed(&hex::decode(seed_hex.to_string()).map_err(
+                    |e| {
+                        Error::RustError(format!(
+                            "{DAP_UPLOAD_RECEIPT_SIGNING_KEY}: Failed to decode hex: {e}"
+                        ))
+                    },
+                )?)
+                .map_err(|e| Error::RustError(format!("{DAP_UPLOAD_RECEIPT_SIGNING_KEY}: {e}")))?,
+            ),
+            Ok(..) => {
+                return Err(Error::RustError(format!(
+                    "{DAP_UPLOAD_RECEIPT_SIGNING_KEY} is only used by the Leader"
+                )))
+            }
+            Err(err) => {
+                trace!("{DAP_UPLOAD_RECEIPT_SIGNING_KEY} not configured: {err:?}");
+                None
+            }
+        };
+
+        const DAP_HPKE_CONFIG_SIGNING_KEY: &str = "DAP_HPKE_CONFIG_SIGNING_KEY";
+        let hpke_config_signing_key = match env.secret(DAP_HPKE_CONFIG_SIGNING_KEY) {
+            Ok(seed_hex) => Some(
+                DapReceiptSigningKey::from_seed(&hex::decode(seed_hex.to_string()).map_err(
+                    |e| {
+                        Error::RustError(format!(
+                            "{DAP_HPKE_CONFIG_SIGNING_KEY}: Failed to decode hex: {e}"
+                        ))
+                    },
+                )?)
+                .map_err(|e| Error::RustError(format!("{DAP_HPKE_CONFIG_SIGNING_KEY}: {e}")))?,
+            ),
+            Err(err) => {
+                trace!("{DAP_HPKE_CONFIG_SIGNING_KEY} not configured: {err:?}");
+                None
+            }
+        };
+
+        const DAP_COLLECTION_EXPORT_URL: &str = "DAP_COLLECTION_EXPORT_URL";
+        const DAP_COLLECTION_EXPORT_HPKE_RECEIVER_CONFIG: &str =
+            "DAP_COLLECTION_EXPORT_HPKE_RECEIVER_CONFIG";
+        const DAP_COLLECTION_EXPORT_SIGNING_KEY: &str = "DAP_COLLECTION_EXPORT_SIGNING_KEY";
+        const DAP_COLLECTION_EXPORT_RETRY: &str = "DAP_COLLECTION_EXPORT_RETRY";
+        let collection_export = match env.var(DAP_COLLECTION_EXPORT_URL) {
+            Ok(..) if !is_leader => {
+                return Err(Error::RustError(format!(
+                    "{DAP_COLLECTION_EXPORT_URL} is only used by the Leader"
+                )))
+            }
+            Ok(url) => Some(CollectionExportConfig {
+                url: url.to_string().parse().map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_COLLECTION_EXPORT_URL}: {err:?}"
+                    ))
+                })?,
+                hpke_receiver_config: serde_json::from_str(
+                    &env.secret(DAP_COLLECTION_EXPORT_HPKE_RECEIVER_CONFIG)?
+                        .to_string(),
+                )
+                .map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_COLLECTION_EXPORT_HPKE_RECEIVER_CONFIG}: {err}"
+                    ))
+                })?,
+                signing_key: match env.secret(DAP_COLLECTION_EXPORT_SIGNING_KEY) {
+                    Ok(key_hex) => Some(hmac::Key::new(
+                        hmac::HMAC_SHA256,
+                        &hex::decode(key_hex.to_string()).map_err(|err| {
+                            Error::RustError(format!(
+                                "{DAP_COLLECTION_EXPORT_SIGNING_KEY}: Failed to decode hex: {err}"
+                            ))
+                        })?,
+                    )),
+                    Err(..) => None,
+                },
+                retry: match env.var(DAP_COLLECTION_EXPORT_RETRY) {
+                    Ok(retry) => Some(serde_json::from_str(&retry.to_string()).map_err(|err| {
+                        Error::RustError(format!(
+                            "Failed to parse {DAP_COLLECTION_EXPORT_RETRY}: {err}"
+                        ))
+                    })?),
+                    Err(..) => None,
+                },
+            }),
+            Err(..) => None,
+        };
+
+        const DAP_BATCH_EVENTS_QUEUE: &str = "DAP_BATCH_EVENTS_QUEUE";
+        let batch_events_queue = env
+            .var(DAP_BATCH_EVENTS_QUEUE)
+            .ok()
+            .map(|binding| binding.to_string());
+
+        const DAP_READ_ONLY: &str = "DAP_READ_ONLY";
+        let read_only = match env.var(DAP_READ_ONLY) {
+            Ok(read_only) => read_only.to_string().parse().map_err(|err| {
+                Error::RustError(format!("Failed to parse {DAP_READ_ONLY}: {err}"))
+            })?,
+            Err(..) => false,
+        };
+        if read_only {
+            info!("{DAP_READ_ONLY} is set: requests that modify storage will be refused");
+        }
+
+        const DAP_BILLING_ENABLED: &str = "DAP_BILLING_ENABLED";
+        let billing_enabled = match env.var(DAP_BILLING_ENABLED) {
+            Ok(billing_enabled) => billing_enabled.to_string().parse().map_err(|err| {
+                Error::RustError(format!("Failed to parse {DAP_BILLING_ENABLED}: {err}"))
+            })?,
+            Err(..) => false,
+        };
+
+        const DAP_AGGREGATION_TELEMETRY_ENABLED: &str = "DAP_AGGREGATION_TELEMETRY_ENABLED";
+        let aggregation_telemetry_enabled = match env.var(DAP_AGGREGATION_TELEMETRY_ENABLED) {
+            Ok(enabled) => enabled.to_string().parse().map_err(|err| {
+                Error::RustError(format!(
+                    "Failed to parse {DAP_AGGREGATION_TELEMETRY_ENABLED}: {err}"
+                ))
+            })?,
+            Err(..) => false,
+        };
+
+        const DAP_DEAD_LETTER_TTL_SECS: &str = "DAP_DEAD_LETTER_TTL_SECS";
+        let dead_letter_ttl_secs = match env.var(DAP_DEAD_LETTER_TTL_SECS) {
+            Ok(ttl) => Some(ttl.to_string().parse().map_err(|err| {
+                Error::RustError(format!("Failed to parse {DAP_DEAD_LETTER_TTL_SECS}: {err}"))
+            })?),
+            Err(..) => None,
+        };
+
+        const DAP_REQUEST_TIME_BUDGET_MS: &str = "DAP_REQUEST_TIME_BUDGET_MS";
+        let request_time_budget = match env.var(DAP_REQUEST_TIME_BUDGET_MS) {
+            Ok(budget) => Some(Duration::from_millis(budget.to_string().parse().map_err(
+                |err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_REQUEST_TIME_BUDGET_MS}: {err}"
+                    ))
+                },
+            )?)),
+            Err(..) => None,
+        };
+
+        const DAP_TASK_GARBAGE_COLLECT_AFTER_SECS: &str = "DAP_TASK_GARBAGE_COLLECT_AFTER_SECS";
+        let task_garbage_collect_after_secs = match env.var(DAP_TASK_GARBAGE_COLLECT_AFTER_SECS) {
+            Ok(secs) => Some(Duration::from_secs(secs.to_string().parse().map_err(
+                |err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_TASK_GARBAGE_COLLECT_AFTER_SECS}: {err}"
+                    ))
+                },
+            )?)),
+            Err(..) => None,
+        };
+
+        const DAP_PROCESSED_COMPACTION_DELAY: &str = "DAP_PROCESSED_COMPACTION_DELAY";
+        let processed_compaction_delay = match env.var(DAP_PROCESSED_COMPACTION_DELAY) {
+            Ok(delay) => Some(Duration::from_secs(delay.to_string().parse().map_err(
+                |err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_PROCESSED_COMPACTION_DELAY}: {err}"
+                    ))
+                },
+            )?)),
+            Err(..) => None,
+        };
+
+        const DAP_AUTH_HEADER_BY_PEER: &str = "DAP_AUTH_HEADER_BY_PEER";
+        let auth_header_by_peer = match env.var(DAP_AUTH_HEADER_BY_PEER) {
+            Ok(config) => serde_json::from_str(config.to_string().as_ref()).map_err(|err| {
+                Error::RustError(format!("Failed to parse {DAP_AUTH_HEADER_BY_PEER}: {err}"))
+            })?,
+            Err(..) => HashMap::new(),
+        };
+
+        const DAP_AGGREGATION_CONTENT_ENCODING: &str = "DAP_AGGREGATION_CONTENT_ENCODING";
+        let aggregation_content_encoding = match env.var(DAP_AGGREGATION_CONTENT_ENCODING) {
+            Ok(..) if !is_leader => {
+                return Err(Error::RustError(format!(
+                    "{DAP_AGGREGATION_CONTENT_ENCODING} is only used by the Leader"
+                )))
+            }
+            Ok(encoding) => {
+                ContentEncoding::from_content_encoding(&encoding.to_string()).map_err(|err| {
+                    Error::RustError(format!(
+                        "Failed to parse {DAP_AGGREGATION_CONTENT_ENCODING}: {err}"
+                    ))
+                })?
+            }
+            Err(..) => None,
+        };
+
+        const DAP_MAX_PENDING_REPORTS_PER_BUCKET: &str = "DAP_MAX_PENDING_REPORTS_PER_BUCKET";
+        let max_pending_reports_per_bucket = match env.var(DAP_MAX_PENDING_REPORTS_PER_BUCKET) {
+            Ok(..) if !is_leader => {
+                return Err(Error::RustError(format!(
+                    "{DAP_MAX_PENDING_REPORTS_PER_BUCKET} is only used by the Leader"
+                )))
+            }
+            Ok(max) => Some(max.to_string().parse().map_err(|err| {
+                Error::RustError(format!(
+                    "Failed to parse {DAP_MAX_PENDING_REPORTS_PER_BUCKET}: {err}"
+                ))
+            })?),
+            Err(..) => None,
+        };
+
+        const DAP_HEALTH_MAX_QUEUE_DEPTH: &str = "DAP_HEALTH_MAX_QUEUE_DEPTH";
+        let health_max_queue_depth = match env.var(DAP_HEALTH_MAX_QUEUE_DEPTH) {
+            Ok(depth) => Some(depth.to_string().parse().map_err(|err| {
+                Error::RustError(format!(
+                    "Failed to parse {DAP_HEALTH_MAX_QUEUE_DEPTH}: {err}"
+                ))
+            })?),
+            Err(..) => None,
+        };
+
+        const DAP_HEALTH_MAX_DURABLE_LATENCY_MS: &str = "DAP_HEALTH_MAX_DURABLE_LATENCY_MS";
+        let health_max_durable_latency_ms = match env.var(DAP_HEALTH_MAX_DURABLE_LATENCY_MS) {
+            Ok(latency) => Some(latency.to_string().parse().map_err(|err| {
+                Error::RustError(format!(
+                    "Failed to parse {DAP_HEALTH_MAX_DURABLE_LATENCY_MS}: {err}"
+                ))
+            })?),
+            Err(..) => None,
+        };
+
         Ok(Self {
             global,
             deployment,
@@ -333,16 +992,44 @@ impl DaphneWorkerConfig {
             admin_token,
             helper_state_store_garbage_collect_after_secs,
             processed_alarm_safety_interval,
+            task_garbage_collect_after_secs,
+            processed_compaction_delay,
             metrics_push_config,
+            metrics_task_labeler,
+            collection_receipt_signing_key,
+            upload_receipt_signing_key,
+            hpke_config_signing_key,
+            collection_export,
+            batch_events_queue,
+            read_only,
+            billing_enabled,
+            aggregation_telemetry_enabled,
+            dead_letter_ttl_secs,
+            request_time_budget,
+            auth_header_by_peer,
+            aggregation_content_encoding,
+            max_pending_reports_per_bucket,
+            health_max_queue_depth,
+            health_max_durable_latency_ms,
         })
     }
 
-    /// Derive the batch name for a report for the given task and with the given report ID.
+    /// Returns `true` if the operator is to be notified of taskprov tasks that are about to
+    /// expire.
+    pub(crate) fn taskprov_expiry_notification_enabled(&self) -> bool {
+        self.taskprov
+            .as_ref()
+            .is_some_and(|taskprov| taskprov.expiry_notification.is_some())
+    }
+
+    /// Derive the batch name for a report for the given task and with the given report ID, for a
+    /// report storage layout with the given number of shards.
     pub(crate) fn durable_name_report_store(
         &self,
         task_config: &DapTaskConfig,
         task_id_hex: &str,
         metadata: &ReportMetadata,
+        report_shard_count: u64,
     ) -> String {
         let mut shard_seed = [0; 8];
         PrgSha3::seed_stream(
@@ -351,12 +1038,42 @@ impl DaphneWorkerConfig {
             metadata.id.as_ref(),
         )
         .fill(&mut shard_seed);
-        let shard = u64::from_be_bytes(shard_seed) % self.report_shard_count;
+        let shard = u64::from_be_bytes(shard_seed) % report_shard_count;
         let epoch = metadata.time - (metadata.time % self.global.report_storage_epoch_duration);
         durable_name_report_store(&task_config.version, task_id_hex, epoch, shard)
     }
 }
 
+/// HTTP client used by the Leader to send requests to a Helper. Clients are cached per Helper
+/// origin so that connections, and thereby TLS sessions, are reused across aggregation rounds.
+#[derive(Clone)]
+pub(crate) struct HelperHttpClient(reqwest_wasm::Client);
+
+impl HelperHttpClient {
+    /// Build a client with the timeouts set in the global config. The client's timeouts are not
+    /// supported when compiled to WebAssembly, where requests are sent with the Fetch API; the
+    /// request timeout is enforced by [`DaphneWorker::send_http`] instead.
+    fn new(global: &DapGlobalConfig) -> std::result::Result<Self, DapError> {
+        #[allow(unused_mut)]
+        let mut builder = reqwest_wasm::Client::builder();
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if let Some(timeout) = global.helper_connect_timeout {
+                builder = builder.connect_timeout(Duration::from_secs(timeout));
+            }
+            if let Some(timeout) = global.helper_request_timeout {
+                builder = builder.timeout(Duration::from_secs(timeout));
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = global;
+        builder
+            .build()
+            .map(Self)
+            .map_err(|e| DapError::Fatal(format!("failed to build Helper HTTP client: {e}")))
+    }
+}
+
 /// Daphne-Worker per-isolate state, which may be used by multiple requests. Includes long-lived configuration,
 /// cached responses from KV, etc.
 pub(crate) struct DaphneWorkerIsolateState {
@@ -375,8 +1092,27 @@ pub(crate) struct DaphneWorkerIsolateState {
     /// Collector bearer token per task.
     collector_bearer_tokens: Arc<RwLock<HashMap<TaskId, BearerToken>>>,
 
+    /// Bearer token header configuration per task.
+    auth_header_configs: Arc<RwLock<HashMap<TaskId, DaphneWorkerAuthHeaderConfig>>>,
+
     /// Task list.
     tasks: Arc<RwLock<HashMap<TaskId, DapTaskConfig>>>,
+
+    /// HTTP clients for the Helper, keyed by origin.
+    helper_http_clients: Arc<RwLock<HashMap<String, HelperHttpClient>>>,
+
+    /// Names of the DO instances that have been registered for task garbage collection.
+    gc_registered_durable_names: Arc<RwLock<HashSet<String>>>,
+
+    /// Time at which the isolate last reset the count of consecutive unauthorized aggregation
+    /// jobs for each Helper, unless it has counted one since.
+    helper_auth_failures_cleared: Arc<RwLock<HashMap<Url, Time>>>,
+
+    /// Override of the global DAP configuration, as last read from KV.
+    global_config_override: Arc<RwLock<Option<CachedGlobalConfigOverride>>>,
+
+    /// Migration of the report storage, as last read from KV.
+    storage_migration: Arc<RwLock<Option<CachedStorageMigration>>>,
 }
 
 impl DaphneWorkerIsolateState {
@@ -392,15 +1128,75 @@ impl DaphneWorkerIsolateState {
             hpke_receiver_configs: Arc::new(RwLock::new(HashMap::new())),
             leader_bearer_tokens: Arc::new(RwLock::new(HashMap::new())),
             collector_bearer_tokens: Arc::new(RwLock::new(HashMap::new())),
+            auth_header_configs: Arc::new(RwLock::new(HashMap::new())),
             tasks: Arc::new(RwLock::new(HashMap::new())),
+            helper_http_clients: Arc::new(RwLock::new(HashMap::new())),
+            gc_registered_durable_names: Arc::new(RwLock::new(HashSet::new())),
+            helper_auth_failures_cleared: Arc::new(RwLock::new(HashMap::new())),
+            global_config_override: Arc::new(RwLock::new(None)),
+            storage_migration: Arc::new(RwLock::new(None)),
         })
     }
+
+    /// Cache the override of the global DAP configuration, unless the cached override has a more
+    /// recent version stamp.
+    fn cache_global_config_override(&self, global_config_override: GlobalConfigOverride) {
+        let mut guard = self
+            .global_config_override
+            .write()
+            .expect("global_config_override: failed to lock");
+        let global_config_override = match guard.take() {
+            Some(cached)
+                if cached.global_config_override.version > global_config_override.version =>
+            {
+                cached.global_config_override
+            }
+            _ => global_config_override,
+        };
+        *guard = Some(CachedGlobalConfigOverride {
+            fetched_at: now(),
+            global_config_override,
+        });
+    }
+
+    /// Cache the storage migration.
+    pub(crate) fn cache_storage_migration(&self, storage_migration: Option<StorageMigration>) {
+        *self
+            .storage_migration
+            .write()
+            .expect("storage_migration: failed to lock") = Some(CachedStorageMigration {
+            fetched_at: now(),
+            storage_migration,
+        });
+    }
 }
 
 /// Daphne-Worker per-request state.
 pub(crate) struct DaphneWorkerRequestState<'srv> {
     pub(crate) isolate_state: &'srv DaphneWorkerIsolateState,
 
+    /// Handlers for report extensions, registered with the router.
+    pub(crate) extension_registry: &'srv DapExtensionRegistry,
+
+    /// Provider of HPKE decryption, registered with the router. If not set, then HPKE receiver
+    /// configs are stored in KV.
+    pub(crate) hpke_provider: Option<&'srv dyn DaphneWorkerHpkeProvider>,
+
+    /// Source of the current time, registered with the router. If not set, then the clock of the
+    /// Workers runtime is used.
+    pub(crate) clock: Option<&'srv dyn Clock>,
+
+    /// Global DAP configuration, with the override set by the administrator applied. See
+    /// [`Self::load_global_config_override`].
+    pub(crate) global_config: DapGlobalConfig,
+
+    /// Migration of the report storage, if any. See [`Self::load_storage_migration`].
+    pub(crate) storage_migration: Option<StorageMigration>,
+
+    /// Whether requests that modify storage are refused, i.e., `DAP_READ_ONLY`, unless overridden
+    /// by the administrator. See [`Self::load_global_config_override`].
+    pub(crate) read_only: bool,
+
     /// Registry for Prometheus metrics collected while handling the request.
     #[allow(dead_code)]
     pub(crate) prometheus_registry: Registry,
@@ -411,27 +1207,97 @@ pub(crate) struct DaphneWorkerRequestState<'srv> {
     /// Hostname parsed from the HTTP request URL. Set to "unspecified-daphne-worker-hsot" if the
     /// hostname is not part of the URL.
     pub(crate) host: String,
+
+    /// Path of the HTTP request URL, if handling an HTTP request. Used as the "instance" member
+    /// of problem details documents.
+    pub(crate) path: Option<String>,
+
+    /// Path prefix under which the endpoints are mounted. See
+    /// [`DaphneWorkerRouter::path_prefix`](crate::DaphneWorkerRouter::path_prefix).
+    pub(crate) path_prefix: &'srv str,
+
+    /// Identifies the request in log lines, in abort responses, and in the requests made to the
+    /// Helper on its behalf. Adopted from the "dap-correlation-id" header of the request, if set.
+    pub(crate) correlation_id: String,
+
+    /// Time (in milliseconds since the UNIX epoch) by which the request must be handled, if a
+    /// request time budget is configured. Sub-requests to DOs are refused once the deadline is
+    /// near.
+    pub(crate) deadline: Option<u64>,
+
+    /// Leader: Position in the aggregation job queue at which the next run of the processing loop
+    /// should resume. Set while fetching reports and returned to the caller of the loop.
+    pub(crate) process_cursor: RefCell<Option<String>>,
 }
 
 impl<'srv> DaphneWorkerRequestState<'srv> {
     pub(crate) fn new(
         isolate_state: &'srv DaphneWorkerIsolateState,
+        extension_registry: &'srv DapExtensionRegistry,
+        hpke_provider: Option<&'srv dyn DaphneWorkerHpkeProvider>,
+        clock: Option<&'srv dyn Clock>,
         req: &Request,
     ) -> Result<Self> {
-        let prometheus_registry = Registry::new();
-        let metrics = DaphneWorkerMetrics::register(&prometheus_registry, None)
-            .map_err(|e| Error::RustError(format!("failed to register metrics: {e}")))?;
-        let host = req
-            .url()?
+        let url = req.url()?;
+        let host = url
             .host_str()
             .unwrap_or("unspecified-daphne-worker-host")
             .to_string();
+        let mut state = Self::with_host(
+            isolate_state,
+            extension_registry,
+            hpke_provider,
+            clock,
+            host,
+        )?;
+        state.path = Some(url.path().to_string());
+        state.correlation_id = correlation_id_for_request(req)?;
+        Ok(state)
+    }
+
+    /// Create the state for handling an event that is not an HTTP request, e.g., a scheduled
+    /// event. `host` is used to label metrics.
+    pub(crate) fn with_host(
+        isolate_state: &'srv DaphneWorkerIsolateState,
+        extension_registry: &'srv DapExtensionRegistry,
+        hpke_provider: Option<&'srv dyn DaphneWorkerHpkeProvider>,
+        clock: Option<&'srv dyn Clock>,
+        host: String,
+    ) -> Result<Self> {
+        let prometheus_registry = Registry::new();
+        let mut metrics = DaphneWorkerMetrics::register(
+            &prometheus_registry,
+            None,
+            isolate_state.config.metrics_task_labeler.clone(),
+        )
+        .map_err(|e| Error::RustError(format!("failed to register metrics: {e}")))?;
+        if isolate_state.config.aggregation_telemetry_enabled {
+            metrics.daphne = metrics.daphne.with_report_tally();
+        }
+        if isolate_state.config.dead_letter_ttl_secs.is_some() {
+            metrics.daphne = metrics.daphne.with_dead_letters();
+        }
+        let deadline = isolate_state
+            .config
+            .request_time_budget
+            .map(|budget| Date::now().as_millis() + budget.as_millis() as u64);
 
         Ok(Self {
             isolate_state,
+            extension_registry,
+            hpke_provider,
+            clock,
+            global_config: isolate_state.config.global.clone(),
+            storage_migration: None,
+            read_only: isolate_state.config.read_only,
             prometheus_registry,
             metrics,
             host,
+            path: None,
+            path_prefix: "",
+            correlation_id: new_correlation_id(),
+            deadline,
+            process_cursor: RefCell::new(None),
         })
     }
 
@@ -439,20 +1305,95 @@ impl<'srv> DaphneWorkerRequestState<'srv> {
         DaphneWorker { state: self, env }
     }
 
-    /// If configured, gather metrics and push to Prometheus server.
-    pub(crate) async fn maybe_push_metrics(&self) -> Result<()> {
-        // Prepare text exposition of metrics.
-        let mut buf = Vec::new();
-        let encoder = prometheus::TextEncoder::new();
-        let metrics_familes = self.prometheus_registry.gather();
-        encoder
-            .encode(&metrics_familes, &mut buf)
-            .expect("failed to encode metrics");
-        let text_metrics = String::from_utf8(buf).expect("text encoding of metrics is not UTF8");
-        info!("Prometheus summary:\n{text_metrics}");
+    /// Apply the override of the global DAP configuration set by the administrator, if any. The
+    /// override is read from KV if the isolate's copy is missing or stale.
+    pub(crate) async fn load_global_config_override(&mut self, env: &Env) -> Result<()> {
+        let isolate_state = self.isolate_state;
+        let is_fresh = isolate_state
+            .global_config_override
+            .read()
+            .expect("global_config_override: failed to lock")
+            .as_ref()
+            .is_some_and(|cached| {
+                now()
+                    < cached
+                        .fetched_at
+                        .saturating_add(GLOBAL_CONFIG_OVERRIDE_REFRESH_SECS)
+            });
+        if !is_fresh {
+            let global_config_override: Option<GlobalConfigOverride> = env
+                .kv(KV_BINDING_DAP_CONFIG)?
+                .get(KV_KEY_GLOBAL_CONFIG_OVERRIDE)
+                .json()
+                .await?;
+            isolate_state.cache_global_config_override(global_config_override.unwrap_or_default());
+        }
 
-        if let Some(ref metrics_push_config) = self.isolate_state.config.metrics_push_config {
-            // Prepare authorization.
+        let mut global_config = isolate_state.config.global.clone();
+        let mut read_only = isolate_state.config.read_only;
+        if let Some(cached) = isolate_state
+            .global_config_override
+            .read()
+            .expect("global_config_override: failed to lock")
+            .as_ref()
+        {
+            cached.global_config_override.apply(&mut global_config);
+            if let Some(read_only_override) = cached.global_config_override.read_only {
+                read_only = read_only_override;
+            }
+        }
+        self.global_config = global_config;
+        self.read_only = read_only;
+        Ok(())
+    }
+
+    /// Load the migration of the report storage, if any. The migration is read from KV if the
+    /// isolate's copy is missing or stale.
+    pub(crate) async fn load_storage_migration(&mut self, env: &Env) -> Result<()> {
+        let isolate_state = self.isolate_state;
+        let is_fresh = isolate_state
+            .storage_migration
+            .read()
+            .expect("storage_migration: failed to lock")
+            .as_ref()
+            .is_some_and(|cached| {
+                now()
+                    < cached
+                        .fetched_at
+                        .saturating_add(STORAGE_MIGRATION_REFRESH_SECS)
+            });
+        if !is_fresh {
+            let storage_migration: Option<StorageMigration> = env
+                .kv(KV_BINDING_DAP_CONFIG)?
+                .get(KV_KEY_STORAGE_MIGRATION)
+                .json()
+                .await?;
+            isolate_state.cache_storage_migration(storage_migration);
+        }
+
+        self.storage_migration = isolate_state
+            .storage_migration
+            .read()
+            .expect("storage_migration: failed to lock")
+            .as_ref()
+            .and_then(|cached| cached.storage_migration.clone());
+        Ok(())
+    }
+
+    /// If configured, gather metrics and push to Prometheus server.
+    pub(crate) async fn maybe_push_metrics(&self) -> Result<()> {
+        // Prepare text exposition of metrics.
+        let mut buf = Vec::new();
+        let encoder = prometheus::TextEncoder::new();
+        let metrics_familes = self.prometheus_registry.gather();
+        encoder
+            .encode(&metrics_familes, &mut buf)
+            .expect("failed to encode metrics");
+        let text_metrics = String::from_utf8(buf).expect("text encoding of metrics is not UTF8");
+        info!("Prometheus summary:\n{text_metrics}");
+
+        if let Some(ref metrics_push_config) = self.isolate_state.config.metrics_push_config {
+            // Prepare authorization.
             let mut headers = reqwest_wasm::header::HeaderMap::new();
             let bearer_token: &str = metrics_push_config.bearer_token.as_ref();
             headers.insert(
@@ -485,22 +1426,27 @@ impl<'srv> DaphneWorkerRequestState<'srv> {
     }
 
     pub(crate) fn dap_abort_to_worker_response(&self, e: DapAbort) -> Result<Response> {
-        let status = if matches!(e, DapAbort::Internal(..)) {
-            500
-        } else {
-            400
+        let status = e.status_code();
+        let retry_after = match &e {
+            DapAbort::Overloaded { retry_after, .. } => Some(retry_after.to_string()),
+            _ if status == 503 => Some(RETRY_AFTER_SECS.to_string()),
+            _ => None,
         };
         self.metrics
             .dap_abort_counter
             .with_label_values(&[&self.host, &e.to_string()])
             .inc();
-        let problem_details = e.into_problem_details();
+        let mut problem_details = e.into_problem_details(self.path.clone());
+        problem_details.correlation_id = Some(self.correlation_id.clone());
         error!(
             "request aborted: {}",
             serde_json::to_string(&problem_details)?
         );
         let mut headers = Headers::new();
         headers.set("Content-Type", "application/problem+json")?;
+        if let Some(retry_after) = retry_after {
+            headers.set("Retry-After", &retry_after)?;
+        }
         Ok(Response::from_json(&problem_details)?
             .with_status(status)
             .with_headers(headers))
@@ -515,13 +1461,22 @@ pub(crate) struct DaphneWorker<'srv> {
 
 impl<'srv> DaphneWorker<'srv> {
     pub(crate) fn durable(&self) -> DurableConnector<'_> {
-        DurableConnector::new(self.env)
+        let durable = DurableConnector::new(self.env).with_deadline(self.state.deadline);
+        if self.config().task_garbage_collect_after_secs.is_some() {
+            durable.with_gc_registry(&self.isolate_state().gc_registered_durable_names)
+        } else {
+            durable
+        }
     }
 
     pub(crate) fn kv(&self) -> Result<KvStore> {
         self.env.kv(KV_BINDING_DAP_CONFIG)
     }
 
+    pub(crate) fn queue(&self, binding: &str) -> Result<Queue> {
+        self.env.queue(binding)
+    }
+
     pub(crate) fn config(&'srv self) -> &'srv DaphneWorkerConfig {
         &self.state.isolate_state.config
     }
@@ -530,6 +1485,15 @@ impl<'srv> DaphneWorker<'srv> {
         self.state.isolate_state
     }
 
+    /// The provider of HPKE decryption: either the one registered with the router or, by default,
+    /// KV.
+    pub(crate) fn hpke_provider(&self) -> &dyn DaphneWorkerHpkeProvider {
+        match self.state.hpke_provider {
+            Some(hpke_provider) => hpke_provider,
+            None => self,
+        }
+    }
+
     /// Set a key/value pair unless the key already exists. If the key exists, then return the current
     /// value. Otherwise return nothing.
     async fn kv_set_if_not_exists<K, V>(
@@ -621,6 +1585,171 @@ impl<'srv> DaphneWorker<'srv> {
         .await
     }
 
+    /// List the HPKE receiver configs stored in KV for the given DAP version, along with their
+    /// validity windows. Configs that were stored without a validity window have none.
+    pub(crate) async fn list_hpke_receiver_configs(
+        &self,
+        version: DapVersion,
+    ) -> Result<Vec<(HpkeReceiverKvKey, Option<HpkeConfigValidity>)>> {
+        let keys = self
+            .kv()?
+            .list()
+            .prefix(format!(
+                "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/version/{version}/"
+            ))
+            .execute()
+            .await?;
+
+        keys.keys
+            .into_iter()
+            .map(|key| {
+                let hpke_receiver_kv_key =
+                    HpkeReceiverKvKey::try_from_name(&key.name).map_err(int_err)?;
+                let validity = key
+                    .metadata
+                    .map(serde_json::from_value)
+                    .transpose()
+                    .map_err(int_err)?;
+                Ok((hpke_receiver_kv_key, validity))
+            })
+            .collect()
+    }
+
+    /// Rotate the HPKE receiver configs for the given DAP version according to the rotation
+    /// parameters in the global config. Configs whose grace period has elapsed are deleted, and a
+    /// new config is generated if the newest config is at least one rotation period old. Configs
+    /// that were stored without a validity window are left alone. Returns the new config, if any.
+    pub(crate) async fn rotate_hpke_receiver_configs(
+        &self,
+        version: DapVersion,
+    ) -> Result<Option<HpkeConfig>> {
+        if version == DapVersion::Unknown {
+            return Err(int_err("unknown DAP version"));
+        }
+        let rotation = self
+            .config()
+            .global
+            .hpke_rotation
+            .as_ref()
+            .ok_or_else(|| int_err("HPKE config rotation is not configured"))?;
+        let kem_id = match self.config().global.supported_hpke_kems.as_slice() {
+            [kem_id] => *kem_id,
+            _ => return Err(int_err("The number of supported HPKE KEMs must be 1")),
+        };
+
+        let now = self.get_current_time();
+        let kv_store = self.kv()?;
+        let mut hpke_config_ids_in_use = HashSet::new();
+        let mut newest_not_before = None;
+        for (hpke_receiver_kv_key, validity) in self.list_hpke_receiver_configs(version).await? {
+            if let Some(validity) = validity {
+                if rotation.is_expired(&validity, now) {
+                    kv_store
+                        .delete(&format!(
+                            "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/{hpke_receiver_kv_key}"
+                        ))
+                        .await?;
+                    self.isolate_state()
+                        .hpke_receiver_configs
+                        .write()
+                        .map_err(|e| int_err(format!("Failed to lock map for writing: {e}")))?
+                        .remove(&hpke_receiver_kv_key);
+                    info!(
+                        "deleted expired HPKE config {}",
+                        hpke_receiver_kv_key.hpke_config_id
+                    );
+                    continue;
+                }
+                newest_not_before = newest_not_before.max(Some(validity.not_before));
+            }
+            hpke_config_ids_in_use.insert(hpke_receiver_kv_key.hpke_config_id);
+        }
+
+        if !rotation.is_rotation_due(newest_not_before, now) {
+            return Ok(None);
+        }
+
+        // Pick an unused config ID, starting from a random one.
+        let first_config_id: u8 = rand::random();
+        let hpke_config_id = (0..=u8::MAX)
+            .map(|i| first_config_id.wrapping_add(i))
+            .find(|hpke_config_id| !hpke_config_ids_in_use.contains(hpke_config_id))
+            .ok_or_else(|| int_err("all HPKE config IDs are in use"))?;
+        let receiver_config = HpkeReceiverConfig::gen(hpke_config_id, kem_id).map_err(int_err)?;
+        let hpke_config = receiver_config.config.clone();
+
+        kv_store
+            .put(
+                &format!(
+                    "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/{}",
+                    HpkeReceiverKvKey {
+                        version,
+                        hpke_config_id,
+                    }
+                ),
+                receiver_config,
+            )?
+            .metadata(rotation.validity_for_new_config(now))?
+            .execute()
+            .await?;
+        info!("generated HPKE config {hpke_config_id}");
+        Ok(Some(hpke_config))
+    }
+
+    /// Install HPKE receiver configs that were generated ahead of time, e.g., offline with `dapf`.
+    /// The request is rejected if any of the config IDs is already in use.
+    pub(crate) async fn import_hpke_receiver_configs(
+        &self,
+        version: DapVersion,
+        configs: Vec<HpkeReceiverConfigWithValidity>,
+    ) -> Result<()> {
+        if version == DapVersion::Unknown {
+            return Err(int_err("unknown DAP version"));
+        }
+
+        let kv_store = self.kv()?;
+        let mut kv_keys = Vec::with_capacity(configs.len());
+        for config in configs.iter() {
+            let hpke_config = &config.receiver_config.config;
+            if !self
+                .config()
+                .global
+                .supported_hpke_kems
+                .contains(&hpke_config.kem_id)
+            {
+                return Err(int_err(format!(
+                    "HPKE config {} uses an unsupported KEM",
+                    hpke_config.id
+                )));
+            }
+
+            let kv_key = format!(
+                "{KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG}/{}",
+                HpkeReceiverKvKey {
+                    version,
+                    hpke_config_id: hpke_config.id,
+                }
+            );
+            if kv_keys.contains(&kv_key) || kv_store.get(&kv_key).text().await?.is_some() {
+                return Err(int_err(format!(
+                    "HPKE config {} already exists",
+                    hpke_config.id
+                )));
+            }
+            kv_keys.push(kv_key);
+        }
+
+        // The validity window is stored as metadata so that it is returned when listing keys.
+        for (kv_key, config) in kv_keys.into_iter().zip(configs) {
+            kv_store
+                .put(&kv_key, config.receiver_config)?
+                .metadata(config.validity)?
+                .execute()
+                .await?;
+        }
+        Ok(())
+    }
+
     /// Retrieve from KV the Leader's bearer token for the given task.
     pub(crate) async fn get_leader_bearer_token<'a>(
         &'a self,
@@ -657,6 +1786,89 @@ impl<'srv> DaphneWorker<'srv> {
         .await
     }
 
+    /// Retrieve from KV the bearer token header configuration for the given task, if any.
+    pub(crate) async fn get_auth_header_config<'a>(
+        &self,
+        task_id: &'a TaskId,
+    ) -> Result<Option<Guarded<'a, TaskId, DaphneWorkerAuthHeaderConfig>>>
+    where
+        'srv: 'a,
+    {
+        self.kv_get_cached(
+            &self.state.isolate_state.auth_header_configs,
+            KV_KEY_PREFIX_AUTH_HEADER,
+            Cow::Borrowed(task_id),
+        )
+        .await
+    }
+
+    /// Set the bearer token header configuration for the given task.
+    pub(crate) async fn set_auth_header_config(
+        &self,
+        task_id: &TaskId,
+        config: &DaphneWorkerAuthHeaderConfig,
+    ) -> Result<Option<DaphneWorkerAuthHeaderConfig>> {
+        self.kv_set_if_not_exists(KV_KEY_PREFIX_AUTH_HEADER, task_id, config.clone())
+            .await
+    }
+
+    /// Determine which bearer token headers are accepted from, and emitted to, the given peer for
+    /// the given task. The task's configuration takes precedence over the configuration of the
+    /// peer Aggregator. If neither is configured, then only the "DAP-Auth-Token" header is used.
+    pub(crate) async fn resolve_auth_header_config(
+        &self,
+        task_id: &TaskId,
+        peer: Option<DapSender>,
+    ) -> Result<DaphneWorkerAuthHeaderConfig> {
+        if let Some(config) = self.get_auth_header_config(task_id).await? {
+            return Ok(config.value().clone());
+        }
+
+        let task_config = self
+            .kv_get_cached(
+                &self.state.isolate_state.tasks,
+                KV_KEY_PREFIX_TASK_CONFIG,
+                Cow::Borrowed(task_id),
+            )
+            .await?;
+        let peer_url = match (task_config.as_ref(), peer) {
+            (Some(task_config), Some(DapSender::Leader)) => Some(&task_config.value().leader_url),
+            (Some(task_config), Some(DapSender::Helper)) => Some(&task_config.value().helper_url),
+            _ => None,
+        };
+        Ok(peer_url
+            .and_then(|url| url.host_str())
+            .and_then(|host| {
+                self.state
+                    .isolate_state
+                    .config
+                    .auth_header_by_peer
+                    .get(host)
+            })
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Retrieve from KV the bearer token of the task's Collector with the given ID. Unlike the
+    /// primary Collector's token, this token is not cached, since collection requests are rare.
+    pub(crate) async fn get_additional_collector_bearer_token(
+        &self,
+        task_id: &TaskId,
+        collector_id: &str,
+    ) -> Result<Option<BearerToken>> {
+        if !is_valid_collector_id(collector_id) {
+            return Ok(None);
+        }
+        self.kv()?
+            .get(&additional_collector_bearer_token_kv_key(
+                task_id,
+                collector_id,
+            ))
+            .json()
+            .await
+            .map_err(Error::from)
+    }
+
     /// Retrieve from KV the configuration for the given task.
     pub(crate) async fn get_task_config<'req>(
         &'srv self,
@@ -683,6 +1895,17 @@ impl<'srv> DaphneWorker<'srv> {
             .await
     }
 
+    /// Record in KV that the given task was configured by taskprov, unless already recorded.
+    pub(crate) async fn set_taskprov_task(&self, task_id: &TaskId) -> Result<()> {
+        self.kv_set_if_not_exists(
+            KV_KEY_PREFIX_TASKPROV_TASK,
+            task_id,
+            TaskprovTask::default(),
+        )
+        .await?;
+        Ok(())
+    }
+
     /// Try retrieving from KV the configuration for the given task. Return an error if the
     /// indicated task is not recognized.
     pub(crate) async fn try_get_task_config<'req>(
@@ -698,6 +1921,321 @@ impl<'srv> DaphneWorker<'srv> {
             .ok_or(DapError::Abort(DapAbort::UnrecognizedTask))
     }
 
+    /// Get the override of the global DAP configuration that is stored in KV.
+    pub(crate) async fn get_global_config_override(&self) -> Result<GlobalConfigOverride> {
+        let global_config_override: Option<GlobalConfigOverride> =
+            self.kv()?.get(KV_KEY_GLOBAL_CONFIG_OVERRIDE).json().await?;
+        Ok(global_config_override.unwrap_or_default())
+    }
+
+    /// Replace the override of the global DAP configuration. The update is refused, and `false` is
+    /// returned, if its version stamp does not succeed the stamp of the stored override.
+    pub(crate) async fn set_global_config_override(
+        &self,
+        global_config_override: GlobalConfigOverride,
+    ) -> Result<bool> {
+        if global_config_override.allow_taskprov == Some(true) && self.config().taskprov.is_none() {
+            return Err(int_err("taskprov is not configured"));
+        }
+        if global_config_override.max_batch_duration == Some(0) {
+            return Err(int_err("max_batch_duration must be positive"));
+        }
+
+        let current = self.get_global_config_override().await?;
+        if global_config_override.version != current.version + 1 {
+            return Ok(false);
+        }
+
+        self.kv()?
+            .put(KV_KEY_GLOBAL_CONFIG_OVERRIDE, &global_config_override)?
+            .execute()
+            .await?;
+        self.isolate_state()
+            .cache_global_config_override(global_config_override);
+        Ok(true)
+    }
+
+    /// Leader: Get the unexpired entries of the quarantine list.
+    pub(crate) async fn get_quarantine(&self) -> Result<Vec<QuarantinedBuckets>> {
+        let quarantine: Option<Vec<QuarantinedBuckets>> =
+            self.kv()?.get(KV_KEY_QUARANTINE).json().await?;
+        let now = self.get_current_time();
+        Ok(quarantine
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| entry.expiration > now)
+            .collect())
+    }
+
+    /// Leader: Replace the quarantine list.
+    pub(crate) async fn set_quarantine(&self, quarantine: Vec<QuarantinedBuckets>) -> Result<()> {
+        let epoch_duration = self.config().global.report_storage_epoch_duration;
+        let mut num_report_stores = 0;
+        for entry in quarantine.iter() {
+            if TaskId::try_from_base64url(&entry.task_id).is_none() {
+                return Err(int_err("task ID is not valid URL-safe base64"));
+            }
+            if entry.start >= entry.end {
+                return Err(int_err("time range is empty"));
+            }
+            let first_epoch = entry.start - (entry.start % epoch_duration);
+            num_report_stores +=
+                (entry.end - first_epoch).div_ceil(epoch_duration) * self.report_shard_count_max();
+        }
+        if num_report_stores > MAX_QUARANTINED_REPORT_STORES {
+            return Err(int_err(format!(
+                "quarantine covers too many report stores: got {num_report_stores}; want at most {MAX_QUARANTINED_REPORT_STORES}"
+            )));
+        }
+
+        self.kv()?
+            .put(KV_KEY_QUARANTINE, quarantine)?
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Count usage of the given task towards the current day, if billing is enabled.
+    pub(crate) async fn record_task_usage(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        usage: TaskUsage,
+    ) -> std::result::Result<(), DapError> {
+        if !self.config().billing_enabled {
+            return Ok(());
+        }
+
+        self.durable()
+            .post(
+                BINDING_DAP_TASK_USAGE_STORE,
+                DURABLE_TASK_USAGE_STORE_ADD,
+                durable_name_task_usage(
+                    &task_config.version,
+                    &task_id.to_hex(),
+                    day_start(self.get_current_time()),
+                ),
+                &usage,
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    /// Get the billing report for the given task, as of the last rollup.
+    pub(crate) async fn get_task_billing(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<Option<TaskBillingReport>> {
+        self.kv()?
+            .get(&format!("{KV_KEY_PREFIX_TASK_BILLING}/{task_id}"))
+            .json()
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Roll up the daily usage counts of the given task into its billing report. Returns `None`
+    /// if the task is not recognized.
+    pub(crate) async fn rollup_task_billing(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<Option<TaskBillingReport>> {
+        let task_config = match self.get_task_config(Cow::Borrowed(task_id)).await? {
+            Some(task_config) => task_config,
+            None => return Ok(None),
+        };
+        let task_id_hex = task_id.to_hex();
+        let now = self.get_current_time();
+
+        let mut report = self.get_task_billing(task_id).await?.unwrap_or_default();
+        let days: Vec<Time> = (report.rollup_start(now)..=day_start(now))
+            .step_by(SECONDS_PER_DAY.try_into().map_err(int_err)?)
+            .collect();
+        let durable = self.durable();
+        let usages: Vec<TaskUsage> = try_join_all(days.iter().map(|day| {
+            durable.get(
+                BINDING_DAP_TASK_USAGE_STORE,
+                DURABLE_TASK_USAGE_STORE_GET,
+                durable_name_task_usage(&task_config.as_ref().version, &task_id_hex, *day),
+            )
+        }))
+        .await?;
+        for (day, usage) in days.into_iter().zip(usages) {
+            report.record_day(day, usage);
+        }
+        report.prune(now);
+        report.updated_at = now;
+
+        self.kv()?
+            .put(&format!("{KV_KEY_PREFIX_TASK_BILLING}/{task_id}"), &report)?
+            .execute()
+            .await?;
+        Ok(Some(report))
+    }
+
+    /// Leader: Defer the aggregation jobs for the given Helper until the given time. KV requires
+    /// entries to live for at least a minute, so the entry may outlive the deferral.
+    pub(crate) async fn put_helper_deferral(&self, helper_url: &Url, until: Time) -> Result<()> {
+        self.kv()?
+            .put(
+                &format!("{KV_KEY_PREFIX_HELPER_DEFERRAL}/{helper_url}"),
+                until.to_string(),
+            )?
+            .expiration(until.max(now() + 60))
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Leader: Get the time until which the aggregation jobs for the given Helper are deferred.
+    pub(crate) async fn get_helper_deferral(&self, helper_url: &Url) -> Result<Option<Time>> {
+        self.kv()?
+            .get(&format!("{KV_KEY_PREFIX_HELPER_DEFERRAL}/{helper_url}"))
+            .text()
+            .await?
+            .map(|until| until.parse().map_err(int_err))
+            .transpose()
+    }
+
+    /// Leader: Count an aggregation job the given Helper rejected as unauthorized. Returns the
+    /// number of consecutive aggregation jobs the Helper rejected this way. Since KV is eventually
+    /// consistent, the count is a lower bound.
+    pub(crate) async fn incr_helper_auth_failures(&self, helper_url: &Url) -> Result<u32> {
+        self.isolate_state()
+            .helper_auth_failures_cleared
+            .write()
+            .expect("helper_auth_failures_cleared: failed to lock")
+            .remove(helper_url);
+        let kv_store = self.kv()?;
+        let key = format!("{KV_KEY_PREFIX_HELPER_AUTH_FAILURES}/{helper_url}");
+        let failures = kv_store
+            .get(&key)
+            .text()
+            .await?
+            .map(|failures| failures.parse::<u32>().map_err(int_err))
+            .transpose()?
+            .unwrap_or(0)
+            + 1;
+        kv_store.put(&key, failures.to_string())?.execute().await?;
+        Ok(failures)
+    }
+
+    /// Leader: Reset the count of consecutive aggregation jobs the given Helper rejected as
+    /// unauthorized. The entry is only deleted if it exists, so that jobs that succeed do not each
+    /// write to KV. Once the isolate has reset the count, it skips KV altogether for
+    /// `HELPER_AUTH_FAILURES_CLEARED_SECS`, unless it counts a failure in the meantime.
+    pub(crate) async fn delete_helper_auth_failures(&self, helper_url: &Url) -> Result<()> {
+        let is_cleared = self
+            .isolate_state()
+            .helper_auth_failures_cleared
+            .read()
+            .expect("helper_auth_failures_cleared: failed to lock")
+            .get(helper_url)
+            .is_some_and(|cleared_at| {
+                now() < cleared_at.saturating_add(HELPER_AUTH_FAILURES_CLEARED_SECS)
+            });
+        if is_cleared {
+            return Ok(());
+        }
+
+        let kv_store = self.kv()?;
+        let key = format!("{KV_KEY_PREFIX_HELPER_AUTH_FAILURES}/{helper_url}");
+        if kv_store.get(&key).text().await?.is_some() {
+            kv_store.delete(&key).await?;
+        }
+        self.isolate_state()
+            .helper_auth_failures_cleared
+            .write()
+            .expect("helper_auth_failures_cleared: failed to lock")
+            .insert(helper_url.clone(), now());
+        Ok(())
+    }
+
+    /// Leader: Count an abandoned aggregation job towards each of the given reports. Returns the
+    /// number of abandoned aggregation jobs each report has been part of. The count is kept in KV
+    /// until the report is too old to be aggregated. Since KV is eventually consistent, the count
+    /// is a lower bound.
+    pub(crate) async fn incr_abandoned_report_counts(
+        &self,
+        task_id: &TaskId,
+        reports: &[Report],
+    ) -> Result<Vec<u32>> {
+        let kv_store = self.kv()?;
+        let epoch_duration = self.config().global.report_storage_epoch_duration;
+        try_join_all(reports.iter().map(|report| {
+            let kv_store = &kv_store;
+            let key = format!(
+                "{KV_KEY_PREFIX_ABANDONED_REPORT}/{}/{}",
+                task_id.to_hex(),
+                report.report_metadata.id.to_hex()
+            );
+            let expiration = report
+                .report_metadata
+                .time
+                .saturating_add(epoch_duration)
+                .max(now() + 60);
+            async move {
+                let attempts = kv_store
+                    .get(&key)
+                    .text()
+                    .await?
+                    .map(|attempts| attempts.parse::<u32>().map_err(int_err))
+                    .transpose()?
+                    .unwrap_or(0)
+                    + 1;
+                kv_store
+                    .put(&key, attempts.to_string())?
+                    .expiration(expiration)
+                    .execute()
+                    .await?;
+                Ok::<_, Error>(attempts)
+            }
+        }))
+        .await
+    }
+
+    /// Leader: Get the IDs of the ReportsPending instances that are currently quarantined.
+    pub(crate) async fn quarantined_reports_pending(
+        &self,
+    ) -> std::result::Result<HashSet<String>, DapError> {
+        let durable = self.durable();
+        let epoch_duration = self.config().global.report_storage_epoch_duration;
+        let mut ids = HashSet::new();
+        for entry in self.get_quarantine().await.map_err(dap_err)? {
+            let task_config = match TaskId::try_from_base64url(&entry.task_id) {
+                Some(task_id) => self
+                    .get_task_config(Cow::Owned(task_id))
+                    .await
+                    .map_err(dap_err)?,
+                None => None,
+            };
+            let task_config = match task_config {
+                Some(task_config) => task_config,
+                // Entries for unrecognized tasks have no effect.
+                None => continue,
+            };
+            let task_id_hex = task_config.key().to_hex();
+
+            let mut epoch = entry.start - (entry.start % epoch_duration);
+            while epoch < entry.end {
+                for shard in 0..self.report_shard_count_max() {
+                    let durable_name = durable_name_report_store(
+                        &task_config.as_ref().version,
+                        &task_id_hex,
+                        epoch,
+                        shard,
+                    );
+                    ids.insert(
+                        durable
+                            .id_hex_from_name(BINDING_DAP_REPORTS_PENDING, &durable_name)
+                            .map_err(dap_err)?,
+                    );
+                }
+                epoch += epoch_duration;
+            }
+        }
+        Ok(ids)
+    }
+
     /// Clear all persistant durable objects storage.
     ///
     /// TODO(cjpatton) Gate this to non-prod deployments. (Prod should do migration.)
@@ -760,71 +2298,231 @@ impl<'srv> DaphneWorker<'srv> {
         }
     }
 
-    /// Get the URL to use for this endpoint, as required by
-    /// draft-dcook-ppm-dap-interop-test-design-02.
-    pub(crate) async fn internal_endpoint_for_task(
+    /// Close the batch currently being filled, if any report has been assigned to it. This method
+    /// is only applicable to fixed-size tasks.
+    pub(crate) async fn internal_close_current_batch(
         &self,
-        version: DapVersion,
-        cmd: InternalTestEndpointForTask,
-    ) -> Result<Response> {
-        if self.config().is_leader && !matches!(cmd.role, InternalTestRole::Leader)
-            || !self.config().is_leader && !matches!(cmd.role, InternalTestRole::Helper)
-        {
-            return Response::from_json(&serde_json::json!({
-                "status": "error",
-                "error": "role mismatch",
-            }));
+        task_id: &TaskId,
+    ) -> std::result::Result<Option<BatchId>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        if !matches!(task_config.as_ref().query, DapQueryConfig::FixedSize { .. }) {
+            return Err(DapError::fatal("query type mismatch"));
         }
 
-        let path = self
-            .config()
-            .base_url
-            .as_ref()
-            .ok_or_else(|| {
-                Error::RustError(format!(
-                    "Environment variable {DAP_BASE_URL} not configured"
-                ))
-            })?
-            .path();
-
-        Response::from_json(&serde_json::json!({
-            "status": "success",
-            "endpoint": format!("{path}{}/", version.as_ref()),
-        }))
+        self.durable()
+            .post(
+                BINDING_DAP_LEADER_BATCH_QUEUE,
+                DURABLE_LEADER_BATCH_QUEUE_CLOSE,
+                durable_name_task(&task_config.as_ref().version, &task_id.to_hex()),
+                &(),
+            )
+            .await
+            .map_err(dap_err)
     }
 
-    /// Configure Daphne-Worker a task, as required by draft-dcook-ppm-dap-interop-test-design-02.
-    pub(crate) async fn internal_add_task(
+    /// Force a batch of the given fixed-size task to be collectable regardless of the task's
+    /// minimum batch size. If `batch_id` is not set, then the Leader closes the batch currently
+    /// being filled (see [`DapLeader::force_close_current_batch`]); the Helper requires the ID of
+    /// the batch closed by the Leader. Returns the ID of the batch that was marked as forced, if
+    /// any.
+    pub(crate) async fn internal_force_close_batch(
         &self,
-        version: DapVersion,
-        cmd: InternalTestAddTask,
-    ) -> Result<()> {
-        // Task ID.
-        let task_id = TaskId::try_from_base64url(&cmd.task_id)
-            .ok_or_else(|| int_err("task ID is not valid URL-safe base64"))?;
-
-        // VDAF config.
-        let vdaf = match (cmd.vdaf.typ.as_ref(), cmd.vdaf.bits) {
-            ("Prio3Count", None) => VdafConfig::Prio3(Prio3Config::Count),
-            ("Prio3Sum", Some(bits)) => {
-                let bits = bits.parse().map_err(int_err)?;
-                VdafConfig::Prio3(Prio3Config::Sum { bits })
+        task_id: &TaskId,
+        batch_id: Option<BatchId>,
+    ) -> std::result::Result<Option<BatchId>, DapError> {
+        let batch_id = match batch_id {
+            Some(batch_id) => batch_id,
+            None if self.config().is_leader => {
+                return self.force_close_current_batch(task_id).await;
+            }
+            None => {
+                return Err(DapError::Abort(DapAbort::BadRequest(
+                    "missing batch ID".into(),
+                )))
             }
-            _ => return Err(int_err("command failed: unrecognized VDAF")),
         };
 
+        let task_config = self.try_get_task_config(task_id).await?;
+        if !matches!(task_config.as_ref().query, DapQueryConfig::FixedSize { .. }) {
+            return Err(DapError::Abort(DapAbort::QueryMismatch {
+                detail: "Only fixed-size batches can be force-closed.".into(),
+                task_id: task_id.clone(),
+            }));
+        }
+        self.mark_batch_forced(task_id, &batch_id).await?;
+        info!("forced batch {batch_id} of task {task_id}");
+        Ok(Some(batch_id))
+    }
+
+    /// Check how a batch of reports would be aggregated, without changing any state (see
+    /// [`DapAggregator::dry_run_aggregation`]). For fixed-size tasks, the reports are checked
+    /// against the given batch or, on the Leader, the current batch by default.
+    pub(crate) async fn internal_dry_run_aggregation(
+        &self,
+        task_id: &TaskId,
+        batch_id: Option<BatchId>,
+        report_batch: &ReportBatch,
+    ) -> std::result::Result<Vec<DapDryRunReportStatus>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let part_batch_sel = match (&task_config.as_ref().query, batch_id) {
+            (DapQueryConfig::TimeInterval, None) => PartialBatchSelector::TimeInterval,
+            (DapQueryConfig::TimeInterval, Some(..)) => {
+                return Err(DapError::Abort(DapAbort::BadRequest(
+                    "batch ID is not applicable to time-interval tasks".into(),
+                )))
+            }
+            (DapQueryConfig::FixedSize { .. }, Some(batch_id)) => {
+                PartialBatchSelector::FixedSizeByBatchId { batch_id }
+            }
+            (DapQueryConfig::FixedSize { .. }, None) if self.config().is_leader => {
+                PartialBatchSelector::FixedSizeByBatchId {
+                    batch_id: self.internal_current_batch(task_id).await?,
+                }
+            }
+            (DapQueryConfig::FixedSize { .. }, None) => {
+                return Err(DapError::Abort(DapAbort::BadRequest(
+                    "batch ID is required for fixed-size tasks".into(),
+                )))
+            }
+        };
+
+        self.dry_run_aggregation(
+            task_id,
+            &part_batch_sel,
+            self.config().is_leader,
+            report_batch,
+        )
+        .await
+    }
+
+    /// Get the URL to use for this endpoint, as required by
+    /// draft-dcook-ppm-dap-interop-test-design-02.
+    pub(crate) async fn internal_endpoint_for_task(
+        &self,
+        version: DapVersion,
+        cmd: InternalTestEndpointForTask,
+    ) -> Result<Response> {
+        if self.config().is_leader && !matches!(cmd.role, InternalTestRole::Leader)
+            || !self.config().is_leader && !matches!(cmd.role, InternalTestRole::Helper)
+        {
+            return Response::from_json(&InternalResponse::<InternalTestEndpoint>::error(
+                InternalErrorCode::RoleMismatch,
+                "role mismatch",
+            ));
+        }
+
+        let path = self
+            .config()
+            .base_url
+            .as_ref()
+            .ok_or_else(|| {
+                Error::RustError(format!(
+                    "Environment variable {DAP_BASE_URL} not configured"
+                ))
+            })?
+            .path();
+
+        Response::from_json(&InternalResponse::success(InternalTestEndpoint {
+            endpoint: format!("{path}{}/", version.as_ref()),
+        }))
+    }
+
+    /// Configure Daphne-Worker a task, as required by draft-dcook-ppm-dap-interop-test-design-02.
+    pub(crate) async fn internal_add_task(
+        &self,
+        version: DapVersion,
+        cmd: InternalTestAddTask,
+    ) -> Result<()> {
+        // Task ID.
+        let task_id = TaskId::try_from_base64url(&cmd.task_id)
+            .ok_or_else(|| cmd_err("task ID is not valid URL-safe base64"))?;
+
+        // VDAF config.
+        let vdaf = match (
+            cmd.vdaf.typ.as_ref(),
+            cmd.vdaf.bits,
+            cmd.vdaf.length,
+            cmd.vdaf.buckets,
+        ) {
+            ("Prio3Count", None, None, None) => VdafConfig::Prio3(Prio3Config::Count),
+            ("Prio3Sum", Some(bits), None, None) => {
+                let bits = bits.parse().map_err(cmd_err)?;
+                VdafConfig::Prio3(Prio3Config::Sum { bits })
+            }
+            ("Prio3SumVec", Some(bits), Some(length), None) => {
+                let bits = bits.parse().map_err(cmd_err)?;
+                let len = length.parse().map_err(cmd_err)?;
+                VdafConfig::Prio3(Prio3Config::SumVec { bits, len })
+            }
+            ("Prio3FixedPoint16BitBoundedL2VecSum", None, Some(length), None) => {
+                let len = length.parse().map_err(cmd_err)?;
+                VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+                    bitsize: Prio3FixedPointBitSize::Fixed16,
+                    len,
+                })
+            }
+            ("Prio3FixedPoint32BitBoundedL2VecSum", None, Some(length), None) => {
+                let len = length.parse().map_err(cmd_err)?;
+                VdafConfig::Prio3(Prio3Config::FixedPointBoundedL2VecSum {
+                    bitsize: Prio3FixedPointBitSize::Fixed32,
+                    len,
+                })
+            }
+            ("Prio3Histogram", None, None, Some(buckets)) => {
+                let buckets = buckets
+                    .iter()
+                    .map(|bucket| bucket.parse())
+                    .collect::<std::result::Result<Vec<u64>, _>>()
+                    .map_err(cmd_err)?;
+                VdafConfig::Prio3(Prio3Config::Histogram { buckets })
+            }
+            _ => return Err(cmd_err("command failed: unrecognized VDAF")),
+        };
+        vdaf.check_params()
+            .map_err(|e| cmd_err(format!("command failed: {e}")))?;
+
+        // DP mechanism.
+        cmd.dp
+            .validate()
+            .map_err(|e| cmd_err(format!("command failed: {e}")))?;
+
+        // Replay filter.
+        if let Some(ref replay_filter) = cmd.replay_filter {
+            replay_filter
+                .validate()
+                .map_err(|e| cmd_err(format!("command failed: {e}")))?;
+        }
+
+        // Batch lifetime.
+        if let Some(ref batch_lifetime) = cmd.batch_lifetime {
+            batch_lifetime
+                .validate()
+                .map_err(|e| cmd_err(format!("command failed: {e}")))?;
+        }
+
+        // Upload authentication.
+        match (cmd.role, &cmd.upload_auth) {
+            (InternalTestRole::Leader, Some(upload_auth)) => upload_auth
+                .validate()
+                .map_err(|e| cmd_err(format!("command failed: {e}")))?,
+            (InternalTestRole::Helper, Some(..)) => {
+                return Err(cmd_err("command failed: unexpected upload authentication"));
+            }
+            (_, None) => (),
+        }
+
         // VDAF verificaiton key.
         let vdaf_verify_key_data = decode_base64url_vec(cmd.vdaf_verify_key.as_bytes())
-            .ok_or_else(|| int_err("VDAF verify key is not valid URL-safe base64"))?;
+            .ok_or_else(|| cmd_err("VDAF verify key is not valid URL-safe base64"))?;
         let vdaf_verify_key = vdaf
             .get_decoded_verify_key(&vdaf_verify_key_data)
-            .map_err(int_err)?;
+            .map_err(cmd_err)?;
 
         // Collector HPKE config.
         let collector_hpke_config_data = decode_base64url_vec(cmd.collector_hpke_config.as_bytes())
-            .ok_or_else(|| int_err("HPKE collector config is not valid URL-safe base64"))?;
+            .ok_or_else(|| cmd_err("HPKE collector config is not valid URL-safe base64"))?;
         let collector_hpke_config =
-            HpkeConfig::get_decoded(&collector_hpke_config_data).map_err(int_err)?;
+            HpkeConfig::get_decoded(&collector_hpke_config_data).map_err(cmd_err)?;
 
         // Leader authentication token.
         let token = BearerToken::from(cmd.leader_authentication_token);
@@ -833,7 +2531,7 @@ impl<'srv> DaphneWorker<'srv> {
             .await?
             .is_some()
         {
-            return Err(int_err(format!(
+            return Err(cmd_err(format!(
                 "command failed: token already exists for the given task ({}) and bearer role (leader)",
                 cmd.task_id
             )));
@@ -848,32 +2546,60 @@ impl<'srv> DaphneWorker<'srv> {
                     .await?
                     .is_some()
                 {
-                    return Err(int_err(format!(
+                    return Err(cmd_err(format!(
                         "command failed: token already exists for the given task ({}) and bearer role (collector)",
                         cmd.task_id
                     )));
                 }
             }
             (InternalTestRole::Leader, None) => {
-                return Err(int_err(
+                return Err(cmd_err(
                     "command failed: missing collector authentication token",
                 ))
             }
             (InternalTestRole::Helper, None) => (),
             (InternalTestRole::Helper, Some(..)) => {
-                return Err(int_err(
+                return Err(cmd_err(
                     "command failed: unexpected collector authentication token",
                 ));
             }
         };
 
+        // Additional Collectors.
+        let additional_collectors = self
+            .internal_add_additional_collectors(&task_id, cmd.role, cmd.additional_collectors)
+            .await?;
+
+        // Bearer token headers.
+        if let Some(auth_header) = cmd.auth_header {
+            if auth_header.accept.is_empty() {
+                return Err(cmd_err(
+                    "command failed: no bearer token header would be accepted",
+                ));
+            }
+            if self
+                .set_auth_header_config(&task_id, &auth_header)
+                .await?
+                .is_some()
+            {
+                return Err(cmd_err(format!(
+                    "command failed: bearer token header config already exists for the given task ({})",
+                    cmd.task_id
+                )));
+            }
+        }
+
         // Query configuraiton.
         let query = match (cmd.query_type, cmd.max_batch_size) {
-            (1, None) => DapQueryConfig::TimeInterval,
-            (1, Some(..)) => return Err(int_err("command failed: unexpected max batch size")),
-            (2, Some(max_batch_size)) => DapQueryConfig::FixedSize { max_batch_size },
-            (2, None) => return Err(int_err("command failed: missing max batch size")),
-            _ => return Err(int_err("command failed: unrecognized query type")),
+            (1, None) if cmd.batch_lifetime.is_none() => DapQueryConfig::TimeInterval,
+            (1, None) => return Err(cmd_err("command failed: unexpected batch lifetime")),
+            (1, Some(..)) => return Err(cmd_err("command failed: unexpected max batch size")),
+            (2, Some(max_batch_size)) => DapQueryConfig::FixedSize {
+                max_batch_size,
+                batch_lifetime: cmd.batch_lifetime,
+            },
+            (2, None) => return Err(cmd_err("command failed: missing max batch size")),
+            _ => return Err(cmd_err("command failed: unrecognized query type")),
         };
 
         if self
@@ -887,16 +2613,23 @@ impl<'srv> DaphneWorker<'srv> {
                     time_precision: cmd.time_precision,
                     expiration: cmd.task_expiration,
                     min_batch_size: cmd.min_batch_size,
+                    max_batch_query_count: cmd.max_batch_query_count.unwrap_or(1),
                     query,
                     vdaf,
                     vdaf_verify_key,
                     collector_hpke_config,
+                    additional_collectors,
+                    dp: cmd.dp,
+                    taskprov_advertisement: None,
+                    replay_filter: cmd.replay_filter,
+                    vdaf_verify_key_rotation: None,
+                    upload_auth: cmd.upload_auth,
                 },
             )
             .await?
             .is_some()
         {
-            Err(int_err(format!(
+            Err(cmd_err(format!(
                 "command failed: config already exists for the given task ({})",
                 cmd.task_id
             )))
@@ -905,9 +2638,706 @@ impl<'srv> DaphneWorker<'srv> {
         }
     }
 
+    /// Parse the additional Collectors of a task being added and store their bearer tokens. Only
+    /// the Leader is configured with the tokens.
+    async fn internal_add_additional_collectors(
+        &self,
+        task_id: &TaskId,
+        role: InternalTestRole,
+        cmds: Vec<InternalTestCollector>,
+    ) -> Result<Vec<DapTaskCollector>> {
+        let mut additional_collectors: Vec<DapTaskCollector> = Vec::with_capacity(cmds.len());
+        for cmd in cmds {
+            if !is_valid_collector_id(&cmd.id) {
+                return Err(cmd_err(format!(
+                    "command failed: invalid collector ID ({})",
+                    cmd.id
+                )));
+            }
+            if additional_collectors
+                .iter()
+                .any(|collector| collector.id == cmd.id)
+            {
+                return Err(cmd_err(format!(
+                    "command failed: duplicate collector ID ({})",
+                    cmd.id
+                )));
+            }
+
+            let hpke_config_data = decode_base64url_vec(cmd.hpke_config.as_bytes())
+                .ok_or_else(|| cmd_err("HPKE collector config is not valid URL-safe base64"))?;
+            let hpke_config = HpkeConfig::get_decoded(&hpke_config_data).map_err(cmd_err)?;
+
+            match (role, cmd.authentication_token) {
+                (InternalTestRole::Leader, Some(token)) => {
+                    let kv_store = self.kv()?;
+                    let kv_key = additional_collector_bearer_token_kv_key(task_id, &cmd.id);
+                    if kv_store.get(&kv_key).text().await?.is_some() {
+                        return Err(cmd_err(format!(
+                            "command failed: token already exists for the given task ({}) and collector ({})",
+                            task_id.to_base64url(),
+                            cmd.id
+                        )));
+                    }
+                    kv_store
+                        .put(&kv_key, BearerToken::from(token))?
+                        .execute()
+                        .await?;
+                }
+                (InternalTestRole::Leader, None) => {
+                    return Err(cmd_err(format!(
+                        "command failed: missing authentication token for collector ({})",
+                        cmd.id
+                    )))
+                }
+                (InternalTestRole::Helper, None) => (),
+                (InternalTestRole::Helper, Some(..)) => {
+                    return Err(cmd_err(format!(
+                        "command failed: unexpected authentication token for collector ({})",
+                        cmd.id
+                    )));
+                }
+            }
+
+            additional_collectors.push(DapTaskCollector {
+                id: cmd.id,
+                hpke_config,
+            });
+        }
+        Ok(additional_collectors)
+    }
+
+    /// List the IDs of the tasks configured in KV.
+    pub(crate) async fn internal_list_tasks(&self) -> Result<Vec<TaskId>> {
+        self.kv_list_task_ids(KV_KEY_PREFIX_TASK_CONFIG).await
+    }
+
+    /// List the task IDs of the KV keys with the given prefix.
+    async fn kv_list_task_ids(&self, kv_key_prefix: &str) -> Result<Vec<TaskId>> {
+        let kv_store = self.kv()?;
+        let prefix = format!("{kv_key_prefix}/");
+        let mut task_ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut builder = kv_store.list().prefix(prefix.clone());
+            if let Some(cursor) = cursor {
+                builder = builder.cursor(cursor);
+            }
+            let list = builder.execute().await?;
+            for key in list.keys {
+                let task_id = key
+                    .name
+                    .strip_prefix(&prefix)
+                    .and_then(|task_id_hex| hex::decode(task_id_hex).ok())
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(TaskId)
+                    .ok_or_else(|| int_err(format!("malformed task key {}", key.name)))?;
+                task_ids.push(task_id);
+            }
+            if list.list_complete {
+                return Ok(task_ids);
+            }
+            cursor = list.cursor;
+        }
+    }
+
+    /// Compare the tasks listed by a manifest with the tasks configured in KV.
+    pub(crate) async fn internal_diff_task_manifest(
+        &self,
+        entries: &[DapTaskManifestEntry],
+    ) -> Result<DapTaskManifestDiff> {
+        let mut installed = HashMap::new();
+        for task_id in self.internal_list_tasks().await? {
+            let task_config = match self.get_task_config(Cow::Borrowed(&task_id)).await? {
+                Some(task_config) => task_config.as_ref().clone(),
+                None => continue,
+            };
+            installed.insert(task_id, task_config);
+        }
+        diff_task_manifest(entries, &installed).map_err(int_err)
+    }
+
+    /// Check that each task created by the manifest is provided with the bearer tokens this
+    /// Aggregator needs. On failure, the reason is returned.
+    pub(crate) fn check_task_manifest_tokens(
+        &self,
+        entries: &[DapTaskManifestEntry],
+        diff: &DapTaskManifestDiff,
+    ) -> std::result::Result<(), String> {
+        for (entry, task_diff) in entries.iter().zip(diff.tasks.iter()) {
+            if !self.config().is_leader && entry.collector_authentication_token.is_some() {
+                return Err(format!(
+                    "task {}: unexpected collector authentication token",
+                    task_diff.task_id
+                ));
+            }
+            if task_diff.change != DapTaskManifestChange::Create {
+                continue;
+            }
+            if entry.leader_authentication_token.is_none() {
+                return Err(format!(
+                    "task {}: missing leader authentication token",
+                    task_diff.task_id
+                ));
+            }
+            if self.config().is_leader && entry.collector_authentication_token.is_none() {
+                return Err(format!(
+                    "task {}: missing collector authentication token",
+                    task_diff.task_id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Install the tasks listed by a manifest: Each task that is created or updated is written to
+    /// KV, along with any bearer tokens provided for it. Applying the same manifest again has no
+    /// further effect.
+    ///
+    /// Only the cache of this isolate is updated. Other isolates may continue to use the previous
+    /// configuration of an updated task until they are recycled.
+    pub(crate) async fn internal_apply_task_manifest(
+        &self,
+        entries: &[DapTaskManifestEntry],
+        diff: &DapTaskManifestDiff,
+    ) -> Result<()> {
+        let kv_store = self.kv()?;
+        let isolate_state = self.isolate_state();
+        for (entry, task_diff) in entries.iter().zip(diff.tasks.iter()) {
+            let task_id = &entry.task_id;
+            if task_diff.change != DapTaskManifestChange::Unchanged {
+                self.replace_task_config(task_id, entry.config.clone())
+                    .await?;
+            }
+
+            for (kv_key_prefix, bearer_tokens, token) in [
+                (
+                    KV_KEY_PREFIX_BEARER_TOKEN_LEADER,
+                    &isolate_state.leader_bearer_tokens,
+                    &entry.leader_authentication_token,
+                ),
+                (
+                    KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR,
+                    &isolate_state.collector_bearer_tokens,
+                    &entry.collector_authentication_token,
+                ),
+            ] {
+                if let Some(token) = token {
+                    kv_store
+                        .put(
+                            &format!("{kv_key_prefix}/{task_id}"),
+                            BearerToken::from(token.as_str()),
+                        )?
+                        .execute()
+                        .await?;
+                    bearer_tokens
+                        .write()
+                        .map_err(|e| int_err(format!("Failed to lock map for writing: {e}")))?
+                        .remove(task_id);
+                }
+            }
+            info!(
+                task_id = task_diff.task_id,
+                change = ?task_diff.change,
+                "installed task from manifest"
+            );
+        }
+        Ok(())
+    }
+
+    /// Get the configuration of the given task, omitting the VDAF verification key.
+    pub(crate) async fn internal_get_task(&self, task_id: &TaskId) -> Result<Option<AdminTask>> {
+        Ok(self
+            .get_task_config(Cow::Borrowed(task_id))
+            .await?
+            .map(|task_config| AdminTask::new(task_id, task_config.as_ref())))
+    }
+
+    /// Delete the configuration and bearer tokens of the given task. Returns `false` if the task
+    /// was not configured.
+    ///
+    /// Only the cache of this isolate is cleared. Other isolates may continue to serve the task
+    /// until they are recycled.
+    pub(crate) async fn internal_delete_task(&self, task_id: &TaskId) -> Result<bool> {
+        let task_config = match self.get_task_config(Cow::Borrowed(task_id)).await? {
+            Some(task_config) => task_config.as_ref().clone(),
+            None => return Ok(false),
+        };
+        self.delete_task_kv(task_id, &task_config).await?;
+        Ok(true)
+    }
+
+    /// Re-register the given task for another DAP version (see [`DapTaskConfig::migrate`]). The
+    /// bearer tokens and bearer token header config of the task are copied to the migrated task.
+    /// If `drain` is set, then the original task is kept so that its remaining reports can be
+    /// aggregated and collected, but it no longer accepts reports from the current batch window
+    /// onwards; otherwise it is deleted. Returns the migrated task, or `None` if the task was not
+    /// configured.
+    ///
+    /// Only the cache of this isolate is updated. Other isolates may continue to serve the
+    /// original task unchanged until they are recycled.
+    pub(crate) async fn internal_migrate_task(
+        &self,
+        task_id: &TaskId,
+        version: DapVersion,
+        drain: bool,
+    ) -> Result<Option<AdminTask>> {
+        let mut task_config = match self.get_task_config(Cow::Borrowed(task_id)).await? {
+            Some(task_config) => task_config.as_ref().clone(),
+            None => return Ok(None),
+        };
+        let (new_task_id, new_task_config) = task_config
+            .migrate(task_id, task_config.version, version)
+            .map_err(|e| cmd_err(format!("command failed: {e}")))?;
+
+        let new_task = AdminTask::new(&new_task_id, &new_task_config);
+        if self
+            .kv_set_if_not_exists(KV_KEY_PREFIX_TASK_CONFIG, &new_task_id, new_task_config)
+            .await?
+            .is_some()
+        {
+            return Err(cmd_err(format!(
+                "command failed: config already exists for the migrated task ({})",
+                new_task_id.to_base64url()
+            )));
+        }
+
+        let mut kv_keys: Vec<(String, String)> = task_config
+            .additional_collectors
+            .iter()
+            .map(|collector| {
+                (
+                    additional_collector_bearer_token_kv_key(task_id, &collector.id),
+                    additional_collector_bearer_token_kv_key(&new_task_id, &collector.id),
+                )
+            })
+            .collect();
+        for kv_key_prefix in [
+            KV_KEY_PREFIX_BEARER_TOKEN_LEADER,
+            KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR,
+            KV_KEY_PREFIX_AUTH_HEADER,
+        ] {
+            kv_keys.push((
+                format!("{kv_key_prefix}/{task_id}"),
+                format!("{kv_key_prefix}/{new_task_id}"),
+            ));
+        }
+        let kv_store = self.kv()?;
+        for (kv_key, new_kv_key) in kv_keys {
+            if let Some(value) = kv_store.get(&kv_key).json::<serde_json::Value>().await? {
+                kv_store.put(&new_kv_key, value)?.execute().await?;
+            }
+        }
+
+        if drain {
+            task_config.expiration = task_config
+                .expiration
+                .min(task_config.quantized_time_lower_bound(self.get_current_time()));
+            self.replace_task_config(task_id, task_config).await?;
+        } else {
+            self.delete_task_kv(task_id, &task_config).await?;
+        }
+        info!(
+            task_id = task_id.to_base64url(),
+            new_task_id = new_task_id.to_base64url(),
+            drain,
+            "migrated task to {}",
+            version.as_ref()
+        );
+        Ok(Some(new_task))
+    }
+
+    /// Add a Collector to the given task. Only the Leader is configured with the Collector's
+    /// bearer token. Returns `false` if the task is not configured.
+    ///
+    /// Only the cache of this isolate is updated. Other isolates may not recognize the Collector
+    /// until they are recycled.
+    pub(crate) async fn internal_add_task_collector(
+        &self,
+        task_id: &TaskId,
+        cmd: InternalTestCollector,
+    ) -> Result<bool> {
+        let mut task_config = match self.get_task_config(Cow::Borrowed(task_id)).await? {
+            Some(task_config) => task_config.as_ref().clone(),
+            None => return Ok(false),
+        };
+        if task_config
+            .additional_collectors
+            .iter()
+            .any(|collector| collector.id == cmd.id)
+        {
+            return Err(cmd_err(format!(
+                "command failed: duplicate collector ID ({})",
+                cmd.id
+            )));
+        }
+
+        let role = if self.config().is_leader {
+            InternalTestRole::Leader
+        } else {
+            InternalTestRole::Helper
+        };
+        let mut collectors = self
+            .internal_add_additional_collectors(task_id, role, vec![cmd])
+            .await?;
+        task_config.additional_collectors.append(&mut collectors);
+        self.replace_task_config(task_id, task_config).await?;
+        Ok(true)
+    }
+
+    /// Remove the Collector with the given ID from the given task and delete its bearer token.
+    /// Returns `false` if the task or the Collector is not configured.
+    ///
+    /// Only the cache of this isolate is updated. Other isolates may continue to recognize the
+    /// Collector until they are recycled.
+    pub(crate) async fn internal_delete_task_collector(
+        &self,
+        task_id: &TaskId,
+        collector_id: &str,
+    ) -> Result<bool> {
+        let mut task_config = match self.get_task_config(Cow::Borrowed(task_id)).await? {
+            Some(task_config) => task_config.as_ref().clone(),
+            None => return Ok(false),
+        };
+        let collector_count = task_config.additional_collectors.len();
+        task_config
+            .additional_collectors
+            .retain(|collector| collector.id != collector_id);
+        if task_config.additional_collectors.len() == collector_count {
+            return Ok(false);
+        }
+
+        self.replace_task_config(task_id, task_config).await?;
+        self.kv()?
+            .delete(&additional_collector_bearer_token_kv_key(
+                task_id,
+                collector_id,
+            ))
+            .await?;
+        Ok(true)
+    }
+
+    /// Overwrite the configuration of the given task in KV and in the cache of this isolate.
+    async fn replace_task_config(
+        &self,
+        task_id: &TaskId,
+        task_config: DapTaskConfig,
+    ) -> Result<()> {
+        self.kv()?
+            .put(
+                &format!("{KV_KEY_PREFIX_TASK_CONFIG}/{task_id}"),
+                &task_config,
+            )?
+            .execute()
+            .await?;
+        self.isolate_state()
+            .tasks
+            .write()
+            .map_err(|e| int_err(format!("Failed to lock map for writing: {e}")))?
+            .insert(task_id.clone(), task_config);
+        Ok(())
+    }
+
+    /// Delete the configuration and bearer tokens of the given task from KV and from the cache of
+    /// this isolate. Returns the number of KV keys deleted.
+    async fn delete_task_kv(&self, task_id: &TaskId, task_config: &DapTaskConfig) -> Result<u64> {
+        let mut kv_keys: Vec<String> = task_config
+            .additional_collectors
+            .iter()
+            .map(|collector| additional_collector_bearer_token_kv_key(task_id, &collector.id))
+            .collect();
+        for kv_key_prefix in [
+            KV_KEY_PREFIX_TASK_CONFIG,
+            KV_KEY_PREFIX_BEARER_TOKEN_LEADER,
+            KV_KEY_PREFIX_BEARER_TOKEN_COLLECTOR,
+            KV_KEY_PREFIX_TASKPROV_TASK,
+            KV_KEY_PREFIX_AUTH_HEADER,
+        ] {
+            kv_keys.push(format!("{kv_key_prefix}/{task_id}"));
+        }
+
+        let kv_store = self.kv()?;
+        for kv_key in kv_keys.iter() {
+            kv_store.delete(kv_key).await?;
+        }
+
+        let isolate_state = self.isolate_state();
+        isolate_state
+            .tasks
+            .write()
+            .map_err(|e| int_err(format!("Failed to lock map for writing: {e}")))?
+            .remove(task_id);
+        for bearer_tokens in [
+            &isolate_state.leader_bearer_tokens,
+            &isolate_state.collector_bearer_tokens,
+        ] {
+            bearer_tokens
+                .write()
+                .map_err(|e| int_err(format!("Failed to lock map for writing: {e}")))?
+                .remove(task_id);
+        }
+        isolate_state
+            .auth_header_configs
+            .write()
+            .map_err(|e| int_err(format!("Failed to lock map for writing: {e}")))?
+            .remove(task_id);
+        kv_keys.len().try_into().map_err(int_err)
+    }
+
+    /// Purge the state of the tasks that expired more than `DAP_TASK_GARBAGE_COLLECT_AFTER_SECS`
+    /// ago. At most [`MAX_GARBAGE_COLLECTED_TASKS_PER_RUN`] tasks are purged at once.
+    ///
+    /// For each task, the DO instances registered with the task's instance of `GarbageCollector`
+    /// are deleted, then (Leader only) its collection jobs, and finally its configuration,
+    /// bearer tokens, and billing report. The configuration is deleted last so that a task whose
+    /// purge fails is retried by the next run.
+    pub(crate) async fn internal_garbage_collect_tasks(&self) -> Result<TaskGarbageCollection> {
+        let grace_period = match self.config().task_garbage_collect_after_secs {
+            Some(grace_period) => grace_period.as_secs(),
+            None => return Err(int_err("task garbage collection is not configured")),
+        };
+        let now = self.get_current_time();
+
+        let mut expired = Vec::new();
+        for task_id in self.internal_list_tasks().await? {
+            if let Some(task_config) = self.get_task_config(Cow::Borrowed(&task_id)).await? {
+                if task_config.as_ref().expiration.saturating_add(grace_period) <= now {
+                    expired.push((task_id.clone(), task_config.as_ref().clone()));
+                }
+            }
+        }
+
+        let mut gc = TaskGarbageCollection {
+            remaining: expired
+                .len()
+                .saturating_sub(MAX_GARBAGE_COLLECTED_TASKS_PER_RUN),
+            ..Default::default()
+        };
+        for (task_id, task_config) in expired
+            .into_iter()
+            .take(MAX_GARBAGE_COLLECTED_TASKS_PER_RUN)
+        {
+            let task = self.garbage_collect_task(&task_id, &task_config).await?;
+            self.emit_batch_events(vec![DapBatchEvent::for_task(
+                DapBatchEventType::Expired,
+                &task_id,
+            )])
+            .await;
+            info!(
+                task_id = task.task_id,
+                expiration = task.expiration,
+                durable_objects = ?task.durable_objects,
+                collection_jobs = task.collection_jobs,
+                kv_keys = task.kv_keys,
+                "garbage collected expired task"
+            );
+
+            let counter = &self.state.metrics.task_gc_deleted_counter;
+            for (binding, count) in task.durable_objects.iter() {
+                counter
+                    .with_label_values(&[&self.state.host, binding])
+                    .inc_by(*count);
+            }
+            counter
+                .with_label_values(&[&self.state.host, "collection_job"])
+                .inc_by(task.collection_jobs);
+            counter
+                .with_label_values(&[&self.state.host, "kv"])
+                .inc_by(task.kv_keys);
+            gc.tasks.push(task);
+        }
+        Ok(gc)
+    }
+
+    async fn garbage_collect_task(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+    ) -> Result<GarbageCollectedTask> {
+        let durable = self.durable();
+        let durable_objects: BTreeMap<String, u64> = durable
+            .post(
+                BINDING_DAP_GARBAGE_COLLECTOR,
+                DURABLE_GARBAGE_COLLECTOR_DELETE_TASK,
+                durable_name_task(&task_config.version, &task_id.to_hex()),
+                &(),
+            )
+            .await?;
+
+        let collection_jobs = if self.config().is_leader {
+            durable
+                .post(
+                    BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                    DURABLE_LEADER_COL_JOB_QUEUE_DELETE_TASK,
+                    durable_name_queue(0),
+                    task_id,
+                )
+                .await?
+        } else {
+            0
+        };
+
+        let kv_store = self.kv()?;
+        kv_store
+            .delete(&format!("{KV_KEY_PREFIX_TASK_BILLING}/{task_id}"))
+            .await?;
+        let kv_keys = self.delete_task_kv(task_id, task_config).await? + 1;
+
+        Ok(GarbageCollectedTask {
+            task_id: task_id.to_base64url(),
+            expiration: task_config.expiration,
+            durable_objects,
+            collection_jobs,
+            kv_keys,
+        })
+    }
+
+    /// Notify the operator of each taskprov task that expires within
+    /// `DAP_TASKPROV_EXPIRY_NOTIFICATION_WINDOW_SECS`. Each task is notified once; a task whose
+    /// notification fails is retried by the next run.
+    pub(crate) async fn internal_notify_expiring_taskprov_tasks(
+        &self,
+    ) -> Result<Vec<TaskprovExpiryNotification>> {
+        let notification_config = match self
+            .config()
+            .taskprov
+            .as_ref()
+            .and_then(|taskprov| taskprov.expiry_notification.as_ref())
+        {
+            Some(notification_config) => notification_config,
+            None => return Err(int_err("taskprov expiry notifications are not configured")),
+        };
+        let now = self.get_current_time();
+        let kv_store = self.kv()?;
+
+        let mut notifications = Vec::new();
+        for task_id in self.kv_list_task_ids(KV_KEY_PREFIX_TASKPROV_TASK).await? {
+            let kv_key = format!("{KV_KEY_PREFIX_TASKPROV_TASK}/{task_id}");
+            let mut taskprov_task: TaskprovTask = match kv_store.get(&kv_key).json().await? {
+                Some(taskprov_task) => taskprov_task,
+                None => continue,
+            };
+            if taskprov_task.expiry_notified_at.is_some() {
+                continue;
+            }
+
+            let task_config = match self.get_task_config(Cow::Borrowed(&task_id)).await? {
+                Some(task_config) => task_config.as_ref().clone(),
+                None => continue,
+            };
+            if task_config.expiration > now.saturating_add(notification_config.window.as_secs()) {
+                continue;
+            }
+
+            let notification = TaskprovExpiryNotification {
+                event: "taskprov_task_expiring",
+                task_id: task_id.to_base64url(),
+                version: task_config.version,
+                role: if self.config().is_leader {
+                    "leader"
+                } else {
+                    "helper"
+                },
+                expiration: task_config.expiration,
+                leader_url: task_config.leader_url,
+                helper_url: task_config.helper_url,
+            };
+            self.send_taskprov_expiry_notification(notification_config, &notification)
+                .await?;
+            info!(
+                task_id = notification.task_id,
+                expiration = notification.expiration,
+                "notified operator of expiring taskprov task"
+            );
+
+            taskprov_task.expiry_notified_at = Some(now);
+            kv_store.put(&kv_key, &taskprov_task)?.execute().await?;
+            notifications.push(notification);
+        }
+        Ok(notifications)
+    }
+
+    async fn send_taskprov_expiry_notification(
+        &self,
+        notification_config: &TaskprovExpiryNotificationConfig,
+        notification: &TaskprovExpiryNotification,
+    ) -> Result<()> {
+        let mut headers = reqwest_wasm::header::HeaderMap::new();
+        if let Some(ref bearer_token) = notification_config.bearer_token {
+            let bearer_token: &str = bearer_token.as_ref();
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static("authorization"),
+                reqwest_wasm::header::HeaderValue::from_str(&format!("Bearer {bearer_token}"))
+                    .map_err(int_err)?,
+            );
+        }
+
+        let reqwest_resp = self
+            .isolate_state()
+            .client
+            .post(notification_config.url.as_str())
+            .json(notification)
+            .headers(headers)
+            .send()
+            .await
+            .map_err(|err| int_err(format!("request to notification server failed: {err:?}")))?;
+
+        let status = reqwest_resp.status();
+        if !status.is_success() {
+            error!("unexpected response from notification server: {reqwest_resp:?}");
+            return Err(int_err(format!(
+                "taskprov expiry notification failed with response status {status}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Corrupt (or restore) the Leader's bearer token for the given task. This is used to test
+    /// that the Helper rejects unauthorized requests from the Leader.
+    ///
+    /// NOTE Only the token cached by the current isolate is evicted. Other isolates continue to
+    /// use the token they have cached.
+    pub(crate) async fn internal_corrupt_leader_bearer_token(
+        &self,
+        cmd: InternalTestCorruptLeaderBearerToken,
+    ) -> Result<()> {
+        if !self.config().is_leader {
+            return Err(int_err("command failed: unexpected role (helper)"));
+        }
+
+        let task_id = TaskId::try_from_base64url(&cmd.task_id)
+            .ok_or_else(|| int_err("task ID is not valid URL-safe base64"))?;
+
+        let kv_key = format!("{KV_KEY_PREFIX_BEARER_TOKEN_LEADER}/{task_id}");
+        let kv_store = self.kv()?;
+        let token: BearerToken = kv_store.get(&kv_key).json().await?.ok_or_else(|| {
+            int_err(format!(
+                "command failed: no leader bearer token for the given task ({})",
+                cmd.task_id
+            ))
+        })?;
+
+        let raw: &str = token.as_ref();
+        let raw = raw.strip_suffix(CORRUPTED_TOKEN_SUFFIX).unwrap_or(raw);
+        let token = if cmd.corrupt {
+            BearerToken::from(format!("{raw}{CORRUPTED_TOKEN_SUFFIX}"))
+        } else {
+            BearerToken::from(raw)
+        };
+        kv_store.put(&kv_key, token)?.execute().await?;
+
+        self.isolate_state()
+            .leader_bearer_tokens
+            .write()
+            .map_err(|e| Error::RustError(format!("Failed to lock map for writing: {e}")))?
+            .remove(&task_id);
+        Ok(())
+    }
+
     pub(crate) fn extract_version_parameter(&self, req: &Request) -> Result<DapVersion> {
         let url = req.url()?;
         let path = url.path();
+        let path = path.strip_prefix(self.state.path_prefix).unwrap_or(path);
         let mut router: Router<bool> = Router::new();
         router.insert("/:version/*remaining", true).unwrap();
         let url_match = router.at(path).unwrap();
@@ -923,10 +3353,116 @@ impl<'srv> DaphneWorker<'srv> {
         mut req: Request,
         ctx: &RouteContext<D>,
     ) -> Result<DapRequest<DaphneWorkerAuth>> {
+        let mut payload = req.bytes().await?;
+
+        // The Leader may compress the body of its aggregation requests.
+        if let Some(encoding) = req.headers().get("Content-Encoding")? {
+            if let Some(encoding) = ContentEncoding::from_content_encoding(&encoding)
+                .map_err(|e| Error::RustError(e.to_string()))?
+            {
+                let decoded = encoding
+                    .decode(&payload)
+                    .map_err(|e| Error::RustError(e.to_string()))?;
+                self.state
+                    .metrics
+                    .compression_bytes_saved_counter
+                    .with_label_values(&[&self.state.host, "received"])
+                    .inc_by(decoded.len().saturating_sub(payload.len()) as u64);
+                payload = decoded;
+            }
+        }
+
+        self.worker_request_to_dap_with_payload(&req, ctx, payload)
+    }
+
+    /// Convert an upload request into a DAP request. Unlike [`Self::worker_request_to_dap`], the
+    /// body is streamed: As soon as the task ID and report metadata have been received, they are
+    /// checked (see [`DapLeader::check_upload_metadata`]), so that a report that would be rejected
+    /// is not buffered in full. If the report is rejected, then the abort is returned.
+    pub(crate) async fn worker_upload_request_to_dap<D>(
+        &'srv self,
+        mut req: Request,
+        ctx: &RouteContext<D>,
+    ) -> Result<std::result::Result<DapRequest<DaphneWorkerAuth>, DapAbort>> {
         let version = self.extract_version_parameter(&req)?;
+        let content_length = req
+            .headers()
+            .get("Content-Length")?
+            .and_then(|content_length| content_length.parse::<usize>().ok())
+            .unwrap_or_default();
+        let taskprov_advertisement = req.headers().get("DAP-Taskprov")?;
+
+        let mut payload = Vec::with_capacity(content_length.min(MAX_UPLOAD_PREALLOCATION));
+        let mut checked = version == DapVersion::Unknown;
+        let mut stream = req.stream()?;
+        while let Some(chunk) = stream.next().await {
+            payload.extend_from_slice(&chunk?);
+            if checked {
+                continue;
+            }
 
-        // Determine the authorization method used by the sender.
-        let bearer_token = req.headers().get("DAP-Auth-Token")?.map(BearerToken::from);
+            let (draft02_task_id, report_metadata) = match decode_report_prefix(&version, &payload)
+            {
+                Ok(Some(prefix)) => prefix,
+                Ok(None) => continue,
+                // The error is reported once the report is decoded in full.
+                Err(..) => {
+                    checked = true;
+                    continue;
+                }
+            };
+            checked = true;
+            let task_id = match draft02_task_id {
+                Some(task_id) => Some(task_id),
+                None => ctx.param("task_id").and_then(TaskId::try_from_base64url),
+            };
+            if let Some(task_id) = task_id {
+                if let Err(e) = self
+                    .check_upload_metadata(
+                        version,
+                        &task_id,
+                        taskprov_advertisement.as_deref(),
+                        &report_metadata,
+                    )
+                    .await
+                {
+                    return Ok(Err(e));
+                }
+            }
+        }
+
+        Ok(Ok(
+            self.worker_request_to_dap_with_payload(&req, ctx, payload)?
+        ))
+    }
+
+    fn worker_request_to_dap_with_payload<D>(
+        &self,
+        req: &Request,
+        ctx: &RouteContext<D>,
+        payload: Vec<u8>,
+    ) -> Result<DapRequest<DaphneWorkerAuth>> {
+        let version = self.extract_version_parameter(req)?;
+
+        // Determine the authorization method used by the sender. Whether the header carrying the
+        // bearer token is accepted for the task is checked when the request is authorized.
+        let bearer_token = match (
+            req.headers().get("DAP-Auth-Token")?,
+            req.headers().get("Authorization")?,
+        ) {
+            (Some(token), None) => Some((
+                BearerToken::from(token),
+                DaphneWorkerAuthHeader::DapAuthToken,
+            )),
+            (None, Some(value)) => DaphneWorkerAuthHeader::parse_authorization(&value)
+                .map(|token| (token, DaphneWorkerAuthHeader::AuthorizationBearer)),
+            (None, None) => None,
+            (Some(..), Some(..)) => {
+                debug!("ambiguous authorization method: bearer tokens were provided in both the DAP-Auth-Token and Authorization headers");
+                None
+            }
+        };
+        let client_bearer_token = bearer_token.as_ref().map(|(token, _header)| token.clone());
         let mut tls_client_auth = req.cf().tls_client_auth();
         if let Some(auth) = &tls_client_auth {
             // The runtime gives us a tls_client_auth whether the communication was secured by it or
@@ -940,7 +3476,9 @@ impl<'srv> DaphneWorker<'srv> {
             }
         }
         let sender_auth = match (bearer_token, tls_client_auth) {
-            (Some(bearer_token), None) => Some(DaphneWorkerAuth::BearerToken(bearer_token)),
+            (Some((bearer_token, header)), None) => {
+                Some(DaphneWorkerAuth::BearerToken(bearer_token, header))
+            }
             (None, Some(tls_client_auth)) => Some(DaphneWorkerAuth::CfTlsClientAuth {
                 cert_issuer: tls_client_auth.cert_issuer_dn_rfc2253(),
                 cert_subject: tls_client_auth.cert_subject_dn_rfc2253(),
@@ -955,7 +3493,46 @@ impl<'srv> DaphneWorker<'srv> {
         let content_type = req.headers().get("Content-Type")?;
         let media_type = DapMediaType::from_str_for_version(version, content_type.as_deref());
 
-        let payload = req.bytes().await?;
+        // A Client authenticates its upload request either by signing it, in which case the
+        // hex-encoded tag is sent in a header, or with a bearer token. A malformed tag is treated
+        // as missing.
+        let client_auth = if media_type.sender() == Some(DapSender::Client) {
+            match req.headers().get("DAP-Client-Signature")? {
+                Some(tag) => hex::decode(tag).ok().map(DapClientAuth::Signature),
+                None => client_bearer_token.map(DapClientAuth::BearerToken),
+            }
+        } else {
+            None
+        };
+
+        // The Leader signals the DAP version of the task in each request to the Helper.
+        let sender_version = req
+            .headers()
+            .get("DAP-Version")?
+            .map(|sender_version| DapVersion::from(sender_version.as_str()));
+
+        // A Collector other than the task's primary Collector identifies itself in its requests
+        // to the Leader, and the Leader relays this in its requests to the Helper.
+        let collector_id = req.headers().get("DAP-Collector-Id")?;
+
+        // draft04 taskprov: The task configuration is advertised in a header.
+        let taskprov = req.headers().get("DAP-Taskprov")?;
+
+        // The Leader signals the ID of the VDAF verification key with which it aggregates.
+        let vdaf_verify_key_id = req
+            .headers()
+            .get("DAP-Vdaf-Verify-Key-Id")?
+            .map(|id| id.parse::<u8>())
+            .transpose()
+            .map_err(|e| int_err(format!("malformed VDAF verify key ID: {e}")))?;
+
+        // A Client that retries an upload request sends the same idempotency key with each
+        // attempt.
+        let idempotency_key = if media_type.sender() == Some(DapSender::Client) {
+            req.headers().get("DAP-Idempotency-Key")?
+        } else {
+            None
+        };
 
         let (task_id, resource) = match version {
             DapVersion::Draft02 => {
@@ -970,7 +3547,7 @@ impl<'srv> DaphneWorker<'srv> {
                 let mut r = Cursor::new(payload.as_ref());
                 (TaskId::decode(&mut r).ok(), DapResource::Undefined)
             }
-            DapVersion::Draft04 => {
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
                 let task_id = ctx.param("task_id").and_then(TaskId::try_from_base64url);
                 let resource = match media_type {
                     DapMediaType::AggregationJobInitReq
@@ -1014,6 +3591,12 @@ impl<'srv> DaphneWorker<'srv> {
             url: req.url()?,
             media_type,
             sender_auth,
+            sender_version,
+            collector_id,
+            taskprov,
+            vdaf_verify_key_id,
+            client_auth,
+            idempotency_key,
         })
     }
 
@@ -1022,7 +3605,88 @@ impl<'srv> DaphneWorker<'srv> {
     }
 
     pub(crate) fn greatest_valid_report_time(&self, now: u64) -> u64 {
-        now.saturating_add(self.config().global.report_storage_max_future_time_skew)
+        self.config().global.greatest_valid_report_time(now)
+    }
+
+    /// Merge an aggregate share into the aggregate store with the given name. If a conflicting
+    /// concurrent update is detected, then the merge is retried against the current version of
+    /// the aggregate store, up to [`MAX_AGG_STORE_MERGE_ATTEMPTS`] times.
+    pub(crate) async fn merge_agg_share(
+        &self,
+        durable_name: String,
+        agg_share_delta: DapAggregateShare,
+    ) -> std::result::Result<Option<u64>, DapError> {
+        let durable = self.durable();
+        let mut expected: AggregateStoreVersion = durable
+            .get(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_GET_VERSION,
+                durable_name.clone(),
+            )
+            .await
+            .map_err(dap_err)?;
+
+        for _ in 0..MAX_AGG_STORE_MERGE_ATTEMPTS {
+            let resp: AggregateStoreMergeResp = durable
+                .post(
+                    BINDING_DAP_AGGREGATE_STORE,
+                    DURABLE_AGGREGATE_STORE_MERGE,
+                    durable_name.clone(),
+                    AggregateStoreMergeReq {
+                        agg_share_delta: agg_share_delta.clone(),
+                        expected,
+                    },
+                )
+                .await
+                .map_err(dap_err)?;
+            match resp {
+                AggregateStoreMergeResp::Merged { report_count, .. } => {
+                    return Ok(Some(report_count))
+                }
+                AggregateStoreMergeResp::AlreadyMerged => return Ok(None),
+                AggregateStoreMergeResp::Conflict(current) => {
+                    self.state
+                        .metrics
+                        .agg_store_merge_conflict_counter
+                        .with_label_values(&[&self.state.host])
+                        .inc();
+                    expected = current;
+                }
+            }
+        }
+
+        Err(DapError::Fatal(format!(
+            "aggregate store {durable_name}: merge failed after {MAX_AGG_STORE_MERGE_ATTEMPTS} conflicting attempts"
+        )))
+    }
+
+    /// Get the HTTP client for the origin of `url`, creating it if necessary.
+    fn helper_http_client(&self, url: &Url) -> std::result::Result<reqwest_wasm::Client, DapError> {
+        let origin = url.origin().ascii_serialization();
+        let clients = &self.isolate_state().helper_http_clients;
+        let cached = clients
+            .read()
+            .map_err(|e| DapError::Fatal(format!("Failed to lock map for reading: {e}")))?
+            .get(&origin)
+            .cloned();
+
+        let (client, status) = match cached {
+            Some(client) => (client, "reused"),
+            None => {
+                let client = HelperHttpClient::new(&self.config().global)?;
+                clients
+                    .write()
+                    .map_err(|e| DapError::Fatal(format!("Failed to lock map for writing: {e}")))?
+                    .insert(origin, client.clone());
+                (client, "created")
+            }
+        };
+        self.state
+            .metrics
+            .helper_http_client_counter
+            .with_label_values(&[&self.state.host, status])
+            .inc();
+        Ok(client.0)
     }
 
     // Generic HTTP POST/PUT
@@ -1052,16 +3716,108 @@ impl<'srv> DaphneWorker<'srv> {
             })?,
         );
 
-        if let Some(DaphneWorkerAuth::BearerToken(bearer_token)) = req.sender_auth {
+        match req.sender_auth {
+            Some(DaphneWorkerAuth::BearerToken(
+                bearer_token,
+                DaphneWorkerAuthHeader::DapAuthToken,
+            )) => {
+                headers.insert(
+                    reqwest_wasm::header::HeaderName::from_static("dap-auth-token"),
+                    reqwest_wasm::header::HeaderValue::from_str(bearer_token.as_ref()).map_err(
+                        |e| {
+                            DapError::Fatal(format!(
+                                "failed to construct dap-auth-token header: {e}"
+                            ))
+                        },
+                    )?,
+                );
+            }
+            Some(DaphneWorkerAuth::BearerToken(
+                bearer_token,
+                DaphneWorkerAuthHeader::AuthorizationBearer,
+            )) => {
+                headers.insert(
+                    reqwest_wasm::header::AUTHORIZATION,
+                    reqwest_wasm::header::HeaderValue::from_str(&format!(
+                        "Bearer {}",
+                        AsRef::<str>::as_ref(&bearer_token)
+                    ))
+                    .map_err(|e| {
+                        DapError::Fatal(format!("failed to construct authorization header: {e}"))
+                    })?,
+                );
+            }
+            Some(DaphneWorkerAuth::CfTlsClientAuth { .. }) | None => (),
+        }
+
+        if let Some(sender_version) = req.sender_version {
             headers.insert(
-                reqwest_wasm::header::HeaderName::from_static("dap-auth-token"),
-                reqwest_wasm::header::HeaderValue::from_str(bearer_token.as_ref()).map_err(
-                    |e| DapError::Fatal(format!("failed to construct dap-auth-token header: {e}")),
+                reqwest_wasm::header::HeaderName::from_static("dap-version"),
+                reqwest_wasm::header::HeaderValue::from_str(sender_version.as_ref()).map_err(
+                    |e| DapError::Fatal(format!("failed to construct dap-version header: {e}")),
                 )?,
             );
         }
 
-        let client = &self.isolate_state().client;
+        if let Some(collector_id) = req.collector_id {
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static("dap-collector-id"),
+                reqwest_wasm::header::HeaderValue::from_str(&collector_id).map_err(|e| {
+                    DapError::Fatal(format!("failed to construct dap-collector-id header: {e}"))
+                })?,
+            );
+        }
+
+        if let Some(taskprov) = req.taskprov {
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static("dap-taskprov"),
+                reqwest_wasm::header::HeaderValue::from_str(&taskprov).map_err(|e| {
+                    DapError::Fatal(format!("failed to construct dap-taskprov header: {e}"))
+                })?,
+            );
+        }
+
+        if let Some(vdaf_verify_key_id) = req.vdaf_verify_key_id {
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static("dap-vdaf-verify-key-id"),
+                reqwest_wasm::header::HeaderValue::from(u16::from(vdaf_verify_key_id)),
+            );
+        }
+
+        headers.insert(
+            reqwest_wasm::header::HeaderName::from_static("dap-correlation-id"),
+            reqwest_wasm::header::HeaderValue::from_str(&self.state.correlation_id).map_err(
+                |e| {
+                    DapError::Fatal(format!(
+                        "failed to construct dap-correlation-id header: {e}"
+                    ))
+                },
+            )?,
+        );
+
+        // Compress the body of aggregation requests, if configured.
+        let payload = match self.config().aggregation_content_encoding {
+            Some(encoding) if is_compressible(&req.media_type) => {
+                let encoded = encoding.encode(&payload)?;
+                headers.insert(
+                    reqwest_wasm::header::CONTENT_ENCODING,
+                    reqwest_wasm::header::HeaderValue::from_static(encoding.as_str()),
+                );
+                headers.insert(
+                    reqwest_wasm::header::ACCEPT_ENCODING,
+                    reqwest_wasm::header::HeaderValue::from_static(encoding.as_str()),
+                );
+                self.state
+                    .metrics
+                    .compression_bytes_saved_counter
+                    .with_label_values(&[&self.state.host, "sent"])
+                    .inc_by(payload.len().saturating_sub(encoded.len()) as u64);
+                encoded
+            }
+            _ => payload,
+        };
+
+        let client = self.helper_http_client(&url)?;
         let reqwest_req = if is_put {
             client.put(url.as_str())
         } else {
@@ -1070,54 +3826,85 @@ impl<'srv> DaphneWorker<'srv> {
         .body(payload)
         .headers(headers);
 
+        // The response is read in full before the request is considered complete, so that the
+        // timeout covers a peer that stalls while sending the body.
+        let send = async {
+            let reqwest_resp = reqwest_req.send().await?;
+            let status = reqwest_resp.status();
+            let headers = reqwest_resp.headers().clone();
+            let body = reqwest_resp.bytes().await?;
+            Ok::<_, reqwest_wasm::Error>((status, headers, body))
+        };
+
+        // Natively, the request timeout is enforced by the client. The Fetch API used in
+        // WebAssembly has no timeout, so the request is abandoned once the timeout elapses.
         let start = Date::now().as_millis();
-        let reqwest_resp = reqwest_req
-            .send()
-            .await
-            .map_err(|e| DapError::Fatal(e.to_string()))?;
+        #[cfg(target_arch = "wasm32")]
+        let result = match self.state.global_config.helper_request_timeout {
+            Some(timeout) => {
+                let delay = Delay::from(Duration::from_secs(timeout));
+                futures::pin_mut!(send, delay);
+                match futures::future::select(send, delay).await {
+                    futures::future::Either::Left((result, _)) => result,
+                    futures::future::Either::Right(((), _)) => {
+                        return Err(DapError::Abort(DapAbort::RetryLater {
+                            detail: format!("request to {url} timed out after {timeout}s"),
+                        }));
+                    }
+                }
+            }
+            None => send.await,
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let result = send.await;
+        // Failing to reach the peer is treated as transient, so that the request may be retried.
+        let (status, resp_headers, body) = result.map_err(|e| {
+            DapError::Abort(DapAbort::RetryLater {
+                detail: format!("request to {url} failed: {e}"),
+            })
+        })?;
         let end = Date::now().as_millis();
         info!("request to {} completed in {}ms", url, end - start);
-        let status = reqwest_resp.status();
         if status == 200 {
             // Translate the reqwest response into a Worker response.
-            let content_type = reqwest_resp
-                .headers()
+            let content_type = resp_headers
                 .get(reqwest_wasm::header::CONTENT_TYPE)
                 .ok_or_else(|| DapError::fatal(INT_ERR_PEER_RESP_MISSING_MEDIA_TYPE))?
                 .to_str()
                 .map_err(|e| DapError::Fatal(e.to_string()))?;
             let media_type = DapMediaType::from_str_for_version(req.version, Some(content_type));
 
-            let payload = reqwest_resp
-                .bytes()
-                .await
-                .map_err(|e| DapError::Fatal(e.to_string()))?
-                .to_vec();
-
             Ok(DapResponse {
                 version: req.version,
-                payload,
+                payload: body.to_vec(),
                 media_type,
             })
         } else {
-            error!("{}: request failed: {:?}", url, reqwest_resp);
-            if status == 400 {
-                if let Some(content_type) = reqwest_resp
-                    .headers()
-                    .get(reqwest_wasm::header::CONTENT_TYPE)
-                {
-                    if content_type == "application/problem+json" {
-                        error!(
-                            "Problem details: {}",
-                            reqwest_resp
-                                .text()
-                                .await
-                                .map_err(|e| DapError::Fatal(e.to_string()))?
-                        );
-                    }
-                }
-            }
-            Err(DapError::fatal(INT_ERR_PEER_ABORT))
+            error!("{}: request failed: status {}", url, status);
+            let retry_after = resp_headers
+                .get(reqwest_wasm::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+            let is_problem = resp_headers
+                .get(reqwest_wasm::header::CONTENT_TYPE)
+                .map_or(false, |content_type| {
+                    content_type == "application/problem+json"
+                });
+
+            // Surface the peer's abort so that misconfigurations, such as the peers speaking
+            // different drafts, are apparent to the operator.
+            let problem = if is_problem {
+                let problem_details = String::from_utf8_lossy(&body);
+                error!("Problem details: {problem_details}");
+                serde_json::from_str::<ProblemDetails>(&problem_details).ok()
+            } else {
+                None
+            };
+            Err(DapError::Abort(DapAbort::from_peer_response(
+                status.as_u16(),
+                retry_after,
+                problem,
+            )))
         }
     }
 }
@@ -8,57 +8,87 @@
 
 use crate::{
     auth::{DaphneWorkerAuth, DaphneWorkerAuthMethod},
-    config::{
-        DaphneWorker, GuardedBearerToken, GuardedDapTaskConfig, GuardedHpkeReceiverConfig,
-        HpkeReceiverKvKey, KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
-    },
+    config::{DaphneWorker, GuardedBearerToken, GuardedDapTaskConfig},
     dap_err,
     durable::{
         aggregate_store::{
-            DURABLE_AGGREGATE_STORE_CHECK_COLLECTED, DURABLE_AGGREGATE_STORE_GET,
-            DURABLE_AGGREGATE_STORE_MARK_COLLECTED, DURABLE_AGGREGATE_STORE_MERGE,
+            AggregateStoreCollectedReq, DURABLE_AGGREGATE_STORE_CHECK_COLLECTED,
+            DURABLE_AGGREGATE_STORE_CHECK_COLLECTED_BY, DURABLE_AGGREGATE_STORE_CHECK_FORCED,
+            DURABLE_AGGREGATE_STORE_GET, DURABLE_AGGREGATE_STORE_GET_COLLECTED_AGG_SHARE,
+            DURABLE_AGGREGATE_STORE_GET_QUERY_COUNT, DURABLE_AGGREGATE_STORE_GET_REPORT_COUNT,
+            DURABLE_AGGREGATE_STORE_MARK_COLLECTED, DURABLE_AGGREGATE_STORE_MARK_FORCED,
+            DURABLE_AGGREGATE_STORE_PUT_COLLECTED_AGG_SHARE, DURABLE_AGGREGATE_STORE_PUT_SPAN,
+        },
+        durable_name_agg_span, durable_name_agg_store, durable_name_collected_agg_share,
+        durable_name_queue, durable_name_task,
+        helper_agg_job_slots::{
+            AcquireSlotRequest, DURABLE_HELPER_AGG_JOB_SLOTS_ACQUIRE,
+            DURABLE_HELPER_AGG_JOB_SLOTS_RELEASE, DURABLE_NAME_HELPER_AGG_JOB_SLOTS,
         },
-        durable_name_agg_store, durable_name_queue, durable_name_task,
         helper_state_store::{
-            durable_helper_state_name, DURABLE_HELPER_STATE_GET, DURABLE_HELPER_STATE_PUT,
+            durable_helper_state_name, PutHelperStateRequest, DURABLE_HELPER_STATE_GET,
+            DURABLE_HELPER_STATE_PUT,
+        },
+        leader_agg_job_queue::{
+            LeaderAggJobQueueGetReq, LeaderAggJobQueueGetResp, DURABLE_LEADER_AGG_JOB_QUEUE_GET,
         },
-        leader_agg_job_queue::DURABLE_LEADER_AGG_JOB_QUEUE_GET,
         leader_batch_queue::{
             BatchCount, DURABLE_LEADER_BATCH_QUEUE_ASSIGN, DURABLE_LEADER_BATCH_QUEUE_REMOVE,
         },
         leader_col_job_queue::{
-            CollectQueueRequest, DURABLE_LEADER_COL_JOB_QUEUE_FINISH,
-            DURABLE_LEADER_COL_JOB_QUEUE_GET, DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT,
-            DURABLE_LEADER_COL_JOB_QUEUE_PUT,
+            CollectQueueRequest, DURABLE_LEADER_COL_JOB_QUEUE_DELETE,
+            DURABLE_LEADER_COL_JOB_QUEUE_FINISH, DURABLE_LEADER_COL_JOB_QUEUE_GET,
+            DURABLE_LEADER_COL_JOB_QUEUE_GET_BATCH_SELECTOR,
+            DURABLE_LEADER_COL_JOB_QUEUE_GET_REPORT_COUNTS,
+            DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT, DURABLE_LEADER_COL_JOB_QUEUE_PUT,
         },
         reports_pending::{
-            PendingReport, ReportsPendingResult, DURABLE_REPORTS_PENDING_GET,
+            PendingReport, ReportsPendingPutReq, ReportsPendingResult, DURABLE_REPORTS_PENDING_GET,
             DURABLE_REPORTS_PENDING_PUT,
         },
-        reports_processed::DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
-        BINDING_DAP_AGGREGATE_STORE, BINDING_DAP_HELPER_STATE_STORE,
-        BINDING_DAP_LEADER_AGG_JOB_QUEUE, BINDING_DAP_LEADER_BATCH_QUEUE,
-        BINDING_DAP_LEADER_COL_JOB_QUEUE, BINDING_DAP_REPORTS_PENDING,
-        BINDING_DAP_REPORTS_PROCESSED,
+        reports_processed::{
+            ReportsProcessedCheckReq, ReportsProcessedMarkReq, ReportsProcessedMarkResp,
+            DURABLE_REPORTS_PROCESSED_CHECK_AGGREGATED, DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
+            DURABLE_REPORTS_PROCESSED_UNMARK_AGGREGATED,
+        },
+        task_usage_store::TaskUsage,
+        upload_idempotency_store::{
+            durable_name_upload_idempotency_key, PutUploadRecordRequest,
+            DURABLE_UPLOAD_IDEMPOTENCY_STORE_GET, DURABLE_UPLOAD_IDEMPOTENCY_STORE_PUT,
+        },
+        BINDING_DAP_AGGREGATE_STORE, BINDING_DAP_HELPER_AGG_JOB_SLOTS,
+        BINDING_DAP_HELPER_STATE_STORE, BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+        BINDING_DAP_LEADER_BATCH_QUEUE, BINDING_DAP_LEADER_COL_JOB_QUEUE,
+        BINDING_DAP_REPORTS_PENDING, BINDING_DAP_REPORTS_PROCESSED,
+        BINDING_DAP_UPLOAD_IDEMPOTENCY_STORE,
     },
-    now, DaphneWorkerReportSelector,
+    DaphneWorkerClock, DaphneWorkerReportSelector,
 };
 use async_trait::async_trait;
 use daphne::{
     aborts::DapAbort,
-    auth::{BearerToken, BearerTokenProvider},
+    auth::{BearerToken, BearerTokenProvider, DapSenderAuth},
+    clock::Clock,
     constants::DapMediaType,
+    events::{DapBatchEvent, DapEventSink},
+    extensions::DapExtensionRegistry,
     hpke::HpkeDecrypter,
     messages::{
         BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq, HpkeCiphertext,
-        PartialBatchSelector, Report, ReportId, ReportMetadata, TaskId, TransitionFailure,
+        HpkeConfig, PartialBatchSelector, Report, ReportId, ReportMetadata, TaskId, Time,
+        TransitionFailure,
     },
     metrics::DaphneMetrics,
-    roles::{early_metadata_check, DapAggregator, DapAuthorizedSender, DapHelper, DapLeader},
-    taskprov::get_taskprov_task_config,
-    DapAggregateShare, DapBatchBucket, DapCollectJob, DapError, DapGlobalConfig, DapHelperState,
-    DapOutputShare, DapQueryConfig, DapRequest, DapResponse, DapSender, DapTaskConfig, DapVersion,
-    MetaAggregationJobId,
+    roles::{early_metadata_check, DapAggregator, DapHelper, DapLeader},
+    storage::{
+        self, DapAggShareCompaction, DapAggregateSpan, DapAggregateStore, DapCollectionJobQueue,
+        DapReportStore, DapTaskConfigStore,
+    },
+    taskprov::{get_taskprov_task_config, resolve_taskprov_version},
+    DapAggregateShare, DapBatchBucket, DapBucketReportCount, DapCollectJob, DapError,
+    DapGlobalConfig, DapHelperState, DapOutputShare, DapPendingCollectJob, DapQueryConfig,
+    DapReportCountBreakdown, DapReportSample, DapRequest, DapResponse, DapSender, DapTaskConfig,
+    DapUploadRecord, DapVersion, MetaAggregationJobId,
 };
 use futures::future::try_join_all;
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
@@ -66,7 +96,7 @@ use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
 };
-use tracing::debug;
+use tracing::{debug, instrument};
 use worker::*;
 
 pub(crate) fn dap_response_to_worker(resp: DapResponse) -> Result<Response> {
@@ -88,78 +118,18 @@ pub(crate) fn dap_response_to_worker(resp: DapResponse) -> Result<Response> {
 
 #[async_trait(?Send)]
 impl<'srv> HpkeDecrypter<'srv> for DaphneWorker<'srv> {
-    type WrappedHpkeConfig = GuardedHpkeReceiverConfig<'srv>;
+    type WrappedHpkeConfig = HpkeConfig;
 
     async fn get_hpke_config_for(
         &'srv self,
         version: DapVersion,
-        _task_id: Option<&TaskId>,
-    ) -> std::result::Result<GuardedHpkeReceiverConfig<'srv>, DapError> {
-        let kv_store = self.kv().map_err(dap_err)?;
-        let keys = kv_store
-            .list()
-            .limit(1)
-            .prefix(KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG.to_string())
-            .execute()
-            .await
-            .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?;
-
-        let hpke_receiver_kv_key = if keys.keys.is_empty() {
-            // Generate a new HPKE receiver config and store it in KV.
-            //
-            // For now, expect that only one KEM algorithm is supported and that only one config
-            // will be used at anyone time.
-            if self.config().global.supported_hpke_kems.len() != 1 {
-                return Err(DapError::Fatal(
-                    "The number of supported HPKE KEMs must be 1".to_string(),
-                ));
-            }
-
-            let mut hpke_config_id = None;
-            for it in self
-                .config()
-                .global
-                .gen_hpke_receiver_config_list(rand::random())
-            {
-                let hpke_receiver_config = it.expect("failed to generate HPKE receiver config");
-                if hpke_config_id.is_none() {
-                    hpke_config_id = Some(hpke_receiver_config.config.id);
-                }
-                let new_kv_config_key = format!(
-                    "{}/{}",
-                    KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
-                    HpkeReceiverKvKey {
-                        version,
-                        hpke_config_id: hpke_receiver_config.config.id
-                    },
-                );
-
-                kv_store
-                    .put(&new_kv_config_key, hpke_receiver_config)
-                    .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?
-                    .execute()
-                    .await
-                    .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?;
-            }
-
-            HpkeReceiverKvKey {
-                version,
-                hpke_config_id: hpke_config_id.unwrap(),
-            }
-        } else {
-            // Return the first HPKE receiver config in the list.
-            HpkeReceiverKvKey::try_from_name(keys.keys[0].name.as_str())?
-        };
-
-        // Fetch the indicated HPKE config from KV.
-        //
-        // TODO(cjpatton) Figure out how likely this is to fail if we had to generate a new key
-        // pair and write it to KV during this call.
-        Ok(self
-            .get_hpke_receiver_config(hpke_receiver_kv_key)
-            .await
-            .map_err(dap_err)?
-            .ok_or_else(|| DapError::fatal("empty HPKE receiver config list"))?)
+        task_id: Option<&TaskId>,
+    ) -> std::result::Result<HpkeConfig, DapError> {
+        self.get_hpke_config_list_for(version, task_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DapError::fatal("empty HPKE receiver config list"))
     }
 
     async fn can_hpke_decrypt(
@@ -168,14 +138,9 @@ impl<'srv> HpkeDecrypter<'srv> for DaphneWorker<'srv> {
         config_id: u8,
     ) -> std::result::Result<bool, DapError> {
         let version = self.try_get_task_config(task_id).await?.as_ref().version;
-        Ok(self
-            .get_hpke_receiver_config(HpkeReceiverKvKey {
-                version,
-                hpke_config_id: config_id,
-            })
+        self.hpke_provider()
+            .can_hpke_decrypt(version, config_id)
             .await
-            .map_err(dap_err)?
-            .is_some())
     }
 
     async fn hpke_decrypt(
@@ -186,23 +151,17 @@ impl<'srv> HpkeDecrypter<'srv> for DaphneWorker<'srv> {
         ciphertext: &HpkeCiphertext,
     ) -> std::result::Result<Vec<u8>, DapError> {
         let version = self.try_get_task_config(task_id).await?.as_ref().version;
-        if let Some(hpke_receiver_config) = self
-            .get_hpke_receiver_config(HpkeReceiverKvKey {
-                version,
-                hpke_config_id: ciphertext.config_id,
-            })
+        self.hpke_provider()
+            .hpke_decrypt(version, info, aad, ciphertext)
             .await
-            .map_err(dap_err)?
-        {
-            Ok(hpke_receiver_config.value().decrypt(
-                info,
-                aad,
-                &ciphertext.enc,
-                &ciphertext.payload,
-            )?)
-        } else {
-            Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
-        }
+    }
+
+    async fn get_hpke_config_list_for(
+        &'srv self,
+        version: DapVersion,
+        _task_id: Option<&TaskId>,
+    ) -> std::result::Result<Vec<HpkeConfig>, DapError> {
+        self.hpke_provider().get_hpke_config_list(version).await
     }
 }
 
@@ -226,6 +185,16 @@ impl<'srv> BearerTokenProvider<'srv> for DaphneWorker<'srv> {
             .map_err(dap_err)
     }
 
+    async fn get_additional_collector_bearer_token_for(
+        &'srv self,
+        task_id: &'srv TaskId,
+        collector_id: &'srv str,
+    ) -> std::result::Result<Option<BearerToken>, DapError> {
+        self.get_additional_collector_bearer_token(task_id, collector_id)
+            .await
+            .map_err(dap_err)
+    }
+
     fn is_taskprov_leader_bearer_token(&self, token: &BearerToken) -> bool {
         self.get_global_config().allow_taskprov
             && match &self.config().taskprov {
@@ -251,7 +220,7 @@ impl<'srv> BearerTokenProvider<'srv> for DaphneWorker<'srv> {
 }
 
 #[async_trait(?Send)]
-impl DapAuthorizedSender<DaphneWorkerAuth> for DaphneWorker<'_> {
+impl DapSenderAuth<DaphneWorkerAuth> for DaphneWorker<'_> {
     async fn authorize(
         &self,
         task_id: &TaskId,
@@ -260,28 +229,42 @@ impl DapAuthorizedSender<DaphneWorkerAuth> for DaphneWorker<'_> {
     ) -> std::result::Result<DaphneWorkerAuth, DapError> {
         // TODO Add support for authorizing the request with TLS client certificates:
         // https://developers.cloudflare.com/workers/runtime-apis/mtls/
-        Ok(DaphneWorkerAuth::BearerToken(
-            self.authorize_with_bearer_token(task_id, media_type)
-                .await?
-                .value()
-                .clone(),
-        ))
+        let bearer_token = self
+            .authorize_with_bearer_token(task_id, media_type)
+            .await?
+            .value()
+            .clone();
+
+        // Only the Leader authorizes requests, so the peer is the Helper.
+        let header = self
+            .resolve_auth_header_config(task_id, Some(DapSender::Helper))
+            .await
+            .map_err(dap_err)?
+            .emit;
+        Ok(DaphneWorkerAuth::BearerToken(bearer_token, header))
     }
-}
-
-#[async_trait(?Send)]
-impl<'srv, 'req> DapAggregator<'srv, 'req, DaphneWorkerAuth> for DaphneWorker<'srv>
-where
-    'srv: 'req,
-{
-    type WrappedDapTaskConfig = GuardedDapTaskConfig<'req>;
 
     async fn unauthorized_reason(
         &self,
         req: &DapRequest<DaphneWorkerAuth>,
     ) -> std::result::Result<Option<String>, DapError> {
         match req.sender_auth {
-            Some(DaphneWorkerAuth::BearerToken(..)) => self.bearer_token_authorized(req).await,
+            Some(DaphneWorkerAuth::BearerToken(_, header)) => {
+                // A request with a missing task ID is denied by `bearer_token_authorized()`.
+                if let Some(ref task_id) = req.task_id {
+                    let accept = self
+                        .resolve_auth_header_config(task_id, req.media_type.sender())
+                        .await
+                        .map_err(dap_err)?
+                        .accept;
+                    if !accept.contains(&header) {
+                        return Ok(Some(format!(
+                            "Request denied: bearer token was provided in an unaccepted header ({header:?})."
+                        )));
+                    }
+                }
+                self.bearer_token_authorized(req).await
+            }
             Some(DaphneWorkerAuth::CfTlsClientAuth {
                 ref cert_issuer,
                 ref cert_subject,
@@ -348,9 +331,238 @@ where
             None => Ok(Some("request denied: no authorization provided".into())),
         }
     }
+}
+
+impl DaphneWorker<'_> {
+    /// Implementation of [`DapAggregator::check_early_reject`]. If `mark` is not set, then the
+    /// reports are not marked as processed (see [`DapAggregator::peek_early_reject`]).
+    async fn early_reject<'b>(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &'b PartialBatchSelector,
+        report_meta: impl Iterator<Item = &'b ReportMetadata>,
+        mark: bool,
+    ) -> std::result::Result<HashMap<ReportId, TransitionFailure>, DapError> {
+        let durable = self.durable();
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_id_hex = task_id.to_hex();
+        let span = task_config
+            .as_ref()
+            .batch_span_for_meta(part_batch_sel, report_meta)?;
+
+        let current_time = self.get_current_time();
+        let global_config = self.get_global_config();
+
+        // Coalesce reports pertaining to the same ReportsProcessed or AggregateStore instance.
+        // Each report ID is sent along with the end of its replay window, if any, so that it can
+        // be purged once the window has ended.
+        let mut reports_processed_request_data: HashMap<String, (Vec<String>, Vec<Time>)> =
+            HashMap::new();
+        let mut agg_store_request_name = Vec::new();
+        let mut agg_store_request_bucket = Vec::new();
+        for (bucket, report_meta) in span.iter() {
+            agg_store_request_name.push(durable_name_agg_store(
+                &task_config.as_ref().version,
+                &task_id_hex,
+                bucket,
+            ));
+            agg_store_request_bucket.push(bucket);
+            for metadata in report_meta {
+                // While the report storage is migrated, the report is marked in both layouts. It
+                // has been processed if either says so.
+                let report_id_hex = hex::encode(metadata.id.get_encoded());
+                let replay_window_end =
+                    global_config.replay_window_end(task_config.as_ref(), metadata.time);
+                for durable_name in self.durable_names_reports_processed(
+                    task_config.as_ref(),
+                    &task_id_hex,
+                    metadata,
+                ) {
+                    let (report_id_hex_set, replay_window_ends) = reports_processed_request_data
+                        .entry(durable_name)
+                        .or_default();
+                    report_id_hex_set.push(report_id_hex.clone());
+                    replay_window_ends.extend(replay_window_end);
+                }
+            }
+        }
+
+        // Send ReportsProcessed requests.
+        let mut reports_processed_requests = Vec::new();
+        let replay_filter = task_config.as_ref().replay_filter;
+        for (durable_name, (report_id_hex_set, replay_window_ends)) in
+            reports_processed_request_data.into_iter()
+        {
+            let durable = &durable;
+            reports_processed_requests.push(async move {
+                if mark {
+                    durable
+                        .post(
+                            BINDING_DAP_REPORTS_PROCESSED,
+                            DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
+                            durable_name,
+                            ReportsProcessedMarkReq {
+                                report_id_hex_set,
+                                replay_filter,
+                                replay_window_ends,
+                                now: current_time,
+                            },
+                        )
+                        .await
+                } else {
+                    let processed = durable
+                        .post(
+                            BINDING_DAP_REPORTS_PROCESSED,
+                            DURABLE_REPORTS_PROCESSED_CHECK_AGGREGATED,
+                            durable_name,
+                            ReportsProcessedCheckReq {
+                                report_id_hex_set,
+                                now: current_time,
+                            },
+                        )
+                        .await?;
+                    Ok(ReportsProcessedMarkResp {
+                        processed,
+                        ..Default::default()
+                    })
+                }
+            });
+        }
+
+        // Send AggregateStore requests.
+        let mut agg_store_requests = Vec::new();
+        for durable_name in agg_store_request_name {
+            agg_store_requests.push(durable.get(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_CHECK_COLLECTED,
+                durable_name,
+            ));
+        }
+
+        // Create the set of reports that have been processed.
+        let reports_processed_responses: Vec<ReportsProcessedMarkResp> =
+            try_join_all(reports_processed_requests)
+                .await
+                .map_err(dap_err)?;
+        let mut reports_processed = HashSet::new();
+        let replay_filter_counter = &self.state.metrics.replay_filter_counter;
+        for response in reports_processed_responses.into_iter() {
+            let stats = response.replay_filter_stats;
+            for (result, count) in [
+                ("negative", stats.negative),
+                ("true_positive", stats.true_positive),
+                ("false_positive", stats.false_positive),
+            ] {
+                if count > 0 {
+                    replay_filter_counter
+                        .with_label_values(&[&self.state.host, result])
+                        .inc_by(count);
+                }
+            }
+            for report_id_hex in response.processed.into_iter() {
+                let report_id = ReportId::get_decoded(&hex::decode(report_id_hex)?)?;
+                reports_processed.insert(report_id);
+            }
+        }
+
+        let agg_store_responses: Vec<bool> =
+            try_join_all(agg_store_requests).await.map_err(dap_err)?;
+
+        // Decide which reports to reject early. A report will be rejected here if, for example,
+        // it has been processed but not collected, or if it has not been proceessed but pertains
+        // to a batch that was previously collected, or if it is not within time bounds specified
+        // by the configuration, including the replay window.
+        let min_time = self.least_valid_report_time(current_time);
+        let max_time = self.greatest_valid_report_time(current_time);
+        let mut early_fails = HashMap::new();
+        for (bucket, collected) in agg_store_request_bucket.iter().zip(agg_store_responses) {
+            for metadata in span.get(bucket).unwrap() {
+                let processed = reports_processed.contains(&metadata.id);
+                if let Some(failure) =
+                    early_metadata_check(metadata, processed, collected, min_time, max_time)
+                {
+                    early_fails.insert(metadata.id.clone(), failure);
+                } else if !global_config.is_replay_window_open(
+                    task_config.as_ref(),
+                    metadata.time,
+                    current_time,
+                ) {
+                    early_fails.insert(metadata.id.clone(), TransitionFailure::ReportDropped);
+                }
+            }
+        }
+
+        Ok(early_fails)
+    }
+
+    /// Store a report in its instance of `ReportsPending`. If `max_pending_reports` is set, then
+    /// the report is rejected if the instance already holds this many reports.
+    async fn store_pending_report(
+        &self,
+        task_id: &TaskId,
+        report: &Report,
+        max_pending_reports: Option<u64>,
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_id_hex = task_id.to_hex();
+        let version = task_config.as_ref().version;
+        let pending_report = PendingReport {
+            version,
+            task_id: task_id.clone(),
+            report_hex: hex::encode(report.get_encoded_with_param(&version)),
+        };
+        let res: ReportsPendingResult = self
+            .durable()
+            .post(
+                BINDING_DAP_REPORTS_PENDING,
+                DURABLE_REPORTS_PENDING_PUT,
+                self.durable_name_reports_pending(
+                    task_config.as_ref(),
+                    &task_id_hex,
+                    &report.report_metadata,
+                ),
+                &ReportsPendingPutReq {
+                    pending_report,
+                    max_pending_reports,
+                },
+            )
+            .await
+            .map_err(dap_err)?;
+
+        match res {
+            ReportsPendingResult::Ok => Ok(()),
+            ReportsPendingResult::ErrReportExists => {
+                // NOTE This check for report replay is not definitive. It's possible for two
+                // reports with the same ID to appear in two different ReportsPending instances.
+                // The definitive check is performed by DapAggregator::check_early_reject(), which
+                // tracks all report IDs consumed for the task in ReportsProcessed. This check
+                // would be too expensive to do during the upload sub-protocol.
+                Err(DapError::Transition(TransitionFailure::ReportReplayed))
+            }
+            ReportsPendingResult::ErrBucketFull => {
+                self.state
+                    .metrics
+                    .report_bucket_full_counter
+                    .with_label_values(&[&self.state.host])
+                    .inc();
+                Err(DapError::Abort(DapAbort::ReportRejected {
+                    detail: "Too many reports are pending aggregation for the report's time."
+                        .into(),
+                }))
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<'srv, 'req> DapAggregator<'srv, 'req, DaphneWorkerAuth> for DaphneWorker<'srv>
+where
+    'srv: 'req,
+{
+    type WrappedDapTaskConfig = GuardedDapTaskConfig<'req>;
 
     fn get_global_config(&self) -> &DapGlobalConfig {
-        &self.config().global
+        &self.state.global_config
     }
 
     fn taskprov_opt_out_reason(
@@ -368,6 +580,7 @@ where
         &'srv self,
         version: DapVersion,
         task_id: Cow<'req, TaskId>,
+        taskprov_advertisement: Option<&str>,
         metadata: Option<&ReportMetadata>,
     ) -> std::result::Result<Option<GuardedDapTaskConfig<'req>>, DapError> {
         let found = self
@@ -383,9 +596,14 @@ where
             return Ok(None);
         }
         let metadata_ref = metadata.unwrap();
+        let taskprov_version = resolve_taskprov_version(
+            self.state.global_config.taskprov_version,
+            taskprov_advertisement,
+        );
         let taskprov_task_config = get_taskprov_task_config(
-            self.config().global.taskprov_version,
+            taskprov_version,
             task_id.as_ref(),
+            taskprov_advertisement,
             metadata_ref,
         )?;
         if taskprov_task_config.is_some() {
@@ -406,7 +624,7 @@ where
             let taskprov_task_id = task_id.as_ref().clone();
             let task_config = DapTaskConfig::try_from_taskprov(
                 version,
-                self.config().global.taskprov_version,
+                taskprov_version,
                 &taskprov_task_id,
                 taskprov_task_config.unwrap(),
                 &taskprov.vdaf_verify_key_init,
@@ -442,6 +660,12 @@ where
                 .await
                 .map_err(dap_err)?;
 
+            // Record that the task was configured by taskprov so that the operator can be
+            // notified before it expires.
+            self.set_taskprov_task(&taskprov_task_id)
+                .await
+                .map_err(dap_err)?;
+
             // Do the usual get again so we cache and return the right type.
             self.get_task_config(Cow::Owned(taskprov_task_id))
                 .await
@@ -451,41 +675,30 @@ where
         }
     }
 
-    fn get_current_time(&self) -> u64 {
-        now()
+    fn clock(&self) -> &dyn Clock {
+        self.state.clock.unwrap_or(&DaphneWorkerClock)
     }
 
     async fn is_batch_overlapping(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
     ) -> std::result::Result<bool, DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
+        storage::is_batch_overlapping(self, task_id, task_config.as_ref(), batch_sel, collector_id)
+            .await
+    }
 
-        // Check whether the request overlaps with previous requests. This is done by
-        // checking the AggregateStore and seeing whether it requests for aggregate
-        // shares that have already been marked collected.
-        let durable = self.durable();
-        let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
-            let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.get(
-                BINDING_DAP_AGGREGATE_STORE,
-                DURABLE_AGGREGATE_STORE_CHECK_COLLECTED,
-                durable_name,
-            ));
-        }
-
-        let responses: Vec<bool> = try_join_all(requests).await.map_err(dap_err)?;
-
-        for collected in responses {
-            if collected {
-                return Ok(true);
-            }
-        }
-
-        Ok(false)
+    async fn get_batch_query_count(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+    ) -> std::result::Result<u64, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        storage::get_batch_query_count(self, task_id, task_config.as_ref(), batch_sel, collector_id)
+            .await
     }
 
     async fn batch_exists(
@@ -494,200 +707,190 @@ where
         batch_id: &BatchId,
     ) -> std::result::Result<bool, DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
-
-        let agg_share: DapAggregateShare = self
-            .durable()
-            .get(
-                BINDING_DAP_AGGREGATE_STORE,
-                DURABLE_AGGREGATE_STORE_GET,
-                durable_name_agg_store(
-                    &task_config.as_ref().version,
-                    &task_id.to_hex(),
-                    &DapBatchBucket::FixedSize { batch_id },
-                ),
-            )
-            .await
-            .map_err(dap_err)?;
-
-        Ok(!agg_share.empty())
+        storage::batch_exists(self, task_id, task_config.as_ref(), batch_id).await
     }
 
-    async fn put_out_shares(
+    async fn mark_batch_forced(
         &self,
         task_id: &TaskId,
-        part_batch_sel: &PartialBatchSelector,
-        out_shares: Vec<DapOutputShare>,
+        batch_id: &BatchId,
     ) -> std::result::Result<(), DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
-
-        let durable = self.durable();
-        let mut requests = Vec::new();
-        for (bucket, agg_share) in task_config
-            .as_ref()
-            .batch_span_for_out_shares(part_batch_sel, out_shares)?
-        {
-            let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.post::<_, ()>(
-                BINDING_DAP_AGGREGATE_STORE,
-                DURABLE_AGGREGATE_STORE_MERGE,
-                durable_name,
-                agg_share,
-            ));
-        }
-        try_join_all(requests).await.map_err(dap_err)?;
-        Ok(())
+        storage::mark_batch_forced(self, task_id, task_config.as_ref(), batch_id).await
     }
 
-    async fn get_agg_share(
+    async fn is_batch_forced(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
-    ) -> std::result::Result<DapAggregateShare, DapError> {
+    ) -> std::result::Result<bool, DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
+        storage::is_batch_forced(self, task_id, task_config.as_ref(), batch_sel).await
+    }
 
-        let durable = self.durable();
-        let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
-            let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.get(
-                BINDING_DAP_AGGREGATE_STORE,
-                DURABLE_AGGREGATE_STORE_GET,
-                durable_name,
-            ));
-        }
-        let responses: Vec<DapAggregateShare> = try_join_all(requests).await.map_err(dap_err)?;
-        let mut agg_share = DapAggregateShare::default();
-        for agg_share_delta in responses {
-            agg_share.merge(agg_share_delta)?;
-        }
-
-        Ok(agg_share)
+    #[instrument(skip_all, fields(%task_id, report_count = out_shares.len()))]
+    async fn put_out_shares(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        out_shares: Vec<DapOutputShare>,
+    ) -> std::result::Result<Vec<DapBatchEvent>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let reports_aggregated = out_shares.len() as u64;
+        let events = storage::put_out_shares(
+            self,
+            task_id,
+            task_config.as_ref(),
+            part_batch_sel,
+            out_shares,
+        )
+        .await?;
+
+        self.record_task_usage(
+            task_id,
+            task_config.as_ref(),
+            TaskUsage {
+                reports_aggregated,
+                ..Default::default()
+            },
+        )
+        .await?;
+        Ok(events)
     }
 
-    async fn check_early_reject<'b>(
+    #[instrument(skip_all, fields(%task_id))]
+    async fn get_agg_share(
         &self,
         task_id: &TaskId,
-        part_batch_sel: &'b PartialBatchSelector,
-        report_meta: impl Iterator<Item = &'b ReportMetadata>,
-    ) -> std::result::Result<HashMap<ReportId, TransitionFailure>, DapError> {
-        let durable = self.durable();
+        batch_sel: &BatchSelector,
+    ) -> std::result::Result<DapAggregateShare, DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
-        let task_id_hex = task_id.to_hex();
-        let span = task_config
-            .as_ref()
-            .batch_span_for_meta(part_batch_sel, report_meta)?;
-
-        // Coalesce reports pertaining to the same ReportsProcessed or AggregateStore instance.
-        let mut reports_processed_request_data: HashMap<String, Vec<String>> = HashMap::new();
-        let mut agg_store_request_name = Vec::new();
-        let mut agg_store_request_bucket = Vec::new();
-        for (bucket, report_meta) in span.iter() {
-            agg_store_request_name.push(durable_name_agg_store(
-                &task_config.as_ref().version,
-                &task_id_hex,
-                bucket,
-            ));
-            agg_store_request_bucket.push(bucket);
-            for metadata in report_meta {
-                let durable_name = self.config().durable_name_report_store(
+        match self.config().global.helper_agg_share_span_buckets {
+            Some(span_buckets) if !self.config().is_leader => {
+                // Buckets that end before the oldest valid report time no longer change.
+                let compaction = DapAggShareCompaction {
+                    span_buckets,
+                    not_after: self.least_valid_report_time(self.get_current_time()),
+                };
+                storage::get_compacted_agg_share(
+                    self,
+                    task_id,
                     task_config.as_ref(),
-                    &task_id_hex,
-                    metadata,
-                );
-                let report_id_hex = hex::encode(metadata.id.get_encoded());
-                let report_id_hex_set = reports_processed_request_data
-                    .entry(durable_name)
-                    .or_default();
-                report_id_hex_set.push(report_id_hex);
-            }
-        }
-
-        // Send ReportsProcessed requests.
-        let mut reports_processed_requests = Vec::new();
-        for (durable_name, report_id_hex_set) in reports_processed_request_data.into_iter() {
-            reports_processed_requests.push(durable.post(
-                BINDING_DAP_REPORTS_PROCESSED,
-                DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED,
-                durable_name,
-                report_id_hex_set,
-            ));
-        }
-
-        // Send AggregateStore requests.
-        let mut agg_store_requests = Vec::new();
-        for durable_name in agg_store_request_name {
-            agg_store_requests.push(durable.get(
-                BINDING_DAP_AGGREGATE_STORE,
-                DURABLE_AGGREGATE_STORE_CHECK_COLLECTED,
-                durable_name,
-            ));
-        }
-
-        // Create the set of reports that have been processed.
-        let reports_processed_responses: Vec<Vec<String>> =
-            try_join_all(reports_processed_requests)
+                    batch_sel,
+                    &compaction,
+                )
                 .await
-                .map_err(dap_err)?;
-        let mut reports_processed = HashSet::new();
-        for response in reports_processed_responses.into_iter() {
-            for report_id_hex in response.into_iter() {
-                let report_id = ReportId::get_decoded(&hex::decode(report_id_hex)?)?;
-                reports_processed.insert(report_id);
             }
+            _ => storage::get_agg_share(self, task_id, task_config.as_ref(), batch_sel).await,
         }
+    }
 
-        let agg_store_responses: Vec<bool> =
-            try_join_all(agg_store_requests).await.map_err(dap_err)?;
-
-        // Decide which reports to reject early. A report will be rejected here if, for example,
-        // it has been processed but not collected, or if it has not been proceessed but pertains
-        // to a batch that was previously collected, or if it is not within time bounds specified
-        // by the configuration.
-        let current_time = self.get_current_time();
-        let min_time = self.least_valid_report_time(current_time);
-        let max_time = self.greatest_valid_report_time(current_time);
-        let mut early_fails = HashMap::new();
-        for (bucket, collected) in agg_store_request_bucket
-            .iter()
-            .zip(agg_store_responses.into_iter())
-        {
-            for metadata in span.get(bucket).unwrap() {
-                let processed = reports_processed.contains(&metadata.id);
-                if let Some(failure) =
-                    early_metadata_check(metadata, processed, collected, min_time, max_time)
-                {
-                    early_fails.insert(metadata.id.clone(), failure);
-                }
-            }
-        }
+    #[instrument(skip_all, fields(%task_id))]
+    async fn check_early_reject<'b>(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &'b PartialBatchSelector,
+        report_meta: impl Iterator<Item = &'b ReportMetadata>,
+    ) -> std::result::Result<HashMap<ReportId, TransitionFailure>, DapError> {
+        self.early_reject(task_id, part_batch_sel, report_meta, true)
+            .await
+    }
 
-        Ok(early_fails)
+    #[instrument(skip_all, fields(%task_id))]
+    async fn peek_early_reject<'b>(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &'b PartialBatchSelector,
+        report_meta: impl Iterator<Item = &'b ReportMetadata>,
+    ) -> std::result::Result<HashMap<ReportId, TransitionFailure>, DapError> {
+        self.early_reject(task_id, part_batch_sel, report_meta, false)
+            .await
     }
 
+    #[instrument(skip_all, fields(%task_id))]
     async fn mark_collected(
         &self,
         task_id: &TaskId,
         batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
     ) -> std::result::Result<(), DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
+        storage::mark_collected(self, task_id, task_config.as_ref(), batch_sel, collector_id)
+            .await?;
+
+        self.record_task_usage(
+            task_id,
+            task_config.as_ref(),
+            TaskUsage {
+                collections_served: 1,
+                ..Default::default()
+            },
+        )
+        .await
+    }
 
-        let durable = self.durable();
-        let mut requests = Vec::new();
-        for bucket in task_config.as_ref().batch_span_for_sel(batch_sel)? {
-            let durable_name =
-                durable_name_agg_store(&task_config.as_ref().version, &task_id.to_hex(), &bucket);
-            requests.push(durable.post::<_, ()>(
+    #[instrument(skip_all, fields(%task_id))]
+    async fn get_collected_agg_share(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+    ) -> std::result::Result<Option<DapAggregateShare>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.durable()
+            .post(
                 BINDING_DAP_AGGREGATE_STORE,
-                DURABLE_AGGREGATE_STORE_MARK_COLLECTED,
-                durable_name,
-                &(),
-            ));
-        }
+                DURABLE_AGGREGATE_STORE_GET_COLLECTED_AGG_SHARE,
+                durable_name_collected_agg_share(
+                    &task_config.as_ref().version,
+                    &task_id.to_hex(),
+                    batch_sel,
+                ),
+                &AggregateStoreCollectedReq {
+                    batch_sel: batch_sel.clone(),
+                    collector_id: collector_id.map(str::to_string),
+                    agg_share: None,
+                },
+            )
+            .await
+            .map_err(dap_err)
+    }
 
-        try_join_all(requests).await.map_err(dap_err)?;
-        Ok(())
+    #[instrument(skip_all, fields(%task_id))]
+    async fn put_collected_agg_share(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+        collector_id: Option<&str>,
+        agg_share: &DapAggregateShare,
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.durable()
+            .post(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_PUT_COLLECTED_AGG_SHARE,
+                durable_name_collected_agg_share(
+                    &task_config.as_ref().version,
+                    &task_id.to_hex(),
+                    batch_sel,
+                ),
+                &AggregateStoreCollectedReq {
+                    batch_sel: batch_sel.clone(),
+                    collector_id: collector_id.map(str::to_string),
+                    agg_share: Some(agg_share.clone()),
+                },
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn rotate_hpke_config(
+        &self,
+        version: DapVersion,
+    ) -> std::result::Result<Option<HpkeConfig>, DapError> {
+        self.rotate_hpke_receiver_configs(version)
+            .await
+            .map_err(dap_err)
     }
 
     async fn current_batch(&self, task_id: &TaskId) -> std::result::Result<BatchId, DapError> {
@@ -697,6 +900,18 @@ where
     fn metrics(&self) -> &DaphneMetrics {
         &self.state.metrics.daphne
     }
+
+    fn extension_registry(&self) -> &DapExtensionRegistry {
+        self.state.extension_registry
+    }
+
+    fn event_sink(&self) -> &dyn DapEventSink {
+        self
+    }
+
+    async fn sleep(&self, duration: std::time::Duration) {
+        Delay::from(duration).await;
+    }
 }
 
 #[async_trait(?Send)]
@@ -706,67 +921,364 @@ where
 {
     type ReportSelector = DaphneWorkerReportSelector;
 
-    async fn put_report(
+    #[instrument(skip_all, fields(%task_id, report_id = %report.report_metadata.id))]
+    async fn put_report(
+        &self,
+        report: &Report,
+        task_id: &TaskId,
+    ) -> std::result::Result<(), DapError> {
+        self.put_pending_report(task_id, report).await?;
+
+        let task_config = self.try_get_task_config(task_id).await?;
+        let report_len = report
+            .get_encoded_with_param(&task_config.as_ref().version)
+            .len();
+        self.record_task_usage(
+            task_id,
+            task_config.as_ref(),
+            TaskUsage {
+                uploads_accepted: 1,
+                bytes_stored: report_len as u64,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn get_reports(
+        &self,
+        report_sel: &DaphneWorkerReportSelector,
+    ) -> std::result::Result<HashMap<TaskId, HashMap<PartialBatchSelector, Vec<Report>>>, DapError>
+    {
+        self.take_pending_reports(report_sel).await
+    }
+
+    async fn requeue_reports(
+        &self,
+        task_id: &TaskId,
+        part_batch_sel: &PartialBatchSelector,
+        reports: Vec<Report>,
+    ) -> std::result::Result<(), DapError> {
+        self.requeue_pending_reports(task_id, part_batch_sel, reports)
+            .await
+    }
+
+    async fn count_abandoned_reports(
+        &self,
+        task_id: &TaskId,
+        reports: &[Report],
+    ) -> std::result::Result<Vec<u32>, DapError> {
+        self.incr_abandoned_report_counts(task_id, reports)
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn put_report_sample(
+        &self,
+        task_id: &TaskId,
+        sample: &DapReportSample,
+    ) -> std::result::Result<(), DapError> {
+        self.store_report_sample(task_id, sample)
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn get_upload_record(
+        &self,
+        task_id: &TaskId,
+        idempotency_key: &str,
+    ) -> std::result::Result<Option<DapUploadRecord>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.durable()
+            .get(
+                BINDING_DAP_UPLOAD_IDEMPOTENCY_STORE,
+                DURABLE_UPLOAD_IDEMPOTENCY_STORE_GET,
+                durable_name_upload_idempotency_key(
+                    &task_config.as_ref().version,
+                    &task_id.to_hex(),
+                    idempotency_key,
+                ),
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn put_upload_record(
+        &self,
+        task_id: &TaskId,
+        idempotency_key: &str,
+        record: &DapUploadRecord,
+        report_time: Time,
+    ) -> std::result::Result<Option<DapUploadRecord>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let expiration = report_time
+            .saturating_add(self.config().global.report_storage_epoch_duration)
+            .max(self.get_current_time() + 60);
+        self.durable()
+            .post(
+                BINDING_DAP_UPLOAD_IDEMPOTENCY_STORE,
+                DURABLE_UPLOAD_IDEMPOTENCY_STORE_PUT,
+                durable_name_upload_idempotency_key(
+                    &task_config.as_ref().version,
+                    &task_id.to_hex(),
+                    idempotency_key,
+                ),
+                &PutUploadRecordRequest {
+                    record: record.clone(),
+                    expiration,
+                },
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn defer_helper(
+        &self,
+        helper_url: &Url,
+        until: Time,
+    ) -> std::result::Result<(), DapError> {
+        self.put_helper_deferral(helper_url, until)
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn helper_deferred_until(
+        &self,
+        helper_url: &Url,
+    ) -> std::result::Result<Option<Time>, DapError> {
+        self.get_helper_deferral(helper_url).await.map_err(dap_err)
+    }
+
+    async fn count_helper_auth_failure(
+        &self,
+        helper_url: &Url,
+    ) -> std::result::Result<u32, DapError> {
+        self.incr_helper_auth_failures(helper_url)
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn reset_helper_auth_failures(
+        &self,
+        helper_url: &Url,
+    ) -> std::result::Result<(), DapError> {
+        self.delete_helper_auth_failures(helper_url)
+            .await
+            .map_err(dap_err)
+    }
+
+    #[instrument(skip_all, fields(%task_id))]
+    async fn init_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_job_id: &Option<CollectionJobId>,
+        collect_req: &CollectionReq,
+        collector_id: Option<&str>,
+    ) -> std::result::Result<Url, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.put_collection_job(
+            task_id,
+            task_config.as_ref(),
+            collect_job_id,
+            collect_req,
+            collector_id,
+        )
+        .await
+    }
+
+    async fn poll_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> std::result::Result<DapCollectJob, DapError> {
+        self.get_collection_job(task_id, collect_id).await
+    }
+
+    async fn delete_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+    ) -> std::result::Result<DapCollectJob, DapError> {
+        self.delete_collection_job(task_id, collect_id).await
+    }
+
+    async fn get_pending_collect_jobs(
+        &self,
+    ) -> std::result::Result<Vec<DapPendingCollectJob>, DapError> {
+        self.get_pending_collection_jobs().await
+    }
+
+    async fn get_report_counts(
+        &self,
+        task_id: &TaskId,
+        batch_sel: &BatchSelector,
+    ) -> std::result::Result<Vec<DapBucketReportCount>, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        storage::get_report_counts(self, task_id, task_config.as_ref(), batch_sel).await
+    }
+
+    #[instrument(
+        skip_all,
+        fields(%task_id, collect_job_id = %collect_id, report_count = collect_resp.report_count)
+    )]
+    async fn finish_collect_job(
+        &self,
+        task_id: &TaskId,
+        collect_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
+        collect_resp: &Collection,
+        report_counts: &DapReportCountBreakdown,
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.finish_collection_job(
+            task_id,
+            task_config.as_ref(),
+            collect_id,
+            batch_sel,
+            collect_resp,
+            report_counts,
+        )
+        .await
+    }
+
+    async fn on_collect_job_finished(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        collect_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+    ) -> std::result::Result<(), DapError> {
+        self.export_collection(task_id, task_config, collect_id, batch_sel, collection)
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn close_current_batch(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<Option<BatchId>, DapError> {
+        self.internal_close_current_batch(task_id).await
+    }
+
+    #[instrument(skip_all, fields(url = %req.url))]
+    async fn send_http_post(
+        &self,
+        req: DapRequest<DaphneWorkerAuth>,
+    ) -> std::result::Result<DapResponse, DapError> {
+        self.send_http(req, false).await
+    }
+
+    #[instrument(skip_all, fields(url = %req.url))]
+    async fn send_http_put(
+        &self,
+        req: DapRequest<DaphneWorkerAuth>,
+    ) -> std::result::Result<DapResponse, DapError> {
+        self.send_http(req, true).await
+    }
+}
+
+#[async_trait(?Send)]
+impl DapTaskConfigStore for DaphneWorker<'_> {
+    async fn get_task_config_by_id(
+        &self,
+        task_id: &TaskId,
+    ) -> std::result::Result<Option<DapTaskConfig>, DapError> {
+        Ok(self
+            .get_task_config(Cow::Borrowed(task_id))
+            .await
+            .map_err(dap_err)?
+            .map(|task_config| task_config.as_ref().clone()))
+    }
+
+    async fn put_task_config(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+    ) -> std::result::Result<(), DapError> {
+        self.set_task_config(task_id, task_config)
+            .await
+            .map_err(dap_err)?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl DapReportStore for DaphneWorker<'_> {
+    type ReportSelector = DaphneWorkerReportSelector;
+
+    async fn put_pending_report(
         &self,
-        report: &Report,
         task_id: &TaskId,
+        report: &Report,
     ) -> std::result::Result<(), DapError> {
-        let task_config = self.try_get_task_config(task_id).await?;
-        let task_id_hex = task_id.to_hex();
-        let version = task_config.as_ref().version;
-        let pending_report = PendingReport {
-            version,
-            task_id: task_id.clone(),
-            report_hex: hex::encode(report.get_encoded_with_param(&version)),
-        };
-        let res: ReportsPendingResult = self
-            .durable()
-            .post(
-                BINDING_DAP_REPORTS_PENDING,
-                DURABLE_REPORTS_PENDING_PUT,
-                self.config().durable_name_report_store(
-                    task_config.as_ref(),
-                    &task_id_hex,
-                    &report.report_metadata,
-                ),
-                &pending_report,
-            )
-            .await
-            .map_err(dap_err)?;
-
-        match res {
-            ReportsPendingResult::Ok => Ok(()),
-            ReportsPendingResult::ErrReportExists => {
-                // NOTE This check for report replay is not definitive. It's possible for two
-                // reports with the same ID to appear in two different ReportsPending instances.
-                // The definitive check is performed by DapAggregator::check_early_reject(), which
-                // tracks all report IDs consumed for the task in ReportsProcessed. This check
-                // would be too expensive to do during the upload sub-protocol.
-                Err(DapError::Transition(TransitionFailure::ReportReplayed))
-            }
-        }
+        self.store_pending_report(
+            task_id,
+            report,
+            self.config().max_pending_reports_per_bucket,
+        )
+        .await
     }
 
-    async fn get_reports(
+    async fn take_pending_reports(
         &self,
         report_sel: &DaphneWorkerReportSelector,
     ) -> std::result::Result<HashMap<TaskId, HashMap<PartialBatchSelector, Vec<Report>>>, DapError>
     {
         let durable = self.durable();
-        // Read at most `report_sel.max_buckets` buckets from the agg job queue. The result is ordered
-        // from oldest to newest.
+        let quarantined = self.quarantined_reports_pending().await?;
+
+        // Read at most `report_sel.max_buckets` buckets from the agg job queue, starting after the
+        // cursor, if any. The result is ordered from oldest to newest. Quarantined buckets are
+        // skipped; they remain in the queue and are fetched again once the quarantine expires.
         //
         // NOTE There is only one agg job queue for now (`queue_num == 0`). In the future, work
         // will be sharded across multiple queues.
-        let res: Vec<String> = durable
+        let max_agg_jobs = report_sel.max_agg_jobs as usize + quarantined.len();
+        let agg_jobs: LeaderAggJobQueueGetResp = durable
             .post(
                 BINDING_DAP_LEADER_AGG_JOB_QUEUE,
                 DURABLE_LEADER_AGG_JOB_QUEUE_GET,
                 durable_name_queue(0),
-                &report_sel.max_agg_jobs,
+                &LeaderAggJobQueueGetReq {
+                    max_agg_jobs,
+                    after: report_sel.cursor.clone(),
+                },
             )
             .await
             .map_err(dap_err)?;
+        let num_fetched = agg_jobs.len();
+        let mut res = Vec::new();
+        let mut num_considered = 0;
+        let mut num_quarantined = 0;
+        let mut cursor = None;
+        for (ordinal, reports_pending_id_hex) in agg_jobs.into_iter() {
+            if res.len() as u64 == report_sel.max_agg_jobs {
+                break;
+            }
+            num_considered += 1;
+            cursor = Some(ordinal);
+            if quarantined.contains(&reports_pending_id_hex) {
+                num_quarantined += 1;
+            } else {
+                res.push(reports_pending_id_hex);
+            }
+        }
+        if num_quarantined > 0 {
+            self.state
+                .metrics
+                .agg_job_quarantined_counter
+                .with_label_values(&[&self.state.host])
+                .inc_by(num_quarantined);
+        }
+
+        // The next run resumes after the last job considered by this run. If the end of the queue
+        // was reached, then the next run starts from the front again.
+        if num_considered == num_fetched && num_fetched < max_agg_jobs {
+            cursor = None;
+        }
+        *self.state.process_cursor.borrow_mut() = cursor;
 
         // Drain at most `report_sel.max_reports` from each ReportsPending instance and group them
         // by task.
@@ -826,7 +1338,12 @@ where
                             BINDING_DAP_LEADER_BATCH_QUEUE,
                             DURABLE_LEADER_BATCH_QUEUE_ASSIGN,
                             durable_name_task(&task_config.as_ref().version, &task_id_hex),
-                            &(task_config.as_ref().min_batch_size, num_unassigned),
+                            &(
+                                task_config.as_ref().min_batch_size,
+                                num_unassigned,
+                                self.get_current_time(),
+                                task_config.as_ref().query.batch_lifetime(),
+                            ),
                         )
                         .await
                         .map_err(dap_err)?;
@@ -834,6 +1351,7 @@ where
                         let BatchCount {
                             batch_id,
                             report_count,
+                            ..
                         } = batch_count;
                         reports_per_part.insert(
                             PartialBatchSelector::FixedSizeByBatchId { batch_id },
@@ -864,19 +1382,247 @@ where
         Ok(reports_per_task_part)
     }
 
-    async fn init_collect_job(
+    async fn requeue_pending_reports(
+        &self,
+        task_id: &TaskId,
+        _part_batch_sel: &PartialBatchSelector,
+        reports: Vec<Report>,
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        let task_id_hex = task_id.to_hex();
+
+        // Coalesce reports pertaining to the same ReportsProcessed instance.
+        let mut reports_processed_request_data: HashMap<String, Vec<String>> = HashMap::new();
+        for report in reports.iter() {
+            let report_id_hex = hex::encode(report.report_metadata.id.get_encoded());
+            for durable_name in self.durable_names_reports_processed(
+                task_config.as_ref(),
+                &task_id_hex,
+                &report.report_metadata,
+            ) {
+                reports_processed_request_data
+                    .entry(durable_name)
+                    .or_default()
+                    .push(report_id_hex.clone());
+            }
+        }
+
+        let durable = self.durable();
+        let mut requests = Vec::new();
+        for (durable_name, report_id_hex_set) in reports_processed_request_data.into_iter() {
+            requests.push(durable.post::<_, ()>(
+                BINDING_DAP_REPORTS_PROCESSED,
+                DURABLE_REPORTS_PROCESSED_UNMARK_AGGREGATED,
+                durable_name,
+                report_id_hex_set,
+            ));
+        }
+        try_join_all(requests).await.map_err(dap_err)?;
+
+        // Put the reports back into ReportsPending.
+        //
+        // NOTE For fixed-size tasks, the reports will be assigned to a batch again by
+        // LeaderBatchQueue. The batch they were originally assigned to may end up with fewer
+        // reports than expected.
+        for report in reports.iter() {
+            self.store_pending_report(task_id, report, None).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl DapAggregateStore for DaphneWorker<'_> {
+    async fn merge_bucket_agg_share(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        agg_share: DapAggregateShare,
+    ) -> std::result::Result<Option<u64>, DapError> {
+        let durable_name = durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket);
+        self.merge_agg_share(durable_name, agg_share).await
+    }
+
+    async fn get_bucket_agg_share(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> std::result::Result<DapAggregateShare, DapError> {
+        self.durable()
+            .get(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_GET,
+                durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket),
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn get_bucket_report_count(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> std::result::Result<u64, DapError> {
+        self.durable()
+            .get(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_GET_REPORT_COUNT,
+                durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket),
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn mark_bucket_collected(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> std::result::Result<(), DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_MARK_COLLECTED,
+                durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket),
+                &collector_id,
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn is_bucket_collected(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> std::result::Result<bool, DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_CHECK_COLLECTED_BY,
+                durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket),
+                &collector_id,
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn get_bucket_query_count(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+        collector_id: Option<&str>,
+    ) -> std::result::Result<u64, DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_GET_QUERY_COUNT,
+                durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket),
+                &collector_id,
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn mark_bucket_forced(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> std::result::Result<(), DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_MARK_FORCED,
+                durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket),
+                &(),
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn is_bucket_forced(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        bucket: &DapBatchBucket<'_>,
+    ) -> std::result::Result<bool, DapError> {
+        self.durable()
+            .get(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_CHECK_FORCED,
+                durable_name_agg_store(&task_config.version, &task_id.to_hex(), bucket),
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn get_span_agg_share(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        span: &DapAggregateSpan,
+    ) -> std::result::Result<Option<DapAggregateShare>, DapError> {
+        let agg_share: DapAggregateShare = self
+            .durable()
+            .get(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_GET,
+                durable_name_agg_span(&task_config.version, &task_id.to_hex(), span),
+            )
+            .await
+            .map_err(dap_err)?;
+        // An empty span is indistinguishable from one that has not been compacted yet; either
+        // way, reading its buckets is cheap.
+        Ok(if agg_share.empty() {
+            None
+        } else {
+            Some(agg_share)
+        })
+    }
+
+    async fn put_span_agg_share(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        span: &DapAggregateSpan,
+        agg_share: DapAggregateShare,
+    ) -> std::result::Result<(), DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_AGGREGATE_STORE,
+                DURABLE_AGGREGATE_STORE_PUT_SPAN,
+                durable_name_agg_span(&task_config.version, &task_id.to_hex(), span),
+                &agg_share,
+            )
+            .await
+            .map_err(dap_err)
+    }
+}
+
+#[async_trait(?Send)]
+impl DapCollectionJobQueue for DaphneWorker<'_> {
+    async fn put_collection_job(
         &self,
         task_id: &TaskId,
+        task_config: &DapTaskConfig,
         collect_job_id: &Option<CollectionJobId>,
         collect_req: &CollectionReq,
+        collector_id: Option<&str>,
     ) -> std::result::Result<Url, DapError> {
-        let task_config = self.try_get_task_config(task_id).await?;
         // Try to put the request into collection job queue. If the request is overlapping
         // with past requests, then abort.
         let collect_queue_req = CollectQueueRequest {
             collect_req: collect_req.clone(),
             task_id: task_id.clone(),
             collect_job_id: collect_job_id.clone(),
+            collector_id: collector_id.map(str::to_string),
         };
         let collect_id: CollectionJobId = self
             .durable()
@@ -890,7 +1636,7 @@ where
             .map_err(dap_err)?;
         debug!("assigned collect_id {collect_id}");
 
-        let url = task_config.as_ref().leader_url.clone();
+        let url = task_config.leader_url.clone();
 
         // Note that we always return the draft02 URI, but draft04 and later ignore it.
         let collect_uri = url
@@ -904,10 +1650,10 @@ where
         Ok(collect_uri)
     }
 
-    async fn poll_collect_job(
+    async fn get_collection_job(
         &self,
         task_id: &TaskId,
-        collect_id: &CollectionJobId,
+        collect_job_id: &CollectionJobId,
     ) -> std::result::Result<DapCollectJob, DapError> {
         let res: DapCollectJob = self
             .durable()
@@ -915,17 +1661,17 @@ where
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT,
                 durable_name_queue(0),
-                (&task_id, &collect_id),
+                (&task_id, &collect_job_id),
             )
             .await
             .map_err(dap_err)?;
         Ok(res)
     }
 
-    async fn get_pending_collect_jobs(
+    async fn get_pending_collection_jobs(
         &self,
-    ) -> std::result::Result<Vec<(TaskId, CollectionJobId, CollectionReq)>, DapError> {
-        let res: Vec<(TaskId, CollectionJobId, CollectionReq)> = self
+    ) -> std::result::Result<Vec<DapPendingCollectJob>, DapError> {
+        let res: Vec<DapPendingCollectJob> = self
             .durable()
             .get(
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
@@ -937,22 +1683,23 @@ where
         Ok(res)
     }
 
-    async fn finish_collect_job(
+    async fn finish_collection_job(
         &self,
         task_id: &TaskId,
-        collect_id: &CollectionJobId,
-        collect_resp: &Collection,
+        task_config: &DapTaskConfig,
+        collect_job_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+        report_counts: &DapReportCountBreakdown,
     ) -> std::result::Result<(), DapError> {
-        let task_config = self.try_get_task_config(task_id).await?;
         let durable = self.durable();
-        if let PartialBatchSelector::FixedSizeByBatchId { ref batch_id } =
-            collect_resp.part_batch_sel
+        if let PartialBatchSelector::FixedSizeByBatchId { ref batch_id } = collection.part_batch_sel
         {
             durable
                 .post(
                     BINDING_DAP_LEADER_BATCH_QUEUE,
                     DURABLE_LEADER_BATCH_QUEUE_REMOVE,
-                    durable_name_task(&task_config.as_ref().version, &task_id.to_hex()),
+                    durable_name_task(&task_config.version, &task_id.to_hex()),
                     batch_id.to_hex(),
                 )
                 .await
@@ -964,25 +1711,65 @@ where
                 BINDING_DAP_LEADER_COL_JOB_QUEUE,
                 DURABLE_LEADER_COL_JOB_QUEUE_FINISH,
                 durable_name_queue(0),
-                (task_id, collect_id, collect_resp),
+                (
+                    task_id,
+                    collect_job_id,
+                    batch_sel,
+                    collection,
+                    report_counts,
+                ),
             )
             .await
             .map_err(dap_err)?;
         Ok(())
     }
 
-    async fn send_http_post(
+    async fn get_collection_report_counts(
         &self,
-        req: DapRequest<DaphneWorkerAuth>,
-    ) -> std::result::Result<DapResponse, DapError> {
-        self.send_http(req, false).await
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> std::result::Result<Option<DapReportCountBreakdown>, DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                DURABLE_LEADER_COL_JOB_QUEUE_GET_REPORT_COUNTS,
+                durable_name_queue(0),
+                (&task_id, &collect_job_id),
+            )
+            .await
+            .map_err(dap_err)
     }
 
-    async fn send_http_put(
+    async fn get_collection_batch_selector(
         &self,
-        req: DapRequest<DaphneWorkerAuth>,
-    ) -> std::result::Result<DapResponse, DapError> {
-        self.send_http(req, true).await
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> std::result::Result<Option<BatchSelector>, DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                DURABLE_LEADER_COL_JOB_QUEUE_GET_BATCH_SELECTOR,
+                durable_name_queue(0),
+                (&task_id, &collect_job_id),
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    async fn delete_collection_job(
+        &self,
+        task_id: &TaskId,
+        collect_job_id: &CollectionJobId,
+    ) -> std::result::Result<DapCollectJob, DapError> {
+        self.durable()
+            .post(
+                BINDING_DAP_LEADER_COL_JOB_QUEUE,
+                DURABLE_LEADER_COL_JOB_QUEUE_DELETE,
+                durable_name_queue(0),
+                (&task_id, &collect_job_id),
+            )
+            .await
+            .map_err(dap_err)
     }
 }
 
@@ -991,11 +1778,13 @@ impl<'srv, 'req> DapHelper<'srv, 'req, DaphneWorkerAuth> for DaphneWorker<'srv>
 where
     'srv: 'req,
 {
+    #[instrument(skip_all, fields(%task_id, agg_job_id = %agg_job_id.to_base64url()))]
     async fn put_helper_state(
         &self,
         task_id: &TaskId,
         agg_job_id: &MetaAggregationJobId,
         helper_state: &DapHelperState,
+        expiration: Option<Time>,
     ) -> std::result::Result<(), DapError> {
         let task_config = self.try_get_task_config(task_id).await?;
         let helper_state_hex = hex::encode(helper_state.get_encoded(&task_config.as_ref().vdaf)?);
@@ -1004,13 +1793,17 @@ where
                 BINDING_DAP_HELPER_STATE_STORE,
                 DURABLE_HELPER_STATE_PUT,
                 durable_helper_state_name(&task_config.as_ref().version, task_id, agg_job_id),
-                helper_state_hex,
+                PutHelperStateRequest {
+                    helper_state_hex,
+                    expiration,
+                },
             )
             .await
             .map_err(dap_err)?;
         Ok(())
     }
 
+    #[instrument(skip_all, fields(%task_id, agg_job_id = %agg_job_id.to_base64url()))]
     async fn get_helper_state(
         &self,
         task_id: &TaskId,
@@ -1038,4 +1831,48 @@ where
             None => Ok(None),
         }
     }
+
+    #[instrument(skip_all, fields(%task_id, agg_job_id = %agg_job_id.to_base64url()))]
+    async fn try_acquire_agg_job_slot(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
+        max_running: u64,
+    ) -> std::result::Result<bool, DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.durable()
+            .post(
+                BINDING_DAP_HELPER_AGG_JOB_SLOTS,
+                DURABLE_HELPER_AGG_JOB_SLOTS_ACQUIRE,
+                DURABLE_NAME_HELPER_AGG_JOB_SLOTS.to_string(),
+                AcquireSlotRequest {
+                    agg_job: durable_helper_state_name(
+                        &task_config.as_ref().version,
+                        task_id,
+                        agg_job_id,
+                    ),
+                    max_running,
+                },
+            )
+            .await
+            .map_err(dap_err)
+    }
+
+    #[instrument(skip_all, fields(%task_id, agg_job_id = %agg_job_id.to_base64url()))]
+    async fn release_agg_job_slot(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &MetaAggregationJobId,
+    ) -> std::result::Result<(), DapError> {
+        let task_config = self.try_get_task_config(task_id).await?;
+        self.durable()
+            .post(
+                BINDING_DAP_HELPER_AGG_JOB_SLOTS,
+                DURABLE_HELPER_AGG_JOB_SLOTS_RELEASE,
+                DURABLE_NAME_HELPER_AGG_JOB_SLOTS.to_string(),
+                durable_helper_state_name(&task_config.as_ref().version, task_id, agg_job_id),
+            )
+            .await
+            .map_err(dap_err)
+    }
 }
@@ -0,0 +1,138 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Dead-letter store for reports rejected during aggregation.
+//!
+//! When `DAP_DEAD_LETTER_TTL_SECS` is set, the reports that the Daphne metrics count as rejected
+//! because they are invalid (see [`DapDeadLetter`]) are captured while handling a request. Before
+//! the response is sent, each is stored in KV as a [`DeadLetter`], which expires after the
+//! configured TTL:
+//!
+//! ```text
+//!     dead_letter/task/<task_id>/<report_id>
+//! ```
+//!
+//! The entries of a task are listed by `GET /internal/dead_letters/task/:task_id` and purged by
+//! `DELETE /internal/dead_letters/task/:task_id`.
+
+use crate::{config::DaphneWorker, int_err, internal_api::DeadLetter};
+use daphne::{messages::TaskId, roles::DapAggregator, DapDeadLetter};
+use futures::future::join_all;
+use tracing::{error, warn};
+use worker::Result;
+
+pub(crate) const KV_KEY_PREFIX_DEAD_LETTER: &str = "dead_letter/task";
+
+/// Maximum number of rejected reports stored per task and request. This bounds the number of KV
+/// writes when a Client is systematically broken; the remaining reports are only counted.
+const MAX_DEAD_LETTERS_PER_REQUEST: usize = 100;
+
+/// KV requires entries to live for at least a minute.
+const MIN_DEAD_LETTER_TTL_SECS: u64 = 60;
+
+impl DeadLetter {
+    pub(crate) fn new(dead_letter: &DapDeadLetter, rejected_at: u64) -> Self {
+        Self {
+            report_id: dead_letter.report_id.to_base64url(),
+            time: dead_letter.time,
+            failure: dead_letter.failure.to_string(),
+            rejected_at,
+        }
+    }
+}
+
+impl DaphneWorker<'_> {
+    /// Store the reports rejected while handling the request, if the dead-letter store is
+    /// enabled. Failures are logged, but otherwise ignored, so that they don't affect the outcome
+    /// of the request.
+    pub(crate) async fn flush_dead_letters(&self) {
+        let ttl = match self.config().dead_letter_ttl_secs {
+            Some(ttl) => ttl.max(MIN_DEAD_LETTER_TTL_SECS),
+            None => return,
+        };
+        let kv_store = match self.kv() {
+            Ok(kv_store) => kv_store,
+            Err(e) => {
+                error!("failed to store dead letters: {e}");
+                return;
+            }
+        };
+
+        let rejected_at = self.get_current_time();
+        for (task_id, dead_letters) in self.state.metrics.daphne.take_dead_letters() {
+            if dead_letters.len() > MAX_DEAD_LETTERS_PER_REQUEST {
+                warn!(
+                    "{} reports of task {task_id} were rejected; storing the first {MAX_DEAD_LETTERS_PER_REQUEST}",
+                    dead_letters.len()
+                );
+            }
+            let results = join_all(dead_letters.iter().take(MAX_DEAD_LETTERS_PER_REQUEST).map(
+                |dead_letter| {
+                    let kv_store = &kv_store;
+                    let key = format!(
+                        "{KV_KEY_PREFIX_DEAD_LETTER}/{}/{}",
+                        task_id.to_hex(),
+                        dead_letter.report_id.to_hex()
+                    );
+                    let entry = DeadLetter::new(dead_letter, rejected_at);
+                    async move {
+                        kv_store
+                            .put(&key, "")?
+                            .metadata(entry)?
+                            .expiration_ttl(ttl)
+                            .execute()
+                            .await
+                    }
+                },
+            ))
+            .await;
+            for e in results.into_iter().filter_map(|res| res.err()) {
+                error!("failed to store dead letter for task {task_id}: {e}");
+            }
+        }
+    }
+
+    /// List the rejected reports of the given task that have not yet expired.
+    pub(crate) async fn internal_list_dead_letters(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<Vec<DeadLetter>> {
+        let mut dead_letters = Vec::new();
+        for key in self.kv_list_dead_letter_keys(task_id).await? {
+            let dead_letter = key
+                .metadata
+                .and_then(|metadata| serde_json::from_value(metadata).ok())
+                .ok_or_else(|| int_err(format!("malformed dead letter {}", key.name)))?;
+            dead_letters.push(dead_letter);
+        }
+        Ok(dead_letters)
+    }
+
+    /// Delete the rejected reports of the given task.
+    pub(crate) async fn internal_purge_dead_letters(&self, task_id: &TaskId) -> Result<()> {
+        let kv_store = self.kv()?;
+        for key in self.kv_list_dead_letter_keys(task_id).await? {
+            kv_store.delete(&key.name).await?;
+        }
+        Ok(())
+    }
+
+    async fn kv_list_dead_letter_keys(&self, task_id: &TaskId) -> Result<Vec<worker::kv::Key>> {
+        let kv_store = self.kv()?;
+        let prefix = format!("{KV_KEY_PREFIX_DEAD_LETTER}/{}/", task_id.to_hex());
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut builder = kv_store.list().prefix(prefix.clone());
+            if let Some(cursor) = cursor {
+                builder = builder.cursor(cursor);
+            }
+            let list = builder.execute().await?;
+            keys.extend(list.keys);
+            if list.list_complete {
+                return Ok(keys);
+            }
+            cursor = list.cursor;
+        }
+    }
+}
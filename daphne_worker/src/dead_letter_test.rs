@@ -0,0 +1,41 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::internal_api::{DeadLetter, DeadLetters};
+use daphne::{
+    messages::{ReportId, TransitionFailure},
+    DapDeadLetter,
+};
+
+#[test]
+fn dead_letter_json() {
+    let dead_letter = DeadLetter::new(
+        &DapDeadLetter {
+            report_id: ReportId([1; 16]),
+            time: 1_700_000_000,
+            failure: TransitionFailure::HpkeDecryptError,
+        },
+        1_700_000_100,
+    );
+    assert_eq!(
+        serde_json::to_value(DeadLetters {
+            dead_letters: vec![dead_letter.clone()],
+        })
+        .unwrap(),
+        serde_json::json!({
+            "dead_letters": [{
+                "report_id": ReportId([1; 16]).to_base64url(),
+                "time": 1_700_000_000,
+                "failure": "hpke_decrypt_error",
+                "rejected_at": 1_700_000_100,
+            }],
+        })
+    );
+
+    // Entries are stored as the metadata of a KV key and read back when listed.
+    let metadata = serde_json::to_value(&dead_letter).unwrap();
+    assert_eq!(
+        serde_json::from_value::<DeadLetter>(metadata).unwrap(),
+        dead_letter
+    );
+}
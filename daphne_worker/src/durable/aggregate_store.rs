@@ -3,35 +3,164 @@
 
 use crate::{
     config::DaphneWorkerConfig,
-    durable::{state_get_or_default, BINDING_DAP_AGGREGATE_STORE},
+    durable::{state_get, state_get_or_default, BINDING_DAP_AGGREGATE_STORE},
     initialize_tracing, int_err,
 };
-use daphne::DapAggregateShare;
+use daphne::{messages::BatchSelector, DapAggregateShare};
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use tracing::info;
 use worker::*;
 
 pub(crate) const DURABLE_AGGREGATE_STORE_GET: &str = "/internal/do/aggregate_store/get";
+pub(crate) const DURABLE_AGGREGATE_STORE_GET_REPORT_COUNT: &str =
+    "/internal/do/aggregate_store/get_report_count";
+pub(crate) const DURABLE_AGGREGATE_STORE_GET_VERSION: &str =
+    "/internal/do/aggregate_store/get_version";
 pub(crate) const DURABLE_AGGREGATE_STORE_MERGE: &str = "/internal/do/aggregate_store/merge";
+pub(crate) const DURABLE_AGGREGATE_STORE_PUT_SPAN: &str = "/internal/do/aggregate_store/put_span";
 pub(crate) const DURABLE_AGGREGATE_STORE_MARK_COLLECTED: &str =
     "/internal/do/aggregate_store/mark_collected";
 pub(crate) const DURABLE_AGGREGATE_STORE_CHECK_COLLECTED: &str =
     "/internal/do/aggregate_store/check_collected";
+pub(crate) const DURABLE_AGGREGATE_STORE_CHECK_COLLECTED_BY: &str =
+    "/internal/do/aggregate_store/check_collected_by";
+pub(crate) const DURABLE_AGGREGATE_STORE_GET_QUERY_COUNT: &str =
+    "/internal/do/aggregate_store/get_query_count";
+pub(crate) const DURABLE_AGGREGATE_STORE_MARK_FORCED: &str =
+    "/internal/do/aggregate_store/mark_forced";
+pub(crate) const DURABLE_AGGREGATE_STORE_CHECK_FORCED: &str =
+    "/internal/do/aggregate_store/check_forced";
+pub(crate) const DURABLE_AGGREGATE_STORE_GET_COLLECTED_AGG_SHARE: &str =
+    "/internal/do/aggregate_store/get_collected_agg_share";
+pub(crate) const DURABLE_AGGREGATE_STORE_PUT_COLLECTED_AGG_SHARE: &str =
+    "/internal/do/aggregate_store/put_collected_agg_share";
+
+/// Version of the aggregate share stored by an [`AggregateStore`]. The version is incremented by
+/// each merge, and the checksum chains the previous checksum with the merged aggregate share.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct AggregateStoreVersion {
+    pub(crate) version: u64,
+    #[serde(with = "hex")]
+    pub(crate) checksum: [u8; 32],
+}
+
+impl AggregateStoreVersion {
+    /// Compute the version that results from merging `agg_share_delta` into this version.
+    fn next(&self, agg_share_delta: &DapAggregateShare) -> Result<Self> {
+        let mut hasher = Context::new(&SHA256);
+        hasher.update(&self.checksum);
+        hasher.update(&self.version.to_be_bytes());
+        hasher.update(&serde_json::to_vec(agg_share_delta)?);
+        Ok(Self {
+            version: self.version + 1,
+            checksum: hasher.finish().as_ref().try_into().map_err(int_err)?,
+        })
+    }
+}
+
+/// Request to merge an aggregate share into an [`AggregateStore`]. The merge is only applied if
+/// the stored version is equal to `expected`.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct AggregateStoreMergeReq {
+    pub(crate) agg_share_delta: DapAggregateShare,
+    pub(crate) expected: AggregateStoreVersion,
+}
+
+/// Result of a merge into an [`AggregateStore`].
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AggregateStoreMergeResp {
+    /// The aggregate share was merged. The new version and the number of reports aggregated into
+    /// the bucket are returned.
+    Merged {
+        version: AggregateStoreVersion,
+        report_count: u64,
+    },
+
+    /// The aggregate share was already merged by a previous attempt of the same request.
+    AlreadyMerged,
+
+    /// The stored version differs from the expected version, i.e., the bucket was updated
+    /// concurrently. The current version is returned.
+    Conflict(AggregateStoreVersion),
+}
+
+/// Request to get or set the aggregate share produced for a Collector's query of a batch. The
+/// aggregate share is only present in requests to set it.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct AggregateStoreCollectedReq {
+    pub(crate) batch_sel: BatchSelector,
+    pub(crate) collector_id: Option<String>,
+    pub(crate) agg_share: Option<DapAggregateShare>,
+}
+
+impl AggregateStoreCollectedReq {
+    /// Storage key of the aggregate share produced for the query.
+    fn key(&self) -> Result<String> {
+        let mut hasher = Context::new(&SHA256);
+        hasher.update(&serde_json::to_vec(&(&self.batch_sel, &self.collector_id))?);
+        Ok(format!(
+            "collected_agg_share/{}",
+            hex::encode(hasher.finish().as_ref())
+        ))
+    }
+}
 
 /// Durable Object (DO) for storing aggregate shares for a bucket of reports.
 ///
 /// This object defines the following API endpoints:
 ///
 /// - `DURABLE_AGGREGATE_STORE_GET`: Return the current value of the aggregate share.
-/// - `DURABLE_AGGREGATE_STORE_MERGE`: Update the aggregate share.
-/// - `DURABLE_AGGREGATE_STORE_MARK_COLLECTED`: Mark the bucket as having been collected.
+/// - `DURABLE_AGGREGATE_STORE_GET_REPORT_COUNT`: Return the number of reports aggregated into the
+///   aggregate share.
+/// - `DURABLE_AGGREGATE_STORE_GET_VERSION`: Return the current version of the aggregate share.
+/// - `DURABLE_AGGREGATE_STORE_MERGE`: Update the aggregate share if its version matches the
+///   expected version.
+/// - `DURABLE_AGGREGATE_STORE_PUT_SPAN`: Set the aggregate share of a compacted span of buckets,
+///   unless it is already set.
+/// - `DURABLE_AGGREGATE_STORE_MARK_COLLECTED`: Mark the bucket as having been collected by the
+///   given Collector.
 /// - `DURABLE_AGGREGATE_STORE_CHECK_COLLECTED`: Return a boolean indicating if the bucket has been
-///   collected.
+///   collected by any Collector.
+/// - `DURABLE_AGGREGATE_STORE_CHECK_COLLECTED_BY`: Return a boolean indicating if the bucket has
+///   been collected by the given Collector.
+/// - `DURABLE_AGGREGATE_STORE_GET_QUERY_COUNT`: Return the number of times the bucket has been
+///   collected by the given Collector.
+/// - `DURABLE_AGGREGATE_STORE_MARK_FORCED`: Mark the bucket as collectable regardless of the
+///   task's minimum batch size.
+/// - `DURABLE_AGGREGATE_STORE_CHECK_FORCED`: Return a boolean indicating if the bucket has been
+///   marked as forced.
+/// - `DURABLE_AGGREGATE_STORE_GET_COLLECTED_AGG_SHARE`: Return the aggregate share produced for a
+///   Collector's query of a batch that starts with this bucket, if any.
+/// - `DURABLE_AGGREGATE_STORE_PUT_COLLECTED_AGG_SHARE`: Set the aggregate share produced for a
+///   Collector's query of a batch that starts with this bucket, unless it is already set.
 ///
 /// The schema for the data stored by this DO is as follows:
 ///
 /// ```text
-/// [Aggregate share] agg_share -> DapAggregateShare
-/// [Collected flag]  collected -> bool
+/// [Aggregate share]   agg_share -> DapAggregateShare
+/// [Aggregate version] agg_share_version -> AggregateStoreVersion
+/// [Collected flag]    collected -> bool
+/// [Collected by]      collected_by -> Vec<Option<String>>
+/// [Query counts]      query_counts -> Vec<(Option<String>, u64)>
+/// [Forced flag]       forced -> bool
+/// [Collected share]   collected_agg_share/<digest> -> DapAggregateShare
 /// ```
+///
+/// An instance either holds the aggregate share of a bucket, or the compacted aggregate share of a
+/// span of buckets (see [`DapAggregateSpan`](daphne::storage::DapAggregateSpan)), which is written
+/// once and never merged into.
+///
+/// The collected-by list records each Collector that has collected the bucket (`None` for the
+/// task's primary Collector). Buckets that were collected before the list was introduced have the
+/// collected flag set and an empty list; these were collected by the primary Collector. Likewise,
+/// the query counts record how many times each Collector has collected the bucket; Collectors that
+/// collected the bucket before the counts were introduced have collected it once.
+///
+/// The collected shares are the aggregate shares, noise included, that were produced for queries
+/// of batches whose first bucket is this one. The digest is the SHA-256 hash of the batch selector
+/// and the Collector's ID.
 #[durable_object]
 pub struct AggregateStore {
     #[allow(dead_code)]
@@ -62,22 +191,65 @@ impl DurableObject for AggregateStore {
         match (req.path().as_ref(), req.method()) {
             // Merge an aggregate share into the stored aggregate.
             //
-            // Input: `agg_share_dellta: DapAggregateShare`
+            // Input: `AggregateStoreMergeReq`
+            // Output: `AggregateStoreMergeResp`
             (DURABLE_AGGREGATE_STORE_MERGE, Method::Post) => {
-                let agg_share_delta = req.json().await?;
+                let merge_req: AggregateStoreMergeReq = req.json().await?;
 
                 // To keep this pair of get and put operations atomic, there should be no await
                 // points between them. See the note below `transaction()` on
                 // https://developers.cloudflare.com/workers/runtime-apis/durable-objects/#transactional-storage-api.
                 // See issue #109.
+                let current: AggregateStoreVersion =
+                    state_get_or_default(&self.state, "agg_share_version").await?;
+                if current != merge_req.expected {
+                    // If the current version is the one that would have resulted from this merge,
+                    // then this request is a retry of a merge that was already applied.
+                    if current == merge_req.expected.next(&merge_req.agg_share_delta)? {
+                        return Response::from_json(&AggregateStoreMergeResp::AlreadyMerged);
+                    }
+                    return Response::from_json(&AggregateStoreMergeResp::Conflict(current));
+                }
+
+                let next = current.next(&merge_req.agg_share_delta)?;
                 let mut agg_share: DapAggregateShare =
                     state_get_or_default(&self.state, "agg_share").await?;
-                agg_share.merge(agg_share_delta).map_err(int_err)?;
+                agg_share
+                    .merge(merge_req.agg_share_delta)
+                    .map_err(int_err)?;
+                let report_count = agg_share.report_count();
                 self.state.storage().put("agg_share", agg_share).await?;
+                self.state.storage().put("agg_share_version", &next).await?;
 
+                Response::from_json(&AggregateStoreMergeResp::Merged {
+                    version: next,
+                    report_count,
+                })
+            }
+
+            // Set the aggregate share of a compacted span. The aggregate share of a span is
+            // computed from buckets that no longer change, so if it is already set, then it is
+            // left as is.
+            //
+            // Input: `DapAggregateShare`
+            (DURABLE_AGGREGATE_STORE_PUT_SPAN, Method::Post) => {
+                let agg_share: DapAggregateShare = req.json().await?;
+                let stored: Option<DapAggregateShare> = state_get(&self.state, "agg_share").await?;
+                if stored.is_none() {
+                    self.state.storage().put("agg_share", agg_share).await?;
+                }
                 Response::from_json(&())
             }
 
+            // Get the current version of the aggregate share.
+            //
+            // Output: `AggregateStoreVersion`
+            (DURABLE_AGGREGATE_STORE_GET_VERSION, Method::Get) => {
+                let version: AggregateStoreVersion =
+                    state_get_or_default(&self.state, "agg_share_version").await?;
+                Response::from_json(&version)
+            }
+
             // Get the current aggregate share.
             //
             // Output: `DapAggregateShare`
@@ -87,9 +259,43 @@ impl DurableObject for AggregateStore {
                 Response::from_json(&agg_share)
             }
 
-            // Mark this bucket as collected.
+            // Get the number of reports aggregated into the current aggregate share.
+            //
+            // Output: `u64`
+            (DURABLE_AGGREGATE_STORE_GET_REPORT_COUNT, Method::Get) => {
+                let agg_share: DapAggregateShare =
+                    state_get_or_default(&self.state, "agg_share").await?;
+                Response::from_json(&agg_share.report_count())
+            }
+
+            // Mark this bucket as collected by the given Collector.
+            //
+            // Input: `collector_id: Option<String>`
             (DURABLE_AGGREGATE_STORE_MARK_COLLECTED, Method::Post) => {
+                let collector_id: Option<String> = req.json().await?;
+                let mut collected_by = self.collected_by().await?;
+                let mut query_counts = self.query_counts().await?;
+                match query_counts.iter_mut().find(|(id, _)| id == &collector_id) {
+                    Some((_, query_count)) => *query_count += 1,
+                    None => query_counts.push((collector_id.clone(), 1)),
+                }
+                if !collected_by.contains(&collector_id) {
+                    info!(
+                        "bucket {} collected by collector {}",
+                        self.state.id().to_string(),
+                        collector_id.as_deref().unwrap_or("(primary)")
+                    );
+                    collected_by.push(collector_id);
+                }
                 self.state.storage().put("collected", true).await?;
+                self.state
+                    .storage()
+                    .put("collected_by", collected_by)
+                    .await?;
+                self.state
+                    .storage()
+                    .put("query_counts", query_counts)
+                    .await?;
                 Response::from_json(&())
             }
 
@@ -101,6 +307,76 @@ impl DurableObject for AggregateStore {
                 Response::from_json(&collected)
             }
 
+            // Check whether this bucket has been collected by the given Collector.
+            //
+            // Input: `collector_id: Option<String>`
+            // Output: `bool`
+            (DURABLE_AGGREGATE_STORE_CHECK_COLLECTED_BY, Method::Post) => {
+                let collector_id: Option<String> = req.json().await?;
+                let collected_by = self.collected_by().await?;
+                Response::from_json(&collected_by.contains(&collector_id))
+            }
+
+            // Get the number of times this bucket has been collected by the given Collector.
+            //
+            // Input: `collector_id: Option<String>`
+            // Output: `u64`
+            (DURABLE_AGGREGATE_STORE_GET_QUERY_COUNT, Method::Post) => {
+                let collector_id: Option<String> = req.json().await?;
+                let query_count = self
+                    .query_counts()
+                    .await?
+                    .into_iter()
+                    .find(|(id, _)| id == &collector_id)
+                    .map_or(0, |(_, query_count)| query_count);
+                Response::from_json(&query_count)
+            }
+
+            // Mark this bucket as forced, i.e., collectable regardless of the task's minimum batch
+            // size.
+            (DURABLE_AGGREGATE_STORE_MARK_FORCED, Method::Post) => {
+                info!("bucket {} forced", self.state.id().to_string());
+                self.state.storage().put("forced", true).await?;
+                Response::from_json(&())
+            }
+
+            // Get the value of the flag indicating whether this bucket has been forced.
+            //
+            // Output: `bool`
+            (DURABLE_AGGREGATE_STORE_CHECK_FORCED, Method::Get) => {
+                let forced: bool = state_get_or_default(&self.state, "forced").await?;
+                Response::from_json(&forced)
+            }
+
+            // Get the aggregate share produced for a Collector's query of a batch.
+            //
+            // Input: `AggregateStoreCollectedReq`
+            // Output: `Option<DapAggregateShare>`
+            (DURABLE_AGGREGATE_STORE_GET_COLLECTED_AGG_SHARE, Method::Post) => {
+                let collected_req: AggregateStoreCollectedReq = req.json().await?;
+                let agg_share: Option<DapAggregateShare> =
+                    state_get(&self.state, &collected_req.key()?).await?;
+                Response::from_json(&agg_share)
+            }
+
+            // Set the aggregate share produced for a Collector's query of a batch. Repeated
+            // queries must be answered with the same aggregate share, so if it is already set,
+            // then it is left as is.
+            //
+            // Input: `AggregateStoreCollectedReq`
+            (DURABLE_AGGREGATE_STORE_PUT_COLLECTED_AGG_SHARE, Method::Post) => {
+                let collected_req: AggregateStoreCollectedReq = req.json().await?;
+                let key = collected_req.key()?;
+                let agg_share = collected_req
+                    .agg_share
+                    .ok_or_else(|| int_err("AggregateStore: aggregate share missing"))?;
+                let stored: Option<DapAggregateShare> = state_get(&self.state, &key).await?;
+                if stored.is_none() {
+                    self.state.storage().put(&key, agg_share).await?;
+                }
+                Response::from_json(&())
+            }
+
             _ => Err(int_err(format!(
                 "AggregatesStore: unexpected request: method={:?}; path={:?}",
                 req.method(),
@@ -109,3 +385,32 @@ impl DurableObject for AggregateStore {
         }
     }
 }
+
+impl AggregateStore {
+    /// Get the list of Collectors that have collected this bucket.
+    async fn collected_by(&self) -> Result<Vec<Option<String>>> {
+        let collected_by: Vec<Option<String>> =
+            state_get_or_default(&self.state, "collected_by").await?;
+        if collected_by.is_empty() && state_get_or_default(&self.state, "collected").await? {
+            // The bucket was collected before Collectors were recorded.
+            return Ok(vec![None]);
+        }
+        Ok(collected_by)
+    }
+
+    /// Get the number of times each Collector has collected this bucket.
+    async fn query_counts(&self) -> Result<Vec<(Option<String>, u64)>> {
+        let query_counts: Vec<(Option<String>, u64)> =
+            state_get_or_default(&self.state, "query_counts").await?;
+        if query_counts.is_empty() {
+            // The bucket was collected, if at all, before query counts were recorded.
+            return Ok(self
+                .collected_by()
+                .await?
+                .into_iter()
+                .map(|collector_id| (collector_id, 1))
+                .collect());
+        }
+        Ok(query_counts)
+    }
+}
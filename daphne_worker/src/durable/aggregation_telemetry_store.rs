@@ -0,0 +1,85 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    config::DaphneWorkerConfig,
+    durable::{state_get_or_default, BINDING_DAP_AGGREGATION_TELEMETRY_STORE},
+    initialize_tracing, int_err,
+    internal_api::TaskAggregationTelemetry,
+};
+use worker::*;
+
+pub(crate) const DURABLE_AGGREGATION_TELEMETRY_STORE_ADD: &str =
+    "/internal/do/aggregation_telemetry_store/add";
+pub(crate) const DURABLE_AGGREGATION_TELEMETRY_STORE_GET: &str =
+    "/internal/do/aggregation_telemetry_store/get";
+
+/// Durable Object (DO) for counting the reports of a task, broken down by outcome.
+///
+/// This object defines the following API endpoints:
+///
+/// - `DURABLE_AGGREGATION_TELEMETRY_STORE_ADD`: Add the given counts to the stored counts.
+/// - `DURABLE_AGGREGATION_TELEMETRY_STORE_GET`: Return the stored counts.
+///
+/// The schema for the data stored by this DO is as follows:
+///
+/// ```text
+/// [Telemetry] telemetry -> TaskAggregationTelemetry
+/// ```
+#[durable_object]
+pub struct AggregationTelemetryStore {
+    #[allow(dead_code)]
+    state: State,
+    env: Env,
+    config: DaphneWorkerConfig,
+    touched: bool,
+}
+
+#[durable_object]
+impl DurableObject for AggregationTelemetryStore {
+    fn new(state: State, env: Env) -> Self {
+        initialize_tracing(&env);
+        let config =
+            DaphneWorkerConfig::from_worker_env(&env).expect("failed to load configuration");
+        Self {
+            state,
+            env,
+            config,
+            touched: false,
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let id_hex = self.state.id().to_string();
+        ensure_garbage_collected!(req, self, id_hex, BINDING_DAP_AGGREGATION_TELEMETRY_STORE);
+
+        match (req.path().as_ref(), req.method()) {
+            // Add to the stored counts.
+            //
+            // Input: `TaskAggregationTelemetry`
+            (DURABLE_AGGREGATION_TELEMETRY_STORE_ADD, Method::Post) => {
+                let delta: TaskAggregationTelemetry = req.json().await?;
+                let mut telemetry: TaskAggregationTelemetry =
+                    state_get_or_default(&self.state, "telemetry").await?;
+                telemetry.merge(&delta);
+                self.state.storage().put("telemetry", telemetry).await?;
+                Response::from_json(&())
+            }
+
+            // Get the stored counts.
+            //
+            // Output: `TaskAggregationTelemetry`
+            (DURABLE_AGGREGATION_TELEMETRY_STORE_GET, Method::Get) => {
+                let telemetry: TaskAggregationTelemetry =
+                    state_get_or_default(&self.state, "telemetry").await?;
+                Response::from_json(&telemetry)
+            }
+
+            _ => Err(int_err(format!(
+                "AggregationTelemetryStore: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+}
@@ -6,12 +6,27 @@ use crate::{
     durable::{DurableConnector, DurableOrdered, DurableReference},
     initialize_tracing, int_err,
 };
+use std::collections::{BTreeMap, HashSet};
 use tracing::{error, trace};
 use worker::*;
 
 pub(crate) const DURABLE_GARBAGE_COLLECTOR_PUT: &str = "/internal/do/garbage_collector/put";
+pub(crate) const DURABLE_GARBAGE_COLLECTOR_DELETE_TASK: &str =
+    "/internal/do/garbage_collector/delete_task";
 
 /// Durable Object (DO) for keeping track of all persistent DO storage.
+///
+/// In test environments, there is a single instance, named "garbage_collector", with which every
+/// DO instance registers itself. If task garbage collection is configured, then there is also an
+/// instance per task, named after the task (see `durable_name_task()`), with which the Worker
+/// registers each DO instance that belongs to the task.
+///
+/// The following API endpoints are defined:
+///
+/// - `DURABLE_GARBAGE_COLLECTOR_PUT`: Schedule a DO instance for deletion.
+/// - `DURABLE_DELETE_ALL`: Delete all scheduled DO instances.
+/// - `DURABLE_GARBAGE_COLLECTOR_DELETE_TASK`: Like `DURABLE_DELETE_ALL`, except that the number
+///   of distinct instances deleted is returned for each binding.
 #[durable_object]
 pub struct GarbageCollector {
     #[allow(dead_code)]
@@ -39,7 +54,9 @@ impl DurableObject for GarbageCollector {
                     | durable::BINDING_DAP_LEADER_AGG_JOB_QUEUE
                     | durable::BINDING_DAP_LEADER_BATCH_QUEUE
                     | durable::BINDING_DAP_LEADER_COL_JOB_QUEUE
-                    | durable::BINDING_DAP_HELPER_STATE_STORE => (),
+                    | durable::BINDING_DAP_HELPER_STATE_STORE
+                    | durable::BINDING_DAP_TASK_USAGE_STORE
+                    | durable::BINDING_DAP_AGGREGATION_TELEMETRY_STORE => (),
                     s => {
                         let message = format!("GarbageCollector: unrecognized binding: {s}");
                         error!("{}", message);
@@ -91,6 +108,33 @@ impl DurableObject for GarbageCollector {
                 Response::from_json(&())
             }
 
+            // Delete all DO instances registered for a task.
+            //
+            // Output: `BTreeMap<String, u64>` (number of instances deleted per binding)
+            (DURABLE_GARBAGE_COLLECTOR_DELETE_TASK, Method::Post) => {
+                let queued: Vec<DurableOrdered<DurableReference>> =
+                    DurableOrdered::get_all(&self.state, "object").await?;
+                let mut deleted = HashSet::new();
+                let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+                for durable_ref in queued.iter().map(|queued| queued.as_ref()) {
+                    if !deleted.insert((&durable_ref.binding, &durable_ref.id_hex)) {
+                        continue;
+                    }
+                    durable
+                        .post_by_id_hex::<_, ()>(
+                            &durable_ref.binding,
+                            durable::DURABLE_DELETE_ALL,
+                            durable_ref.id_hex.clone(),
+                            &(),
+                        )
+                        .await?;
+                    *counts.entry(durable_ref.binding.clone()).or_default() += 1;
+                }
+
+                self.state.storage().delete_all().await?;
+                Response::from_json(&counts)
+            }
+
             _ => {
                 let message = format!(
                     "unexpected request: method={:?}; path={:?}",
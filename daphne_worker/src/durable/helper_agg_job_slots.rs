@@ -0,0 +1,125 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    config::DaphneWorkerConfig, durable::state_get_or_default, initialize_tracing, int_err, now,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use worker::*;
+
+pub(crate) const DURABLE_HELPER_AGG_JOB_SLOTS_ACQUIRE: &str =
+    "/internal/do/helper_agg_job_slots/acquire";
+pub(crate) const DURABLE_HELPER_AGG_JOB_SLOTS_RELEASE: &str =
+    "/internal/do/helper_agg_job_slots/release";
+pub(crate) const DURABLE_HELPER_AGG_JOB_SLOTS_COUNT: &str =
+    "/internal/do/helper_agg_job_slots/count";
+
+/// Name of the only instance of `HelperAggregationJobSlots`.
+pub(crate) const DURABLE_NAME_HELPER_AGG_JOB_SLOTS: &str = "helper_agg_job_slots";
+
+/// Request to acquire a slot for an aggregation job.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct AcquireSlotRequest {
+    /// Name of the aggregation job's instance of `HelperStateStore`.
+    pub(crate) agg_job: String,
+
+    /// Maximum number of slots that may be taken.
+    pub(crate) max_running: u64,
+}
+
+/// Durable Object (DO) for limiting the number of aggregation jobs run by the Helper at once.
+///
+/// This object implements the following API endpoints:
+///
+/// - `DURABLE_HELPER_AGG_JOB_SLOTS_ACQUIRE`: Take a slot for an aggregation job, unless all of
+///   the slots are taken.
+/// - `DURABLE_HELPER_AGG_JOB_SLOTS_RELEASE`: Release the slot taken by an aggregation job.
+/// - `DURABLE_HELPER_AGG_JOB_SLOTS_COUNT`: Count the slots that are taken.
+///
+/// The schema for data stored in instances of this DO is as follows:
+///
+/// ```text
+/// [Slots] slots -> HashMap<String, u64>
+/// ```
+///
+/// Each slot maps the name of the aggregation job's instance of `HelperStateStore` to the time at
+/// which the slot was taken. Once the instance of `HelperStateStore` has been deleted, the
+/// aggregation job can no longer be continued, so its slot is released.
+#[durable_object]
+pub struct HelperAggregationJobSlots {
+    state: State,
+    config: DaphneWorkerConfig,
+}
+
+#[durable_object]
+impl DurableObject for HelperAggregationJobSlots {
+    fn new(state: State, env: Env) -> Self {
+        initialize_tracing(&env);
+        let config =
+            DaphneWorkerConfig::from_worker_env(&env).expect("failed to load configuration");
+        Self { state, config }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        match (req.path().as_ref(), req.method()) {
+            // Take a slot for an aggregation job. An aggregation job that already holds a slot
+            // keeps it.
+            //
+            // Input: `AcquireSlotRequest`
+            // Output: `bool` (`false` if all of the slots are taken)
+            (DURABLE_HELPER_AGG_JOB_SLOTS_ACQUIRE, Method::Post) => {
+                let acquire_req: AcquireSlotRequest = req.json().await?;
+                let mut slots = self.get_live_slots().await?;
+                if !slots.contains_key(&acquire_req.agg_job) {
+                    if u64::try_from(slots.len()).unwrap() >= acquire_req.max_running {
+                        return Response::from_json(&false);
+                    }
+                    slots.insert(acquire_req.agg_job, now());
+                }
+                self.state.storage().put("slots", slots).await?;
+                Response::from_json(&true)
+            }
+
+            // Release the slot taken by an aggregation job, if any.
+            //
+            // Input: `agg_job: String`
+            (DURABLE_HELPER_AGG_JOB_SLOTS_RELEASE, Method::Post) => {
+                let agg_job: String = req.json().await?;
+                let mut slots = self.get_live_slots().await?;
+                slots.remove(&agg_job);
+                self.state.storage().put("slots", slots).await?;
+                Response::from_json(&())
+            }
+
+            // Count the slots that are taken.
+            //
+            // Output: `u64`
+            (DURABLE_HELPER_AGG_JOB_SLOTS_COUNT, Method::Get) => {
+                let slots = self.get_live_slots().await?;
+                Response::from_json(&u64::try_from(slots.len()).unwrap())
+            }
+
+            _ => Err(int_err(format!(
+                "HelperAggregationJobSlots: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+}
+
+impl HelperAggregationJobSlots {
+    /// Get the slots whose aggregation job's state has not yet been deleted.
+    async fn get_live_slots(&self) -> Result<HashMap<String, u64>> {
+        let mut slots: HashMap<String, u64> = state_get_or_default(&self.state, "slots").await?;
+        let lifetime = self
+            .config
+            .helper_state_store_garbage_collect_after_secs
+            .expect("Daphne-Worker not configured as helper")
+            .as_secs();
+        let now = now();
+        slots.retain(|_, taken_at| taken_at.saturating_add(lifetime) > now);
+        Ok(slots)
+    }
+}
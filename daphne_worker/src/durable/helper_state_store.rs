@@ -1,8 +1,13 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use crate::{config::DaphneWorkerConfig, durable::state_get, initialize_tracing, int_err};
-use daphne::{messages::TaskId, DapVersion, MetaAggregationJobId};
+use crate::{config::DaphneWorkerConfig, durable::state_get, initialize_tracing, int_err, now};
+use daphne::{
+    messages::{TaskId, Time},
+    DapVersion, MetaAggregationJobId,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use tracing::{trace, warn};
 use worker::*;
 
@@ -22,14 +27,26 @@ pub(crate) fn durable_helper_state_name(
 pub(crate) const DURABLE_HELPER_STATE_PUT: &str = "/internal/do/helper_state/put";
 pub(crate) const DURABLE_HELPER_STATE_GET: &str = "/internal/do/helper_state/get";
 
+/// Request to store the Helper's state.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct PutHelperStateRequest {
+    /// Hex-encoded state.
+    pub(crate) helper_state_hex: String,
+
+    /// Time after which the state is no longer returned, if any.
+    pub(crate) expiration: Option<Time>,
+}
+
 /// Durable Object (DO) for storing the Helper's state for a given aggregation job.
 ///
 /// This object implements the following API endpoints:
 ///
 /// - `DURABLE_HELPER_STATE_PUT`: Stores Helper's hex-encoded state.
-/// - `DURABLE_HELPER_STATE_GET`: Drains the Helper's hex-encoded state.
+/// - `DURABLE_HELPER_STATE_GET`: Drains the Helper's hex-encoded state, unless it has expired.
 ///
-/// The state blob is stored in `helper_state`.
+/// The state blob is stored in `helper_state` and its expiration time, if any, in `expiration`.
+/// The instance is deleted by an alarm once the state expires or once the instance is garbage
+/// collected, whichever comes first.
 #[durable_object]
 pub struct HelperStateStore {
     state: State,
@@ -52,41 +69,72 @@ impl DurableObject for HelperStateStore {
 
     async fn fetch(&mut self, mut req: Request) -> Result<Response> {
         // Ensure this DO instance is garbage collected eventually.
-        ensure_alarmed!(
-            self,
-            self.config
-                .helper_state_store_garbage_collect_after_secs
-                .expect("Daphne-Worker not configured as helper")
-        );
+        let garbage_collect_after = self
+            .config
+            .helper_state_store_garbage_collect_after_secs
+            .expect("Daphne-Worker not configured as helper");
+        ensure_alarmed!(self, garbage_collect_after);
 
         match (req.path().as_ref(), req.method()) {
             // Store the Helper's state.
             //
-            // Input: `helper_state_hex: String` (hex-encoded state)
+            // Input: `PutHelperStateRequest`
             (DURABLE_HELPER_STATE_PUT, Method::Post) => {
                 // The state is handled as an opaque hex string.
-                let mut helper_state_hex: Option<String> =
+                let helper_state_hex: Option<String> =
                     state_get(&self.state, "helper_state").await?;
                 if helper_state_hex.is_some() {
                     // TODO spec: Handle this as an abort rather than an internal error.
                     return Err(int_err("tried to overwrite helper state"));
                 }
 
-                helper_state_hex = req.json().await?;
+                let put_req: PutHelperStateRequest = req.json().await?;
                 self.state
                     .storage()
-                    .put("helper_state", helper_state_hex)
+                    .put("helper_state", put_req.helper_state_hex)
                     .await?;
+                if let Some(expiration) = put_req.expiration {
+                    self.state.storage().put("expiration", expiration).await?;
+
+                    // Delete the state once it expires, unless the instance is garbage collected
+                    // first. The alarm may fire late, so the expiration is also checked when the
+                    // state is drained.
+                    let expire_after = Duration::from_secs(expiration.saturating_sub(now()));
+                    if expire_after < garbage_collect_after {
+                        if let Err(e) = self.state.storage().set_alarm(expire_after).await {
+                            if matches!(
+                                self.config.deployment,
+                                crate::config::DaphneWorkerDeployment::Dev
+                            ) {
+                                // The experimental-local dev environment doesn't have working
+                                // alarms yet. The expiration is still checked on drain.
+                                warn!("ignoring set_alarm() failure in a dev environment until --experimental-local implements it: {e}");
+                            } else {
+                                return Err(e);
+                            }
+                        }
+                    }
+                }
                 Response::from_json(&())
             }
 
             // Drain the Helper's state.
             //
-            // Output: `String` (hex-encoded state)
+            // Output: `Option<String>` (hex-encoded state)
             (DURABLE_HELPER_STATE_GET, Method::Post) => {
                 let helper_state: Option<String> = state_get(&self.state, "helper_state").await?;
-                if helper_state.is_some() {
-                    self.state.storage().delete("helper_state").await?;
+                if helper_state.is_none() {
+                    return Response::from_json(&helper_state);
+                }
+
+                let expiration: Option<Time> = state_get(&self.state, "expiration").await?;
+                self.state.storage().delete_all().await?;
+                if expiration.is_some_and(|expiration| now() >= expiration) {
+                    trace!(
+                        "HelperStateStore: state of instance {} expired",
+                        self.state.id().to_string()
+                    );
+                    return Response::from_json(&None::<String>);
                 }
                 Response::from_json(&helper_state)
             }
@@ -6,6 +6,7 @@ use crate::{
     durable::{DurableOrdered, BINDING_DAP_LEADER_AGG_JOB_QUEUE},
     initialize_tracing, int_err,
 };
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 use worker::*;
 
@@ -13,14 +14,28 @@ pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_PUT: &str = "/internal/do/agg_job_
 pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_GET: &str = "/internal/do/agg_job_queue/get";
 pub(crate) const DURABLE_LEADER_AGG_JOB_QUEUE_FINISH: &str = "/internal/do/agg_job_queue/finish";
 
+/// Input of `DURABLE_LEADER_AGG_JOB_QUEUE_GET`.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct LeaderAggJobQueueGetReq {
+    /// Maximum number of jobs to fetch.
+    pub(crate) max_agg_jobs: usize,
+
+    /// If set, then only jobs after the job with this ordinal are fetched.
+    pub(crate) after: Option<String>,
+}
+
+/// Output of `DURABLE_LEADER_AGG_JOB_QUEUE_GET`: the ordinal and the name of the `ReportsPending`
+/// instance of each job, in queue order.
+pub(crate) type LeaderAggJobQueueGetResp = Vec<(String, String)>;
+
 /// Durable Object (DO) representing an aggregation job queue.
 ///
 /// This object defines the following API endpoints:
 ///
 /// - `DURABLE_LEADER_AGG_JOB_QUEUE_PUT`: Adds a job to the queue. This is called by an instance of
 ///   `ReportsPending`.
-/// - `DURABLE_LEADER_AGG_JOB_QUEUE_PUT`: Fetches the desired number of jobs from the front of the
-///    queue.
+/// - `DURABLE_LEADER_AGG_JOB_QUEUE_GET`: Fetches the desired number of jobs from the front of the
+///    queue, or from the position following a given job.
 /// - `DURABLE_LEADER_AGG_JOB_QUEUE_FINISH`: Removes the indicated job from the queue.
 ///
 /// The schemea for data stored in instances of this DO is as follows:
@@ -74,19 +89,33 @@ impl DurableObject for LeaderAggregationJobQueue {
                 Response::from_json(&())
             }
 
-            // Fetch the aggregation jobs at the fron tf the queue.
+            // Fetch the aggregation jobs at the fron tf the queue, or those following the
+            // indicated job.
             //
-            // Input: `max_agg_jobs: usize`,
-            // Output: `Vec<String>` (the names of the `ReportsPending` instances from which to
-            // drain reports)
+            // Input: `get_req: LeaderAggJobQueueGetReq`,
+            // Output: `LeaderAggJobQueueGetResp` (the names of the `ReportsPending` instances from
+            // which to drain reports, along with their ordinals)
             (DURABLE_LEADER_AGG_JOB_QUEUE_GET, Method::Post) => {
-                let max_agg_jobs: usize = req.json().await?;
-                let res: Vec<String> =
-                    DurableOrdered::get_front(&self.state, "agg_job", max_agg_jobs)
+                let get_req: LeaderAggJobQueueGetReq = req.json().await?;
+                let agg_jobs: Vec<DurableOrdered<String>> = match get_req.after {
+                    Some(ref after) => {
+                        DurableOrdered::get_front_after(
+                            &self.state,
+                            "agg_job",
+                            get_req.max_agg_jobs,
+                            after,
+                        )
                         .await?
-                        .into_iter()
-                        .map(|agg_job| agg_job.into_item())
-                        .collect();
+                    }
+                    None => {
+                        DurableOrdered::get_front(&self.state, "agg_job", get_req.max_agg_jobs)
+                            .await?
+                    }
+                };
+                let res: LeaderAggJobQueueGetResp = agg_jobs
+                    .into_iter()
+                    .map(|agg_job| (agg_job.ordinal().to_string(), agg_job.into_item()))
+                    .collect();
 
                 debug!("agg job queue: {:?}", res);
                 Response::from_json(&res)
@@ -6,7 +6,10 @@ use crate::{
     durable::{state_get, DurableOrdered, BINDING_DAP_LEADER_BATCH_QUEUE},
     initialize_tracing, int_err,
 };
-use daphne::messages::BatchId;
+use daphne::{
+    messages::{BatchId, Time},
+    DapBatchLifetimeConfig, DapStaleBatchPolicy,
+};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
@@ -16,6 +19,7 @@ pub(crate) const DURABLE_LEADER_BATCH_QUEUE_ASSIGN: &str = "/internal/do/leader_
 pub(crate) const DURABLE_LEADER_BATCH_QUEUE_CURRENT: &str =
     "/internal/do/leader_batch_queue/current";
 pub(crate) const DURABLE_LEADER_BATCH_QUEUE_REMOVE: &str = "/internal/do/leader_batch_queue/remove";
+pub(crate) const DURABLE_LEADER_BATCH_QUEUE_CLOSE: &str = "/internal/do/leader_batch_queue/close";
 
 const CURRENT: &str = "current";
 const PENDING_PREFIX: &str = "pending";
@@ -24,6 +28,10 @@ const PENDING_PREFIX: &str = "pending";
 pub(crate) struct BatchCount {
     pub(crate) batch_id: BatchId,
     pub(crate) report_count: usize,
+
+    /// Time at which the first report was assigned to the batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<Time>,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -40,6 +48,7 @@ pub(crate) enum LeaderBatchQueueResult {
 /// - `DURABLE_LEADER_BATCH_QUEUE_ASSIGN`: Assign the requested number of reports to batches.
 /// - `DURABLE_LEADER_BATCH_QUEUE_CURRENT`: Return the ID of the oldest, non-yet-collected batch.
 /// - `DURABLE_LEADER_BATCH_QUEUE_REMOVE`: Remove the given batch from the queue.
+/// - `DURABLE_LEADER_BATCH_QUEUE_CLOSE`: Close the batch currently being filled.
 ///
 /// The schema for data stored in instances of this DO is as follows:
 ///
@@ -51,6 +60,11 @@ pub(crate) enum LeaderBatchQueueResult {
 /// ```
 ///
 /// Note that the queue ordinal format is inherited from [`DurableOrdered::new_strictly_ordered`].
+///
+/// If the task has a [`DapBatchLifetimeConfig`], then the current batch is closed once it is stale,
+/// i.e., once the configured amount of time has passed since the first report was assigned to it.
+/// Under [`DapStaleBatchPolicy::Close`], a closed batch that has not been filled is also removed
+/// from the queue. Staleness is checked when reports are assigned.
 #[durable_object]
 pub struct LeaderBatchQueue {
     #[allow(dead_code)]
@@ -69,6 +83,7 @@ impl LeaderBatchQueue {
             BatchCount {
                 batch_id: BatchId(rng.gen()),
                 report_count: 0,
+                created_at: None,
             },
             PENDING_PREFIX,
         )
@@ -86,6 +101,17 @@ impl LeaderBatchQueue {
         debug!("LeaderBatchQueue: created batch {batch_id_hex}");
         Ok(queued.into_item())
     }
+
+    async fn remove_batch(&self, batch_id_hex: &str) -> Result<()> {
+        let lookup_key = lookup_key(batch_id_hex);
+        if let Some(lookup_val) = state_get::<String>(&self.state, &lookup_key).await? {
+            self.state.storage().delete(&lookup_val).await?;
+        }
+
+        self.state.storage().delete(&lookup_key).await?;
+        debug!("LeaderBatchQueue: removed batch {batch_id_hex}");
+        Ok(())
+    }
 }
 
 #[durable_object]
@@ -125,25 +151,57 @@ impl DurableObject for LeaderBatchQueue {
             // Assign the requested number of reports to a sequence of batch IDs. For each batch
             // ID, return the number of reports assigned to the batch.
             //
-            // Input: `(batch_size, num_unassigned): (usize, usize)`
+            // Input: `(batch_size, num_unassigned, now, batch_lifetime): (usize, usize, Time,
+            // Option<DapBatchLifetimeConfig>)`
             // Output: `Vec<BatchCount>`
             (DURABLE_LEADER_BATCH_QUEUE_ASSIGN, Method::Post) => {
-                let (batch_size, mut num_unassigned): (usize, usize) = req.json().await?;
+                let (batch_size, mut num_unassigned, now, batch_lifetime): (
+                    usize,
+                    usize,
+                    Time,
+                    Option<DapBatchLifetimeConfig>,
+                ) = req.json().await?;
                 if batch_size == 0 {
                     return Err(int_err("LeaderBatchQueue: called with batch_size is 0"));
                 }
 
                 // Read the batch that is currently being filled from storage, or, if this is the
                 // first time this LeaderBatchQueue instance has been touched, create a new batch.
-                let mut curr = if let Some(curr) = state_get(&self.state, CURRENT).await? {
-                    curr
-                } else {
-                    self.create_batch().await?
-                };
+                let mut curr: BatchCount =
+                    if let Some(curr) = state_get(&self.state, CURRENT).await? {
+                        curr
+                    } else {
+                        self.create_batch().await?
+                    };
+
+                // If the current batch was removed from the queue, e.g., because it was
+                // collected before it was filled, then create a new one.
+                let curr_id_hex = curr.batch_id.to_hex();
+                if state_get::<String>(&self.state, &lookup_key(&curr_id_hex))
+                    .await?
+                    .is_none()
+                {
+                    curr = self.create_batch().await?;
+                } else if let Some(ref batch_lifetime) = batch_lifetime {
+                    // If the current batch is stale, then close it and create a new one.
+                    let stale = curr
+                        .created_at
+                        .is_some_and(|created_at| batch_lifetime.is_stale(created_at, now));
+                    if stale {
+                        if batch_lifetime.policy == DapStaleBatchPolicy::Close
+                            && curr.report_count < batch_size
+                        {
+                            self.remove_batch(&curr_id_hex).await?;
+                        }
+                        debug!("LeaderBatchQueue: closed stale batch {curr_id_hex}");
+                        curr = self.create_batch().await?;
+                    }
+                }
 
                 let mut batch_assignments = vec![BatchCount {
                     batch_id: curr.batch_id.clone(),
                     report_count: 0,
+                    created_at: None,
                 }];
 
                 while num_unassigned > 0 {
@@ -151,6 +209,7 @@ impl DurableObject for LeaderBatchQueue {
                         std::cmp::min(batch_size, curr.report_count + num_unassigned)
                             - curr.report_count;
                     curr.report_count += num_assigned;
+                    curr.created_at.get_or_insert(now);
                     batch_assignments.last_mut().unwrap().report_count += num_assigned;
                     num_unassigned -= num_assigned;
 
@@ -172,14 +231,35 @@ impl DurableObject for LeaderBatchQueue {
             // Input: `batch_id_hex: String`
             (DURABLE_LEADER_BATCH_QUEUE_REMOVE, Method::Post) => {
                 let batch_id_hex: String = req.json().await?;
-                let lookup_key = lookup_key(&batch_id_hex);
-                if let Some(lookup_val) = state_get::<String>(&self.state, &lookup_key).await? {
-                    self.state.storage().delete(&lookup_val).await?;
+                self.remove_batch(&batch_id_hex).await?;
+                Response::from_json(&())
+            }
+
+            // Close the batch currently being filled, so that no more reports are assigned to it,
+            // and return its ID. The batch stays in the queue until it is collected. If no report
+            // has been assigned to the current batch, then nothing is closed.
+            //
+            // Output: `Option<BatchId>`
+            (DURABLE_LEADER_BATCH_QUEUE_CLOSE, Method::Post) => {
+                let curr = match state_get::<BatchCount>(&self.state, CURRENT).await? {
+                    Some(curr) if curr.report_count > 0 => curr,
+                    _ => return Response::from_json(&Option::<BatchId>::None),
+                };
+
+                // The current batch may have been removed from the queue, e.g., because it was
+                // collected before it was filled.
+                let curr_id_hex = curr.batch_id.to_hex();
+                if state_get::<String>(&self.state, &lookup_key(&curr_id_hex))
+                    .await?
+                    .is_none()
+                {
+                    return Response::from_json(&Option::<BatchId>::None);
                 }
 
-                self.state.storage().delete(&lookup_key).await?;
-                debug!("LeaderBatchQueue: removed batch {}", batch_id_hex);
-                Response::from_json(&())
+                let next = self.create_batch().await?;
+                self.state.storage().put(CURRENT, &next).await?;
+                debug!("LeaderBatchQueue: closed batch {curr_id_hex}");
+                Response::from_json(&Some(curr.batch_id))
             }
 
             _ => Err(int_err(format!(
@@ -3,12 +3,15 @@
 
 use crate::{
     config::DaphneWorkerConfig,
-    durable::{state_get, state_get_or_default, DurableOrdered, BINDING_DAP_LEADER_COL_JOB_QUEUE},
+    durable::{
+        state_get, state_get_or_default, state_list, DurableOrdered,
+        BINDING_DAP_LEADER_COL_JOB_QUEUE, MAX_DELETE_KEYS,
+    },
     initialize_tracing, int_err,
 };
 use daphne::{
-    messages::{Collection, CollectionJobId, CollectionReq, TaskId},
-    DapCollectJob, DapVersion,
+    messages::{BatchSelector, Collection, CollectionJobId, CollectionReq, TaskId},
+    DapCollectJob, DapPendingCollectJob, DapReportCountBreakdown, DapVersion,
 };
 use prio::{
     codec::ParameterizedEncode,
@@ -19,6 +22,8 @@ use worker::*;
 
 const PENDING_PREFIX: &str = "pending";
 const PROCESSED_PREFIX: &str = "processed";
+const REPORT_COUNTS_PREFIX: &str = "report_counts";
+const BATCH_SELECTOR_PREFIX: &str = "batch_selector";
 
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_PUT: &str = "/internal/do/leader_col_job_queue/put";
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET: &str = "/internal/do/leader_col_job_queue/get";
@@ -26,6 +31,14 @@ pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_FINISH: &str =
     "/internal/do/leader_col_job_queue/finish";
 pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT: &str =
     "/internal/do/leader_col_job_queue/get_result";
+pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET_REPORT_COUNTS: &str =
+    "/internal/do/leader_col_job_queue/get_report_counts";
+pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_GET_BATCH_SELECTOR: &str =
+    "/internal/do/leader_col_job_queue/get_batch_selector";
+pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_DELETE: &str =
+    "/internal/do/leader_col_job_queue/delete";
+pub(crate) const DURABLE_LEADER_COL_JOB_QUEUE_DELETE_TASK: &str =
+    "/internal/do/leader_col_job_queue/delete_task";
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -33,6 +46,33 @@ pub(crate) struct CollectQueueRequest {
     pub collect_req: CollectionReq,
     pub task_id: TaskId,
     pub collect_job_id: Option<CollectionJobId>,
+    #[serde(default)]
+    pub collector_id: Option<String>,
+}
+
+/// An item of the pending queue. Items queued before additional Collectors were supported do not
+/// carry a Collector ID; these were issued by the task's primary Collector.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum PendingCollectionJob {
+    WithCollector(TaskId, CollectionJobId, CollectionReq, Option<String>),
+    Legacy(TaskId, CollectionJobId, CollectionReq),
+}
+
+impl From<PendingCollectionJob> for DapPendingCollectJob {
+    fn from(pending: PendingCollectionJob) -> Self {
+        match pending {
+            PendingCollectionJob::WithCollector(
+                task_id,
+                collect_job_id,
+                collect_req,
+                collector,
+            ) => (task_id, collect_job_id, collect_req, collector),
+            PendingCollectionJob::Legacy(task_id, collect_job_id, collect_req) => {
+                (task_id, collect_job_id, collect_req, None)
+            }
+        }
+    }
 }
 
 /// Durable Object (DO) for storing the Leader's state for a given task.
@@ -41,22 +81,33 @@ pub(crate) struct CollectQueueRequest {
 ///
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_PUT:` Create a collection job for a CollectReq.
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_GET`: Get the entire list of pending collection jobs.
-/// - `DURABLE_LEADER_COL_JOB_QUEUE_FINISH`: Complete a collection job and store the CollectResp.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_FINISH`: Complete a collection job and store the CollectResp,
+///   the batch selector resolved for it, and the breakdown of its report count.
 /// - `DURABLE_LEADER_COL_JOB_QUEUE_GET_RESULT`: Poll the queue to see if a collect job is
 ///   complete.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_GET_REPORT_COUNTS`: Get the breakdown of the report count of a
+///   completed collect job.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_GET_BATCH_SELECTOR`: Get the batch selector resolved for a
+///   completed collect job.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_DELETE`: Delete a pending or completed collection job.
+/// - `DURABLE_LEADER_COL_JOB_QUEUE_DELETE_TASK`: Delete the pending and completed collection jobs
+///   of a task.
 ///
 /// The schema for data stored in instances of this DO is as follows:
 ///
 /// ```text
 /// [Pending Lookup ID] pending/id/<collection_job_id> -> String (reference to queue element)
 /// [Pending queue]     pending/next_ordinal -> u64
-/// [Pending queue]     pending/item/order/<order> -> (CollectionJobId, CollectReq)
+/// [Pending queue]     pending/item/order/<order> -> (TaskId, CollectionJobId, CollectReq, Option<String>)
 /// [Processed]         processed/<collection_job_id> -> CollectResp
+/// [Report counts]     report_counts/<collection_job_id> -> DapReportCountBreakdown
+/// [Batch selector]    batch_selector/<collection_job_id> -> BatchSelector
 /// ```
 ///
+/// Collection jobs completed before report counts (resp. batch selectors) were recorded have no
+/// breakdown (resp. batch selector).
+///
 /// Note that the queue ordinal format is inherited from [`DurableOrdered::new_strictly_ordered`].
-//
-// TODO Implement collection job deletion per the DAP-02.
 #[durable_object]
 pub struct LeaderCollectionJobQueue {
     #[allow(dead_code)]
@@ -102,9 +153,15 @@ impl DurableObject for LeaderCollectionJobQueue {
                         // enumerating collect URIs. Second, it provides a stable map from requests
                         // to URIs, which prevents us from processing the same collect request more
                         // than once.
-                        let collect_req_bytes = collect_queue_req
+                        //
+                        // Requests from different Collectors are mapped to different URIs, since
+                        // each Collector gets its own result.
+                        let mut collect_req_bytes = collect_queue_req
                             .collect_req
                             .get_encoded_with_param(&DapVersion::Draft02);
+                        if let Some(ref collector_id) = collect_queue_req.collector_id {
+                            collect_req_bytes.extend_from_slice(collector_id.as_bytes());
+                        }
                         let mut collection_job_id_bytes = [0; 16];
                         PrgSha3::seed_stream(
                             self.config.collection_job_id_key.as_ref().unwrap(),
@@ -123,10 +180,11 @@ impl DurableObject for LeaderCollectionJobQueue {
                 if processed.is_none() && !pending {
                     let queued = DurableOrdered::new_strictly_ordered(
                         &self.state,
-                        (
+                        PendingCollectionJob::WithCollector(
                             collect_queue_req.task_id,
                             collection_job_id.clone(),
                             collect_queue_req.collect_req,
+                            collect_queue_req.collector_id,
                         ),
                         PENDING_PREFIX,
                     )
@@ -142,25 +200,29 @@ impl DurableObject for LeaderCollectionJobQueue {
 
             // Get the list of pending collection jobs (oldest jobs first).
             //
-            // Output: `Vec<(Id, CollectReq)>`
+            // Output: `Vec<DapPendingCollectJob>`
             (DURABLE_LEADER_COL_JOB_QUEUE_GET, Method::Get) => {
-                let queue: Vec<(TaskId, CollectionJobId, CollectionReq)> =
-                    DurableOrdered::get_all(&self.state, PENDING_PREFIX)
+                let queue: Vec<DapPendingCollectJob> =
+                    DurableOrdered::<PendingCollectionJob>::get_all(&self.state, PENDING_PREFIX)
                         .await?
                         .into_iter()
-                        .map(|queued| queued.into_item())
+                        .map(|queued| queued.into_item().into())
                         .collect();
                 Response::from_json(&queue)
             }
 
-            // Remove a collection job from the pending queue and store the CollectResp.
+            // Remove a collection job from the pending queue and store the CollectResp, the batch
+            // selector resolved for the job, and the breakdown of its report count.
             //
-            // Input: `(collection_job_id, collect_resp): (Id, CollectResp)`
+            // Input: `(task_id, collection_job_id, batch_sel, collect_resp, report_counts):
+            // (TaskId, Id, BatchSelector, CollectResp, DapReportCountBreakdown)`
             (DURABLE_LEADER_COL_JOB_QUEUE_FINISH, Method::Post) => {
-                let (task_id, collection_job_id, collect_resp): (
+                let (task_id, collection_job_id, batch_sel, collect_resp, report_counts): (
                     TaskId,
                     CollectionJobId,
+                    BatchSelector,
                     Collection,
+                    DapReportCountBreakdown,
                 ) = req.json().await?;
                 let processed_key = processed_key(&task_id, &collection_job_id);
                 let processed: Option<Collection> = state_get(&self.state, &processed_key).await?;
@@ -179,7 +241,21 @@ impl DurableObject for LeaderCollectionJobQueue {
                 let mut storage = self.state.storage();
                 let f = storage.delete(&pending_key);
 
-                // Store the CollectResp.
+                // Store the CollectResp, the batch selector, and the breakdown of its report count.
+                self.state
+                    .storage()
+                    .put(
+                        &batch_selector_key(&task_id, &collection_job_id),
+                        batch_sel,
+                    )
+                    .await?;
+                self.state
+                    .storage()
+                    .put(
+                        &report_counts_key(&task_id, &collection_job_id),
+                        report_counts,
+                    )
+                    .await?;
                 self.state
                     .storage()
                     .put(&processed_key, collect_resp)
@@ -214,6 +290,118 @@ impl DurableObject for LeaderCollectionJobQueue {
                 }
             }
 
+            // Get the breakdown of the report count of a completed collection job.
+            //
+            // Input: `(task_id, collection_job_id): (TaskId, Id)`
+            // Output: `Option<DapReportCountBreakdown>`
+            (DURABLE_LEADER_COL_JOB_QUEUE_GET_REPORT_COUNTS, Method::Post) => {
+                let (task_id, collection_job_id): (TaskId, CollectionJobId) = req.json().await?;
+                let report_counts: Option<DapReportCountBreakdown> = state_get(
+                    &self.state,
+                    &report_counts_key(&task_id, &collection_job_id),
+                )
+                .await?;
+                Response::from_json(&report_counts)
+            }
+
+            // Get the batch selector resolved for a completed collection job.
+            //
+            // Input: `(task_id, collection_job_id): (TaskId, Id)`
+            // Output: `Option<BatchSelector>`
+            (DURABLE_LEADER_COL_JOB_QUEUE_GET_BATCH_SELECTOR, Method::Post) => {
+                let (task_id, collection_job_id): (TaskId, CollectionJobId) = req.json().await?;
+                let batch_sel: Option<BatchSelector> = state_get(
+                    &self.state,
+                    &batch_selector_key(&task_id, &collection_job_id),
+                )
+                .await?;
+                Response::from_json(&batch_sel)
+            }
+
+            // Delete a collection job. If the job is pending, then it is removed from the queue;
+            // if it is completed, then its result is deleted.
+            //
+            // Input: `(task_id, collection_job_id): (TaskId, Id)`
+            // Output: `DapCollectJob` (status of the job before it was deleted)
+            (DURABLE_LEADER_COL_JOB_QUEUE_DELETE, Method::Post) => {
+                let (task_id, collection_job_id): (TaskId, CollectionJobId) = req.json().await?;
+                let pending_key = pending_key(&task_id, &collection_job_id);
+                let processed_key = processed_key(&task_id, &collection_job_id);
+                let lookup_val: Option<String> = state_get(&self.state, &pending_key).await?;
+                let processed: Option<Collection> = state_get(&self.state, &processed_key).await?;
+
+                let mut keys = vec![
+                    pending_key,
+                    processed_key,
+                    report_counts_key(&task_id, &collection_job_id),
+                    batch_selector_key(&task_id, &collection_job_id),
+                ];
+                let pending = lookup_val.is_some();
+                keys.extend(lookup_val);
+                self.state.storage().delete_multiple(keys).await?;
+
+                if let Some(collect_resp) = processed {
+                    Response::from_json(&DapCollectJob::Done(collect_resp))
+                } else if pending {
+                    Response::from_json(&DapCollectJob::Pending)
+                } else {
+                    Response::from_json(&DapCollectJob::Unknown)
+                }
+            }
+
+            // Delete the pending and completed collection jobs of a task.
+            //
+            // Input: `task_id: TaskId`
+            // Output: `u64` (number of collection jobs deleted)
+            (DURABLE_LEADER_COL_JOB_QUEUE_DELETE_TASK, Method::Post) => {
+                let task_id: TaskId = req.json().await?;
+                let pending: Vec<(String, String)> = state_list(
+                    &self.state,
+                    &format!("{PENDING_PREFIX}/tasks/{}/", task_id.to_base64url()),
+                )
+                .await?;
+                let processed: Vec<(String, Collection)> = state_list(
+                    &self.state,
+                    &format!("{PROCESSED_PREFIX}/tasks/{}/", task_id.to_base64url()),
+                )
+                .await?;
+                let report_counts: Vec<(String, DapReportCountBreakdown)> = state_list(
+                    &self.state,
+                    &format!("{REPORT_COUNTS_PREFIX}/tasks/{}/", task_id.to_base64url()),
+                )
+                .await?;
+                let batch_sels: Vec<(String, BatchSelector)> = state_list(
+                    &self.state,
+                    &format!("{BATCH_SELECTOR_PREFIX}/tasks/{}/", task_id.to_base64url()),
+                )
+                .await?;
+
+                let count = pending.len() + processed.len();
+                let keys: Vec<String> = pending
+                    .into_iter()
+                    .flat_map(|(pending_key, lookup_val)| [pending_key, lookup_val])
+                    .chain(
+                        processed
+                            .into_iter()
+                            .map(|(processed_key, _)| processed_key),
+                    )
+                    .chain(
+                        report_counts
+                            .into_iter()
+                            .map(|(report_counts_key, _)| report_counts_key),
+                    )
+                    .chain(
+                        batch_sels
+                            .into_iter()
+                            .map(|(batch_selector_key, _)| batch_selector_key),
+                    )
+                    .collect();
+                for keys in keys.chunks(MAX_DELETE_KEYS) {
+                    self.state.storage().delete_multiple(keys.to_vec()).await?;
+                }
+                Response::from_json(&count)
+            }
+
             _ => Err(int_err(format!(
                 "LeaderCollectionJobQueue: unexpected request: method={:?}; path={:?}",
                 req.method(),
@@ -238,3 +426,19 @@ fn processed_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> Strin
         collection_job_id.to_base64url()
     )
 }
+
+fn report_counts_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> String {
+    format!(
+        "{REPORT_COUNTS_PREFIX}/tasks/{}/collection_jobs/{}",
+        task_id.to_base64url(),
+        collection_job_id.to_base64url()
+    )
+}
+
+fn batch_selector_key(task_id: &TaskId, collection_job_id: &CollectionJobId) -> String {
+    format!(
+        "{BATCH_SELECTOR_PREFIX}/tasks/{}/collection_jobs/{}",
+        task_id.to_base64url(),
+        collection_job_id.to_base64url()
+    )
+}
@@ -2,14 +2,26 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::{int_err, now};
-use daphne::{messages::TaskId, DapBatchBucket, DapVersion};
+use daphne::{
+    messages::{BatchSelector, TaskId},
+    storage::DapAggregateSpan,
+    DapBatchBucket, DapVersion,
+};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::cmp::min;
+use std::{cmp::min, collections::HashSet, sync::RwLock};
 use worker::*;
 
 pub(crate) const DURABLE_DELETE_ALL: &str = "/internal/do/delete_all";
 
+/// Error returned by [`DurableConnector`] when a sub-request is refused because the deadline of
+/// the request being handled is near.
+pub(crate) const ERR_DEADLINE_EXCEEDED: &str = "not enough time left to send request to DO";
+
+/// Minimum amount of time (in milliseconds) that must remain before the deadline in order for a
+/// sub-request to a DO to be sent.
+const MIN_REMAINING_TIME_FOR_DURABLE_REQUEST_MS: u64 = 1000;
+
 pub(crate) const BINDING_DAP_REPORTS_PENDING: &str = "DAP_REPORTS_PENDING";
 pub(crate) const BINDING_DAP_REPORTS_PROCESSED: &str = "DAP_REPORTS_PROCESSED";
 pub(crate) const BINDING_DAP_AGGREGATE_STORE: &str = "DAP_AGGREGATE_STORE";
@@ -17,7 +29,11 @@ pub(crate) const BINDING_DAP_LEADER_AGG_JOB_QUEUE: &str = "DAP_LEADER_AGG_JOB_QU
 pub(crate) const BINDING_DAP_LEADER_BATCH_QUEUE: &str = "DAP_LEADER_BATCH_QUEUE";
 pub(crate) const BINDING_DAP_LEADER_COL_JOB_QUEUE: &str = "DAP_LEADER_COL_JOB_QUEUE";
 pub(crate) const BINDING_DAP_HELPER_STATE_STORE: &str = "DAP_HELPER_STATE_STORE";
+pub(crate) const BINDING_DAP_HELPER_AGG_JOB_SLOTS: &str = "DAP_HELPER_AGG_JOB_SLOTS";
 pub(crate) const BINDING_DAP_GARBAGE_COLLECTOR: &str = "DAP_GARBAGE_COLLECTOR";
+pub(crate) const BINDING_DAP_TASK_USAGE_STORE: &str = "DAP_TASK_USAGE_STORE";
+pub(crate) const BINDING_DAP_AGGREGATION_TELEMETRY_STORE: &str = "DAP_AGGREGATION_TELEMETRY_STORE";
+pub(crate) const BINDING_DAP_UPLOAD_IDEMPOTENCY_STORE: &str = "DAP_UPLOAD_IDEMPOTENCY_STORE";
 
 const ERR_NO_VALUE: &str = "No such value in storage.";
 
@@ -35,14 +51,103 @@ const ERR_NO_VALUE: &str = "No such value in storage.";
 // TODO(bhalley) does this need to be configurable?
 const MAX_KEYS: usize = 128;
 
+/// Maximum number of keys that can be deleted at once from DO storage.
+pub(crate) const MAX_DELETE_KEYS: usize = 128;
+
 /// Used to send HTTP requests to a durable object (DO) instance.
 pub(crate) struct DurableConnector<'a> {
     env: &'a Env,
+    deadline: Option<u64>,
+    gc_registry: Option<&'a RwLock<HashSet<String>>>,
 }
 
 impl<'a> DurableConnector<'a> {
     pub(crate) fn new(env: &'a Env) -> Self {
-        DurableConnector { env }
+        DurableConnector {
+            env,
+            deadline: None,
+            gc_registry: None,
+        }
+    }
+
+    /// Register each per-task DO instance with the task's instance of `GarbageCollector` the
+    /// first time it is used, so that it can be deleted once the task has expired. `registered`
+    /// is the set of names of the instances that have already been registered.
+    pub(crate) fn with_gc_registry(mut self, registered: &'a RwLock<HashSet<String>>) -> Self {
+        self.gc_registry = Some(registered);
+        self
+    }
+
+    /// Register the DO instance with the given binding and name for garbage collection, if
+    /// configured and if the instance belongs to a task.
+    async fn register_for_gc(&self, durable_binding: &str, durable_name: &str) -> Result<()> {
+        let registered = match self.gc_registry {
+            Some(registered) => registered,
+            None => return Ok(()),
+        };
+        // Instances of ReportsProcessed, HelperStateStore and UploadIdempotencyStore delete
+        // themselves once they are no longer needed, so they don't need to be registered.
+        if !matches!(
+            durable_binding,
+            BINDING_DAP_REPORTS_PENDING
+                | BINDING_DAP_AGGREGATE_STORE
+                | BINDING_DAP_LEADER_BATCH_QUEUE
+                | BINDING_DAP_TASK_USAGE_STORE
+                | BINDING_DAP_AGGREGATION_TELEMETRY_STORE
+        ) {
+            return Ok(());
+        }
+        let task_name = match durable_name_task_of(durable_name) {
+            Some(task_name) => task_name,
+            None => return Ok(()),
+        };
+        if registered
+            .read()
+            .map_err(|e| int_err(format!("Failed to lock set for reading: {e}")))?
+            .contains(durable_name)
+        {
+            return Ok(());
+        }
+
+        let durable_ref = DurableReference {
+            binding: durable_binding.to_string(),
+            id_hex: self.id_hex_from_name(durable_binding, durable_name)?,
+            task_id: None,
+        };
+        let stub = self
+            .env
+            .durable_object(BINDING_DAP_GARBAGE_COLLECTOR)?
+            .id_from_name(task_name)?
+            .get_stub()?;
+        durable_request::<_, ()>(
+            stub,
+            garbage_collector::DURABLE_GARBAGE_COLLECTOR_PUT,
+            Method::Post,
+            Some(&durable_ref),
+        )
+        .await?;
+        registered
+            .write()
+            .map_err(|e| int_err(format!("Failed to lock set for writing: {e}")))?
+            .insert(durable_name.to_string());
+        Ok(())
+    }
+
+    /// Refuse to send requests once the given deadline (in milliseconds since the UNIX epoch) is
+    /// near. A refused request results in an error with message [`ERR_DEADLINE_EXCEEDED`].
+    pub(crate) fn with_deadline(mut self, deadline: Option<u64>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    fn check_deadline(&self) -> Result<()> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_sub(Date::now().as_millis());
+            if remaining < MIN_REMAINING_TIME_FOR_DURABLE_REQUEST_MS {
+                return Err(Error::RustError(ERR_DEADLINE_EXCEEDED.into()));
+            }
+        }
+        Ok(())
     }
 
     /// Send a GET request with the given path to the DO instance with the given binding and name.
@@ -53,6 +158,8 @@ impl<'a> DurableConnector<'a> {
         durable_path: &'static str,
         durable_name: String,
     ) -> Result<O> {
+        self.check_deadline()?;
+        self.register_for_gc(durable_binding, &durable_name).await?;
         let namespace = self.env.durable_object(durable_binding)?;
         let stub = namespace.id_from_name(&durable_name)?.get_stub()?;
         durable_request(stub, durable_path, Method::Get, None::<()>).await
@@ -67,11 +174,23 @@ impl<'a> DurableConnector<'a> {
         durable_name: String,
         data: I,
     ) -> Result<O> {
+        self.check_deadline()?;
+        self.register_for_gc(durable_binding, &durable_name).await?;
         let namespace = self.env.durable_object(durable_binding)?;
         let stub = namespace.id_from_name(&durable_name)?.get_stub()?;
         durable_request(stub, durable_path, Method::Post, Some(data)).await
     }
 
+    /// Compute the hex identifier of the DO instance with the given binding and name.
+    pub(crate) fn id_hex_from_name(
+        &self,
+        durable_binding: &str,
+        durable_name: &str,
+    ) -> Result<String> {
+        let namespace = self.env.durable_object(durable_binding)?;
+        Ok(namespace.id_from_name(durable_name)?.to_string())
+    }
+
     /// Send a POST request with the given path to the DO instance with the given binding and hex
     /// identifier. The body of the request is a JSON object. The response is expected to be a JSON
     /// object.
@@ -82,6 +201,7 @@ impl<'a> DurableConnector<'a> {
         durable_id_hex: String,
         data: I,
     ) -> Result<O> {
+        self.check_deadline()?;
         let namespace = self.env.durable_object(durable_binding)?;
         let stub = namespace.id_from_string(&durable_id_hex)?.get_stub()?;
         durable_request(stub, durable_path, Method::Post, Some(data)).await
@@ -210,6 +330,28 @@ pub(crate) async fn state_get<T: for<'a> Deserialize<'a>>(
     })
 }
 
+/// List the key/value pairs whose key starts with the given prefix.
+///
+/// WARNING: All matching pairs are loaded into memory at once. This should only be used when the
+/// number of matching pairs is strictly controlled or when the caller is not on the hot path.
+pub(crate) async fn state_list<T: for<'a> Deserialize<'a>>(
+    state: &State,
+    prefix: &str,
+) -> Result<Vec<(String, T)>> {
+    let iter = state
+        .storage()
+        .list_with_options(ListOptions::new().prefix(prefix))
+        .await?
+        .entries();
+    let mut js_item = iter.next()?;
+    let mut res = Vec::new();
+    while !js_item.done() {
+        res.push(serde_wasm_bindgen::from_value(js_item.value()).map_err(int_err)?);
+        js_item = iter.next()?;
+    }
+    Ok(res)
+}
+
 /// Set a key/value pair unless the key already exists. If the key exists, then return the current
 /// value. Otherwise return nothing.
 pub(crate) async fn state_set_if_not_exists<T: for<'a> Deserialize<'a> + Serialize>(
@@ -256,10 +398,64 @@ pub(crate) fn durable_name_agg_store(
     )
 }
 
+/// Name of the `AggregateStore` instance that holds the compacted aggregate share of a span of
+/// buckets.
+pub(crate) fn durable_name_agg_span(
+    version: &DapVersion,
+    task_id_hex: &str,
+    span: &DapAggregateSpan,
+) -> String {
+    format!(
+        "{}/span/{}/{}",
+        durable_name_task(version, task_id_hex),
+        span.start,
+        span.duration,
+    )
+}
+
+/// Name of the `AggregateStore` instance that holds the aggregate shares produced for queries of
+/// the batch, i.e., the instance of the first bucket of the batch.
+pub(crate) fn durable_name_collected_agg_share(
+    version: &DapVersion,
+    task_id_hex: &str,
+    batch_sel: &BatchSelector,
+) -> String {
+    let bucket = match batch_sel {
+        BatchSelector::TimeInterval { batch_interval } => DapBatchBucket::TimeInterval {
+            batch_window: batch_interval.start,
+        },
+        BatchSelector::FixedSizeByBatchId { batch_id } => DapBatchBucket::FixedSize { batch_id },
+    };
+    durable_name_agg_store(version, task_id_hex, &bucket)
+}
+
+pub(crate) fn durable_name_task_usage(version: &DapVersion, task_id_hex: &str, day: u64) -> String {
+    format!(
+        "{}/usage/day/{:020}",
+        durable_name_task(version, task_id_hex),
+        day
+    )
+}
+
+pub(crate) fn durable_name_task_telemetry(version: &DapVersion, task_id_hex: &str) -> String {
+    format!("{}/telemetry", durable_name_task(version, task_id_hex))
+}
+
 pub(crate) fn durable_name_task(version: &DapVersion, task_id_hex: &str) -> String {
     format!("{}/task/{}", version.as_ref(), task_id_hex)
 }
 
+/// If the DO instance with the given name belongs to a task, then return the prefix of the name
+/// that identifies the task (see [`durable_name_task`]).
+pub(crate) fn durable_name_task_of(durable_name: &str) -> Option<&str> {
+    let mut parts = durable_name.splitn(4, '/');
+    let (version, task, task_id_hex) = (parts.next()?, parts.next()?, parts.next()?);
+    if task != "task" || version.is_empty() || task_id_hex.is_empty() {
+        return None;
+    }
+    Some(&durable_name[..version.len() + task.len() + task_id_hex.len() + 2])
+}
+
 fn durable_name_bucket(bucket: &DapBatchBucket<'_>) -> String {
     match bucket {
         DapBatchBucket::TimeInterval { batch_window } => {
@@ -298,7 +494,18 @@ impl<T: for<'a> Deserialize<'a> + Serialize> DurableOrdered<T> {
     /// the queue's namespace, i.e., the prefix of each key for each key/value pair in the queue.
     /// At most `limit` queue elements are returned.
     pub(crate) async fn get_front(state: &State, prefix: &str, limit: usize) -> Result<Vec<Self>> {
-        get_front(state, prefix, Some(limit)).await
+        get_front(state, prefix, Some(limit), None).await
+    }
+
+    /// Like [`Self::get_front`], except that only elements whose ordinal is greater than `after`
+    /// are returned. This is used to resume reading a queue where a previous read stopped.
+    pub(crate) async fn get_front_after(
+        state: &State,
+        prefix: &str,
+        limit: usize,
+        after: &str,
+    ) -> Result<Vec<Self>> {
+        get_front(state, prefix, Some(limit), Some(after)).await
     }
 
     /// Return all elements in the queue.
@@ -307,7 +514,7 @@ impl<T: for<'a> Deserialize<'a> + Serialize> DurableOrdered<T> {
     /// to start rate limiting the Worker. This should only be used when the size of the queue is
     /// strictly controlled.
     async fn get_all(state: &State, prefix: &str) -> Result<Vec<Self>> {
-        get_front(state, prefix, None).await
+        get_front(state, prefix, None, None).await
     }
 
     /// Create a new element for a roughly ordered queue. (Use `put()` to store it.)
@@ -399,6 +606,10 @@ impl<T: for<'a> Deserialize<'a> + Serialize> DurableOrdered<T> {
     pub(crate) fn into_item(self) -> T {
         self.item
     }
+
+    pub(crate) fn ordinal(&self) -> &str {
+        &self.ordinal
+    }
 }
 
 impl<T> AsRef<T> for DurableOrdered<T> {
@@ -411,12 +622,19 @@ async fn get_front<T: for<'a> Deserialize<'a> + Serialize>(
     state: &State,
     prefix: &str,
     limit: Option<usize>,
+    after: Option<&str>,
 ) -> Result<Vec<DurableOrdered<T>>> {
     let key_prefix = format!("{prefix}/item/");
+    let start_key = after.map(|after| format!("{key_prefix}{after}"));
     let mut opt = ListOptions::new().prefix(&key_prefix);
+    if let Some(ref start_key) = start_key {
+        // The start of the range is inclusive, so fetch one more element in case the first one
+        // is the element at `after`.
+        opt = opt.start(start_key);
+    }
     if let Some(limit) = limit {
         // Note we impose an upper limit on the user's specified limit.
-        opt = opt.limit(min(limit, MAX_KEYS));
+        opt = opt.limit(min(limit + usize::from(after.is_some()), MAX_KEYS));
     }
     let iter = state.storage().list_with_options(opt).await?.entries();
     let mut js_item = iter.next()?;
@@ -428,6 +646,10 @@ async fn get_front<T: for<'a> Deserialize<'a> + Serialize>(
             return Err(int_err("queue element key is improperly formatted"));
         }
         let ordinal = &key[key_prefix.len()..];
+        if Some(ordinal) == after {
+            js_item = iter.next()?;
+            continue;
+        }
         res.push(DurableOrdered {
             item,
             prefix: prefix.to_string(),
@@ -435,11 +657,16 @@ async fn get_front<T: for<'a> Deserialize<'a> + Serialize>(
         });
         js_item = iter.next()?;
     }
+    if let Some(limit) = limit {
+        res.truncate(limit);
+    }
     Ok(res)
 }
 
 pub(crate) mod aggregate_store;
+pub(crate) mod aggregation_telemetry_store;
 pub(crate) mod garbage_collector;
+pub(crate) mod helper_agg_job_slots;
 pub(crate) mod helper_state_store;
 pub(crate) mod leader_agg_job_queue;
 pub(crate) mod leader_batch_queue;
@@ -448,3 +675,7 @@ pub(crate) mod leader_col_job_queue;
 pub(crate) mod mod_test;
 pub(crate) mod reports_pending;
 pub(crate) mod reports_processed;
+#[cfg(test)]
+mod reports_processed_test;
+pub(crate) mod task_usage_store;
+pub(crate) mod upload_idempotency_store;
@@ -2,11 +2,12 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use crate::durable::{
-    durable_name_agg_store, durable_name_queue, durable_name_report_store,
-    reports_pending::PendingReport,
+    durable_name_agg_span, durable_name_agg_store, durable_name_queue, durable_name_report_store,
+    durable_name_task, durable_name_task_of, reports_pending::PendingReport,
 };
 use daphne::{
     messages::{BatchId, Report, ReportId, ReportMetadata, TaskId},
+    storage::DapAggregateSpan,
     test_version, test_versions, DapBatchBucket, DapVersion,
 };
 use paste::paste;
@@ -36,6 +37,30 @@ fn durable_name() {
         durable_name_agg_store(&DapVersion::Draft02, &id1.to_hex(), &DapBatchBucket::TimeInterval{ batch_window: time }),
         "v02/task/1111111111111111111111111111111111111111111111111111111111111111/window/1664850074",
     );
+
+    assert_eq!(
+        durable_name_agg_span(&DapVersion::Draft02, &id1.to_hex(), &DapAggregateSpan{ start: time, duration: 86400 }),
+        "v02/task/1111111111111111111111111111111111111111111111111111111111111111/span/1664850074/86400",
+    );
+}
+
+#[test]
+fn durable_name_task_prefix() {
+    let task_name = durable_name_task(&DapVersion::Draft04, &TaskId([17; 32]).to_hex());
+    let report_store_name = durable_name_report_store(
+        &DapVersion::Draft04,
+        &TaskId([17; 32]).to_hex(),
+        1664850074,
+        0,
+    );
+
+    assert_eq!(durable_name_task_of(&task_name), Some(task_name.as_str()));
+    assert_eq!(
+        durable_name_task_of(&report_store_name),
+        Some(task_name.as_str())
+    );
+    assert_eq!(durable_name_task_of(&durable_name_queue(0)), None);
+    assert_eq!(durable_name_task_of("garbage_collector"), None);
 }
 
 // Test that the `PendingReport.report_id_hex()` method properly extracts the report ID from the
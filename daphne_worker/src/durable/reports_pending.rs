@@ -8,7 +8,7 @@ use crate::{
         leader_agg_job_queue::{
             DURABLE_LEADER_AGG_JOB_QUEUE_FINISH, DURABLE_LEADER_AGG_JOB_QUEUE_PUT,
         },
-        state_get, state_set_if_not_exists, DurableConnector, DurableOrdered,
+        state_get, state_get_or_default, state_set_if_not_exists, DurableConnector, DurableOrdered,
         BINDING_DAP_LEADER_AGG_JOB_QUEUE, BINDING_DAP_REPORTS_PENDING, MAX_KEYS,
     },
     initialize_tracing, int_err,
@@ -27,6 +27,18 @@ pub(crate) const DURABLE_REPORTS_PENDING_PUT: &str = "/internal/do/reports_pendi
 pub(crate) enum ReportsPendingResult {
     Ok,
     ErrReportExists,
+
+    /// The instance holds the maximum number of pending reports.
+    ErrBucketFull,
+}
+
+/// Input of `DURABLE_REPORTS_PENDING_PUT`.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ReportsPendingPutReq {
+    pub(crate) pending_report: PendingReport,
+
+    /// If set, then the report is refused if the instance already holds this many reports.
+    pub(crate) max_pending_reports: Option<u64>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -46,7 +58,11 @@ impl PendingReport {
     pub(crate) fn report_id_hex(&self) -> Option<&str> {
         match self.version {
             DapVersion::Draft02 if self.report_hex.len() >= 96 => Some(&self.report_hex[64..96]),
-            DapVersion::Draft04 if self.report_hex.len() >= 32 => Some(&self.report_hex[..32]),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06
+                if self.report_hex.len() >= 32 =>
+            {
+                Some(&self.report_hex[..32])
+            }
             DapVersion::Unknown => unreachable!("unhandled version {:?}", self.version),
             _ => None,
         }
@@ -59,8 +75,9 @@ impl PendingReport {
 ///
 /// - `DURABLE_REPORTS_PENDING_PUT`: Used to store a report uploaded by a Client. Whenever this
 ///   instance becomes non-empty, an aggregate job is created and dispatched to
-///   `LeaderAggregationJobQueue`. If report is found in this instance with the same ID, then an
-///   error is returned.
+///   `LeaderAggregationJobQueue`. If report is found in this instance with the same ID, or if the
+///   instance holds the maximum number of reports indicated by the request, then an error is
+///   returned.
 ///
 /// - `DURABLE_REPORTS_PENDING_GET`: Used to drain reports from storage so that they can be
 ///   aggregated. Whenever the instance becomes empty, the aggregation job is removed from
@@ -70,6 +87,7 @@ impl PendingReport {
 ///
 /// ```text
 /// [Pending report]  pending/<report_id> -> PendingReport
+/// [Report count]    pending_count -> u64
 /// [Aggregation job] agg_job -> DurableOrdered<PendingReport>
 /// ```
 ///
@@ -130,7 +148,13 @@ impl DurableObject for ReportsPending {
                 // NOTE In order to support DAP tasks that require longer batch lifetimes, it will
                 // necessary to check if the lifetime has been reached before removing reports from
                 // storage. We might consider putting reports in KV instead.
+                let pending_count: u64 = state_get_or_default(&self.state, "pending_count").await?;
+                let num_drained = u64::try_from(keys.len()).unwrap();
                 self.state.storage().delete_multiple(keys).await?;
+                self.state
+                    .storage()
+                    .put("pending_count", pending_count.saturating_sub(num_drained))
+                    .await?;
 
                 // Check if this bucket is now empty, and if so, remove it from the agg job queue.
                 let empty = self
@@ -176,13 +200,22 @@ impl DurableObject for ReportsPending {
 
             // Store a report.
             //
-            // Input: `pending_report: PendingReport`
+            // Input: `put_req: ReportsPendingPutReq`
             // Output: `ReportsPendingResult`
             (DURABLE_REPORTS_PENDING_PUT, Method::Post) => {
-                let pending_report: PendingReport = req.json().await?;
+                let put_req: ReportsPendingPutReq = req.json().await?;
+                let pending_report = put_req.pending_report;
                 let report_id_hex = pending_report
                     .report_id_hex()
                     .ok_or_else(|| int_err("failed to parse report ID from report"))?;
+                let pending_count: u64 = state_get_or_default(&self.state, "pending_count").await?;
+                if put_req
+                    .max_pending_reports
+                    .is_some_and(|max| pending_count >= max)
+                {
+                    return Response::from_json(&ReportsPendingResult::ErrBucketFull);
+                }
+
                 let key = format!("pending/{report_id_hex}");
                 let exists = state_set_if_not_exists(&self.state, &key, &pending_report)
                     .await?
@@ -190,6 +223,10 @@ impl DurableObject for ReportsPending {
                 if exists {
                     return Response::from_json(&ReportsPendingResult::ErrReportExists);
                 }
+                self.state
+                    .storage()
+                    .put("pending_count", pending_count + 1)
+                    .await?;
 
                 // Check if processing for this bucket of reports has been scheduled. If not, add
                 // this bucket to the aggregation job queue.
@@ -3,30 +3,87 @@
 
 use crate::{
     config::DaphneWorkerConfig,
-    durable::{state_set_if_not_exists, BINDING_DAP_REPORTS_PROCESSED},
-    initialize_tracing, int_err,
+    durable::{state_get, state_list, BINDING_DAP_REPORTS_PROCESSED, MAX_DELETE_KEYS},
+    initialize_tracing, int_err, now,
 };
+use daphne::{messages::Time, DapReplayFilterConfig};
 use futures::future::try_join_all;
-use std::time::Duration;
-use tracing::warn;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::{cmp::min, collections::BTreeSet, time::Duration};
+use tracing::{info, trace, warn};
 use worker::*;
 
 pub(crate) const DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED: &str =
     "/internal/do/report_store/mark_aggregated";
+pub(crate) const DURABLE_REPORTS_PROCESSED_UNMARK_AGGREGATED: &str =
+    "/internal/do/report_store/unmark_aggregated";
+pub(crate) const DURABLE_REPORTS_PROCESSED_CHECK_AGGREGATED: &str =
+    "/internal/do/report_store/check_aggregated";
 
 /// Durable Object (DO) for tracking which reports have been processed.
 ///
-/// This object defines a single API endpoint, `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`, which
-/// is used to mark a set of reports as aggregated. It returns the set of reports in that have
-/// already been aggregated (and thus need to be rejected by the caller).
+/// The following API endpoints are defined:
+///
+/// - `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`: Used to mark a set of reports as aggregated. It
+///   returns the set of reports in that have already been aggregated (and thus need to be
+///   rejected by the caller).
+///
+///   If the request carries a [`DapReplayFilterConfig`], then a Bloom filter over the processed
+///   report IDs is kept in memory, and only reports that the filter indicates were probably
+///   processed are checked against storage. The filter is stored in chunks, and each request
+///   writes the chunks it modified before it marks any report, so the stored filter never misses
+///   a processed report. The filter is loaded the first time it is needed after the instance is
+///   loaded; it is rebuilt from the report IDs only if it has not been stored yet or was stored
+///   with a different configuration. A request without a filter configuration discards the stored
+///   filter, since the reports it marks are not inserted. The response includes how effective the
+///   filter was.
+///
+/// - `DURABLE_REPORTS_PROCESSED_UNMARK_AGGREGATED`: Used by the Leader to unmark a set of reports
+///   belonging to an abandoned aggregation job so that they can be aggregated again.
+///
+/// - `DURABLE_REPORTS_PROCESSED_CHECK_AGGREGATED`: Used for dry runs of aggregation. It returns
+///   the set of reports that have already been aggregated without marking any report.
 ///
 /// The schema for stored report IDs is as follows:
 ///
 /// ```text
 ///     processed/<report_id> -> bool
+///     expiry/<replay_window_end>/<report_id> -> bool
+///     replay_filter_config -> DapReplayFilterConfig
+///     replay_filter/<index> -> String
+/// ```
+///
+/// where `<report_id>` is the hex-encoded report ID, `<replay_window_end>` is the zero-padded
+/// time at which the report's replay window ends, and `<index>` is the index of a hex-encoded
+/// chunk of the replay filter (chunks that are all zero are not stored).
+///
+/// The filter has false positives, so the exact report IDs are still stored: the filter saves
+/// storage reads, not storage. It adds at most `MAX_REPLAY_FILTER_BITS / 4` bytes (2 MiB, as it is
+/// hex-encoded) to the instance.
+///
+/// Reports whose replay window has ended (see `report_replay_window` in
+/// [`DapGlobalConfig`](daphne::DapGlobalConfig)) are rejected before they are checked against
+/// this state, so their IDs need not be kept. If a replay window is configured, then each report
+/// is indexed by the end of its replay window when it is marked, and both the mark and the check
+/// requests first purge the IDs whose window has ended. The current time is taken from the
+/// request rather than from the DO's clock, so that it agrees with the Aggregator's check of the
+/// replay window. Report IDs that were compacted are kept until the instance is deleted.
+///
+/// If `DAP_PROCESSED_COMPACTION_DELAY` is configured, then the first alarm compacts this state
+/// once the instance's report storage epoch is expected to be closed: the report IDs are sorted,
+/// packed into immutable chunks, and the individual keys are deleted. The second alarm deletes
+/// the instance as usual. The schema for the compacted state is as follows:
+///
+/// ```text
+///     compaction -> CompactionStats
+///     compacted/<chunk> -> String
 /// ```
 ///
-/// where `<report_id>` is the hex-encoded report ID.
+/// where `<chunk>` is the index of the chunk and each chunk is the concatenation of (at most
+/// [`COMPACTED_CHUNK_LEN`]) sorted, hex-encoded report IDs. If a report is unmarked after
+/// compaction, then `processed/<report_id>` is set to `false`; this tombstone takes precedence
+/// over the compacted state.
 #[durable_object]
 pub struct ReportsProcessed {
     #[allow(dead_code)]
@@ -35,21 +92,502 @@ pub struct ReportsProcessed {
     config: DaphneWorkerConfig,
     touched: bool,
     alarmed: bool,
+    replay_filter: Option<ReplayFilter>,
+
+    /// Set once the stored replay filter has been discarded by a request without a filter.
+    replay_filter_discarded: bool,
+}
+
+/// Request to `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ReportsProcessedMarkReq {
+    /// Hex-encoded report IDs.
+    pub(crate) report_id_hex_set: Vec<String>,
+
+    /// The replay filter of the task, if any.
+    #[serde(default)]
+    pub(crate) replay_filter: Option<DapReplayFilterConfig>,
+
+    /// Time at which the replay window of each report ends, in the same order as
+    /// `report_id_hex_set`. Empty if no replay window is configured.
+    #[serde(default)]
+    pub(crate) replay_window_ends: Vec<Time>,
+
+    /// The Aggregator's current time. Report IDs whose replay window has ended are purged.
+    #[serde(default)]
+    pub(crate) now: Time,
+}
+
+/// Request to `DURABLE_REPORTS_PROCESSED_CHECK_AGGREGATED`.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ReportsProcessedCheckReq {
+    /// Hex-encoded report IDs.
+    pub(crate) report_id_hex_set: Vec<String>,
+
+    /// The Aggregator's current time. Report IDs whose replay window has ended are purged.
+    pub(crate) now: Time,
+}
+
+/// Response of `DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED`.
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct ReportsProcessedMarkResp {
+    /// Subset of the report IDs in the request that were already processed.
+    pub(crate) processed: Vec<String>,
+
+    /// How effective the replay filter was. All zero if the filter is not enabled.
+    pub(crate) replay_filter_stats: ReplayFilterStats,
+}
+
+/// Outcome of checking the reports of a request against the replay filter.
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct ReplayFilterStats {
+    /// Reports that were not in the filter and were therefore not checked against storage.
+    pub(crate) negative: u64,
+
+    /// Reports that were in the filter and had been processed.
+    pub(crate) true_positive: u64,
+
+    /// Reports that were in the filter but had not been processed.
+    pub(crate) false_positive: u64,
+}
+
+/// Upper bound on the size of a replay filter, in bits (i.e., 1 MiB).
+pub(crate) const MAX_REPLAY_FILTER_BITS: u64 = 1 << 23;
+
+/// Number of 64-bit words of a replay filter stored under each key (i.e., 8 KiB).
+pub(crate) const REPLAY_FILTER_CHUNK_WORDS: usize = 1024;
+
+/// A Bloom filter over hex-encoded report IDs.
+pub(crate) struct ReplayFilter {
+    bits: Vec<u64>,
+
+    /// Size of the filter in bits.
+    pub(crate) num_bits: u64,
+    num_hashes: u64,
+
+    /// Indices of the chunks modified since they were last taken.
+    dirty: BTreeSet<usize>,
+}
+
+impl ReplayFilter {
+    /// Create an empty filter sized for the given number of reports and false-positive rate. The
+    /// size is capped at [`MAX_REPLAY_FILTER_BITS`], in which case the false-positive rate may be
+    /// higher than configured.
+    pub(crate) fn new(config: &DapReplayFilterConfig) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let report_count = config.expected_report_count.max(1) as f64;
+        let num_bits = (-report_count * config.false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .clamp(64.0, MAX_REPLAY_FILTER_BITS as f64) as u64;
+        let num_hashes = (num_bits as f64 / report_count * ln2)
+            .round()
+            .clamp(1.0, 32.0) as u64;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            dirty: BTreeSet::new(),
+        }
+    }
+
+    // Report IDs are chosen by Clients, so they are hashed before indexing the filter.
+    fn indices(&self, report_id_hex: &str) -> impl Iterator<Item = u64> {
+        let hash = digest(&SHA256, report_id_hex.as_bytes());
+        let (h1, h2) = hash.as_ref().split_at(8);
+        let h1 = u64::from_le_bytes(h1.try_into().unwrap());
+        let h2 = u64::from_le_bytes(h2[..8].try_into().unwrap()) | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub(crate) fn insert(&mut self, report_id_hex: &str) {
+        for index in self.indices(report_id_hex).collect::<Vec<_>>() {
+            let word = (index / 64) as usize;
+            let bit = 1 << (index % 64);
+            if self.bits[word] & bit == 0 {
+                self.bits[word] |= bit;
+                self.dirty.insert(word / REPLAY_FILTER_CHUNK_WORDS);
+            }
+        }
+    }
+
+    /// Returns `false` if the report ID was definitely not inserted.
+    pub(crate) fn contains(&self, report_id_hex: &str) -> bool {
+        self.indices(report_id_hex)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    /// Return the hex-encoded chunks modified since the last call, along with their indices.
+    pub(crate) fn take_dirty_chunks(&mut self) -> Vec<(usize, String)> {
+        std::mem::take(&mut self.dirty)
+            .into_iter()
+            .map(|index| {
+                let start = index * REPLAY_FILTER_CHUNK_WORDS;
+                let end = min(start + REPLAY_FILTER_CHUNK_WORDS, self.bits.len());
+                let bytes: Vec<u8> = self.bits[start..end]
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .collect();
+                (index, hex::encode(bytes))
+            })
+            .collect()
+    }
+
+    /// Set a chunk returned by [`Self::take_dirty_chunks`] for a filter with the same
+    /// configuration.
+    pub(crate) fn load_chunk(&mut self, index: usize, chunk: &str) -> Result<()> {
+        let bytes = hex::decode(chunk)
+            .map_err(|e| int_err(format!("replay filter chunk is not hex-encoded: {e}")))?;
+        let start = index.saturating_mul(REPLAY_FILTER_CHUNK_WORDS);
+        let end = min(
+            start.saturating_add(REPLAY_FILTER_CHUNK_WORDS),
+            self.bits.len(),
+        );
+        if start >= end || bytes.len() != (end - start) * 8 {
+            return Err(int_err(format!(
+                "replay filter chunk {index} does not match the size of the filter"
+            )));
+        }
+        for (word, bytes) in self.bits[start..end].iter_mut().zip(bytes.chunks(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        Ok(())
+    }
+}
+
+/// Maximum number of report IDs purged by each request.
+const MAX_PURGED_REPORTS_PER_REQUEST: usize = 1024;
+
+/// Key under which a report is indexed by the end of its replay window.
+pub(crate) fn expiry_key(replay_window_end: Time, report_id_hex: &str) -> String {
+    format!("expiry/{replay_window_end:020}/{report_id_hex}")
+}
+
+/// Upper bound (exclusive) of the keys of the reports whose replay window has ended at time `now`.
+pub(crate) fn expiry_key_bound(now: Time) -> String {
+    format!("expiry/{:020}", now.saturating_add(1))
+}
+
+/// Number of report IDs stored in each chunk of compacted state. Each report ID is encoded with
+/// [`REPORT_ID_HEX_LEN`] characters, so a chunk is well under the maximum size of a value in DO
+/// storage.
+pub(crate) const COMPACTED_CHUNK_LEN: usize = 2048;
+
+const REPORT_ID_HEX_LEN: usize = 32;
+
+/// Size of the replay-protection state of an instance of ReportsProcessed before and after it was
+/// compacted. The size in bytes is the total length of the keys and JSON-encoded values.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct CompactionStats {
+    pub(crate) compacted_at: Time,
+    pub(crate) report_count: usize,
+    pub(crate) before_keys: usize,
+    pub(crate) before_bytes: usize,
+    pub(crate) after_keys: usize,
+    pub(crate) after_bytes: usize,
+}
+
+/// Merge the report IDs marked as processed (`true`) or unmarked (`false`) since the last
+/// compaction into the compacted state.
+pub(crate) fn compact_report_ids(
+    compacted: &[String],
+    processed: &[(String, bool)],
+) -> Vec<String> {
+    let mut report_ids: BTreeSet<&str> = compacted
+        .iter()
+        .flat_map(|chunk| chunk_report_ids(chunk))
+        .collect();
+    for (report_id_hex, is_processed) in processed {
+        if *is_processed {
+            report_ids.insert(report_id_hex);
+        } else {
+            report_ids.remove(report_id_hex.as_str());
+        }
+    }
+    report_ids
+        .into_iter()
+        .collect::<Vec<_>>()
+        .chunks(COMPACTED_CHUNK_LEN)
+        .map(|chunk| chunk.concat())
+        .collect()
+}
+
+/// Check if the compacted state contains the report ID.
+pub(crate) fn compacted_contains(compacted: &[String], report_id_hex: &str) -> bool {
+    compacted.iter().any(|chunk| {
+        let (mut lo, mut hi) = (0, chunk.len() / REPORT_ID_HEX_LEN);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let start = mid * REPORT_ID_HEX_LEN;
+            match chunk[start..start + REPORT_ID_HEX_LEN].cmp(report_id_hex) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return true,
+            }
+        }
+        false
+    })
+}
+
+/// Size of a key/value pair in DO storage, i.e., the length of the key and JSON-encoded value.
+fn entry_size<T: Serialize>(key: &str, val: &T) -> usize {
+    key.len() + serde_json::to_string(val).map_or(0, |val| val.len())
+}
+
+fn chunk_report_ids(chunk: &str) -> impl Iterator<Item = &str> {
+    (0..chunk.len() / REPORT_ID_HEX_LEN)
+        .map(move |i| &chunk[i * REPORT_ID_HEX_LEN..(i + 1) * REPORT_ID_HEX_LEN])
 }
 
 impl ReportsProcessed {
-    /// Check if the report has been processed. If not, return None; otherwise, return the ID.
-    async fn to_checked(&self, report_id_hex: String) -> Result<Option<String>> {
+    /// Check if the report has been processed.
+    async fn is_processed(&self, compacted: &[String], report_id_hex: &str) -> Result<bool> {
         let key = format!("processed/{report_id_hex}");
-        let processed: bool = state_set_if_not_exists(&self.state, &key, &true)
-            .await?
-            .unwrap_or(false);
+        Ok(match state_get::<bool>(&self.state, &key).await? {
+            Some(processed) => processed,
+            None => compacted_contains(compacted, report_id_hex),
+        })
+    }
+
+    /// Check if the report has been processed. If not, mark it as processed and return None;
+    /// otherwise, return the ID. If `maybe_processed` is `false`, then the report is known not to
+    /// have been processed and storage is not checked. If `replay_window_end` is set, then the
+    /// report is indexed by it so that it can be purged once its replay window has ended.
+    async fn to_checked(
+        &self,
+        compacted: &[String],
+        report_id_hex: String,
+        maybe_processed: bool,
+        replay_window_end: Option<Time>,
+    ) -> Result<Option<String>> {
+        let processed = maybe_processed && self.is_processed(compacted, &report_id_hex).await?;
         if processed {
             Ok(Some(report_id_hex))
         } else {
+            if let Some(replay_window_end) = replay_window_end {
+                self.state
+                    .storage()
+                    .put(&expiry_key(replay_window_end, &report_id_hex), &true)
+                    .await?;
+            }
+            self.state
+                .storage()
+                .put(&format!("processed/{report_id_hex}"), &true)
+                .await?;
             Ok(None)
         }
     }
+
+    /// Delete the IDs of the reports whose replay window has ended at time `now`, at most
+    /// [`MAX_PURGED_REPORTS_PER_REQUEST`] of them. Returns the number of IDs purged.
+    async fn purge_expired(&self, now: Time) -> Result<usize> {
+        let bound = expiry_key_bound(now);
+        let iter = self
+            .state
+            .storage()
+            .list_with_options(
+                ListOptions::new()
+                    .prefix("expiry/")
+                    .end(&bound)
+                    .limit(MAX_PURGED_REPORTS_PER_REQUEST),
+            )
+            .await?
+            .keys();
+        let mut keys = Vec::new();
+        let mut js_key = iter.next()?;
+        while !js_key.done() {
+            let key = js_key
+                .value()
+                .as_string()
+                .ok_or_else(|| int_err("ReportsProcessed: expiry key is not a string"))?;
+            let report_id_hex = key
+                .rsplit('/')
+                .next()
+                .ok_or_else(|| int_err("ReportsProcessed: expiry key is improperly formatted"))?;
+            keys.push(format!("processed/{report_id_hex}"));
+            keys.push(key);
+            js_key = iter.next()?;
+        }
+        for keys in keys.chunks(MAX_DELETE_KEYS) {
+            self.state.storage().delete_multiple(keys.to_vec()).await?;
+        }
+        let purged = keys.len() / 2;
+        if purged > 0 {
+            trace!("ReportsProcessed: purged {purged} report IDs whose replay window has ended");
+        }
+        Ok(purged)
+    }
+
+    /// Load the replay filter from storage. If the filter has not been stored with the given
+    /// configuration, then it is rebuilt from the reports marked as processed and stored.
+    async fn load_replay_filter(
+        &self,
+        config: &DapReplayFilterConfig,
+        compacted: &[String],
+    ) -> Result<ReplayFilter> {
+        let mut replay_filter = ReplayFilter::new(config);
+        let stored_chunks: Vec<(String, String)> =
+            state_list(&self.state, "replay_filter/").await?;
+        let stored_config: Option<DapReplayFilterConfig> =
+            state_get(&self.state, "replay_filter_config").await?;
+        if stored_config.as_ref() == Some(config) {
+            for (key, chunk) in stored_chunks {
+                let index = key["replay_filter/".len()..].parse().map_err(|e| {
+                    int_err(format!(
+                        "replay filter chunk key is improperly formatted: {e}"
+                    ))
+                })?;
+                replay_filter.load_chunk(index, &chunk)?;
+            }
+            return Ok(replay_filter);
+        }
+
+        for report_id_hex in compacted.iter().flat_map(|chunk| chunk_report_ids(chunk)) {
+            replay_filter.insert(report_id_hex);
+        }
+        for (key, is_processed) in state_list::<bool>(&self.state, "processed/").await? {
+            if is_processed {
+                replay_filter.insert(&key["processed/".len()..]);
+            }
+        }
+
+        // Delete the configuration before the stale chunks and store it after the new ones, so
+        // that a partially stored filter is never loaded.
+        self.state.storage().delete("replay_filter_config").await?;
+        let stale_keys = stored_chunks
+            .into_iter()
+            .map(|(key, _chunk)| key)
+            .collect::<Vec<_>>();
+        for keys in stale_keys.chunks(MAX_DELETE_KEYS) {
+            self.state.storage().delete_multiple(keys.to_vec()).await?;
+        }
+        self.put_replay_filter_chunks(replay_filter.take_dirty_chunks())
+            .await?;
+        self.state
+            .storage()
+            .put("replay_filter_config", config)
+            .await?;
+        info!(
+            num_bits = replay_filter.num_bits,
+            "rebuilt replay filter of ReportsProcessed"
+        );
+        Ok(replay_filter)
+    }
+
+    async fn put_replay_filter_chunks(&self, chunks: Vec<(usize, String)>) -> Result<()> {
+        for (index, chunk) in chunks {
+            self.state
+                .storage()
+                .put(&format!("replay_filter/{index}"), &chunk)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Time after which the instance is deleted.
+    fn lifetime(&self) -> Duration {
+        Duration::from_secs(self.config.global.report_storage_epoch_duration)
+            .saturating_add(self.config.processed_alarm_safety_interval)
+    }
+
+    /// Time after which the instance is compacted, if compaction is enabled.
+    fn compaction_delay(&self) -> Option<Duration> {
+        self.config
+            .processed_compaction_delay
+            .filter(|delay| *delay < self.lifetime())
+    }
+
+    /// Get the chunks of compacted state, ordered by index. If the instance has not been
+    /// compacted, then the result is empty.
+    async fn get_compacted(&self) -> Result<Vec<String>> {
+        if state_get::<CompactionStats>(&self.state, "compaction")
+            .await?
+            .is_none()
+        {
+            return Ok(Vec::new());
+        }
+        let mut chunks: Vec<(usize, String)> = state_list(&self.state, "compacted/")
+            .await?
+            .into_iter()
+            .map(|(key, chunk)| {
+                key["compacted/".len()..]
+                    .parse()
+                    .map(|index| (index, chunk))
+                    .map_err(|e| {
+                        int_err(format!("compacted chunk key is improperly formatted: {e}"))
+                    })
+            })
+            .collect::<Result<_>>()?;
+        chunks.sort_by_key(|(index, _chunk)| *index);
+        Ok(chunks.into_iter().map(|(_index, chunk)| chunk).collect())
+    }
+
+    /// Rewrite the replay-protection state into its compacted form.
+    async fn compact(&self) -> Result<()> {
+        let compacted = self.get_compacted().await?;
+        let processed: Vec<(String, bool)> = state_list(&self.state, "processed/").await?;
+
+        let before_keys = processed.len() + compacted.len();
+        let before_bytes = processed
+            .iter()
+            .map(|(key, is_processed)| entry_size(key, is_processed))
+            .chain(
+                compacted
+                    .iter()
+                    .enumerate()
+                    .map(|(index, chunk)| entry_size(&format!("compacted/{index}"), chunk)),
+            )
+            .sum();
+
+        let processed: Vec<(String, bool)> = processed
+            .into_iter()
+            .map(|(key, is_processed)| (key["processed/".len()..].to_string(), is_processed))
+            .collect();
+        let chunks = compact_report_ids(&compacted, &processed);
+        let stats = CompactionStats {
+            compacted_at: now(),
+            report_count: chunks
+                .iter()
+                .map(|chunk| chunk.len() / REPORT_ID_HEX_LEN)
+                .sum(),
+            before_keys,
+            before_bytes,
+            after_keys: chunks.len(),
+            after_bytes: chunks
+                .iter()
+                .enumerate()
+                .map(|(index, chunk)| entry_size(&format!("compacted/{index}"), chunk))
+                .sum(),
+        };
+
+        // Write the new chunks before deleting the old state so that no report is ever forgotten.
+        // Chunks from a previous compaction that are not overwritten are deleted.
+        for (index, chunk) in chunks.iter().enumerate() {
+            self.state
+                .storage()
+                .put(&format!("compacted/{index}"), chunk)
+                .await?;
+        }
+        self.state.storage().put("compaction", &stats).await?;
+        let stale_keys = processed
+            .iter()
+            .map(|(report_id_hex, _is_processed)| format!("processed/{report_id_hex}"))
+            .chain((chunks.len()..compacted.len()).map(|index| format!("compacted/{index}")))
+            .collect::<Vec<_>>();
+        for keys in stale_keys.chunks(MAX_DELETE_KEYS) {
+            self.state.storage().delete_multiple(keys.to_vec()).await?;
+        }
+
+        info!(
+            report_count = stats.report_count,
+            before_keys = stats.before_keys,
+            before_bytes = stats.before_bytes,
+            after_keys = stats.after_keys,
+            after_bytes = stats.after_bytes,
+            "compacted ReportsProcessed"
+        );
+        Ok(())
+    }
 }
 
 #[durable_object]
@@ -64,6 +602,8 @@ impl DurableObject for ReportsProcessed {
             config,
             touched: false,
             alarmed: false,
+            replay_filter: None,
+            replay_filter_discarded: false,
         }
     }
 
@@ -72,26 +612,128 @@ impl DurableObject for ReportsProcessed {
         ensure_garbage_collected!(req, self, id_hex.clone(), BINDING_DAP_REPORTS_PROCESSED);
         ensure_alarmed!(
             self,
-            Duration::from_secs(self.config.global.report_storage_epoch_duration)
-                .saturating_add(self.config.processed_alarm_safety_interval)
+            self.compaction_delay().unwrap_or_else(|| self.lifetime())
         );
 
         match (req.path().as_ref(), req.method()) {
             // Mark a set of reports as aggregated. Return the set of report IDs that already
             // exist.
             //
-            // Input: `report_id_hex_set: Vec<String>` (hex-encoded report IDs)
-            // Output: `Vec<String>` (subset of the inputs that already exist).
+            // Input: `ReportsProcessedMarkReq`
+            // Output: `ReportsProcessedMarkResp`
             (DURABLE_REPORTS_PROCESSED_MARK_AGGREGATED, Method::Post) => {
-                let report_id_hex_set: Vec<String> = req.json().await?;
+                let mark_req: ReportsProcessedMarkReq = req.json().await?;
+                self.purge_expired(mark_req.now).await?;
+                let compacted = self.get_compacted().await?;
+                match (mark_req.replay_filter, &self.replay_filter) {
+                    (Some(config), None) => {
+                        self.replay_filter =
+                            Some(self.load_replay_filter(&config, &compacted).await?);
+                        self.replay_filter_discarded = false;
+                    }
+                    (None, _) if !self.replay_filter_discarded => {
+                        // The reports marked by this request are not inserted into the filter, so
+                        // the stored filter would miss them.
+                        self.replay_filter = None;
+                        self.state.storage().delete("replay_filter_config").await?;
+                        self.replay_filter_discarded = true;
+                    }
+                    _ => (),
+                }
+
+                // Check each report against the replay filter, if enabled. Each report is
+                // inserted as it is checked, so that a report that appears twice in the request
+                // is checked against storage the second time.
+                let checks: Vec<(String, bool)> = mark_req
+                    .report_id_hex_set
+                    .into_iter()
+                    .map(|report_id_hex| match self.replay_filter {
+                        Some(ref mut replay_filter) => {
+                            let maybe_processed = replay_filter.contains(&report_id_hex);
+                            replay_filter.insert(&report_id_hex);
+                            (report_id_hex, maybe_processed)
+                        }
+                        None => (report_id_hex, true),
+                    })
+                    .collect();
+
+                // Store the filter before marking any report, so that the stored filter never
+                // misses a processed report. If this fails, then the filter in memory is ahead of
+                // storage, so it is loaded again by the next request.
+                if let Some(ref mut replay_filter) = self.replay_filter {
+                    let chunks = replay_filter.take_dirty_chunks();
+                    if let Err(e) = self.put_replay_filter_chunks(chunks).await {
+                        self.replay_filter = None;
+                        return Err(e);
+                    }
+                }
+
+                let mut resp = ReportsProcessedMarkResp::default();
                 let mut requests = Vec::new();
-                for report_id_hex in report_id_hex_set.into_iter() {
-                    requests.push(self.to_checked(report_id_hex));
+                for (index, (report_id_hex, maybe_processed)) in checks.iter().enumerate() {
+                    if self.replay_filter.is_some() && !maybe_processed {
+                        resp.replay_filter_stats.negative += 1;
+                    }
+                    requests.push(self.to_checked(
+                        &compacted,
+                        report_id_hex.clone(),
+                        *maybe_processed,
+                        mark_req.replay_window_ends.get(index).copied(),
+                    ));
                 }
 
                 let responses: Vec<Option<String>> = try_join_all(requests).await?;
-                let res: Vec<String> = responses.into_iter().flatten().collect();
-                Response::from_json(&res)
+                for ((_report_id_hex, maybe_processed), response) in
+                    checks.into_iter().zip(responses)
+                {
+                    if self.replay_filter.is_some() && maybe_processed {
+                        if response.is_some() {
+                            resp.replay_filter_stats.true_positive += 1;
+                        } else {
+                            resp.replay_filter_stats.false_positive += 1;
+                        }
+                    }
+                    resp.processed.extend(response);
+                }
+                Response::from_json(&resp)
+            }
+
+            // Unmark a set of reports as aggregated. Reports in the compacted state are
+            // tombstoned.
+            //
+            // Input: `report_id_hex_set: Vec<String>` (hex-encoded report IDs)
+            (DURABLE_REPORTS_PROCESSED_UNMARK_AGGREGATED, Method::Post) => {
+                let report_id_hex_set: Vec<String> = req.json().await?;
+                let compacted = self.get_compacted().await?;
+                let mut keys = Vec::new();
+                for report_id_hex in report_id_hex_set.into_iter() {
+                    let key = format!("processed/{report_id_hex}");
+                    if compacted_contains(&compacted, &report_id_hex) {
+                        self.state.storage().put(&key, &false).await?;
+                    } else {
+                        keys.push(key);
+                    }
+                }
+                self.state.storage().delete_multiple(keys).await?;
+                Response::from_json(&())
+            }
+
+            // Return the subset of a set of reports that have been aggregated. No report is
+            // marked.
+            //
+            // Input: `ReportsProcessedCheckReq`
+            // Output: `Vec<String>` (subset of the inputs that have been aggregated)
+            (DURABLE_REPORTS_PROCESSED_CHECK_AGGREGATED, Method::Post) => {
+                let check_req: ReportsProcessedCheckReq = req.json().await?;
+                self.purge_expired(check_req.now).await?;
+                let compacted = self.get_compacted().await?;
+                let mut processed = Vec::new();
+                for report_id_hex in check_req.report_id_hex_set.into_iter() {
+                    if self.is_processed(&compacted, &report_id_hex).await? {
+                        processed.push(report_id_hex);
+                    }
+                }
+                Response::from_json(&processed)
             }
 
             _ => Err(int_err(format!(
@@ -103,9 +745,26 @@ impl DurableObject for ReportsProcessed {
     }
 
     async fn alarm(&mut self) -> Result<Response> {
+        // The first alarm compacts the instance and schedules the second, which deletes it.
+        if let Some(delay) = self.compaction_delay() {
+            if state_get::<CompactionStats>(&self.state, "compaction")
+                .await?
+                .is_none()
+            {
+                self.compact().await?;
+                self.state
+                    .storage()
+                    .set_alarm(self.lifetime().saturating_sub(delay))
+                    .await?;
+                return Response::from_json(&());
+            }
+        }
+
         self.state.storage().delete_all().await?;
         self.alarmed = false;
         self.touched = false;
+        self.replay_filter = None;
+        self.replay_filter_discarded = false;
         Response::from_json(&())
     }
 }
@@ -0,0 +1,149 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::durable::reports_processed::{
+    compact_report_ids, compacted_contains, expiry_key, expiry_key_bound, ReplayFilter,
+    COMPACTED_CHUNK_LEN, MAX_REPLAY_FILTER_BITS, REPLAY_FILTER_CHUNK_WORDS,
+};
+use daphne::{messages::ReportId, DapReplayFilterConfig};
+use rand::prelude::*;
+
+fn report_id_hex() -> String {
+    ReportId(thread_rng().gen()).to_hex()
+}
+
+#[test]
+fn compact() {
+    let report_ids: Vec<String> = (0..COMPACTED_CHUNK_LEN + 1)
+        .map(|_| report_id_hex())
+        .collect();
+    let processed: Vec<(String, bool)> = report_ids
+        .iter()
+        .map(|report_id_hex| (report_id_hex.clone(), true))
+        .collect();
+
+    let compacted = compact_report_ids(&[], &processed);
+    assert_eq!(compacted.len(), 2);
+    for report_id_hex in &report_ids {
+        assert!(compacted_contains(&compacted, report_id_hex));
+    }
+    assert!(!compacted_contains(&compacted, &report_id_hex()));
+    assert!(!compacted_contains(&[], &report_ids[0]));
+}
+
+#[test]
+fn compact_with_tombstones() {
+    let (marked, unmarked, added) = (report_id_hex(), report_id_hex(), report_id_hex());
+    let compacted = compact_report_ids(&[], &[(marked.clone(), true), (unmarked.clone(), true)]);
+
+    // Reports unmarked since the last compaction are dropped and new reports are added.
+    let compacted = compact_report_ids(
+        &compacted,
+        &[(unmarked.clone(), false), (added.clone(), true)],
+    );
+    assert!(compacted_contains(&compacted, &marked));
+    assert!(!compacted_contains(&compacted, &unmarked));
+    assert!(compacted_contains(&compacted, &added));
+}
+
+#[test]
+fn expiry_key_ordering() {
+    let report_id_hex = report_id_hex();
+    let now = 1_000_000;
+
+    // Reports whose replay window has ended sort before the bound; the others sort after it.
+    let bound = expiry_key_bound(now);
+    assert!(expiry_key(0, &report_id_hex) < bound);
+    assert!(expiry_key(now - 1, &report_id_hex) < bound);
+    assert!(expiry_key(now, &report_id_hex) < bound);
+    assert!(expiry_key(now + 1, &report_id_hex) > bound);
+    assert!(expiry_key(10 * now, &report_id_hex) > bound);
+}
+
+#[test]
+fn replay_filter() {
+    let mut replay_filter = ReplayFilter::new(&DapReplayFilterConfig {
+        expected_report_count: 1000,
+        false_positive_rate: 0.01,
+    });
+    let inserted: Vec<String> = (0..1000).map(|_| report_id_hex()).collect();
+    for report_id_hex in &inserted {
+        replay_filter.insert(report_id_hex);
+    }
+
+    // No false negatives.
+    for report_id_hex in &inserted {
+        assert!(replay_filter.contains(report_id_hex));
+    }
+
+    // The false-positive rate is roughly as configured.
+    let false_positives = (0..10_000)
+        .filter(|_| replay_filter.contains(&report_id_hex()))
+        .count();
+    assert!(false_positives < 300, "{false_positives} false positives");
+}
+
+#[test]
+fn replay_filter_chunks() {
+    let config = DapReplayFilterConfig {
+        expected_report_count: 100_000,
+        false_positive_rate: 0.01,
+    };
+    let mut replay_filter = ReplayFilter::new(&config);
+    let num_chunks =
+        (replay_filter.num_bits.div_ceil(64) as usize).div_ceil(REPLAY_FILTER_CHUNK_WORDS);
+    assert!(num_chunks > 1);
+    assert!(replay_filter.take_dirty_chunks().is_empty());
+
+    // Inserting a report modifies at most one chunk per hash.
+    let inserted: Vec<String> = (0..1000).map(|_| report_id_hex()).collect();
+    replay_filter.insert(&inserted[0]);
+    let mut chunks = replay_filter.take_dirty_chunks();
+    assert!(!chunks.is_empty() && chunks.len() <= 7);
+    assert!(replay_filter.take_dirty_chunks().is_empty());
+
+    // Inserting the same report again modifies nothing.
+    replay_filter.insert(&inserted[0]);
+    assert!(replay_filter.take_dirty_chunks().is_empty());
+
+    // The filter loaded from its chunks contains the same reports. Later chunks overwrite
+    // earlier ones.
+    for report_id_hex in &inserted[1..] {
+        replay_filter.insert(report_id_hex);
+    }
+    chunks.extend(replay_filter.take_dirty_chunks());
+    assert!(chunks.len() <= num_chunks + 7);
+    let mut loaded = ReplayFilter::new(&config);
+    for (index, chunk) in &chunks {
+        loaded.load_chunk(*index, chunk).unwrap();
+    }
+    for report_id_hex in &inserted {
+        assert!(loaded.contains(report_id_hex));
+    }
+    assert!(loaded.take_dirty_chunks().is_empty());
+
+    // A chunk from a filter with a different configuration is rejected.
+    let (index, chunk) = &chunks[0];
+    let mut other = ReplayFilter::new(&DapReplayFilterConfig {
+        expected_report_count: 1000,
+        false_positive_rate: 0.01,
+    });
+    assert!(other.load_chunk(*index, chunk).is_err());
+    assert!(loaded.load_chunk(num_chunks, chunk).is_err());
+}
+
+#[test]
+fn replay_filter_size() {
+    // About 9.6 bits per report for a 1% false-positive rate.
+    let replay_filter = ReplayFilter::new(&DapReplayFilterConfig {
+        expected_report_count: 1000,
+        false_positive_rate: 0.01,
+    });
+    assert_eq!(replay_filter.num_bits, 9586);
+
+    let replay_filter = ReplayFilter::new(&DapReplayFilterConfig {
+        expected_report_count: 1_000_000_000,
+        false_positive_rate: 0.01,
+    });
+    assert_eq!(replay_filter.num_bits, MAX_REPLAY_FILTER_BITS);
+}
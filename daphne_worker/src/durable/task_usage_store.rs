@@ -0,0 +1,129 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    config::DaphneWorkerConfig,
+    durable::{state_get_or_default, BINDING_DAP_TASK_USAGE_STORE},
+    initialize_tracing, int_err,
+};
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+pub(crate) const DURABLE_TASK_USAGE_STORE_ADD: &str = "/internal/do/task_usage_store/add";
+pub(crate) const DURABLE_TASK_USAGE_STORE_GET: &str = "/internal/do/task_usage_store/get";
+
+/// Usage of a task, as counted for billing.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct TaskUsage {
+    /// Number of reports accepted by the Leader for upload.
+    pub(crate) uploads_accepted: u64,
+
+    /// Number of reports aggregated.
+    pub(crate) reports_aggregated: u64,
+
+    /// Number of batches collected.
+    pub(crate) collections_served: u64,
+
+    /// Number of bytes of reports accepted by the Leader for storage.
+    pub(crate) bytes_stored: u64,
+}
+
+impl TaskUsage {
+    /// Add the counts of `other` to this usage.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.uploads_accepted = self.uploads_accepted.saturating_add(other.uploads_accepted);
+        self.reports_aggregated = self
+            .reports_aggregated
+            .saturating_add(other.reports_aggregated);
+        self.collections_served = self
+            .collections_served
+            .saturating_add(other.collections_served);
+        self.bytes_stored = self.bytes_stored.saturating_add(other.bytes_stored);
+    }
+
+    /// Compute the usage that was added to `prev` in order to get this usage.
+    pub(crate) fn delta_since(&self, prev: &Self) -> Self {
+        Self {
+            uploads_accepted: self.uploads_accepted.saturating_sub(prev.uploads_accepted),
+            reports_aggregated: self
+                .reports_aggregated
+                .saturating_sub(prev.reports_aggregated),
+            collections_served: self
+                .collections_served
+                .saturating_sub(prev.collections_served),
+            bytes_stored: self.bytes_stored.saturating_sub(prev.bytes_stored),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Durable Object (DO) for counting the usage of a task during one day.
+///
+/// This object defines the following API endpoints:
+///
+/// - `DURABLE_TASK_USAGE_STORE_ADD`: Add the given counts to the stored usage.
+/// - `DURABLE_TASK_USAGE_STORE_GET`: Return the stored usage.
+///
+/// The schema for the data stored by this DO is as follows:
+///
+/// ```text
+/// [Usage] usage -> TaskUsage
+/// ```
+#[durable_object]
+pub struct TaskUsageStore {
+    #[allow(dead_code)]
+    state: State,
+    env: Env,
+    config: DaphneWorkerConfig,
+    touched: bool,
+}
+
+#[durable_object]
+impl DurableObject for TaskUsageStore {
+    fn new(state: State, env: Env) -> Self {
+        initialize_tracing(&env);
+        let config =
+            DaphneWorkerConfig::from_worker_env(&env).expect("failed to load configuration");
+        Self {
+            state,
+            env,
+            config,
+            touched: false,
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let id_hex = self.state.id().to_string();
+        ensure_garbage_collected!(req, self, id_hex, BINDING_DAP_TASK_USAGE_STORE);
+
+        match (req.path().as_ref(), req.method()) {
+            // Add to the stored usage.
+            //
+            // Input: `TaskUsage`
+            (DURABLE_TASK_USAGE_STORE_ADD, Method::Post) => {
+                let usage_delta: TaskUsage = req.json().await?;
+                let mut usage: TaskUsage = state_get_or_default(&self.state, "usage").await?;
+                usage.merge(&usage_delta);
+                self.state.storage().put("usage", usage).await?;
+                Response::from_json(&())
+            }
+
+            // Get the stored usage.
+            //
+            // Output: `TaskUsage`
+            (DURABLE_TASK_USAGE_STORE_GET, Method::Get) => {
+                let usage: TaskUsage = state_get_or_default(&self.state, "usage").await?;
+                Response::from_json(&usage)
+            }
+
+            _ => Err(int_err(format!(
+                "TaskUsageStore: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+}
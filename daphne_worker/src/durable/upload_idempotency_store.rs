@@ -0,0 +1,128 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    durable::{durable_name_task, state_get},
+    initialize_tracing, int_err, now,
+};
+use daphne::{messages::Time, DapUploadRecord, DapVersion};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::trace;
+use worker::*;
+
+pub(crate) fn durable_name_upload_idempotency_key(
+    version: &DapVersion,
+    task_id_hex: &str,
+    idempotency_key: &str,
+) -> String {
+    format!(
+        "{}/upload/{}",
+        durable_name_task(version, task_id_hex),
+        idempotency_key
+    )
+}
+
+pub(crate) const DURABLE_UPLOAD_IDEMPOTENCY_STORE_GET: &str =
+    "/internal/do/upload_idempotency_store/get";
+pub(crate) const DURABLE_UPLOAD_IDEMPOTENCY_STORE_PUT: &str =
+    "/internal/do/upload_idempotency_store/put";
+
+/// Request to record the outcome of an upload request.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct PutUploadRecordRequest {
+    pub(crate) record: DapUploadRecord,
+
+    /// Time after which the record is deleted.
+    pub(crate) expiration: Time,
+}
+
+/// Durable Object (DO) for storing the outcome of the upload request that the Leader handled under
+/// a given idempotency key (see [`DapRequest::idempotency_key`](daphne::DapRequest)). Each
+/// instance is named by the task and the key.
+///
+/// This object implements the following API endpoints:
+///
+/// - `DURABLE_UPLOAD_IDEMPOTENCY_STORE_GET`: Returns the record, unless it has expired.
+/// - `DURABLE_UPLOAD_IDEMPOTENCY_STORE_PUT`: Stores the record. If one is stored already, then the
+///   outcomes missing from it are filled in and the record as it was before is returned. An alarm
+///   is set to delete the instance once the record expires.
+///
+/// The record is stored in `record` and its expiration time in `expiration`.
+#[durable_object]
+pub struct UploadIdempotencyStore {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for UploadIdempotencyStore {
+    fn new(state: State, env: Env) -> Self {
+        initialize_tracing(&env);
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        match (req.path().as_ref(), req.method()) {
+            // Get the record.
+            //
+            // Output: `Option<DapUploadRecord>`
+            (DURABLE_UPLOAD_IDEMPOTENCY_STORE_GET, Method::Get) => {
+                Response::from_json(&self.get_record().await?)
+            }
+
+            // Store the record, or fill in the outcomes missing from the record stored already.
+            //
+            // Input: `PutUploadRecordRequest`
+            // Output: `Option<DapUploadRecord>` (the record stored already, if any)
+            (DURABLE_UPLOAD_IDEMPOTENCY_STORE_PUT, Method::Post) => {
+                let put_req: PutUploadRecordRequest = req.json().await?;
+                if let Some(stored_record) = self.get_record().await? {
+                    let mut record = stored_record.clone();
+                    record.merge(&put_req.record);
+                    self.state.storage().put("record", record).await?;
+                    return Response::from_json(&Some(stored_record));
+                }
+
+                self.state.storage().put("record", put_req.record).await?;
+                self.state
+                    .storage()
+                    .put("expiration", put_req.expiration)
+                    .await?;
+                self.state
+                    .storage()
+                    .set_alarm(Duration::from_secs(
+                        put_req.expiration.saturating_sub(now()),
+                    ))
+                    .await?;
+                Response::from_json(&None::<DapUploadRecord>)
+            }
+
+            _ => Err(int_err(format!(
+                "UploadIdempotencyStore: unexpected request: method={:?}; path={:?}",
+                req.method(),
+                req.path()
+            ))),
+        }
+    }
+
+    async fn alarm(&mut self) -> Result<Response> {
+        self.state.storage().delete_all().await?;
+        trace!(
+            "UploadIdempotencyStore: deleted instance {}",
+            self.state.id().to_string()
+        );
+        Response::from_json(&())
+    }
+}
+
+impl UploadIdempotencyStore {
+    /// Get the record, unless it has expired. The alarm that deletes an expired record may not
+    /// have fired yet.
+    async fn get_record(&self) -> Result<Option<DapUploadRecord>> {
+        let expiration: Option<Time> = state_get(&self.state, "expiration").await?;
+        if expiration.map_or(true, |expiration| now() >= expiration) {
+            return Ok(None);
+        }
+        state_get(&self.state, "record").await
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Batch lifecycle events.
+//!
+//! When `DAP_BATCH_EVENTS_QUEUE` is set, each [`DapBatchEvent`] emitted by the Aggregator is sent
+//! to the Workers queue bound to that name, from which a consumer Worker can forward it to
+//! downstream systems. Otherwise events are logged as JSON by [`DapLogEventSink`].
+
+use crate::{config::DaphneWorker, dap_err};
+use async_trait::async_trait;
+use daphne::{
+    events::{DapBatchEvent, DapEventSink, DapLogEventSink},
+    DapError,
+};
+use worker::Queue;
+
+/// Event sink that sends each event to a Workers queue.
+pub(crate) struct DaphneWorkerQueueEventSink {
+    queue: Queue,
+}
+
+impl DaphneWorkerQueueEventSink {
+    pub(crate) fn new(queue: Queue) -> Self {
+        Self { queue }
+    }
+}
+
+#[async_trait(?Send)]
+impl DapEventSink for DaphneWorkerQueueEventSink {
+    async fn emit(&self, event: &DapBatchEvent) -> Result<(), DapError> {
+        self.queue
+            .send(event)
+            .await
+            .map_err(|e| DapError::Fatal(format!("failed to send batch event to queue: {e}")))
+    }
+}
+
+#[async_trait(?Send)]
+impl DapEventSink for DaphneWorker<'_> {
+    async fn emit(&self, event: &DapBatchEvent) -> Result<(), DapError> {
+        let result = match self.config().batch_events_queue {
+            Some(ref binding) => {
+                DaphneWorkerQueueEventSink::new(self.queue(binding).map_err(dap_err)?)
+                    .emit(event)
+                    .await
+            }
+            None => DapLogEventSink.emit(event).await,
+        };
+
+        let status = if result.is_ok() { "emitted" } else { "failed" };
+        self.state
+            .metrics
+            .batch_event_counter
+            .with_label_values(&[&self.state.host, status])
+            .inc();
+        result
+    }
+}
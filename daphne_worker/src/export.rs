@@ -0,0 +1,199 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Export of collection results to the operator.
+//!
+//! When `DAP_COLLECTION_EXPORT_URL` is set, the Leader decrypts the result of each completed
+//! collect job and POSTs it to the URL as a [`CollectionExport`]. This requires the Leader to hold
+//! the Collector's HPKE receiver config (`DAP_COLLECTION_EXPORT_HPKE_RECEIVER_CONFIG`); jobs whose
+//! aggregate shares are encrypted under a different config are not exported. If
+//! `DAP_COLLECTION_EXPORT_SIGNING_KEY` is set, then the body is authenticated with HMAC-SHA256
+//! and the hex-encoded tag is sent in the [`COLLECTION_EXPORT_SIGNATURE_HEADER`] header.
+
+use crate::{config::DaphneWorker, int_err};
+use daphne::{
+    hpke::HpkeReceiverConfig,
+    messages::{BatchSelector, Collection, CollectionJobId, Interval, TaskId},
+    DapAggregateResult, DapRetryConfig, DapTaskConfig, DapVersion,
+};
+use ring::hmac;
+use serde::Serialize;
+use tracing::{debug, info, warn};
+use url::Url;
+use worker::{Delay, Result};
+
+/// HTTP header carrying the signature of an exported collection.
+pub(crate) const COLLECTION_EXPORT_SIGNATURE_HEADER: &str = "x-daphne-signature";
+
+/// Parameters for exporting collection results.
+pub(crate) struct CollectionExportConfig {
+    /// URL to which each result is POSTed.
+    pub(crate) url: Url,
+
+    /// HPKE receiver config of the Collector, used to decrypt the aggregate shares.
+    pub(crate) hpke_receiver_config: HpkeReceiverConfig,
+
+    /// Optional: Key used to sign the body of each request. If not configured, then requests are
+    /// not signed.
+    pub(crate) signing_key: Option<hmac::Key>,
+
+    /// Optional: How to retry a request that fails with a transient error. If not configured,
+    /// then each request is sent once.
+    pub(crate) retry: Option<DapRetryConfig>,
+}
+
+/// The result of a completed collect job, as exported to the operator.
+#[derive(Debug, Serialize)]
+pub(crate) struct CollectionExport {
+    /// Always "collection_finished".
+    pub(crate) event: &'static str,
+
+    /// The task ID (base64url).
+    pub(crate) task_id: String,
+
+    /// The collection job ID (base64url).
+    pub(crate) collection_job_id: String,
+    pub(crate) version: DapVersion,
+    pub(crate) batch_selector: BatchSelector,
+    pub(crate) report_count: u64,
+    pub(crate) interval: Option<Interval>,
+    pub(crate) result: DapAggregateResult,
+}
+
+/// Compute the hex-encoded HMAC-SHA256 tag of the given request body.
+pub(crate) fn sign_collection_export(signing_key: &hmac::Key, body: &[u8]) -> String {
+    hex::encode(hmac::sign(signing_key, body))
+}
+
+impl DaphneWorker<'_> {
+    /// Export the result of a completed collect job, if configured.
+    pub(crate) async fn export_collection(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        collect_id: &CollectionJobId,
+        batch_sel: &BatchSelector,
+        collection: &Collection,
+    ) -> Result<()> {
+        let export_config = match self.config().collection_export {
+            Some(ref export_config) => export_config,
+            None => return Ok(()),
+        };
+
+        let config_id = export_config.hpke_receiver_config.config.id;
+        if collection
+            .encrypted_agg_shares
+            .iter()
+            .any(|encrypted_agg_share| encrypted_agg_share.config_id != config_id)
+        {
+            debug!("not exporting collect job {collect_id}: aggregate shares are not encrypted under HPKE config {config_id}");
+            return Ok(());
+        }
+
+        let result = task_config
+            .vdaf
+            .consume_encrypted_agg_shares(
+                &export_config.hpke_receiver_config,
+                task_id,
+                batch_sel,
+                collection.report_count,
+                collection.encrypted_agg_shares.clone(),
+                task_config.version,
+            )
+            .await
+            .map_err(|e| int_err(format!("failed to decrypt collection for export: {e}")))?;
+
+        let export = CollectionExport {
+            event: "collection_finished",
+            task_id: task_id.to_base64url(),
+            collection_job_id: collect_id.to_base64url(),
+            version: task_config.version,
+            batch_selector: batch_sel.clone(),
+            report_count: collection.report_count,
+            interval: collection.interval.clone(),
+            result,
+        };
+        let body = serde_json::to_vec(&export)?;
+
+        // If the request fails, then retry it with exponential backoff, if configured.
+        let retry = export_config.retry.as_ref();
+        let max_attempts = retry.map_or(1, |retry| retry.max_attempts.max(1));
+        let mut delay_ms = retry.map_or(0, |retry| retry.initial_delay_ms);
+        let mut attempt = 1;
+        let counter = &self.state.metrics.collection_export_counter;
+        loop {
+            match self.send_collection_export(export_config, &body).await {
+                Ok(()) => {
+                    counter
+                        .with_label_values(&[&self.state.host, "exported"])
+                        .inc();
+                    info!(
+                        task_id = export.task_id,
+                        collection_job_id = export.collection_job_id,
+                        "exported collection"
+                    );
+                    return Ok(());
+                }
+                Err(e) if attempt < max_attempts => {
+                    counter
+                        .with_label_values(&[&self.state.host, "retried"])
+                        .inc();
+                    warn!(
+                        "retrying export of collect job {collect_id} in {delay_ms}ms (attempt {attempt} of {max_attempts} failed): {e}"
+                    );
+                    Delay::from(std::time::Duration::from_millis(delay_ms)).await;
+                    delay_ms = delay_ms
+                        .saturating_mul(2)
+                        .min(retry.map_or(0, |retry| retry.max_delay_ms));
+                    attempt += 1;
+                }
+                Err(e) => {
+                    counter
+                        .with_label_values(&[&self.state.host, "failed"])
+                        .inc();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    async fn send_collection_export(
+        &self,
+        export_config: &CollectionExportConfig,
+        body: &[u8],
+    ) -> Result<()> {
+        let mut headers = reqwest_wasm::header::HeaderMap::new();
+        headers.insert(
+            reqwest_wasm::header::CONTENT_TYPE,
+            reqwest_wasm::header::HeaderValue::from_static("application/json"),
+        );
+        if let Some(ref signing_key) = export_config.signing_key {
+            headers.insert(
+                reqwest_wasm::header::HeaderName::from_static(COLLECTION_EXPORT_SIGNATURE_HEADER),
+                reqwest_wasm::header::HeaderValue::from_str(&sign_collection_export(
+                    signing_key,
+                    body,
+                ))
+                .map_err(int_err)?,
+            );
+        }
+
+        let reqwest_resp = self
+            .isolate_state()
+            .client
+            .post(export_config.url.as_str())
+            .body(body.to_vec())
+            .headers(headers)
+            .send()
+            .await
+            .map_err(|err| int_err(format!("request to export server failed: {err:?}")))?;
+
+        let status = reqwest_resp.status();
+        if !status.is_success() {
+            return Err(int_err(format!(
+                "collection export failed with response status {status}"
+            )));
+        }
+        Ok(())
+    }
+}
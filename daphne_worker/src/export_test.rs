@@ -0,0 +1,15 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::export::sign_collection_export;
+use ring::hmac;
+
+#[test]
+fn sign_collection_export_hmac_sha256() {
+    // RFC 4231, test case 2.
+    let signing_key = hmac::Key::new(hmac::HMAC_SHA256, b"Jefe");
+    assert_eq!(
+        sign_collection_export(&signing_key, b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
@@ -0,0 +1,186 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Health and readiness checks.
+//!
+//! `GET /healthz` checks that the Aggregator can reach its storage, i.e., KV and DOs.
+//! `GET /readyz` also checks that an HPKE config is available for the default DAP version and
+//! that the Aggregator is not too far behind: for the Leader, the depth of the aggregation job
+//! queue; for the Helper, the number of aggregation jobs that are running. Each check feeds the
+//! `health_check_status` and `health_check_latency_ms` gauges.
+
+use crate::{
+    config::DaphneWorker,
+    durable::{
+        durable_name_queue,
+        helper_agg_job_slots::{
+            DURABLE_HELPER_AGG_JOB_SLOTS_COUNT, DURABLE_NAME_HELPER_AGG_JOB_SLOTS,
+        },
+        leader_agg_job_queue::{
+            LeaderAggJobQueueGetReq, LeaderAggJobQueueGetResp, DURABLE_LEADER_AGG_JOB_QUEUE_GET,
+        },
+        BINDING_DAP_HELPER_AGG_JOB_SLOTS, BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+    },
+    internal_api::{HealthCheck, HealthReport, HealthStatus},
+};
+use worker::Date;
+
+pub(crate) const HEALTH_CHECK_KV: &str = "kv";
+pub(crate) const HEALTH_CHECK_DURABLE: &str = "durable_object";
+pub(crate) const HEALTH_CHECK_HPKE_CONFIG: &str = "hpke_config";
+pub(crate) const HEALTH_CHECK_QUEUE_DEPTH: &str = "queue_depth";
+
+/// Maximum number of aggregation jobs fetched to measure the depth of the Leader's queue if no
+/// threshold is configured. Deeper queues are reported as having this depth.
+const MAX_MEASURED_QUEUE_DEPTH: u64 = 1000;
+
+/// Build the result of a check. `value` is either the value that was checked, if any, or the
+/// reason the check could not be performed. If `max` is set, then the check fails if the value
+/// exceeds it.
+pub(crate) fn health_check(
+    name: &str,
+    latency_ms: u64,
+    value: Result<Option<u64>, String>,
+    max: Option<u64>,
+) -> HealthCheck {
+    let (status, value, detail) = match value {
+        Ok(Some(value)) if max.is_some_and(|max| value > max) => (
+            HealthStatus::Fail,
+            Some(value),
+            Some(format!("{value} exceeds the threshold of {}", max.unwrap())),
+        ),
+        Ok(value) => (HealthStatus::Pass, value, None),
+        Err(detail) => (HealthStatus::Fail, None, Some(detail)),
+    };
+    HealthCheck {
+        name: name.to_string(),
+        status,
+        latency_ms,
+        value,
+        detail,
+    }
+}
+
+impl DaphneWorker<'_> {
+    /// Check the dependencies of the Aggregator. If `ready` is set, then the checks for
+    /// `/readyz` are run in addition to those for `/healthz`.
+    pub(crate) async fn internal_health(&self, ready: bool) -> HealthReport {
+        let mut checks = Vec::with_capacity(4);
+
+        let start = Date::now().as_millis();
+        let res = self.get_global_config_override().await;
+        checks.push(health_check(
+            HEALTH_CHECK_KV,
+            Date::now().as_millis() - start,
+            res.map(|_| None).map_err(|e| e.to_string()),
+            None,
+        ));
+
+        // The depth of the queue is read from DO storage, so this request also measures its
+        // latency.
+        let start = Date::now().as_millis();
+        let queue_depth = self.queue_depth().await.map_err(|e| e.to_string());
+        let latency_ms = Date::now().as_millis() - start;
+        checks.push(health_check(
+            HEALTH_CHECK_DURABLE,
+            latency_ms,
+            queue_depth
+                .as_ref()
+                .map(|_| Some(latency_ms))
+                .map_err(Clone::clone),
+            self.config().health_max_durable_latency_ms,
+        ));
+
+        if ready {
+            let start = Date::now().as_millis();
+            let res = self.hpke_config_count().await.and_then(|count| {
+                if count == 0 {
+                    Err(format!(
+                        "no HPKE config for {}",
+                        self.config().default_version
+                    ))
+                } else {
+                    Ok(Some(count))
+                }
+            });
+            checks.push(health_check(
+                HEALTH_CHECK_HPKE_CONFIG,
+                Date::now().as_millis() - start,
+                res,
+                None,
+            ));
+
+            checks.push(health_check(
+                HEALTH_CHECK_QUEUE_DEPTH,
+                latency_ms,
+                queue_depth.map(Some),
+                self.config().health_max_queue_depth,
+            ));
+        }
+
+        let metrics = &self.state.metrics;
+        for check in &checks {
+            metrics
+                .health_check_status_gauge
+                .with_label_values(&[&self.state.host, &check.name])
+                .set(i64::from(check.status == HealthStatus::Pass));
+            metrics
+                .health_check_latency_gauge
+                .with_label_values(&[&self.state.host, &check.name])
+                .set(check.latency_ms.try_into().unwrap_or(i64::MAX));
+        }
+
+        HealthReport::new(checks)
+    }
+
+    /// Leader: the number of aggregation jobs in the queue, up to one more than the threshold.
+    /// Helper: the number of aggregation jobs that are running.
+    async fn queue_depth(&self) -> worker::Result<u64> {
+        if self.config().is_leader {
+            let max_agg_jobs = self
+                .config()
+                .health_max_queue_depth
+                .map_or(MAX_MEASURED_QUEUE_DEPTH, |max| max.saturating_add(1));
+            let agg_jobs: LeaderAggJobQueueGetResp = self
+                .durable()
+                .post(
+                    BINDING_DAP_LEADER_AGG_JOB_QUEUE,
+                    DURABLE_LEADER_AGG_JOB_QUEUE_GET,
+                    durable_name_queue(0),
+                    &LeaderAggJobQueueGetReq {
+                        max_agg_jobs: max_agg_jobs.try_into().unwrap_or(usize::MAX),
+                        after: None,
+                    },
+                )
+                .await?;
+            Ok(agg_jobs.len().try_into().unwrap())
+        } else {
+            self.durable()
+                .get(
+                    BINDING_DAP_HELPER_AGG_JOB_SLOTS,
+                    DURABLE_HELPER_AGG_JOB_SLOTS_COUNT,
+                    DURABLE_NAME_HELPER_AGG_JOB_SLOTS.to_string(),
+                )
+                .await
+        }
+    }
+
+    /// The number of HPKE configs available for the default DAP version. Unlike serving the HPKE
+    /// config to Clients, this does not generate a config if there is none.
+    async fn hpke_config_count(&self) -> Result<u64, String> {
+        let version = self.config().default_version;
+        let count = match self.state.hpke_provider {
+            Some(hpke_provider) => hpke_provider
+                .get_hpke_config_list(version)
+                .await
+                .map_err(|e| e.to_string())?
+                .len(),
+            None => self
+                .list_hpke_receiver_configs(version)
+                .await
+                .map_err(|e| e.to_string())?
+                .len(),
+        };
+        Ok(count.try_into().unwrap())
+    }
+}
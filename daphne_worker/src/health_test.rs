@@ -0,0 +1,36 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::{
+    health::{health_check, HEALTH_CHECK_KV, HEALTH_CHECK_QUEUE_DEPTH},
+    internal_api::{HealthReport, HealthStatus},
+};
+
+#[test]
+fn health_check_threshold() {
+    let check = health_check(HEALTH_CHECK_QUEUE_DEPTH, 5, Ok(Some(10)), None);
+    assert_eq!(check.status, HealthStatus::Pass);
+    assert_eq!(check.value, Some(10));
+
+    let check = health_check(HEALTH_CHECK_QUEUE_DEPTH, 5, Ok(Some(10)), Some(10));
+    assert_eq!(check.status, HealthStatus::Pass);
+
+    let check = health_check(HEALTH_CHECK_QUEUE_DEPTH, 5, Ok(Some(11)), Some(10));
+    assert_eq!(check.status, HealthStatus::Fail);
+    assert_eq!(check.value, Some(11));
+    assert!(check.detail.is_some());
+}
+
+#[test]
+fn health_report_status() {
+    let pass = health_check(HEALTH_CHECK_KV, 1, Ok(None), None);
+    let report = HealthReport::new(vec![pass.clone()]);
+    assert_eq!(report.status, HealthStatus::Pass);
+    assert_eq!(report.http_status(), 200);
+
+    let fail = health_check(HEALTH_CHECK_KV, 1, Err("unreachable".into()), None);
+    assert_eq!(fail.detail.as_deref(), Some("unreachable"));
+    let report = HealthReport::new(vec![pass, fail]);
+    assert_eq!(report.status, HealthStatus::Fail);
+    assert_eq!(report.http_status(), 503);
+}
@@ -0,0 +1,209 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Providers of HPKE decryption for Daphne-Worker.
+//!
+//! By default, HPKE receiver configs, including their secret keys, are stored in KV and
+//! decryption happens in the Worker. An external key management service (e.g., a KMS or HSM) can
+//! be used instead by setting [`DaphneWorkerRouter::hpke_provider`](crate::DaphneWorkerRouter).
+//! In that case the secret keys never leave the provider: the Worker only asks the provider for
+//! the HPKE configs to advertise and for the decryption of each ciphertext.
+
+use crate::{
+    config::{DaphneWorker, HpkeReceiverKvKey, KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG},
+    dap_err,
+};
+use async_trait::async_trait;
+use daphne::{
+    messages::{HpkeCiphertext, HpkeConfig, Time, TransitionFailure},
+    roles::DapAggregator,
+    DapError, DapVersion,
+};
+
+/// Provider of the HPKE configs advertised by the Aggregator and of decryption under the
+/// corresponding secret keys.
+#[async_trait(?Send)]
+pub trait DaphneWorkerHpkeProvider {
+    /// Get the list of HPKE configs to advertise for the given DAP version. The first config is
+    /// the one Clients should use. The list must not be empty.
+    async fn get_hpke_config_list(&self, version: DapVersion) -> Result<Vec<HpkeConfig>, DapError>;
+
+    /// Returns `true` if a ciphertext with the given HPKE config ID can be decrypted.
+    async fn can_hpke_decrypt(&self, version: DapVersion, config_id: u8) -> Result<bool, DapError>;
+
+    /// Decrypt the given ciphertext. If the HPKE config ID is unknown, then the return value is
+    /// `DapError::Transition(TransitionFailure::HpkeUnknownConfigId)`.
+    async fn hpke_decrypt(
+        &self,
+        version: DapVersion,
+        info: &[u8],
+        aad: &[u8],
+        ciphertext: &HpkeCiphertext,
+    ) -> Result<Vec<u8>, DapError>;
+}
+
+impl DaphneWorker<'_> {
+    /// Get the KV key of the HPKE receiver config that Clients should use. If there are no
+    /// configs for the given version, then generate one and store it in KV.
+    async fn get_or_generate_hpke_receiver_kv_key(
+        &self,
+        version: DapVersion,
+    ) -> Result<HpkeReceiverKvKey, DapError> {
+        let hpke_receiver_kv_keys = self
+            .list_hpke_receiver_configs(version)
+            .await
+            .map_err(dap_err)?;
+
+        if hpke_receiver_kv_keys.is_empty() {
+            // Generate a new HPKE receiver config and store it in KV.
+            //
+            // For now, expect that only one KEM algorithm is supported and that only one config
+            // will be used at anyone time.
+            if self.config().global.supported_hpke_kems.len() != 1 {
+                return Err(DapError::Fatal(
+                    "The number of supported HPKE KEMs must be 1".to_string(),
+                ));
+            }
+
+            let kv_store = self.kv().map_err(dap_err)?;
+            let mut hpke_config_id = None;
+            for it in self
+                .config()
+                .global
+                .gen_hpke_receiver_config_list(rand::random())
+            {
+                let hpke_receiver_config = it.expect("failed to generate HPKE receiver config");
+                if hpke_config_id.is_none() {
+                    hpke_config_id = Some(hpke_receiver_config.config.id);
+                }
+                let new_kv_config_key = format!(
+                    "{}/{}",
+                    KV_KEY_PREFIX_HPKE_RECEIVER_CONFIG,
+                    HpkeReceiverKvKey {
+                        version,
+                        hpke_config_id: hpke_receiver_config.config.id
+                    },
+                );
+
+                kv_store
+                    .put(&new_kv_config_key, hpke_receiver_config)
+                    .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?
+                    .execute()
+                    .await
+                    .map_err(|e| DapError::Fatal(format!("kv_store: {e}")))?;
+            }
+
+            Ok(HpkeReceiverKvKey {
+                version,
+                hpke_config_id: hpke_config_id.unwrap(),
+            })
+        } else {
+            // Return the HPKE receiver config that is currently valid and became valid most
+            // recently. Configs that were imported with a validity window take precedence over
+            // configs without one.
+            let now = self.get_current_time();
+            let mut selected: Option<(Option<Time>, HpkeReceiverKvKey)> = None;
+            for (hpke_receiver_kv_key, validity) in hpke_receiver_kv_keys {
+                if matches!(validity, Some(ref validity) if !validity.contains(now)) {
+                    continue;
+                }
+                let not_before = validity.map(|validity| validity.not_before);
+                if selected
+                    .as_ref()
+                    .is_none_or(|(selected_not_before, _)| not_before > *selected_not_before)
+                {
+                    selected = Some((not_before, hpke_receiver_kv_key));
+                }
+            }
+            let (_, hpke_receiver_kv_key) = selected
+                .ok_or_else(|| DapError::fatal("no HPKE receiver config is currently valid"))?;
+            Ok(hpke_receiver_kv_key)
+        }
+    }
+}
+
+/// The default provider: HPKE receiver configs are stored in KV.
+#[async_trait(?Send)]
+impl DaphneWorkerHpkeProvider for DaphneWorker<'_> {
+    async fn get_hpke_config_list(&self, version: DapVersion) -> Result<Vec<HpkeConfig>, DapError> {
+        // Advertise each config that is currently valid, most recent first. Configs without a
+        // validity window are advertised last.
+        let now = self.get_current_time();
+        let mut hpke_receiver_kv_keys = self
+            .list_hpke_receiver_configs(version)
+            .await
+            .map_err(dap_err)?
+            .into_iter()
+            .filter(|(_, validity)| {
+                validity
+                    .as_ref()
+                    .is_none_or(|validity| validity.contains(now))
+            })
+            .collect::<Vec<_>>();
+        if hpke_receiver_kv_keys.is_empty() {
+            // Fetch the indicated HPKE config from KV.
+            //
+            // TODO(cjpatton) Figure out how likely this is to fail if we had to generate a new
+            // key pair and write it to KV during this call.
+            let hpke_receiver_kv_key = self.get_or_generate_hpke_receiver_kv_key(version).await?;
+            let hpke_receiver_config = self
+                .get_hpke_receiver_config(hpke_receiver_kv_key)
+                .await
+                .map_err(dap_err)?
+                .ok_or_else(|| DapError::fatal("empty HPKE receiver config list"))?;
+            return Ok(vec![hpke_receiver_config.value().config.clone()]);
+        }
+        hpke_receiver_kv_keys.sort_by_key(|(_, validity)| {
+            std::cmp::Reverse(validity.as_ref().map(|validity| validity.not_before))
+        });
+
+        let mut hpke_configs = Vec::with_capacity(hpke_receiver_kv_keys.len());
+        for (hpke_receiver_kv_key, _) in hpke_receiver_kv_keys {
+            if let Some(hpke_receiver_config) = self
+                .get_hpke_receiver_config(hpke_receiver_kv_key)
+                .await
+                .map_err(dap_err)?
+            {
+                hpke_configs.push(hpke_receiver_config.value().config.clone());
+            }
+        }
+        Ok(hpke_configs)
+    }
+
+    async fn can_hpke_decrypt(&self, version: DapVersion, config_id: u8) -> Result<bool, DapError> {
+        Ok(self
+            .get_hpke_receiver_config(HpkeReceiverKvKey {
+                version,
+                hpke_config_id: config_id,
+            })
+            .await
+            .map_err(dap_err)?
+            .is_some())
+    }
+
+    async fn hpke_decrypt(
+        &self,
+        version: DapVersion,
+        info: &[u8],
+        aad: &[u8],
+        ciphertext: &HpkeCiphertext,
+    ) -> Result<Vec<u8>, DapError> {
+        if let Some(hpke_receiver_config) = self
+            .get_hpke_receiver_config(HpkeReceiverKvKey {
+                version,
+                hpke_config_id: ciphertext.config_id,
+            })
+            .await
+            .map_err(dap_err)?
+        {
+            Ok(hpke_receiver_config.value().decrypt(
+                info,
+                aad,
+                &ciphertext.enc,
+                &ciphertext.payload,
+            )?)
+        } else {
+            Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
+        }
+    }
+}
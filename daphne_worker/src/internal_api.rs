@@ -0,0 +1,343 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Typed responses of the internal and admin endpoints.
+//!
+//! Each response of the interop test API (`/internal/test/*`) and each failed request to an admin
+//! endpoint carries an [`InternalResponse`] in its body. A failure is described by an
+//! [`InternalErrorCode`], which tooling can match on, and a human-readable detail. The successful
+//! responses of the admin endpoints carry their payload directly, as before.
+//!
+//! The health and readiness endpoints (`/healthz` and `/readyz`) respond with a [`HealthReport`].
+//!
+//! `GET /internal/telemetry/aggregation` responds with an [`AggregationTelemetry`].
+//!
+//! `GET /internal/dead_letters/task/:task_id` responds with a [`DeadLetters`].
+//!
+//! `GET /internal/storage_migration` responds with a [`StorageMigrationStatus`].
+//!
+//! `GET /internal/report_samples/task/:task_id` responds with a [`ReportSampleStats`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Whether a request succeeded.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalStatus {
+    Success,
+    Error,
+}
+
+/// The reason why a request failed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalErrorCode {
+    /// The admin bearer token is not configured, so the admin API is disabled.
+    AdminNotConfigured,
+
+    /// The admin bearer token is missing or invalid.
+    Unauthorized,
+
+    /// The request is malformed, e.g., an ID is not valid URL-safe base64.
+    BadRequest,
+
+    /// The task is not recognized.
+    UnrecognizedTask,
+
+    /// The requested resource, other than a task, does not exist.
+    NotFound,
+
+    /// The request conflicts with the current state.
+    Conflict,
+
+    /// The feature used by the request is not configured.
+    NotConfigured,
+
+    /// Interop test API: The role of the Aggregator does not match the request.
+    RoleMismatch,
+
+    /// Interop test API: The command could not be carried out.
+    CommandFailed,
+}
+
+impl InternalErrorCode {
+    /// The HTTP status of a failed admin request. (Following
+    /// draft-dcook-ppm-dap-interop-test-design, failed commands of the interop test API are
+    /// reported in the body of a successful response.)
+    pub fn http_status(self) -> u16 {
+        match self {
+            Self::AdminNotConfigured
+            | Self::BadRequest
+            | Self::NotConfigured
+            | Self::RoleMismatch
+            | Self::CommandFailed => 400,
+            Self::Unauthorized => 401,
+            Self::UnrecognizedTask | Self::NotFound => 404,
+            Self::Conflict => 409,
+        }
+    }
+}
+
+/// Envelope of the response of an internal or admin endpoint. The payload, if any, is flattened
+/// into the envelope, so it must serialize as a JSON object.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InternalResponse<T = ()> {
+    pub status: InternalStatus,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<InternalErrorCode>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+
+    /// Same as `detail`. This field is required by draft-dcook-ppm-dap-interop-test-design.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(flatten)]
+    pub payload: Option<T>,
+}
+
+impl<T> InternalResponse<T> {
+    pub fn success(payload: T) -> Self {
+        Self {
+            status: InternalStatus::Success,
+            error_code: None,
+            detail: None,
+            error: None,
+            payload: Some(payload),
+        }
+    }
+
+    pub fn error<S: ToString>(error_code: InternalErrorCode, detail: S) -> Self {
+        let detail = detail.to_string();
+        Self {
+            status: InternalStatus::Error,
+            error_code: Some(error_code),
+            detail: Some(detail.clone()),
+            error: Some(detail),
+            payload: None,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == InternalStatus::Success
+    }
+}
+
+/// Payload of a successful `endpoint_for_task` command of the interop test API.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InternalTestEndpoint {
+    pub endpoint: String,
+}
+
+/// Outcome of a health check.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Pass,
+    Fail,
+}
+
+/// Result of checking one of the dependencies of the Aggregator.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HealthCheck {
+    /// Name of the check, e.g., "kv" or "durable_object".
+    pub name: String,
+
+    pub status: HealthStatus,
+
+    /// Time (in milliseconds) it took to perform the check.
+    pub latency_ms: u64,
+
+    /// The value that was checked, if any, e.g., the depth of the aggregation job queue.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+
+    /// Why the check failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Response of the health and readiness endpoints. The Aggregator is healthy if each of the
+/// checks passed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<HealthCheck>) -> Self {
+        let status = if checks
+            .iter()
+            .all(|check| check.status == HealthStatus::Pass)
+        {
+            HealthStatus::Pass
+        } else {
+            HealthStatus::Fail
+        };
+        Self { status, checks }
+    }
+
+    /// The HTTP status of the response: 503 Service Unavailable if a check failed.
+    pub fn http_status(&self) -> u16 {
+        match self.status {
+            HealthStatus::Pass => 200,
+            HealthStatus::Fail => 503,
+        }
+    }
+}
+
+/// Cumulative number of reports of a task, broken down by outcome, as counted by this
+/// Aggregator.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TaskAggregationTelemetry {
+    /// Number of reports for which aggregation was attempted, i.e., the number of reports that
+    /// were aggregated or rejected.
+    pub reports_processed: u64,
+
+    /// Number of reports aggregated.
+    pub reports_aggregated: u64,
+
+    /// Number of reports in the batches that were collected. A report is counted each time its
+    /// batch is collected.
+    pub reports_collected: u64,
+
+    /// Number of reports rejected during aggregation, keyed by the reason, e.g.,
+    /// "report_replayed".
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reports_rejected: BTreeMap<String, u64>,
+}
+
+/// Response of `GET /internal/telemetry/aggregation`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AggregationTelemetry {
+    /// Counts for each task, keyed by task ID (URL-safe base64). Tasks for which no report has
+    /// been counted are omitted.
+    pub tasks: BTreeMap<String, TaskAggregationTelemetry>,
+}
+
+/// A report of a task that was rejected during aggregation because it is invalid.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DeadLetter {
+    /// The report ID (URL-safe base64).
+    pub report_id: String,
+
+    /// The timestamp of the report.
+    pub time: u64,
+
+    /// The reason the report was rejected, e.g., "hpke_decrypt_error".
+    pub failure: String,
+
+    /// The time at which the report was rejected.
+    pub rejected_at: u64,
+}
+
+/// Response of `GET /internal/dead_letters/task/:task_id`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DeadLetters {
+    /// The reports rejected for the task that have not yet expired, in no particular order.
+    pub dead_letters: Vec<DeadLetter>,
+}
+
+/// Request and response of `POST /task/:task_id/force_close_batch`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ForcedBatch {
+    /// The ID of the batch (URL-safe base64). In the request, this may be omitted for the Leader,
+    /// which then closes the batch currently being filled. In the response, it is `null` if there
+    /// was no report to collect.
+    #[serde(default)]
+    pub batch_id: Option<String>,
+}
+
+/// Phase of a migration of report storage to a new layout.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMigrationPhase {
+    /// Report IDs are marked as processed in both layouts and a report is considered processed if
+    /// either layout says so. Pending reports are stored in the new layout.
+    DualWrite,
+
+    /// Only the new layout is used.
+    Complete,
+}
+
+/// A migration of report storage from one number of report shards to another. Stored in KV while
+/// the migration is in progress.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StorageMigration {
+    /// Number of report shards of the old layout.
+    pub from_report_shard_count: u64,
+
+    /// Number of report shards of the new layout.
+    pub report_shard_count: u64,
+
+    pub phase: StorageMigrationPhase,
+
+    /// Time at which the dual-write phase was started.
+    pub started_at: u64,
+
+    /// Time at which the migration was cut over to the new layout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<u64>,
+}
+
+/// Request of `PUT /internal/storage_migration`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StorageMigrationReq {
+    /// Number of report shards of the new layout.
+    pub report_shard_count: u64,
+}
+
+/// Response of `GET /internal/storage_migration`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StorageMigrationStatus {
+    /// Number of report shards configured by `DAP_REPORT_SHARD_COUNT`.
+    pub configured_report_shard_count: u64,
+
+    /// The migration in progress, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<StorageMigration>,
+
+    /// Time from which the migration may be cut over, if it is in the dual-write phase.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cutover_ready_at: Option<u64>,
+
+    /// Whether the migration may be cut over now.
+    pub cutover_ready: bool,
+}
+
+/// Response of `GET /internal/report_samples/task/:task_id`: statistics over the metadata of the
+/// uploaded reports of the task that were sampled and have not yet expired.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ReportSampleStats {
+    /// Number of sampled reports.
+    pub sample_count: u64,
+
+    /// Number of sampled reports that were rejected, by problem type (e.g., "reportRejected").
+    pub rejected: BTreeMap<String, u64>,
+
+    /// Minimum, median, and maximum difference between the timestamp of a sampled report and the
+    /// time it was uploaded, in seconds. Not set if there are no samples.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_skew_min: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_skew_median: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_skew_max: Option<i64>,
+
+    /// Number of sampled reports carrying each type of extension in the report metadata
+    /// (draft02 only).
+    pub extensions: BTreeMap<u16, u64>,
+
+    /// Number of sampled reports uploaded with the "dap-taskprov" header.
+    pub taskprov_header: u64,
+
+    /// Number of sampled reports encrypted to each of the Leader's HPKE config IDs.
+    pub leader_hpke_config_ids: BTreeMap<u8, u64>,
+
+    /// Number of sampled reports encrypted to each of the Helper's HPKE config IDs.
+    pub helper_hpke_config_ids: BTreeMap<u8, u64>,
+}
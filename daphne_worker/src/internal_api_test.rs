@@ -0,0 +1,50 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::internal_api::{
+    InternalErrorCode, InternalResponse, InternalStatus, InternalTestEndpoint,
+};
+use serde_json::json;
+
+#[test]
+fn internal_response_json() {
+    let resp = InternalResponse::success(());
+    assert_eq!(
+        serde_json::to_value(&resp).unwrap(),
+        json!({ "status": "success" })
+    );
+    let decoded: InternalResponse = serde_json::from_value(json!({ "status": "success" })).unwrap();
+    assert!(decoded.is_success());
+
+    let resp = InternalResponse::success(InternalTestEndpoint {
+        endpoint: "/v04/".into(),
+    });
+    assert_eq!(
+        serde_json::to_value(&resp).unwrap(),
+        json!({ "status": "success", "endpoint": "/v04/" })
+    );
+    let decoded: InternalResponse<InternalTestEndpoint> =
+        serde_json::from_value(json!({ "status": "success", "endpoint": "/v04/" })).unwrap();
+    assert_eq!(decoded, resp);
+}
+
+#[test]
+fn internal_response_error_json() {
+    let resp: InternalResponse<InternalTestEndpoint> =
+        InternalResponse::error(InternalErrorCode::RoleMismatch, "role mismatch");
+    let value = serde_json::to_value(&resp).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "status": "error",
+            "error_code": "role_mismatch",
+            "detail": "role mismatch",
+            "error": "role mismatch",
+        })
+    );
+
+    let decoded: InternalResponse<InternalTestEndpoint> = serde_json::from_value(value).unwrap();
+    assert_eq!(decoded.status, InternalStatus::Error);
+    assert_eq!(decoded.error_code, Some(InternalErrorCode::RoleMismatch));
+    assert_eq!(decoded.payload, None);
+}
@@ -12,7 +12,8 @@
 //!
 //! The code is intended to be packaged with
 //! [workers-rs](https://github.com/cloudflare/workers-rs). See [`DaphneWorkerRouter`] for usage
-//! instructions.
+//! instructions. To mount the endpoints under a path prefix, run middleware around request
+//! handling, or disable the endpoints of a role, build the router with [`DapRouter`].
 //!
 //! # Architecture
 //!
@@ -51,6 +52,12 @@
 //! where `<version>` is the DAP version, `<task_id>` the task ID0, `<epoch>` the report's epoch,
 //! and `<shard>` is the report's shard.
 //!
+//! To change `DAP_REPORT_SHARD_COUNT` without allowing reports to be replayed, the administrator
+//! migrates the report storage with `PUT /internal/storage_migration`, which starts writing to
+//! both the old and the new layout, then `POST /internal/storage_migration/cutover` once `GET
+//! /internal/storage_migration` reports that the cutover is ready. See the `storage_migration`
+//! module for details.
+//!
 //! ## Aggregate Storage (Leader and Helper)
 //!
 //! The `AggregateStore` DO is used by the Leader and Helper to store aggregate shares that are
@@ -81,7 +88,11 @@
 //! Aggregation jobs are driven by the Leader's main processing loop (see
 //! [`DapLeader::process()`](daphne::roles::DapLeader::process)). The report selector for
 //! Daphne-Worker, [`DaphneWorkerReportSelector`], indicates the number of jobs to fetch at once
-//! (`max_agg_jobs`) and the number of reports to drain per job (`max_reports`).
+//! (`max_agg_jobs`) and the number of reports to drain per job (`max_reports`). Each run returns
+//! a cursor pointing to the last job it fetched; passing the cursor to the next run (`cursor`)
+//! resumes the queue from that point, so that a large backlog is drained in order rather than by
+//! re-scanning the front of the queue. Once the end of the queue is reached, no cursor is returned
+//! and the next run starts from the front.
 //!
 //! Jobs are handled roughly in order of creation (oldest jobs are handled first). The time at
 //! which an aggregation job was created is used determine the order in which it was processed.
@@ -105,6 +116,10 @@
 //! created, the Leader checksto see if the job can be completed (i.e., the span of batch buckets
 //! contains a sufficient number of reports).
 //!
+//! If `DAP_COLLECTION_EXPORT_URL` is set, then the result of each completed job is also decrypted
+//! with `DAP_COLLECTION_EXPORT_HPKE_RECEIVER_CONFIG` and POSTed to that URL as JSON, e.g., for
+//! ingestion by a metrics pipeline. A failed export is logged but does not fail the job.
+//!
 //! ## Batch Queue (Leader-only).
 //!
 //! > NOTE: This scheme is not expected to scale well. Currently it is only suited for driving
@@ -122,6 +137,13 @@
 //! batch in the front of the queue is filled first; if the batch is saturated (i.e., the target
 //! batch size is met) then the batch is removed from the queue and the process is repeated.
 //!
+//! A fixed-size task may set a `batch_lifetime` for batches that do not fill up (see
+//! [`DapBatchLifetimeConfig`]). Once the batch being filled is stale, it is closed and reports are
+//! assigned to a new batch. Depending on the policy, the stale batch is either dropped from the
+//! queue or may be collected even though it is smaller than the minimum batch size. The policy is
+//! set in the task config, e.g., via the task manifest or the `batch_lifetime` field of `POST
+//! /task`.
+//!
 //! ## Storage of the Helper's State (Helper-only)
 //!
 //! The `HelperStateStore` DO is used to store the Helper's state
@@ -135,6 +157,303 @@
 //! where `<version>` is the DAP version, `<task_id>` is the task ID, and `<agg_job_id>` is the
 //! aggregation job ID.
 //!
+//! If `helper_state_store` is set in the DAP global config, then an instance of the DO sets an
+//! alarm that deletes it once the state it holds expires. An AggregationJobInitReq whose state
+//! would exceed `helper_state_store.max_encoded_len` is rejected before its reports are prepared.
+//!
+//! If `helper_agg_job_limit` is set in the DAP global config, then the single instance of the
+//! `HelperAggregationJobSlots` DO keeps track of the aggregation jobs that are running. An
+//! aggregation job holds a slot from the time its state is stored until it is continued, or until
+//! its instance of `HelperStateStore` is deleted.
+//!
+//! If `helper_agg_job_limit.retry_after` is also set, then an AggregationJobInitReq that finds
+//! every slot taken is rejected with status 429 and a `Retry-After` header indicating how long
+//! (in seconds) the Leader should back off. When the Leader receives such a response, it defers
+//! every aggregation job for that Helper until then. The reports of a deferred job are returned
+//! to storage, and the deferral is recorded in KV under `helper_deferral/<helper_url>`. Deferred
+//! jobs are counted by the `agg_job_deferred` metric.
+//!
+//! If `helper_circuit_breaker` is set in the DAP global config, then the Leader counts the
+//! aggregation jobs in a row that the Helper rejected as unauthorized (`helper_auth_failure`
+//! metric) in KV under `helper_auth_failures/<helper_url>`. Once the count reaches
+//! `helper_circuit_breaker.failure_threshold`, the aggregation jobs for the Helper are deferred for
+//! `helper_circuit_breaker.open_for` seconds, just as they are when the Helper is overloaded
+//! (`helper_circuit_breaker_opened` metric). The count is reset once the Helper accepts a job. An
+//! isolate that has reset the count doesn't check KV again for a minute, unless it counts another
+//! failure in the meantime.
+//!
+//! If `agg_job_abandon` is set in the DAP global config, then the Leader counts the aggregation
+//! jobs each report was part of that had to be abandoned, e.g., because the Helper did not
+//! respond. The count is recorded in KV under `abandoned_report/task/<task_id>/<report_id>` until
+//! the report is too old to be aggregated. Once a report reaches `agg_job_abandon.max_attempts`,
+//! it is rejected (counted with the "rejected_agg_job_abandoned" status) rather than returned to
+//! storage.
+//!
+//! ## Usage Counting (Leader and Helper)
+//!
+//! If `DAP_BILLING_ENABLED` is set, then the `TaskUsageStore` DO is used to count the usage of
+//! each task for billing. There is one instance per task and per day:
+//!
+//! ```text
+//!     <version>/task/<task_id>/usage/day/<day>
+//! ```
+//!
+//! where `<version>` is the DAP version, `<task_id>` is the task ID, and `<day>` is the UNIX
+//! timestamp of the start of the day. The daily counts are rolled up into a usage report for the
+//! task by `POST /admin/tasks/<task_id>/billing`; the report is stored in KV and can be retrieved
+//! with `GET /admin/tasks/<task_id>/billing`.
+//!
+//! ## Aggregation Telemetry (Leader and Helper)
+//!
+//! If `DAP_AGGREGATION_TELEMETRY_ENABLED` is set, then the `AggregationTelemetryStore` DO is used
+//! to count the reports of each task that were aggregated, rejected (by reason), and collected.
+//! There is one instance per task:
+//!
+//! ```text
+//!     <version>/task/<task_id>/telemetry
+//! ```
+//!
+//! The counts are updated at the end of each request that processes reports and are served, for
+//! dashboarding, by `GET /internal/telemetry/aggregation` as a JSON
+//! [`internal_api::AggregationTelemetry`]. Unlike the ad-hoc
+//! [`DapLeaderProcessTelemetry`](daphne::DapLeaderProcessTelemetry) returned by a run of the
+//! processing loop, the counts are cumulative.
+//!
+//! If `DAP_DEAD_LETTER_TTL_SECS` is set, then the reports rejected during aggregation because they
+//! are invalid, e.g., they could not be decrypted or failed VDAF preparation, are stored in KV
+//! until they expire, so that systematic Client bugs can be debugged. The Leader also stores the
+//! reports rejected by the Helper. The entries of a task, i.e., the report ID, timestamp, reason for the
+//! rejection, and time of the rejection, are listed as a JSON [`internal_api::DeadLetters`] by
+//! `GET /internal/dead_letters/task/:task_id` and purged by
+//! `DELETE /internal/dead_letters/task/:task_id`. At most 100 reports are stored per task and
+//! request.
+//!
+//! If `report_sampling` is set in the global configuration, then the Leader stores the metadata
+//! of a fraction of the uploaded reports in KV for a day: the skew of the report's timestamp, the
+//! extensions visible at upload, and the IDs of the HPKE configs used. Rejected reports are
+//! sampled as well. `GET /internal/report_samples/task/:task_id` summarizes the samples of a task
+//! as a JSON [`internal_api::ReportSampleStats`], so that operators can detect misconfigured
+//! Clients across the fleet; `DELETE /internal/report_samples/task/:task_id` purges them.
+//!
+//! # HPKE Config Rotation
+//!
+//! By default, HPKE receiver configs are stored in KV. Alternatively, decryption can be delegated
+//! to an external key management service by setting [`DaphneWorkerRouter::hpke_provider`]; the
+//! secret keys then never leave the service, and the KV-backed endpoints below are not used. If `hpke_rotation` is set in the DAP global config,
+//! then `POST /<version>/hpke_receiver_configs/rotate` (e.g., triggered by a cron job) generates a
+//! new config once per rotation period and deletes the configs whose grace period has elapsed.
+//! Each config is advertised for several rotation periods, so all configs that are currently
+//! valid are listed in the response to `GET /<version>/hpke_config`, most recent first. Reports
+//! encrypted to a config are accepted until the config is deleted.
+//!
+//! # Global Configuration
+//!
+//! The global DAP configuration is set by `DAP_GLOBAL_CONFIG`. Some of its parameters
+//! (`max_batch_duration`, `min_batch_interval_start`, `max_batch_interval_end`, `allow_taskprov`,
+//! and `taskprov_version`) may be overridden at runtime, without redeploying the Worker, with `PUT
+//! /internal/global_config`; the current override is returned by `GET /internal/global_config`.
+//! The override is a JSON object that sets any of these parameters along with a `version` stamp,
+//! which must be one more than the stamp of the override it replaces (initially 0). Otherwise the
+//! update is refused with 409 Conflict. The override is stored in KV, so that all of its
+//! parameters take effect together, and is picked up by each isolate within a minute. Taskprov can
+//! only be enabled by the override if it is configured by the environment.
+//!
+//! The override may also set `read_only`, which takes precedence over `DAP_READ_ONLY`. In
+//! read-only mode, requests that modify storage are refused with 503 Service Unavailable: only GET
+//! and HEAD requests are served, along with the few routes for other methods that are marked as
+//! read-only, e.g., polling a collection job. `PUT /internal/global_config` is among them, so that
+//! the mode can be lifted at runtime.
+//!
+//! Draft02 and draft04 (and later) tasks may be served by the same deployment, under `/v02/` and
+//! `/v04/` respectively. The batch interval rules (`max_batch_duration`,
+//! `min_batch_interval_start`, `max_batch_interval_end`, and `batch_interval_future_tolerance`)
+//! and `max_upload_batch_len` may be set for each version in the `versions` section of
+//! `DAP_GLOBAL_CONFIG` (e.g., `"versions": {"v02": {"max_batch_duration": 3600}}`), in which case
+//! they apply to tasks of that version instead of the values set for all versions, including any
+//! runtime override. `DAP_DEFAULT_VERSION` only selects the version of the endpoints that are
+//! served without a version prefix.
+//!
+//! The size (in bytes) of request bodies may be limited for each kind of endpoint with
+//! `max_request_size` (e.g., `"max_request_size": {"upload": 4096, "aggregate": 1048576}`).
+//! Oversized requests are rejected with 413 Payload Too Large before their body is decoded.
+//!
+//! # Task Administration
+//!
+//! Task configs are stored in KV. The administrator adds a task with `POST /task`, lists the IDs
+//! of the configured tasks with `GET /task`, and gets or deletes a task with `GET /task/<task_id>`
+//! and `DELETE /task/<task_id>`. The VDAF verification key is not included in the response to
+//! `GET`. Deleting a task also deletes its bearer tokens, but not its reports or aggregate shares.
+//!
+//! Tasks may also be installed in bulk from a declarative manifest (see
+//! [`DapTaskManifest`](daphne::manifest::DapTaskManifest)) with `POST /admin/tasks/manifest`. The
+//! response is a JSON [`DapTaskManifestDiff`](daphne::manifest::DapTaskManifestDiff) stating
+//! whether each listed task would be created, updated, or left unchanged, and which configured
+//! tasks are not listed. Nothing is changed unless the query parameter `apply=true` is set, in
+//! which case created and updated tasks are written to KV along with the bearer tokens listed for
+//! them. Applying a manifest is idempotent, and tasks that are not listed are never deleted. A task
+//! that is created must list its bearer tokens (on the Helper, only the Leader's).
+//!
+//! The VDAFs that a task may be configured with are listed by `GET
+//! /.well-known/daphne/supported_vdafs`, along with the code point of each VDAF for each DAP
+//! version and the range of each parameter (see
+//! [`supported_vdafs`](daphne::vdaf::supported_vdafs)). The endpoint is not authenticated. A task
+//! whose VDAF parameters are out of range is rejected, whether it is added by the administrator, by
+//! a manifest, or via taskprov.
+//!
+//! The VDAF verification key of a task may be rotated with a manifest update, without creating a
+//! new task (see [`DapVdafVerifyKeyRotation`](daphne::DapVdafVerifyKeyRotation)). Each key has an
+//! ID, and the Leader sends the ID of its current key in the "dap-vdaf-verify-key-id" header of
+//! each request to the Helper. The Helper prepares the reports of an aggregation job with the key
+//! that has this ID and aborts the job if it has no such key.
+//!
+//! A task may be upgraded to a newer DAP version with `POST /task/<task_id>/migrate`. The body is
+//! a JSON object with the target `version` (e.g., "v04") and an optional `drain` flag. The task is
+//! re-registered under a new ID derived from the old ID and the version (see
+//! [`DapTaskConfig::migrate`](daphne::DapTaskConfig::migrate)), so both Aggregators migrate the
+//! task independently and agree on the result, which is returned as for `GET /task/<task_id>`.
+//! The bearer tokens are copied to the new task. If `drain` is set, then the old task is kept so
+//! that its remaining reports are aggregated and collected, but it stops accepting reports from
+//! the current batch window onwards; otherwise it is deleted as by `DELETE /task/<task_id>`.
+//!
+//! For testing and incident response, a fixed-size batch may be made collectable before it has
+//! `min_batch_size` reports with `POST /task/<task_id>/force_close_batch`. On the Leader, an empty
+//! JSON object closes the batch currently being filled, so that new reports are assigned to a new
+//! batch (see [`DapLeader::force_close_current_batch`]). The batch is recorded as forced, and its
+//! ID is returned as `{"batch_id": ...}` (`null` if no report has been assigned to it). The Helper
+//! checks the batch size independently, so the same request, with the `batch_id` returned by the
+//! Leader, must also be sent to the Helper before the batch is collected.
+//!
+//! To debug the configuration of a task, the administrator may check how a batch of reports would
+//! be aggregated with `POST /admin/tasks/<task_id>/dry_run`. The body is a
+//! [`ReportBatch`](daphne::messages::ReportBatch) encoded for the task's DAP version, and the
+//! response is a JSON list of [`DapDryRunReportStatus`](daphne::DapDryRunReportStatus), one per
+//! report, stating why the report would be rejected, if at all. No state is changed: the reports
+//! are not marked as processed and aggregate shares are not updated. For fixed-size tasks, the
+//! batch is selected with the query parameter `batch_id` (on the Leader, the current batch by
+//! default).
+//!
+//! Collectors other than the task's primary Collector may be added to a task after it has been
+//! configured with `POST /task/<task_id>/collectors` and removed with `DELETE
+//! /task/<task_id>/collectors/<collector_id>`. Each Collector has its own bearer token (Leader
+//! only) and HPKE config, so that several parties can collect the same task without sharing
+//! credentials. Removing a Collector also deletes its bearer token.
+//!
+//! If `DAP_TASK_GARBAGE_COLLECT_AFTER_SECS` is set, then the state of expired tasks is purged by
+//! `POST /internal/garbage_collect_tasks` or by a scheduled event (see
+//! [`DaphneWorkerRouter::handle_scheduled`]). To make this possible, the Worker registers each DO
+//! instance that belongs to a task with an instance of the `GarbageCollector` DO for the task the
+//! first time the instance is used. Once the task has been expired for the configured amount of
+//! time, the registered instances, the task's collection jobs, and its KV state are deleted. The
+//! response lists what was deleted for each task.
+//!
+//! A task may have Collectors in addition to its primary Collector, each with its own HPKE config
+//! and (on the Leader) bearer token. These are configured by the `additional_collectors` field of
+//! the task. A request from such a Collector carries its ID in the "dap-collector-id" header, and
+//! the Leader relays the header to the Helper. Each Collector may collect each batch once.
+//!
+//! While a collection job is pending, the Collector may follow its progress with `GET
+//! /<version>/tasks/<task_id>/collection_jobs/<collect_job_id>/status` (draft04 and later). The
+//! request is authorized like the collection request itself. The response is a JSON
+//! [`CollectionJobStatus`](daphne::CollectionJobStatus) listing the number of reports aggregated
+//! so far, how full the batch is, and when it is estimated to be ready.
+//!
+//! To find batches worth collecting, the Collector may request `GET
+//! /<version>/tasks/<task_id>/batch_suggestions` (draft04 and later), authorized like a
+//! collection request. The response is a JSON [`DapBatchSuggestions`](daphne::DapBatchSuggestions)
+//! listing the batch intervals (for time-interval tasks) or the current batch ID (for fixed-size
+//! tasks) that meet the task's minimum batch size and have not been collected by the Collector.
+//!
+//! The Collector selects the encoding of the result of a collection job with the "accept" header
+//! of the request polling the job. By default, the result is the TLS-encoded `Collection` message.
+//! For debugging, the result may instead be requested as JSON with "accept: application/json";
+//! this requires the admin bearer token in the "X-Daphne-Worker-Admin-Bearer-Token" header. A
+//! request accepting neither is answered with status 406.
+//!
+//! The Collector may delete a collection job with `DELETE
+//! /<version>/tasks/<task_id>/collection_jobs/<collect_job_id>` (draft04 and later). A pending job
+//! is cancelled and its batch may be collected by a later job; the result of a completed job is
+//! deleted, but its batch remains collected. Deletions are counted by the
+//! `collect_job_deleted_counter` metric.
+//!
+//! If `max_upload_batch_len` is set in the global configuration, then Clients may upload several
+//! reports in one request with `POST /<version>/tasks/<task_id>/reports/batch` (draft04 and
+//! later). The body is a [`ReportBatch`](daphne::messages::ReportBatch). Each report is handled as
+//! if it were uploaded on its own, and the response is a JSON list of
+//! [`DapReportUploadStatus`](daphne::DapReportUploadStatus), one per report.
+//!
+//! A Leader task may restrict uploads to a closed population of Clients by setting `upload_auth`
+//! (see [`DapUploadAuth`](daphne::auth::DapUploadAuth)). Clients then present the task's bearer
+//! token in the "dap-auth-token" or "authorization" header, or sign each upload request and send
+//! the hex-encoded HMAC-SHA256 tag in the "dap-client-signature" header. Uploads without a valid
+//! credential are rejected with "unauthorizedRequest".
+//!
+//! A Client may safely retry an upload request by sending the same idempotency key (up to 64
+//! URL-safe characters) in the "dap-idempotency-key" header of each attempt. Once a request is
+//! accepted (or, for a batched upload, handled), the SHA-256 digest of its body and the outcome of
+//! each of its reports are kept in an instance of the `UploadIdempotencyStore` DO, named by the
+//! task and the key, until its reports are too old to be uploaded. A retry with the same body is
+//! answered with the recorded outcome instead of "reportReplayed", and a request with a different
+//! body under the same key is rejected with "idempotencyKeyConflict". For a batched upload, a
+//! report that was rejected by the original request, e.g., because another request uploaded it,
+//! is reported as rejected by each retry. A report that failed for a transient reason (e.g., a
+//! storage error) is not recorded, so each retry uploads it again, and is accepted if it turns out
+//! that the original request stored it. A single report is recorded as pending before it is
+//! stored, so that this also holds if its outcome could not be recorded.
+//!
+//! Both draft02 and draft04 of the taskprov extension are supported. In draft04, the task
+//! configuration is carried in the "dap-taskprov" header of the upload request rather than in the
+//! report extension. The Leader relays the header in each of its requests to the Helper.
+//!
+//! If `DAP_TASKPROV_EXPIRY_NOTIFICATION_URL` is set, then the operator is notified of each task
+//! configured by taskprov that is about to expire, so that renewing the task can be coordinated
+//! with the peer Aggregator before collections are missed. The notification is triggered by
+//! `POST /internal/notify_expiring_taskprov_tasks` or by a scheduled event. It is a JSON object
+//! listing the task ID, its expiration, and the URLs of both Aggregators, and is POSTed to the
+//! URL once per task.
+//!
+//! The optional `dp` field of the task configures a differential privacy mechanism (see
+//! [`DapDpConfig`](daphne::DapDpConfig)). If set, then each Aggregator adds noise to its aggregate
+//! share before encrypting it to the Collector. The noised aggregate share is stored in the
+//! `AggregateStore` of the batch's first bucket, and repeated queries of the batch by the same
+//! Collector are answered with it, so that the noise cannot be averaged away. A retried
+//! aggregate share request is not counted as another query of the batch.
+//!
+//! By default, bearer tokens are carried in the "DAP-Auth-Token" header. To interoperate with
+//! deployments that use "Authorization: Bearer <token>" instead (e.g., some versions of Janus),
+//! the optional `auth_header` field of the task sets which headers are accepted from and emitted
+//! to the peer, e.g., `{"accept": ["dap_auth_token", "authorization_bearer"], "emit":
+//! "authorization_bearer"}`. This covers requests from the Leader to the Helper and from the
+//! Collector to the Leader. The same can be configured for all tasks with a given peer Aggregator
+//! by `DAP_AUTH_HEADER_BY_PEER`.
+//!
+//! Load balancers and orchestrators may probe `GET /healthz` and `GET /readyz`. The former checks
+//! that KV and DO storage are reachable; the latter also checks that an HPKE config is available
+//! for the default DAP version and that the depth of the aggregation job queue (Leader) or the
+//! number of running aggregation jobs (Helper) is below `DAP_HEALTH_MAX_QUEUE_DEPTH`. The response
+//! is a JSON [`internal_api::HealthReport`] with status 200 OK if each check passed and 503
+//! Service Unavailable otherwise. The outcome and latency of each check are recorded by the
+//! `health_check_status` and `health_check_latency_ms` gauges.
+//!
+//! Each request is assigned a correlation ID, which is included in every log line emitted while
+//! handling it, in the "correlationid" member of abort responses, and in the "dap-correlation-id"
+//! header of the response. The Leader sends the ID in the same header in each of its requests to
+//! the Helper, and the Helper adopts it, so that the logs of both Aggregators can be joined when
+//! debugging a failed aggregation job. A Client or Collector may likewise set the header to choose
+//! the ID of its request.
+//!
+//! # Interop Testing
+//!
+//! If internal test endpoints are enabled, then the Aggregator API of
+//! [draft-dcook-ppm-dap-interop-test-design](https://datatracker.ietf.org/doc/draft-dcook-ppm-dap-interop-test-design/)
+//! is served under `/internal/test/` (e.g., `ready`, `endpoint_for_task`, and `add_task`), both
+//! with and without a version prefix. As required by the draft, a command that fails is reported
+//! with `"status": "error"` and a description of the problem in the body of a successful response.
+//!
+//! The responses of the interop test API, as well as the bodies of failed admin requests, are
+//! typed by [`internal_api::InternalResponse`]. Besides the fields required by the draft, a
+//! failure carries an `error_code` (see [`internal_api::InternalErrorCode`]) and a `detail`, so
+//! that test harnesses and tooling need not match on error strings.
+//!
 //! # Environment Variables
 //!
 //! The runtime behavior of Daphne-Worker is controlled by the environment variables defined in the
@@ -147,27 +466,74 @@
 //! | ---- | ---- | ------- | ----------- |
 //! | `DAP_AGGREGATOR_ROLE` | `String` | no | Aggregator role, either "leader" or "helper". |
 //! | `DAP_COLLECT_ID_KEY` | `String` | yes | Hex-encoded key used to derive the collection job ID from the collect request |
+//! | `DAP_COLLECTION_RECEIPT_SIGNING_KEY` | `String` | yes | Optional, Leader-only: Hex-encoded Ed25519 seed used to sign receipts for completed collections. |
+//! | `DAP_UPLOAD_RECEIPT_SIGNING_KEY` | `String` | yes | Optional, Leader-only: Hex-encoded Ed25519 seed used to sign receipts for uploaded reports. If set, the response to each accepted upload is a JSON [`DapUploadReceipt`](daphne::receipt::DapUploadReceipt). |
+//! | `DAP_HPKE_CONFIG_SIGNING_KEY` | `String` | yes | Optional: Hex-encoded Ed25519 seed used to sign the HPKE configs advertised by the Aggregator. If set, then the signature is sent in the "X-Daphne-Hpke-Config-Signature" header of the response to `GET /<version>/hpke_config`. |
+//! | `DAP_COLLECTION_EXPORT_URL` | `Url` | no | Optional, Leader-only: URL to which the result of each completed collect job is POSTed. |
+//! | `DAP_COLLECTION_EXPORT_HPKE_RECEIVER_CONFIG` | [`HpkeReceiverConfig`](daphne::hpke::HpkeReceiverConfig) | yes | Required if `DAP_COLLECTION_EXPORT_URL` is set: The Collector's HPKE receiver config, used to decrypt the results. |
+//! | `DAP_COLLECTION_EXPORT_SIGNING_KEY` | `String` | yes | Optional: Hex-encoded HMAC-SHA256 key used to sign exported results. |
+//! | `DAP_COLLECTION_EXPORT_RETRY` | [`DapRetryConfig`](daphne::DapRetryConfig) | no | Optional: How to retry a failed export. If not set, then each export is attempted once. |
+//! | `DAP_BATCH_EVENTS_QUEUE` | `String` | no | Optional: Binding of the Workers queue to which batch lifecycle events ([`DapBatchEvent`](daphne::events::DapBatchEvent)) are sent. If not set, then events are logged. |
+//! | `DAP_BILLING_ENABLED` | `bool` | no | Optional: If "true", then count the usage of each task for billing. |
+//! | `DAP_AGGREGATION_TELEMETRY_ENABLED` | `bool` | no | Optional: If "true", then count the reports of each task by outcome for `GET /internal/telemetry/aggregation`. |
+//! | `DAP_DEAD_LETTER_TTL_SECS` | `u64` | no | Optional: If set, then the reports rejected during aggregation because they are invalid (e.g., they could not be decrypted) are stored for this long (in seconds) for `GET /internal/dead_letters/task/:task_id`. |
+//! | `DAP_REQUEST_TIME_BUDGET_MS` | `u64` | no | Optional: Amount of time (in milliseconds) each request is allowed to take. If set, then sub-requests to DOs are refused once the deadline is near, and the request is aborted with 503 Service Unavailable so that it may be retried. |
+//! | `DAP_TASK_GARBAGE_COLLECT_AFTER_SECS` | `u64` | no | Optional: Time (in seconds) to wait after a task has expired before purging its state. If not set, then expired tasks are not garbage collected. |
+//! | `DAP_TASKPROV_EXPIRY_NOTIFICATION_URL` | `Url` | no | Optional: URL to which notifications of expiring taskprov tasks are POSTed. |
+//! | `DAP_TASKPROV_EXPIRY_NOTIFICATION_WINDOW_SECS` | `u64` | no | Required if `DAP_TASKPROV_EXPIRY_NOTIFICATION_URL` is set: How long (in seconds) before the expiration of a taskprov task to notify the operator. |
+//! | `DAP_TASKPROV_EXPIRY_NOTIFICATION_BEARER_TOKEN` | `String` | yes | Optional: Bearer token to present when POSTing notifications of expiring taskprov tasks. |
+//! | `DAP_PROCESSED_COMPACTION_DELAY` | `u64` | no | Optional: Time (in seconds) after an instance of `ReportsProcessed` is first used after which its state is compacted. |
+//! | `DAP_AUTH_HEADER_BY_PEER` | `String` | no | Optional: JSON object mapping the host of a peer Aggregator's URL to the bearer token headers accepted from and emitted to it, in the format of the `auth_header` field of a task. |
+//! | `DAP_AGGREGATION_CONTENT_ENCODING` | `String` | no | Optional, Leader only: Content coding ("gzip" or "deflate") with which to compress aggregation requests sent to the Helper. The Helper must support it. |
+//! | `DAP_METRICS_TASK_LABELS` | [`DaphneMetricsTaskLabelConfig`](daphne::metrics::DaphneMetricsTaskLabelConfig) | no | Optional: If set, then report and aggregation job metrics are also broken down by task, with the label derived from the task ID as configured. If not set, then per-task metrics are not recorded. |
+//! | `DAP_MAX_PENDING_REPORTS_PER_BUCKET` | `u64` | no | Optional, Leader-only: Maximum number of reports pending aggregation per `ReportsPending` instance. Once reached, uploads to the instance are rejected with "reportRejected" and counted by the `report_bucket_full` metric. |
+//! | `DAP_HEALTH_MAX_QUEUE_DEPTH` | `u64` | no | Optional: Depth of the aggregation job queue (Leader) or number of running aggregation jobs (Helper) above which `/readyz` fails. |
+//! | `DAP_HEALTH_MAX_DURABLE_LATENCY_MS` | `u64` | no | Optional: Latency (in milliseconds) of DO storage above which `/healthz` and `/readyz` fail. |
+//! | `DAP_READ_ONLY` | `bool` | no | Optional: If "true", then refuse requests that modify storage with 503 Service Unavailable. Requests that only read storage are handled as usual. May be overridden at runtime (see "Global Configuration"). |
 //! | `DAP_GLOBAL_CONFIG` | [`DapGlobalConfig`](daphne::DapGlobalConfig) | no | DAP global config. |
 //! | `DAP_DEPLOYMENT` | `String` | no | Deployment type, only "prod" for now. |
 //! | `DAP_REPORT_SHARD_COUNT` | `u64` | no | Number of report shards per storage epoch. |
 //! | `DAP_REPORT_SHARD_KEY` | `String` | yes | Hex-encoded key used to hash a report into one of the report shards. |
-pub use crate::tracing_utils::initialize_tracing;
+pub use crate::hpke::DaphneWorkerHpkeProvider;
+pub use crate::router::{DapAggregatorRole, DapMiddleware, DapRouter};
+pub use crate::tracing_utils::{
+    initialize_tracing, initialize_tracing_with_exporter, DaphneWorkerTraceExporter,
+};
 use crate::{
-    config::{DaphneWorkerIsolateState, DaphneWorkerRequestState},
+    auth::{DaphneWorkerAuth, DaphneWorkerAuthHeaderConfig},
+    compression::ContentEncoding,
+    config::{
+        DaphneWorker, DaphneWorkerIsolateState, DaphneWorkerRequestState, GlobalConfigOverride,
+        QuarantinedBuckets,
+    },
     dap::dap_response_to_worker,
+    durable::ERR_DEADLINE_EXCEEDED,
+    internal_api::{
+        DeadLetters, ForcedBatch, InternalErrorCode, InternalResponse, StorageMigrationReq,
+    },
+    tracing_utils::CORRELATION_ID_HEADER,
 };
 use daphne::{
     aborts::DapAbort,
-    auth::BearerToken,
-    constants::DapMediaType,
-    messages::{CollectionJobId, Duration, TaskId, Time},
+    auth::{BearerToken, DapUploadAuth},
+    clock::Clock,
+    constants::{DapCollectionFormat, DapMediaType},
+    extensions::DapExtensionRegistry,
+    hpke::HpkeReceiverConfigWithValidity,
+    messages::{
+        decode_report_prefix, encode_base64url, BatchId, Collection, CollectionJobId, Duration,
+        ReportBatch, TaskId, Time,
+    },
+    receipt::{sign_hpke_configs, DapCollectionReceipt, DapUploadReceipt},
     roles::{DapAggregator, DapHelper, DapLeader},
-    DapCollectJob, DapError, DapResponse, DapVersion,
+    storage::DapCollectionJobQueue,
+    DapBatchLifetimeConfig, DapCollectJob, DapDpConfig, DapError, DapReplayFilterConfig,
+    DapRequest, DapResource, DapResponse, DapTaskConfig, DapVersion,
 };
 use once_cell::sync::OnceCell;
-use prio::codec::ParameterizedEncode;
+use prio::codec::{Decode, ParameterizedEncode};
 use serde::{Deserialize, Serialize};
-use std::str;
+use std::{cell::RefCell, str};
 use tracing::{debug, error, info_span, Instrument};
 use worker::*;
 
@@ -179,6 +545,13 @@ pub struct DaphneWorkerReportSelector {
 
     /// Maximum number of reports to drain for each aggregation job.
     pub max_reports: u64,
+
+    /// Cursor returned by the previous run of the processing loop, in the `cursor` field of
+    /// [`DapLeaderProcessTelemetry`](daphne::DapLeaderProcessTelemetry). If set, then aggregation
+    /// jobs are fetched from where the previous run stopped rather than from the front of the
+    /// queue.
+    #[serde(default)]
+    pub cursor: Option<String>,
 }
 
 /// HTTP request handler for Daphne-Worker.
@@ -190,12 +563,36 @@ pub struct DaphneWorkerRouter {
     /// If true, then respond to unhandled requests with 200 OK instead of 404 Not Found. The
     /// response body can be overrided by setting environment variable DAP_DEFAULT_RESPONSE_HTML.
     pub enable_default_response: bool,
+
+    /// Handlers for report extensions other than taskprov. Reports carrying an extension with no
+    /// handler are rejected.
+    pub extension_registry: DapExtensionRegistry,
+
+    /// Provider of HPKE decryption, e.g., backed by an external key management service. If not
+    /// set, then HPKE receiver configs are stored in KV. See [`DaphneWorkerHpkeProvider`].
+    pub hpke_provider: Option<Box<dyn DaphneWorkerHpkeProvider>>,
+
+    /// Source of the current time. If not set, then the clock of the Workers runtime is used.
+    /// Tests may set a [`MockClock`](daphne::clock::MockClock) in order to control time.
+    pub clock: Option<Box<dyn Clock>>,
+
+    /// Path prefix under which the endpoints are mounted, e.g., "/dap". Must either be empty or
+    /// start with "/" and have no trailing "/". See [`DapRouter::with_path_prefix`].
+    pub path_prefix: String,
+
+    /// Middleware run around the handling of each request. See [`DapMiddleware`].
+    pub middleware: Vec<Box<dyn DapMiddleware>>,
+
+    /// Roles whose endpoints are not mounted, even if `DAP_AGGREGATOR_ROLE` is set to one of them.
+    pub disabled_roles: Vec<DapAggregatorRole>,
 }
 
 /// The response body for unhandled requests when [`DaphneWorkerRouter::enable_default_response`]
 /// is set. This value can be overrided by DAP_DEFAULT_RESPONSE_HTML.
 pub const DEFAULT_RESPONSE_HTML: &str = "<body>Daphne-Worker</body>";
 
+const ERR_READ_ONLY: &str = "service is in read-only mode";
+
 static ISOLATE_STATE: OnceCell<DaphneWorkerIsolateState> = OnceCell::new();
 
 impl DaphneWorkerRouter {
@@ -222,19 +619,57 @@ impl DaphneWorkerRouter {
         // it's definitely ready for use even if the caller hasn't done anything.
         initialize_tracing(&env);
 
-        #[allow(unused_assignments)]
         let mut uncached_isolate_state: Option<DaphneWorkerIsolateState> = None;
-        let shared_state = if env.var("DAP_NO_CACHE").is_ok() {
-            debug!("isolate state caching is disabled");
-            uncached_isolate_state = Some(DaphneWorkerIsolateState::from_worker_env(&env)?);
-            uncached_isolate_state.as_ref().unwrap()
-        } else {
-            ISOLATE_STATE.get_or_try_init(|| DaphneWorkerIsolateState::from_worker_env(&env))?
-        };
-        let state = DaphneWorkerRequestState::new(shared_state, &req)?;
+        let shared_state = get_isolate_state(&env, &mut uncached_isolate_state)?;
+        let mut state = DaphneWorkerRequestState::new(
+            shared_state,
+            &self.extension_registry,
+            self.hpke_provider.as_deref(),
+            self.clock.as_deref(),
+            &req,
+        )?;
+        state.load_global_config_override(&env).await?;
+        state.load_storage_migration(&env).await?;
+        state.path_prefix = &self.path_prefix;
+
+        // Each route is mounted under the path prefix.
+        let route = |path: &str| format!("{}{path}", self.path_prefix);
 
+        // In read-only mode, only GET and HEAD requests are served, along with the routes for other
+        // methods that are mounted with `read_only_route`, i.e., that do not modify storage.
+        let read_only_routes = RefCell::new(matchit::Router::new());
+        let read_only_route = |method: Method, path: &str| -> Result<String> {
+            let path = route(path);
+            read_only_routes
+                .borrow_mut()
+                .insert(path.clone(), method)
+                .map_err(|e| Error::RustError(e.to_string()))?;
+            Ok(path)
+        };
         let router = Router::with_data(&state)
-            .get_async("/:version/hpke_config", |req, ctx| async move {
+            // Health and readiness probes. These are not authenticated.
+            .get_async(&route("/healthz"), |_req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                let report = daph
+                    .internal_health(false)
+                    .instrument(info_span!("healthz"))
+                    .await;
+                Ok(Response::from_json(&report)?.with_status(report.http_status()))
+            })
+            .get_async(&route("/readyz"), |_req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                let report = daph
+                    .internal_health(true)
+                    .instrument(info_span!("readyz"))
+                    .await;
+                Ok(Response::from_json(&report)?.with_status(report.http_status()))
+            })
+            // List the VDAFs that tasks may be configured with. This is not authenticated.
+            .get_async(
+                &route("/.well-known/daphne/supported_vdafs"),
+                |_req, _ctx| async move { Response::from_json(&daphne::vdaf::supported_vdafs()) },
+            )
+            .get_async(&route("/:version/hpke_config"), |req, ctx| async move {
                 let daph = ctx.data.handler(&ctx.env);
                 let req = daph.worker_request_to_dap(req, &ctx).await?;
                 match daph
@@ -242,23 +677,14 @@ impl DaphneWorkerRouter {
                     .instrument(info_span!("hpke_config"))
                     .await
                 {
-                    Ok(req) => dap_response_to_worker(req),
+                    Ok(resp) => hpke_config_to_worker(&daph, resp),
                     Err(e) => daph.state.dap_abort_to_worker_response(e),
                 }
             })
-            .post_async("/task", |mut req, ctx| async move {
+            .post_async(&route("/task"), |mut req, ctx| async move {
                 let daph = ctx.data.handler(&ctx.env);
-                let admin_token = req
-                    .headers()
-                    .get("X-Daphne-Worker-Admin-Bearer-Token")?
-                    .map(BearerToken::from);
-
-                if daph.config().admin_token.is_none() {
-                    return Response::error("admin not configured", 400);
-                }
-
-                if admin_token.is_none() || admin_token != daph.config().admin_token {
-                    return Response::error("missing or invalid bearer token for admin", 401);
+                if let Some(resp) = check_admin_token(&req, &daph)? {
+                    return Ok(resp);
                 }
 
                 let cmd: InternalTestAddTask = req.json().await?;
@@ -266,14 +692,637 @@ impl DaphneWorkerRouter {
                     .instrument(info_span!("task"))
                     .await?;
                 Response::empty()
-            });
+            })
+            // Admin API for auditing and retiring tasks.
+            .get_async(&route("/task"), |req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = check_admin_token(&req, &daph)? {
+                    return Ok(resp);
+                }
+                let task_ids: Vec<String> = daph
+                    .internal_list_tasks()
+                    .await?
+                    .iter()
+                    .map(TaskId::to_base64url)
+                    .collect();
+                Response::from_json(&task_ids)
+            })
+            .get_async(&route("/task/:task_id"), |req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = check_admin_token(&req, &daph)? {
+                    return Ok(resp);
+                }
+                let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                    Some(id) => id,
+                    None => return admin_error(InternalErrorCode::BadRequest, "invalid task ID"),
+                };
+                match daph.internal_get_task(&task_id).await? {
+                    Some(task) => Response::from_json(&task),
+                    None => admin_error(InternalErrorCode::UnrecognizedTask, "unrecognized task"),
+                }
+            })
+            .delete_async(&route("/task/:task_id"), |req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = check_admin_token(&req, &daph)? {
+                    return Ok(resp);
+                }
+                let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                    Some(id) => id,
+                    None => return admin_error(InternalErrorCode::BadRequest, "invalid task ID"),
+                };
+                if daph
+                    .internal_delete_task(&task_id)
+                    .instrument(info_span!("task_delete"))
+                    .await?
+                {
+                    Response::empty()
+                } else {
+                    admin_error(InternalErrorCode::UnrecognizedTask, "unrecognized task")
+                }
+            })
+            .post_async(
+                &route("/task/:task_id/migrate"),
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    let cmd: InternalTestMigrateTask = req.json().await?;
+                    match daph
+                        .internal_migrate_task(&task_id, cmd.version, cmd.drain)
+                        .instrument(info_span!("task_migrate"))
+                        .await?
+                    {
+                        Some(task) => Response::from_json(&task),
+                        None => {
+                            admin_error(InternalErrorCode::UnrecognizedTask, "unrecognized task")
+                        }
+                    }
+                },
+            )
+            .post_async(
+                &route("/task/:task_id/force_close_batch"),
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    let cmd: ForcedBatch = req.json().await?;
+                    let batch_id = match cmd.batch_id.as_deref().map(BatchId::try_from_base64url) {
+                        Some(Some(id)) => Some(id),
+                        Some(None) => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid batch ID")
+                        }
+                        None => None,
+                    };
+                    match daph
+                        .internal_force_close_batch(&task_id, batch_id)
+                        .instrument(info_span!("task_force_close_batch"))
+                        .await
+                    {
+                        Ok(batch_id) => Response::from_json(&ForcedBatch {
+                            batch_id: batch_id.map(|id| id.to_base64url()),
+                        }),
+                        Err(DapError::Abort(DapAbort::UnrecognizedTask)) => {
+                            admin_error(InternalErrorCode::UnrecognizedTask, "unrecognized task")
+                        }
+                        Err(DapError::Abort(
+                            DapAbort::BadRequest(detail) | DapAbort::QueryMismatch { detail, .. },
+                        )) => admin_error(InternalErrorCode::BadRequest, detail),
+                        Err(e) => Err(int_err(e)),
+                    }
+                },
+            )
+            .post_async(
+                &route("/task/:task_id/collectors"),
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    let cmd: InternalTestCollector = req.json().await?;
+                    if daph
+                        .internal_add_task_collector(&task_id, cmd)
+                        .instrument(info_span!("task_collector_add"))
+                        .await?
+                    {
+                        Response::empty()
+                    } else {
+                        admin_error(InternalErrorCode::UnrecognizedTask, "unrecognized task")
+                    }
+                },
+            )
+            .delete_async(
+                &route("/task/:task_id/collectors/:collector_id"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    let collector_id = ctx.param("collector_id").unwrap();
+                    if daph
+                        .internal_delete_task_collector(&task_id, collector_id)
+                        .instrument(info_span!("task_collector_delete"))
+                        .await?
+                    {
+                        Response::empty()
+                    } else {
+                        admin_error(
+                            InternalErrorCode::NotFound,
+                            "unrecognized task or collector",
+                        )
+                    }
+                },
+            )
+            .post_async(
+                &route("/:version/hpke_receiver_configs"),
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+
+                    let version = daph.extract_version_parameter(&req)?;
+                    let configs: Vec<HpkeReceiverConfigWithValidity> = req.json().await?;
+                    daph.import_hpke_receiver_configs(version, configs)
+                        .instrument(info_span!("hpke_receiver_configs"))
+                        .await?;
+                    Response::empty()
+                },
+            )
+            .post_async(
+                &route("/:version/hpke_receiver_configs/rotate"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+
+                    // Returns the config that was generated, if any.
+                    let version = daph.extract_version_parameter(&req)?;
+                    let hpke_config = daph
+                        .rotate_hpke_receiver_configs(version)
+                        .instrument(info_span!("hpke_receiver_configs_rotate"))
+                        .await?;
+                    Response::from_json(&hpke_config)
+                },
+            )
+            // Admin API for checking how a batch of reports would be aggregated.
+            .post_async(
+                &route("/admin/tasks/:task_id/dry_run"),
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    let batch_id = match req
+                        .url()?
+                        .query_pairs()
+                        .find(|(name, _value)| name == "batch_id")
+                    {
+                        Some((_name, value)) => match BatchId::try_from_base64url(value) {
+                            Some(batch_id) => Some(batch_id),
+                            None => {
+                                return admin_error(
+                                    InternalErrorCode::BadRequest,
+                                    "invalid batch ID",
+                                )
+                            }
+                        },
+                        None => None,
+                    };
+                    let report_batch = match ReportBatch::get_decoded(&req.bytes().await?) {
+                        Ok(report_batch) => report_batch,
+                        Err(e) => {
+                            return admin_error(
+                                InternalErrorCode::BadRequest,
+                                format!("malformed report batch: {e}"),
+                            )
+                        }
+                    };
+                    match daph
+                        .internal_dry_run_aggregation(&task_id, batch_id, &report_batch)
+                        .instrument(info_span!("dry_run_aggregation"))
+                        .await
+                    {
+                        Ok(statuses) => Response::from_json(&statuses),
+                        Err(DapError::Abort(DapAbort::UnrecognizedTask)) => {
+                            admin_error(InternalErrorCode::UnrecognizedTask, "unrecognized task")
+                        }
+                        Err(DapError::Abort(DapAbort::BadRequest(detail))) => {
+                            admin_error(InternalErrorCode::BadRequest, detail)
+                        }
+                        Err(e) => Err(int_err(e)),
+                    }
+                },
+            )
+            .post_async(&route("/admin/tasks/manifest"), |mut req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = check_admin_token(&req, &daph)? {
+                    return Ok(resp);
+                }
+                let apply = req
+                    .url()?
+                    .query_pairs()
+                    .any(|(name, value)| name == "apply" && value == "true");
+                let entries = match DapTaskConfig::from_manifest(&req.bytes().await?) {
+                    Ok(entries) => entries,
+                    Err(e) => return admin_error(InternalErrorCode::BadRequest, e.to_string()),
+                };
+                let mut diff = daph.internal_diff_task_manifest(&entries).await?;
+                if let Err(e) = daph.check_task_manifest_tokens(&entries, &diff) {
+                    return admin_error(InternalErrorCode::BadRequest, e);
+                }
+                if apply {
+                    daph.internal_apply_task_manifest(&entries, &diff)
+                        .instrument(info_span!("task_manifest"))
+                        .await?;
+                    diff.applied = true;
+                }
+                Response::from_json(&diff)
+            })
+            // Admin API for usage reports.
+            .get_async(
+                &route("/admin/tasks/:task_id/billing"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    match daph.get_task_billing(&task_id).await? {
+                        Some(report) => Response::from_json(&report),
+                        None => {
+                            admin_error(InternalErrorCode::NotFound, "no usage report for task")
+                        }
+                    }
+                },
+            )
+            .post_async(
+                &route("/admin/tasks/:task_id/billing"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    match daph
+                        .rollup_task_billing(&task_id)
+                        .instrument(info_span!("billing_rollup"))
+                        .await?
+                    {
+                        Some(report) => Response::from_json(&report),
+                        None => {
+                            admin_error(InternalErrorCode::UnrecognizedTask, "unrecognized task")
+                        }
+                    }
+                },
+            )
+            // Admin API for cumulative per-task report counts.
+            .get_async(
+                &route("/internal/telemetry/aggregation"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    if !daph.config().aggregation_telemetry_enabled {
+                        return admin_error(
+                            InternalErrorCode::NotConfigured,
+                            "aggregation telemetry not enabled",
+                        );
+                    }
+                    let telemetry = daph
+                        .internal_aggregation_telemetry()
+                        .instrument(info_span!("aggregation_telemetry"))
+                        .await?;
+                    Response::from_json(&telemetry)
+                },
+            )
+            // Admin API for reports rejected during aggregation.
+            .get_async(
+                &route("/internal/dead_letters/task/:task_id"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    if daph.config().dead_letter_ttl_secs.is_none() {
+                        return admin_error(
+                            InternalErrorCode::NotConfigured,
+                            "dead-letter store not enabled",
+                        );
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    let dead_letters = daph
+                        .internal_list_dead_letters(&task_id)
+                        .instrument(info_span!("dead_letters_list"))
+                        .await?;
+                    Response::from_json(&DeadLetters { dead_letters })
+                },
+            )
+            .delete_async(
+                &route("/internal/dead_letters/task/:task_id"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    daph.internal_purge_dead_letters(&task_id)
+                        .instrument(info_span!("dead_letters_purge"))
+                        .await?;
+                    Response::empty()
+                },
+            )
+            // Admin API for the metadata of sampled uploads.
+            .get_async(
+                &route("/internal/report_samples/task/:task_id"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    let stats = daph
+                        .internal_report_sample_stats(&task_id)
+                        .instrument(info_span!("report_samples"))
+                        .await?;
+                    Response::from_json(&stats)
+                },
+            )
+            .delete_async(
+                &route("/internal/report_samples/task/:task_id"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let task_id = match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
+                        Some(id) => id,
+                        None => {
+                            return admin_error(InternalErrorCode::BadRequest, "invalid task ID")
+                        }
+                    };
+                    daph.internal_purge_report_samples(&task_id)
+                        .instrument(info_span!("report_samples_purge"))
+                        .await?;
+                    Response::empty()
+                },
+            )
+            // Admin API for updating the global DAP configuration at runtime.
+            .get_async(&route("/internal/global_config"), |req, ctx| async move {
+                let daph = ctx.data.handler(&ctx.env);
+                if let Some(resp) = check_admin_token(&req, &daph)? {
+                    return Ok(resp);
+                }
+                Response::from_json(&daph.get_global_config_override().await?)
+            })
+            // Served in read-only mode so that the administrator can lift it.
+            .put_async(
+                &read_only_route(Method::Put, "/internal/global_config")?,
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let global_config_override: GlobalConfigOverride = req.json().await?;
+                    if daph
+                        .set_global_config_override(global_config_override)
+                        .instrument(info_span!("global_config"))
+                        .await?
+                    {
+                        Response::empty()
+                    } else {
+                        admin_error(
+                            InternalErrorCode::Conflict,
+                            "version stamp does not succeed the current override",
+                        )
+                    }
+                },
+            )
+            // Admin API for migrating the report storage to a new number of shards.
+            .get_async(
+                &route("/internal/storage_migration"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let status = daph
+                        .internal_storage_migration_status()
+                        .instrument(info_span!("storage_migration_status"))
+                        .await?;
+                    Response::from_json(&status)
+                },
+            )
+            .put_async(
+                &route("/internal/storage_migration"),
+                |mut req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    let migration_req: StorageMigrationReq = req.json().await?;
+                    if migration_req.report_shard_count == 0 {
+                        return admin_error(
+                            InternalErrorCode::BadRequest,
+                            "report_shard_count must be positive",
+                        );
+                    }
+                    match daph
+                        .internal_start_storage_migration(migration_req)
+                        .instrument(info_span!("storage_migration_start"))
+                        .await?
+                    {
+                        None => Response::empty(),
+                        Some(reason) => admin_error(InternalErrorCode::Conflict, reason),
+                    }
+                },
+            )
+            .post_async(
+                &route("/internal/storage_migration/cutover"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    match daph
+                        .internal_cutover_storage_migration()
+                        .instrument(info_span!("storage_migration_cutover"))
+                        .await?
+                    {
+                        None => Response::empty(),
+                        Some(reason) => admin_error(InternalErrorCode::Conflict, reason),
+                    }
+                },
+            )
+            .delete_async(
+                &route("/internal/storage_migration"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    match daph
+                        .internal_delete_storage_migration()
+                        .instrument(info_span!("storage_migration_delete"))
+                        .await?
+                    {
+                        None => Response::empty(),
+                        Some(reason) => admin_error(InternalErrorCode::Conflict, reason),
+                    }
+                },
+            )
+            // Admin API for purging the state of expired tasks.
+            .post_async(
+                &route("/internal/garbage_collect_tasks"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    if daph.config().task_garbage_collect_after_secs.is_none() {
+                        return admin_error(
+                            InternalErrorCode::NotConfigured,
+                            "task garbage collection not configured",
+                        );
+                    }
+                    let gc = daph
+                        .internal_garbage_collect_tasks()
+                        .instrument(info_span!("garbage_collect_tasks"))
+                        .await?;
+                    Response::from_json(&gc)
+                },
+            )
+            // Admin API for notifying the operator of taskprov tasks that are about to expire.
+            .post_async(
+                &route("/internal/notify_expiring_taskprov_tasks"),
+                |req, ctx| async move {
+                    let daph = ctx.data.handler(&ctx.env);
+                    if let Some(resp) = check_admin_token(&req, &daph)? {
+                        return Ok(resp);
+                    }
+                    if !daph.config().taskprov_expiry_notification_enabled() {
+                        return admin_error(
+                            InternalErrorCode::NotConfigured,
+                            "taskprov expiry notifications not configured",
+                        );
+                    }
+                    let notifications = daph
+                        .internal_notify_expiring_taskprov_tasks()
+                        .instrument(info_span!("notify_expiring_taskprov_tasks"))
+                        .await?;
+                    Response::from_json(&notifications)
+                },
+            );
 
         let router = match env.var("DAP_AGGREGATOR_ROLE")?.to_string().as_ref() {
-            "leader" => {
+            role if self
+                .disabled_roles
+                .iter()
+                .any(|disabled| disabled.as_str() == role) =>
+            {
                 router
-                    .post_async("/v02/upload", put_report_into_task) // draft02
-                    .put_async("/:version/tasks/:task_id/reports", put_report_into_task)
-                    .post_async("/v02/collect", |req, ctx| async move {
+            }
+            "leader" => {
+                let router = router
+                    // Admin API for excluding buckets of reports from aggregation.
+                    .get_async(&route("/quarantine"), |req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = check_admin_token(&req, &daph)? {
+                            return Ok(resp);
+                        }
+                        Response::from_json(&daph.get_quarantine().await?)
+                    })
+                    .put_async(&route("/quarantine"), |mut req, ctx| async move {
+                        let daph = ctx.data.handler(&ctx.env);
+                        if let Some(resp) = check_admin_token(&req, &daph)? {
+                            return Ok(resp);
+                        }
+                        let quarantine: Vec<QuarantinedBuckets> = req.json().await?;
+                        daph.set_quarantine(quarantine)
+                            .instrument(info_span!("quarantine"))
+                            .await?;
+                        Response::empty()
+                    })
+                    .post_async(&route("/v02/upload"), put_report_into_task) // draft02
+                    .put_async(
+                        &route("/:version/tasks/:task_id/reports"),
+                        put_report_into_task,
+                    )
+                    .post_async(
+                        &route("/:version/tasks/:task_id/reports/batch"),
+                        |req, ctx| async move {
+                            let daph = ctx.data.handler(&ctx.env);
+                            let req = daph.worker_request_to_dap(req, &ctx).await?;
+
+                            match daph
+                                .http_post_upload_batch(&req)
+                                .instrument(info_span!("upload_batch"))
+                                .await
+                            {
+                                Ok(statuses) => Response::from_json(&statuses),
+                                Err(e) => daph.state.dap_abort_to_worker_response(e),
+                            }
+                        },
+                    )
+                    .post_async(&route("/v02/collect"), |req, ctx| async move {
                         let daph = ctx.data.handler(&ctx.env);
                         let req = daph.worker_request_to_dap(req, &ctx).await?;
 
@@ -294,7 +1343,7 @@ impl DaphneWorkerRouter {
                         }
                     }) // draft02
                     .get_async(
-                        "/v02/collect/task/:task_id/req/:collect_id",
+                        &route("/v02/collect/task/:task_id/req/:collect_id"),
                         |req, ctx| async move {
                             let task_id =
                                 match ctx.param("task_id").and_then(TaskId::try_from_base64url) {
@@ -322,17 +1371,26 @@ impl DaphneWorkerRouter {
                             };
                             let daph = ctx.data.handler(&ctx.env);
                             let version = daph.extract_version_parameter(&req)?;
+                            let format = match collection_format(&req, &daph, version)? {
+                                Ok(format) => format,
+                                Err(resp) => return Ok(resp),
+                            };
                             match daph
                                 .poll_collect_job(&task_id, &collect_id)
                                 .instrument(info_span!("poll_collect_job (draft02)"))
                                 .await
                             {
                                 Ok(DapCollectJob::Done(collect_resp)) => {
-                                    dap_response_to_worker(DapResponse {
-                                        version: DapVersion::Draft02,
-                                        media_type: DapMediaType::Collection,
-                                        payload: collect_resp.get_encoded_with_param(&version),
-                                    })
+                                    collection_to_worker(
+                                        &daph,
+                                        DapVersion::Draft02,
+                                        &task_id,
+                                        &collect_id,
+                                        &collect_resp,
+                                        format,
+                                        collect_resp.get_encoded_with_param(&version),
+                                    )
+                                    .await
                                 }
                                 Ok(DapCollectJob::Pending) => {
                                     Ok(Response::empty().unwrap().with_status(202))
@@ -348,7 +1406,7 @@ impl DaphneWorkerRouter {
                         },
                     ) // draft02
                     .put_async(
-                        "/:version/tasks/:task_id/collection_jobs/:collect_job_id",
+                        &route("/:version/tasks/:task_id/collection_jobs/:collect_job_id"),
                         |req, ctx| async move {
                             let daph = ctx.data.handler(&ctx.env);
                             let req = daph.worker_request_to_dap(req, &ctx).await?;
@@ -363,10 +1421,19 @@ impl DaphneWorkerRouter {
                             }
                         },
                     )
+                    // Polling a collection job does not modify storage.
                     .post_async(
-                        "/:version/tasks/:task_id/collection_jobs/:collect_job_id",
+                        &read_only_route(
+                            Method::Post,
+                            "/:version/tasks/:task_id/collection_jobs/:collect_job_id",
+                        )?,
                         |req, ctx| async move {
                             let daph = ctx.data.handler(&ctx.env);
+                            let version = daph.extract_version_parameter(&req)?;
+                            let format = match collection_format(&req, &daph, version)? {
+                                Ok(format) => format,
+                                Err(resp) => return Ok(resp),
+                            };
                             let req = daph.worker_request_to_dap(req, &ctx).await?;
                             let task_id = match req.task_id() {
                                 Ok(id) => id,
@@ -394,11 +1461,16 @@ impl DaphneWorkerRouter {
                                 .await
                             {
                                 Ok(DapCollectJob::Done(collect_resp)) => {
-                                    dap_response_to_worker(DapResponse {
-                                        version: req.version,
-                                        media_type: DapMediaType::Collection,
-                                        payload: collect_resp.get_encoded_with_param(&req.version),
-                                    })
+                                    collection_to_worker(
+                                        &daph,
+                                        req.version,
+                                        task_id,
+                                        &collect_job_id,
+                                        &collect_resp,
+                                        format,
+                                        collect_resp.get_encoded_with_param(&req.version),
+                                    )
+                                    .await
                                 }
                                 Ok(DapCollectJob::Pending) => {
                                     Ok(Response::empty().unwrap().with_status(202))
@@ -413,24 +1485,76 @@ impl DaphneWorkerRouter {
                             }
                         },
                     )
-                    .post_async("/internal/process", |mut req, ctx| async move {
-                        // TODO(cjpatton) Only enable this if `self.enable_internal_test` is set.
-                        let daph = ctx.data.handler(&ctx.env);
-                        let report_sel: DaphneWorkerReportSelector = req.json().await?;
-                        match daph
-                            .process(&report_sel, &daph.state.host)
-                            .instrument(info_span!("process"))
-                            .await
-                        {
-                            Ok(telem) => {
-                                debug!("{:?}", telem);
-                                Response::from_json(&telem)
+                    .get_async(
+                        &route("/:version/tasks/:task_id/collection_jobs/:collect_job_id/status"),
+                        |req, ctx| async move {
+                            let daph = ctx.data.handler(&ctx.env);
+                            let mut req = daph.worker_request_to_dap(req, &ctx).await?;
+                            // The request has no body and hence no media type. It is sent by the
+                            // Collector.
+                            req.media_type = DapMediaType::CollectReq;
+
+                            match daph
+                                .http_get_collect_job_status(&req)
+                                .instrument(info_span!("collect_job_status"))
+                                .await
+                            {
+                                Ok(status) => Response::from_json(&status),
+                                Err(e) => daph.state.dap_abort_to_worker_response(e),
                             }
-                            Err(e) => daph.state.dap_abort_to_worker_response(e),
-                        }
-                    })
+                        },
+                    )
                     .get_async(
-                        "/internal/current_batch/task/:task_id",
+                        &route("/:version/tasks/:task_id/batch_suggestions"),
+                        |req, ctx| async move {
+                            let daph = ctx.data.handler(&ctx.env);
+                            let mut req = daph.worker_request_to_dap(req, &ctx).await?;
+                            // The request has no body and hence no media type. It is sent by the
+                            // Collector.
+                            req.media_type = DapMediaType::CollectReq;
+
+                            match daph
+                                .http_get_batch_suggestions(&req)
+                                .instrument(info_span!("batch_suggestions"))
+                                .await
+                            {
+                                Ok(suggestions) => Response::from_json(&suggestions),
+                                Err(e) => daph.state.dap_abort_to_worker_response(e),
+                            }
+                        },
+                    )
+                    .delete_async(
+                        &route("/:version/tasks/:task_id/collection_jobs/:collect_job_id"),
+                        |req, ctx| async move {
+                            let daph = ctx.data.handler(&ctx.env);
+                            let mut req = daph.worker_request_to_dap(req, &ctx).await?;
+                            // The request has no body and hence no media type. It is sent by the
+                            // Collector.
+                            req.media_type = DapMediaType::CollectReq;
+                            req.resource = match ctx
+                                .param("collect_job_id")
+                                .and_then(CollectionJobId::try_from_base64url)
+                            {
+                                Some(id) => DapResource::CollectionJob(id),
+                                None => {
+                                    return daph.state.dap_abort_to_worker_response(
+                                        DapAbort::BadRequest("malformed collect id".into()),
+                                    )
+                                }
+                            };
+
+                            match daph
+                                .http_delete_collect_job(&req)
+                                .instrument(info_span!("collect (DELETE)"))
+                                .await
+                            {
+                                Ok(()) => Ok(Response::empty().unwrap().with_status(204)),
+                                Err(e) => daph.state.dap_abort_to_worker_response(e),
+                            }
+                        },
+                    )
+                    .get_async(
+                        &route("/internal/current_batch/task/:task_id"),
                         |_req, ctx| async move {
                             // Return the ID of the oldest, not-yet-collecgted batch for the specified
                             // task. The task ID and batch ID are both encoded in URL-safe base64.
@@ -459,22 +1583,49 @@ impl DaphneWorkerRouter {
                                 Err(e) => daph.state.dap_abort_to_worker_response(e.into()),
                             }
                         },
-                    )
+                    );
+
+                // The HTTP variant of the processing trigger is only meant for test environments.
+                // In production, processing is triggered via a service binding; see
+                // [`DaphneWorkerRouter::handle_process_request`].
+                if self.enable_internal_test {
+                    router
+                        .post_async(&route("/internal/process"), |mut req, ctx| async move {
+                            let daph = ctx.data.handler(&ctx.env);
+                            let report_sel: DaphneWorkerReportSelector = req.json().await?;
+                            process_and_respond(&daph, &report_sel).await
+                        })
+                        // Used to test that the Helper rejects requests with an invalid bearer
+                        // token.
+                        .post_async(
+                            &route("/internal/test/corrupt_leader_bearer_token"),
+                            |mut req, ctx| async move {
+                                let daph = ctx.data.handler(&ctx.env);
+                                let cmd: InternalTestCorruptLeaderBearerToken = req.json().await?;
+                                daph.internal_corrupt_leader_bearer_token(cmd)
+                                    .instrument(info_span!("corrupt_leader_bearer_token"))
+                                    .await?;
+                                Response::from_json(&InternalResponse::success(()))
+                            },
+                        )
+                } else {
+                    router
+                }
             }
 
             "helper" => router
-                .post_async("/:version/aggregate", handle_agg_job) // draft02
-                .post_async("/:version/aggregate_share", handle_agg_share_req) // draft02
+                .post_async(&route("/:version/aggregate"), handle_agg_job) // draft02
+                .post_async(&route("/:version/aggregate_share"), handle_agg_share_req) // draft02
                 .put_async(
-                    "/:version/tasks/:task_id/aggregation_jobs/:agg_job_id",
+                    &route("/:version/tasks/:task_id/aggregation_jobs/:agg_job_id"),
                     handle_agg_job,
                 )
                 .post_async(
-                    "/:version/tasks/:task_id/aggregation_jobs/:agg_job_id",
+                    &route("/:version/tasks/:task_id/aggregation_jobs/:agg_job_id"),
                     handle_agg_job,
                 )
                 .post_async(
-                    "/:version/tasks/:task_id/aggregate_shares",
+                    &route("/:version/tasks/:task_id/aggregate_shares"),
                     handle_agg_share_req,
                 ),
 
@@ -483,7 +1634,7 @@ impl DaphneWorkerRouter {
 
         let router = if self.enable_internal_test {
             router
-                .post_async("/internal/delete_all", |_req, ctx| async move {
+                .post_async(&route("/internal/delete_all"), |_req, ctx| async move {
                     let daph = ctx.data.handler(&ctx.env);
                     match daph
                         .internal_delete_all()
@@ -495,11 +1646,12 @@ impl DaphneWorkerRouter {
                     }
                 })
                 // Endpoints for draft-dcook-ppm-dap-interop-test-design-02
-                .post_async("/internal/test/ready", |_req, _ctx| async move {
-                    Response::from_json(&())
-                })
                 .post_async(
-                    "/internal/test/endpoint_for_task",
+                    &read_only_route(Method::Post, "/internal/test/ready")?,
+                    |_req, _ctx| async move { Response::from_json(&()) },
+                )
+                .post_async(
+                    &read_only_route(Method::Post, "/internal/test/endpoint_for_task")?,
                     |mut req, ctx| async move {
                         let daph = ctx.data.handler(&ctx.env);
                         let cmd: InternalTestEndpointForTask = req.json().await?;
@@ -509,7 +1661,7 @@ impl DaphneWorkerRouter {
                     },
                 )
                 .post_async(
-                    "/:version/internal/test/endpoint_for_task",
+                    &read_only_route(Method::Post, "/:version/internal/test/endpoint_for_task")?,
                     |mut req, ctx| async move {
                         let daph = ctx.data.handler(&ctx.env);
                         let cmd: InternalTestEndpointForTask = req.json().await?;
@@ -519,28 +1671,32 @@ impl DaphneWorkerRouter {
                             .await
                     },
                 )
-                .post_async("/internal/test/add_task", |mut req, ctx| async move {
+                .post_async(&route("/internal/test/add_task"), |mut req, ctx| async move {
                     let daph = ctx.data.handler(&ctx.env);
-                    let cmd: InternalTestAddTask = req.json().await?;
-                    daph.internal_add_task(daph.config().default_version, cmd)
-                        .instrument(info_span!("add_task"))
-                        .await?;
-                    Response::from_json(&serde_json::json!({
-                        "status": "success",
-                    }))
+                    let cmd: InternalTestAddTask = match req.json().await {
+                        Ok(cmd) => cmd,
+                        Err(e) => return interop_test_response(Err(e)),
+                    };
+                    interop_test_response(
+                        daph.internal_add_task(daph.config().default_version, cmd)
+                            .instrument(info_span!("add_task"))
+                            .await,
+                    )
                 })
                 .post_async(
-                    "/:version/internal/test/add_task",
+                    &route("/:version/internal/test/add_task"),
                     |mut req, ctx| async move {
                         let daph = ctx.data.handler(&ctx.env);
-                        let cmd: InternalTestAddTask = req.json().await?;
+                        let cmd: InternalTestAddTask = match req.json().await {
+                            Ok(cmd) => cmd,
+                            Err(e) => return interop_test_response(Err(e)),
+                        };
                         let version = daph.extract_version_parameter(&req)?;
-                        daph.internal_add_task(version, cmd)
-                            .instrument(info_span!("add_task"))
-                            .await?;
-                        Response::from_json(&serde_json::json!({
-                            "status": "success",
-                        }))
+                        interop_test_response(
+                            daph.internal_add_task(version, cmd)
+                                .instrument(info_span!("add_task"))
+                                .await,
+                        )
                     },
                 )
         } else {
@@ -548,7 +1704,7 @@ impl DaphneWorkerRouter {
         };
 
         let router = if self.enable_default_response {
-            router.or_else_any_method_async("/*catchall", |_req, ctx| async move {
+            router.or_else_any_method_async(&route("/*catchall"), |_req, ctx| async move {
                 match ctx.var("DAP_DEFAULT_RESPONSE_HTML") {
                     Ok(text) => Response::from_html(text.to_string()),
                     Err(..) => Response::from_html(DEFAULT_RESPONSE_HTML),
@@ -558,11 +1714,39 @@ impl DaphneWorkerRouter {
             router
         };
 
-        // NOTE that we do not have a tracing span for the whole request because it typically
-        // reports the same times as the span covering the specific API entry point that the
-        // router creates. If curious, you can add .instrument(info_span!("http")) just before
-        // the await and see.
-        let result = router.run(req, env).await;
+        // The request is handled in a span that carries its correlation ID, so that the ID is
+        // included in each log line. Its times are typically the same as those reported by the
+        // span covering the specific API entry point that the router creates.
+        let span = info_span!("request", correlation_id = %state.correlation_id);
+        let mut result = None;
+        let mut middleware_run = 0;
+        for middleware in &self.middleware {
+            match middleware.before(&req, &env).await {
+                Ok(None) => middleware_run += 1,
+                Ok(Some(resp)) => {
+                    result = Some(Ok(resp));
+                    break;
+                }
+                Err(e) => {
+                    result = Some(Err(e));
+                    break;
+                }
+            }
+        }
+        let mut result = match result {
+            Some(result) => result,
+            None if state.read_only && !is_read_only_request(&req, &read_only_routes.borrow())? => {
+                Response::error(ERR_READ_ONLY, 503)
+            }
+            None => router.run(req, env).instrument(span).await,
+        };
+        for middleware in self.middleware[..middleware_run].iter().rev() {
+            result = match result {
+                Ok(resp) => middleware.after(resp).await,
+                Err(e) => Err(e),
+            };
+        }
+        let result = with_correlation_id(result, &state.correlation_id);
 
         state
             .metrics
@@ -582,6 +1766,221 @@ impl DaphneWorkerRouter {
 
         result
     }
+
+    /// Entry point for triggering the Leader's main processing loop via a [service
+    /// binding](https://developers.cloudflare.com/workers/runtime-apis/service-bindings/).
+    ///
+    /// This allows a dedicated scheduler Worker to drive aggregation and collection without the
+    /// Leader exposing a routable internal endpoint. The request body is expected to be a JSON
+    /// [`DaphneWorkerReportSelector`]; the path is ignored. The response body is the
+    /// [`DapLeaderProcessTelemetry`](daphne::DapLeaderProcessTelemetry) encoded in JSON.
+    ///
+    /// The caller is responsible for ensuring that the request came from the service binding. For
+    /// example:
+    ///
+    /// ```ignore
+    /// use daphne_worker::DaphneWorkerRouter;
+    /// use worker::*;
+    ///
+    /// // Deployed without any routes, so that it's only reachable via the service binding.
+    /// #[event(fetch)]
+    /// pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
+    ///     let router = DaphneWorkerRouter::default();
+    ///     router.handle_process_request(req, env).await
+    /// }
+    /// ```
+    pub async fn handle_process_request(&self, mut req: Request, env: Env) -> Result<Response> {
+        initialize_tracing(&env);
+
+        if req.method() != Method::Post {
+            return Response::error("method not allowed", 405);
+        }
+
+        let mut uncached_isolate_state: Option<DaphneWorkerIsolateState> = None;
+        let shared_state = get_isolate_state(&env, &mut uncached_isolate_state)?;
+        let mut state = DaphneWorkerRequestState::new(
+            shared_state,
+            &self.extension_registry,
+            self.hpke_provider.as_deref(),
+            self.clock.as_deref(),
+            &req,
+        )?;
+        state.load_global_config_override(&env).await?;
+        state.load_storage_migration(&env).await?;
+        let daph = state.handler(&env);
+        if !daph.config().is_leader {
+            return Err(Error::RustError(
+                "processing can only be triggered for the Leader".into(),
+            ));
+        }
+        if state.read_only {
+            return Response::error(ERR_READ_ONLY, 503);
+        }
+
+        let report_sel: DaphneWorkerReportSelector = req.json().await?;
+        let result = process_and_respond(&daph, &report_sel)
+            .instrument(info_span!("request", correlation_id = %state.correlation_id))
+            .await;
+        let result = with_correlation_id(result, &state.correlation_id);
+
+        state
+            .metrics
+            .http_status_code_counter
+            .with_label_values(&[
+                &state.host,
+                &format!("{}", result.as_ref().map_or(500, |resp| resp.status_code())),
+            ])
+            .inc();
+        state.maybe_push_metrics().await?;
+
+        result
+    }
+
+    /// Entry point for [scheduled
+    /// events](https://developers.cloudflare.com/workers/runtime-apis/scheduled-event/), e.g.,
+    /// triggered by a Cron Trigger. This runs the task garbage collector if
+    /// `DAP_TASK_GARBAGE_COLLECT_AFTER_SECS` is configured and notifies the operator of expiring
+    /// taskprov tasks if `DAP_TASKPROV_EXPIRY_NOTIFICATION_URL` is configured. For example:
+    ///
+    /// ```ignore
+    /// use daphne_worker::DaphneWorkerRouter;
+    /// use worker::*;
+    ///
+    /// #[event(scheduled)]
+    /// pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    ///     let router = DaphneWorkerRouter::default();
+    ///     if let Err(e) = router.handle_scheduled(env).await {
+    ///         console_error!("scheduled event failed: {e}");
+    ///     }
+    /// }
+    /// ```
+    pub async fn handle_scheduled(&self, env: Env) -> Result<()> {
+        initialize_tracing(&env);
+
+        let mut uncached_isolate_state: Option<DaphneWorkerIsolateState> = None;
+        let shared_state = get_isolate_state(&env, &mut uncached_isolate_state)?;
+        let config = &shared_state.config;
+        let mut state = DaphneWorkerRequestState::with_host(
+            shared_state,
+            &self.extension_registry,
+            self.hpke_provider.as_deref(),
+            self.clock.as_deref(),
+            "scheduled".into(),
+        )?;
+        state.load_global_config_override(&env).await?;
+        if state.read_only {
+            return Ok(());
+        }
+        state.load_storage_migration(&env).await?;
+        let daph = state.handler(&env);
+        async {
+            if config.taskprov_expiry_notification_enabled() {
+                let notifications = daph
+                    .internal_notify_expiring_taskprov_tasks()
+                    .instrument(info_span!("notify_expiring_taskprov_tasks"))
+                    .await?;
+                debug!("{:?}", notifications);
+            }
+            if config.task_garbage_collect_after_secs.is_some() {
+                let gc = daph
+                    .internal_garbage_collect_tasks()
+                    .instrument(info_span!("garbage_collect_tasks"))
+                    .await?;
+                debug!("{:?}", gc);
+            }
+            Ok::<_, Error>(())
+        }
+        .instrument(info_span!("scheduled", correlation_id = %state.correlation_id))
+        .await?;
+        state.maybe_push_metrics().await
+    }
+}
+
+/// Check that the request carries the administrator's bearer token. If not, then return the
+/// response to send instead.
+fn check_admin_token(req: &Request, daph: &DaphneWorker<'_>) -> Result<Option<Response>> {
+    let admin_token = req
+        .headers()
+        .get("X-Daphne-Worker-Admin-Bearer-Token")?
+        .map(BearerToken::from);
+
+    if daph.config().admin_token.is_none() {
+        return Ok(Some(admin_error(
+            InternalErrorCode::AdminNotConfigured,
+            "admin not configured",
+        )?));
+    }
+
+    if admin_token.is_none() || admin_token != daph.config().admin_token {
+        return Ok(Some(admin_error(
+            InternalErrorCode::Unauthorized,
+            "missing or invalid bearer token for admin",
+        )?));
+    }
+
+    Ok(None)
+}
+
+/// Set the correlation ID of the request in the "dap-correlation-id" header of the response.
+fn with_correlation_id(result: Result<Response>, correlation_id: &str) -> Result<Response> {
+    let mut resp = result?;
+    resp.headers_mut()
+        .set(CORRELATION_ID_HEADER, correlation_id)?;
+    Ok(resp)
+}
+
+/// Respond to a failed admin request. The body is an [`InternalResponse`].
+fn admin_error<S: ToString>(error_code: InternalErrorCode, detail: S) -> Result<Response> {
+    Ok(
+        Response::from_json(&InternalResponse::<()>::error(error_code, detail))?
+            .with_status(error_code.http_status()),
+    )
+}
+
+/// Get the isolate state, either from the cache or, if caching is disabled, by constructing it
+/// into `uncached`.
+fn get_isolate_state<'a>(
+    env: &Env,
+    uncached: &'a mut Option<DaphneWorkerIsolateState>,
+) -> Result<&'a DaphneWorkerIsolateState> {
+    if env.var("DAP_NO_CACHE").is_ok() {
+        debug!("isolate state caching is disabled");
+        Ok(uncached.insert(DaphneWorkerIsolateState::from_worker_env(env)?))
+    } else {
+        ISOLATE_STATE.get_or_try_init(|| DaphneWorkerIsolateState::from_worker_env(env))
+    }
+}
+
+/// Check whether the request may be served in read-only mode, i.e., whether it is a GET or HEAD
+/// request or is for one of the `read_only_routes`, which map each path to the method for which it
+/// is mounted.
+fn is_read_only_request(req: &Request, read_only_routes: &matchit::Router<Method>) -> Result<bool> {
+    if matches!(req.method(), Method::Get | Method::Head) {
+        return Ok(true);
+    }
+    Ok(read_only_routes
+        .at(req.url()?.path())
+        .is_ok_and(|matched| *matched.value == req.method()))
+}
+
+async fn process_and_respond(
+    daph: &DaphneWorker<'_>,
+    report_sel: &DaphneWorkerReportSelector,
+) -> Result<Response> {
+    let result = daph
+        .process(report_sel, &daph.state.host)
+        .instrument(info_span!("process"))
+        .await;
+    daph.flush_aggregation_telemetry().await;
+    daph.flush_dead_letters().await;
+    match result {
+        Ok(mut telem) => {
+            telem.cursor = daph.state.process_cursor.take();
+            debug!("{:?}", telem);
+            Response::from_json(&telem)
+        }
+        Err(e) => daph.state.dap_abort_to_worker_response(e),
+    }
 }
 
 async fn put_report_into_task(
@@ -589,31 +1988,64 @@ async fn put_report_into_task(
     ctx: RouteContext<&DaphneWorkerRequestState<'_>>,
 ) -> Result<Response> {
     let daph = ctx.data.handler(&ctx.env);
-    let req = daph.worker_request_to_dap(req, &ctx).await?;
+    let req = match daph.worker_upload_request_to_dap(req, &ctx).await? {
+        Ok(req) => req,
+        Err(e) => return daph.state.dap_abort_to_worker_response(e),
+    };
 
     match daph
         .http_post_upload(&req)
         .instrument(info_span!("upload"))
         .await
     {
-        Ok(()) => Response::empty(),
+        Ok(()) => upload_receipt_to_worker(&daph, &req),
         Err(e) => daph.state.dap_abort_to_worker_response(e),
     }
 }
 
+/// Construct the response for an accepted report. If a receipt signing key is configured, then
+/// the body of the response is a signed [`DapUploadReceipt`] encoded as JSON; otherwise the body
+/// is empty.
+fn upload_receipt_to_worker(
+    daph: &DaphneWorker<'_>,
+    req: &DapRequest<DaphneWorkerAuth>,
+) -> Result<Response> {
+    let signing_key = match daph.config().upload_receipt_signing_key {
+        Some(ref signing_key) => signing_key,
+        None => return Response::empty(),
+    };
+
+    // The report was decoded when it was uploaded, so this is not expected to fail.
+    let metadata = match decode_report_prefix(&req.version, &req.payload) {
+        Ok(Some((_draft02_task_id, metadata))) => metadata,
+        Ok(None) => return Err(int_err("uploaded report is too short")),
+        Err(e) => return Err(int_err(e)),
+    };
+    let task_id = req.task_id().map_err(int_err)?;
+    Response::from_json(&DapUploadReceipt::sign(
+        signing_key,
+        req.version,
+        task_id,
+        &metadata,
+    ))
+}
+
 async fn handle_agg_job(
     req: Request,
     ctx: RouteContext<&DaphneWorkerRequestState<'_>>,
 ) -> Result<Response> {
     let daph = ctx.data.handler(&ctx.env);
+    let accept_encoding = req.headers().get("Accept-Encoding")?;
     let req = daph.worker_request_to_dap(req, &ctx).await?;
 
-    match daph
+    let result = daph
         .http_post_aggregate(&req)
         .instrument(info_span!("aggregate"))
-        .await
-    {
-        Ok(resp) => dap_response_to_worker(resp),
+        .await;
+    daph.flush_aggregation_telemetry().await;
+    daph.flush_dead_letters().await;
+    match result {
+        Ok(resp) => compressed_dap_response_to_worker(resp, accept_encoding.as_deref()),
         Err(e) => daph.state.dap_abort_to_worker_response(e),
     }
 }
@@ -623,20 +2055,143 @@ async fn handle_agg_share_req(
     ctx: RouteContext<&DaphneWorkerRequestState<'_>>,
 ) -> Result<Response> {
     let daph = ctx.data.handler(&ctx.env);
+    let accept_encoding = req.headers().get("Accept-Encoding")?;
     let req = daph.worker_request_to_dap(req, &ctx).await?;
 
-    match daph
+    let result = daph
         .http_post_aggregate_share(&req)
         .instrument(info_span!("aggregate_share"))
-        .await
-    {
-        Ok(resp) => dap_response_to_worker(resp),
+        .await;
+    daph.flush_aggregation_telemetry().await;
+    daph.flush_dead_letters().await;
+    match result {
+        Ok(resp) => compressed_dap_response_to_worker(resp, accept_encoding.as_deref()),
         Err(e) => daph.state.dap_abort_to_worker_response(e),
     }
 }
 
+/// Convert a response to an aggregation request. If the Leader accepts a supported content
+/// coding, then it is set as the content encoding of the response, in which case the runtime
+/// compresses the body.
+fn compressed_dap_response_to_worker(
+    resp: DapResponse,
+    accept_encoding: Option<&str>,
+) -> Result<Response> {
+    let mut worker_resp = dap_response_to_worker(resp)?;
+    if let Some(encoding) = accept_encoding.and_then(ContentEncoding::from_accept_encoding) {
+        worker_resp
+            .headers_mut()
+            .set("Content-Encoding", encoding.as_str())?;
+    }
+    Ok(worker_resp)
+}
+
+/// Construct the response advertising the HPKE configs. If an HPKE config signing key is
+/// configured, then the signature over the payload (see [`daphne::receipt::sign_hpke_configs`]) is
+/// attached in the "X-Daphne-Hpke-Config-Signature" header as URL-safe base64.
+fn hpke_config_to_worker(daph: &DaphneWorker<'_>, resp: DapResponse) -> Result<Response> {
+    let signature = daph
+        .config()
+        .hpke_config_signing_key
+        .as_ref()
+        .map(|signing_key| sign_hpke_configs(signing_key, resp.version, &resp.payload));
+
+    let mut resp = dap_response_to_worker(resp)?;
+    if let Some(signature) = signature {
+        resp.headers_mut().set(
+            "X-Daphne-Hpke-Config-Signature",
+            &encode_base64url(signature),
+        )?;
+    }
+    Ok(resp)
+}
+
+/// Negotiate the encoding of the result of a collection job from the "accept" header of the
+/// request polling the job (see [`DapCollectionFormat`]). The JSON form is for debugging and
+/// requires the admin bearer token. Returns the response to send instead, if the format is not
+/// acceptable or the request is not authorized.
+fn collection_format(
+    req: &Request,
+    daph: &DaphneWorker<'_>,
+    version: DapVersion,
+) -> Result<std::result::Result<DapCollectionFormat, Response>> {
+    let accept = req.headers().get("Accept")?;
+    match DapCollectionFormat::from_accept_for_version(version, accept.as_deref()) {
+        Some(DapCollectionFormat::Json) => match check_admin_token(req, daph)? {
+            Some(resp) => Ok(Err(resp)),
+            None => Ok(Ok(DapCollectionFormat::Json)),
+        },
+        Some(format) => Ok(Ok(format)),
+        None => Ok(Err(Response::error("not acceptable", 406)?)),
+    }
+}
+
+/// Construct the response for a completed collection job. If a receipt signing key is
+/// configured and the batch selector of the job was recorded, then a signed
+/// [`DapCollectionReceipt`] is attached to the response in the "X-Daphne-Collection-Receipt"
+/// header as URL-safe base64 encoded JSON. If the breakdown of the
+/// collection's report count was recorded, then the [`daphne::DapReportCountBreakdown`] is
+/// attached in the "X-Daphne-Report-Counts" header in the same encoding. The `Collection` message
+/// has no field in which to carry either. If `format` is [`DapCollectionFormat::Json`], then the
+/// body is the `Collection` encoded as JSON rather than `payload`.
+async fn collection_to_worker(
+    daph: &DaphneWorker<'_>,
+    version: DapVersion,
+    task_id: &TaskId,
+    collect_id: &CollectionJobId,
+    collection: &Collection,
+    format: DapCollectionFormat,
+    payload: Vec<u8>,
+) -> Result<Response> {
+    let report_counts = match daph.get_collection_report_counts(task_id, collect_id).await {
+        Ok(report_counts) => report_counts,
+        Err(e) => return daph.state.dap_abort_to_worker_response(e.into()),
+    };
+
+    let mut resp = match format {
+        DapCollectionFormat::Tls => dap_response_to_worker(DapResponse {
+            version,
+            media_type: DapMediaType::Collection,
+            payload,
+        })?,
+        DapCollectionFormat::Json => Response::from_json(collection)?,
+    };
+
+    if let Some(ref signing_key) = daph.config().collection_receipt_signing_key {
+        let batch_sel = match daph.get_collection_batch_selector(task_id, collect_id).await {
+            Ok(batch_sel) => batch_sel,
+            Err(e) => return daph.state.dap_abort_to_worker_response(e.into()),
+        };
+        if let Some(batch_sel) = batch_sel {
+            let receipt =
+                DapCollectionReceipt::sign(signing_key, version, task_id, &batch_sel, collection);
+            resp.headers_mut().set(
+                "X-Daphne-Collection-Receipt",
+                &encode_base64url(serde_json::to_vec(&receipt)?),
+            )?;
+        }
+    }
+
+    if let Some(report_counts) = report_counts {
+        resp.headers_mut().set(
+            "X-Daphne-Report-Counts",
+            &encode_base64url(serde_json::to_vec(&report_counts)?),
+        )?;
+    }
+    Ok(resp)
+}
+
+/// The clock of the Workers runtime.
+pub(crate) struct DaphneWorkerClock;
+
+impl Clock for DaphneWorkerClock {
+    fn now_millis(&self) -> u64 {
+        Date::now().as_millis()
+    }
+}
+
 pub(crate) fn now() -> u64 {
-    Date::now().as_millis() / 1000
+    DaphneWorkerClock.now()
 }
 
 pub(crate) fn int_err<S: ToString>(s: S) -> Error {
@@ -644,6 +2199,31 @@ pub(crate) fn int_err<S: ToString>(s: S) -> Error {
     Error::RustError("internalError".to_string())
 }
 
+/// Error for a command to the interop test API that cannot be carried out. Unlike [`int_err`], the
+/// message is returned to the caller (see [`interop_test_response`]).
+pub(crate) fn cmd_err<S: ToString>(s: S) -> Error {
+    Error::RustError(s.to_string())
+}
+
+/// Respond to a command of the interop test API. As required by
+/// draft-dcook-ppm-dap-interop-test-design, a command that fails is reported in the body of a
+/// successful response.
+fn interop_test_response(result: Result<()>) -> Result<Response> {
+    match result {
+        Ok(()) => Response::from_json(&InternalResponse::success(())),
+        Err(e) => {
+            let error = match e {
+                Error::RustError(s) => s,
+                e => e.to_string(),
+            };
+            Response::from_json(&InternalResponse::<()>::error(
+                InternalErrorCode::CommandFailed,
+                error,
+            ))
+        }
+    }
+}
+
 /// Convert a [`worker::Error`] into a [`daphne::DapError`].
 ///
 /// NOTE Alternatively, we could implement `From<worker::Error>` for `daphne::DapError` in the
@@ -652,7 +2232,12 @@ pub(crate) fn int_err<S: ToString>(s: S) -> Error {
 /// changes that are on the main branch but not yet released. Thus, synchronizing this dependency
 /// between both crates is not currently feasible.
 pub(crate) fn dap_err(e: Error) -> DapError {
-    DapError::Fatal(format!("worker: {e}"))
+    match e {
+        Error::RustError(s) if s == ERR_DEADLINE_EXCEEDED => {
+            DapError::Abort(DapAbort::RetryLater { detail: s })
+        }
+        e => DapError::Fatal(format!("worker: {e}")),
+    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize)]
@@ -667,12 +2252,23 @@ pub(crate) struct InternalTestEndpointForTask {
     role: InternalTestRole,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestCorruptLeaderBearerToken {
+    task_id: String, // base64url
+    corrupt: bool,
+}
+
 #[derive(Deserialize)]
 pub(crate) struct InternalTestVdaf {
     #[serde(rename = "type")]
     typ: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     bits: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buckets: Option<Vec<String>>,
 }
 
 #[derive(Deserialize)]
@@ -689,18 +2285,83 @@ pub(crate) struct InternalTestAddTask {
     vdaf_verify_key: String, // base64url
     query_type: u8,
     min_batch_size: u64,
+    #[serde(default)]
+    max_batch_query_count: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_batch_size: Option<u64>,
     time_precision: Duration,
     collector_hpke_config: String, // base64url
     task_expiration: Time,
+    #[serde(default)]
+    additional_collectors: Vec<InternalTestCollector>,
+    #[serde(default)]
+    dp: DapDpConfig,
+    #[serde(default)]
+    auth_header: Option<DaphneWorkerAuthHeaderConfig>,
+    #[serde(default)]
+    replay_filter: Option<DapReplayFilterConfig>,
+    #[serde(default)]
+    batch_lifetime: Option<DapBatchLifetimeConfig>,
+    #[serde(default)]
+    upload_auth: Option<DapUploadAuth>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestMigrateTask {
+    version: DapVersion,
+    #[serde(default)]
+    drain: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct InternalTestCollector {
+    id: String,
+    hpke_config: String, // base64url
+    #[serde(default)]
+    authentication_token: Option<String>,
 }
 
 mod auth;
 #[cfg(test)]
 mod auth_test;
+mod billing;
+#[cfg(test)]
+mod billing_test;
+mod compression;
+#[cfg(test)]
+mod compression_test;
 mod config;
 mod dap;
+mod dead_letter;
+#[cfg(test)]
+mod dead_letter_test;
 mod durable;
+mod events;
+mod export;
+#[cfg(test)]
+mod export_test;
+mod health;
+#[cfg(test)]
+mod health_test;
+mod hpke;
+pub mod internal_api;
+#[cfg(test)]
+mod internal_api_test;
 mod metrics;
+mod report_sample;
+#[cfg(test)]
+mod report_sample_test;
+mod router;
+#[cfg(test)]
+mod router_test;
+mod storage_migration;
+#[cfg(test)]
+mod storage_migration_test;
+mod telemetry;
+#[cfg(test)]
+mod telemetry_test;
 mod tracing_utils;
+#[cfg(test)]
+mod tracing_utils_test;
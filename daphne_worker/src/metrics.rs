@@ -4,8 +4,12 @@
 //! Daphne-Worker metrics.
 
 use crate::DapError;
-use daphne::metrics::DaphneMetrics;
-use prometheus::{register_int_counter_vec_with_registry, IntCounterVec, Registry};
+use daphne::metrics::{DaphneMetrics, DaphneMetricsBuckets, DaphneMetricsTaskLabeler};
+use prometheus::{
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry, IntCounterVec,
+    IntGaugeVec, Registry,
+};
+use std::sync::Arc;
 
 pub(crate) struct DaphneWorkerMetrics {
     /// Daphne metrics.
@@ -16,10 +20,52 @@ pub(crate) struct DaphneWorkerMetrics {
 
     /// DAP aborts.
     pub(crate) dap_abort_counter: IntCounterVec,
+
+    /// Leader: Aggregation jobs skipped because their bucket of reports is quarantined.
+    pub(crate) agg_job_quarantined_counter: IntCounterVec,
+
+    /// Leader: HTTP clients for the Helper, by whether the client was created or reused.
+    pub(crate) helper_http_client_counter: IntCounterVec,
+
+    /// Conflicting concurrent updates detected while merging into an aggregate store.
+    pub(crate) agg_store_merge_conflict_counter: IntCounterVec,
+
+    /// State deleted by the task garbage collector, by type: the DO binding, "collection_job", or
+    /// "kv".
+    pub(crate) task_gc_deleted_counter: IntCounterVec,
+
+    /// Bytes saved by compressing aggregation messages, by direction: "sent" (Leader) or
+    /// "received" (Helper).
+    pub(crate) compression_bytes_saved_counter: IntCounterVec,
+
+    /// Leader: Exports of collection results, by status: "exported", "retried", or "failed".
+    pub(crate) collection_export_counter: IntCounterVec,
+
+    /// Reports checked against a replay filter, by result: "negative", "true_positive", or
+    /// "false_positive".
+    pub(crate) replay_filter_counter: IntCounterVec,
+
+    /// Batch lifecycle events, by status: "emitted" or "failed".
+    pub(crate) batch_event_counter: IntCounterVec,
+
+    /// Leader: Uploads rejected because too many reports are pending in their bucket.
+    pub(crate) report_bucket_full_counter: IntCounterVec,
+
+    /// Outcome of each health check: 1 if the check passed and 0 otherwise.
+    pub(crate) health_check_status_gauge: IntGaugeVec,
+
+    /// Time (in milliseconds) it took to perform each health check.
+    pub(crate) health_check_latency_gauge: IntGaugeVec,
 }
 
 impl DaphneWorkerMetrics {
-    pub(crate) fn register(registry: &Registry, prefix: Option<&str>) -> Result<Self, DapError> {
+    /// Register the metrics with the given registry. If a task labeler is provided, then per-task
+    /// metrics are recorded.
+    pub(crate) fn register(
+        registry: &Registry,
+        prefix: Option<&str>,
+        task_labeler: Option<Arc<DaphneMetricsTaskLabeler>>,
+    ) -> Result<Self, DapError> {
         let front = if let Some(prefix) = prefix {
             format!("{prefix}_")
         } else {
@@ -40,12 +86,104 @@ impl DaphneWorkerMetrics {
             registry
         )?;
 
-        let daphne = DaphneMetrics::register(registry, prefix)?;
+        let agg_job_quarantined_counter = register_int_counter_vec_with_registry!(
+            format!("{front}agg_job_quarantined"),
+            "Aggregation jobs skipped because their bucket of reports is quarantined.",
+            &["host"],
+            registry
+        )?;
+
+        let helper_http_client_counter = register_int_counter_vec_with_registry!(
+            format!("{front}helper_http_client"),
+            "HTTP clients for the Helper, by whether the client was created or reused.",
+            &["host", "status"],
+            registry
+        )?;
+
+        let agg_store_merge_conflict_counter = register_int_counter_vec_with_registry!(
+            format!("{front}agg_store_merge_conflict"),
+            "Conflicting concurrent updates detected while merging into an aggregate store.",
+            &["host"],
+            registry
+        )?;
+
+        let task_gc_deleted_counter = register_int_counter_vec_with_registry!(
+            format!("{front}task_gc_deleted"),
+            "State deleted by the task garbage collector.",
+            &["host", "type"],
+            registry
+        )?;
+
+        let compression_bytes_saved_counter = register_int_counter_vec_with_registry!(
+            format!("{front}compression_bytes_saved"),
+            "Bytes saved by compressing aggregation messages.",
+            &["host", "direction"],
+            registry
+        )?;
+
+        let collection_export_counter = register_int_counter_vec_with_registry!(
+            format!("{front}collection_export"),
+            "Exports of collection results.",
+            &["host", "status"],
+            registry
+        )?;
+
+        let replay_filter_counter = register_int_counter_vec_with_registry!(
+            format!("{front}replay_filter"),
+            "Reports checked against a replay filter.",
+            &["host", "result"],
+            registry
+        )?;
+
+        let batch_event_counter = register_int_counter_vec_with_registry!(
+            format!("{front}batch_event"),
+            "Batch lifecycle events.",
+            &["host", "status"],
+            registry
+        )?;
+
+        let report_bucket_full_counter = register_int_counter_vec_with_registry!(
+            format!("{front}report_bucket_full"),
+            "Uploads rejected because too many reports are pending in their bucket.",
+            &["host"],
+            registry
+        )?;
+
+        let health_check_status_gauge = register_int_gauge_vec_with_registry!(
+            format!("{front}health_check_status"),
+            "Outcome of each health check (1 if passed).",
+            &["host", "check"],
+            registry
+        )?;
+
+        let health_check_latency_gauge = register_int_gauge_vec_with_registry!(
+            format!("{front}health_check_latency_ms"),
+            "Time (in milliseconds) it took to perform each health check.",
+            &["host", "check"],
+            registry
+        )?;
+
+        let mut daphne =
+            DaphneMetrics::register(registry, prefix, &DaphneMetricsBuckets::default())?;
+        if let Some(task_labeler) = task_labeler {
+            daphne = daphne.with_task_labels(task_labeler);
+        }
 
         Ok(Self {
             daphne,
             http_status_code_counter,
             dap_abort_counter,
+            agg_job_quarantined_counter,
+            helper_http_client_counter,
+            agg_store_merge_conflict_counter,
+            task_gc_deleted_counter,
+            compression_bytes_saved_counter,
+            collection_export_counter,
+            replay_filter_counter,
+            batch_event_counter,
+            report_bucket_full_counter,
+            health_check_status_gauge,
+            health_check_latency_gauge,
         })
     }
 }
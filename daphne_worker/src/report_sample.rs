@@ -0,0 +1,126 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Store for the metadata of sampled uploads.
+//!
+//! When `report_sampling` is set in the global configuration, the Leader samples a fraction of
+//! the uploaded reports (see [`DapReportSample`]). Each sample is stored in KV for a day:
+//!
+//! ```text
+//!     report_sample/task/<task_id>/<report_id>
+//! ```
+//!
+//! `GET /internal/report_samples/task/:task_id` summarizes the samples of a task as a
+//! [`ReportSampleStats`], e.g., to detect a fleet of Clients with a skewed clock or a stale HPKE
+//! config. `DELETE /internal/report_samples/task/:task_id` purges them.
+
+use crate::{config::DaphneWorker, int_err, internal_api::ReportSampleStats};
+use daphne::{messages::TaskId, DapReportSample};
+use worker::Result;
+
+pub(crate) const KV_KEY_PREFIX_REPORT_SAMPLE: &str = "report_sample/task";
+
+/// How long a sample is kept.
+const REPORT_SAMPLE_TTL_SECS: u64 = 24 * 60 * 60;
+
+impl ReportSampleStats {
+    pub(crate) fn from_samples(samples: &[DapReportSample]) -> Self {
+        let mut stats = Self {
+            sample_count: u64::try_from(samples.len()).unwrap(),
+            ..Default::default()
+        };
+        for sample in samples {
+            if let Some(ref rejected) = sample.rejected {
+                *stats.rejected.entry(rejected.clone()).or_default() += 1;
+            }
+            for extension in &sample.extensions {
+                *stats.extensions.entry(*extension).or_default() += 1;
+            }
+            if sample.taskprov_header {
+                stats.taskprov_header += 1;
+            }
+            if let Some(config_id) = sample.hpke_config_ids.first() {
+                *stats.leader_hpke_config_ids.entry(*config_id).or_default() += 1;
+            }
+            if let Some(config_id) = sample.hpke_config_ids.get(1) {
+                *stats.helper_hpke_config_ids.entry(*config_id).or_default() += 1;
+            }
+        }
+
+        let mut time_skews = samples
+            .iter()
+            .map(|sample| sample.time_skew)
+            .collect::<Vec<_>>();
+        time_skews.sort_unstable();
+        stats.time_skew_min = time_skews.first().copied();
+        stats.time_skew_median = time_skews.get(time_skews.len() / 2).copied();
+        stats.time_skew_max = time_skews.last().copied();
+        stats
+    }
+}
+
+impl DaphneWorker<'_> {
+    /// Store the sample of an uploaded report.
+    pub(crate) async fn store_report_sample(
+        &self,
+        task_id: &TaskId,
+        sample: &DapReportSample,
+    ) -> Result<()> {
+        let key = format!(
+            "{KV_KEY_PREFIX_REPORT_SAMPLE}/{}/{}",
+            task_id.to_hex(),
+            sample.report_id.to_hex()
+        );
+        self.kv()?
+            .put(&key, "")?
+            .metadata(sample)?
+            .expiration_ttl(REPORT_SAMPLE_TTL_SECS)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Summarize the samples of the given task that have not yet expired.
+    pub(crate) async fn internal_report_sample_stats(
+        &self,
+        task_id: &TaskId,
+    ) -> Result<ReportSampleStats> {
+        let mut samples = Vec::new();
+        for key in self.kv_list_report_sample_keys(task_id).await? {
+            let sample = key
+                .metadata
+                .and_then(|metadata| serde_json::from_value(metadata).ok())
+                .ok_or_else(|| int_err(format!("malformed report sample {}", key.name)))?;
+            samples.push(sample);
+        }
+        Ok(ReportSampleStats::from_samples(&samples))
+    }
+
+    /// Delete the samples of the given task.
+    pub(crate) async fn internal_purge_report_samples(&self, task_id: &TaskId) -> Result<()> {
+        let kv_store = self.kv()?;
+        for key in self.kv_list_report_sample_keys(task_id).await? {
+            kv_store.delete(&key.name).await?;
+        }
+        Ok(())
+    }
+
+    async fn kv_list_report_sample_keys(&self, task_id: &TaskId) -> Result<Vec<worker::kv::Key>> {
+        let kv_store = self.kv()?;
+        let prefix = format!("{KV_KEY_PREFIX_REPORT_SAMPLE}/{}/", task_id.to_hex());
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut builder = kv_store.list().prefix(prefix.clone());
+            if let Some(cursor) = cursor {
+                builder = builder.cursor(cursor);
+            }
+            let list = builder.execute().await?;
+            keys.extend(list.keys);
+            if list.list_complete {
+                return Ok(keys);
+            }
+            cursor = list.cursor;
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::internal_api::ReportSampleStats;
+use daphne::{messages::ReportId, DapReportSample};
+
+fn sample(time_skew: i64, hpke_config_ids: Vec<u8>, rejected: Option<&str>) -> DapReportSample {
+    DapReportSample {
+        report_id: ReportId([1; 16]),
+        time_skew,
+        extensions: Vec::new(),
+        taskprov_header: false,
+        hpke_config_ids,
+        rejected: rejected.map(str::to_string),
+    }
+}
+
+#[test]
+fn report_sample_stats() {
+    assert_eq!(
+        serde_json::to_value(ReportSampleStats::from_samples(&[])).unwrap(),
+        serde_json::json!({
+            "sample_count": 0,
+            "rejected": {},
+            "extensions": {},
+            "taskprov_header": 0,
+            "leader_hpke_config_ids": {},
+            "helper_hpke_config_ids": {},
+        })
+    );
+
+    let mut with_extension = sample(-5, vec![1, 2], None);
+    with_extension.extensions = vec![0xff00];
+    with_extension.taskprov_header = true;
+    let stats = ReportSampleStats::from_samples(&[
+        sample(-30, vec![1, 2], None),
+        with_extension,
+        sample(3600, vec![7, 2], Some("reportRejected")),
+    ]);
+    assert_eq!(
+        serde_json::to_value(stats).unwrap(),
+        serde_json::json!({
+            "sample_count": 3,
+            "rejected": { "reportRejected": 1 },
+            "time_skew_min": -30,
+            "time_skew_median": -5,
+            "time_skew_max": 3600,
+            "extensions": { "65280": 1 },
+            "taskprov_header": 1,
+            "leader_hpke_config_ids": { "1": 2, "7": 1 },
+            "helper_hpke_config_ids": { "2": 3 },
+        })
+    );
+}
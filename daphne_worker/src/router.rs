@@ -0,0 +1,149 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Builder for embedding Daphne-Worker's request handler in another Worker.
+//!
+//! [`DaphneWorkerRouter::default()`] serves the DAP endpoints at the root of the Worker's URL and
+//! picks the endpoints to mount from `DAP_AGGREGATOR_ROLE`. An embedder that serves other
+//! endpoints from the same Worker can use [`DapRouter`] instead to mount the endpoints under a
+//! path prefix, to wrap request handling in [`DapMiddleware`] (e.g., for authentication or
+//! logging), or to refuse to serve the endpoints of a role. For example:
+//!
+//! ```ignore
+//! use daphne_worker::{DapAggregatorRole, DapRouter};
+//! use worker::*;
+//!
+//! #[event(fetch)]
+//! pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
+//!     let router = DapRouter::new()
+//!         .with_path_prefix("/dap")
+//!         .with_disabled_role(DapAggregatorRole::Helper)
+//!         .build();
+//!     router.handle_request(req, env).await
+//! }
+//! ```
+
+use crate::{DaphneWorkerHpkeProvider, DaphneWorkerRouter};
+use async_trait::async_trait;
+use daphne::{clock::Clock, extensions::DapExtensionRegistry};
+use worker::{Env, Request, Response, Result};
+
+/// The role of the Aggregator, as configured by `DAP_AGGREGATOR_ROLE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DapAggregatorRole {
+    Leader,
+    Helper,
+}
+
+impl DapAggregatorRole {
+    /// The value of `DAP_AGGREGATOR_ROLE` for this role.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Leader => "leader",
+            Self::Helper => "helper",
+        }
+    }
+}
+
+/// Hooks run around the handling of each HTTP request by
+/// [`DaphneWorkerRouter::handle_request`].
+///
+/// Middleware runs before the request is routed, in the order in which it was added, and after
+/// the response is produced, in the reverse order.
+#[async_trait(?Send)]
+pub trait DapMiddleware {
+    /// Called before the request is routed. If a response is returned, then the request is not
+    /// routed and the response is sent instead, e.g., to refuse an unauthenticated request. The
+    /// response is still passed to [`Self::after`] of the middleware that ran before this one.
+    async fn before(&self, _req: &Request, _env: &Env) -> Result<Option<Response>> {
+        Ok(None)
+    }
+
+    /// Called with the response to the request, which may be modified or replaced.
+    async fn after(&self, resp: Response) -> Result<Response> {
+        Ok(resp)
+    }
+}
+
+/// Builder for [`DaphneWorkerRouter`].
+#[derive(Default)]
+pub struct DapRouter {
+    router: DaphneWorkerRouter,
+}
+
+impl DapRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mount the endpoints under the given path prefix, e.g., "/dap". Requests to paths outside
+    /// of the prefix are not handled. `DAP_BASE_URL` must include the prefix.
+    pub fn with_path_prefix(mut self, path_prefix: &str) -> Self {
+        self.router.path_prefix = normalize_path_prefix(path_prefix);
+        self
+    }
+
+    /// Run the given middleware around the handling of each request.
+    pub fn with_middleware(mut self, middleware: impl DapMiddleware + 'static) -> Self {
+        self.router.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Do not mount the endpoints of the given role. If `DAP_AGGREGATOR_ROLE` is set to this role,
+    /// then only the endpoints common to both roles (health probes, HPKE configs, and the
+    /// administrator's endpoints) are served.
+    pub fn with_disabled_role(mut self, role: DapAggregatorRole) -> Self {
+        if !self.router.disabled_roles.contains(&role) {
+            self.router.disabled_roles.push(role);
+        }
+        self
+    }
+
+    /// See [`DaphneWorkerRouter::enable_internal_test`].
+    pub fn with_internal_test(mut self, enable: bool) -> Self {
+        self.router.enable_internal_test = enable;
+        self
+    }
+
+    /// See [`DaphneWorkerRouter::enable_default_response`].
+    pub fn with_default_response(mut self, enable: bool) -> Self {
+        self.router.enable_default_response = enable;
+        self
+    }
+
+    /// See [`DaphneWorkerRouter::extension_registry`].
+    pub fn with_extension_registry(mut self, extension_registry: DapExtensionRegistry) -> Self {
+        self.router.extension_registry = extension_registry;
+        self
+    }
+
+    /// See [`DaphneWorkerRouter::hpke_provider`].
+    pub fn with_hpke_provider(
+        mut self,
+        hpke_provider: impl DaphneWorkerHpkeProvider + 'static,
+    ) -> Self {
+        self.router.hpke_provider = Some(Box::new(hpke_provider));
+        self
+    }
+
+    /// See [`DaphneWorkerRouter::clock`].
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.router.clock = Some(Box::new(clock));
+        self
+    }
+
+    pub fn build(self) -> DaphneWorkerRouter {
+        self.router
+    }
+}
+
+/// Normalize a path prefix so that it can be prepended to the path of each route: The prefix
+/// starts with "/" and has no trailing "/". The empty prefix mounts the routes at the root.
+pub(crate) fn normalize_path_prefix(path_prefix: &str) -> String {
+    let path_prefix = path_prefix.trim_matches('/');
+    if path_prefix.is_empty() {
+        String::new()
+    } else {
+        format!("/{path_prefix}")
+    }
+}
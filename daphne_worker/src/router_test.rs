@@ -0,0 +1,32 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::router::{normalize_path_prefix, DapAggregatorRole, DapRouter};
+
+#[test]
+fn path_prefix() {
+    assert_eq!(normalize_path_prefix(""), "");
+    assert_eq!(normalize_path_prefix("/"), "");
+    assert_eq!(normalize_path_prefix("dap"), "/dap");
+    assert_eq!(normalize_path_prefix("/dap/"), "/dap");
+    assert_eq!(normalize_path_prefix("/api/dap"), "/api/dap");
+}
+
+#[test]
+fn build() {
+    let router = DapRouter::new().build();
+    assert_eq!(router.path_prefix, "");
+    assert!(router.disabled_roles.is_empty());
+    assert!(router.middleware.is_empty());
+
+    let router = DapRouter::new()
+        .with_path_prefix("/dap/")
+        .with_disabled_role(DapAggregatorRole::Helper)
+        .with_disabled_role(DapAggregatorRole::Helper)
+        .with_internal_test(true)
+        .build();
+    assert_eq!(router.path_prefix, "/dap");
+    assert_eq!(router.disabled_roles, vec![DapAggregatorRole::Helper]);
+    assert!(router.enable_internal_test);
+    assert!(!router.enable_default_response);
+}
@@ -0,0 +1,252 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Migration of report storage to a new layout.
+//!
+//! The `ReportsPending` and `ReportsProcessed` instances to which a report is mapped depend on the
+//! number of report shards (`DAP_REPORT_SHARD_COUNT`). Changing the shard count outright would
+//! lose track of the reports that were already marked as processed, allowing them to be replayed.
+//! Instead, the administrator migrates to the new shard count in two steps:
+//!
+//! 1. `PUT /internal/storage_migration` with a [`StorageMigrationReq`] starts the dual-write
+//!    phase. Reports are marked as processed in both layouts and a report is considered processed
+//!    if either layout says so. Reports pending aggregation are stored in the new layout; those
+//!    already stored in the old layout are still drained by the aggregation job queue.
+//!
+//! 2. `POST /internal/storage_migration/cutover` completes the migration once the reports that are
+//!    only marked in the old layout are too old to be uploaded again (see
+//!    [`StorageMigration::cutover_ready_at`]). From then on, only the new layout is used.
+//!
+//! Once `DAP_REPORT_SHARD_COUNT` is set to the new shard count, the completed migration is removed
+//! with `DELETE /internal/storage_migration`. A migration in the dual-write phase may be aborted
+//! the same way, since the old layout is complete until the cutover.
+//!
+//! The migration is stored in KV and is picked up by each isolate within a minute. `GET
+//! /internal/storage_migration` responds with a [`StorageMigrationStatus`].
+
+use crate::{
+    config::{DaphneWorker, STORAGE_MIGRATION_REFRESH_SECS},
+    internal_api::{
+        StorageMigration, StorageMigrationPhase, StorageMigrationReq, StorageMigrationStatus,
+    },
+};
+use daphne::{
+    messages::{ReportMetadata, Time},
+    roles::DapAggregator,
+    DapGlobalConfig, DapTaskConfig,
+};
+use worker::Result;
+
+pub(crate) const KV_KEY_STORAGE_MIGRATION: &str = "storage_migration";
+
+impl StorageMigration {
+    /// The time from which the migration may be cut over to the new layout. By then, each isolate
+    /// has been marking reports in both layouts for long enough that any report marked only in
+    /// the old layout is rejected for being too old.
+    pub(crate) fn cutover_ready_at(&self, global_config: &DapGlobalConfig) -> Time {
+        global_config
+            .greatest_valid_report_time(
+                self.started_at
+                    .saturating_add(STORAGE_MIGRATION_REFRESH_SECS),
+            )
+            .saturating_add(global_config.report_storage_epoch_duration)
+    }
+
+    /// The shard counts of the layouts in which reports are marked as processed. The first is the
+    /// layout in which reports pending aggregation are stored.
+    pub(crate) fn report_shard_counts_processed(&self) -> Vec<u64> {
+        match self.phase {
+            StorageMigrationPhase::DualWrite
+                if self.from_report_shard_count != self.report_shard_count =>
+            {
+                vec![self.report_shard_count, self.from_report_shard_count]
+            }
+            _ => vec![self.report_shard_count],
+        }
+    }
+
+    /// The number of shards that covers both layouts. (The name of a report store depends on the
+    /// shard, but not on the shard count.)
+    pub(crate) fn report_shard_count_max(&self) -> u64 {
+        self.from_report_shard_count.max(self.report_shard_count)
+    }
+}
+
+impl StorageMigrationStatus {
+    pub(crate) fn new(
+        configured_report_shard_count: u64,
+        migration: Option<StorageMigration>,
+        global_config: &DapGlobalConfig,
+        now: Time,
+    ) -> Self {
+        let cutover_ready_at = migration
+            .as_ref()
+            .filter(|migration| migration.phase == StorageMigrationPhase::DualWrite)
+            .map(|migration| migration.cutover_ready_at(global_config));
+        Self {
+            configured_report_shard_count,
+            migration,
+            cutover_ready_at,
+            cutover_ready: cutover_ready_at.is_some_and(|ready_at| now >= ready_at),
+        }
+    }
+}
+
+impl DaphneWorker<'_> {
+    /// The shard count of the layout in which reports pending aggregation are stored.
+    fn report_shard_count(&self) -> u64 {
+        match self.state.storage_migration {
+            Some(ref migration) => migration.report_shard_count,
+            None => self.config().report_shard_count,
+        }
+    }
+
+    /// The shard counts of the layouts in which reports are marked as processed.
+    fn report_shard_counts_processed(&self) -> Vec<u64> {
+        match self.state.storage_migration {
+            Some(ref migration) => migration.report_shard_counts_processed(),
+            None => vec![self.config().report_shard_count],
+        }
+    }
+
+    /// The number of shards that covers each layout that may hold reports pending aggregation.
+    pub(crate) fn report_shard_count_max(&self) -> u64 {
+        match self.state.storage_migration {
+            Some(ref migration) => migration.report_shard_count_max(),
+            None => self.config().report_shard_count,
+        }
+    }
+
+    /// Derive the name of the `ReportsPending` instance in which to store the report.
+    pub(crate) fn durable_name_reports_pending(
+        &self,
+        task_config: &DapTaskConfig,
+        task_id_hex: &str,
+        metadata: &ReportMetadata,
+    ) -> String {
+        self.config().durable_name_report_store(
+            task_config,
+            task_id_hex,
+            metadata,
+            self.report_shard_count(),
+        )
+    }
+
+    /// Derive the names of the `ReportsProcessed` instances in which the report is marked as
+    /// processed, one for each layout in use.
+    pub(crate) fn durable_names_reports_processed(
+        &self,
+        task_config: &DapTaskConfig,
+        task_id_hex: &str,
+        metadata: &ReportMetadata,
+    ) -> Vec<String> {
+        let mut durable_names: Vec<String> = Vec::new();
+        for report_shard_count in self.report_shard_counts_processed() {
+            let durable_name = self.config().durable_name_report_store(
+                task_config,
+                task_id_hex,
+                metadata,
+                report_shard_count,
+            );
+            if !durable_names.contains(&durable_name) {
+                durable_names.push(durable_name);
+            }
+        }
+        durable_names
+    }
+
+    /// Get the status of the storage migration stored in KV.
+    pub(crate) async fn internal_storage_migration_status(&self) -> Result<StorageMigrationStatus> {
+        let migration = self.get_storage_migration().await?;
+        Ok(StorageMigrationStatus::new(
+            self.config().report_shard_count,
+            migration,
+            self.get_global_config(),
+            self.get_current_time(),
+        ))
+    }
+
+    /// Start migrating to the given number of report shards. Returns the reason the migration
+    /// cannot be started, if any.
+    pub(crate) async fn internal_start_storage_migration(
+        &self,
+        req: StorageMigrationReq,
+    ) -> Result<Option<String>> {
+        if req.report_shard_count == self.config().report_shard_count {
+            return Ok(Some("report_shard_count is already configured".into()));
+        }
+        if self.get_storage_migration().await?.is_some() {
+            return Ok(Some("a migration is already in progress".into()));
+        }
+
+        let migration = StorageMigration {
+            from_report_shard_count: self.config().report_shard_count,
+            report_shard_count: req.report_shard_count,
+            phase: StorageMigrationPhase::DualWrite,
+            started_at: self.get_current_time(),
+            completed_at: None,
+        };
+        self.put_storage_migration(Some(migration)).await?;
+        Ok(None)
+    }
+
+    /// Cut the migration over to the new layout. Returns the reason the migration cannot be cut
+    /// over, if any.
+    pub(crate) async fn internal_cutover_storage_migration(&self) -> Result<Option<String>> {
+        let now = self.get_current_time();
+        let mut migration = match self.get_storage_migration().await? {
+            Some(migration) if migration.phase == StorageMigrationPhase::DualWrite => migration,
+            Some(..) => return Ok(Some("the migration is already complete".into())),
+            None => return Ok(Some("no migration is in progress".into())),
+        };
+        let ready_at = migration.cutover_ready_at(self.get_global_config());
+        if now < ready_at {
+            return Ok(Some(format!(
+                "the migration may not be cut over before {ready_at}"
+            )));
+        }
+
+        migration.phase = StorageMigrationPhase::Complete;
+        migration.completed_at = Some(now);
+        self.put_storage_migration(Some(migration)).await?;
+        Ok(None)
+    }
+
+    /// Remove the migration, aborting it if it is in the dual-write phase. Returns the reason the
+    /// migration cannot be removed, if any.
+    pub(crate) async fn internal_delete_storage_migration(&self) -> Result<Option<String>> {
+        let migration = match self.get_storage_migration().await? {
+            Some(migration) => migration,
+            None => return Ok(Some("no migration is in progress".into())),
+        };
+        if migration.phase == StorageMigrationPhase::Complete
+            && migration.report_shard_count != self.config().report_shard_count
+        {
+            return Ok(Some(format!(
+                "DAP_REPORT_SHARD_COUNT must be set to {} first",
+                migration.report_shard_count
+            )));
+        }
+
+        self.put_storage_migration(None).await?;
+        Ok(None)
+    }
+
+    async fn get_storage_migration(&self) -> Result<Option<StorageMigration>> {
+        Ok(self.kv()?.get(KV_KEY_STORAGE_MIGRATION).json().await?)
+    }
+
+    async fn put_storage_migration(&self, migration: Option<StorageMigration>) -> Result<()> {
+        match migration {
+            Some(ref migration) => {
+                self.kv()?
+                    .put(KV_KEY_STORAGE_MIGRATION, migration)?
+                    .execute()
+                    .await?
+            }
+            None => self.kv()?.delete(KV_KEY_STORAGE_MIGRATION).await?,
+        }
+        self.isolate_state().cache_storage_migration(migration);
+        Ok(())
+    }
+}
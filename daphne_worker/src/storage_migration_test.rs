@@ -0,0 +1,98 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::internal_api::{StorageMigration, StorageMigrationPhase, StorageMigrationStatus};
+use daphne::DapGlobalConfig;
+
+fn global_config() -> DapGlobalConfig {
+    serde_json::from_value(serde_json::json!({
+        "report_storage_epoch_duration": 3600,
+        "report_storage_max_future_time_skew": 300,
+        "max_batch_duration": 360000,
+        "min_batch_interval_start": 259200,
+        "max_batch_interval_end": 259200,
+        "supported_hpke_kems": ["x25519_hkdf_sha256"],
+        "allow_taskprov": false,
+        "taskprov_version": "v02",
+    }))
+    .unwrap()
+}
+
+fn migration(phase: StorageMigrationPhase) -> StorageMigration {
+    StorageMigration {
+        from_report_shard_count: 2,
+        report_shard_count: 8,
+        phase,
+        started_at: 1_700_000_000,
+        completed_at: None,
+    }
+}
+
+#[test]
+fn report_shard_counts() {
+    let migration = migration(StorageMigrationPhase::DualWrite);
+    assert_eq!(migration.report_shard_counts_processed(), vec![8, 2]);
+    assert_eq!(migration.report_shard_count_max(), 8);
+
+    let migration = StorageMigration {
+        phase: StorageMigrationPhase::Complete,
+        completed_at: Some(1_700_010_000),
+        ..migration
+    };
+    assert_eq!(migration.report_shard_counts_processed(), vec![8]);
+    assert_eq!(migration.report_shard_count_max(), 8);
+}
+
+#[test]
+fn cutover_ready_at() {
+    // The isolates' refresh interval, the maximum future time skew, and the report storage epoch
+    // must pass before the old layout may be dropped.
+    let migration = migration(StorageMigrationPhase::DualWrite);
+    assert_eq!(
+        migration.cutover_ready_at(&global_config()),
+        1_700_000_000 + 60 + 300 + 3600
+    );
+}
+
+#[test]
+fn status() {
+    let global_config = global_config();
+    let ready_at = 1_700_000_000 + 60 + 300 + 3600;
+
+    let status = StorageMigrationStatus::new(2, None, &global_config, ready_at);
+    assert_eq!(status.cutover_ready_at, None);
+    assert!(!status.cutover_ready);
+
+    let dual_write = migration(StorageMigrationPhase::DualWrite);
+    let status =
+        StorageMigrationStatus::new(2, Some(dual_write.clone()), &global_config, ready_at - 1);
+    assert_eq!(status.cutover_ready_at, Some(ready_at));
+    assert!(!status.cutover_ready);
+
+    let status = StorageMigrationStatus::new(2, Some(dual_write), &global_config, ready_at);
+    assert!(status.cutover_ready);
+    assert_eq!(
+        serde_json::to_value(&status).unwrap(),
+        serde_json::json!({
+            "configured_report_shard_count": 2,
+            "migration": {
+                "from_report_shard_count": 2,
+                "report_shard_count": 8,
+                "phase": "dual_write",
+                "started_at": 1_700_000_000,
+            },
+            "cutover_ready_at": ready_at,
+            "cutover_ready": true,
+        })
+    );
+
+    // A completed migration is no longer cut over.
+    let complete = StorageMigration {
+        phase: StorageMigrationPhase::Complete,
+        completed_at: Some(ready_at),
+        ..migration(StorageMigrationPhase::DualWrite)
+    };
+    let status = StorageMigrationStatus::new(8, Some(complete), &global_config, ready_at);
+    assert_eq!(status.cutover_ready_at, None);
+    assert!(!status.cutover_ready);
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Cumulative per-task aggregation telemetry.
+//!
+//! When `DAP_AGGREGATION_TELEMETRY_ENABLED` is set, the reports counted by the Daphne metrics
+//! while handling a request are tallied per task. Before the response is sent, the tally is added
+//! to the counts stored for each task by the `AggregationTelemetryStore` DO. The counts are
+//! served by `GET /internal/telemetry/aggregation` as an [`AggregationTelemetry`].
+
+use crate::{
+    config::DaphneWorker,
+    durable::{
+        aggregation_telemetry_store::{
+            DURABLE_AGGREGATION_TELEMETRY_STORE_ADD, DURABLE_AGGREGATION_TELEMETRY_STORE_GET,
+        },
+        durable_name_task_telemetry, BINDING_DAP_AGGREGATION_TELEMETRY_STORE,
+    },
+    internal_api::{AggregationTelemetry, TaskAggregationTelemetry},
+};
+use daphne::messages::TaskId;
+use futures::future::try_join_all;
+use std::{borrow::Cow, collections::HashMap};
+use tracing::error;
+use worker::Result;
+
+impl TaskAggregationTelemetry {
+    /// Convert the number of reports tallied for a task, keyed by the status with which they were
+    /// counted (e.g., "aggregated" or "rejected_report_replayed"). Other statuses are ignored.
+    pub(crate) fn from_report_counts(counts: &HashMap<String, u64>) -> Self {
+        let mut telemetry = Self::default();
+        for (status, &count) in counts {
+            match (status.as_str(), status.strip_prefix("rejected_")) {
+                ("collected", _) => telemetry.reports_collected += count,
+                ("aggregated", _) => {
+                    telemetry.reports_aggregated += count;
+                    telemetry.reports_processed += count;
+                }
+                (_, Some(reason)) => {
+                    *telemetry
+                        .reports_rejected
+                        .entry(reason.to_string())
+                        .or_default() += count;
+                    telemetry.reports_processed += count;
+                }
+                _ => (),
+            }
+        }
+        telemetry
+    }
+
+    /// Add the counts of `other` to these counts.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.reports_processed = self
+            .reports_processed
+            .saturating_add(other.reports_processed);
+        self.reports_aggregated = self
+            .reports_aggregated
+            .saturating_add(other.reports_aggregated);
+        self.reports_collected = self
+            .reports_collected
+            .saturating_add(other.reports_collected);
+        for (reason, count) in &other.reports_rejected {
+            let total = self.reports_rejected.entry(reason.clone()).or_default();
+            *total = total.saturating_add(*count);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl DaphneWorker<'_> {
+    /// Add the reports counted while handling the request to the counts stored for each task, if
+    /// aggregation telemetry is enabled. Failures are logged, but otherwise ignored, so that they
+    /// don't affect the outcome of the request.
+    pub(crate) async fn flush_aggregation_telemetry(&self) {
+        if !self.config().aggregation_telemetry_enabled {
+            return;
+        }
+
+        for (task_id, counts) in self.state.metrics.daphne.take_report_tally() {
+            let delta = TaskAggregationTelemetry::from_report_counts(&counts);
+            if delta.is_empty() {
+                continue;
+            }
+            if let Err(e) = self.add_task_aggregation_telemetry(&task_id, &delta).await {
+                error!("failed to record aggregation telemetry for task {task_id}: {e}");
+            }
+        }
+    }
+
+    async fn add_task_aggregation_telemetry(
+        &self,
+        task_id: &TaskId,
+        delta: &TaskAggregationTelemetry,
+    ) -> Result<()> {
+        let task_config = match self.get_task_config(Cow::Borrowed(task_id)).await? {
+            Some(task_config) => task_config,
+            None => return Ok(()),
+        };
+        self.durable()
+            .post(
+                BINDING_DAP_AGGREGATION_TELEMETRY_STORE,
+                DURABLE_AGGREGATION_TELEMETRY_STORE_ADD,
+                durable_name_task_telemetry(&task_config.as_ref().version, &task_id.to_hex()),
+                delta,
+            )
+            .await
+    }
+
+    /// Get the counts stored for each task configured in KV.
+    pub(crate) async fn internal_aggregation_telemetry(&self) -> Result<AggregationTelemetry> {
+        let mut tasks = Vec::new();
+        for task_id in self.internal_list_tasks().await? {
+            let version = match self.get_task_config(Cow::Borrowed(&task_id)).await? {
+                Some(task_config) => task_config.as_ref().version,
+                None => continue,
+            };
+            tasks.push((task_id, version));
+        }
+
+        let durable = self.durable();
+        let counts: Vec<TaskAggregationTelemetry> =
+            try_join_all(tasks.iter().map(|(task_id, version)| {
+                durable.get(
+                    BINDING_DAP_AGGREGATION_TELEMETRY_STORE,
+                    DURABLE_AGGREGATION_TELEMETRY_STORE_GET,
+                    durable_name_task_telemetry(version, &task_id.to_hex()),
+                )
+            }))
+            .await?;
+
+        Ok(AggregationTelemetry {
+            tasks: tasks
+                .into_iter()
+                .zip(counts)
+                .filter(|(_task, telemetry)| !telemetry.is_empty())
+                .map(|((task_id, _version), telemetry)| (task_id.to_base64url(), telemetry))
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::internal_api::{AggregationTelemetry, TaskAggregationTelemetry};
+use std::collections::{BTreeMap, HashMap};
+
+#[test]
+fn from_report_counts() {
+    let telemetry = TaskAggregationTelemetry::from_report_counts(&HashMap::from([
+        ("aggregated".to_string(), 10),
+        ("collected".to_string(), 8),
+        ("rejected_report_replayed".to_string(), 2),
+        ("rejected_vdaf_prep_error".to_string(), 1),
+        // Not an outcome of aggregation.
+        ("report_too_early".to_string(), 3),
+    ]));
+    assert_eq!(
+        telemetry,
+        TaskAggregationTelemetry {
+            reports_processed: 13,
+            reports_aggregated: 10,
+            reports_collected: 8,
+            reports_rejected: BTreeMap::from([
+                ("report_replayed".to_string(), 2),
+                ("vdaf_prep_error".to_string(), 1),
+            ]),
+        }
+    );
+
+    assert!(
+        TaskAggregationTelemetry::from_report_counts(&HashMap::from([(
+            "report_too_early".to_string(),
+            1
+        )]))
+        .is_empty()
+    );
+}
+
+#[test]
+fn merge() {
+    let mut telemetry = TaskAggregationTelemetry {
+        reports_processed: 3,
+        reports_aggregated: 2,
+        reports_collected: 0,
+        reports_rejected: BTreeMap::from([("report_replayed".to_string(), 1)]),
+    };
+    telemetry.merge(&TaskAggregationTelemetry {
+        reports_processed: 2,
+        reports_aggregated: 0,
+        reports_collected: 2,
+        reports_rejected: BTreeMap::from([
+            ("report_replayed".to_string(), 1),
+            ("batch_collected".to_string(), 1),
+        ]),
+    });
+    assert_eq!(
+        telemetry,
+        TaskAggregationTelemetry {
+            reports_processed: 5,
+            reports_aggregated: 2,
+            reports_collected: 2,
+            reports_rejected: BTreeMap::from([
+                ("batch_collected".to_string(), 1),
+                ("report_replayed".to_string(), 2),
+            ]),
+        }
+    );
+}
+
+#[test]
+fn aggregation_telemetry_json() {
+    let telemetry = AggregationTelemetry {
+        tasks: BTreeMap::from([(
+            "task".to_string(),
+            TaskAggregationTelemetry {
+                reports_processed: 1,
+                reports_aggregated: 1,
+                ..Default::default()
+            },
+        )]),
+    };
+    assert_eq!(
+        serde_json::to_value(&telemetry).unwrap(),
+        serde_json::json!({
+            "tasks": {
+                "task": {
+                    "reports_processed": 1,
+                    "reports_aggregated": 1,
+                    "reports_collected": 0,
+                },
+            },
+        })
+    );
+}
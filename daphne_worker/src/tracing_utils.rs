@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use chrono::{SecondsFormat, Utc};
+use rand::prelude::*;
 use std::{fmt::Result as FmtResult, io, str, sync::Once};
 use tracing::{event, Level, Subscriber};
 use tracing_core::span;
@@ -143,8 +144,42 @@ where
     }
 }
 
+/// Header carrying the correlation ID of a request. The Leader sends the ID of the request it is
+/// handling in each of its requests to the Helper, so that the logs of both Aggregators can be
+/// joined when debugging a failed aggregation job.
+pub(crate) const CORRELATION_ID_HEADER: &str = "DAP-Correlation-Id";
+
+/// Generate a new correlation ID.
+pub(crate) fn new_correlation_id() -> String {
+    hex::encode(thread_rng().gen::<[u8; 16]>())
+}
+
+/// Check that a correlation ID set by the sender of a request can be adopted, i.e., that it is not
+/// too long and can be carried in a header and log line without escaping.
+pub(crate) fn is_valid_correlation_id(correlation_id: &str) -> bool {
+    !correlation_id.is_empty()
+        && correlation_id.len() <= 64
+        && correlation_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Determine the correlation ID of a request: the ID set by the sender, if any and if valid;
+/// otherwise a new ID.
+pub(crate) fn correlation_id_for_request(req: &Request) -> Result<String> {
+    Ok(req
+        .headers()
+        .get(CORRELATION_ID_HEADER)?
+        .filter(|correlation_id| is_valid_correlation_id(correlation_id))
+        .unwrap_or_else(new_correlation_id))
+}
+
 static INITIALIZE_TRACING: Once = Once::new();
 
+/// A [`Layer`] to which spans and events are forwarded in addition to the worker console, e.g.,
+/// an OpenTelemetry layer that exports traces to the operator's collector.
+pub type DaphneWorkerTraceExporter = Box<dyn Layer<registry::Registry> + Send + Sync + 'static>;
+
 /// Setup logging.
 ///
 /// Initialize tracing using configuration from DAP_TRACING in the environment
@@ -155,6 +190,16 @@ static INITIALIZE_TRACING: Once = Once::new();
 /// Logging will only be initialized once, no matter how many times this
 /// function is called.
 pub fn initialize_tracing(env: &Env) {
+    initialize_tracing_with_exporter(env, None);
+}
+
+/// Like [`initialize_tracing`], except that spans and events are also passed to `exporter`, if
+/// set. Spans are filtered the same way as log output is.
+///
+/// Since tracing is only initialized once, this function needs to be called before the first
+/// request is handled, i.e., before calling into [`DaphneWorkerRouter`](crate::DaphneWorkerRouter),
+/// in order for the exporter to be installed.
+pub fn initialize_tracing_with_exporter(env: &Env, exporter: Option<DaphneWorkerTraceExporter>) {
     // We have to do this in a once block as the worker instance can get multiple invocations of
     // main() within its lifetime.
     INITIALIZE_TRACING.call_once(|| {
@@ -163,6 +208,7 @@ pub fn initialize_tracing(env: &Env) {
             Err(_) => "info".to_string(),
         };
         registry()
+            .with(exporter)
             .with(
                 fmt::layer()
                     .with_writer(LogWriter::new)
@@ -0,0 +1,17 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::tracing_utils::{is_valid_correlation_id, new_correlation_id};
+
+#[test]
+fn correlation_id() {
+    let correlation_id = new_correlation_id();
+    assert!(is_valid_correlation_id(&correlation_id));
+    assert_ne!(correlation_id, new_correlation_id());
+
+    assert!(is_valid_correlation_id("0a1b2c3d-request_1"));
+    assert!(!is_valid_correlation_id(""));
+    assert!(!is_valid_correlation_id(&"a".repeat(65)));
+    assert!(!is_valid_correlation_id("request 1"));
+    assert!(!is_valid_correlation_id("request\n1"));
+}
@@ -1,12 +1,38 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
+use daphne::clock::Clock;
 use daphne_worker::{initialize_tracing, DaphneWorkerRouter};
-use tracing::info;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{error, info};
 use worker::*;
 
+#[cfg(feature = "loadgen")]
+pub mod loadgen;
+#[cfg(all(test, feature = "loadgen"))]
+mod loadgen_test;
 mod utils;
 
+/// Time (in seconds) added to the clock of the Workers runtime. Set by the e2e tests with
+/// `POST /internal/test/clock_offset` in order to move the Aggregator into the future.
+static CLOCK_OFFSET_SECS: AtomicU64 = AtomicU64::new(0);
+
+/// The clock of the Workers runtime, offset by [`CLOCK_OFFSET_SECS`].
+struct TestClock;
+
+impl Clock for TestClock {
+    fn now_millis(&self) -> u64 {
+        Date::now().as_millis() + CLOCK_OFFSET_SECS.load(Ordering::Relaxed) * 1000
+    }
+}
+
+/// Request of `POST /internal/test/clock_offset`.
+#[derive(Deserialize)]
+struct ClockOffset {
+    offset_secs: u64,
+}
+
 fn log_request(req: &Request) {
     info!(
         "[{}], located at: {:?}, within: {}",
@@ -27,9 +53,34 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
 
     log_request(&req);
 
+    if req.method() == Method::Post && req.path() == "/internal/test/clock_offset" {
+        let mut req = req;
+        let clock_offset: ClockOffset = req.json().await?;
+        CLOCK_OFFSET_SECS.store(clock_offset.offset_secs, Ordering::Relaxed);
+        return Response::empty();
+    }
+
     let router = DaphneWorkerRouter {
         enable_internal_test: true,
         enable_default_response: false,
+        clock: Some(Box::new(TestClock)),
+        ..Default::default()
     };
     router.handle_request(req, env).await
 }
+
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    utils::set_panic_hook();
+    initialize_tracing(&env);
+
+    let router = DaphneWorkerRouter {
+        enable_internal_test: true,
+        enable_default_response: false,
+        clock: Some(Box::new(TestClock)),
+        ..Default::default()
+    };
+    if let Err(e) = router.handle_scheduled(env).await {
+        error!("scheduled event failed: {e}");
+    }
+}
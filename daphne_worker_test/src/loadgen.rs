@@ -0,0 +1,367 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Load generation for capacity planning.
+//!
+//! [`LoadGen`] uploads reports for a task at a target rate for a fixed duration, then optionally
+//! drives the Leader's processing loop (`POST /internal/process`) until the uploaded reports are
+//! drained. It reports the achieved throughput and the latency percentiles of each phase.
+//!
+//! Reports are produced with [`DapClient`], so the Aggregators' HPKE configs are fetched once and
+//! cached. Uploads run concurrently on the current task, up to [`LoadGenConfig::concurrency`] at a
+//! time; if the Leader cannot keep up, then the achieved rate falls short of the target. For
+//! example:
+//!
+//! ```ignore
+//! let report = LoadGen::new(config).run().await?;
+//! println!("{}", serde_json::to_string_pretty(&report).unwrap());
+//! ```
+//!
+//! This module is only available with the "loadgen" feature.
+
+use async_trait::async_trait;
+use daphne::{
+    auth::{DapClientAuth, DapUploadAuth},
+    client::{DapClient, DapClientHttpClient, DapClientHttpMethod, DapClientHttpResponse},
+    messages::{decode_base64url_vec, TaskId},
+    DapError, DapLeaderProcessTelemetry, DapMeasurement, DapRequest, DapVersion, VdafConfig,
+};
+use daphne_worker::DaphneWorkerReportSelector;
+use futures::StreamExt;
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime};
+use url::Url;
+
+/// Parameters of a load test.
+pub struct LoadGenConfig {
+    pub version: DapVersion,
+    pub task_id: TaskId,
+
+    /// The Aggregators' base URLs for the DAP version (e.g., "http://127.0.0.1:8787/v04/").
+    pub leader_url: Url,
+    pub helper_url: Url,
+
+    pub vdaf: VdafConfig,
+
+    /// The measurement of each report.
+    pub measurement: DapMeasurement,
+
+    /// Client credential, if the task requires upload authentication.
+    pub upload_auth: Option<DapUploadAuth>,
+
+    /// Target number of reports uploaded per second.
+    pub reports_per_sec: u64,
+
+    /// How long to upload reports for.
+    pub duration: Duration,
+
+    /// Maximum number of uploads in flight at once.
+    pub concurrency: usize,
+
+    /// If set, then the Leader's processing loop is driven with this selector once the uploads
+    /// are done, until it no longer processes any reports.
+    pub process: Option<DaphneWorkerReportSelector>,
+
+    /// Maximum number of runs of the processing loop.
+    pub max_process_runs: u64,
+}
+
+/// Latency percentiles, in milliseconds.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl LatencySummary {
+    /// Compute the percentiles of the given latencies (in milliseconds) using the nearest-rank
+    /// method. The summary of an empty list is all zeros.
+    pub fn from_samples(mut samples: Vec<u64>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_unstable();
+        let rank = |percentile: usize| {
+            let index = (percentile * samples.len()).div_ceil(100);
+            samples[index.saturating_sub(1)]
+        };
+        Self {
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+/// Outcome of the upload phase.
+#[derive(Debug, Serialize)]
+pub struct UploadSummary {
+    pub reports_uploaded: u64,
+    pub reports_failed: u64,
+    pub elapsed_ms: u64,
+
+    /// Reports uploaded per second.
+    pub throughput: f64,
+    pub latency: LatencySummary,
+}
+
+/// Outcome of the processing phase.
+#[derive(Debug, Serialize)]
+pub struct ProcessSummary {
+    pub runs: u64,
+    pub reports_processed: u64,
+    pub reports_aggregated: u64,
+    pub reports_collected: u64,
+    pub elapsed_ms: u64,
+
+    /// Reports aggregated per second.
+    pub throughput: f64,
+    pub latency: LatencySummary,
+}
+
+/// Outcome of a load test.
+#[derive(Debug, Serialize)]
+pub struct LoadGenReport {
+    pub upload: UploadSummary,
+    pub process: Option<ProcessSummary>,
+}
+
+/// HTTP transport for [`DapClient`].
+pub struct LoadGenHttpClient(pub reqwest::Client);
+
+#[async_trait(?Send)]
+impl DapClientHttpClient for LoadGenHttpClient {
+    async fn send_http(
+        &self,
+        method: DapClientHttpMethod,
+        req: DapRequest<()>,
+    ) -> Result<DapClientHttpResponse, DapError> {
+        let builder = match method {
+            DapClientHttpMethod::Get => self.0.get(req.url),
+            DapClientHttpMethod::Post => self.0.post(req.url),
+            DapClientHttpMethod::Put => self.0.put(req.url),
+        };
+        let builder = match req.media_type.as_str_for_version(req.version) {
+            Some(content_type) if method != DapClientHttpMethod::Get => builder
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(req.payload),
+            _ => builder,
+        };
+        let builder = match req.client_auth {
+            Some(DapClientAuth::BearerToken(token)) => {
+                let token: &str = token.as_ref();
+                builder.header(reqwest::header::AUTHORIZATION, format!("Bearer {token}"))
+            }
+            Some(DapClientAuth::Signature(tag)) => {
+                builder.header("dap-client-signature", hex::encode(tag))
+            }
+            None => builder,
+        };
+        let builder = match req.idempotency_key {
+            Some(idempotency_key) => builder.header("dap-idempotency-key", idempotency_key),
+            None => builder,
+        };
+
+        let resp = builder
+            .send()
+            .await
+            .map_err(|e| DapError::Fatal(format!("request failed: {e}")))?;
+        let status = resp.status().as_u16();
+        let hpke_config_signature = resp
+            .headers()
+            .get("x-daphne-hpke-config-signature")
+            .and_then(|value| decode_base64url_vec(value.as_bytes()));
+        let payload = resp
+            .bytes()
+            .await
+            .map_err(|e| DapError::Fatal(format!("failed to read response: {e}")))?
+            .to_vec();
+        Ok(DapClientHttpResponse {
+            status,
+            payload,
+            hpke_config_signature,
+        })
+    }
+
+    async fn sleep(&self, millis: u64) {
+        tokio::time::sleep(Duration::from_millis(millis)).await;
+    }
+}
+
+/// Load generator for a task.
+pub struct LoadGen {
+    config: LoadGenConfig,
+    http: LoadGenHttpClient,
+    client: DapClient,
+}
+
+impl LoadGen {
+    pub fn new(config: LoadGenConfig) -> Self {
+        let client = DapClient::new(
+            config.version,
+            config.task_id.clone(),
+            config.leader_url.clone(),
+            config.helper_url.clone(),
+            config.vdaf.clone(),
+        );
+        let client = match config.upload_auth {
+            Some(ref upload_auth) => client.with_upload_auth(upload_auth.clone()),
+            None => client,
+        };
+        Self {
+            config,
+            http: LoadGenHttpClient(reqwest::Client::new()),
+            client,
+        }
+    }
+
+    /// Run the upload phase, followed by the processing phase if configured.
+    pub async fn run(&self) -> Result<LoadGenReport, DapError> {
+        let upload = self.upload().await?;
+        let process = match self.config.process {
+            Some(ref report_sel) => Some(self.process(report_sel).await?),
+            None => None,
+        };
+        Ok(LoadGenReport { upload, process })
+    }
+
+    /// Upload reports at the target rate for the configured duration. Failed uploads are counted
+    /// but not retried.
+    pub async fn upload(&self) -> Result<UploadSummary, DapError> {
+        // Fetch the HPKE configs up front, so that the first uploads are not slowed down.
+        self.client.hpke_configs(&self.http, now()).await?;
+
+        let reports_per_sec = self.config.reports_per_sec.max(1);
+        let total = self.config.duration.as_secs() * reports_per_sec
+            + u64::from(self.config.duration.subsec_millis()) * reports_per_sec / 1000;
+        let start = Instant::now();
+        let results: Vec<Result<u64, DapError>> = futures::stream::iter(0..total)
+            .map(|i| async move {
+                let scheduled_at = start + Duration::from_secs(i) / reports_per_sec as u32;
+                tokio::time::sleep_until(scheduled_at.into()).await;
+                let sent_at = Instant::now();
+                self.client
+                    .produce_and_upload(
+                        &self.http,
+                        now(),
+                        self.config.measurement.clone(),
+                        Vec::new(),
+                    )
+                    .await?;
+                Ok(millis(sent_at.elapsed()))
+            })
+            .buffer_unordered(self.config.concurrency.max(1))
+            .collect()
+            .await;
+        let elapsed = start.elapsed();
+
+        let mut latencies = Vec::with_capacity(results.len());
+        let mut reports_failed = 0;
+        for result in results {
+            match result {
+                Ok(latency) => latencies.push(latency),
+                Err(e) => {
+                    tracing::warn!("upload failed: {e}");
+                    reports_failed += 1;
+                }
+            }
+        }
+        let reports_uploaded = latencies.len() as u64;
+        Ok(UploadSummary {
+            reports_uploaded,
+            reports_failed,
+            elapsed_ms: millis(elapsed),
+            throughput: reports_uploaded as f64 / elapsed.as_secs_f64(),
+            latency: LatencySummary::from_samples(latencies),
+        })
+    }
+
+    /// Drive the Leader's processing loop until a run processes no reports or the maximum number
+    /// of runs is reached.
+    pub async fn process(
+        &self,
+        report_sel: &DaphneWorkerReportSelector,
+    ) -> Result<ProcessSummary, DapError> {
+        let mut url = self.config.leader_url.clone();
+        url.set_path("internal/process");
+
+        let mut summary = ProcessSummary {
+            runs: 0,
+            reports_processed: 0,
+            reports_aggregated: 0,
+            reports_collected: 0,
+            elapsed_ms: 0,
+            throughput: 0.0,
+            latency: LatencySummary::default(),
+        };
+        let mut latencies = Vec::new();
+        let mut cursor = report_sel.cursor.clone();
+        let start = Instant::now();
+        while summary.runs < self.config.max_process_runs {
+            let sent_at = Instant::now();
+            let telem = self
+                .post_process(
+                    &url,
+                    &DaphneWorkerReportSelector {
+                        max_agg_jobs: report_sel.max_agg_jobs,
+                        max_reports: report_sel.max_reports,
+                        cursor: cursor.take(),
+                    },
+                )
+                .await?;
+            latencies.push(millis(sent_at.elapsed()));
+            summary.runs += 1;
+            summary.reports_processed += telem.reports_processed;
+            summary.reports_aggregated += telem.reports_aggregated;
+            summary.reports_collected += telem.reports_collected;
+            cursor = telem.cursor;
+            if telem.reports_processed == 0 {
+                break;
+            }
+        }
+        let elapsed = start.elapsed();
+
+        summary.elapsed_ms = millis(elapsed);
+        summary.throughput = summary.reports_aggregated as f64 / elapsed.as_secs_f64();
+        summary.latency = LatencySummary::from_samples(latencies);
+        Ok(summary)
+    }
+
+    async fn post_process(
+        &self,
+        url: &Url,
+        report_sel: &DaphneWorkerReportSelector,
+    ) -> Result<DapLeaderProcessTelemetry, DapError> {
+        let resp = self
+            .http
+            .0
+            .post(url.as_str())
+            .json(report_sel)
+            .send()
+            .await
+            .map_err(|e| DapError::Fatal(format!("request failed: {e}")))?;
+        if !resp.status().is_success() {
+            return Err(DapError::Fatal(format!(
+                "processing failed with status {}",
+                resp.status()
+            )));
+        }
+        resp.json()
+            .await
+            .map_err(|e| DapError::Fatal(format!("failed to parse telemetry: {e}")))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("system time is before the UNIX epoch")
+        .as_secs()
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
@@ -0,0 +1,46 @@
+// Copyright (c) 2023 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::loadgen::LatencySummary;
+use rand::prelude::*;
+
+#[test]
+fn latency_summary() {
+    assert_eq!(
+        LatencySummary::from_samples(Vec::new()),
+        LatencySummary::default()
+    );
+
+    assert_eq!(
+        LatencySummary::from_samples(vec![7]),
+        LatencySummary {
+            p50: 7,
+            p90: 7,
+            p99: 7,
+            max: 7,
+        }
+    );
+
+    // Nearest rank: The p-th percentile of 100 samples is the p-th smallest.
+    let mut samples: Vec<u64> = (1..=100).collect();
+    samples.shuffle(&mut thread_rng());
+    assert_eq!(
+        LatencySummary::from_samples(samples),
+        LatencySummary {
+            p50: 50,
+            p90: 90,
+            p99: 99,
+            max: 100,
+        }
+    );
+
+    assert_eq!(
+        LatencySummary::from_samples(vec![40, 10, 30, 20]),
+        LatencySummary {
+            p50: 20,
+            p90: 40,
+            p99: 40,
+            max: 40,
+        }
+    );
+}
@@ -6,23 +6,32 @@
 mod test_runner;
 
 use daphne::{
+    aborts::DapAbortType,
     async_test_versions,
     constants::DapMediaType,
     messages::{
+        encode_base64url,
         taskprov::{
             DpConfig, QueryConfig, QueryConfigVar, TaskConfig, UrlBytes, VdafConfig, VdafTypeVar,
         },
-        BatchSelector, Collection, CollectionReq, Extension, HpkeCiphertext, Interval, Query,
-        Report, ReportId, ReportMetadata, TaskId,
+        AggregationJobId, AggregationJobInitReq, BatchSelector, Collection, CollectionReq,
+        Draft02AggregationJobId, Extension, HpkeCiphertext, Interval, PartialBatchSelector, Query,
+        Report, ReportBatch, ReportId, ReportMetadata, TaskId, TransitionFailure,
     },
     taskprov::{compute_task_id, TaskprovVersion},
-    DapAggregateResult, DapMeasurement, DapTaskConfig, DapVersion,
+    DapAggregateResult, DapDryRunReportStatus, DapMeasurement, DapReportUploadStatus,
+    DapTaskConfig, DapVersion,
+};
+use daphne_worker::{
+    internal_api::{
+        AggregationTelemetry, HealthReport, HealthStatus, InternalErrorCode, InternalResponse,
+        InternalStatus, InternalTestEndpoint,
+    },
+    DaphneWorkerReportSelector,
 };
-use daphne_worker::DaphneWorkerReportSelector;
 use paste::paste;
-use prio::codec::{ParameterizedDecode, ParameterizedEncode};
+use prio::codec::{Encode, ParameterizedDecode, ParameterizedEncode};
 use rand::prelude::*;
-use serde::Deserialize;
 use serde_json::json;
 use std::cmp::{max, min};
 use test_runner::{TestRunner, MIN_BATCH_SIZE, TIME_PRECISION};
@@ -42,15 +51,6 @@ macro_rules! async_test_version {
     };
 }
 
-#[derive(Deserialize)]
-struct InternalTestEndpointForTaskResult {
-    status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    endpoint: Option<String>,
-}
-
 async fn e2e_helper_ready(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     t.helper_post_internal::<_, ()>("/internal/test/ready", &())
@@ -67,6 +67,34 @@ async fn e2e_leader_ready(version: DapVersion) {
 
 async_test_versions! { e2e_leader_ready }
 
+async fn e2e_health(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    for base_url in [&t.leader_url, &t.helper_url] {
+        let mut url = base_url.clone();
+        url.set_path("healthz");
+        let resp = client.get(url).send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+        let report: HealthReport = resp.json().await.unwrap();
+        assert_eq!(report.status, HealthStatus::Pass);
+
+        // The outcome of the readiness checks depends on the state of the deployment, but each
+        // check is reported and storage is reachable.
+        let mut url = base_url.clone();
+        url.set_path("readyz");
+        let report: HealthReport = client.get(url).send().await.unwrap().json().await.unwrap();
+        let names: Vec<&str> = report.checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["kv", "durable_object", "hpke_config", "queue_depth"]
+        );
+        assert_eq!(report.checks[0].status, HealthStatus::Pass);
+        assert_eq!(report.checks[1].status, HealthStatus::Pass);
+    }
+}
+
+async_test_versions! { e2e_health }
+
 async fn e2e_leader_endpoint_for_task(version: DapVersion, want_prefix: bool) {
     let prefix = if want_prefix {
         format!("/{}", version.as_ref())
@@ -74,7 +102,7 @@ async fn e2e_leader_endpoint_for_task(version: DapVersion, want_prefix: bool) {
         String::from("")
     };
     let t = TestRunner::default_with_version(version).await;
-    let res: InternalTestEndpointForTaskResult = t
+    let res: InternalResponse<InternalTestEndpoint> = t
         .leader_post_internal(
             format!("{}/internal/test/endpoint_for_task", prefix).as_ref(),
             &json!({
@@ -83,17 +111,13 @@ async fn e2e_leader_endpoint_for_task(version: DapVersion, want_prefix: bool) {
             }),
         )
         .await;
-    assert_eq!(
-        res.status, "success",
-        "response status: {}, error: {:?}",
-        res.status, res.error
-    );
+    assert!(res.is_success(), "error: {:?}", res.detail);
     let expected = if want_prefix {
         format!("/{}/", version.as_ref())
     } else {
         String::from("/v04/") // Must match DAP_DEFAULT_VERSION
     };
-    assert_eq!(res.endpoint.unwrap(), expected);
+    assert_eq!(res.payload.unwrap().endpoint, expected);
 }
 
 async fn e2e_leader_endpoint_for_task_unprefixed(version: DapVersion) {
@@ -115,7 +139,7 @@ async fn e2e_helper_endpoint_for_task(version: DapVersion, want_prefix: bool) {
         String::from("")
     };
     let t = TestRunner::default_with_version(version).await;
-    let res: InternalTestEndpointForTaskResult = t
+    let res: InternalResponse<InternalTestEndpoint> = t
         .helper_post_internal(
             format!("{}/internal/test/endpoint_for_task", prefix).as_ref(),
             &json!({
@@ -124,17 +148,13 @@ async fn e2e_helper_endpoint_for_task(version: DapVersion, want_prefix: bool) {
             }),
         )
         .await;
-    assert_eq!(
-        res.status, "success",
-        "response status: {}, error: {:?}",
-        res.status, res.error
-    );
+    assert!(res.is_success(), "error: {:?}", res.detail);
     let expected = if want_prefix {
         format!("/{}/", version.as_ref())
     } else {
         String::from("/v04/") // Must match DAP_DEFAULT_VERSION
     };
-    assert_eq!(res.endpoint.unwrap(), expected);
+    assert_eq!(res.payload.unwrap().endpoint, expected);
 }
 
 async fn e2e_helper_endpoint_for_task_unprefixed(version: DapVersion) {
@@ -149,6 +169,40 @@ async fn e2e_helper_endpoint_for_task_prefixed(version: DapVersion) {
 
 async_test_versions! { e2e_helper_endpoint_for_task_prefixed }
 
+async fn e2e_internal_test_add_task_error(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let res: InternalResponse<InternalTestEndpoint> = t
+        .leader_post_internal(
+            format!("/{}/internal/test/add_task", version.as_ref()).as_ref(),
+            &json!({
+                "task_id": t.task_id.to_base64url(),
+                "leader": t.leader_url,
+                "helper": t.helper_url,
+                "vdaf": {
+                    "type": "Prio3Poplar",
+                },
+                "leader_authentication_token": t.leader_bearer_token.clone(),
+                "collector_authentication_token": t.collector_bearer_token.clone(),
+                "role": "leader",
+                "vdaf_verify_key": "",
+                "query_type": 1,
+                "min_batch_size": t.task_config.min_batch_size,
+                "time_precision": t.task_config.time_precision,
+                "collector_hpke_config": "",
+                "task_expiration": t.task_config.expiration,
+            }),
+        )
+        .await;
+    assert_eq!(res.status, InternalStatus::Error);
+    assert_eq!(res.error_code, Some(InternalErrorCode::CommandFailed));
+    assert_eq!(
+        res.detail.unwrap(),
+        "command failed: unrecognized VDAF".to_string()
+    );
+}
+
+async_test_versions! { e2e_internal_test_add_task_error }
+
 async fn e2e_leader_hpke_config(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let client = t.http_client();
@@ -216,7 +270,7 @@ async fn e2e_leader_upload(version: DapVersion) {
         DapMediaType::Report,
         report.get_encoded_with_param(&version),
         400,
-        "reportRejected",
+        "reportReplayed",
     )
     .await;
 
@@ -264,7 +318,7 @@ async fn e2e_leader_upload(version: DapVersion) {
         DapMediaType::Report,
         report.get_encoded_with_param(&version),
         400,
-        "reportRejected",
+        "reportDecryptFailed",
     )
     .await;
 
@@ -318,7 +372,7 @@ async fn e2e_leader_upload(version: DapVersion) {
     );
     let builder = match t.version {
         DapVersion::Draft02 => client.post(url.as_str()),
-        DapVersion::Draft04 => client.put(url.as_str()),
+        DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => client.put(url.as_str()),
         _ => unreachable!("unhandled version {}", t.version),
     };
     let resp = builder
@@ -360,6 +414,59 @@ async fn e2e_leader_upload(version: DapVersion) {
 
 async_test_versions! { e2e_leader_upload }
 
+async fn e2e_leader_upload_batch(version: DapVersion) {
+    if version == DapVersion::Draft02 {
+        // Batched upload is not supported in draft02.
+        return;
+    }
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+
+    let report = t
+        .task_config
+        .vdaf
+        .produce_report(
+            &hpke_config_list,
+            t.now,
+            &t.task_id,
+            DapMeasurement::U64(1),
+            version,
+        )
+        .unwrap();
+
+    // The second copy of the report is rejected as a replay; the first is accepted.
+    let url = t
+        .leader_url
+        .join(&format!("{}/batch", t.upload_path()))
+        .unwrap();
+    let resp = client
+        .post(url.as_str())
+        .body(ReportBatch::from_reports(&version, &[report.clone(), report.clone()]).get_encoded())
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            DapMediaType::ReportBatch
+                .as_str_for_version(version)
+                .unwrap(),
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let statuses: Vec<DapReportUploadStatus> = resp.json().await.unwrap();
+    assert_eq!(statuses.len(), 2);
+    let report_id = report.report_metadata.id.to_base64url();
+    assert_eq!(statuses[0].report_id.as_ref(), Some(&report_id));
+    assert!(statuses[0].problem.is_none());
+    assert_eq!(statuses[1].report_id.as_ref(), Some(&report_id));
+    assert_eq!(
+        statuses[1].problem.as_ref().unwrap().abort_type(),
+        Some(DapAbortType::ReportDuplicated)
+    );
+}
+
+async_test_versions! { e2e_leader_upload_batch }
+
 #[tokio::test]
 #[cfg_attr(not(feature = "test_e2e"), ignore)]
 async fn e2e_leader_upload_taskprov() {
@@ -481,6 +588,69 @@ async fn e2e_leader_upload_taskprov() {
     )
     .await;
 
+    // Generate and upload a report with draft04 taskprov, which advertises the task config in a
+    // header.
+    let payload = taskprov_task_config.get_encoded_with_param(&TaskprovVersion::Draft04);
+    let task_id = compute_task_id(TaskprovVersion::Draft04, &payload).unwrap();
+    let extensions = vec![Extension::Taskprov {
+        payload: Vec::new(),
+    }];
+    let report = t
+        .task_config
+        .vdaf
+        .produce_report_with_extensions(
+            &hpke_config_list,
+            t.now,
+            &task_id,
+            DapMeasurement::U64(23),
+            extensions,
+            version,
+        )
+        .unwrap();
+    t.leader_post_taskprov_expect_ok(
+        &client,
+        path,
+        DapMediaType::Report,
+        report.get_encoded_with_param(&version),
+        Some(&encode_base64url(&payload)),
+    )
+    .await;
+
+    // Generate and upload a report with draft04 taskprov but without the header. The empty
+    // extension payload is then interpreted as a draft02 task config, which doesn't match the task
+    // ID.
+    let task_config = TaskConfig {
+        task_info: "Hi again".as_bytes().to_vec(),
+        ..taskprov_task_config.clone()
+    };
+    let payload = task_config.get_encoded_with_param(&TaskprovVersion::Draft04);
+    let task_id = compute_task_id(TaskprovVersion::Draft04, &payload).unwrap();
+    let extensions = vec![Extension::Taskprov {
+        payload: Vec::new(),
+    }];
+    let report = t
+        .task_config
+        .vdaf
+        .produce_report_with_extensions(
+            &hpke_config_list,
+            t.now,
+            &task_id,
+            DapMeasurement::U64(23),
+            extensions,
+            version,
+        )
+        .unwrap();
+    t.leader_post_expect_abort(
+        &client,
+        None, // dap_auth_token
+        path,
+        DapMediaType::Report,
+        report.get_encoded_with_param(&version),
+        400,
+        "unrecognizedTask",
+    )
+    .await;
+
     // Generate and upload a report with taskprov but only one endpoint, which is an error.
     //
     // We have to make this by hand as if we cut and paste a pre-serialized one it
@@ -541,6 +711,7 @@ async fn e2e_internal_leader_process(version: DapVersion) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
         max_reports: t.task_config.min_batch_size,
+        cursor: None,
     };
 
     let batch_interval = t.batch_interval();
@@ -590,6 +761,286 @@ async fn e2e_internal_leader_process(version: DapVersion) {
 
 async_test_versions! { e2e_internal_leader_process }
 
+// Test that the Helper rejects aggregation jobs that are not authorized by the Leader's bearer
+// token.
+async fn e2e_helper_agg_job_abort_unauthorized(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let mut rng = thread_rng();
+
+    let (path, agg_job_init_req) = match version {
+        DapVersion::Draft02 => (
+            "aggregate".to_string(),
+            AggregationJobInitReq {
+                draft02_task_id: Some(t.task_id.clone()),
+                draft02_agg_job_id: Some(Draft02AggregationJobId(rng.gen())),
+                agg_param: Vec::new(),
+                part_batch_sel: PartialBatchSelector::TimeInterval,
+                report_shares: Vec::new(),
+                draft06_leader_messages: None,
+            },
+        ),
+        _ => (
+            format!(
+                "tasks/{}/aggregation_jobs/{}",
+                t.task_id.to_base64url(),
+                AggregationJobId(rng.gen()).to_base64url()
+            ),
+            AggregationJobInitReq {
+                draft02_task_id: None,
+                draft02_agg_job_id: None,
+                agg_param: Vec::new(),
+                part_batch_sel: PartialBatchSelector::TimeInterval,
+                report_shares: Vec::new(),
+                draft06_leader_messages: (version == DapVersion::Draft06).then(Vec::new),
+            },
+        ),
+    };
+    let data = agg_job_init_req.get_encoded_with_param(&version);
+
+    // Missing bearer token.
+    t.helper_put_expect_abort(
+        &client,
+        None,
+        &path,
+        DapMediaType::AggregationJobInitReq,
+        data.clone(),
+        400,
+        "unauthorizedRequest",
+    )
+    .await;
+
+    // Incorrect bearer token.
+    t.helper_put_expect_abort(
+        &client,
+        Some(&format!("{}-corrupted", t.leader_bearer_token)),
+        &path,
+        DapMediaType::AggregationJobInitReq,
+        data,
+        400,
+        "unauthorizedRequest",
+    )
+    .await;
+}
+
+async_test_versions! { e2e_helper_agg_job_abort_unauthorized }
+
+// Test that the Leader fails to aggregate reports while its bearer token is rejected by the Helper
+// and recovers once the token is restored.
+async fn e2e_leader_process_abort_unauthorized(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let path = t.upload_path();
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+        max_reports: t.task_config.min_batch_size,
+        cursor: None,
+    };
+
+    let batch_interval = t.batch_interval();
+    let mut rng = thread_rng();
+    let mut produce_report = || {
+        let now = rng.gen_range(t.report_interval(&batch_interval));
+        t.task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                now,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap()
+            .get_encoded_with_param(&version)
+    };
+
+    // Corrupt the Leader's bearer token. The Helper should reject the aggregation job. This is
+    // not a transient error, so the Leader should fail the job rather than abandon it, and return
+    // its reports to storage.
+    t.leader_put_expect_ok(&client, &path, DapMediaType::Report, produce_report())
+        .await;
+    let res: InternalResponse = t
+        .leader_post_internal(
+            "/internal/test/corrupt_leader_bearer_token",
+            &json!({
+                "task_id": t.task_id.to_base64url(),
+                "corrupt": true,
+            }),
+        )
+        .await;
+    assert!(res.is_success(), "{:?}", res.detail);
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_aggregated, 0, "reports aggregated");
+
+    // Restore the Leader's bearer token. The report that was rejected should be aggregated.
+    let res: InternalResponse = t
+        .leader_post_internal(
+            "/internal/test/corrupt_leader_bearer_token",
+            &json!({
+                "task_id": t.task_id.to_base64url(),
+                "corrupt": false,
+            }),
+        )
+        .await;
+    assert!(res.is_success(), "{:?}", res.detail);
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_aggregated, 1, "reports aggregated");
+}
+
+async_test_versions! { e2e_leader_process_abort_unauthorized }
+
+async fn get_aggregation_telemetry(t: &TestRunner, base_url: &Url) -> AggregationTelemetry {
+    let mut url = base_url.clone();
+    url.set_path("internal/telemetry/aggregation");
+    let resp = t
+        .http_client()
+        .get(url.clone())
+        .header(
+            "x-daphne-worker-admin-bearer-token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200, "request to {url} failed");
+    resp.json().await.unwrap()
+}
+
+// Test that both Aggregators count the reports of each task by outcome.
+async fn e2e_aggregation_telemetry(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let path = t.upload_path();
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let task_id = t.task_id.to_base64url();
+
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100,
+        max_reports: 100,
+        cursor: None,
+    };
+    t.internal_process(&client, &report_sel).await;
+    let before = [
+        get_aggregation_telemetry(&t, &t.leader_url).await,
+        get_aggregation_telemetry(&t, &t.helper_url).await,
+    ];
+
+    t.leader_put_expect_ok(
+        &client,
+        &path,
+        DapMediaType::Report,
+        t.task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                t.now,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap()
+            .get_encoded_with_param(&version),
+    )
+    .await;
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert!(agg_telem.reports_aggregated >= 1, "reports aggregated");
+
+    for (base_url, before) in [&t.leader_url, &t.helper_url].into_iter().zip(before) {
+        let after = get_aggregation_telemetry(&t, base_url).await;
+        let before = before.tasks.get(&task_id).cloned().unwrap_or_default();
+        let after = after.tasks.get(&task_id).cloned().unwrap_or_default();
+        assert!(after.reports_aggregated > before.reports_aggregated);
+        assert!(after.reports_processed > before.reports_processed);
+    }
+}
+
+async_test_versions! { e2e_aggregation_telemetry }
+
+async fn leader_put_quarantine(t: &TestRunner, quarantine: serde_json::Value) {
+    let mut url = t.leader_url.clone();
+    url.set_path("quarantine");
+    let resp = t
+        .http_client()
+        .put(url.clone())
+        .json(&quarantine)
+        .header(
+            "x-daphne-worker-admin-bearer-token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(
+        resp.status(),
+        200,
+        "request to {url} failed: {}",
+        resp.text().await.unwrap()
+    );
+}
+
+// Test that the Leader does not aggregate reports in quarantined buckets.
+async fn e2e_leader_process_quarantine(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let path = t.upload_path();
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+        max_reports: t.task_config.min_batch_size,
+        cursor: None,
+    };
+
+    let batch_interval = t.batch_interval();
+    leader_put_quarantine(
+        &t,
+        json!([{
+            "task_id": t.task_id.to_base64url(),
+            "start": batch_interval.start,
+            "end": batch_interval.end(),
+            "expiration": t.now + 3600,
+        }]),
+    )
+    .await;
+
+    let mut rng = thread_rng();
+    for _ in 0..report_sel.max_reports {
+        let now = rng.gen_range(t.report_interval(&batch_interval));
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_processed, 0, "reports processed");
+
+    // Lift the quarantine. The reports should now be aggregated.
+    leader_put_quarantine(&t, json!([])).await;
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(
+        agg_telem.reports_aggregated, report_sel.max_reports,
+        "reports aggregated"
+    );
+}
+
+async_test_versions! { e2e_leader_process_quarantine }
+
 // Test that all reports eventually get drained at minimum aggregation rate.
 async fn e2e_leader_process_min_agg_rate(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
@@ -625,6 +1076,7 @@ async fn e2e_leader_process_min_agg_rate(version: DapVersion) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 1,
         max_reports: 1,
+        cursor: None,
     };
 
     for i in 0..7 {
@@ -639,6 +1091,130 @@ async fn e2e_leader_process_min_agg_rate(version: DapVersion) {
 
 async_test_versions! { e2e_leader_process_min_agg_rate }
 
+// Test that the processing loop can be resumed from the cursor returned by the previous run, so
+// that the aggregation job queue is drained in a single pass.
+async fn e2e_leader_process_cursor(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let batch_interval = t.batch_interval();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+
+    let mut rng = thread_rng();
+    for _ in 0..7 {
+        let now = rng.gen_range(t.report_interval(&batch_interval));
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version),
+        )
+        .await;
+    }
+
+    // Each run handles one job and returns a cursor pointing to it. No cursor is returned once
+    // the end of the queue is reached.
+    let mut report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 1,
+        max_reports: 100,
+        cursor: None,
+    };
+    let mut reports_processed = 0;
+    for _ in 0..100 {
+        let agg_telem = t.internal_process(&client, &report_sel).await;
+        reports_processed += agg_telem.reports_processed;
+        report_sel.cursor = agg_telem.cursor;
+        if report_sel.cursor.is_none() {
+            break;
+        }
+    }
+    assert!(report_sel.cursor.is_none(), "end of queue not reached");
+    assert_eq!(reports_processed, 7, "reports processed");
+}
+
+async_test_versions! { e2e_leader_process_cursor }
+
+async fn leader_dry_run(t: &TestRunner, reports: &[Report]) -> Vec<DapDryRunReportStatus> {
+    let mut url = t.leader_url.clone();
+    url.set_path(&format!("admin/tasks/{}/dry_run", t.task_id.to_base64url()));
+    let resp = t
+        .http_client()
+        .post(url.clone())
+        .body(ReportBatch::from_reports(&t.version, reports).get_encoded())
+        .header(
+            "x-daphne-worker-admin-bearer-token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200, "request to {url} failed");
+    resp.json().await.unwrap()
+}
+
+// Test that reports whose replay window has ended are dropped rather than aggregated, and that
+// their IDs are purged from storage.
+async fn e2e_leader_process_replay_window_expired(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, &client).await;
+    let path = t.upload_path();
+    let replay_window = t.global_config.report_replay_window.unwrap();
+
+    // The first report is current; the second is older than the replay window, but still within
+    // the report storage epoch.
+    let mut reports = Vec::new();
+    for time in [t.now, t.now - replay_window - TIME_PRECISION] {
+        let report = t
+            .task_config
+            .vdaf
+            .produce_report(
+                &hpke_config_list,
+                time,
+                &t.task_id,
+                DapMeasurement::U64(1),
+                version,
+            )
+            .unwrap();
+        t.leader_put_expect_ok(
+            &client,
+            &path,
+            DapMediaType::Report,
+            report.get_encoded_with_param(&version),
+        )
+        .await;
+        reports.push(report);
+    }
+
+    let report_sel = DaphneWorkerReportSelector {
+        max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
+        max_reports: 100,
+        cursor: None,
+    };
+    let agg_telem = t.internal_process(&client, &report_sel).await;
+    assert_eq!(agg_telem.reports_processed, 2, "reports processed");
+    assert_eq!(agg_telem.reports_aggregated, 1, "reports aggregated");
+
+    // Both reports were marked as processed. The ID of the current report is kept, but the ID of
+    // the expired report is purged, so the latter is dropped rather than rejected as replayed.
+    let statuses = leader_dry_run(&t, &reports).await;
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(statuses[0].failure, Some(TransitionFailure::ReportReplayed));
+    assert_eq!(statuses[1].failure, Some(TransitionFailure::ReportDropped));
+}
+
+async_test_versions! { e2e_leader_process_replay_window_expired }
+
 async fn e2e_leader_collect_ok(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let batch_interval = t.batch_interval();
@@ -698,6 +1274,7 @@ async fn e2e_leader_collect_ok(version: DapVersion) {
             &DaphneWorkerReportSelector {
                 max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
                 max_reports: 100,
+                cursor: None,
             },
         )
         .await;
@@ -817,6 +1394,7 @@ async fn e2e_leader_collect_ok_interleaved(version: DapVersion) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
         max_reports: 100,
+        cursor: None,
     };
 
     // All reports for the task get processed ...
@@ -898,6 +1476,7 @@ async fn e2e_leader_collect_not_ready_min_batch_size(version: DapVersion) {
             &DaphneWorkerReportSelector {
                 max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
                 max_reports: 100,
+                cursor: None,
             },
         )
         .await;
@@ -1092,6 +1671,7 @@ async fn e2e_leader_collect_abort_overlapping_batch_interval(version: DapVersion
             &DaphneWorkerReportSelector {
                 max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
                 max_reports: 100,
+                cursor: None,
             },
         )
         .await;
@@ -1164,6 +1744,7 @@ async fn e2e_fixed_size(version: DapVersion, use_current: bool) {
     let report_sel = DaphneWorkerReportSelector {
         max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
         max_reports: 100,
+        cursor: None,
     };
 
     let client = t.http_client();
@@ -1358,7 +1939,7 @@ async fn e2e_fixed_size_current(version: DapVersion) {
 
 async_test_versions! { e2e_fixed_size_current }
 
-async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
+async fn e2e_leader_collect_taskprov(version: DapVersion, taskprov_version: TaskprovVersion) {
     let t = TestRunner::default_with_version(version).await;
     let batch_interval = t.batch_interval();
 
@@ -1387,11 +1968,11 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
             var: VdafTypeVar::Prio3Aes128Sum { bit_length: 10 },
         },
     };
-    let payload = taskprov_task_config.get_encoded_with_param(&TaskprovVersion::Draft02);
-    let task_id = compute_task_id(TaskprovVersion::Draft02, &payload).unwrap();
+    let payload = taskprov_task_config.get_encoded_with_param(&taskprov_version);
+    let task_id = compute_task_id(taskprov_version, &payload).unwrap();
     let task_config = DapTaskConfig::try_from_taskprov(
         version,
-        TaskprovVersion::Draft02,
+        taskprov_version,
         &task_id.clone(),
         taskprov_task_config.clone(),
         &t.taskprov_vdaf_verify_key_init,
@@ -1400,6 +1981,13 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
     .unwrap();
     let path = t.upload_path_for_task(&task_id);
 
+    // In draft04, the task config is advertised in a header rather than the report extension.
+    let (payload, taskprov_advertisement) = match taskprov_version {
+        TaskprovVersion::Draft04 => (Vec::new(), Some(encode_base64url(&payload))),
+        _ => (payload, None),
+    };
+    assert_eq!(task_config.taskprov_advertisement, taskprov_advertisement);
+
     // The reports are uploaded in the background.
     let mut rng = thread_rng();
     for _ in 0..t.task_config.min_batch_size {
@@ -1407,7 +1995,7 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
             payload: payload.clone(),
         }];
         let now = rng.gen_range(t.report_interval(&batch_interval));
-        t.leader_put_expect_ok(
+        t.leader_post_taskprov_expect_ok(
             &client,
             &path,
             DapMediaType::Report,
@@ -1423,6 +2011,7 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
                 )
                 .unwrap()
                 .get_encoded_with_param(&version),
+            taskprov_advertisement.as_deref(),
         )
         .await;
     }
@@ -1455,6 +2044,7 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
             &DaphneWorkerReportSelector {
                 max_agg_jobs: 100, // Needs to be sufficiently large to touch each bucket.
                 max_reports: 100,
+                cursor: None,
             },
         )
         .await;
@@ -1506,7 +2096,17 @@ async fn e2e_leader_collect_taskprov_ok(version: DapVersion) {
     );
 }
 
-async_test_version! { e2e_leader_collect_taskprov_ok, Draft02 }
+async fn e2e_leader_collect_taskprov_draft02_ok(version: DapVersion) {
+    e2e_leader_collect_taskprov(version, TaskprovVersion::Draft02).await;
+}
+
+async_test_version! { e2e_leader_collect_taskprov_draft02_ok, Draft02 }
+
+async fn e2e_leader_collect_taskprov_draft04_ok(version: DapVersion) {
+    e2e_leader_collect_taskprov(version, TaskprovVersion::Draft04).await;
+}
+
+async_test_version! { e2e_leader_collect_taskprov_draft04_ok, Draft02 }
 
 async fn e2e_helper_admin_add_task(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
@@ -1554,3 +2154,321 @@ async fn e2e_helper_admin_add_task(version: DapVersion) {
 }
 
 async_test_versions! { e2e_helper_admin_add_task }
+
+async fn e2e_helper_admin_get_and_delete_task(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let task_id = TaskId(thread_rng().gen()).to_base64url();
+    let admin_req = |method: reqwest::Method, path: &str| {
+        client
+            .request(
+                method,
+                Url::parse("http://127.0.0.1:8788/")
+                    .unwrap()
+                    .join(path)
+                    .unwrap(),
+            )
+            .header(
+                "x-daphne-worker-admin-bearer-token",
+                "administrator bearer token",
+            )
+    };
+
+    let add_task_cmd = serde_json::json!({
+        "collector_hpke_config": "kwAgAAEAAQAgAPjfKNRNrnodTEuoCKA5qAOTaWOmVlmNVyAXOL6__20",
+        "leader": format!("http://cool.leader/{}/", version.as_ref()),
+        "helper": format!("https:/awesome.helper.web:8788/{}/", version.as_ref()),
+        "leader_authentication_token": "leader bearer token",
+        "min_batch_size": 10,
+        "query_type": 1,
+        "role": "helper",
+        "task_expiration": 1670880698,
+        "task_id": task_id,
+        "time_precision": 3600,
+        "vdaf": {
+            "type":"Prio3Count"
+        },
+        "vdaf_verify_key": "y4e6alnJMQ0MZTvdJRJx5Q"
+    });
+    let resp = admin_req(reqwest::Method::POST, "task")
+        .json(&add_task_cmd)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200, "{}", resp.text().await.unwrap());
+
+    // The task is listed.
+    let task_ids: Vec<String> = admin_req(reqwest::Method::GET, "task")
+        .send()
+        .await
+        .expect("request failed")
+        .json()
+        .await
+        .unwrap();
+    assert!(task_ids.contains(&task_id));
+
+    // The task config is returned without the VDAF verification key.
+    let resp = admin_req(reqwest::Method::GET, &format!("task/{task_id}"))
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let task: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(task["task_id"], task_id);
+    assert_eq!(task["min_batch_size"], 10);
+    assert!(task.get("vdaf_verify_key").is_none());
+
+    // Requests without the admin token are refused.
+    let resp = client
+        .delete(format!("http://127.0.0.1:8788/task/{task_id}"))
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+
+    // Once deleted, the task is no longer recognized.
+    let resp = admin_req(reqwest::Method::DELETE, &format!("task/{task_id}"))
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let resp = admin_req(reqwest::Method::GET, &format!("task/{task_id}"))
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 404);
+    let resp = admin_req(reqwest::Method::DELETE, &format!("task/{task_id}"))
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 404);
+}
+
+async_test_versions! { e2e_helper_admin_get_and_delete_task }
+
+async fn e2e_helper_admin_task_manifest(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let task_id = TaskId(thread_rng().gen()).to_base64url();
+    let manifest_req = |query: &str, manifest: &serde_json::Value| {
+        client
+            .post(format!("http://127.0.0.1:8788/admin/tasks/manifest{query}"))
+            .header(
+                "x-daphne-worker-admin-bearer-token",
+                "administrator bearer token",
+            )
+            .json(manifest)
+    };
+
+    // A task that is created must list the Leader's bearer token.
+    let mut manifest = serde_json::json!({
+        "tasks": [{
+            "task_id": task_id,
+            "config": t.task_config,
+        }],
+    });
+    let resp = manifest_req("?apply=true", &manifest)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 400);
+
+    // By default, the manifest is only compared with the installed tasks.
+    manifest["tasks"][0]["leader_authentication_token"] = "leader bearer token".into();
+    let diff: serde_json::Value = manifest_req("", &manifest)
+        .send()
+        .await
+        .expect("request failed")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(diff["tasks"][0]["task_id"], task_id);
+    assert_eq!(diff["tasks"][0]["change"], "create");
+    assert_eq!(diff["applied"], false);
+
+    // Applying the manifest is idempotent.
+    for change in ["create", "unchanged"] {
+        let diff: serde_json::Value = manifest_req("?apply=true", &manifest)
+            .send()
+            .await
+            .expect("request failed")
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(diff["tasks"][0]["change"], change);
+        assert_eq!(diff["applied"], true);
+    }
+
+    let resp = client
+        .get(format!("http://127.0.0.1:8788/task/{task_id}"))
+        .header(
+            "x-daphne-worker-admin-bearer-token",
+            "administrator bearer token",
+        )
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200);
+}
+
+async_test_versions! { e2e_helper_admin_task_manifest }
+
+#[tokio::test]
+#[cfg_attr(not(feature = "test_e2e"), ignore)]
+async fn e2e_helper_admin_global_config_override() {
+    let client = reqwest::Client::new();
+    let url = Url::parse("http://127.0.0.1:8788/internal/global_config").unwrap();
+    let admin_req = |method: reqwest::Method| {
+        client.request(method, url.clone()).header(
+            "x-daphne-worker-admin-bearer-token",
+            "administrator bearer token",
+        )
+    };
+
+    let current: serde_json::Value = admin_req(reqwest::Method::GET)
+        .send()
+        .await
+        .expect("request failed")
+        .json()
+        .await
+        .unwrap();
+    let version = current["version"].as_u64().unwrap();
+
+    // Override with the configured value so as not to interfere with other tests.
+    let update = json!({
+        "version": version + 1,
+        "max_batch_duration": 360000,
+    });
+    let resp = admin_req(reqwest::Method::PUT)
+        .json(&update)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200, "{}", resp.text().await.unwrap());
+
+    // The same version stamp cannot be applied twice.
+    let resp = admin_req(reqwest::Method::PUT)
+        .json(&update)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 409);
+
+    let current: serde_json::Value = admin_req(reqwest::Method::GET)
+        .send()
+        .await
+        .expect("request failed")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(current, update);
+
+    // Requests without the admin token are refused.
+    let resp = client
+        .put(url.clone())
+        .json(&json!({ "version": version + 2 }))
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 401);
+}
+
+async fn e2e_helper_admin_add_and_delete_task_collector(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let client = t.http_client();
+    let task_id = TaskId(thread_rng().gen()).to_base64url();
+    let admin_req = |method: reqwest::Method, path: &str| {
+        client
+            .request(
+                method,
+                Url::parse("http://127.0.0.1:8788/")
+                    .unwrap()
+                    .join(path)
+                    .unwrap(),
+            )
+            .header(
+                "x-daphne-worker-admin-bearer-token",
+                "administrator bearer token",
+            )
+    };
+
+    let add_task_cmd = serde_json::json!({
+        "collector_hpke_config": "kwAgAAEAAQAgAPjfKNRNrnodTEuoCKA5qAOTaWOmVlmNVyAXOL6__20",
+        "leader": format!("http://cool.leader/{}/", version.as_ref()),
+        "helper": format!("https:/awesome.helper.web:8788/{}/", version.as_ref()),
+        "leader_authentication_token": "leader bearer token",
+        "min_batch_size": 10,
+        "query_type": 1,
+        "role": "helper",
+        "task_expiration": 1670880698,
+        "task_id": task_id,
+        "time_precision": 3600,
+        "vdaf": {
+            "type":"Prio3Count"
+        },
+        "vdaf_verify_key": "y4e6alnJMQ0MZTvdJRJx5Q"
+    });
+    let resp = admin_req(reqwest::Method::POST, "task")
+        .json(&add_task_cmd)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200, "{}", resp.text().await.unwrap());
+
+    // Add a Collector to the task. The Helper is not configured with the Collector's token.
+    let add_collector_cmd = serde_json::json!({
+        "id": "auditor",
+        "hpke_config": "kwAgAAEAAQAgAPjfKNRNrnodTEuoCKA5qAOTaWOmVlmNVyAXOL6__20",
+    });
+    let resp = admin_req(reqwest::Method::POST, &format!("task/{task_id}/collectors"))
+        .json(&add_collector_cmd)
+        .send()
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), 200, "{}", resp.text().await.unwrap());
+
+    let task: serde_json::Value = admin_req(reqwest::Method::GET, &format!("task/{task_id}"))
+        .send()
+        .await
+        .expect("request failed")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(task["additional_collectors"][0]["id"], "auditor");
+
+    // Collector IDs are unique.
+    let resp = admin_req(reqwest::Method::POST, &format!("task/{task_id}/collectors"))
+        .json(&add_collector_cmd)
+        .send()
+        .await
+        .expect("request failed");
+    assert!(!resp.status().is_success());
+
+    // Once removed, the Collector is no longer recognized.
+    let resp = admin_req(
+        reqwest::Method::DELETE,
+        &format!("task/{task_id}/collectors/auditor"),
+    )
+    .send()
+    .await
+    .expect("request failed");
+    assert_eq!(resp.status(), 200);
+    let task: serde_json::Value = admin_req(reqwest::Method::GET, &format!("task/{task_id}"))
+        .send()
+        .await
+        .expect("request failed")
+        .json()
+        .await
+        .unwrap();
+    assert!(task.get("additional_collectors").is_none());
+    let resp = admin_req(
+        reqwest::Method::DELETE,
+        &format!("task/{task_id}/collectors/auditor"),
+    )
+    .send()
+    .await
+    .expect("request failed");
+    assert_eq!(resp.status(), 404);
+}
+
+async_test_versions! { e2e_helper_admin_add_and_delete_task_collector }
@@ -5,6 +5,7 @@
 
 use assert_matches::assert_matches;
 use daphne::{
+    aborts::DapAbortType,
     constants::DapMediaType,
     hpke::HpkeReceiverConfig,
     messages::{
@@ -12,15 +13,16 @@ use daphne::{
         HpkeConfigList, HpkeKdfId, HpkeKemId, Interval, TaskId,
     },
     taskprov::TaskprovVersion,
-    DapGlobalConfig, DapLeaderProcessTelemetry, DapQueryConfig, DapTaskConfig, DapVersion,
-    Prio3Config, VdafConfig,
+    DapDpConfig, DapGlobalConfig, DapLeaderProcessTelemetry, DapQueryConfig, DapTaskConfig,
+    DapVersion, Prio3Config, VdafConfig,
 };
-use daphne_worker::DaphneWorkerReportSelector;
+use daphne_worker::{internal_api::InternalResponse, DaphneWorkerReportSelector};
 use hpke_rs::{HpkePrivateKey, HpkePublicKey};
 use prio::codec::{Decode, Encode};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::HashMap;
 use std::ops::Range;
 use std::time::SystemTime;
 use url::Url;
@@ -30,13 +32,6 @@ pub(crate) const MIN_BATCH_SIZE: u64 = 10;
 pub(crate) const MAX_BATCH_SIZE: u64 = 12;
 pub(crate) const TIME_PRECISION: Duration = 3600; // seconds
 
-#[derive(Deserialize)]
-struct InternalTestAddTaskResult {
-    status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
-}
-
 #[allow(dead_code)]
 pub struct TestRunner {
     pub global_config: DapGlobalConfig,
@@ -68,6 +63,7 @@ impl TestRunner {
             version,
             &DapQueryConfig::FixedSize {
                 max_batch_size: MAX_BATCH_SIZE,
+                batch_lifetime: None,
             },
         )
         .await
@@ -87,6 +83,8 @@ impl TestRunner {
         let version_path = match version {
             DapVersion::Draft02 => "v02",
             DapVersion::Draft04 => "v04",
+            DapVersion::Draft05 => "v05",
+            DapVersion::Draft06 => "v06",
             _ => panic!("unimplemented DapVersion"),
         };
         let mut leader_url = Url::parse(&format!("http://leader:8787/{}/", version_path)).unwrap();
@@ -111,22 +109,50 @@ impl TestRunner {
             expiration: now + 604800, // one week from now
             time_precision: TIME_PRECISION,
             min_batch_size: MIN_BATCH_SIZE,
+            max_batch_query_count: 1,
             query: query_config.clone(),
             vdaf: VDAF_CONFIG.clone(),
             vdaf_verify_key: VDAF_CONFIG.gen_verify_key(),
             collector_hpke_config: collector_hpke_receiver.config.clone(),
+            additional_collectors: Vec::new(),
+            dp: DapDpConfig::None,
+            taskprov_advertisement: None,
+            replay_filter: None,
+            vdaf_verify_key_rotation: None,
+            upload_auth: None,
         };
 
         // This block needs to be kept in-sync with daphne_worker_test/wrangler.toml.
         let global_config = DapGlobalConfig {
             report_storage_epoch_duration: 604800,
             report_storage_max_future_time_skew: 300,
+            report_time_skew_tolerance: None,
+            report_replay_window: Some(86400),
             max_batch_duration: 360000,
             min_batch_interval_start: 259200,
             max_batch_interval_end: 259200,
+            batch_interval_future_tolerance: None,
+            helper_connect_timeout: None,
+            helper_request_timeout: None,
             supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+            hpke_rotation: None,
+            agg_job_init_retry: None,
+            agg_job_continue_retry: None,
+            agg_job_abandon: None,
+            helper_circuit_breaker: None,
+            report_sampling: None,
+            reject_untruncated_report_time: false,
+            max_concurrent_collect_jobs: Some(4),
+            max_concurrent_collect_jobs_per_task: None,
+            max_upload_batch_len: Some(100),
+            helper_agg_job_limit: None,
+            helper_state_store: None,
+            helper_prep_concurrency: None,
+            helper_agg_share_span_buckets: None,
+            max_request_size: None,
             allow_taskprov: true,
             taskprov_version: TaskprovVersion::Draft02,
+            versions: HashMap::new(),
         };
         let taskprov_vdaf_verify_key_init =
             hex::decode("b029a72fa327931a5cb643dcadcaafa098fcbfac07d990cb9e7c9a8675fafb18")
@@ -176,7 +202,7 @@ impl TestRunner {
 
         let (query_type, max_batch_size) = match t.task_config.query {
             DapQueryConfig::TimeInterval => (1, None),
-            DapQueryConfig::FixedSize { max_batch_size } => (2, Some(max_batch_size)),
+            DapQueryConfig::FixedSize { max_batch_size, .. } => (2, Some(max_batch_size)),
         };
 
         // Configure the endpoints.
@@ -202,14 +228,10 @@ impl TestRunner {
             "task_expiration": t.task_config.expiration,
         });
         let add_task_path = format!("{}/internal/test/add_task", version.as_ref());
-        let res: InternalTestAddTaskResult = t
+        let res: InternalResponse = t
             .leader_post_internal(&add_task_path, &leader_add_task_cmd)
             .await;
-        assert_eq!(
-            res.status, "success",
-            "response status: {}, error: {:?}",
-            res.status, res.error
-        );
+        assert!(res.is_success(), "error: {:?}", res.detail);
 
         // Configure the Helper with the task.
         let helper_add_task_cmd = json!({
@@ -227,14 +249,10 @@ impl TestRunner {
             "collector_hpke_config": collector_hpke_config_base64url.clone(),
             "task_expiration": t.task_config.expiration,
         });
-        let res: InternalTestAddTaskResult = t
+        let res: InternalResponse = t
             .helper_post_internal(&add_task_path, &helper_add_task_cmd)
             .await;
-        assert_eq!(
-            res.status, "success",
-            "response status: {}, error: {:?}",
-            res.status, res.error
-        );
+        assert!(res.is_success(), "error: {:?}", res.detail);
 
         t
     }
@@ -304,6 +322,20 @@ impl TestRunner {
         path: &str,
         media_type: DapMediaType,
         data: Vec<u8>,
+    ) {
+        self.leader_post_taskprov_expect_ok(client, path, media_type, data, None)
+            .await;
+    }
+
+    /// Send a POST request, advertising the given draft04 taskprov task config (if any) in the
+    /// "dap-taskprov" header.
+    pub async fn leader_post_taskprov_expect_ok(
+        &self,
+        client: &reqwest::Client,
+        path: &str,
+        media_type: DapMediaType,
+        data: Vec<u8>,
+        taskprov_advertisement: Option<&str>,
     ) {
         let url = self.leader_url.join(path).unwrap();
         let mut headers = reqwest::header::HeaderMap::new();
@@ -315,6 +347,12 @@ impl TestRunner {
                 .parse()
                 .unwrap(),
         );
+        if let Some(taskprov_advertisement) = taskprov_advertisement {
+            headers.insert(
+                reqwest::header::HeaderName::from_static("dap-taskprov"),
+                reqwest::header::HeaderValue::from_str(taskprov_advertisement).unwrap(),
+            );
+        }
         let resp = client
             .post(url.as_str())
             .body(data)
@@ -381,10 +419,17 @@ impl TestRunner {
         );
 
         let problem_details: serde_json::Value = resp.json().await.unwrap();
-        let got = problem_details.as_object().unwrap().get("type").unwrap();
+        let got = problem_details
+            .as_object()
+            .unwrap()
+            .get("type")
+            .unwrap()
+            .as_str()
+            .unwrap();
         assert_eq!(
-            got,
-            &format!("urn:ietf:params:ppm:dap:error:{}", expected_err_type)
+            DapAbortType::from_urn(got).map(|abort_type| abort_type.as_str()),
+            Some(expected_err_type),
+            "unexpected problem type: {got}"
         );
     }
 
@@ -495,10 +540,89 @@ impl TestRunner {
         );
 
         let problem_details: serde_json::Value = resp.json().await.unwrap();
-        let got = problem_details.as_object().unwrap().get("type").unwrap();
+        let got = problem_details
+            .as_object()
+            .unwrap()
+            .get("type")
+            .unwrap()
+            .as_str()
+            .unwrap();
         assert_eq!(
-            got,
-            &format!("urn:ietf:params:ppm:dap:error:{}", expected_err_type)
+            DapAbortType::from_urn(got).map(|abort_type| abort_type.as_str()),
+            Some(expected_err_type),
+            "unexpected problem type: {got}"
+        );
+    }
+
+    /// Send a PUT request or, if draft02 is in use, a POST request to the Helper and expect an
+    /// abort.
+    #[allow(clippy::too_many_arguments)]
+    #[allow(dead_code)]
+    pub async fn helper_put_expect_abort(
+        &self,
+        client: &reqwest::Client,
+        dap_auth_token: Option<&str>,
+        path: &str,
+        media_type: DapMediaType,
+        data: Vec<u8>,
+        expected_status: u16,
+        expected_err_type: &str,
+    ) {
+        let url = self.helper_url.join(path).unwrap();
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            media_type
+                .as_str_for_version(self.version)
+                .unwrap()
+                .parse()
+                .unwrap(),
+        );
+        if let Some(token) = dap_auth_token {
+            headers.insert(
+                reqwest::header::HeaderName::from_static("dap-auth-token"),
+                reqwest::header::HeaderValue::from_str(token).unwrap(),
+            );
+        }
+
+        // draft02 always POSTs
+        let builder = if self.version == DapVersion::Draft02 {
+            client.post(url.as_str())
+        } else {
+            client.put(url.as_str())
+        };
+        let resp = builder
+            .body(data)
+            .headers(headers)
+            .send()
+            .await
+            .expect("request failed");
+
+        assert_eq!(
+            reqwest::StatusCode::from_u16(expected_status).unwrap(),
+            resp.status(),
+            "unexpected response status: {:?}",
+            resp.text().await.unwrap()
+        );
+
+        assert_eq!(
+            resp.headers().get("Content-Type").unwrap(),
+            "application/problem+json"
+        );
+
+        let problem_details: serde_json::Value = resp.json().await.unwrap();
+        let got = problem_details
+            .as_object()
+            .unwrap()
+            .get("type")
+            .unwrap()
+            .as_str()
+            .unwrap();
+        assert_eq!(
+            DapAbortType::from_urn(got).map(|abort_type| abort_type.as_str()),
+            Some(expected_err_type),
+            "unexpected problem type: {got}"
         );
     }
 
@@ -658,10 +782,36 @@ impl TestRunner {
         }
     }
 
+    /// Move the clock of both Aggregators forward by the given number of seconds, relative to the
+    /// clock of the Workers runtime. Set the offset to zero to reset the clocks. This does not
+    /// affect the clock of the DOs.
+    #[allow(dead_code)]
+    pub async fn internal_clock_offset(&self, offset_secs: u64) {
+        let client = self.http_client();
+        for base_url in [&self.leader_url, &self.helper_url] {
+            let mut url = base_url.clone();
+            url.set_path("internal/test/clock_offset");
+            let resp = client
+                .post(url.as_str())
+                .json(&serde_json::json!({ "offset_secs": offset_secs }))
+                .send()
+                .await
+                .expect("request failed");
+            assert_eq!(
+                200,
+                resp.status(),
+                "unexpected response status: {:?}",
+                resp.text().await.unwrap()
+            );
+        }
+    }
+
     pub fn upload_path_for_task(&self, id: &TaskId) -> String {
         match self.version {
             DapVersion::Draft02 => "upload".to_string(),
-            DapVersion::Draft04 => format!("tasks/{}/reports", id.to_base64url()),
+            DapVersion::Draft04 | DapVersion::Draft05 | DapVersion::Draft06 => {
+                format!("tasks/{}/reports", id.to_base64url())
+            }
             _ => unreachable!("unknown version"),
         }
     }